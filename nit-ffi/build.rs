@@ -0,0 +1,15 @@
+use std::env;
+use std::path::PathBuf;
+
+fn main() {
+    let crate_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+
+    cbindgen::Builder::new()
+        .with_crate(&crate_dir)
+        .with_language(cbindgen::Language::C)
+        .with_include_guard("NIT_H")
+        .with_autogen_warning("/* Generated by cbindgen from nit-ffi/src/lib.rs. Do not edit. */")
+        .generate()
+        .expect("couldn't generate nit.h")
+        .write_to_file(crate_dir.join("include").join("nit.h"));
+}
@@ -0,0 +1,131 @@
+#ifndef NIT_H
+#define NIT_H
+
+/* Generated by cbindgen from nit-ffi/src/lib.rs. Do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * A walk through the history of HEAD, newest commits first.
+ */
+typedef struct NitLog NitLog;
+
+/**
+ * An open repository.
+ */
+typedef struct NitRepository NitRepository;
+
+/**
+ * One commit from a `NitLog`. Its strings belong to the entry, and are freed by
+ * `nit_log_entry_clear` or the next call to `nit_log_next` with the same entry.
+ */
+typedef struct NitLogEntry {
+  char *oid;
+  char *author_name;
+  char *author_email;
+  /**
+   * Seconds since the Unix epoch.
+   */
+  int64_t time;
+  char *message;
+} NitLogEntry;
+
+/**
+ * Open the repository whose working tree is at `path`. Returns null if there isn't one.
+ *
+ * # Safety
+ * `path` must be a NUL-terminated string.
+ */
+struct NitRepository *nit_repository_open(const char *path);
+
+/**
+ * Close a repository opened with `nit_repository_open`.
+ *
+ * # Safety
+ * `repo` must be null or have come from `nit_repository_open`, and not be used again.
+ */
+void nit_repository_free(struct NitRepository *repo);
+
+/**
+ * The status of the repository, one line per changed path. Each line is two status letters
+ * and the path: the first letter compares HEAD to the index and the second compares the
+ * index to the working tree, as in `git status --porcelain`. Untracked files are shown as
+ * `??`. Returns null on error.
+ *
+ * # Safety
+ * `repo` must have come from `nit_repository_open`.
+ */
+char *nit_status(const struct NitRepository *repo);
+
+/**
+ * Add a file, or everything in a directory, to the index. `path` is relative to the working
+ * tree. Returns 0 on success and -1 on error.
+ *
+ * # Safety
+ * `repo` must have come from `nit_repository_open`, and `path` must be a NUL-terminated
+ * string.
+ */
+int nit_add(const struct NitRepository *repo, const char *path);
+
+/**
+ * Commit the index on top of HEAD, taking the author from `GIT_AUTHOR_NAME` and
+ * `GIT_AUTHOR_EMAIL`. Returns the new commit's ObjectId, or null on error.
+ *
+ * # Safety
+ * `repo` must have come from `nit_repository_open`, and `message` must be a NUL-terminated
+ * string.
+ */
+char *nit_commit(const struct NitRepository *repo, const char *message);
+
+/**
+ * Start walking the history of HEAD. Returns null on error.
+ *
+ * # Safety
+ * `repo` must have come from `nit_repository_open`, and must outlive the walk.
+ */
+struct NitLog *nit_log_new(const struct NitRepository *repo);
+
+/**
+ * Fill in `entry` with the next commit. Returns 1 if there was one, 0 at the end of the
+ * history, and -1 on error.
+ *
+ * # Safety
+ * `log` must have come from `nit_log_new`, and `entry` must point to a `NitLogEntry` that
+ * is zeroed or was filled in by an earlier call.
+ */
+int nit_log_next(struct NitLog *log, struct NitLogEntry *entry);
+
+/**
+ * Free the strings in a `NitLogEntry`, leaving it zeroed.
+ *
+ * # Safety
+ * `entry` must point to a `NitLogEntry` that is zeroed or was filled in by `nit_log_next`.
+ */
+void nit_log_entry_clear(struct NitLogEntry *entry);
+
+/**
+ * Finish a walk started with `nit_log_new`.
+ *
+ * # Safety
+ * `log` must be null or have come from `nit_log_new`, and not be used again.
+ */
+void nit_log_free(struct NitLog *log);
+
+/**
+ * Free a string returned by nit.
+ *
+ * # Safety
+ * `s` must be null or have come from nit, and not be used again.
+ */
+void nit_string_free(char *s);
+
+/**
+ * The message for the last error on this thread, or null if there hasn't been one. The
+ * string belongs to nit, and stays valid until the next error on this thread.
+ */
+const char *nit_last_error(void);
+
+#endif /* NIT_H */
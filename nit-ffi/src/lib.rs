@@ -0,0 +1,493 @@
+//! C bindings for nit, so that programs that aren't written in Rust can open a repository,
+//! look at its status, add files, commit, and walk its history. `include/nit.h` is generated
+//! from this file when the crate is built.
+//!
+//! Functions that can fail return a negative number or a null pointer, and leave a message
+//! for `nit_last_error`. Strings returned by nit belong to the caller, who frees them with
+//! `nit_string_free`.
+
+use std::cell::RefCell;
+use std::collections::{BTreeSet, BinaryHeap, HashSet};
+use std::error::Error;
+use std::ffi::{CStr, CString};
+use std::fmt::Display;
+use std::fs;
+use std::os::raw::{c_char, c_int};
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+use nit::database::{Author, Blob, Commit, ObjectId, Tree};
+use nit::ignore::Ignores;
+use nit::index::Index;
+use nit::inspector::{ChangeKind, Inspector};
+use nit::repository::{git_dir, Repository};
+use nit::workspace::Workspace;
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Keep an error, and everything that caused it, for `nit_last_error`.
+fn set_error(err: &dyn Error) {
+    let mut message = err.to_string();
+    let mut source = err.source();
+    while let Some(cause) = source {
+        message.push_str(&format!(": {}", cause));
+        source = cause.source();
+    }
+
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+/// Run `f`, turning an error into `fallback` after noting it for `nit_last_error`.
+fn catch<T>(fallback: T, f: impl FnOnce() -> Result<T>) -> T {
+    match f() {
+        Ok(value) => value,
+        Err(err) => {
+            set_error(err.as_ref());
+            fallback
+        }
+    }
+}
+
+fn message(text: impl Display) -> Box<dyn Error> {
+    text.to_string().into()
+}
+
+/// Borrow a string passed in from C.
+///
+/// # Safety
+/// `s` must be null or point to a NUL-terminated string.
+unsafe fn borrow_str<'a>(s: *const c_char, what: &str) -> Result<&'a str> {
+    if s.is_null() {
+        return Err(message(format!("{} is null", what)));
+    }
+    Ok(CStr::from_ptr(s).to_str()?)
+}
+
+/// Hand a string over to C, to be freed with `nit_string_free`.
+fn into_c_string(s: impl Into<Vec<u8>>) -> Result<*mut c_char> {
+    Ok(CString::new(s)?.into_raw())
+}
+
+/// An open repository.
+pub struct NitRepository {
+    root_path: PathBuf,
+    repo: Repository,
+}
+
+impl NitRepository {
+    fn workspace(&self) -> Result<Workspace> {
+        let symlinks = self
+            .repo
+            .config()?
+            .get_bool("core.symlinks")?
+            .unwrap_or(true);
+        Ok(Workspace::new(&self.root_path).with_symlinks(symlinks))
+    }
+
+    fn add(&self, path: &Path) -> Result<()> {
+        let workspace = self.workspace()?;
+        let database = self.repo.database();
+        let mut index = Index::new(self.repo.index_path());
+        index.load_for_update()?;
+
+        let result = (|| -> Result<()> {
+            for pathname in workspace.list_files(self.root_path.join(path))? {
+                let data = workspace.read_file(&pathname)?;
+                let stat = workspace.stat_file(&pathname)?;
+                let oid = database.store(&Blob::new(data))?;
+                let tracked = index.entry_for_path(&pathname).map(|entry| entry.mode());
+                let mode = workspace.mode_for_stat(&stat, tracked);
+                index.add_with_mode(&pathname, oid, stat, mode);
+            }
+            Ok(index.write_updates()?)
+        })();
+        if result.is_err() {
+            index.lockfile_mut().rollback()?;
+        }
+        result
+    }
+
+    fn status(&self) -> Result<String> {
+        let workspace = self.workspace()?;
+        let database = self.repo.database();
+        let mut index = Index::new(self.repo.index_path());
+        index.load()?;
+        let inspector = Inspector::new(database, &index, &workspace);
+
+        let head = self.repo.refs().read_head()?;
+        let head_tree = match &head {
+            Some(oid) => Some(*database.load_commit(oid)?.tree()),
+            None => None,
+        };
+        let tree = database.load_tree_list(head_tree.as_ref())?;
+
+        let code = |change: Option<ChangeKind>| match change {
+            None => ' ',
+            Some(ChangeKind::Added) => 'A',
+            Some(ChangeKind::Deleted) => 'D',
+            Some(ChangeKind::Modified) => 'M',
+            Some(ChangeKind::Untracked) => '?',
+        };
+
+        let paths: BTreeSet<_> = tree.keys().chain(index.entries().keys()).collect();
+        let mut status = String::new();
+        for path in paths {
+            let entry = index.entry_for_path(path);
+            let staged = inspector.compare_tree_to_index(tree.get(path), entry);
+            let unstaged = match entry {
+                Some(entry) => {
+                    let stat = workspace.try_stat_file(path)?;
+                    inspector.compare_index_to_workspace(Some(entry), stat.as_ref())?
+                }
+                None => None,
+            };
+            if staged.is_some() || unstaged.is_some() {
+                status.push_str(&format!(
+                    "{}{} {}\n",
+                    code(staged),
+                    code(unstaged),
+                    path.display()
+                ));
+            }
+        }
+
+        let ignores = Ignores::load(&self.root_path)?;
+        for file in workspace.list_files_in_root()? {
+            let path = Path::new(&file);
+            let ignored = ignores
+                .matching(path, false)
+                .is_some_and(|found| !found.negated);
+            if !index.is_tracked(&path) && !ignored {
+                status.push_str(&format!("?? {}\n", file));
+            }
+        }
+
+        Ok(status)
+    }
+
+    fn commit(&self, message_text: &str) -> Result<ObjectId> {
+        if message_text.trim().is_empty() {
+            return Err(message("Aborting commit due to empty commit message."));
+        }
+
+        let database = self.repo.database();
+        let refs = self.repo.refs();
+        let mut index = Index::new(self.repo.index_path());
+        index.load()?;
+
+        let mut root = Tree::build(index.entries().values().cloned().collect());
+        root.traverse(&mut |tree| database.store(tree))?;
+        let tree = database.store(&root)?;
+
+        let name = std::env::var("GIT_AUTHOR_NAME")
+            .map_err(|_| message("Could not load GIT_AUTHOR_NAME environment variable"))?;
+        let email = std::env::var("GIT_AUTHOR_EMAIL")
+            .map_err(|_| message("Could not load GIT_AUTHOR_EMAIL environment variable"))?;
+
+        let parent = refs.read_head()?;
+        let reflog_action = match parent {
+            Some(_) => "commit",
+            None => "commit (initial)",
+        };
+        let commit = Commit::new(
+            parent,
+            tree,
+            Author::new(name, email, Utc::now()),
+            message_text.to_owned(),
+        );
+        let oid = database.store(&commit)?;
+        refs.update_head(&oid, &format!("{}: {}", reflog_action, commit.title_line()))?;
+
+        Ok(oid)
+    }
+}
+
+/// Open the repository whose working tree is at `path`. Returns null if there isn't one.
+///
+/// # Safety
+/// `path` must be a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn nit_repository_open(path: *const c_char) -> *mut NitRepository {
+    catch(std::ptr::null_mut(), || {
+        let path = borrow_str(path, "path")?;
+        let root_path = fs::canonicalize(path)?;
+        let git_path = git_dir(&root_path);
+        if !git_path.is_dir() {
+            return Err(message(format!("not a nit repository: {}", path)));
+        }
+
+        let repo = Repository::new(git_path);
+        Ok(Box::into_raw(Box::new(NitRepository { root_path, repo })))
+    })
+}
+
+/// Close a repository opened with `nit_repository_open`.
+///
+/// # Safety
+/// `repo` must be null or have come from `nit_repository_open`, and not be used again.
+#[no_mangle]
+pub unsafe extern "C" fn nit_repository_free(repo: *mut NitRepository) {
+    if !repo.is_null() {
+        drop(Box::from_raw(repo));
+    }
+}
+
+/// The status of the repository, one line per changed path. Each line is two status letters
+/// and the path: the first letter compares HEAD to the index and the second compares the
+/// index to the working tree, as in `git status --porcelain`. Untracked files are shown as
+/// `??`. Returns null on error.
+///
+/// # Safety
+/// `repo` must have come from `nit_repository_open`.
+#[no_mangle]
+pub unsafe extern "C" fn nit_status(repo: *const NitRepository) -> *mut c_char {
+    catch(std::ptr::null_mut(), || into_c_string((*repo).status()?))
+}
+
+/// Add a file, or everything in a directory, to the index. `path` is relative to the working
+/// tree. Returns 0 on success and -1 on error.
+///
+/// # Safety
+/// `repo` must have come from `nit_repository_open`, and `path` must be a NUL-terminated
+/// string.
+#[no_mangle]
+pub unsafe extern "C" fn nit_add(repo: *const NitRepository, path: *const c_char) -> c_int {
+    catch(-1, || {
+        (*repo).add(Path::new(borrow_str(path, "path")?))?;
+        Ok(0)
+    })
+}
+
+/// Commit the index on top of HEAD, taking the author from `GIT_AUTHOR_NAME` and
+/// `GIT_AUTHOR_EMAIL`. Returns the new commit's ObjectId, or null on error.
+///
+/// # Safety
+/// `repo` must have come from `nit_repository_open`, and `message` must be a NUL-terminated
+/// string.
+#[no_mangle]
+pub unsafe extern "C" fn nit_commit(
+    repo: *const NitRepository,
+    message: *const c_char,
+) -> *mut c_char {
+    catch(std::ptr::null_mut(), || {
+        let oid = (*repo).commit(borrow_str(message, "message")?)?;
+        into_c_string(oid.to_string())
+    })
+}
+
+/// A walk through the history of HEAD, newest commits first.
+pub struct NitLog {
+    repo: *const NitRepository,
+    /// Commits still to show, by time. ObjectIds aren't `Ord`, so they're kept as bytes.
+    queue: BinaryHeap<(i64, [u8; 20])>,
+    seen: HashSet<ObjectId>,
+}
+
+/// One commit from a `NitLog`. Its strings belong to the entry, and are freed by
+/// `nit_log_entry_clear` or the next call to `nit_log_next` with the same entry.
+#[repr(C)]
+pub struct NitLogEntry {
+    pub oid: *mut c_char,
+    pub author_name: *mut c_char,
+    pub author_email: *mut c_char,
+    /// Seconds since the Unix epoch.
+    pub time: i64,
+    pub message: *mut c_char,
+}
+
+impl NitLog {
+    /// The repository the walk started from, which the caller keeps open until it's done.
+    fn repo(&self) -> &Repository {
+        unsafe { &(*self.repo).repo }
+    }
+
+    fn push(&mut self, oid: ObjectId) -> Result<()> {
+        if self.seen.insert(oid) {
+            let commit = self.repo().database().load_commit(&oid)?;
+            self.queue
+                .push((commit.author().time().timestamp(), *oid.bytes()));
+        }
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<Option<(ObjectId, Commit)>> {
+        let oid = match self.queue.pop() {
+            Some((_, oid)) => ObjectId::from(oid),
+            None => return Ok(None),
+        };
+        let commit = self.repo().database().load_commit(&oid)?;
+        for parent in commit.parents() {
+            self.push(*parent)?;
+        }
+        Ok(Some((oid, commit)))
+    }
+}
+
+/// Start walking the history of HEAD. Returns null on error.
+///
+/// # Safety
+/// `repo` must have come from `nit_repository_open`, and must outlive the walk.
+#[no_mangle]
+pub unsafe extern "C" fn nit_log_new(repo: *const NitRepository) -> *mut NitLog {
+    catch(std::ptr::null_mut(), || {
+        let mut log = NitLog {
+            repo,
+            queue: BinaryHeap::new(),
+            seen: HashSet::new(),
+        };
+        if let Some(head) = (*repo).repo.refs().read_head()? {
+            log.push(head)?;
+        }
+        Ok(Box::into_raw(Box::new(log)))
+    })
+}
+
+/// Fill in `entry` with the next commit. Returns 1 if there was one, 0 at the end of the
+/// history, and -1 on error.
+///
+/// # Safety
+/// `log` must have come from `nit_log_new`, and `entry` must point to a `NitLogEntry` that
+/// is zeroed or was filled in by an earlier call.
+#[no_mangle]
+pub unsafe extern "C" fn nit_log_next(log: *mut NitLog, entry: *mut NitLogEntry) -> c_int {
+    catch(-1, || {
+        nit_log_entry_clear(entry);
+        let (oid, commit) = match (*log).next()? {
+            Some(next) => next,
+            None => return Ok(0),
+        };
+
+        *entry = NitLogEntry {
+            oid: into_c_string(oid.to_string())?,
+            author_name: into_c_string(commit.author().name())?,
+            author_email: into_c_string(commit.author().email())?,
+            time: commit.author().time().timestamp(),
+            message: into_c_string(commit.message())?,
+        };
+        Ok(1)
+    })
+}
+
+/// Free the strings in a `NitLogEntry`, leaving it zeroed.
+///
+/// # Safety
+/// `entry` must point to a `NitLogEntry` that is zeroed or was filled in by `nit_log_next`.
+#[no_mangle]
+pub unsafe extern "C" fn nit_log_entry_clear(entry: *mut NitLogEntry) {
+    let entry = &mut *entry;
+    for s in [
+        &mut entry.oid,
+        &mut entry.author_name,
+        &mut entry.author_email,
+        &mut entry.message,
+    ] {
+        nit_string_free(std::mem::replace(s, std::ptr::null_mut()));
+    }
+    entry.time = 0;
+}
+
+/// Finish a walk started with `nit_log_new`.
+///
+/// # Safety
+/// `log` must be null or have come from `nit_log_new`, and not be used again.
+#[no_mangle]
+pub unsafe extern "C" fn nit_log_free(log: *mut NitLog) {
+    if !log.is_null() {
+        drop(Box::from_raw(log));
+    }
+}
+
+/// Free a string returned by nit.
+///
+/// # Safety
+/// `s` must be null or have come from nit, and not be used again.
+#[no_mangle]
+pub unsafe extern "C" fn nit_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// The message for the last error on this thread, or null if there hasn't been one. The
+/// string belongs to nit, and stays valid until the next error on this thread.
+#[no_mangle]
+pub extern "C" fn nit_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(std::ptr::null(), |message| message.as_ptr())
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn c_string(ptr: *mut c_char) -> String {
+        assert!(!ptr.is_null());
+        let s = unsafe { CStr::from_ptr(ptr) }.to_str().unwrap().to_owned();
+        unsafe { nit_string_free(ptr) };
+        s
+    }
+
+    #[test]
+    fn adds_commits_and_walks_history() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        for dir in ["objects", "refs"] {
+            fs::create_dir_all(root.join(".git").join(dir)).unwrap();
+        }
+        fs::write(root.join("hello.txt"), "hello").unwrap();
+        std::env::set_var("GIT_AUTHOR_NAME", "A. U. Thor");
+        std::env::set_var("GIT_AUTHOR_EMAIL", "author@example.com");
+
+        let path = CString::new(root.to_str().unwrap()).unwrap();
+        let repo = unsafe { nit_repository_open(path.as_ptr()) };
+        assert!(!repo.is_null());
+
+        unsafe {
+            assert_eq!(c_string(nit_status(repo)), "?? hello.txt\n");
+
+            let file = CString::new("hello.txt").unwrap();
+            assert_eq!(nit_add(repo, file.as_ptr()), 0);
+            assert_eq!(c_string(nit_status(repo)), "A  hello.txt\n");
+
+            let missing = CString::new("missing.txt").unwrap();
+            assert_eq!(nit_add(repo, missing.as_ptr()), -1);
+            assert!(!nit_last_error().is_null());
+
+            let message = CString::new("First\n").unwrap();
+            let first = c_string(nit_commit(repo, message.as_ptr()));
+            fs::write(root.join("hello.txt"), "goodbye").unwrap();
+            assert_eq!(c_string(nit_status(repo)), " M hello.txt\n");
+            assert_eq!(nit_add(repo, file.as_ptr()), 0);
+            let message = CString::new("Second\n").unwrap();
+            let second = c_string(nit_commit(repo, message.as_ptr()));
+
+            let log = nit_log_new(repo);
+            let mut entry: NitLogEntry = std::mem::zeroed();
+            let mut history = Vec::new();
+            while nit_log_next(log, &mut entry) == 1 {
+                let oid = CStr::from_ptr(entry.oid).to_str().unwrap().to_owned();
+                let message = CStr::from_ptr(entry.message).to_str().unwrap().to_owned();
+                history.push((oid, message));
+            }
+            nit_log_entry_clear(&mut entry);
+            nit_log_free(log);
+            nit_repository_free(repo);
+
+            assert_eq!(
+                history,
+                vec![
+                    (second, "Second\n".to_owned()),
+                    (first, "First\n".to_owned())
+                ]
+            );
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use std::path::Path;
+
+use crate::repository::git_dir;
+use crate::utils::glob_matches;
+use crate::Result;
+
+/// The state an attribute can be given for a path.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttributeValue {
+    /// Given as `name`.
+    Set,
+    /// Given as `-name`.
+    Unset,
+    /// Given as `name=value`.
+    Value(String),
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    pattern: String,
+    attributes: Vec<(String, AttributeValue)>,
+}
+
+/// The attributes assigned to paths by `.gitattributes` in the root of the workspace
+/// and by `.git/info/attributes`, e.g. `*.rs diff=rust`.
+#[derive(Debug, Clone, Default)]
+pub struct Attributes {
+    rules: Vec<Rule>,
+}
+
+impl Attributes {
+    /// Read the attribute files for a workspace. Missing files are treated as empty.
+    pub fn load(root_path: &Path) -> Result<Self> {
+        let mut attributes = Self::default();
+
+        for path in [
+            root_path.join(".gitattributes"),
+            git_dir(root_path).join("info").join("attributes"),
+        ]
+        .iter()
+        {
+            match std::fs::read_to_string(path) {
+                Ok(content) => attributes.parse(&content),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(attributes)
+    }
+
+    /// Add the rules in an attributes file. Later rules take precedence over earlier ones.
+    pub fn parse(&mut self, content: &str) {
+        for line in content.lines() {
+            let mut words = line.split_whitespace();
+            let pattern = match words.next() {
+                Some(pattern) if !pattern.starts_with('#') => pattern,
+                _ => continue,
+            };
+
+            let attributes = words
+                .map(|word| {
+                    if let Some(name) = word.strip_prefix('-') {
+                        (name.to_owned(), AttributeValue::Unset)
+                    } else if let Some((name, value)) = word.split_once('=') {
+                        (name.to_owned(), AttributeValue::Value(value.to_owned()))
+                    } else {
+                        (word.to_owned(), AttributeValue::Set)
+                    }
+                })
+                .collect();
+
+            self.rules.push(Rule {
+                pattern: pattern.to_owned(),
+                attributes,
+            });
+        }
+    }
+
+    /// Look up the value of an attribute for a path, relative to the workspace root.
+    pub fn get(&self, path: &Path, name: &str) -> Option<&AttributeValue> {
+        let path = path.to_string_lossy();
+
+        self.rules
+            .iter()
+            .rev()
+            .filter(|rule| pattern_matches(&rule.pattern, &path))
+            .find_map(|rule| {
+                rule.attributes
+                    .iter()
+                    .rev()
+                    .find(|(attribute, _)| attribute == name)
+                    .map(|(_, value)| value)
+            })
+    }
+
+    /// Every value given to an attribute by any rule, e.g. every diff driver in use.
+    pub fn values_of<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.rules
+            .iter()
+            .flat_map(|rule| rule.attributes.iter())
+            .filter_map(move |(attribute, value)| match value {
+                AttributeValue::Value(value) if attribute == name => Some(value.as_str()),
+                _ => None,
+            })
+    }
+}
+
+/// Patterns without a slash match a path's file name in any directory; patterns with
+/// one match the whole path from the root.
+fn pattern_matches(pattern: &str, path: &str) -> bool {
+    match pattern.strip_prefix('/') {
+        Some(pattern) => glob_matches(pattern.as_bytes(), path.as_bytes()),
+        None if pattern.contains('/') => glob_matches(pattern.as_bytes(), path.as_bytes()),
+        None => {
+            let name = path.rsplit('/').next().unwrap_or(path);
+            glob_matches(pattern.as_bytes(), name.as_bytes())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn matches_paths_to_attributes() {
+        let mut attributes = Attributes::default();
+        attributes
+            .parse("# Comment\n*.rs diff=rust\n/docs/** -diff\nsrc/gen/*.rs diff=generated\n");
+
+        let diff = |path: &str| attributes.get(Path::new(path), "diff").cloned();
+
+        assert_eq!(
+            diff("src/main.rs"),
+            Some(AttributeValue::Value("rust".to_owned()))
+        );
+        assert_eq!(
+            diff("src/gen/parser.rs"),
+            Some(AttributeValue::Value("generated".to_owned()))
+        );
+        assert_eq!(diff("docs/deep/guide.rs"), Some(AttributeValue::Unset));
+        assert_eq!(diff("README.md"), None);
+
+        let drivers: Vec<_> = attributes.values_of("diff").collect();
+        assert_eq!(drivers, vec!["rust", "generated"]);
+    }
+}
@@ -0,0 +1,338 @@
+//! Working out which commit last changed each line of a file.
+
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, FixedOffset};
+use thiserror::Error;
+
+use crate::database::{Commit, Database, ObjectId};
+use crate::diff::{self, Algorithm, EditKind, Whitespace};
+use crate::vfs::Vfs;
+use crate::Result;
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum BlameError {
+    #[error("no such path {0} in {1}")]
+    NoSuchPath(PathBuf, ObjectId),
+}
+
+/// Settings that change how lines are followed back through history.
+#[derive(Debug, Clone, Default)]
+pub struct BlameOptions {
+    /// Which differences in whitespace to ignore, so that a line that only had its
+    /// whitespace changed is blamed on the commit that last really changed it.
+    pub whitespace: Whitespace,
+    /// Commits to look past, such as ones that reformatted everything. The lines they
+    /// changed are blamed on whatever changed the lines they replaced instead.
+    pub ignore_revs: HashSet<ObjectId>,
+}
+
+/// One line of a file, and the commit it came from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlameLine {
+    /// The commit that last changed the line.
+    pub oid: ObjectId,
+    /// The line's number in that commit's version of the file, numbered from 1.
+    pub original_line: usize,
+    /// The file's path in that commit.
+    pub path: PathBuf,
+    /// The line's text, including its trailing newline if it has one.
+    pub text: String,
+}
+
+/// A line's index in the final file, and its index in some commit's version of the file.
+type LinePair = (usize, usize);
+
+/// Lines of the final file that are still to be blamed, waiting at a commit.
+struct Suspect {
+    path: PathBuf,
+    lines: Vec<LinePair>,
+}
+
+/// Blame every line of the file at `path` in the commit `start`. Lines are passed back to
+/// whichever parent has them unchanged, and blamed on the commit where they stop.
+pub fn blame<F: Vfs>(
+    database: &Database<F>,
+    start: &ObjectId,
+    path: &Path,
+    options: &BlameOptions,
+) -> Result<Vec<BlameLine>> {
+    let entry = database
+        .entry_at_path(start, path)?
+        .filter(|entry| !entry.is_tree())
+        .ok_or_else(|| BlameError::NoSuchPath(path.to_owned(), *start))?;
+    let lines = diff::lines(database.load_blob(entry.oid())?.to_bytestr());
+
+    let mut results: Vec<Option<BlameLine>> = vec![None; lines.len()];
+    let mut pending = HashMap::new();
+    let mut queue = BinaryHeap::new();
+    let suspect = Suspect {
+        path: path.to_owned(),
+        lines: (0..lines.len()).map(|i| (i, i)).collect(),
+    };
+    enqueue(database, &mut pending, &mut queue, *start, suspect)?;
+
+    while let Some((_, oid)) = queue.pop() {
+        let suspect: Suspect = match pending.remove(&oid) {
+            Some(suspect) => suspect,
+            None => continue,
+        };
+        let commit = database.load_commit(&oid)?;
+        let current = load_lines(database, &oid, &suspect.path, options.whitespace)?;
+
+        let mut remaining = suspect.lines;
+        for parent in commit.parents() {
+            if remaining.is_empty() {
+                break;
+            }
+            let previous = load_lines(database, parent, &suspect.path, options.whitespace)?;
+            if previous.is_empty() {
+                continue;
+            }
+
+            let (passed, kept) = split_lines(remaining, &match_lines(&previous, &current));
+            remaining = kept;
+            if !passed.is_empty() {
+                let suspect = Suspect {
+                    path: suspect.path.clone(),
+                    lines: passed,
+                };
+                enqueue(database, &mut pending, &mut queue, *parent, suspect)?;
+            }
+        }
+
+        if let (true, Some(parent)) = (options.ignore_revs.contains(&oid), commit.parent()) {
+            let previous = load_lines(database, parent, &suspect.path, options.whitespace)?;
+            let (passed, kept) = split_lines(remaining, &guess_replaced_lines(&previous, &current));
+            remaining = kept;
+            if !passed.is_empty() {
+                let suspect = Suspect {
+                    path: suspect.path.clone(),
+                    lines: passed,
+                };
+                enqueue(database, &mut pending, &mut queue, *parent, suspect)?;
+            }
+        }
+
+        for (index, line) in remaining {
+            results[index] = Some(BlameLine {
+                oid,
+                original_line: line + 1,
+                path: suspect.path.clone(),
+                text: lines[index].text.clone(),
+            });
+        }
+    }
+
+    Ok(results
+        .into_iter()
+        .map(|line| line.expect("every line is blamed on some commit"))
+        .collect())
+}
+
+/// Queue up lines to be looked for in a commit, along with any already waiting there.
+fn enqueue<F: Vfs>(
+    database: &Database<F>,
+    pending: &mut HashMap<ObjectId, Suspect>,
+    queue: &mut BinaryHeap<(DateTime<FixedOffset>, ObjectId)>,
+    oid: ObjectId,
+    suspect: Suspect,
+) -> Result<()> {
+    match pending.get_mut(&oid) {
+        Some(existing) => existing.lines.extend(suspect.lines),
+        None => {
+            let commit: Commit = database.load_commit(&oid)?;
+            queue.push((commit.committer().time(), oid));
+            pending.insert(oid, suspect);
+        }
+    }
+    Ok(())
+}
+
+/// The lines of the file at a path in a commit, or none if it isn't there.
+fn load_lines<F: Vfs>(
+    database: &Database<F>,
+    oid: &ObjectId,
+    path: &Path,
+    whitespace: Whitespace,
+) -> Result<Vec<diff::Line>> {
+    match database.entry_at_path(oid, path)? {
+        Some(entry) if !entry.is_tree() => {
+            let blob = database.load_blob(entry.oid())?;
+            Ok(diff::lines_with(blob.to_bytestr(), whitespace))
+        }
+        _ => Ok(Vec::new()),
+    }
+}
+
+/// Split lines waiting at a commit into those found in a parent, given how the commit's
+/// lines map to the parent's, moved to their places there, and those that weren't.
+fn split_lines(
+    lines: Vec<LinePair>,
+    line_map: &HashMap<usize, usize>,
+) -> (Vec<LinePair>, Vec<LinePair>) {
+    let mut passed = Vec::new();
+    let mut kept = Vec::new();
+    for (index, line) in lines {
+        match line_map.get(&line) {
+            Some(&previous) => passed.push((index, previous)),
+            None => kept.push((index, line)),
+        }
+    }
+    (passed, kept)
+}
+
+/// Map each line of `current` that's unchanged from `previous` to its index there.
+fn match_lines(previous: &[diff::Line], current: &[diff::Line]) -> HashMap<usize, usize> {
+    diff::diff(previous, current, Algorithm::Myers)
+        .into_iter()
+        .filter(|edit| edit.kind == EditKind::Equal)
+        .filter_map(|edit| Some((edit.b_line?.number - 1, edit.a_line?.number - 1)))
+        .collect()
+}
+
+/// Map the changed lines of `current` to the lines of `previous` they most likely took
+/// the place of: within each run of changes, the first deleted line is matched with the
+/// first inserted one, and so on. Inserted lines left over have no match.
+fn guess_replaced_lines(previous: &[diff::Line], current: &[diff::Line]) -> HashMap<usize, usize> {
+    let mut map = HashMap::new();
+    let mut deleted = Vec::new();
+    let mut inserted = Vec::new();
+    let edits = diff::diff(previous, current, Algorithm::Myers);
+
+    for edit in edits.iter().map(Some).chain(std::iter::once(None)) {
+        match edit.map(|edit| (edit.kind, edit)) {
+            Some((EditKind::Delete, edit)) => deleted.extend(&edit.a_line),
+            Some((EditKind::Insert, edit)) => inserted.extend(&edit.b_line),
+            _ => {
+                for (a, b) in deleted.drain(..).zip(inserted.drain(..)) {
+                    map.insert(b.number - 1, a.number - 1);
+                }
+                inserted.clear();
+            }
+        }
+    }
+
+    map
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::{Author, Blob};
+    use crate::vfs::MemoryFs;
+    use chrono::{TimeZone, Utc};
+
+    struct History {
+        database: Database<MemoryFs>,
+        commits: Vec<ObjectId>,
+    }
+
+    impl History {
+        fn new() -> Self {
+            let fs = MemoryFs::new();
+            fs.create_dir_all(Path::new("/objects")).unwrap();
+            Self {
+                database: Database::with_vfs(fs, "/objects"),
+                commits: Vec::new(),
+            }
+        }
+
+        /// Commit a new version of `file.txt` on top of the last commit.
+        fn commit(&mut self, text: &str) -> ObjectId {
+            let parents = self.commits.last().copied().into_iter().collect();
+            self.commit_with_parents(text, parents)
+        }
+
+        fn commit_with_parents(&mut self, text: &str, parents: Vec<ObjectId>) -> ObjectId {
+            let blob = self.database.store(&Blob::new(text.into())).unwrap();
+            let mut tree = b"100644 file.txt\0".to_vec();
+            tree.extend_from_slice(blob.bytes());
+            let tree = self.database.store_raw("tree", &tree).unwrap();
+
+            let time = Utc.timestamp(1_600_000_000 + self.commits.len() as i64, 0);
+            let author = Author::new("A. U. Thor".into(), "author@example.com".into(), time);
+            let commit = Commit::with_parents(parents, tree, author, "Change\n".into());
+            let oid = self.database.store(&commit).unwrap();
+            self.commits.push(oid);
+            oid
+        }
+
+        fn blame(&self, options: &BlameOptions) -> Vec<usize> {
+            let head = self.commits.last().unwrap();
+            blame(&self.database, head, Path::new("file.txt"), options)
+                .unwrap()
+                .iter()
+                .map(|line| {
+                    self.commits
+                        .iter()
+                        .position(|oid| *oid == line.oid)
+                        .unwrap()
+                })
+                .collect()
+        }
+    }
+
+    #[test]
+    fn blames_each_line_on_the_commit_that_last_changed_it() {
+        let mut history = History::new();
+        history.commit("a\nb\nc\n");
+        history.commit("a\nB\nc\n");
+        history.commit("a\nB\nc\nd\n");
+
+        assert_eq!(history.blame(&Default::default()), vec![0, 1, 0, 2]);
+
+        let lines = blame(
+            &history.database,
+            &history.commits[2],
+            Path::new("file.txt"),
+            &Default::default(),
+        )
+        .unwrap();
+        assert_eq!(lines[2].original_line, 3);
+        assert_eq!(lines[3].text, "d\n");
+    }
+
+    #[test]
+    fn can_ignore_whitespace_changes() {
+        let mut history = History::new();
+        history.commit("fn main() {\nbody();\n}\n");
+        history.commit("fn main() {\n    body();\n}\n");
+
+        assert_eq!(history.blame(&Default::default()), vec![0, 1, 0]);
+        let options = BlameOptions {
+            whitespace: Whitespace::IgnoreAll,
+            ..Default::default()
+        };
+        assert_eq!(history.blame(&options), vec![0, 0, 0]);
+    }
+
+    #[test]
+    fn looks_past_ignored_revisions() {
+        let mut history = History::new();
+        history.commit("a\nb\n");
+        history.commit("a\nb\nc\n");
+        let reformat = history.commit("A\nB\nC\nD\n");
+        history.commit("A\nB\nC\nD\ne\n");
+
+        assert_eq!(history.blame(&Default::default()), vec![2, 2, 2, 2, 3]);
+        let options = BlameOptions {
+            ignore_revs: std::iter::once(reformat).collect(),
+            ..Default::default()
+        };
+        assert_eq!(history.blame(&options), vec![0, 0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn follows_lines_into_either_side_of_a_merge() {
+        let mut history = History::new();
+        let base = history.commit("a\nb\n");
+        let left = history.commit("a\nleft\nb\n");
+        let right = history.commit_with_parents("a\nb\nright\n", vec![base]);
+        history.commit_with_parents("a\nleft\nb\nright\n", vec![left, right]);
+
+        assert_eq!(history.blame(&Default::default()), vec![0, 1, 0, 2]);
+    }
+}
@@ -0,0 +1,687 @@
+use anyhow::Context;
+use nit::{
+    database::{Blob, Database, DatabaseEntry},
+    diff,
+    lockfile::LockfileError,
+    repository::{git_dir, Repository},
+};
+use std::fs;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+
+use super::{open_workspace, run_editor, workspace_paths, CommandContext, Env};
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Choose which changes to tracked files to stage, hunk by hunk
+    #[structopt(short = "p", long = "patch")]
+    patch: bool,
+    /// Stage modifications and deletions of tracked files, without adding new files
+    #[structopt(short = "u", long = "update", conflicts_with_all = &["patch", "all"])]
+    update: bool,
+    /// Stage modifications and deletions of tracked files, and add untracked files
+    #[structopt(short = "A", long = "all", conflicts_with = "patch")]
+    all: bool,
+    paths: Vec<String>,
+}
+
+pub fn run(args: Args, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let Args {
+        patch,
+        update,
+        all,
+        paths,
+    } = args;
+    let paths = paths.iter().map(Path::new).collect();
+    let (added, removed) = if patch {
+        add_patch(paths, ctx)?;
+        return Ok(());
+    } else if update || all {
+        add_updates(paths, all, &ctx.root_path)?
+    } else {
+        (add_files_to_repository(paths, &ctx.root_path)?, Vec::new())
+    };
+
+    if ctx.is_verbose() {
+        for path in added {
+            writeln!(ctx.stdout, "add '{}'", path.display())?;
+        }
+        for path in removed {
+            writeln!(ctx.stdout, "remove '{}'", path.display())?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Add the given files to the index, returning the paths whose entries changed.
+pub fn add_files_to_repository(
+    paths: Vec<&Path>,
+    root_path: &Path,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let paths = paths
+        .into_iter()
+        .map(|path| {
+            canonicalize_keeping_symlink(path)
+                .with_context(|| format!("Couldn't add file: {:?}", &path))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    Ok(Repository::open(root_path).add(&paths)?)
+}
+
+/// Make a path absolute, resolving any symlinks in it except the last part, so that a
+/// symlink is added as itself rather than as what it points at.
+fn canonicalize_keeping_symlink(path: &Path) -> std::io::Result<PathBuf> {
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) if fs::symlink_metadata(path)?.file_type().is_symlink() => {
+            let parent = if parent.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                parent
+            };
+            Ok(fs::canonicalize(parent)?.join(name))
+        }
+        _ => fs::canonicalize(path),
+    }
+}
+
+/// Stage every change to tracked files under the given paths (or the whole workspace),
+/// including deletions, and with `untracked`, add any new files too. Returns the paths that
+/// were added or changed, and the paths that were removed.
+pub fn add_updates(
+    paths: Vec<&Path>,
+    untracked: bool,
+    root_path: &Path,
+) -> anyhow::Result<(Vec<PathBuf>, Vec<PathBuf>)> {
+    let repo = Repository::open(root_path);
+    let mut index = repo.configured_index()?;
+    let workspace = repo.workspace()?;
+
+    (|| -> anyhow::Result<_> {
+        index.load_for_update()?;
+
+        let mut prefixes = workspace_paths(&paths, root_path)?;
+        if prefixes.is_empty() {
+            prefixes.push(PathBuf::new());
+        }
+        let in_pathspec = |path: &Path| prefixes.iter().any(|prefix| path.starts_with(prefix));
+
+        let tracked: Vec<PathBuf> = index
+            .entries()
+            .keys()
+            .filter(|path| in_pathspec(path))
+            .cloned()
+            .collect();
+
+        let mut to_add = Vec::new();
+        let mut removed = Vec::new();
+        for path in tracked {
+            match workspace.try_stat_file(&path)? {
+                Some(stat) if stat.is_file() => to_add.push(path),
+                _ => {
+                    index.remove(&path);
+                    removed.push(path);
+                }
+            }
+        }
+
+        if untracked {
+            let ignores = repo.ignores()?;
+            for prefix in &prefixes {
+                if workspace.try_stat_file(prefix)?.is_none() {
+                    continue;
+                }
+                for path in repo.files_to_add(&workspace, &index, &ignores, prefix)? {
+                    if !index.is_tracked(&Path::new(&path)) {
+                        to_add.push(PathBuf::from(path));
+                    }
+                }
+            }
+        }
+
+        let added = repo.add_to_index(&mut index, &workspace, &to_add)?;
+
+        index.write_updates()?;
+        Ok((added, removed))
+    })()
+    .or_else(|e| {
+        if let Some(nit::Error::Lockfile(LockfileError::LockDenied(_))) = e.downcast_ref() {
+            // We couldn't get the lock, so leave it in place.
+        } else {
+            index.lockfile_mut().rollback()?;
+        }
+
+        Err(e)
+    })
+}
+
+/// Go through the changes to tracked files hunk by hunk, asking which to stage. The chosen
+/// hunks are applied to the staged version of each file, leaving the workspace alone.
+pub fn add_patch(paths: Vec<&Path>, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let CommandContext {
+        root_path,
+        env,
+        stdin: input,
+        stdout: output,
+        ..
+    } = ctx;
+    let git_path = git_dir(root_path);
+    let repo = Repository::open(&*root_path);
+    let mut index = repo.configured_index()?;
+    let workspace = open_workspace(root_path)?;
+    let database = Database::new(git_path.join("objects"));
+
+    (|| -> anyhow::Result<()> {
+        index.load_for_update()?;
+
+        let prefixes = workspace_paths(&paths, root_path)?;
+
+        let entries: Vec<_> = index
+            .entries()
+            .iter()
+            .filter(|(path, _)| {
+                prefixes.is_empty() || prefixes.iter().any(|prefix| path.starts_with(prefix))
+            })
+            .map(|(path, entry)| (path.clone(), *entry.oid(), entry.mode()))
+            .collect();
+
+        for (path, oid, mode) in entries {
+            // Deleted files have no hunks to choose from.
+            if workspace.try_stat_file(&path)?.is_none() {
+                continue;
+            }
+
+            let old = database.load_blob(&oid)?.to_bytestr().to_vec();
+            let new = workspace.read_file(&path)?;
+            if old == new {
+                continue;
+            }
+
+            let hunks = diff::diff_hunks(&old, &new, &path, &diff::DiffOptions::default());
+            if hunks.is_empty() {
+                continue;
+            }
+
+            let name = path.to_string_lossy();
+            write!(
+                output,
+                "diff --git a/{0} b/{0}\n--- a/{0}\n+++ b/{0}\n",
+                name
+            )?;
+
+            let (chosen, quit) = choose_hunks(hunks, input, output, env, &repo)?;
+            if !chosen.is_empty() {
+                let data = diff::apply_hunks(&diff::lines(&old), &chosen);
+                let blob_oid = database.store(&Blob::new(data.into_bytes()))?;
+                index.add_from_db(&path, &DatabaseEntry::new(blob_oid, mode));
+            }
+
+            if quit {
+                break;
+            }
+        }
+
+        index.write_updates()?;
+        Ok(())
+    })()
+    .or_else(|e| {
+        if let Some(nit::Error::Lockfile(LockfileError::LockDenied(_))) = e.downcast_ref() {
+            // We couldn't get the lock, so leave it in place.
+        } else {
+            index.lockfile_mut().rollback()?;
+        }
+
+        Err(e)
+    })
+}
+
+const ADD_PATCH_HELP: &str = "\
+y - stage this hunk
+n - do not stage this hunk
+q - quit; do not stage this hunk or any of the remaining ones
+a - stage this hunk and all later hunks in the file
+d - do not stage this hunk or any of the later hunks in the file
+s - split the current hunk into smaller hunks
+e - manually edit the current hunk
+? - print help
+";
+
+/// Ask about each of a file's hunks in turn, returning the hunks to stage and whether the
+/// user asked to stop altogether.
+fn choose_hunks(
+    hunks: Vec<diff::Hunk>,
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+    env: &Env,
+    repo: &Repository,
+) -> anyhow::Result<(Vec<diff::Hunk>, bool)> {
+    let mut queue: std::collections::VecDeque<_> = hunks.into();
+    let mut chosen = Vec::new();
+
+    while let Some(hunk) = queue.pop_front() {
+        write!(output, "{}", hunk)?;
+
+        loop {
+            let pieces = hunk.split();
+            let options = if pieces.len() > 1 {
+                "y,n,q,a,d,s,e,?"
+            } else {
+                "y,n,q,a,d,e,?"
+            };
+            write!(output, "Stage this hunk [{}]? ", options)?;
+            output.flush()?;
+
+            let mut answer = String::new();
+            if input.read_line(&mut answer)? == 0 {
+                return Ok((chosen, true));
+            }
+
+            match answer.trim() {
+                "y" => chosen.push(hunk),
+                "n" => {}
+                "q" => return Ok((chosen, true)),
+                "a" => {
+                    chosen.push(hunk);
+                    chosen.extend(queue);
+                    return Ok((chosen, false));
+                }
+                "d" => return Ok((chosen, false)),
+                "s" if pieces.len() > 1 => {
+                    writeln!(output, "Split into {} hunks.", pieces.len())?;
+                    for piece in pieces.into_iter().rev() {
+                        queue.push_front(piece);
+                    }
+                }
+                "e" => match edit_hunk(&hunk, env, repo)? {
+                    Some(edited) => chosen.push(edited),
+                    None => {
+                        writeln!(output, "Your edited hunk does not apply.")?;
+                        continue;
+                    }
+                },
+                _ => {
+                    write!(output, "{}", ADD_PATCH_HELP)?;
+                    continue;
+                }
+            }
+
+            break;
+        }
+    }
+
+    Ok((chosen, false))
+}
+
+/// Let the user edit a hunk in their editor, and read back what they left.
+fn edit_hunk(
+    hunk: &diff::Hunk,
+    env: &Env,
+    repo: &Repository,
+) -> anyhow::Result<Option<diff::Hunk>> {
+    let path = repo.git_path().join("ADD_EDIT.patch");
+    fs::write(&path, format!("{}{}", hunk, EDIT_HUNK_HELP))?;
+
+    let edited = run_editor(env, repo, &["GIT_EDITOR"], &path);
+    let text = edited.and_then(|_| Ok(fs::read_to_string(&path)?));
+    fs::remove_file(&path)?;
+
+    let text = text?;
+    let body = text.split_once('\n').map_or("", |(_, body)| body);
+    Ok(hunk.edited(body))
+}
+
+const EDIT_HUNK_HELP: &str = "\
+# ---
+# To remove '-' lines, make them ' ' lines (context).
+# To remove '+' lines, delete them.
+# Lines starting with # will be removed.
+";
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::test::{context, init, write_and_commit};
+    use nit::index::Index;
+    use std::fs::File;
+    use std::os::unix::fs::PermissionsExt;
+
+    const REGULAR_MODE: u32 = 0o100644;
+    const EXECUTABLE_MODE: u32 = 0o100755;
+
+    #[test]
+    fn adds_a_file_to_the_index() {
+        let tmp = init().unwrap();
+        let git_dir = tmp.path().join(".git");
+        let index_dir = git_dir.join("index");
+        let mut index = Index::new(index_dir);
+
+        let file_path = tmp.path().join("hello.txt");
+        let mut file = File::create(&file_path).unwrap();
+        file.write_all("Hello, world".as_bytes()).unwrap();
+
+        add_files_to_repository(vec![&file_path], tmp.path()).unwrap();
+
+        index.load_for_update().unwrap();
+
+        let entries: Vec<_> = index
+            .entries()
+            .values()
+            .map(|entry| (entry.mode(), entry.path()))
+            .collect();
+
+        assert_eq!(entries, vec![(REGULAR_MODE, Path::new("hello.txt"))]);
+    }
+
+    #[test]
+    fn adds_an_executable_file_to_the_index() {
+        let tmp = init().unwrap();
+        let git_dir = tmp.path().join(".git");
+        let mut index = Index::new(git_dir.join("index"));
+        let file_path = tmp.path().join("hello.txt");
+        let mut file = File::create(&file_path).unwrap();
+        file.write_all("Hello, world".as_bytes()).unwrap();
+
+        // Set it to executable.
+        let mut permissions = file.metadata().unwrap().permissions();
+        permissions.set_mode(0o755);
+        file.set_permissions(permissions).unwrap();
+
+        add_files_to_repository(vec![&file_path], tmp.path()).unwrap();
+
+        index.load_for_update().unwrap();
+
+        let entries: Vec<_> = index
+            .entries()
+            .values()
+            .map(|entry| (entry.mode(), entry.path()))
+            .collect();
+
+        assert_eq!(entries, vec![(EXECUTABLE_MODE, Path::new("hello.txt"))]);
+    }
+
+    #[test]
+    fn adds_multiple_files_to_index() {
+        let tmp = init().unwrap();
+        let git_dir = tmp.path().join(".git");
+        let mut index = Index::new(git_dir.join("index"));
+
+        let file_path = tmp.path().join("hello.txt");
+        let mut file = File::create(&file_path).unwrap();
+        file.write_all("Hello, world".as_bytes()).unwrap();
+
+        let file_path_2 = tmp.path().join("hohoho.txt");
+        let mut file = File::create(&file_path_2).unwrap();
+        file.write_all("Merry christmas!".as_bytes()).unwrap();
+
+        add_files_to_repository(vec![&file_path, &file_path_2], tmp.path()).unwrap();
+
+        index.load_for_update().unwrap();
+
+        let entries: Vec<_> = index
+            .entries()
+            .values()
+            .map(|entry| (entry.mode(), entry.path()))
+            .collect();
+
+        assert_eq!(
+            entries,
+            vec![
+                (REGULAR_MODE, Path::new("hello.txt")),
+                (REGULAR_MODE, Path::new("hohoho.txt"))
+            ]
+        );
+    }
+
+    #[test]
+    fn incrementally_add_files_to_index() {
+        let tmp = init().unwrap();
+        let git_dir = tmp.path().join(".git");
+        let mut index = Index::new(git_dir.join("index"));
+        let file_path = tmp.path().join("hello.txt");
+
+        let mut file = File::create(&file_path).unwrap();
+        file.write_all("Hello, world".as_bytes()).unwrap();
+        add_files_to_repository(vec![&file_path], tmp.path()).unwrap();
+
+        index.load_for_update().unwrap();
+
+        let entries: Vec<_> = index
+            .entries()
+            .values()
+            .map(|entry| (entry.mode(), entry.path()))
+            .collect();
+
+        assert_eq!(entries, vec![(REGULAR_MODE, Path::new("hello.txt"))]);
+
+        // Add another file, reload and reread entries
+
+        let file_path_2 = tmp.path().join("hohoho.txt");
+        let mut file = File::create(&file_path_2).unwrap();
+        file.write_all("Merry christmas!".as_bytes()).unwrap();
+
+        add_files_to_repository(vec![&file_path_2], tmp.path()).unwrap();
+
+        index.load_for_update().unwrap();
+
+        let entries: Vec<_> = index
+            .entries()
+            .values()
+            .map(|entry| (entry.mode(), entry.path()))
+            .collect();
+
+        assert_eq!(
+            entries,
+            vec![
+                (REGULAR_MODE, Path::new("hello.txt")),
+                (REGULAR_MODE, Path::new("hohoho.txt"))
+            ]
+        );
+    }
+
+    #[test]
+    fn adds_a_directory_to_the_index() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+        let git_dir = tmp_path.join(".git");
+        let mut index = Index::new(git_dir.join("index"));
+
+        std::fs::create_dir(tmp_path.join("a")).unwrap();
+
+        let file_path = tmp_path.join("hello.txt");
+        let mut file = File::create(&file_path).unwrap();
+        file.write_all("Hello, world".as_bytes()).unwrap();
+
+        let file_path_2 = tmp_path.join("hohoho.txt");
+        let mut file = File::create(&file_path_2).unwrap();
+        file.write_all("Merry christmas!".as_bytes()).unwrap();
+
+        let file_path_3 = tmp_path.join("a").join("b.txt");
+        let mut file = File::create(&file_path_3).unwrap();
+        file.write_all("bbbb".as_bytes()).unwrap();
+
+        let file_path_4 = tmp_path.join("a").join("c.txt");
+        let mut file = File::create(&file_path_4).unwrap();
+        file.write_all("cccc".as_bytes()).unwrap();
+
+        add_files_to_repository(vec![&tmp_path.join("a")], tmp_path).unwrap();
+
+        index.load_for_update().unwrap();
+
+        let entries: Vec<_> = index
+            .entries()
+            .values()
+            .map(|entry| (entry.mode(), entry.path()))
+            .collect();
+
+        assert_eq!(
+            entries,
+            vec![
+                (REGULAR_MODE, Path::new("a/b.txt")),
+                (REGULAR_MODE, Path::new("a/c.txt"))
+            ]
+        );
+    }
+
+    #[test]
+    fn fails_for_non_existent_files() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        assert!(add_files_to_repository(vec![&tmp_path.join("a")], tmp_path).is_err());
+    }
+
+    #[test]
+    fn fails_for_unreadable_existent_files() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        let file = File::create(tmp_path.join("shhh.txt")).unwrap();
+
+        let mut permissions = file.metadata().unwrap().permissions();
+        let mode = permissions.mode();
+        // Set it to unreadable.
+        permissions.set_mode(mode & 0b1011111111);
+        file.set_permissions(permissions).unwrap();
+
+        // assert!(add_files_to_repository(vec![&tmp_path.join("shhh.txt")], &tmp_path).is_err());
+    }
+
+    #[test]
+    fn stages_chosen_hunks() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        let original: String = (1..=10).map(|n| format!("line {}\n", n)).collect();
+        write_and_commit(tmp_path, &[("lines.txt", &original)], "First");
+
+        let changed = original
+            .replace("line 1\n", "line one\n")
+            .replace("line 10\n", "line ten\n");
+        std::fs::write(tmp_path.join("lines.txt"), &changed).unwrap();
+
+        let mut input = std::io::Cursor::new("n\ny\n");
+        let mut output = Vec::new();
+        let mut sink = std::io::sink();
+        let mut ctx = context(tmp_path, &mut input, &mut output, &mut sink);
+        add_patch(vec![], &mut ctx).unwrap();
+        drop(ctx);
+
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("diff --git a/lines.txt b/lines.txt\n"));
+        assert_eq!(
+            output.matches("Stage this hunk [y,n,q,a,d,e,?]? ").count(),
+            2
+        );
+
+        let mut index = Index::new(tmp_path.join(".git").join("index"));
+        index.load().unwrap();
+        let staged = *index.entry_for_path(&"lines.txt").unwrap().oid();
+        let database = Database::new(tmp_path.join(".git").join("objects"));
+        let expected = original.replace("line 10\n", "line ten\n");
+        assert_eq!(
+            staged,
+            database
+                .hash_object(&Blob::new(expected.into_bytes()))
+                .unwrap()
+        );
+        assert_eq!(
+            std::fs::read_to_string(tmp_path.join("lines.txt")).unwrap(),
+            changed
+        );
+    }
+
+    #[test]
+    fn adds_updates_to_tracked_files() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        write_and_commit(
+            tmp_path,
+            &[("a.txt", "a"), ("b.txt", "b"), ("dir/c.txt", "c")],
+            "First",
+        );
+        std::fs::write(tmp_path.join("a.txt"), "changed").unwrap();
+        std::fs::remove_file(tmp_path.join("b.txt")).unwrap();
+        std::fs::write(tmp_path.join("d.txt"), "d").unwrap();
+        std::fs::write(tmp_path.join("dir/e.txt"), "e").unwrap();
+
+        let staged = || {
+            let mut index = Index::new(tmp_path.join(".git").join("index"));
+            index.load().unwrap();
+            index
+                .entries()
+                .iter()
+                .map(|(path, entry)| (path.to_string_lossy().into_owned(), *entry.oid()))
+                .collect::<Vec<_>>()
+        };
+        let database = Database::new(tmp_path.join(".git").join("objects"));
+        let blob = |data: &str| {
+            database
+                .hash_object(&Blob::new(data.as_bytes().to_vec()))
+                .unwrap()
+        };
+
+        add_updates(vec![], false, tmp_path).unwrap();
+        assert_eq!(
+            staged(),
+            vec![
+                ("a.txt".to_owned(), blob("changed")),
+                ("dir/c.txt".to_owned(), blob("c")),
+            ]
+        );
+
+        add_updates(vec![&tmp_path.join("dir")], true, tmp_path).unwrap();
+        assert_eq!(
+            staged(),
+            vec![
+                ("a.txt".to_owned(), blob("changed")),
+                ("dir/c.txt".to_owned(), blob("c")),
+                ("dir/e.txt".to_owned(), blob("e")),
+            ]
+        );
+    }
+
+    #[test]
+    fn leaves_ignored_files_out_of_directories() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+        std::fs::write(tmp_path.join(".gitignore"), "*.log\nbuild/\n").unwrap();
+        write_and_commit(tmp_path, &[("kept.log", "tracked anyway")], "First");
+        for (name, contents) in [
+            ("a.txt", "a"),
+            ("debug.log", "ignored"),
+            ("build/out.txt", "ignored"),
+            ("kept.log", "changed"),
+        ] {
+            std::fs::create_dir_all(tmp_path.join(name).parent().unwrap()).unwrap();
+            std::fs::write(tmp_path.join(name), contents).unwrap();
+        }
+        let staged = || {
+            let mut index = Index::new(tmp_path.join(".git/index"));
+            index.load().unwrap();
+            let paths: Vec<_> = index.entries().keys().cloned().collect();
+            paths
+        };
+
+        add_updates(vec![], true, tmp_path).unwrap();
+        assert_eq!(
+            staged(),
+            [".gitignore", "a.txt", "kept.log"].map(PathBuf::from)
+        );
+        let (added, _) = add_updates(vec![], true, tmp_path).unwrap();
+        assert!(added.is_empty());
+
+        std::fs::write(tmp_path.join("b.txt"), "b").unwrap();
+        add_files_to_repository(vec![tmp_path], tmp_path).unwrap();
+        assert_eq!(
+            staged(),
+            [".gitignore", "a.txt", "b.txt", "kept.log"].map(PathBuf::from)
+        );
+
+        // A file that's named is added even if it's ignored.
+        add_files_to_repository(vec![&tmp_path.join("debug.log")], tmp_path).unwrap();
+        assert!(staged().contains(&PathBuf::from("debug.log")));
+    }
+}
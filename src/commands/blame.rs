@@ -0,0 +1,277 @@
+use anyhow::anyhow;
+use anyhow::Context;
+use nit::{
+    blame::BlameOptions,
+    diff,
+    refs::HEAD,
+    repository::{git_dir, Repository},
+    revision::Revision,
+};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+
+use super::CommandContext;
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Ignore whitespace when working out whether a line changed
+    #[structopt(short = "w")]
+    ignore_whitespace: bool,
+    /// Only show the lines in <start>,<end>, where <end> may be +<count>
+    #[structopt(short = "L", value_name = "start,end")]
+    range: Option<String>,
+    /// Look past this commit, blaming the lines it changed on earlier ones
+    #[structopt(long = "ignore-rev", value_name = "rev", number_of_values = 1)]
+    ignore_revs: Vec<String>,
+    /// Look past the commits listed in this file, as with --ignore-rev
+    #[structopt(long = "ignore-revs-file", value_name = "file", number_of_values = 1)]
+    ignore_revs_files: Vec<PathBuf>,
+    /// The commit to start from (defaults to HEAD), and the file to blame
+    #[structopt(required = true, max_values = 2)]
+    args: Vec<String>,
+}
+
+pub fn run(args: Args, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let Args {
+        ignore_whitespace,
+        range,
+        ignore_revs,
+        ignore_revs_files,
+        args,
+    } = args;
+    let (revision, path) = match args.as_slice() {
+        [path] => (HEAD, path),
+        [revision, path] => (revision.as_str(), path),
+        _ => unreachable!("there are one or two arguments"),
+    };
+    let whitespace = match ignore_whitespace {
+        true => diff::Whitespace::IgnoreAll,
+        false => diff::Whitespace::Exact,
+    };
+    let ignored = IgnoredRevs {
+        revs: &ignore_revs,
+        files: &ignore_revs_files,
+    };
+    let msg = blame(
+        revision,
+        Path::new(path),
+        range.as_deref(),
+        whitespace,
+        &ignored,
+        &ctx.root_path,
+    )?;
+    write!(ctx.stdout, "{}", msg)?;
+
+    Ok(())
+}
+
+/// The commits `blame` should look past, given on the command line, as well as those in
+/// the file named by `blame.ignoreRevsFile`.
+pub struct IgnoredRevs<'a> {
+    pub revs: &'a [String],
+    pub files: &'a [PathBuf],
+}
+
+/// Show the commit that last changed each line of a file, with its author and date.
+pub fn blame(
+    revision: &str,
+    path: &Path,
+    range: Option<&str>,
+    whitespace: diff::Whitespace,
+    ignored: &IgnoredRevs,
+    root_path: &Path,
+) -> anyhow::Result<String> {
+    let repo = Repository::new(git_dir(root_path));
+    let database = repo.database();
+    let refs = repo.refs();
+    let start = Revision::resolve(revision, database, refs)?;
+
+    let mut revs = ignored.revs.to_vec();
+    let config_file = repo.config()?.get("blame.ignoreRevsFile")?;
+    let files = config_file
+        .iter()
+        .map(PathBuf::from)
+        .chain(ignored.files.iter().cloned());
+    for file in files {
+        let file = root_path.join(file);
+        let content = fs::read_to_string(&file)
+            .with_context(|| format!("could not open ignore-revs file {}", file.display()))?;
+        revs.extend(
+            content
+                .lines()
+                .map(|line| line.split('#').next().unwrap_or_default().trim())
+                .filter(|line| !line.is_empty())
+                .map(str::to_owned),
+        );
+    }
+
+    let options = BlameOptions {
+        whitespace,
+        ignore_revs: revs
+            .iter()
+            .map(|rev| Revision::resolve(rev, database, refs))
+            .collect::<Result<_, _>>()?,
+    };
+    let lines = nit::blame::blame(database, &start, path, &options)?;
+    let range = match range {
+        Some(range) => parse_line_range(range, lines.len())?,
+        None => 0..lines.len(),
+    };
+
+    let mut authors = HashMap::new();
+    for line in &lines[range.clone()] {
+        if let std::collections::hash_map::Entry::Vacant(entry) = authors.entry(line.oid) {
+            let commit = database.load_commit(&line.oid)?;
+            entry.insert(commit);
+        }
+    }
+    let name_width = authors
+        .values()
+        .map(|commit| commit.author().name().chars().count())
+        .max()
+        .unwrap_or_default();
+    let number_width = range.end.to_string().len();
+
+    let mut msg = String::new();
+    for (number, line) in lines[range.clone()].iter().enumerate() {
+        let commit = &authors[&line.oid];
+        let oid = match commit.parents().is_empty() {
+            true => format!("^{}", line.oid.short(7)),
+            false => line.oid.short(8),
+        };
+        msg.push_str(&format!(
+            "{} ({:name_width$} {} {:>number_width$}) {}",
+            oid,
+            commit.author().name(),
+            commit.author().time().format("%Y-%m-%d %H:%M:%S %z"),
+            range.start + number + 1,
+            line.text,
+            name_width = name_width,
+            number_width = number_width,
+        ));
+        if !line.text.ends_with('\n') {
+            msg.push('\n');
+        }
+    }
+
+    Ok(msg)
+}
+
+/// Parse a range of lines given as `<start>,<end>` or `<start>,+<count>`, numbered from 1,
+/// into the indices of the lines in a file with `len` lines. Either end can be left out.
+fn parse_line_range(range: &str, len: usize) -> anyhow::Result<std::ops::Range<usize>> {
+    let invalid = || anyhow!("invalid -L argument: '{}'", range);
+    let (start, end) = range.split_once(',').unwrap_or((range, ""));
+
+    let start = match start {
+        "" => 1,
+        start => start.parse::<usize>().map_err(|_| invalid())?,
+    };
+    if start == 0 {
+        return Err(invalid());
+    }
+    if start > len {
+        return Err(anyhow!("file has only {} lines", len));
+    }
+
+    let end = match end {
+        "" => len,
+        end => match end.strip_prefix('+') {
+            Some(count) => start - 1 + count.parse::<usize>().map_err(|_| invalid())?,
+            None => end.parse().map_err(|_| invalid())?,
+        },
+    };
+    if end < start {
+        return Err(invalid());
+    }
+
+    Ok(start - 1..end.min(len))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::log::*;
+    use crate::commands::test::{init, write_and_commit, LOG_ONELINE};
+    use nit::config::Config;
+
+    #[test]
+    fn blames_lines_on_commits() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        write_and_commit(tmp_path, &[("file.txt", "one\ntwo\nthree\n")], "First");
+        write_and_commit(tmp_path, &[("file.txt", "one\n  two\nthree\n")], "Indent");
+        write_and_commit(
+            tmp_path,
+            &[("file.txt", "ONE\n  two\nthree\nfour")],
+            "Shout",
+        );
+        let oids: Vec<_> = log(None, &[], &LOG_ONELINE, tmp_path)
+            .unwrap()
+            .lines()
+            .rev()
+            .map(|line| line[..7].to_owned())
+            .collect();
+
+        let blamed = |range, whitespace, ignored: &IgnoredRevs| {
+            blame(
+                HEAD,
+                Path::new("file.txt"),
+                range,
+                whitespace,
+                ignored,
+                tmp_path,
+            )
+            .unwrap()
+        };
+        let none = IgnoredRevs {
+            revs: &[],
+            files: &[],
+        };
+        let msg = blamed(None, diff::Whitespace::Exact, &none);
+        let lines: Vec<_> = msg.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with(&oids[2]));
+        assert!(lines[0].contains(" (A. U. Thor "));
+        assert!(lines[0].ends_with(" 1) ONE"));
+        assert!(lines[1].starts_with(&oids[1]));
+        assert!(lines[2].starts_with(&format!("^{}", oids[0])));
+        assert!(lines[3].ends_with(" 4) four"));
+
+        let starts = |msg: String| -> Vec<String> {
+            msg.lines()
+                .map(|line| line.trim_start_matches('^')[..7].to_owned())
+                .collect()
+        };
+        assert_eq!(
+            starts(blamed(Some("2,+2"), diff::Whitespace::IgnoreAll, &none)),
+            vec![oids[0].clone(), oids[0].clone()]
+        );
+        assert!(blame(
+            HEAD,
+            Path::new("file.txt"),
+            Some("5"),
+            diff::Whitespace::Exact,
+            &none,
+            tmp_path
+        )
+        .is_err());
+
+        std::fs::write(
+            tmp_path.join(".ignore-revs"),
+            format!("# Noise\n{}\n", oids[2]),
+        )
+        .unwrap();
+        let mut config = Config::new(tmp_path.join(".git/config"));
+        config.load_for_update().unwrap();
+        config.set("blame.ignoreRevsFile", ".ignore-revs").unwrap();
+        config.save().unwrap();
+        assert_eq!(
+            starts(blamed(Some("1,1"), diff::Whitespace::Exact, &none)),
+            vec![oids[0].clone()]
+        );
+    }
+}
@@ -0,0 +1,452 @@
+use anyhow::anyhow;
+use nit::{
+    database::ObjectId,
+    merge::{self},
+    refs::{Refs, HEAD},
+    repository::{git_dir, Repository, RepositoryError, Upstream},
+    revision::Revision,
+};
+use std::path::Path;
+use structopt::StructOpt;
+
+use super::status::divergence_counts;
+use super::CommandContext;
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Delete the named branches, which must be merged into HEAD
+    #[structopt(short = "d", long = "delete")]
+    delete: bool,
+    /// Delete the named branches, even if they aren't merged
+    #[structopt(short = "D")]
+    force_delete: bool,
+    /// With --delete, allow deleting unmerged branches
+    #[structopt(short = "f", long = "force")]
+    force: bool,
+    /// When creating a branch, set it up to track the branch it starts from
+    #[structopt(short = "t", long = "track")]
+    track: bool,
+    /// Set the upstream of the named branch (or the current branch)
+    #[structopt(short = "u", long = "set-upstream-to")]
+    set_upstream_to: Option<String>,
+    /// Remove the upstream of the named branch (or the current branch)
+    #[structopt(long = "unset-upstream")]
+    unset_upstream: bool,
+    /// When listing, show each branch's commit and how it compares with its upstream.
+    /// Given twice, name the upstream too
+    #[structopt(short = "v", long = "verbose", parse(from_occurrences))]
+    verbose: u8,
+    /// The branch to create, and where to start it; or the branches to delete
+    args: Vec<String>,
+}
+
+pub fn run(args: Args, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let Args {
+        delete,
+        force_delete,
+        force,
+        track,
+        set_upstream_to: upstream,
+        unset_upstream: unset,
+        verbose,
+        args,
+    } = args;
+    let branch = args.first().map(String::as_str);
+
+    if delete || force_delete {
+        let msg = delete_branches(&args, force || force_delete, &ctx.root_path)?;
+        write!(ctx.info(), "{}", msg)?;
+    } else if let Some(upstream) = upstream {
+        let msg = set_upstream_to(&upstream, branch, &ctx.root_path)?;
+        write!(ctx.info(), "{}", msg)?;
+    } else if unset {
+        unset_upstream(branch, &ctx.root_path)?;
+    } else {
+        let msg = match args.as_slice() {
+            [name] => create_branch(name, None, track, &ctx.root_path)?,
+            [name, start_point] => create_branch(name, Some(start_point), track, &ctx.root_path)?,
+            [] => {
+                let msg = list_branches(verbose, &ctx.root_path)?;
+                write!(ctx.stdout, "{}", msg)?;
+                return Ok(());
+            }
+            _ => return Err(anyhow!("too many arguments for a create operation")),
+        };
+        write!(ctx.info(), "{}", msg)?;
+    }
+
+    Ok(())
+}
+
+pub fn upstream_message(branch: &str, upstream: &Upstream) -> String {
+    let merge_branch = Refs::short_name(&upstream.merge);
+    match upstream.remote.as_str() {
+        "." => format!(
+            "Branch '{}' set up to track local branch '{}'.\n",
+            branch, merge_branch
+        ),
+        remote => format!(
+            "Branch '{}' set up to track remote branch '{}' from '{}'.\n",
+            branch, merge_branch, remote
+        ),
+    }
+}
+
+pub fn create_branch(
+    name: &str,
+    start_point: Option<&str>,
+    track: bool,
+    root_path: &Path,
+) -> anyhow::Result<String> {
+    let repo = Repository::new(git_dir(root_path));
+    let refs = repo.refs();
+
+    let start_oid: ObjectId = match start_point {
+        Some(revision) => Revision::resolve(revision, repo.database(), refs)?,
+        None => refs
+            .read_head()?
+            .ok_or_else(|| anyhow!("Not a valid object name: '{}'.", HEAD))?,
+    };
+
+    let upstream = match start_point {
+        Some(revision) => repo.upstream_for_start_point(revision, track)?,
+        None if track => repo.upstream_for_start_point(HEAD, track)?,
+        None => None,
+    };
+
+    refs.create_branch(
+        name,
+        &start_oid,
+        &format!("branch: Created from {}", start_point.unwrap_or(HEAD)),
+    )?;
+
+    match upstream {
+        Some(upstream) => {
+            repo.set_upstream(name, &upstream)?;
+            Ok(upstream_message(name, &upstream))
+        }
+        None => Ok(String::new()),
+    }
+}
+
+/// Set the upstream of a branch (or the current branch) to an existing local or
+/// remote-tracking branch.
+pub fn set_upstream_to(
+    upstream: &str,
+    branch: Option<&str>,
+    root_path: &Path,
+) -> anyhow::Result<String> {
+    let repo = Repository::new(git_dir(root_path));
+    let branch = current_or_named_branch(&repo, branch)?;
+
+    if repo.refs().read_ref(upstream)?.is_none() {
+        return Err(RepositoryError::NoSuchUpstream(upstream.to_owned()).into());
+    }
+
+    let upstream = repo
+        .upstream_for_start_point(upstream, true)?
+        .ok_or_else(|| RepositoryError::NotABranch(upstream.to_owned()))?;
+
+    repo.set_upstream(&branch, &upstream)?;
+    Ok(upstream_message(&branch, &upstream))
+}
+
+pub fn unset_upstream(branch: Option<&str>, root_path: &Path) -> anyhow::Result<()> {
+    let repo = Repository::new(git_dir(root_path));
+    let branch = current_or_named_branch(&repo, branch)?;
+
+    if repo.get_upstream(&branch)?.is_none() {
+        return Err(anyhow!("Branch '{}' has no upstream information", branch));
+    }
+
+    repo.unset_upstream(&branch)?;
+    Ok(())
+}
+
+fn current_or_named_branch(repo: &Repository, branch: Option<&str>) -> anyhow::Result<String> {
+    match branch {
+        Some(branch) => {
+            if repo
+                .refs()
+                .read_ref(&format!("refs/heads/{}", branch))?
+                .is_none()
+            {
+                return Err(anyhow!("branch '{}' does not exist", branch));
+            }
+            Ok(branch.to_owned())
+        }
+        None => {
+            let current_ref = repo.refs().current_ref()?;
+            match current_ref.strip_prefix("refs/heads/") {
+                Some(branch) => Ok(branch.to_owned()),
+                None => Err(anyhow!("HEAD is detached, so it has no upstream")),
+            }
+        }
+    }
+}
+
+pub fn list_branches(verbose: u8, root_path: &Path) -> anyhow::Result<String> {
+    let repo = Repository::new(git_dir(root_path));
+    let refs = repo.refs();
+    let current_ref = refs.current_ref()?;
+
+    let mut branches: Vec<(String, Option<ObjectId>)> = Vec::new();
+    if current_ref == HEAD {
+        if let Some(oid) = refs.read_head()? {
+            branches.push((format!("(HEAD detached at {})", oid.short(7)), Some(oid)));
+        }
+    }
+    for branch in refs.list(Some("refs/heads/"))? {
+        branches.push((branch.name, branch.oid));
+    }
+
+    let width = branches
+        .iter()
+        .map(|(name, _)| Refs::short_name(name).chars().count())
+        .max()
+        .unwrap_or(0);
+
+    let mut msg = String::new();
+    for (name, oid) in &branches {
+        let marker = if *name == current_ref || name.starts_with('(') {
+            '*'
+        } else {
+            ' '
+        };
+        let short_name = Refs::short_name(name);
+
+        if verbose == 0 {
+            msg.push_str(&format!("{} {}\n", marker, short_name));
+            continue;
+        }
+
+        let oid = match oid {
+            Some(oid) => oid,
+            None => continue,
+        };
+        let title = repo.database().load_commit(oid)?.title_line().to_owned();
+
+        let tracking = match name.strip_prefix("refs/heads/") {
+            Some(branch) => repo.divergence(branch)?,
+            None => None,
+        };
+        let tracking = tracking.and_then(|divergence| {
+            let counts = divergence_counts(&divergence);
+            match (verbose, counts) {
+                (1, Some(counts)) => Some(counts),
+                (1, None) => None,
+                (_, Some(counts)) => Some(format!(
+                    "{}: {}",
+                    Refs::short_name(&divergence.upstream),
+                    counts
+                )),
+                (_, None) => Some(Refs::short_name(&divergence.upstream).to_owned()),
+            }
+        });
+
+        msg.push_str(&format!(
+            "{} {:width$} {} ",
+            marker,
+            short_name,
+            oid.short(7),
+            width = width
+        ));
+        if let Some(tracking) = tracking {
+            msg.push_str(&format!("[{}] ", tracking));
+        }
+        msg.push_str(&title);
+        msg.push('\n');
+    }
+
+    Ok(msg)
+}
+
+pub fn delete_branches(names: &[String], force: bool, root_path: &Path) -> anyhow::Result<String> {
+    let repo = Repository::new(git_dir(root_path));
+    let database = repo.database();
+    let refs = repo.refs();
+
+    let current_ref = refs.current_ref()?;
+    let head_oid = refs.read_head()?;
+    let mut msg = String::new();
+
+    for name in names {
+        if Refs::short_name(&current_ref) == name {
+            return Err(anyhow!(
+                "Cannot delete branch '{}' checked out at '{}'",
+                name,
+                root_path.display()
+            ));
+        }
+
+        let oid = refs
+            .read_ref(&format!("refs/heads/{}", name))?
+            .ok_or_else(|| anyhow!("branch '{}' not found.", name))?;
+
+        if !force {
+            let merged = match &head_oid {
+                Some(head_oid) => merge::is_ancestor(database, &oid, head_oid)?,
+                None => false,
+            };
+
+            if !merged {
+                return Err(anyhow!(
+                    "The branch '{}' is not fully merged.\n\
+                     If you are sure you want to delete it, run 'nit branch -D {}'.",
+                    name,
+                    name
+                ));
+            }
+        }
+
+        let oid = refs.delete_branch(name)?;
+        repo.unset_upstream(name)?;
+        msg.push_str(&format!(
+            "Deleted branch {} (was {}).\n",
+            name,
+            oid.short(7)
+        ));
+    }
+
+    Ok(msg)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::test::{init, write_and_commit};
+    use crate::commands::{checkout::*, status::*};
+
+    #[test]
+    fn deletes_only_merged_branches_without_force() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        write_and_commit(tmp_path, &[("hello.txt", "one")], "First");
+        create_branch("merged", None, false, tmp_path).unwrap();
+        checkout(
+            "HEAD",
+            CheckoutMode::NewBranch(NewBranch {
+                name: "topic/unmerged",
+                track: false,
+            }),
+            tmp_path,
+        )
+        .unwrap();
+        write_and_commit(tmp_path, &[("hello.txt", "two")], "Second");
+        let tip = std::fs::read_to_string(tmp_path.join(".git/refs/heads/topic/unmerged")).unwrap();
+        checkout("master", CheckoutMode::Auto, tmp_path).unwrap();
+
+        let msg = delete_branches(&["merged".to_owned()], false, tmp_path).unwrap();
+        assert!(msg.starts_with("Deleted branch merged (was "));
+
+        let err = delete_branches(&["topic/unmerged".to_owned()], false, tmp_path).unwrap_err();
+        assert!(err.to_string().contains("is not fully merged"));
+
+        let msg = delete_branches(&["topic/unmerged".to_owned()], true, tmp_path).unwrap();
+        assert_eq!(
+            msg,
+            format!("Deleted branch topic/unmerged (was {}).\n", &tip[0..7])
+        );
+        assert!(!tmp_path.join(".git/refs/heads/topic").exists());
+
+        assert!(delete_branches(&["master".to_owned()], true, tmp_path).is_err());
+    }
+
+    #[test]
+    fn configures_branch_upstreams() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        write_and_commit(tmp_path, &[("hello.txt", "one")], "First");
+        let git_path = tmp_path.join(".git");
+        let remote_ref = git_path.join("refs/remotes/origin/main");
+        std::fs::create_dir_all(remote_ref.parent().unwrap()).unwrap();
+        std::fs::copy(git_path.join("refs/heads/master"), &remote_ref).unwrap();
+
+        let msg = create_branch("from-remote", Some("origin/main"), false, tmp_path).unwrap();
+        assert_eq!(
+            msg,
+            "Branch 'from-remote' set up to track remote branch 'main' from 'origin'.\n"
+        );
+
+        let msg = create_branch("from-local", Some("master"), false, tmp_path).unwrap();
+        assert_eq!(msg, "");
+
+        let msg = set_upstream_to("master", Some("from-local"), tmp_path).unwrap();
+        assert_eq!(
+            msg,
+            "Branch 'from-local' set up to track local branch 'master'.\n"
+        );
+        assert!(set_upstream_to("origin/nope", Some("from-local"), tmp_path).is_err());
+
+        let repo = Repository::new(&git_path);
+        assert_eq!(
+            repo.upstream_of("from-remote").unwrap().as_deref(),
+            Some("refs/remotes/origin/main")
+        );
+        assert_eq!(
+            repo.upstream_of("from-local").unwrap().as_deref(),
+            Some("refs/heads/master")
+        );
+        assert_eq!(repo.upstream_of("master").unwrap(), None);
+
+        unset_upstream(Some("from-local"), tmp_path).unwrap();
+        assert_eq!(repo.upstream_of("from-local").unwrap(), None);
+    }
+
+    #[test]
+    fn counts_commits_ahead_of_and_behind_upstream() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        write_and_commit(tmp_path, &[("hello.txt", "one")], "First");
+        let git_path = tmp_path.join(".git");
+        let remote_ref = git_path.join("refs/remotes/origin/main");
+        std::fs::create_dir_all(remote_ref.parent().unwrap()).unwrap();
+        std::fs::copy(git_path.join("refs/heads/master"), &remote_ref).unwrap();
+        set_upstream_to("origin/main", None, tmp_path).unwrap();
+
+        assert_eq!(
+            status_header(tmp_path, true).unwrap(),
+            "On branch master\nYour branch is up to date with 'origin/main'.\n"
+        );
+
+        write_and_commit(tmp_path, &[("hello.txt", "two")], "Second");
+        write_and_commit(tmp_path, &[("hello.txt", "three")], "Third");
+
+        assert_eq!(
+            status_header(tmp_path, true).unwrap(),
+            "On branch master\n\
+             Your branch is ahead of 'origin/main' by 2 commits.\n  \
+             (use \"nit push\" to publish your local commits)\n"
+        );
+        assert_eq!(
+            status_header(tmp_path, false).unwrap(),
+            "## master...origin/main [ahead 2]\n"
+        );
+
+        create_branch("lagging", Some("master~2"), false, tmp_path).unwrap();
+        set_upstream_to("master", Some("lagging"), tmp_path).unwrap();
+        checkout("lagging", CheckoutMode::Auto, tmp_path).unwrap();
+        write_and_commit(tmp_path, &[("other.txt", "four")], "Fourth");
+
+        assert_eq!(
+            status_header(tmp_path, false).unwrap(),
+            "## lagging...master [ahead 1, behind 2]\n"
+        );
+
+        let listing = list_branches(1, tmp_path).unwrap();
+        let lines: Vec<_> = listing.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("* lagging "));
+        assert!(lines[0].ends_with(" [ahead 1, behind 2] Fourth"));
+        assert!(lines[1].starts_with("  master  "));
+        assert!(lines[1].ends_with(" [ahead 2] Third"));
+
+        let listing = list_branches(2, tmp_path).unwrap();
+        assert!(listing.contains(" [origin/main: ahead 2] Third\n"));
+
+        assert_eq!(list_branches(0, tmp_path).unwrap(), "* lagging\n  master\n");
+    }
+}
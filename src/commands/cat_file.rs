@@ -0,0 +1,245 @@
+use anyhow::anyhow;
+use nit::{
+    repository::{git_dir, Repository},
+    revision::{Revision, RevisionError},
+};
+use std::io::{BufRead, Write};
+use std::path::Path;
+use structopt::StructOpt;
+
+use super::ls_tree::ls_tree;
+use super::{CommandContext, ExitStatus};
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Show the object's type
+    #[structopt(short = "t")]
+    kind: bool,
+    /// Show the object's size
+    #[structopt(short = "s")]
+    size: bool,
+    /// Show the object's contents, listing a tree's entries as ls-tree does
+    #[structopt(short = "p")]
+    pretty: bool,
+    /// Show nothing, but exit with a non-zero status if there's no such object
+    #[structopt(short = "e")]
+    exists: bool,
+    /// Show each object's ObjectId, type and size, then its contents and a newline. With
+    /// <format>, show that in place of the first line, as with --batch-check
+    #[structopt(
+        long = "batch",
+        value_name = "format",
+        require_equals = true,
+        conflicts_with_all = &["kind", "size", "pretty", "exists", "batch-check"]
+    )]
+    batch: Option<Option<String>>,
+    /// Show each object's ObjectId, type and size, or "<name> missing" if there's no such
+    /// object. With <format>, show that instead, with %(objectname), %(objecttype),
+    /// %(objectsize) and %(rest), what follows the name on its line, filled in
+    #[structopt(
+        long = "batch-check",
+        value_name = "format",
+        require_equals = true,
+        conflicts_with_all = &["kind", "size", "pretty", "exists"]
+    )]
+    batch_check: Option<Option<String>>,
+    /// The object, after the type it should be if none of -t, -s, -p or -e is given
+    args: Vec<String>,
+}
+
+pub fn run(args: Args, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let Args {
+        kind,
+        size,
+        pretty,
+        exists,
+        batch,
+        batch_check,
+        args,
+    } = args;
+    let batch = match (batch, batch_check) {
+        (Some(format), _) => Some((format, true)),
+        (None, Some(format)) => Some((format, false)),
+        (None, None) => None,
+    };
+    if let Some((format, contents)) = batch {
+        let format = format.as_deref().unwrap_or(BATCH_FORMAT);
+        cat_file_batch(format, contents, ctx.stdin, ctx.stdout, &ctx.root_path)?;
+        return Ok(());
+    }
+
+    let (query, object) = match (kind, size, pretty, exists, args.as_slice()) {
+        (true, false, false, false, [object]) => (CatFileQuery::Type, object),
+        (false, true, false, false, [object]) => (CatFileQuery::Size, object),
+        (false, false, true, false, [object]) => (CatFileQuery::Pretty, object),
+        (false, false, false, true, [object]) => (CatFileQuery::Exists, object),
+        (false, false, false, false, [kind, object]) => {
+            (CatFileQuery::Contents(kind.clone()), object)
+        }
+        _ => {
+            return Err(anyhow!(
+                "usage: nit cat-file (-t | -s | -p | -e | <type>) <object>"
+            ))
+        }
+    };
+    match cat_file(object, &query, &ctx.root_path)? {
+        Some(output) => ctx.stdout.write_all(&output)?,
+        None => return Err(ExitStatus::Failure.into()),
+    }
+
+    Ok(())
+}
+
+/// What `cat-file` should show about an object.
+pub enum CatFileQuery {
+    Type,
+    Size,
+    /// The object's contents, or for a tree, its entries.
+    Pretty,
+    /// Nothing: only whether the object exists.
+    Exists,
+    /// The object's contents, so long as it's of the given type.
+    Contents(String),
+}
+
+/// Show something about an object, as it is after any replacement. With `Exists`, there's
+/// no output, and `None` is returned if there's no such object.
+pub fn cat_file(
+    object: &str,
+    query: &CatFileQuery,
+    root_path: &Path,
+) -> anyhow::Result<Option<Vec<u8>>> {
+    let repo = Repository::new(git_dir(root_path));
+    let database = repo.database();
+    let oid = match Revision::resolve_object(object, database, repo.refs()) {
+        Ok(oid) => oid,
+        Err(nit::Error::Revision(_)) if matches!(query, CatFileQuery::Exists) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let (kind, data) = database.read_object(&database.replacement(&oid)?)?;
+
+    let output = match query {
+        CatFileQuery::Type => format!("{}\n", kind).into_bytes(),
+        CatFileQuery::Size => format!("{}\n", data.len()).into_bytes(),
+        CatFileQuery::Exists => Vec::new(),
+        CatFileQuery::Pretty if kind == "tree" => {
+            ls_tree(&oid.to_string(), false, false, &[], root_path)?.into_bytes()
+        }
+        CatFileQuery::Pretty => data,
+        CatFileQuery::Contents(expected) if *expected == kind => data,
+        CatFileQuery::Contents(_) => return Err(anyhow!("{}: bad file", object)),
+    };
+
+    Ok(Some(output))
+}
+
+/// The line `cat-file --batch` and `--batch-check` show for each object by default.
+pub const BATCH_FORMAT: &str = "%(objectname) %(objecttype) %(objectsize)";
+
+/// Answer a question about each object named on a line of `input`, as `cat-file --batch`
+/// does: for each, write a line in `format`, followed with `contents` by the object's
+/// contents and a newline. If `format` uses `%(rest)`, the name ends at the first
+/// whitespace on the line. Names that don't resolve are answered with `<name> missing`.
+/// The output is flushed after each answer, so that another program can ask one question
+/// at a time.
+pub fn cat_file_batch(
+    format: &str,
+    contents: bool,
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+    root_path: &Path,
+) -> anyhow::Result<()> {
+    let repo = Repository::new(git_dir(root_path));
+    let database = repo.database();
+    let split_rest = format.contains("%(rest)");
+
+    for line in input.lines() {
+        let line = line?;
+        let (name, rest) = match split_rest {
+            true => line.split_once(char::is_whitespace).unwrap_or((&line, "")),
+            false => (line.as_str(), ""),
+        };
+
+        let oid = match Revision::resolve_object(name, database, repo.refs()) {
+            Ok(oid) => oid,
+            Err(nit::Error::Revision(RevisionError::AmbiguousObjectId(_))) => {
+                writeln!(output, "{} ambiguous", name)?;
+                output.flush()?;
+                continue;
+            }
+            Err(nit::Error::Revision(_)) => {
+                writeln!(output, "{} missing", name)?;
+                output.flush()?;
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let (kind, data) = database.read_object(&database.replacement(&oid)?)?;
+
+        let header = format
+            .replace("%(objectname)", &oid.to_string())
+            .replace("%(objecttype)", &kind)
+            .replace("%(objectsize)", &data.len().to_string())
+            .replace("%(rest)", rest);
+        writeln!(output, "{}", header)?;
+        if contents {
+            output.write_all(&data)?;
+            writeln!(output)?;
+        }
+        output.flush()?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::test::{init, write_and_commit};
+
+    #[test]
+    fn answers_object_queries_in_batches() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        write_and_commit(tmp_path, &[("dir/a.txt", "hello\n")], "First");
+        let repo = Repository::new(tmp_path.join(".git"));
+        let head = repo.refs().read_head().unwrap().unwrap();
+        let blob = repo
+            .database()
+            .entry_at_path(&head, Path::new("dir/a.txt"))
+            .unwrap()
+            .unwrap();
+
+        let mut input = std::io::Cursor::new("HEAD:dir/a.txt\nnope\n");
+        let mut output = Vec::new();
+        cat_file_batch(BATCH_FORMAT, true, &mut input, &mut output, tmp_path).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!("{} blob 6\nhello\n\nnope missing\n", blob.oid())
+        );
+
+        let mut input = std::io::Cursor::new("HEAD some words\n");
+        let mut output = Vec::new();
+        let format = "%(objecttype): %(rest)";
+        cat_file_batch(format, false, &mut input, &mut output, tmp_path).unwrap();
+        assert_eq!(output, b"commit: some words\n");
+
+        let cat = |object: &str, query| cat_file(object, &query, tmp_path);
+        assert_eq!(
+            cat("HEAD", CatFileQuery::Type).unwrap().unwrap(),
+            b"commit\n"
+        );
+        assert_eq!(
+            cat("HEAD:dir/a.txt", CatFileQuery::Size).unwrap().unwrap(),
+            b"6\n"
+        );
+        let tree = cat("HEAD:dir", CatFileQuery::Pretty).unwrap().unwrap();
+        assert_eq!(
+            String::from_utf8(tree).unwrap(),
+            format!("100644 blob {}\ta.txt\n", blob.oid())
+        );
+        assert_eq!(cat("HEAD:nope", CatFileQuery::Exists).unwrap(), None);
+        assert!(cat("HEAD", CatFileQuery::Contents("blob".to_owned())).is_err());
+    }
+}
@@ -0,0 +1,103 @@
+use nit::ignore::Ignores;
+use std::path::Path;
+use structopt::StructOpt;
+
+use super::{workspace_paths, CommandContext, ExitStatus};
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Show the ignore file, line and pattern that decided each path
+    #[structopt(short = "v", long = "verbose")]
+    verbose: bool,
+    #[structopt(required = true)]
+    paths: Vec<String>,
+}
+
+pub fn run(args: Args, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let Args { verbose, paths } = args;
+    let paths: Vec<_> = paths.iter().map(Path::new).collect();
+    let msg = check_ignore(&paths, verbose, &ctx.root_path)?;
+    write!(ctx.stdout, "{}", msg)?;
+    if msg.is_empty() {
+        return Err(ExitStatus::Failure.into());
+    }
+
+    Ok(())
+}
+
+/// List the given paths that are ignored, or with `verbose`, the pattern that decided each
+/// path that any pattern matched, as `source:line:pattern<TAB>path`.
+pub fn check_ignore(paths: &[&Path], verbose: bool, root_path: &Path) -> anyhow::Result<String> {
+    let ignores = Ignores::load(root_path)?;
+
+    let mut msg = String::new();
+    for (given, path) in paths.iter().zip(workspace_paths(paths, root_path)?) {
+        let is_dir = root_path.join(&path).is_dir();
+        let found = match ignores.matching(&path, is_dir) {
+            Some(found) if verbose || !found.negated => found,
+            _ => continue,
+        };
+
+        if verbose {
+            msg.push_str(&format!(
+                "{}:{}:{}\t",
+                found.source.display(),
+                found.line,
+                found.pattern
+            ));
+        }
+        msg.push_str(&format!("{}\n", given.display()));
+    }
+
+    Ok(msg)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::status::*;
+    use crate::commands::test::init;
+    use std::path::PathBuf;
+
+    #[test]
+    fn reports_ignored_files() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        std::fs::create_dir_all(tmp_path.join("target/debug")).unwrap();
+        std::fs::write(tmp_path.join("target/debug/nit"), "").unwrap();
+        std::fs::write(tmp_path.join(".gitignore"), "/target\n*.log\n!keep.log\n").unwrap();
+        std::fs::write(tmp_path.join("debug.log"), "").unwrap();
+        std::fs::write(tmp_path.join("keep.log"), "").unwrap();
+
+        assert_eq!(
+            get_repository_status(true, tmp_path).unwrap(),
+            "?? .gitignore\n?? keep.log\n!! debug.log\n!! target/\n"
+        );
+
+        let paths = [
+            tmp_path.join("debug.log"),
+            tmp_path.join("keep.log"),
+            tmp_path.join("target/debug/nit"),
+            tmp_path.join(".gitignore"),
+        ];
+        let paths: Vec<_> = paths.iter().map(PathBuf::as_path).collect();
+
+        let msg = check_ignore(&paths, false, tmp_path).unwrap();
+        assert_eq!(
+            msg,
+            format!("{}\n{}\n", paths[0].display(), paths[2].display())
+        );
+
+        let msg = check_ignore(&paths, true, tmp_path).unwrap();
+        assert_eq!(
+            msg,
+            format!(
+                ".gitignore:2:*.log\t{}\n.gitignore:3:!keep.log\t{}\n.gitignore:1:/target\t{}\n",
+                paths[0].display(),
+                paths[1].display(),
+                paths[2].display()
+            )
+        );
+    }
+}
@@ -0,0 +1,63 @@
+use anyhow::anyhow;
+use nit::refs::{self, RefFormatOptions};
+use structopt::StructOpt;
+
+use super::{CommandContext, ExitStatus};
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Allow names with only one component, like `HEAD`
+    #[structopt(long = "allow-onelevel")]
+    allow_onelevel: bool,
+    /// Allow a single `*` in the name, as in a refspec's pattern
+    #[structopt(long = "refspec-pattern")]
+    refspec_pattern: bool,
+    /// Drop leading slashes and squash runs of slashes, and print the name that results
+    #[structopt(long = "normalize")]
+    normalize: bool,
+    /// Check the name as a branch name, and print it
+    #[structopt(long = "branch", conflicts_with_all = &["allow-onelevel", "refspec-pattern", "normalize"])]
+    branch: bool,
+    /// The name to check: a full ref name such as `refs/heads/master`, or a branch name
+    /// with --branch
+    name: String,
+}
+
+pub fn run(args: Args, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let Args {
+        allow_onelevel,
+        refspec_pattern,
+        normalize,
+        branch,
+        name,
+    } = args;
+    let options = RefFormatOptions {
+        allow_onelevel,
+        refspec_pattern,
+        normalize,
+    };
+    match check_ref_format_command(&name, options, branch)? {
+        Some(name) if branch || normalize => writeln!(ctx.stdout, "{}", name)?,
+        Some(_) => {}
+        None => return Err(ExitStatus::Failure.into()),
+    }
+
+    Ok(())
+}
+
+/// Check a ref name, returning it as it should be written if it's allowed. Branch names are
+/// an error if they aren't allowed, as they are for `git check-ref-format --branch`.
+fn check_ref_format_command(
+    name: &str,
+    options: RefFormatOptions,
+    branch: bool,
+) -> anyhow::Result<Option<String>> {
+    if !branch {
+        return Ok(refs::check_ref_format(name, options));
+    }
+
+    match refs::is_valid_branch_name(name) {
+        true => Ok(Some(name.to_owned())),
+        false => Err(anyhow!("'{}' is not a valid branch name", name)),
+    }
+}
@@ -0,0 +1,403 @@
+use anyhow::anyhow;
+use nit::{
+    database::Database,
+    lockfile::LockfileError,
+    migration::Migration,
+    refs::{Refs, HEAD},
+    repository::{git_dir, Repository},
+    revision::Revision,
+};
+use std::path::Path;
+use structopt::StructOpt;
+
+use super::branch::upstream_message;
+use super::{open_workspace, CommandContext};
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Create a new branch at <revision> (or HEAD) and switch to it
+    #[structopt(short = "b")]
+    new_branch: Option<String>,
+    /// Set up the new branch to track the branch it starts from
+    #[structopt(short = "t", long = "track", requires = "new-branch")]
+    track: bool,
+    revision: Option<String>,
+}
+
+pub fn run(args: Args, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let Args {
+        new_branch,
+        track,
+        revision,
+    } = args;
+    let mode = match new_branch.as_deref() {
+        Some(name) => CheckoutMode::NewBranch(NewBranch { name, track }),
+        None => CheckoutMode::Auto,
+    };
+    let revision = match (&revision, &mode) {
+        (Some(revision), _) => revision.as_str(),
+        (None, CheckoutMode::NewBranch(_)) => HEAD,
+        (None, _) => return Err(anyhow!("You must specify a revision to check out")),
+    };
+
+    let msg = checkout(revision, mode, &ctx.root_path)?;
+    write!(ctx.info_stderr(), "{}", msg)?;
+
+    Ok(())
+}
+
+const DETACHED_HEAD_MESSAGE: &str = "\
+You are in 'detached HEAD' state. You can look around, make experimental
+changes and commit them, and you can discard any commits you make in this
+state without impacting any branches by performing another checkout.
+
+If you want to create a new branch to retain commits you create, you may
+do so (now or later) by using the branch command. Example:
+
+  nit branch <new-branch-name>
+";
+
+/// A branch to create at the checkout target before switching to it.
+pub struct NewBranch<'a> {
+    pub name: &'a str,
+    /// Whether to set the branch's upstream to the ref it starts from.
+    pub track: bool,
+}
+
+/// How HEAD should be updated once the workspace has been moved to the target revision.
+pub enum CheckoutMode<'a> {
+    /// Point HEAD at the revision if it names a branch, otherwise detach HEAD.
+    Auto,
+    /// Detach HEAD at the revision, even if it names a branch.
+    Detach,
+    /// Create a new branch at the revision and point HEAD at it.
+    NewBranch(NewBranch<'a>),
+}
+
+pub fn checkout(revision: &str, mode: CheckoutMode, root_path: &Path) -> anyhow::Result<String> {
+    let git_path = git_dir(root_path);
+    let mut index = Repository::new(&git_path).configured_index()?;
+    let workspace = open_workspace(root_path)?;
+    let database = Database::new(git_path.join("objects"));
+    let refs = Refs::new(&git_path);
+
+    (|| -> anyhow::Result<String> {
+        index.load_for_update()?;
+
+        let current_ref = refs.current_ref()?;
+        let current_oid = refs.read_head()?;
+        let target_oid = Revision::resolve(revision, &database, &refs)?;
+
+        // Check the new branch can be created before touching the workspace.
+        let upstream = match &mode {
+            CheckoutMode::NewBranch(branch) => {
+                refs.check_new_branch(branch.name)?;
+                Repository::new(&git_path).upstream_for_start_point(revision, branch.track)?
+            }
+            _ => None,
+        };
+
+        let diff = database.tree_diff(current_oid.as_ref(), Some(&target_oid))?;
+        Migration::new(&database, &mut index, &workspace, diff).apply_changes()?;
+        index.write_updates()?;
+
+        let mut msg = String::new();
+
+        let from = match &current_oid {
+            Some(oid) if current_ref == HEAD => oid.to_string(),
+            _ => Refs::short_name(&current_ref).to_owned(),
+        };
+        let to = match &mode {
+            CheckoutMode::NewBranch(branch) => branch.name,
+            _ => revision,
+        };
+        let reflog_msg = format!("checkout: moving from {} to {}", from, to);
+
+        match &mode {
+            CheckoutMode::Auto => refs.set_head(revision, &target_oid, &reflog_msg)?,
+            CheckoutMode::Detach => refs.detach_head(&target_oid, &reflog_msg)?,
+            CheckoutMode::NewBranch(branch) => {
+                refs.create_branch(
+                    branch.name,
+                    &target_oid,
+                    &format!("branch: Created from {}", revision),
+                )?;
+                if let Some(upstream) = upstream {
+                    Repository::new(&git_path).set_upstream(branch.name, &upstream)?;
+                    msg.push_str(&upstream_message(branch.name, &upstream));
+                }
+                refs.set_head(branch.name, &target_oid, &reflog_msg)?;
+            }
+        }
+
+        let new_ref = refs.current_ref()?;
+
+        if let Some(current_oid) = &current_oid {
+            if current_ref == HEAD && (new_ref != HEAD || current_oid != &target_oid) {
+                let commit = database.load_commit(current_oid)?;
+                msg.push_str(&format!(
+                    "Previous HEAD position was {} {}\n",
+                    current_oid.short(7),
+                    commit.title_line()
+                ));
+            }
+        }
+
+        if new_ref == HEAD {
+            if current_ref != HEAD {
+                msg.push_str(&format!(
+                    "Note: checking out '{}'.\n\n{}\n",
+                    revision, DETACHED_HEAD_MESSAGE
+                ));
+            }
+            let commit = database.load_commit(&target_oid)?;
+            msg.push_str(&format!(
+                "HEAD is now at {} {}\n",
+                target_oid.short(7),
+                commit.title_line()
+            ));
+        } else if let CheckoutMode::NewBranch(_) = mode {
+            msg.push_str(&format!(
+                "Switched to a new branch '{}'\n",
+                Refs::short_name(&new_ref)
+            ));
+        } else if new_ref == current_ref {
+            msg.push_str(&format!("Already on '{}'\n", Refs::short_name(&new_ref)));
+        } else {
+            msg.push_str(&format!(
+                "Switched to branch '{}'\n",
+                Refs::short_name(&new_ref)
+            ));
+        }
+
+        Ok(msg)
+    })()
+    .or_else(|e| {
+        // Cleanup lockfile if we had issues
+        if let Some(nit::Error::Lockfile(LockfileError::LockDenied(_))) = e.downcast_ref() {
+            // We couldn't get the lock, so leave it in place.
+        } else {
+            index.lockfile_mut().rollback()?;
+        }
+
+        Err(e)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::test::{author_options, init, write_and_commit};
+    use crate::commands::{add::*, branch::*, commit::*};
+    use nit::{index::Index, inspector::Inspector};
+    use std::path::PathBuf;
+
+    #[test]
+    fn checks_out_a_branch() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        write_and_commit(tmp_path, &[("hello.txt", "one")], "First");
+        create_branch("topic", None, false, tmp_path).unwrap();
+        write_and_commit(
+            tmp_path,
+            &[("hello.txt", "two"), ("nested/new.txt", "new")],
+            "Second",
+        );
+
+        let msg = checkout("topic", CheckoutMode::Auto, tmp_path).unwrap();
+        assert_eq!(msg, "Switched to branch 'topic'\n");
+
+        assert_eq!(
+            std::fs::read_to_string(tmp_path.join("hello.txt")).unwrap(),
+            "one"
+        );
+        assert!(!tmp_path.join("nested").exists());
+
+        let mut index = Index::new(tmp_path.join(".git").join("index"));
+        index.load().unwrap();
+        let entries: Vec<_> = index.entries().keys().cloned().collect();
+        assert_eq!(entries, vec![PathBuf::from("hello.txt")]);
+    }
+
+    #[test]
+    fn checks_out_many_files_in_parallel() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        write_and_commit(tmp_path, &[("hello.txt", "one")], "First");
+        create_branch("topic", None, false, tmp_path).unwrap();
+        checkout("topic", CheckoutMode::Auto, tmp_path).unwrap();
+
+        let files: Vec<_> = (0..150)
+            .map(|i| (format!("dir{}/sub/file{}.txt", i % 10, i), i.to_string()))
+            .collect();
+        let files: Vec<_> = files
+            .iter()
+            .map(|(path, contents)| (path.as_str(), contents.as_str()))
+            .collect();
+        write_and_commit(tmp_path, &files, "Second");
+
+        checkout("master", CheckoutMode::Auto, tmp_path).unwrap();
+        assert!(!tmp_path.join("dir0").exists());
+
+        checkout("topic", CheckoutMode::Auto, tmp_path).unwrap();
+        for (path, contents) in &files {
+            assert_eq!(
+                std::fs::read_to_string(tmp_path.join(path)).unwrap(),
+                *contents
+            );
+        }
+
+        let mut index = Index::new(tmp_path.join(".git").join("index"));
+        index.load().unwrap();
+        assert_eq!(index.entries().len(), files.len() + 1);
+    }
+
+    #[test]
+    fn checks_out_symlinks_as_plain_files_without_core_symlinks() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        write_and_commit(tmp_path, &[("hello.txt", "hello")], "First");
+        create_branch("topic", None, false, tmp_path).unwrap();
+        checkout("topic", CheckoutMode::Auto, tmp_path).unwrap();
+        std::os::unix::fs::symlink("hello.txt", tmp_path.join("link")).unwrap();
+        add_files_to_repository(vec![&tmp_path.join("link")], tmp_path).unwrap();
+        create_commit("Second".to_owned(), false, &[], &author_options(), tmp_path).unwrap();
+
+        let index_path = tmp_path.join(".git/index");
+        let link_entry = || {
+            let mut index = Index::new(&index_path);
+            index.load().unwrap();
+            index.entry_for_path(&"link").unwrap().clone()
+        };
+        assert_eq!(link_entry().mode(), 0o120000);
+
+        checkout("master", CheckoutMode::Auto, tmp_path).unwrap();
+        std::fs::write(tmp_path.join(".git/config"), "[core]\n\tsymlinks = false\n").unwrap();
+        checkout("topic", CheckoutMode::Auto, tmp_path).unwrap();
+
+        let stat: nit::vfs::Stat = std::fs::symlink_metadata(tmp_path.join("link"))
+            .unwrap()
+            .into();
+        assert!(stat.is_file());
+        assert_eq!(
+            std::fs::read_to_string(tmp_path.join("link")).unwrap(),
+            "hello.txt"
+        );
+        assert_eq!(link_entry().mode(), 0o120000);
+
+        // Neither status nor adding the file again should turn it into a plain file.
+        let database = Database::new(tmp_path.join(".git/objects"));
+        let workspace = open_workspace(tmp_path).unwrap();
+        let mut index = Index::new(&index_path);
+        index.load().unwrap();
+        let inspector = Inspector::new(&database, &index, &workspace);
+        assert_eq!(
+            inspector
+                .compare_index_to_workspace(Some(&link_entry()), Some(&stat))
+                .unwrap(),
+            None
+        );
+        add_files_to_repository(vec![&tmp_path.join("link")], tmp_path).unwrap();
+        assert_eq!(link_entry().mode(), 0o120000);
+
+        // Where symlinks are supported, a plain file in place of one is a change.
+        std::fs::remove_file(tmp_path.join(".git/config")).unwrap();
+        let workspace = open_workspace(tmp_path).unwrap();
+        let inspector = Inspector::new(&database, &index, &workspace);
+        assert_eq!(
+            inspector
+                .compare_index_to_workspace(Some(&link_entry()), Some(&stat))
+                .unwrap(),
+            Some(nit::inspector::ChangeKind::Modified)
+        );
+    }
+
+    #[test]
+    fn refuses_to_overwrite_local_changes() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        write_and_commit(tmp_path, &[("a.txt", "a"), ("b.txt", "b")], "First");
+        create_branch("topic", None, false, tmp_path).unwrap();
+        write_and_commit(tmp_path, &[("a.txt", "aa"), ("b.txt", "bb")], "Second");
+
+        std::fs::write(tmp_path.join("a.txt"), "local a").unwrap();
+        std::fs::write(tmp_path.join("b.txt"), "local b").unwrap();
+
+        let err = checkout("topic", CheckoutMode::Auto, tmp_path).unwrap_err();
+        let err = err.downcast_ref::<nit::Error>().unwrap();
+        assert_eq!(
+            std::error::Error::source(err).unwrap().to_string(),
+            "Your local changes to the following files would be overwritten by checkout:
+\ta.txt
+\tb.txt
+Please commit your changes or stash them before you switch branches.
+Aborting"
+        );
+
+        assert_eq!(
+            std::fs::read_to_string(tmp_path.join("a.txt")).unwrap(),
+            "local a"
+        );
+        assert_eq!(
+            std::fs::read_to_string(tmp_path.join("b.txt")).unwrap(),
+            "local b"
+        );
+        assert!(!tmp_path.join(".git").join("index.lock").exists());
+    }
+
+    #[test]
+    fn checks_out_a_new_tracking_branch() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        write_and_commit(tmp_path, &[("hello.txt", "one")], "First");
+        let git_path = tmp_path.join(".git");
+        let remote_ref = git_path.join("refs/remotes/origin/main");
+        std::fs::create_dir_all(remote_ref.parent().unwrap()).unwrap();
+        std::fs::copy(git_path.join("refs/heads/master"), &remote_ref).unwrap();
+
+        let msg = checkout(
+            "origin/main",
+            CheckoutMode::NewBranch(NewBranch {
+                name: "topic",
+                track: true,
+            }),
+            tmp_path,
+        )
+        .unwrap();
+
+        assert_eq!(
+            msg,
+            "Branch 'topic' set up to track remote branch 'main' from 'origin'.\n\
+             Switched to a new branch 'topic'\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(git_path.join("HEAD")).unwrap(),
+            "ref: refs/heads/topic\n"
+        );
+
+        let config = Repository::new(&git_path).config().unwrap();
+        assert_eq!(
+            config.get("branch.topic.remote").unwrap().as_deref(),
+            Some("origin")
+        );
+        assert_eq!(
+            config.get("branch.topic.merge").unwrap().as_deref(),
+            Some("refs/heads/main")
+        );
+
+        assert!(checkout(
+            "HEAD",
+            CheckoutMode::NewBranch(NewBranch {
+                name: "topic",
+                track: false,
+            }),
+            tmp_path,
+        )
+        .is_err());
+    }
+}
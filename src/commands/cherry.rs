@@ -0,0 +1,152 @@
+use anyhow::anyhow;
+use nit::{
+    diff,
+    merge::{self},
+    refs::HEAD,
+    repository::{git_dir, Repository},
+    revision::Revision,
+};
+use std::collections::HashSet;
+use std::path::Path;
+use structopt::StructOpt;
+
+use super::CommandContext;
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Show each commit's title as well as its id
+    #[structopt(short = "v")]
+    verbose: bool,
+    /// The branch to look for equivalent commits in (defaults to the current upstream)
+    upstream: Option<String>,
+    /// The branch whose commits to check (defaults to HEAD)
+    head: Option<String>,
+    /// Don't report commits up to and including this one
+    limit: Option<String>,
+}
+
+pub fn run(args: Args, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let Args {
+        verbose,
+        upstream,
+        head,
+        limit,
+    } = args;
+    let msg = cherry(
+        upstream.as_deref(),
+        head.as_deref(),
+        limit.as_deref(),
+        verbose,
+        &ctx.root_path,
+    )?;
+    write!(ctx.stdout, "{}", msg)?;
+
+    Ok(())
+}
+
+/// List the commits on `head` that aren't on `upstream`, oldest first, marking with `-` those
+/// that have an equivalent change upstream and with `+` those that don't.
+pub fn cherry(
+    upstream: Option<&str>,
+    head: Option<&str>,
+    limit: Option<&str>,
+    verbose: bool,
+    root_path: &Path,
+) -> anyhow::Result<String> {
+    let repo = Repository::new(git_dir(root_path));
+    let database = repo.database();
+    let refs = repo.refs();
+
+    let upstream = match upstream {
+        Some(upstream) => upstream.to_owned(),
+        None => {
+            let current_ref = refs.current_ref()?;
+            let branch = current_ref.strip_prefix("refs/heads/");
+            branch
+                .map(|branch| repo.upstream_of(branch))
+                .transpose()?
+                .flatten()
+                .ok_or_else(|| {
+                    anyhow!("Could not find a tracked remote branch, please specify <upstream> manually.")
+                })?
+        }
+    };
+
+    let upstream_oid = Revision::resolve(&upstream, database, refs)?;
+    let head_oid = Revision::resolve(head.unwrap_or(HEAD), database, refs)?;
+
+    let (mut local, theirs) = merge::unique_commits(database, &head_oid, &upstream_oid)?;
+
+    if let Some(limit) = limit {
+        let limit_oid = Revision::resolve(limit, database, refs)?;
+        let (after_limit, _) = merge::unique_commits(database, &head_oid, &limit_oid)?;
+        local.retain(|oid| after_limit.contains(oid));
+    }
+
+    let upstream_patches = theirs
+        .iter()
+        .map(|oid| diff::patch_id(database, oid))
+        .collect::<Result<HashSet<_>, _>>()?;
+
+    let mut msg = String::new();
+    for oid in local.iter().rev() {
+        let sign = if upstream_patches.contains(&diff::patch_id(database, oid)?) {
+            '-'
+        } else {
+            '+'
+        };
+
+        msg.push_str(&format!("{} {}", sign, oid));
+        if verbose {
+            let commit = database.load_commit(oid)?;
+            msg.push_str(&format!(" {}", commit.title_line()));
+        }
+        msg.push('\n');
+    }
+
+    Ok(msg)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::test::{init, write_and_commit};
+    use crate::commands::{branch::*, checkout::*};
+
+    #[test]
+    fn marks_commits_already_applied_upstream() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        write_and_commit(tmp_path, &[("hello.txt", "one\n")], "First");
+        create_branch("topic", None, false, tmp_path).unwrap();
+
+        write_and_commit(tmp_path, &[("a.txt", "upstream\n")], "Upstream only");
+        write_and_commit(tmp_path, &[("hello.txt", "one\ntwo\n")], "Add two");
+
+        checkout("topic", CheckoutMode::Auto, tmp_path).unwrap();
+        write_and_commit(tmp_path, &[("hello.txt", "one\n  two\n")], "Add two, again");
+        write_and_commit(tmp_path, &[("b.txt", "local\n")], "Local only");
+
+        let msg = cherry(Some("master"), None, None, true, tmp_path).unwrap();
+        let lines: Vec<_> = msg.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("- "));
+        assert!(lines[0].ends_with(" Add two, again"));
+        assert!(lines[1].starts_with("+ "));
+        assert!(lines[1].ends_with(" Local only"));
+
+        let msg = cherry(
+            Some("master"),
+            Some("topic"),
+            Some("topic^"),
+            false,
+            tmp_path,
+        )
+        .unwrap();
+        assert_eq!(msg.lines().count(), 1);
+        assert!(msg.starts_with("+ "));
+
+        assert!(cherry(None, None, None, false, tmp_path).is_err());
+    }
+}
@@ -0,0 +1,532 @@
+use anyhow::anyhow;
+use anyhow::Context;
+use chrono::{DateTime, FixedOffset, Local};
+use nit::{
+    config::Config,
+    database::{parse_date, Author, Database, Identity},
+    fsmonitor::FsMonitor,
+    index::Index,
+    inspector::Inspector,
+    repository::{git_dir, CommitOptions, Repository},
+    revision::Revision,
+    trailers::{self, Trailer},
+};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+use thiserror::Error;
+
+use super::status::status_header;
+use super::{open_workspace, run_auto_gc, CommandContext, Env, ExitStatus};
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Use the given message. If given more than once, each becomes a paragraph
+    #[structopt(long = "message", short = "m", number_of_values = 1)]
+    messages: Vec<String>,
+    /// Read the message from a file, or from stdin if the file is "-"
+    #[structopt(long = "file", short = "F", conflicts_with = "messages")]
+    file: Option<PathBuf>,
+    /// Allow recording a commit with the same tree as its parent
+    #[structopt(long = "allow-empty")]
+    allow_empty: bool,
+    /// Add a trailer such as "Reviewed-by: Name <email>" to the message
+    #[structopt(long = "trailer", number_of_values = 1)]
+    trailers: Vec<Trailer>,
+    /// Make a commit that `rebase --autosquash` will fold into <commit>, dropping its message
+    #[structopt(long = "fixup", value_name = "commit", conflicts_with = "squash")]
+    fixup: Option<String>,
+    /// Make a commit that `rebase --autosquash` will fold into <commit>, keeping its message
+    #[structopt(long = "squash", value_name = "commit")]
+    squash: Option<String>,
+    /// Record <author>, given as "Name <email>", as the commit's author
+    #[structopt(long = "author", value_name = "author")]
+    author: Option<Identity>,
+    /// Record the commit as made at <date>, e.g. "2021-01-01 12:00:00 +0100", rather than now
+    #[structopt(long = "date", value_name = "date", parse(try_from_str = parse_date))]
+    date: Option<DateTime<FixedOffset>>,
+}
+
+pub fn run(args: Args, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let Args {
+        messages,
+        file,
+        allow_empty,
+        trailers,
+        fixup,
+        squash,
+        author,
+        date,
+    } = args;
+    let target = fixup
+        .map(|target| ("fixup", target))
+        .or_else(|| squash.map(|target| ("squash", target)));
+    let message = match target {
+        Some((action, target)) => {
+            let body = if messages.is_empty() && file.is_none() {
+                None
+            } else {
+                Some(commit_message(&messages, file.as_deref(), ctx.stdin)?)
+            };
+            fixup_message(action, &target, body, &ctx.root_path)?
+        }
+        None => commit_message(&messages, file.as_deref(), ctx.stdin)?,
+    };
+    let config = ctx.repo.config()?;
+    let options = commit_options(author, date, &ctx.env, Some(&config))?;
+    let msg = match create_commit(message, allow_empty, &trailers, &options, &ctx.root_path) {
+        Ok(msg) => msg,
+        // Like git, say why there's nothing to commit as output rather than as an error.
+        Err(e) => match e.downcast_ref() {
+            Some(NothingToCommit(summary)) => {
+                writeln!(ctx.stdout, "{}", summary)?;
+                return Err(ExitStatus::Failure.into());
+            }
+            None => return Err(e),
+        },
+    };
+    write!(ctx.info(), "{}", msg)?;
+    run_auto_gc(ctx);
+
+    Ok(())
+}
+
+/// Returned when the index has nothing that isn't committed already, with a summary of what
+/// could be added instead.
+#[derive(Debug, Error)]
+#[error("{0}")]
+pub struct NothingToCommit(pub String);
+
+/// Build a commit message from `-m` paragraphs or from the file given with `-F`.
+pub fn commit_message(
+    messages: &[String],
+    file: Option<&Path>,
+    stdin: &mut dyn Read,
+) -> anyhow::Result<String> {
+    match file {
+        Some(path) if path == Path::new("-") => {
+            let mut message = String::new();
+            stdin.read_to_string(&mut message)?;
+            Ok(message)
+        }
+        Some(path) => fs::read_to_string(path)
+            .with_context(|| format!("could not read log file '{}'", path.display())),
+        None if messages.is_empty() => Err(anyhow!(
+            "No commit message given: use -m <message> or -F <file>"
+        )),
+        None => Ok(messages
+            .iter()
+            .map(|message| message.trim_end())
+            .collect::<Vec<_>>()
+            .join("\n\n")),
+    }
+}
+
+/// The message for a commit that `rebase --autosquash` will meld into `target`.
+pub fn fixup_message(
+    action: &str,
+    target: &str,
+    body: Option<String>,
+    root_path: &Path,
+) -> anyhow::Result<String> {
+    let repo = Repository::new(git_dir(root_path));
+    let oid = Revision::resolve(target, repo.database(), repo.refs())?;
+    let commit = repo.database().load_commit(&oid)?;
+
+    let mut message = format!("{}! {}\n", action, commit.title_line());
+    if let Some(body) = body {
+        message.push('\n');
+        message.push_str(&body);
+    }
+
+    Ok(message)
+}
+
+pub fn create_commit(
+    message: String,
+    allow_empty: bool,
+    trailers: &[Trailer],
+    options: &CommitOptions,
+    root_path: &Path,
+) -> anyhow::Result<String> {
+    let repo = Repository::open(root_path);
+    let database = repo.database();
+    let mut index = repo.configured_index()?;
+    index.load()?;
+
+    let root_oid = repo.write_tree(&index)?;
+    let parent = repo.refs().read_head()?;
+
+    let unchanged = match &parent {
+        Some(parent) => database.load_commit(parent)?.tree() == &root_oid,
+        None => index.entries().is_empty(),
+    };
+    if unchanged && !allow_empty {
+        let summary = nothing_to_commit(database, &index, root_path)?;
+        return Err(NothingToCommit(summary.trim_end().to_owned()).into());
+    }
+
+    if message.trim().is_empty() {
+        return Err(anyhow!("Aborting commit due to empty commit message."));
+    }
+    let msg = match trailers {
+        [] => message,
+        trailers => trailers::append(&message, trailers),
+    };
+
+    let commit_oid = repo.commit(&msg, options)?;
+
+    let root_msg = match parent {
+        Some(_) => "",
+        None => "(root-commit) ",
+    };
+
+    Ok(format!(
+        "[{}{}] {}",
+        root_msg,
+        commit_oid,
+        msg.lines().next().unwrap_or("")
+    ))
+}
+
+/// Who a new commit is by, and when, as given on the command line. Whatever isn't given is
+/// worked out as [`resolve_identity`] does, with the committer being the author if there's
+/// nothing more specific to go on.
+pub fn commit_options(
+    author: Option<Identity>,
+    date: Option<DateTime<FixedOffset>>,
+    env: &Env,
+    config: Option<&Config>,
+) -> anyhow::Result<CommitOptions> {
+    let author = match author {
+        Some(author) => author,
+        None => resolve_identity(env, config, "AUTHOR")?,
+    };
+    let mut options = CommitOptions::new(author);
+    if let Ok(committer) = committer_identity(env, config) {
+        options = options.with_committer(committer);
+    }
+    if let Some(date) = date {
+        options = options.with_timestamp(date);
+    }
+
+    Ok(options)
+}
+
+/// The author for new commits, made now.
+pub fn author_from_env(env: &Env, config: Option<&Config>) -> anyhow::Result<Author> {
+    Ok(resolve_identity(env, config, "AUTHOR")?.at(Local::now()))
+}
+
+/// The committer for new commits: the committer's own identity, or else the author's.
+pub fn committer_identity(env: &Env, config: Option<&Config>) -> anyhow::Result<Identity> {
+    resolve_identity(env, config, "COMMITTER").or_else(|_| resolve_identity(env, config, "AUTHOR"))
+}
+
+/// The identity of the author or committer, for `role` `AUTHOR` or `COMMITTER`, as git works
+/// it out: each of the name and email comes from `GIT_<role>_NAME` or `GIT_<role>_EMAIL`,
+/// then from `author.*` or `committer.*` in the config, then from `user.*`. The email can
+/// also come from `EMAIL`, as a last resort.
+pub fn resolve_identity(
+    env: &Env,
+    config: Option<&Config>,
+    role: &str,
+) -> anyhow::Result<Identity> {
+    let resolve = |field: &str| -> anyhow::Result<Option<String>> {
+        if let Some(value) = env.var(&format!("GIT_{}_{}", role, field.to_uppercase())) {
+            return Ok(Some(value.to_owned()));
+        }
+        let section = role.to_lowercase();
+        for key in [format!("{}.{}", section, field), format!("user.{}", field)] {
+            if let Some(value) = config.map(|config| config.get(&key)).transpose()?.flatten() {
+                return Ok(Some(value));
+            }
+        }
+        Ok(None)
+    };
+    let unknown = |field: &str| {
+        anyhow!(
+            "{} identity unknown: set GIT_{}_{} or user.{}",
+            role.to_lowercase(),
+            role,
+            field.to_uppercase(),
+            field
+        )
+    };
+
+    let name = resolve("name")?.ok_or_else(|| unknown("name"))?;
+    let email = match resolve("email")? {
+        Some(email) => email,
+        None => env
+            .var("EMAIL")
+            .map(str::to_owned)
+            .ok_or_else(|| unknown("email"))?,
+    };
+
+    Ok(Identity::new(name, email))
+}
+
+/// Explain why there was nothing to commit: the branch, and whether there are changes
+/// that could be added.
+pub fn nothing_to_commit(
+    database: &Database,
+    index: &Index,
+    root_path: &Path,
+) -> anyhow::Result<String> {
+    let workspace = open_workspace(root_path)?;
+    let inspector = Inspector::new(database, index, &workspace);
+    let repo = Repository::open(root_path);
+    let monitor = FsMonitor::from_repo(&repo)?;
+    let mut scan = monitor
+        .map(|monitor| monitor.begin(root_path))
+        .transpose()?;
+
+    // With a monitor, every path it can't vouch for has to be looked at, so that the next
+    // check can trust what this one found.
+    let mut unstaged = false;
+    for (path, entry) in index.entries() {
+        if scan.as_ref().is_some_and(|scan| scan.is_unchanged(path)) {
+            continue;
+        }
+
+        let stat = workspace.try_stat_file(path)?;
+        if inspector
+            .compare_index_to_workspace(Some(entry), stat.as_ref())?
+            .is_some()
+        {
+            unstaged = true;
+            match &mut scan {
+                Some(scan) => scan.mark_dirty(path),
+                None => break,
+            }
+        }
+    }
+    if let Some(scan) = scan {
+        scan.finish()?;
+    }
+
+    let untracked = workspace
+        .list_files_in_root()?
+        .iter()
+        .any(|path| !index.is_tracked(&Path::new(path)));
+
+    let mut msg = status_header(root_path, true)?;
+    msg.push('\n');
+    msg.push_str(if unstaged {
+        "no changes added to commit (use \"nit add\")\n"
+    } else if untracked {
+        "nothing added to commit but untracked files present (use \"nit add\" to track)\n"
+    } else {
+        "nothing to commit, working tree clean\n"
+    });
+
+    Ok(msg)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::commit;
+    use crate::commands::test::{author_options, context, init, write_and_commit};
+    use crate::commands::{add::*, interpret_trailers::*};
+    use std::fs::File;
+    use std::io::Write;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn makes_a_commit() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        let file_path = &tmp_path.join("hello.txt");
+        let mut file = File::create(file_path).unwrap();
+        file.write_all("Hello, world".as_bytes()).unwrap();
+
+        add_files_to_repository(vec![&file_path], tmp_path).unwrap();
+
+        create_commit(
+            "Commit message is here".to_owned(),
+            false,
+            &[],
+            &author_options(),
+            tmp_path,
+        )
+        .unwrap();
+
+        let err =
+            create_commit("Again".to_owned(), false, &[], &author_options(), tmp_path).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "On branch master\n\nnothing to commit, working tree clean"
+        );
+
+        std::fs::write(file_path, "Changed").unwrap();
+        let err =
+            create_commit("Again".to_owned(), false, &[], &author_options(), tmp_path).unwrap_err();
+        assert!(err
+            .to_string()
+            .ends_with("no changes added to commit (use \"nit add\")"));
+
+        create_commit("Empty".to_owned(), true, &[], &author_options(), tmp_path).unwrap();
+    }
+
+    #[test]
+    fn adds_trailers_to_commit_messages() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        std::fs::write(tmp_path.join("hello.txt"), "hello").unwrap();
+        add_files_to_repository(vec![&tmp_path.join("hello.txt")], tmp_path).unwrap();
+        let trailers = vec!["Reviewed-by: A. Reviewer".parse().unwrap()];
+        create_commit(
+            "Add hello".to_owned(),
+            false,
+            &trailers,
+            &author_options(),
+            tmp_path,
+        )
+        .unwrap();
+
+        let repo = Repository::new(tmp_path.join(".git"));
+        let head = repo.refs().read_head().unwrap().unwrap();
+        let commit = repo.database().load_commit(&head).unwrap();
+        assert_eq!(commit.message(), "Add hello\n\nReviewed-by: A. Reviewer\n");
+        assert_eq!(commit.trailers(), trailers);
+
+        let more = vec!["Acked-by=Someone".parse().unwrap()];
+        assert_eq!(
+            interpret_trailers(commit.message(), &more, false),
+            "Add hello\n\nReviewed-by: A. Reviewer\nAcked-by: Someone\n"
+        );
+        assert_eq!(
+            interpret_trailers(commit.message(), &[], true),
+            "Reviewed-by: A. Reviewer\n"
+        );
+    }
+
+    #[test]
+    fn builds_commit_messages_from_paragraphs_or_files() -> anyhow::Result<()> {
+        let paragraphs = vec!["Title\n".to_owned(), "Body text.".to_owned()];
+        assert_eq!(
+            commit_message(&paragraphs, None, &mut std::io::empty())?,
+            "Title\n\nBody text."
+        );
+        assert!(commit_message(&[], None, &mut std::io::empty()).is_err());
+
+        let tmp = tempfile::tempdir()?;
+        let path = tmp.path().join("MESSAGE");
+        std::fs::write(&path, "From a file\n")?;
+        assert_eq!(
+            commit_message(&[], Some(&path), &mut std::io::empty())?,
+            "From a file\n"
+        );
+
+        drop(tmp);
+        assert!(commit_message(&[], Some(&path), &mut std::io::empty()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn asks_the_fsmonitor_hook_what_changed() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+        write_and_commit(tmp_path, &[("a.txt", "a"), ("b.txt", "b")], "First");
+
+        // The hook logs how it was called, and reports whatever is listed in `changed`.
+        let hook = tmp_path.join(".git/hook.sh");
+        std::fs::write(
+            &hook,
+            "#!/bin/sh\necho \"$1 $2\" >> .git/hook.log\nprintf 'token%s\\0' $(wc -l < .git/hook.log)\n\
+             [ -f changed ] && tr '\\n' '\\0' < changed\nexit 0\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&hook, std::fs::Permissions::from_mode(0o755)).unwrap();
+        std::fs::write(
+            tmp_path.join(".git/config"),
+            format!("[core]\n\tfsmonitor = {}\n", hook.display()),
+        )
+        .unwrap();
+
+        let database = Database::new(tmp_path.join(".git/objects"));
+        let summary = || {
+            let mut index = Index::new(tmp_path.join(".git/index"));
+            index.load().unwrap();
+            let summary = nothing_to_commit(&database, &index, tmp_path).unwrap();
+            summary.lines().last().unwrap().to_owned()
+        };
+        let clean =
+            "nothing added to commit but untracked files present (use \"nit add\" to track)";
+        let dirty = "no changes added to commit (use \"nit add\")";
+
+        std::fs::write(tmp_path.join("changed"), "").unwrap();
+        assert_eq!(summary(), clean);
+
+        // A change the hook doesn't report goes unnoticed, as the hook is trusted...
+        std::fs::write(tmp_path.join("a.txt"), "A").unwrap();
+        assert_eq!(summary(), clean);
+
+        // ...until it does report it, and it stays dirty until the index changes.
+        std::fs::write(tmp_path.join("changed"), "a.txt\n").unwrap();
+        assert_eq!(summary(), dirty);
+        std::fs::write(tmp_path.join("changed"), "").unwrap();
+        assert_eq!(summary(), dirty);
+
+        assert_eq!(
+            std::fs::read_to_string(tmp_path.join(".git/hook.log")).unwrap(),
+            "2 \n2 token1\n2 token2\n2 token3\n"
+        );
+    }
+
+    #[test]
+    fn makes_reproducible_commits() {
+        let commit_in = || {
+            let tmp = init().unwrap();
+            let tmp_path = tmp.path();
+            std::fs::write(tmp_path.join("a.txt"), "a").unwrap();
+            add_files_to_repository(vec![&tmp_path.join("a.txt")], tmp_path).unwrap();
+
+            let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+            let mut stdin = std::io::empty();
+            let mut ctx = context(tmp_path, &mut stdin, &mut stdout, &mut stderr);
+            ctx.env = vec![
+                ("GIT_COMMITTER_NAME", "C. O. Mitter"),
+                ("GIT_COMMITTER_EMAIL", "committer@example.com"),
+            ]
+            .into_iter()
+            .collect();
+            let args = commit::Args::from_iter(&[
+                "commit",
+                "-m",
+                "First",
+                "--author",
+                "A. U. Thor <author@example.com>",
+                "--date",
+                "2021-01-01T12:00:00+01:00",
+            ]);
+            commit::run(args, &mut ctx).unwrap();
+
+            let repo = Repository::new(tmp_path.join(".git"));
+            let head = repo.refs().read_head().unwrap().unwrap();
+            let commit = repo.database().load_commit(&head).unwrap();
+            (head, commit)
+        };
+
+        let (oid, commit) = commit_in();
+        assert_eq!(
+            commit.author().to_string(),
+            "A. U. Thor <author@example.com> 1609498800 +0100"
+        );
+        assert_eq!(
+            commit.committer().to_string(),
+            "C. O. Mitter <committer@example.com> 1609498800 +0100"
+        );
+        assert_eq!(commit_in().0, oid);
+
+        let options = commit_options(None, None, &Env::default(), None);
+        assert!(options.is_err());
+        let author = Identity::new("A", "a@example.com");
+        let options = commit_options(Some(author.clone()), None, &Env::default(), None).unwrap();
+        assert_eq!(options, CommitOptions::new(author));
+    }
+}
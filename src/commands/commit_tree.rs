@@ -0,0 +1,117 @@
+use anyhow::anyhow;
+use nit::{
+    database::{Commit, ObjectId},
+    repository::{git_dir, Repository},
+    revision::Revision,
+};
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+
+use super::commit::{author_from_env, commit_message};
+use super::{CommandContext, Env};
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// The tree to commit
+    tree: String,
+    /// A parent of the new commit. Can be given more than once
+    #[structopt(short = "p", number_of_values = 1)]
+    parents: Vec<String>,
+    /// Use the given message. If given more than once, each becomes a paragraph
+    #[structopt(short = "m", number_of_values = 1)]
+    messages: Vec<String>,
+    /// Read the message from a file, or from stdin if the file is "-". With neither this
+    /// nor -m, the message is read from stdin
+    #[structopt(short = "F", conflicts_with = "messages")]
+    file: Option<PathBuf>,
+}
+
+pub fn run(args: Args, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let Args {
+        tree,
+        parents,
+        messages,
+        file,
+    } = args;
+    let file = file.or_else(|| Some(PathBuf::from("-")).filter(|_| messages.is_empty()));
+    let message = commit_message(&messages, file.as_deref(), ctx.stdin)?;
+    let oid = commit_tree(&tree, &parents, message, &ctx.env, &ctx.root_path)?;
+    writeln!(ctx.stdout, "{}", oid)?;
+
+    Ok(())
+}
+
+/// Store a commit of the given tree with the given parents, leaving HEAD and the index alone.
+pub fn commit_tree(
+    tree: &str,
+    parents: &[String],
+    message: String,
+    env: &Env,
+    root_path: &Path,
+) -> anyhow::Result<ObjectId> {
+    let repo = Repository::new(git_dir(root_path));
+    let database = repo.database();
+
+    let tree_oid = Revision::resolve_tree_ish(tree, database, repo.refs())?;
+    let tree_oid = match database.load(&tree_oid)? {
+        nit::database::ParsedObject::Tree(_) => tree_oid,
+        object => return Err(anyhow!("{} is a {}, not a tree", tree, object.kind())),
+    };
+
+    let parents = parents
+        .iter()
+        .map(|parent| Revision::resolve(parent, database, repo.refs()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if message.trim().is_empty() {
+        return Err(anyhow!("Aborting commit due to empty commit message."));
+    }
+
+    let author = author_from_env(env, Some(&repo.config()?))?;
+    let commit = Commit::with_parents(parents, tree_oid, author, message);
+    Ok(database.store(&commit)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::test::{author_env, init, write_and_commit};
+    use crate::commands::write_tree::*;
+    use nit::merge;
+
+    #[test]
+    fn commits_trees_directly() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        write_and_commit(tmp_path, &[("a.txt", "a")], "First");
+        let tree = write_tree_from_index(tmp_path).unwrap().to_string();
+
+        let root = commit_tree(&tree, &[], "Root\n".to_owned(), &author_env(), tmp_path).unwrap();
+        let parents = [root.to_string(), "HEAD".to_owned()];
+        let merge = commit_tree(
+            &tree,
+            &parents,
+            "Merge\n".to_owned(),
+            &author_env(),
+            tmp_path,
+        )
+        .unwrap();
+
+        let repo = Repository::new(tmp_path.join(".git"));
+        let database = repo.database();
+        let head = repo.refs().read_head().unwrap().unwrap();
+        assert_ne!(head, merge);
+
+        let commit = database.load_commit(&merge).unwrap();
+        assert_eq!(commit.parents(), &[root, head][..]);
+        assert_eq!(commit.tree().to_string(), tree);
+        assert_eq!(commit.message(), "Merge\n");
+
+        assert_eq!(
+            merge::merge_base(database, &merge, &head).unwrap(),
+            Some(head)
+        );
+        assert!(commit_tree("HEAD", &[], "Oops".to_owned(), &author_env(), tmp_path).is_err());
+    }
+}
@@ -0,0 +1,230 @@
+use nit::{protocol, repository::Repository};
+use std::ffi::OsString;
+use std::io::{BufWriter, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use structopt::StructOpt;
+
+use super::CommandContext;
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// The address to listen on
+    #[structopt(long = "listen", default_value = "0.0.0.0")]
+    listen: String,
+    /// The port to listen on
+    #[structopt(long = "port", default_value = "9418")]
+    port: u16,
+    /// Look requested paths up under this directory, so that `git://host/repo` is
+    /// `<base-path>/repo`
+    #[structopt(long = "base-path")]
+    base_path: Option<PathBuf>,
+    /// Serve every repository, not just those with a `git-daemon-export-ok` file
+    #[structopt(long = "export-all")]
+    export_all: bool,
+    /// Only serve repositories inside these directories (anywhere, if none are given)
+    directories: Vec<PathBuf>,
+}
+
+pub fn run(args: Args, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let Args {
+        listen,
+        port,
+        base_path,
+        export_all,
+        directories,
+    } = args;
+    let listener = TcpListener::bind((listen.as_str(), port))?;
+    writeln!(ctx.info_stderr(), "Listening on {}", listener.local_addr()?)?;
+
+    let options = DaemonOptions {
+        base_path,
+        export_all,
+        directories,
+    };
+    serve(listener, options)
+}
+
+/// Which repositories the daemon serves.
+#[derive(Debug, Clone, Default)]
+pub struct DaemonOptions {
+    pub base_path: Option<PathBuf>,
+    pub export_all: bool,
+    pub directories: Vec<PathBuf>,
+}
+
+/// Serve fetches over the `git://` protocol to every client that connects, each on a
+/// thread of its own. Only upload-pack is offered, so the repositories can be read but
+/// never changed. This doesn't return unless the listener fails.
+pub fn serve(listener: TcpListener, options: DaemonOptions) -> anyhow::Result<()> {
+    let options = Arc::new(options);
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let options = Arc::clone(&options);
+        // Anything that goes wrong is the client's to hear about, as far as it can be.
+        thread::spawn(move || handle_connection(stream, &options));
+    }
+
+    Ok(())
+}
+
+/// Read which service a client wants, and for which repository, and serve it if allowed.
+fn handle_connection(stream: TcpStream, options: &DaemonOptions) -> anyhow::Result<()> {
+    let mut input = &stream;
+    let mut output = BufWriter::new(&stream);
+
+    let request = protocol::read_packet(&mut input)?.unwrap_or_default();
+    let request = String::from_utf8_lossy(&request);
+    let request = request.split('\0').next().unwrap_or_default();
+    let (service, path) = request.split_once(' ').unwrap_or((request, ""));
+
+    let git_dir = match (service, find_repository(path, options)) {
+        ("git-upload-pack", Some(git_dir)) => git_dir,
+        ("git-upload-pack", None) => {
+            let message = format!("access denied or repository not exported: {}", path);
+            return deny(&mut output, &message);
+        }
+        (service, _) => return deny(&mut output, &format!("service not enabled: {}", service)),
+    };
+
+    let repo = Repository::new(git_dir);
+    if let Err(e) = repo.check_format() {
+        return deny(&mut output, &e.to_string());
+    }
+    protocol::upload_pack(&repo, &mut input, &mut output)?;
+    Ok(())
+}
+
+fn deny(output: &mut BufWriter<&TcpStream>, message: &str) -> anyhow::Result<()> {
+    protocol::write_packet(output, format!("ERR {}\n", message).as_bytes())?;
+    output.flush()?;
+    Ok(())
+}
+
+/// Find the `.git` directory of the repository a client asked for: the path, the path with
+/// `.git` added, or the `.git` directory inside either, under the base path if there is
+/// one. It has to be inside one of the allowed directories, if any are given, and has to
+/// be exported unless every repository is.
+fn find_repository(path: &str, options: &DaemonOptions) -> Option<PathBuf> {
+    let requested = Path::new(path);
+    if !requested.is_absolute()
+        || requested
+            .components()
+            .any(|component| component == Component::ParentDir)
+    {
+        return None;
+    }
+    let path = match &options.base_path {
+        Some(base) => base.join(requested.strip_prefix("/").ok()?),
+        None => requested.to_owned(),
+    };
+
+    let mut with_extension = OsString::from(&path);
+    with_extension.push(".git");
+    let with_extension = PathBuf::from(with_extension);
+    let git_dir = [&path, &with_extension]
+        .iter()
+        .flat_map(|path| [path.join(".git"), path.to_path_buf()])
+        .find(|path| path.join("objects").is_dir())?
+        .canonicalize()
+        .ok()?;
+
+    let allowed = options.directories.is_empty()
+        || options
+            .directories
+            .iter()
+            .any(|dir| dir.canonicalize().is_ok_and(|dir| git_dir.starts_with(dir)));
+    let exported = options.export_all || git_dir.join("git-daemon-export-ok").is_file();
+    match allowed && exported {
+        true => Some(git_dir),
+        false => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::test::write_and_commit;
+    use crate::commands::{fetch::*, init::*, ls_remote::*};
+    use nit::refs::Refs;
+
+    #[test]
+    fn fetches_from_a_daemon() {
+        let (local, origin, hidden) = ("daemon_local", "daemon_origin", "daemon_hidden");
+        let base = tempfile::tempdir().unwrap();
+        let (local_path, origin_path) = (base.path().join(local), base.path().join(origin));
+        for name in &[local, origin, hidden] {
+            std::fs::create_dir(base.path().join(name)).unwrap();
+            init_repository(&base.path().join(name)).unwrap();
+        }
+        std::fs::write(origin_path.join(".git/git-daemon-export-ok"), "").unwrap();
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let options = DaemonOptions {
+            base_path: Some(base.path().to_owned()),
+            ..Default::default()
+        };
+        thread::spawn(move || serve(listener, options));
+
+        std::fs::write(
+            local_path.join(".git/config"),
+            format!(
+                "[remote \"origin\"]\n\turl = git://{}/{}\n\
+                 [remote \"hidden\"]\n\turl = git://{0}/{}\n",
+                address, origin, hidden
+            ),
+        )
+        .unwrap();
+
+        let lines: String = (1..=40).map(|n| format!("line {}\n", n)).collect();
+        write_and_commit(&origin_path, &[("a.txt", &lines)], "First");
+        let refs = |path: &Path| {
+            let refs = Refs::new(&path.join(".git"));
+            (
+                refs.read_ref("master").unwrap(),
+                refs.read_ref("origin/master").unwrap(),
+            )
+        };
+
+        let msg = fetch(None, false, &mut Vec::new(), &local_path).unwrap();
+        assert!(msg.contains(" * [new branch]      master -> origin/master"));
+        assert_eq!(refs(&local_path).1, refs(&origin_path).0);
+
+        // The second fetch only needs what's new, and the file can be sent as a delta
+        // against the version fetched the first time.
+        let changed = lines.replace("line 20", "line twenty");
+        write_and_commit(&origin_path, &[("a.txt", &changed)], "Second");
+        let mut progress = Vec::new();
+        fetch(None, false, &mut progress, &local_path).unwrap();
+        assert_eq!(refs(&local_path).1, refs(&origin_path).0);
+        assert_eq!(progress, vec!["remote: Total 3\n"]);
+        let repo = Repository::new(local_path.join(".git"));
+        let tip = refs(&local_path).1.unwrap();
+        let entry = repo.database().entry_at_path(&tip, Path::new("a.txt"));
+        let (_, data) = repo
+            .database()
+            .read_object(entry.unwrap().unwrap().oid())
+            .unwrap();
+        assert_eq!(data, changed.as_bytes());
+
+        let options = LsRemoteOptions {
+            heads: true,
+            tags: false,
+            refs_only: false,
+        };
+        let listed = ls_remote(Some("origin"), &[], &options, &mut Vec::new(), &local_path);
+        assert_eq!(
+            listed.unwrap(),
+            format!("{}\trefs/heads/master\n", refs(&origin_path).0.unwrap())
+        );
+
+        let err = fetch(Some("hidden"), false, &mut Vec::new(), &local_path).unwrap_err();
+        assert!(err
+            .root_cause()
+            .to_string()
+            .contains("access denied or repository not exported: /daemon_hidden"));
+    }
+}
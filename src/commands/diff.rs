@@ -0,0 +1,339 @@
+use anyhow::anyhow;
+use nit::{
+    attributes::Attributes,
+    diff,
+    merge::{self},
+    quote::quote_non_ascii,
+    refs::HEAD,
+    repository::{git_dir, Repository},
+    revision::Revision,
+};
+use std::path::Path;
+use structopt::StructOpt;
+
+use super::{CommandContext, ExitStatus};
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    #[structopt(flatten)]
+    diff_args: DiffArgs,
+    /// Exit with status 1 if there are differences, and 0 if there are none
+    #[structopt(long = "exit-code")]
+    exit_code: bool,
+    /// Show nothing, and exit as with --exit-code
+    #[structopt(long = "quiet")]
+    quiet: bool,
+    /// Two commits or trees to compare, or a range such as `a..b` or `a...b`
+    revisions: Vec<String>,
+}
+
+pub fn run(args: Args, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let Args {
+        diff_args,
+        exit_code,
+        quiet,
+        revisions,
+    } = args;
+    let msg = diff_revisions(&revisions, &diff_args, &ctx.root_path)?;
+    if !quiet {
+        write!(ctx.stdout, "{}", msg)?;
+    }
+    if (exit_code || quiet) && !msg.is_empty() {
+        return Err(ExitStatus::Failure.into());
+    }
+
+    Ok(())
+}
+
+/// Options shared by the commands that show diffs.
+#[derive(Debug, StructOpt)]
+pub struct DiffArgs {
+    /// Choose a diff algorithm: myers (the default), patience or histogram
+    #[structopt(long = "diff-algorithm")]
+    pub diff_algorithm: Option<diff::Algorithm>,
+    /// Show <n> lines of context around each change, instead of three
+    #[structopt(short = "U", long = "unified")]
+    pub unified: Option<usize>,
+    /// Show the whole function around each change
+    #[structopt(short = "W", long = "function-context")]
+    pub function_context: bool,
+    /// Ignore whitespace when comparing lines
+    #[structopt(short = "w", long = "ignore-all-space")]
+    pub ignore_all_space: bool,
+    /// Ignore changes in the amount of whitespace, and whitespace at the ends of lines
+    #[structopt(short = "b", long = "ignore-space-change")]
+    pub ignore_space_change: bool,
+    /// Ignore changes whose lines are all blank
+    #[structopt(long = "ignore-blank-lines")]
+    pub ignore_blank_lines: bool,
+}
+
+/// Show the patch between two commits or trees, given either as two arguments or as a
+/// range. `a...b` compares `b` with the point it forked from `a`.
+pub fn diff_revisions(
+    revisions: &[String],
+    diff_args: &DiffArgs,
+    root_path: &Path,
+) -> anyhow::Result<String> {
+    let repo = Repository::new(git_dir(root_path));
+    let options = diff_options(&repo, diff_args, root_path)?;
+    let database = repo.database();
+    let refs = repo.refs();
+    let resolve = |expr: &str| {
+        let expr = if expr.is_empty() { HEAD } else { expr };
+        Revision::resolve_tree_ish(expr, database, refs)
+    };
+
+    let (a, b) = match revisions {
+        [range] if range.contains("...") => {
+            let (a, b) = range.split_once("...").unwrap_or_default();
+            let (a, b) = (resolve(a)?, resolve(b)?);
+            let base = merge::merge_base(database, &a, &b)?
+                .ok_or_else(|| anyhow!("{}: no merge base", range))?;
+            (base, b)
+        }
+        [range] if range.contains("..") => {
+            let (a, b) = range.split_once("..").unwrap_or_default();
+            (resolve(a)?, resolve(b)?)
+        }
+        [a, b] => (resolve(a)?, resolve(b)?),
+        _ => return Err(anyhow!("usage: nit diff <commit> <commit>")),
+    };
+
+    Ok(diff::tree_patch(database, Some(&a), Some(&b), &options)?)
+}
+
+/// Combine the diff settings from the config with those given on the command line.
+fn diff_options(
+    repo: &Repository,
+    diff_args: &DiffArgs,
+    root_path: &Path,
+) -> anyhow::Result<diff::DiffOptions> {
+    let config = repo.config()?;
+
+    let algorithm = match diff_args.diff_algorithm {
+        Some(algorithm) => algorithm,
+        None => match config.get("diff.algorithm")? {
+            Some(name) => name.parse()?,
+            None => diff::Algorithm::default(),
+        },
+    };
+
+    let context = match diff_args.unified {
+        Some(context) => context,
+        None => match config.get("diff.context")? {
+            Some(context) => context
+                .parse()
+                .map_err(|_| anyhow!("bad config variable 'diff.context': {}", context))?,
+            None => diff::HUNK_CONTEXT,
+        },
+    };
+
+    let functions = diff::FunctionNames::new(Attributes::load(root_path)?, &config)?;
+
+    let whitespace = if diff_args.ignore_all_space {
+        diff::Whitespace::IgnoreAll
+    } else if diff_args.ignore_space_change {
+        diff::Whitespace::IgnoreChange
+    } else {
+        diff::Whitespace::Exact
+    };
+
+    Ok(diff::DiffOptions {
+        algorithm,
+        context,
+        function_context: diff_args.function_context,
+        functions,
+        whitespace,
+        ignore_blank_lines: diff_args.ignore_blank_lines,
+        quote_non_ascii: quote_non_ascii(&config)?,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::test::{annotate_tag, init, write_and_commit};
+    use std::os::unix::fs::PermissionsExt;
+
+    fn default_diff_args() -> DiffArgs {
+        DiffArgs {
+            diff_algorithm: None,
+            unified: None,
+            function_context: false,
+            ignore_all_space: false,
+            ignore_space_change: false,
+            ignore_blank_lines: false,
+        }
+    }
+
+    #[test]
+    fn diffs_two_commits() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        write_and_commit(
+            tmp_path,
+            &[
+                ("hello.txt", "one\ntwo\n"),
+                ("gone/deep.txt", "bye\n"),
+                ("run.sh", "echo hi\n"),
+            ],
+            "First",
+        );
+
+        std::fs::remove_dir_all(tmp_path.join("gone")).unwrap();
+        std::fs::remove_file(tmp_path.join(".git/index")).unwrap();
+        let mut perms = std::fs::metadata(tmp_path.join("run.sh"))
+            .unwrap()
+            .permissions();
+        perms.set_mode(0o755);
+        std::fs::set_permissions(tmp_path.join("run.sh"), perms).unwrap();
+        write_and_commit(
+            tmp_path,
+            &[
+                ("hello.txt", "one\n2\n"),
+                ("new/file.txt", "hi"),
+                ("run.sh", "echo hi\n"),
+            ],
+            "Second",
+        );
+
+        let expected = "\
+diff --git a/gone/deep.txt b/gone/deep.txt
+deleted file mode 100644
+index b023018..0000000
+--- a/gone/deep.txt
++++ /dev/null
+@@ -1 +0,0 @@
+-bye
+diff --git a/hello.txt b/hello.txt
+index 814f4a4..99b356d 100644
+--- a/hello.txt
++++ b/hello.txt
+@@ -1,2 +1,2 @@
+ one
+-two
++2
+diff --git a/new/file.txt b/new/file.txt
+new file mode 100644
+index 0000000..32f95c0
+--- /dev/null
++++ b/new/file.txt
+@@ -0,0 +1 @@
++hi
+\\ No newline at end of file
+diff --git a/run.sh b/run.sh
+old mode 100644
+new mode 100755
+";
+
+        let diff = diff_revisions(
+            &["master~1".to_owned(), "master".to_owned()],
+            &default_diff_args(),
+            tmp_path,
+        );
+        assert_eq!(diff.unwrap(), expected);
+        let diff = diff_revisions(&["master^..".to_owned()], &default_diff_args(), tmp_path);
+        assert_eq!(diff.unwrap(), expected);
+
+        let reversed =
+            diff_revisions(&["HEAD..HEAD^".to_owned()], &default_diff_args(), tmp_path).unwrap();
+        assert!(reversed.contains("--- /dev/null\n+++ b/gone/deep.txt\n"));
+    }
+
+    #[test]
+    fn shows_function_names_from_diff_drivers() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        let before = "impl Thing {\n    fn one() {\n        a();\n        b();\n        c();\n        d();\n    }\n}\n";
+        write_and_commit(tmp_path, &[("lib.rs", before)], "First");
+        write_and_commit(
+            tmp_path,
+            &[("lib.rs", &before.replace("d()", "e()"))],
+            "Second",
+        );
+        std::fs::write(tmp_path.join(".gitattributes"), "*.rs diff=rust\n").unwrap();
+
+        let range = ["HEAD^..HEAD".to_owned()];
+        let diff = diff_revisions(&range, &default_diff_args(), tmp_path).unwrap();
+        assert!(diff.contains("\n@@ -3,6 +3,6 @@ fn one() {\n"));
+
+        let args = DiffArgs {
+            unified: Some(0),
+            ..default_diff_args()
+        };
+        let diff = diff_revisions(&range, &args, tmp_path).unwrap();
+        assert!(diff.contains("\n@@ -6 +6 @@ fn one() {\n-        d();\n+        e();\n"));
+
+        std::fs::write(
+            tmp_path.join(".git/config"),
+            "[diff \"rust\"]\n\txfuncname = ^(impl.*)$\n",
+        )
+        .unwrap();
+        let args = DiffArgs {
+            function_context: true,
+            ..default_diff_args()
+        };
+        let diff = diff_revisions(&range, &args, tmp_path).unwrap();
+        assert!(diff.contains("\n@@ -1,8 +1,8 @@\n impl Thing {\n"));
+    }
+
+    #[test]
+    fn hides_whitespace_only_changes() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        write_and_commit(
+            tmp_path,
+            &[("spaces.txt", "a b\n"), ("words.txt", "one\n")],
+            "First",
+        );
+        write_and_commit(
+            tmp_path,
+            &[("spaces.txt", "a    b  \n"), ("words.txt", "two\n")],
+            "Second",
+        );
+
+        let range = ["HEAD^..HEAD".to_owned()];
+        let diff = diff_revisions(&range, &default_diff_args(), tmp_path).unwrap();
+        assert!(diff.contains("diff --git a/spaces.txt b/spaces.txt\n"));
+
+        let args = DiffArgs {
+            ignore_space_change: true,
+            ..default_diff_args()
+        };
+        let diff = diff_revisions(&range, &args, tmp_path).unwrap();
+        assert!(!diff.contains("spaces.txt"));
+        assert!(diff.contains("-one\n+two\n"));
+    }
+
+    #[test]
+    fn diffs_annotated_tags() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        write_and_commit(tmp_path, &[("a.txt", "one\n")], "First");
+        annotate_tag(tmp_path, "v1.0", "HEAD", "Version 1.0");
+        write_and_commit(tmp_path, &[("a.txt", "two\n")], "Second");
+        let tag = annotate_tag(tmp_path, "v2.0", "HEAD", "Version 2.0");
+
+        let expected = diff_revisions(
+            &["HEAD~1".to_owned(), "HEAD".to_owned()],
+            &default_diff_args(),
+            tmp_path,
+        )
+        .unwrap();
+        assert!(expected.contains("-one\n+two\n"));
+        for revisions in [
+            vec!["v1.0", "v2.0"],
+            vec!["v1.0..v2.0"],
+            vec!["v1.0", &tag.to_string()[..7]],
+        ] {
+            let revisions: Vec<_> = revisions.into_iter().map(str::to_owned).collect();
+            let diff = diff_revisions(&revisions, &default_diff_args(), tmp_path);
+            assert_eq!(diff.unwrap(), expected);
+        }
+    }
+}
@@ -0,0 +1,39 @@
+use nit::{
+    inspector::Inspector,
+    quote::quote_non_ascii,
+    repository::{git_dir, Repository},
+};
+use std::path::Path;
+use structopt::StructOpt;
+
+use super::diff_tree::format_raw_changes;
+use super::{open_workspace, CommandContext};
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Only show the status and path of each change
+    #[structopt(long = "name-status")]
+    name_status: bool,
+}
+
+pub fn run(args: Args, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let Args { name_status } = args;
+    let msg = diff_files(name_status, &ctx.root_path)?;
+    write!(ctx.stdout, "{}", msg)?;
+
+    Ok(())
+}
+
+/// List the paths whose files in the working tree differ from their entries in the index.
+pub fn diff_files(name_status: bool, root_path: &Path) -> anyhow::Result<String> {
+    let repo = Repository::new(git_dir(root_path));
+    let mut index = repo.index();
+    index.load()?;
+    let workspace = open_workspace(root_path)?;
+
+    let inspector = Inspector::new(repo.database(), &index, &workspace);
+    let changes = inspector.workspace_changes()?;
+
+    let quote = quote_non_ascii(&repo.config()?)?;
+    Ok(format_raw_changes(&changes, name_status, quote))
+}
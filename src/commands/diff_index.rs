@@ -0,0 +1,94 @@
+use nit::{
+    inspector::Inspector,
+    quote::quote_non_ascii,
+    repository::{git_dir, Repository},
+    revision::Revision,
+};
+use std::path::Path;
+use structopt::StructOpt;
+
+use super::diff_tree::format_raw_changes;
+use super::{open_workspace, CommandContext};
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Compare the tree with the index rather than the working tree
+    #[structopt(long = "cached")]
+    cached: bool,
+    /// Only show the status and path of each change
+    #[structopt(long = "name-status")]
+    name_status: bool,
+    /// The commit or tree to compare against
+    tree_ish: String,
+}
+
+pub fn run(args: Args, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let Args {
+        cached,
+        name_status,
+        tree_ish,
+    } = args;
+    let msg = diff_index(&tree_ish, cached, name_status, &ctx.root_path)?;
+    write!(ctx.stdout, "{}", msg)?;
+
+    Ok(())
+}
+
+/// List the paths whose entries in a tree differ from those in the index, or unless
+/// `cached`, from the files in the working tree. Files in the working tree that have
+/// changed are shown with a null ObjectId, rather than being hashed.
+pub fn diff_index(
+    tree_ish: &str,
+    cached: bool,
+    name_status: bool,
+    root_path: &Path,
+) -> anyhow::Result<String> {
+    let repo = Repository::new(git_dir(root_path));
+    let database = repo.database();
+    let mut index = repo.index();
+    index.load()?;
+    let workspace = open_workspace(root_path)?;
+
+    let oid = Revision::resolve_tree_ish(tree_ish, database, repo.refs())?;
+    let tree = database.load_tree_list(Some(&oid))?;
+    let inspector = Inspector::new(database, &index, &workspace);
+    let changes = inspector.tree_changes(&tree, cached)?;
+
+    let quote = quote_non_ascii(&repo.config()?)?;
+    Ok(format_raw_changes(&changes, name_status, quote))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::test::{init, write_and_commit};
+    use crate::commands::{add::*, diff_files::*};
+    use nit::database::ObjectId;
+
+    #[test]
+    fn lists_raw_changes_against_the_index_and_working_tree() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        write_and_commit(tmp_path, &[("a.txt", "a"), ("b.txt", "b")], "First");
+        std::fs::write(tmp_path.join("a.txt"), "staged").unwrap();
+        add_files_to_repository(vec![&tmp_path.join("a.txt")], tmp_path).unwrap();
+        std::fs::write(tmp_path.join("a.txt"), "unstaged").unwrap();
+        std::fs::remove_file(tmp_path.join("b.txt")).unwrap();
+        std::fs::write(tmp_path.join("untracked.txt"), "c").unwrap();
+
+        assert_eq!(diff_files(true, tmp_path).unwrap(), "M\ta.txt\nD\tb.txt\n");
+        assert_eq!(
+            diff_index("HEAD", true, true, tmp_path).unwrap(),
+            "M\ta.txt\n"
+        );
+
+        let raw = diff_index("HEAD", false, false, tmp_path).unwrap();
+        let null = ObjectId::NULL.to_string();
+        let lines: Vec<_> = raw.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with(":100644 100644 "));
+        assert!(lines[0].ends_with(&format!(" {} M\ta.txt", null)));
+        assert!(lines[1].starts_with(":100644 000000 "));
+    }
+}
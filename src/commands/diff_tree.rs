@@ -0,0 +1,178 @@
+use anyhow::anyhow;
+use nit::{
+    diff,
+    quote::quote_non_ascii,
+    repository::{git_dir, Repository},
+    revision::Revision,
+};
+use std::path::Path;
+use structopt::StructOpt;
+
+use super::CommandContext;
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Compare the files in differing subtrees, rather than listing the subtrees
+    #[structopt(short = "r")]
+    recursive: bool,
+    /// Only show the status and path of each change
+    #[structopt(long = "name-status")]
+    name_status: bool,
+    /// Show a root commit's files as added, rather than showing nothing for it
+    #[structopt(long = "root")]
+    root: bool,
+    /// Show a merge's changes against each of its parents, rather than showing nothing
+    #[structopt(short = "m")]
+    merges: bool,
+    /// Don't show the commit's ObjectId before its changes
+    #[structopt(long = "no-commit-id")]
+    no_commit_id: bool,
+    /// A commit, or two commits or trees to compare
+    #[structopt(required = true, max_values = 2)]
+    revisions: Vec<String>,
+}
+
+pub fn run(args: Args, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let Args {
+        recursive,
+        name_status,
+        root,
+        merges,
+        no_commit_id,
+        revisions,
+    } = args;
+    let options = DiffTreeOptions {
+        recursive,
+        name_status,
+        root,
+        merges,
+        commit_id: !no_commit_id,
+    };
+    let msg = diff_tree(&revisions, &options, &ctx.root_path)?;
+    write!(ctx.stdout, "{}", msg)?;
+
+    Ok(())
+}
+
+/// What `diff-tree` should compare, and how it should show the changes.
+pub struct DiffTreeOptions {
+    pub recursive: bool,
+    pub name_status: bool,
+    pub root: bool,
+    pub merges: bool,
+    pub commit_id: bool,
+}
+
+/// List the changes between two trees in git's raw format. Given a single commit, list
+/// the changes it made against its parent instead, after the commit's ObjectId. Nothing is
+/// shown for a root commit unless `root`, or for a merge unless `merges`, in which case
+/// its changes against each parent are listed in turn.
+pub fn diff_tree(
+    revisions: &[String],
+    options: &DiffTreeOptions,
+    root_path: &Path,
+) -> anyhow::Result<String> {
+    let repo = Repository::new(git_dir(root_path));
+    let database = repo.database();
+    let refs = repo.refs();
+    let quote = quote_non_ascii(&repo.config()?)?;
+    let format =
+        |changes: Vec<diff::RawChange>| format_raw_changes(&changes, options.name_status, quote);
+
+    let oid = match revisions {
+        [a, b] => {
+            let a = Revision::resolve_tree_ish(a, database, refs)?;
+            let b = Revision::resolve_tree_ish(b, database, refs)?;
+            let changes = diff::raw_changes(database, Some(&a), Some(&b), options.recursive)?;
+            return Ok(format(changes));
+        }
+        [revision] => Revision::resolve(revision, database, refs)?,
+        _ => return Err(anyhow!("usage: nit diff-tree <tree-ish> [<tree-ish>]")),
+    };
+
+    let parents = database.load_commit(&oid)?.parents().to_vec();
+    let bases = match parents.as_slice() {
+        [] if options.root => vec![None],
+        [parent] => vec![Some(*parent)],
+        [_, _, ..] if options.merges => parents.iter().copied().map(Some).collect(),
+        _ => Vec::new(),
+    };
+
+    let mut msg = String::new();
+    for base in bases {
+        let changes = diff::raw_changes(database, base.as_ref(), Some(&oid), options.recursive)?;
+        if changes.is_empty() {
+            continue;
+        }
+        if options.commit_id {
+            msg.push_str(&format!("{}\n", oid));
+        }
+        msg.push_str(&format(changes));
+    }
+
+    Ok(msg)
+}
+
+/// Show raw changes as diff-tree does: in full, or with `name_status`, as status and path.
+/// Paths with bytes outside ASCII are quoted if `quote_non_ascii`.
+pub fn format_raw_changes(
+    changes: &[diff::RawChange],
+    name_status: bool,
+    quote_non_ascii: bool,
+) -> String {
+    changes
+        .iter()
+        .map(|change| match name_status {
+            true => change.to_name_status(quote_non_ascii),
+            false => change.to_raw(None, quote_non_ascii),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::test::{init, write_and_commit};
+
+    #[test]
+    fn lists_raw_changes_with_diff_tree() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        write_and_commit(tmp_path, &[("a.txt", "a"), ("dir/b.txt", "b")], "First");
+        write_and_commit(tmp_path, &[("dir/b.txt", "c")], "Second");
+
+        let diff_trees = |revisions: &[&str], recursive, name_status, root| {
+            let options = DiffTreeOptions {
+                recursive,
+                name_status,
+                root,
+                merges: false,
+                commit_id: true,
+            };
+            let revisions: Vec<_> = revisions.iter().map(|r| r.to_string()).collect();
+            diff_tree(&revisions, &options, tmp_path).unwrap()
+        };
+
+        let repo = Repository::new(tmp_path.join(".git"));
+        let head = repo.refs().read_head().unwrap().unwrap();
+        let head_files = repo.database().load_tree_list(Some(&head)).unwrap();
+        let b = head_files[Path::new("dir/b.txt")].oid();
+
+        assert_eq!(
+            diff_trees(&["HEAD"], false, true, false),
+            format!("{}\nM\tdir\n", head)
+        );
+        let raw = diff_trees(&["HEAD"], true, false, false);
+        assert!(raw.ends_with(&format!(" {} M\tdir/b.txt\n", b)), "{}", raw);
+        assert!(raw.starts_with(&format!("{}\n:100644 100644 ", head)));
+
+        // Root commits show nothing unless asked, and two trees are compared without a header.
+        assert_eq!(diff_trees(&["HEAD~1"], true, true, false), "");
+        assert_eq!(diff_trees(&["HEAD~1"], true, true, true).lines().count(), 3);
+        assert_eq!(
+            diff_trees(&["HEAD", "HEAD~1"], true, true, false),
+            "M\tdir/b.txt\n"
+        );
+    }
+}
@@ -0,0 +1,388 @@
+use anyhow::anyhow;
+use nit::{
+    config::Config,
+    merge::{self},
+    progress::{Progress, StderrProgress},
+    refs::{FetchHead, Refs},
+    remote::{Refspec, Remote},
+    repository::{git_dir, Repository},
+};
+use std::path::Path;
+use structopt::StructOpt;
+
+use super::push::{ref_status_line, RefStatus};
+use super::CommandContext;
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Delete remote-tracking refs for refs that no longer exist on the remote
+    #[structopt(short = "p", long = "prune")]
+    prune: bool,
+    /// Don't report progress
+    #[structopt(short = "q", long = "quiet")]
+    quiet: bool,
+    /// The remote to fetch from (the current branch's remote, or origin, by default)
+    remote: Option<String>,
+}
+
+pub fn run(args: Args, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let Args {
+        prune,
+        quiet,
+        remote,
+    } = args;
+    let mut progress = StderrProgress::new(quiet || ctx.is_quiet());
+    let msg = fetch(remote.as_deref(), prune, &mut progress, &ctx.root_path)?;
+    write!(ctx.info_stderr(), "{}", msg)?;
+
+    Ok(())
+}
+
+/// The remote to use when none is given: the current branch's, or else `origin`.
+pub fn remote_name(remote: Option<&str>, repo: &Repository) -> anyhow::Result<String> {
+    if let Some(remote) = remote {
+        return Ok(remote.to_owned());
+    }
+
+    let current_ref = repo.refs().current_ref()?;
+    let configured = match current_ref.strip_prefix("refs/heads/") {
+        Some(branch) => repo.config()?.get(&format!("branch.{}.remote", branch))?,
+        None => None,
+    };
+
+    Ok(configured.unwrap_or_else(|| "origin".to_owned()))
+}
+
+/// Copy the remote's refs, mapped through its fetch refspecs, into our remote-tracking
+/// refs, along with the objects they need. With `prune`, or `fetch.prune` or
+/// `remote.<name>.prune` set, remote-tracking refs for refs the remote no longer has are
+/// deleted. All the ref updates are made together, or not at all.
+pub fn fetch(
+    remote: Option<&str>,
+    prune: bool,
+    progress: &mut dyn Progress,
+    root_path: &Path,
+) -> anyhow::Result<String> {
+    let repo = Repository::new(git_dir(root_path));
+    let config = repo.config()?;
+    let remote_name = remote_name(remote, &repo)?;
+
+    let url = config
+        .get(&format!("remote.{}.url", remote_name))?
+        .ok_or_else(|| {
+            anyhow!(
+                "fatal: '{}' does not appear to be a git repository",
+                remote_name
+            )
+        })?;
+    let remote = Remote::open(&url, root_path)?;
+    let specs = fetch_refspecs(&config, &remote_name)?;
+    let prune = prune
+        || config
+            .get_bool(&format!("remote.{}.prune", remote_name))?
+            .or(config.get_bool("fetch.prune")?)
+            .unwrap_or(false);
+
+    let remote_refs = remote.list_refs()?;
+    let mapped: Vec<_> = remote_refs
+        .iter()
+        .filter_map(|(name, new)| {
+            specs.iter().find_map(|spec| {
+                let local_name = spec.map(name)?;
+                Some((spec, name, local_name, new))
+            })
+        })
+        .collect();
+
+    let tips: Vec<_> = mapped.iter().map(|(_, _, _, new)| **new).collect();
+    remote.fetch_objects(&repo, &tips, progress)?;
+
+    // The current branch's upstream is the ref a pull would merge.
+    let merge_ref = match repo.refs().current_ref()?.strip_prefix("refs/heads/") {
+        Some(branch)
+            if config.get(&format!("branch.{}.remote", branch))? == Some(remote_name.clone()) =>
+        {
+            config.get(&format!("branch.{}.merge", branch))?
+        }
+        _ => None,
+    };
+    let fetch_heads: Vec<_> = mapped
+        .iter()
+        .map(|(_, source, _, new)| {
+            let description = match (
+                source.strip_prefix("refs/heads/"),
+                source.strip_prefix("refs/tags/"),
+            ) {
+                (Some(branch), _) => format!("branch '{}' of {}", branch, url),
+                (_, Some(tag)) => format!("tag '{}' of {}", tag, url),
+                _ => format!("'{}' of {}", source, url),
+            };
+            FetchHead {
+                oid: **new,
+                for_merge: merge_ref.as_ref() == Some(*source),
+                description,
+            }
+        })
+        .collect();
+    repo.refs().write_fetch_head(&fetch_heads)?;
+
+    let mut transaction = repo.refs().transaction();
+    let mut lines = Vec::new();
+    let mut rejected = false;
+
+    for (spec, source, local_name, new) in mapped {
+        let old = repo.refs().read_ref(&local_name)?;
+        let status = match &old {
+            Some(old) if old == new => continue,
+            None => RefStatus::New,
+            Some(old) if merge::is_ancestor(repo.database(), old, new)? => {
+                RefStatus::FastForward(*old, *new)
+            }
+            Some(old) if spec.force => RefStatus::Forced(*old, *new),
+            Some(_) => RefStatus::Rejected("non-fast-forward"),
+        };
+
+        let reason = match status {
+            RefStatus::Rejected(_) => None,
+            RefStatus::FastForward(..) => Some("fast-forward"),
+            RefStatus::Forced(..) => Some("forced-update"),
+            _ => Some("storing head"),
+        };
+        match reason {
+            Some(reason) => {
+                let message = format!("fetch {}: {}", remote_name, reason);
+                transaction.update(&local_name, old.as_ref(), Some(new), &message);
+            }
+            None => rejected = true,
+        }
+        lines.push((status, source.clone(), local_name));
+    }
+
+    if prune {
+        // Symbolic refs like `refs/remotes/origin/HEAD` are never pruned, as in git.
+        let local_refs = repo.refs().list(Some("refs/"))?;
+        for local_ref in local_refs.into_iter().filter(|r| r.symref.is_none()) {
+            let source = specs
+                .iter()
+                .find_map(|spec| spec.reverse().map(&local_ref.name));
+            let gone =
+                source.is_some_and(|source| !remote_refs.iter().any(|(name, _)| *name == source));
+
+            if gone {
+                let message = format!("fetch {}: prune", remote_name);
+                transaction.update(&local_ref.name, local_ref.oid.as_ref(), None, &message);
+                lines.push((RefStatus::Deleted, String::new(), local_ref.name));
+            }
+        }
+    }
+
+    transaction.commit()?;
+
+    if lines.is_empty() {
+        return Ok(String::new());
+    }
+
+    let width = lines
+        .iter()
+        .map(|(_, source, _)| Refs::short_name(source).chars().count())
+        .max()
+        .unwrap_or(0)
+        .max("(none)".len());
+    let mut msg = format!("From {}\n", url);
+    for (status, source, local_name) in &lines {
+        let source = match source.as_str() {
+            "" => "(none)",
+            source => Refs::short_name(source),
+        };
+        let line = ref_status_line(status, &format!("{:<1$}", source, width), local_name);
+        msg.push_str(&line);
+    }
+
+    if rejected {
+        return Err(anyhow!(
+            "{}error: some local refs could not be updated",
+            msg
+        ));
+    }
+
+    Ok(msg)
+}
+
+/// The refspecs a remote is fetched with: its `remote.<name>.fetch` settings, or if there
+/// are none, its branches into its remote-tracking refs.
+pub fn fetch_refspecs(config: &Config, remote_name: &str) -> anyhow::Result<Vec<Refspec>> {
+    let mut specs = config
+        .get_all(&format!("remote.{}.fetch", remote_name))?
+        .iter()
+        .map(|spec| spec.parse())
+        .collect::<Result<Vec<Refspec>, _>>()?;
+    if specs.is_empty() {
+        specs.push(format!("+refs/heads/*:refs/remotes/{}/*", remote_name).parse()?);
+    }
+
+    Ok(specs)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::test::{author_env, init, pack_refs, write_and_commit};
+    use crate::commands::{branch::*, rebase::*};
+    use nit::{
+        database::Database,
+        refs::{FETCH_HEAD, HEAD, ORIG_HEAD},
+        revision::Revision,
+    };
+
+    #[test]
+    fn fetches_and_prunes_remote_tracking_refs() {
+        let (local, origin) = (init().unwrap(), init().unwrap());
+        let (local_path, origin_path) = (local.path(), origin.path());
+        std::fs::write(
+            local_path.join(".git/config"),
+            format!("[remote \"origin\"]\n\turl = {}\n", origin_path.display()),
+        )
+        .unwrap();
+
+        write_and_commit(origin_path, &[("a.txt", "a")], "First");
+        create_branch("topic", None, false, origin_path).unwrap();
+        create_branch("doomed", None, false, origin_path).unwrap();
+
+        let mut progress = Vec::new();
+        let msg = fetch(None, false, &mut progress, local_path).unwrap();
+        assert_eq!(progress, vec!["remote: Total 3\n".to_owned()]);
+        assert_eq!(
+            msg,
+            format!(
+                "From {}\n \
+                 * [new branch]      doomed -> origin/doomed\n \
+                 * [new branch]      master -> origin/master\n \
+                 * [new branch]      topic  -> origin/topic\n",
+                origin_path.display()
+            )
+        );
+        let local_refs = Refs::new(&local_path.join(".git"));
+        let first = local_refs.read_ref("origin/master").unwrap().unwrap();
+        assert!(Database::new(local_path.join(".git/objects"))
+            .contains(&first)
+            .unwrap());
+
+        delete_branches(&["doomed".to_owned()], true, origin_path).unwrap();
+        write_and_commit(origin_path, &[("a.txt", "b")], "Second");
+        assert_eq!(
+            fetch(None, false, &mut Vec::new(), local_path)
+                .unwrap()
+                .lines()
+                .count(),
+            2
+        );
+        assert!(local_refs.read_ref("origin/doomed").unwrap().is_some());
+
+        // A ref that can't be locked stops the whole prune.
+        let lock = local_path.join(".git/refs/remotes/origin/topic.lock");
+        create_branch("other", None, false, origin_path).unwrap();
+        delete_branches(&["topic".to_owned()], true, origin_path).unwrap();
+        std::fs::write(&lock, "").unwrap();
+        assert!(fetch(None, true, &mut Vec::new(), local_path).is_err());
+        assert!(local_refs.read_ref("origin/doomed").unwrap().is_some());
+        assert!(local_refs.read_ref("origin/other").unwrap().is_none());
+
+        std::fs::remove_file(&lock).unwrap();
+        let msg = fetch(None, true, &mut Vec::new(), local_path).unwrap();
+        assert!(msg.contains(" - [deleted]         (none) -> origin/doomed\n"));
+        assert!(msg.contains(" - [deleted]         (none) -> origin/topic\n"));
+        assert_eq!(
+            local_refs
+                .list(Some("refs/remotes/"))
+                .unwrap()
+                .into_iter()
+                .map(|r| r.name)
+                .collect::<Vec<_>>(),
+            vec!["refs/remotes/origin/master", "refs/remotes/origin/other"]
+        );
+    }
+
+    #[test]
+    fn prunes_packed_remote_tracking_refs() {
+        let (local, origin) = (init().unwrap(), init().unwrap());
+        let (local_path, origin_path) = (local.path(), origin.path());
+        std::fs::write(
+            local_path.join(".git/config"),
+            format!("[remote \"origin\"]\n\turl = {}\n", origin_path.display()),
+        )
+        .unwrap();
+
+        write_and_commit(origin_path, &[("a.txt", "a")], "First");
+        create_branch("doomed", None, false, origin_path).unwrap();
+        fetch(None, false, &mut Vec::new(), local_path).unwrap();
+        let local_refs = Refs::new(&local_path.join(".git"));
+        local_refs
+            .set_symref("refs/remotes/origin/HEAD", "refs/remotes/origin/master")
+            .unwrap();
+        pack_refs(local_path);
+
+        delete_branches(&["doomed".to_owned()], true, origin_path).unwrap();
+        let msg = fetch(None, true, &mut Vec::new(), local_path).unwrap();
+        assert!(msg.contains(" - [deleted]         (none) -> origin/doomed\n"));
+        assert!(!msg.contains("origin/HEAD"));
+        assert_eq!(local_refs.read_ref("origin/doomed").unwrap(), None);
+        assert_eq!(
+            local_refs
+                .list(Some("refs/remotes/"))
+                .unwrap()
+                .into_iter()
+                .map(|r| r.name)
+                .collect::<Vec<_>>(),
+            vec!["refs/remotes/origin/HEAD", "refs/remotes/origin/master"]
+        );
+    }
+
+    #[test]
+    fn records_fetch_head_and_orig_head() {
+        let (local, origin) = (init().unwrap(), init().unwrap());
+        let (local_path, origin_path) = (local.path(), origin.path());
+        std::fs::write(
+            local_path.join(".git/config"),
+            format!(
+                "[remote \"origin\"]\n\turl = {}\n\
+                 [branch \"master\"]\n\tremote = origin\n\tmerge = refs/heads/master\n",
+                origin_path.display()
+            ),
+        )
+        .unwrap();
+
+        write_and_commit(origin_path, &[("a.txt", "a")], "First");
+        create_branch("topic", None, false, origin_path).unwrap();
+        write_and_commit(origin_path, &[("a.txt", "b")], "Second");
+        write_and_commit(local_path, &[("b.txt", "b")], "Local");
+
+        fetch(None, false, &mut Vec::new(), local_path).unwrap();
+        let origin_refs = Refs::new(&origin_path.join(".git"));
+        let (master, topic) = (
+            origin_refs.read_ref("master").unwrap().unwrap(),
+            origin_refs.read_ref("topic").unwrap().unwrap(),
+        );
+        assert_eq!(
+            std::fs::read_to_string(local_path.join(".git/FETCH_HEAD")).unwrap(),
+            format!(
+                "{}\t\tbranch 'master' of {2}\n\
+                 {}\tnot-for-merge\tbranch 'topic' of {2}\n",
+                master,
+                topic,
+                origin_path.display()
+            )
+        );
+
+        // FETCH_HEAD resolves to the ref to be merged, and ORIG_HEAD to where a rebase
+        // started from.
+        let repo = Repository::new(local_path.join(".git"));
+        let resolve = |name| Revision::resolve(name, repo.database(), repo.refs()).unwrap();
+        assert_eq!(resolve(FETCH_HEAD), master);
+        assert_eq!(resolve("FETCH_HEAD^"), topic);
+
+        let local_head = resolve(HEAD);
+        rebase(FETCH_HEAD, false, false, &author_env(), local_path).unwrap();
+        assert_eq!(resolve(ORIG_HEAD), local_head);
+        assert_eq!(resolve("HEAD^"), master);
+    }
+}
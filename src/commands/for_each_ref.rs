@@ -0,0 +1,133 @@
+use nit::{
+    ref_format::{self, RefFormat, SortKey},
+    repository::{git_dir, Repository},
+};
+use std::path::Path;
+use structopt::StructOpt;
+
+use super::CommandContext;
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// What to print for each ref, e.g. "%(refname) %(objectname) %(committerdate)"
+    #[structopt(long = "format")]
+    format: Option<RefFormat>,
+    /// A field to sort by, with a leading "-" to reverse the order. The last key given
+    /// is the one sorted by first
+    #[structopt(long = "sort", number_of_values = 1)]
+    sort: Vec<SortKey>,
+    /// Only list refs that start with one of these prefixes or match one of these globs
+    patterns: Vec<String>,
+}
+
+pub fn run(args: Args, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let Args {
+        format,
+        sort,
+        patterns,
+    } = args;
+    let msg = for_each_ref(
+        &format.unwrap_or_default(),
+        &sort,
+        &patterns,
+        &ctx.root_path,
+    )?;
+    write!(ctx.stdout, "{}", msg)?;
+
+    Ok(())
+}
+
+/// List the refs matching any of the patterns, or every ref if there are none.
+pub fn for_each_ref(
+    format: &RefFormat,
+    sort: &[SortKey],
+    patterns: &[String],
+    root_path: &Path,
+) -> anyhow::Result<String> {
+    let repo = Repository::new(git_dir(root_path));
+
+    let refs = repo
+        .refs()
+        .list(Some("refs/"))?
+        .into_iter()
+        .filter(|r| {
+            patterns.is_empty()
+                || patterns
+                    .iter()
+                    .any(|pattern| ref_format::pattern_matches(pattern, &r.name))
+        })
+        .collect();
+    let sort: Vec<_> = sort.iter().rev().copied().collect();
+
+    Ok(ref_format::format_refs(
+        refs,
+        format,
+        &sort,
+        repo.database(),
+    )?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::branch::*;
+    use crate::commands::test::{annotate_tag, init, write_and_commit};
+    use nit::refs::Refs;
+
+    #[test]
+    fn formats_refs() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        write_and_commit(tmp_path, &[("a.txt", "a")], "First");
+        create_branch("topic", None, false, tmp_path).unwrap();
+        write_and_commit(tmp_path, &[("a.txt", "b")], "Second");
+        let refs = Refs::new(&tmp_path.join(".git"));
+        let head = refs.read_head().unwrap().unwrap();
+        refs.update_ref("refs/remotes/origin/master", &head, "fetch")
+            .unwrap();
+
+        let format = "%(refname:short) %(objecttype) %(subject)%09%%"
+            .parse()
+            .unwrap();
+        let sort = ["-refname".parse().unwrap()];
+        assert_eq!(
+            for_each_ref(&format, &sort, &["refs/heads".to_owned()], tmp_path).unwrap(),
+            "topic commit First\t%\nmaster commit Second\t%\n"
+        );
+
+        let listing = for_each_ref(&RefFormat::default(), &[], &[], tmp_path).unwrap();
+        assert_eq!(listing.lines().count(), 3);
+        assert_eq!(
+            listing.lines().next(),
+            Some(format!("{} commit\trefs/heads/master", head).as_str())
+        );
+    }
+
+    #[test]
+    fn peels_annotated_tags() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        write_and_commit(tmp_path, &[("a.txt", "a")], "First");
+        let refs = Refs::new(&tmp_path.join(".git"));
+        let head = refs.read_head().unwrap().unwrap();
+        let tag = annotate_tag(tmp_path, "v1.0", "HEAD", "Version 1.0");
+        let outer = annotate_tag(tmp_path, "v1.0-signed", "v1.0", "Signed 1.0");
+        refs.create_tag("light", &head).unwrap();
+
+        let format = "%(refname:short) %(objecttype) %(objectname) %(subject) | \
+                      %(*objecttype) %(*objectname) %(*subject)"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            for_each_ref(&format, &[], &["refs/tags".to_owned()], tmp_path).unwrap(),
+            format!(
+                "light commit {0} First |   \n\
+                 v1.0 tag {1} Version 1.0 | commit {0} First\n\
+                 v1.0-signed tag {2} Signed 1.0 | commit {0} First\n",
+                head, tag, outer
+            )
+        );
+    }
+}
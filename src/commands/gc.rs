@@ -0,0 +1,439 @@
+use anyhow::anyhow;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use nit::{
+    config::Config,
+    database::{Commit, Database, ObjectId, Tag, Tree, TreeEntry},
+    lockfile::{Lockfile, LockfileError},
+    pack::{ObjectType, PackEntry},
+    refs::Refs,
+    repository::{git_dir, set_git_env, Repository},
+    revision::parse_date,
+};
+use std::collections::HashSet;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::{env, io::Write};
+use structopt::StructOpt;
+
+use super::repack::repack;
+use super::CommandContext;
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Only tidy up if there are more loose objects than `gc.auto` (6700 by default) or
+    /// more packs than `gc.autoPackLimit` (50 by default). Setting either to 0 turns that
+    /// check off
+    #[structopt(long = "auto")]
+    auto: bool,
+}
+
+pub fn run(args: Args, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let Args { auto } = args;
+    let msg = gc(auto, &ctx.root_path)?;
+    write!(ctx.info_stderr(), "{}", msg)?;
+
+    Ok(())
+}
+
+/// Read a number from the config, or use a default if it isn't set.
+pub fn config_number(config: &Config, key: &str, default: usize) -> anyhow::Result<usize> {
+    match config.get(key)? {
+        Some(value) => value
+            .parse()
+            .map_err(|_| anyhow!("bad config variable '{}': {}", key, value)),
+        None => Ok(default),
+    }
+}
+
+/// How many loose objects there can be before `gc --auto` packs them.
+const GC_AUTO: usize = 6700;
+
+/// How many packs there can be before `gc --auto` combines them.
+const GC_AUTO_PACK_LIMIT: usize = 50;
+
+/// How old reflog entries have to be before gc drops them, unless `gc.reflogExpire` says.
+const GC_REFLOG_EXPIRE: &str = "90.days.ago";
+
+/// How old unreachable objects have to be before gc deletes them, unless `gc.pruneExpire`
+/// says.
+const GC_PRUNE_EXPIRE: &str = "2.weeks.ago";
+
+/// Read an expiry date from the config, or use a default if it isn't set. `never` gives
+/// `None`, meaning nothing expires.
+pub fn config_expiry(
+    config: &Config,
+    key: &str,
+    default: &str,
+    now: DateTime<Utc>,
+) -> anyhow::Result<Option<DateTime<Utc>>> {
+    let value = config.get(key)?.unwrap_or_else(|| default.to_owned());
+    match value.as_str() {
+        "never" | "false" => Ok(None),
+        text => match parse_date(text, now) {
+            Some(date) => Ok(Some(date)),
+            None => Err(anyhow!("bad config variable '{}': {}", key, value)),
+        },
+    }
+}
+
+/// Tidy up the repository: drop reflog entries older than `gc.reflogExpire`, put every
+/// object reachable from a ref or a reflog into a single pack, and delete unreachable
+/// objects older than `gc.pruneExpire`. With `auto`, only do so if there are more loose
+/// objects than `gc.auto` or more packs than `gc.autoPackLimit`; if it's only the loose
+/// objects, they're packed on their own.
+pub fn gc(auto: bool, root_path: &Path) -> anyhow::Result<String> {
+    let git_path = git_dir(root_path);
+    let all = match auto {
+        true => match needs_gc(&Repository::new(&git_path))? {
+            Some(all) => all,
+            None => return Ok(String::new()),
+        },
+        false => true,
+    };
+
+    let mut lock = Lockfile::new(&git_path.join("gc"));
+    match lock.hold_for_update() {
+        Err(nit::Error::Lockfile(LockfileError::LockDenied(_))) if auto => return Ok(String::new()),
+        result => result.context("gc is already running")?,
+    }
+
+    let msg = tidy(all, root_path);
+    lock.rollback()?;
+
+    match auto {
+        true => Ok(format!(
+            "Auto packing the repository for optimum performance.\n{}",
+            msg?
+        )),
+        false => msg,
+    }
+}
+
+/// Do gc's work, once it holds the lock.
+fn tidy(all: bool, root_path: &Path) -> anyhow::Result<String> {
+    let repo = Repository::new(git_dir(root_path));
+    let config = repo.config()?;
+    let now = Utc::now();
+    let reflog_expire = config_expiry(&config, "gc.reflogExpire", GC_REFLOG_EXPIRE, now)?;
+    let prune_expire = config_expiry(&config, "gc.pruneExpire", GC_PRUNE_EXPIRE, now)?;
+
+    if let Some(date) = reflog_expire {
+        for name in repo.refs().reflogs()? {
+            repo.refs().expire_reflog(&name, date)?;
+        }
+    }
+
+    // Unreachable objects in the old packs are kept loose, to be pruned when they're old
+    // enough, unless they'd be pruned straight away.
+    let loosen = prune_expire != Some(now);
+    let msg = repack(all, loosen, true, None, None, root_path)?;
+
+    if let Some(date) = prune_expire {
+        // Every reachable object is packed now, so the loose objects left are unreachable,
+        // apart from any that have been staged but not committed.
+        let mut index = repo.configured_index()?;
+        index.load()?;
+        let staged = index.entries().values().map(|entry| *entry.oid()).collect();
+        repo.database().prune_loose(&staged, date.timestamp())?;
+    }
+
+    Ok(msg)
+}
+
+/// Whether `gc --auto` has work to do: `Some(true)` if there are so many packs that
+/// everything should be repacked, `Some(false)` if only the loose objects need packing.
+fn needs_gc(repo: &Repository) -> anyhow::Result<Option<bool>> {
+    let config = repo.config()?;
+    let database = repo.database();
+
+    let pack_limit = config_number(&config, "gc.autoPackLimit", GC_AUTO_PACK_LIMIT)?;
+    if pack_limit > 0 && database.packs()?.len() > pack_limit {
+        return Ok(Some(true));
+    }
+
+    let loose_limit = config_number(&config, "gc.auto", GC_AUTO)?;
+    if loose_limit > 0 && database.estimate_loose_objects()? > loose_limit {
+        return Ok(Some(false));
+    }
+
+    Ok(None)
+}
+
+/// Run `gc --auto` after a command that may have written a lot of loose objects. Unless
+/// `gc.autoDetach` is false, it runs in another process, so the command can finish first.
+pub fn auto_gc(repo: &Repository, root_path: &Path, stderr: &mut dyn Write) -> anyhow::Result<()> {
+    if needs_gc(repo)?.is_none() {
+        return Ok(());
+    }
+
+    if repo.config()?.get_bool("gc.autoDetach")?.unwrap_or(true) {
+        writeln!(
+            stderr,
+            "Auto packing the repository in background for optimum performance."
+        )?;
+        writeln!(stderr, "See \"nit gc --help\" for manual housekeeping.")?;
+        let mut command = Command::new(env::current_exe()?);
+        set_git_env(&mut command, &repo.git_env());
+        command
+            .args(["gc", "--auto"])
+            .current_dir(root_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+    } else {
+        write!(stderr, "{}", gc(true, root_path)?)?;
+    }
+
+    Ok(())
+}
+
+/// Collect every object reachable from HEAD, any ref, or any position recorded in a reflog:
+/// the commits and annotated tags first, and then the trees and blobs they refer to, each
+/// named after the last part of its path.
+pub fn reachable_objects(database: &Database, refs: &Refs) -> anyhow::Result<Vec<PackEntry>> {
+    let mut tips = vec![refs.read_head()?];
+    tips.extend(refs.list(None)?.into_iter().map(|r| r.oid));
+
+    // Reflogs can mention objects that are long gone, which are no loss.
+    for name in refs.reflogs()? {
+        for entry in refs.reflog(&name)? {
+            for oid in [entry.old, entry.new] {
+                if !oid.is_null() && database.contains(&oid)? {
+                    tips.push(Some(oid));
+                }
+            }
+        }
+    }
+
+    let (mut entries, trees) = reachable_history(database, tips.into_iter().flatten().collect())?;
+    let mut seen = entries.iter().map(|entry| entry.oid).collect();
+    for tree in trees {
+        add_tree_objects(database, tree, String::new(), &mut seen, &mut entries)?;
+    }
+
+    Ok(entries)
+}
+
+/// Walk from `tips` through annotated tags and commits' parents, collecting the commits and
+/// tags on the way and any blobs they point at, along with the trees they point at, which
+/// are left for the caller to walk if it needs to.
+pub fn reachable_history(
+    database: &Database,
+    tips: Vec<ObjectId>,
+) -> anyhow::Result<(Vec<PackEntry>, Vec<ObjectId>)> {
+    let mut seen = HashSet::new();
+    let mut entries = Vec::new();
+    let mut trees = Vec::new();
+    let mut queue = tips;
+
+    while let Some(oid) = queue.pop() {
+        if seen.contains(&oid) {
+            continue;
+        }
+
+        // Most refs point at commits, but tags and replacements can be of any type.
+        let (kind, data) = database.read_object(&oid)?;
+        let kind = match kind.as_str() {
+            "commit" => {
+                let commit = Commit::parse(&data)?;
+                queue.extend(commit.parents().iter().copied());
+                trees.push(*commit.tree());
+                ObjectType::Commit
+            }
+            "tag" => {
+                queue.push(*Tag::parse(&data)?.object());
+                ObjectType::Tag
+            }
+            "tree" => {
+                trees.push(oid);
+                continue;
+            }
+            "blob" => ObjectType::Blob,
+            kind => return Err(anyhow!("object {} has unknown type '{}'", oid, kind)),
+        };
+
+        seen.insert(oid);
+        entries.push(PackEntry {
+            oid,
+            kind,
+            data,
+            name: String::new(),
+        });
+    }
+
+    Ok((entries, trees))
+}
+
+fn add_tree_objects(
+    database: &Database,
+    oid: ObjectId,
+    name: String,
+    seen: &mut HashSet<ObjectId>,
+    entries: &mut Vec<PackEntry>,
+) -> anyhow::Result<()> {
+    if !seen.insert(oid) {
+        return Ok(());
+    }
+
+    let (_, data) = database.read_object(&oid)?;
+    let tree = Tree::parse(&data)?;
+    entries.push(PackEntry {
+        oid,
+        kind: ObjectType::Tree,
+        data,
+        name,
+    });
+
+    for (name, entry) in tree.entries() {
+        let entry = match entry {
+            TreeEntry::Stored(entry) => entry,
+            _ => continue,
+        };
+        let name = name.to_string_lossy().into_owned();
+
+        if entry.is_tree() {
+            add_tree_objects(database, *entry.oid(), name, seen, entries)?;
+        } else if seen.insert(*entry.oid()) {
+            let (_, data) = database.read_object(entry.oid())?;
+            entries.push(PackEntry {
+                oid: *entry.oid(),
+                kind: ObjectType::Blob,
+                data,
+                name,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::test::{annotate_tag, init, write_and_commit};
+    use nit::database::Blob;
+
+    #[test]
+    fn packs_automatically_past_thresholds() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        write_and_commit(tmp_path, &[("a.txt", "a")], "First");
+        std::fs::write(
+            tmp_path.join(".git").join("config"),
+            "[gc]\n\tauto = 1\n\tautoPackLimit = 2\n",
+        )
+        .unwrap();
+
+        let repo = Repository::new(tmp_path.join(".git"));
+        let database = repo.database();
+
+        // Loose objects are counted by sampling the `17` directory, so make sure there's one.
+        let contents = (0..)
+            .map(|i| format!("{}\n", i))
+            .find(|data| {
+                let blob = Blob::new(data.clone().into_bytes());
+                database
+                    .hash_object(&blob)
+                    .unwrap()
+                    .to_string()
+                    .starts_with("17")
+            })
+            .unwrap();
+        if database.estimate_loose_objects().unwrap() == 0 {
+            assert_eq!(gc(true, tmp_path).unwrap(), "");
+        }
+        write_and_commit(tmp_path, &[("b.txt", &contents)], "Second");
+
+        let msg = gc(true, tmp_path).unwrap();
+        assert!(msg.starts_with("Auto packing the repository for optimum performance.\nTotal "));
+        assert!(database.loose_objects().unwrap().is_empty());
+        assert_eq!(gc(true, tmp_path).unwrap(), "");
+
+        for file in ["c.txt", "d.txt"].iter() {
+            write_and_commit(tmp_path, &[(file, file)], file);
+            repack(false, false, true, None, None, tmp_path).unwrap();
+        }
+        assert_eq!(database.packs().unwrap().len(), 3);
+        assert!(gc(true, tmp_path).unwrap().contains("Total 12 "));
+        assert_eq!(database.packs().unwrap().len(), 1);
+
+        assert!(gc(false, tmp_path).unwrap().starts_with("Total 12 "));
+    }
+
+    #[test]
+    fn expires_reflogs_and_prunes_unreachable_objects() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        write_and_commit(tmp_path, &[("a.txt", "a")], "First");
+        let repo = Repository::new(tmp_path.join(".git"));
+        let first = repo.refs().read_head().unwrap().unwrap();
+        write_and_commit(tmp_path, &[("a.txt", "b")], "Second");
+        let second = repo.refs().read_head().unwrap().unwrap();
+
+        // The second commit is only in the reflogs now, and the blob is in nothing at all.
+        repo.refs()
+            .update_ref("refs/heads/master", &first, "reset: moving to HEAD^")
+            .unwrap();
+        let blob = repo.database().store(&Blob::new(b"lost".to_vec())).unwrap();
+        let contains = |oid| {
+            Repository::new(tmp_path.join(".git"))
+                .database()
+                .contains(oid)
+        };
+
+        gc(false, tmp_path).unwrap();
+        assert!(contains(&second).unwrap());
+        assert!(contains(&blob).unwrap());
+
+        let config = tmp_path.join(".git").join("config");
+        std::fs::write(&config, "[gc]\n\tpruneExpire = now\n").unwrap();
+        gc(false, tmp_path).unwrap();
+        assert!(contains(&second).unwrap());
+        assert!(!contains(&blob).unwrap());
+
+        std::fs::write(&config, "[gc]\n\tpruneExpire = now\n\treflogExpire = now\n").unwrap();
+        gc(false, tmp_path).unwrap();
+        assert!(!contains(&second).unwrap());
+        assert!(contains(&first).unwrap());
+        assert!(repo.refs().reflog("HEAD").unwrap().is_empty());
+
+        std::fs::write(&config, "[gc]\n\tpruneExpire = 9999999999.years.ago\n").unwrap();
+        assert!(gc(false, tmp_path).is_err());
+    }
+
+    #[test]
+    fn keeps_annotated_tags_and_what_they_tag() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        write_and_commit(tmp_path, &[("a.txt", "a")], "First");
+        let repo = Repository::new(tmp_path.join(".git"));
+        let first = repo.refs().read_head().unwrap().unwrap();
+        let tag = annotate_tag(tmp_path, "v1.0", "HEAD", "Version 1.0");
+        let blob = repo
+            .database()
+            .store(&Blob::new(b"tagged".to_vec()))
+            .unwrap();
+        repo.refs().create_tag("blob", &blob).unwrap();
+        let blob_tag = annotate_tag(tmp_path, "blob-tag", "blob", "A blob");
+        repo.refs().delete_ref("refs/tags/blob").unwrap();
+        write_and_commit(tmp_path, &[("a.txt", "b")], "Second");
+
+        // Only the tag keeps the first commit now.
+        let config = tmp_path.join(".git").join("config");
+        std::fs::write(&config, "[gc]\n\tpruneExpire = now\n\treflogExpire = now\n").unwrap();
+        gc(false, tmp_path).unwrap();
+
+        let repo = Repository::new(tmp_path.join(".git"));
+        let database = repo.database();
+        assert!(database.loose_objects().unwrap().is_empty());
+        for oid in [tag, first, blob_tag, blob] {
+            assert!(database.contains(&oid).unwrap(), "{} was pruned", oid);
+        }
+        assert!(database.load(&tag).is_ok());
+    }
+}
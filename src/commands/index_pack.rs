@@ -0,0 +1,62 @@
+use anyhow::anyhow;
+use anyhow::Context;
+use nit::{
+    database::ObjectId,
+    pack::{Pack, PackIndex},
+    repository::{git_dir, Repository},
+};
+use std::fs;
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+
+use super::CommandContext;
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Complete a thin pack, by adding the objects its deltas are made against from this
+    /// repository. The completed pack is written over the one given
+    #[structopt(long = "fix-thin")]
+    fix_thin: bool,
+    /// The pack to index. The index is written next to it, with the extension `.idx`
+    pack: PathBuf,
+}
+
+pub fn run(args: Args, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let Args { fix_thin, pack } = args;
+    let checksum = index_pack(&pack, fix_thin, &ctx.root_path)?;
+    writeln!(ctx.stdout, "{}", checksum)?;
+
+    Ok(())
+}
+
+/// Write the index for a pack file next to it, returning the pack's checksum. With
+/// `fix_thin`, any delta bases missing from the pack are first found in the repository at
+/// `root_path` and appended to it.
+fn index_pack(pack_path: &Path, fix_thin: bool, root_path: &Path) -> anyhow::Result<ObjectId> {
+    if pack_path.extension() != Some("pack".as_ref()) {
+        return Err(anyhow!(
+            "packfile name '{}' does not end with '.pack'",
+            pack_path.display()
+        ));
+    }
+
+    let bytes =
+        fs::read(pack_path).with_context(|| format!("Could not read {}", pack_path.display()))?;
+    let pack = if fix_thin {
+        let repo = Repository::new(git_dir(root_path));
+        let database = repo.database();
+        let (fixed, pack) = Pack::fix_thin(&bytes, &|oid| database.find_object(oid))?;
+        if fixed != bytes {
+            fs::write(pack_path, fixed)?;
+        }
+        pack
+    } else {
+        Pack::parse(&bytes)?
+    };
+    fs::write(
+        pack_path.with_extension("idx"),
+        PackIndex::new(&pack).to_bytes(),
+    )?;
+
+    Ok(*pack.checksum())
+}
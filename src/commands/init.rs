@@ -0,0 +1,64 @@
+use nit::config::Config;
+use std::fs;
+use std::path::Path;
+use structopt::StructOpt;
+
+use super::CommandContext;
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    #[structopt(default_value = ".")]
+    path: String,
+}
+
+pub fn run(args: Args, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let Args { path } = args;
+    let msg = init_repository(&ctx.root_path.join(path))?;
+    write!(ctx.info(), "{}", msg)?;
+
+    Ok(())
+}
+
+pub fn init_repository(path: &Path) -> anyhow::Result<String> {
+    let root_path = fs::canonicalize(Path::new(path))?;
+    let git_path = root_path.join(".git");
+    for &dir in ["objects", "refs"].iter() {
+        fs::create_dir_all(git_path.join(dir))?;
+    }
+
+    // Only filesystems that can't make symlinks need saying so.
+    let probe = git_path.join("symlink-test");
+    let symlinks = nit::sys::symlink("testing", &probe).is_ok();
+    let _ = fs::remove_file(&probe);
+    if !symlinks {
+        let mut config = Config::new(git_path.join("config"));
+        config.load_for_update()?;
+        config.set("core.symlinks", "false")?;
+        config.save()?;
+    }
+
+    Ok(format!(
+        "Initialised empty Nit repository in {}\n",
+        git_path.to_str().unwrap_or("Unknown")
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::commands::test::init;
+
+    #[test]
+    fn inits_a_repository() {
+        let tmp = init().unwrap();
+        let mut dirs: Vec<_> = std::fs::read_dir(tmp.path().join(".git"))
+            .unwrap()
+            .map(|p| {
+                let p = p.unwrap();
+                p.file_name()
+            })
+            .collect();
+        dirs.sort();
+
+        assert_eq!(dirs, vec!["objects", "refs"]);
+    }
+}
@@ -0,0 +1,53 @@
+use nit::trailers::{self, Trailer};
+use std::fs;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+use super::CommandContext;
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// A trailer to add, such as "Reviewed-by: Name <email>"
+    #[structopt(long = "trailer", number_of_values = 1)]
+    trailers: Vec<Trailer>,
+    /// Print only the trailers already in the message
+    #[structopt(long = "parse")]
+    parse: bool,
+    /// Read the message from this file instead of stdin
+    file: Option<PathBuf>,
+}
+
+pub fn run(args: Args, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let Args {
+        trailers,
+        parse,
+        file,
+    } = args;
+    let message = match file {
+        Some(file) => fs::read_to_string(file)?,
+        None => {
+            let mut message = String::new();
+            ctx.stdin.read_to_string(&mut message)?;
+            message
+        }
+    };
+    write!(
+        ctx.stdout,
+        "{}",
+        interpret_trailers(&message, &trailers, parse)
+    )?;
+
+    Ok(())
+}
+
+/// Add trailers to a message, or with `parse`, list the trailers it already has.
+pub fn interpret_trailers(message: &str, trailers: &[Trailer], parse: bool) -> String {
+    if parse {
+        return trailers::parse(message)
+            .iter()
+            .map(|trailer| format!("{}\n", trailer))
+            .collect();
+    }
+
+    trailers::append(message, trailers)
+}
@@ -0,0 +1,235 @@
+use anyhow::anyhow;
+use nit::{
+    database::{Commit, ObjectId},
+    refs::HEAD,
+    repository::{git_dir, Repository},
+    revision::Revision,
+    revwalk::{PathFilter, RevWalk},
+};
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+
+use super::CommandContext;
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Show each commit on one line, as its abbreviated id and title
+    #[structopt(long = "oneline")]
+    oneline: bool,
+    /// Show at most <n> commits
+    #[structopt(short = "n", long = "max-count")]
+    max_count: Option<usize>,
+    /// Keep following a file's history back past the commits that renamed it
+    #[structopt(long = "follow")]
+    follow: bool,
+    /// The commit to start from (defaults to HEAD)
+    revision: Option<String>,
+    /// Only show commits that changed these files
+    #[structopt(last = true)]
+    paths: Vec<PathBuf>,
+}
+
+pub fn run(args: Args, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let Args {
+        oneline,
+        max_count,
+        follow,
+        revision,
+        paths,
+    } = args;
+    let options = LogOptions {
+        oneline,
+        max_count,
+        follow,
+    };
+    let msg = log(revision.as_deref(), &paths, &options, &ctx.root_path)?;
+    write!(ctx.stdout, "{}", msg)?;
+
+    Ok(())
+}
+
+/// How `log` should pick out and show commits.
+pub struct LogOptions {
+    pub oneline: bool,
+    pub max_count: Option<usize>,
+    pub follow: bool,
+}
+
+/// List the commits reachable from a revision, newest first. Given paths, only the commits
+/// that changed them are listed, and with `follow`, a single path's history carries on
+/// under its old name past a commit that renamed it.
+pub fn log(
+    revision: Option<&str>,
+    paths: &[PathBuf],
+    options: &LogOptions,
+    root_path: &Path,
+) -> anyhow::Result<String> {
+    if options.follow && paths.len() != 1 {
+        return Err(anyhow!("--follow requires exactly one pathspec"));
+    }
+
+    let repo = Repository::new(git_dir(root_path));
+    let database = repo.database();
+    let start = Revision::resolve(revision.unwrap_or(HEAD), database, repo.refs())?;
+
+    let mut filters: Vec<_> = paths
+        .iter()
+        .map(|path| PathFilter::new(path, options.follow))
+        .collect();
+    let mut msg = String::new();
+    let mut shown = 0;
+    for commit in RevWalk::new(database, &[start])? {
+        if Some(shown) == options.max_count {
+            break;
+        }
+
+        let (oid, commit) = commit?;
+        let mut changed = filters.is_empty();
+        for filter in &mut filters {
+            changed |= filter.changed(database, &oid, &commit)?;
+        }
+        if !changed {
+            continue;
+        }
+
+        if options.oneline {
+            msg.push_str(&format!("{} {}\n", oid.short(7), commit.title_line()));
+        } else {
+            if shown > 0 {
+                msg.push('\n');
+            }
+            msg.push_str(&format_commit(&oid, &commit));
+        }
+        shown += 1;
+    }
+
+    Ok(msg)
+}
+
+/// A commit as `log` shows it in full: its id, parents if it's a merge, author, date and
+/// indented message.
+fn format_commit(oid: &ObjectId, commit: &Commit) -> String {
+    let mut msg = format!("commit {}\n", oid);
+    if commit.parents().len() > 1 {
+        let parents: Vec<_> = commit.parents().iter().map(|oid| oid.short(7)).collect();
+        msg.push_str(&format!("Merge: {}\n", parents.join(" ")));
+    }
+
+    let author = commit.author();
+    msg.push_str(&format!("Author: {} <{}>\n", author.name(), author.email()));
+    msg.push_str(&format!(
+        "Date:   {}\n\n",
+        author.time().format("%a %b %-d %H:%M:%S %Y %z")
+    ));
+    for line in commit.message().trim_end().lines() {
+        msg.push_str(&format!("    {}\n", line));
+    }
+
+    msg
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::test::{
+        annotate_tag, author_options, init, write_and_commit, LOG_ONELINE,
+    };
+    use crate::commands::{add::*, commit::*};
+
+    #[test]
+    fn logs_a_file_across_renames() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        let text = "one\ntwo\nthree\nfour\nfive\n";
+        write_and_commit(
+            tmp_path,
+            &[("old.txt", text), ("other.txt", "x")],
+            "Add old",
+        );
+        write_and_commit(tmp_path, &[("other.txt", "y")], "Change other");
+        std::fs::remove_file(tmp_path.join("old.txt")).unwrap();
+        std::fs::create_dir(tmp_path.join("dir")).unwrap();
+        std::fs::write(tmp_path.join("dir/new.txt"), text.replace("five", "5")).unwrap();
+        add_updates(vec![], true, tmp_path).unwrap();
+        create_commit(
+            "Move old to new".to_owned(),
+            false,
+            &[],
+            &author_options(),
+            tmp_path,
+        )
+        .unwrap();
+        write_and_commit(tmp_path, &[("dir/new.txt", "changed\n")], "Rewrite new");
+
+        let titles = |paths: &[&str], follow| {
+            let options = LogOptions {
+                oneline: true,
+                max_count: None,
+                follow,
+            };
+            let paths: Vec<_> = paths.iter().map(PathBuf::from).collect();
+            log(None, &paths, &options, tmp_path)
+                .unwrap()
+                .lines()
+                .map(|line| line[8..].to_owned())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            titles(&[], false),
+            vec!["Rewrite new", "Move old to new", "Change other", "Add old"]
+        );
+        assert_eq!(
+            titles(&["dir/new.txt"], false),
+            vec!["Rewrite new", "Move old to new"]
+        );
+        assert_eq!(
+            titles(&["dir/new.txt"], true),
+            vec!["Rewrite new", "Move old to new", "Add old"]
+        );
+        assert_eq!(
+            titles(&["old.txt"], false),
+            vec!["Move old to new", "Add old"]
+        );
+        assert_eq!(
+            titles(&["other.txt"], true),
+            vec!["Change other", "Add old"]
+        );
+
+        let options = LogOptions {
+            oneline: false,
+            max_count: Some(1),
+            follow: false,
+        };
+        let msg = log(None, &[], &options, tmp_path).unwrap();
+        assert!(msg.starts_with("commit "));
+        assert!(msg.contains("\nAuthor: A. U. Thor <author@example.com>\nDate:   "));
+        assert!(msg.ends_with("\n\n    Rewrite new\n"));
+
+        let options = LogOptions {
+            oneline: true,
+            max_count: None,
+            follow: true,
+        };
+        assert!(log(None, &[], &options, tmp_path).is_err());
+    }
+
+    #[test]
+    fn logs_from_annotated_tags() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        write_and_commit(tmp_path, &[("a.txt", "a")], "First");
+        annotate_tag(tmp_path, "v1.0", "HEAD", "Version 1.0");
+        write_and_commit(tmp_path, &[("a.txt", "b")], "Second");
+
+        let titles: Vec<_> = log(Some("v1.0"), &[], &LOG_ONELINE, tmp_path)
+            .unwrap()
+            .lines()
+            .map(|line| line[8..].to_owned())
+            .collect();
+        assert_eq!(titles, ["First"]);
+        assert!(log(Some("v1.0~1"), &[], &LOG_ONELINE, tmp_path).is_err());
+    }
+}
@@ -0,0 +1,178 @@
+use nit::{
+    ref_format::{self},
+    refs::HEAD,
+    remote::Remote,
+    repository::{git_dir, Repository},
+};
+use std::io::Write;
+use std::path::Path;
+use structopt::StructOpt;
+
+use super::fetch::remote_name;
+use super::CommandContext;
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Only list branches
+    #[structopt(long = "heads")]
+    heads: bool,
+    /// Only list tags
+    #[structopt(long = "tags")]
+    tags: bool,
+    /// Don't list HEAD, or the objects annotated tags peel to
+    #[structopt(long = "refs")]
+    refs_only: bool,
+    /// The remote's name or URL (the current branch's remote, or origin, by default)
+    remote: Option<String>,
+    /// Only list refs whose names end with one of these, after a `/`. They can be globs
+    patterns: Vec<String>,
+}
+
+pub fn run(args: Args, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let Args {
+        heads,
+        tags,
+        refs_only,
+        remote,
+        patterns,
+    } = args;
+    let options = LsRemoteOptions {
+        heads,
+        tags,
+        refs_only,
+    };
+    let root_path = ctx.root_path.clone();
+    let msg = ls_remote(
+        remote.as_deref(),
+        &patterns,
+        &options,
+        ctx.info_stderr(),
+        &root_path,
+    )?;
+    write!(ctx.stdout, "{}", msg)?;
+
+    Ok(())
+}
+
+/// Which of a remote's refs `ls-remote` should list.
+pub struct LsRemoteOptions {
+    pub heads: bool,
+    pub tags: bool,
+    pub refs_only: bool,
+}
+
+/// List the refs a remote advertises, given by its name or URL. With patterns, only the
+/// refs whose names end in one of them are listed, where the pattern must match whole
+/// parts of the name. As in git, when the remote is the default one, its URL is reported.
+pub fn ls_remote(
+    remote: Option<&str>,
+    patterns: &[String],
+    options: &LsRemoteOptions,
+    stderr: &mut dyn Write,
+    root_path: &Path,
+) -> anyhow::Result<String> {
+    let repo = Repository::new(git_dir(root_path));
+    let remote_name = remote_name(remote, &repo)?;
+    let url = repo
+        .config()?
+        .get(&format!("remote.{}.url", remote_name))?
+        .unwrap_or(remote_name);
+    let remote_given = remote.is_some();
+    let remote = Remote::open(&url, root_path)?;
+    if !remote_given {
+        writeln!(stderr, "From {}", url)?;
+    }
+
+    let wanted = |name: &str| {
+        let kind = match (options.heads, options.tags) {
+            (false, false) => !options.refs_only || name != HEAD,
+            (heads, tags) => {
+                (heads && name.starts_with("refs/heads/"))
+                    || (tags && name.starts_with("refs/tags/"))
+            }
+        };
+        let peeled = name.ends_with("^{}");
+        let matched = patterns.is_empty()
+            || patterns
+                .iter()
+                .any(|pattern| ref_format::tail_matches(pattern, name));
+        kind && !(peeled && options.refs_only) && matched
+    };
+
+    Ok(remote
+        .advertised_refs()?
+        .into_iter()
+        .filter(|(name, _)| wanted(name))
+        .map(|(name, oid)| format!("{}\t{}\n", oid, name))
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::branch::*;
+    use crate::commands::test::{init, write_and_commit};
+
+    #[test]
+    fn lists_remote_refs_without_fetching() {
+        let (local, origin) = (init().unwrap(), init().unwrap());
+        let (local_path, origin_path) = (local.path(), origin.path());
+        std::fs::write(
+            local_path.join(".git/config"),
+            format!("[remote \"origin\"]\n\turl = {}\n", origin_path.display()),
+        )
+        .unwrap();
+
+        write_and_commit(origin_path, &[("a.txt", "a")], "First");
+        create_branch("topic", None, false, origin_path).unwrap();
+        let origin_repo = Repository::new(origin_path.join(".git"));
+        let head = origin_repo.refs().read_head().unwrap().unwrap();
+        let tag = format!(
+            "object {}\ntype commit\ntag v1\ntagger A <a@example.com> 0 +0000\n\nv1\n",
+            head
+        );
+        let tag = origin_repo
+            .database()
+            .store_raw("tag", tag.as_bytes())
+            .unwrap();
+        origin_repo.refs().create_tag("v1", &tag).unwrap();
+
+        let list = |patterns: &[&str], heads, tags, refs_only| {
+            let options = LsRemoteOptions {
+                heads,
+                tags,
+                refs_only,
+            };
+            let patterns: Vec<_> = patterns.iter().map(|p| p.to_string()).collect();
+            ls_remote(
+                Some("origin"),
+                &patterns,
+                &options,
+                &mut Vec::new(),
+                local_path,
+            )
+            .unwrap()
+        };
+
+        assert_eq!(
+            list(&[], false, false, false),
+            format!(
+                "{head}\tHEAD\n{head}\trefs/heads/master\n{head}\trefs/heads/topic\n\
+                 {tag}\trefs/tags/v1\n{head}\trefs/tags/v1^{{}}\n",
+                head = head,
+                tag = tag
+            )
+        );
+        assert_eq!(
+            list(&[], false, true, true),
+            format!("{}\trefs/tags/v1\n", tag)
+        );
+        assert_eq!(list(&["topic"], false, false, false).lines().count(), 1);
+        assert_eq!(list(&["master"], false, false, true).lines().count(), 1);
+        assert!(Repository::new(local_path.join(".git"))
+            .database()
+            .loose_objects()
+            .unwrap()
+            .is_empty());
+    }
+}
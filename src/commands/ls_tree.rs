@@ -0,0 +1,192 @@
+use anyhow::anyhow;
+use nit::{
+    database::{Database, DatabaseEntry, ObjectId, TreeEntry},
+    quote::{quote_non_ascii, quote_path},
+    repository::{git_dir, Repository},
+    revision::Revision,
+};
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+
+use super::CommandContext;
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Recurse into subtrees, listing only the files in them
+    #[structopt(short = "r")]
+    recursive: bool,
+    /// Show the size of each file
+    #[structopt(short = "l", long = "long")]
+    long: bool,
+    /// The tree, or commit whose tree, to list
+    tree_ish: String,
+    /// Only list these paths. A path ending in `/` lists the directory's contents
+    paths: Vec<String>,
+}
+
+pub fn run(args: Args, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let Args {
+        recursive,
+        long,
+        tree_ish,
+        paths,
+    } = args;
+    let msg = ls_tree(&tree_ish, recursive, long, &paths, &ctx.root_path)?;
+    write!(ctx.stdout, "{}", msg)?;
+
+    Ok(())
+}
+
+/// List the entries of a tree as `mode type oid<TAB>path`, with `long` adding each blob's size.
+pub fn ls_tree(
+    tree_ish: &str,
+    recursive: bool,
+    long: bool,
+    paths: &[String],
+    root_path: &Path,
+) -> anyhow::Result<String> {
+    let repo = Repository::new(git_dir(root_path));
+    let database = repo.database();
+    let oid = Revision::resolve_tree_ish(tree_ish, database, repo.refs())?;
+    let quote = quote_non_ascii(&repo.config()?)?;
+
+    let mut entries = Vec::new();
+    list_tree(
+        database,
+        &oid,
+        Path::new(""),
+        recursive,
+        paths,
+        &mut entries,
+    )?;
+
+    let mut msg = String::new();
+    for (path, entry) in entries {
+        let kind = match entry.mode() {
+            0o40000 => "tree",
+            0o160000 => "commit",
+            _ => "blob",
+        };
+        msg.push_str(&format!("{:06o} {} {}", entry.mode(), kind, entry.oid()));
+        if long {
+            let size = match kind {
+                "blob" => database
+                    .load_blob(entry.oid())?
+                    .to_bytestr()
+                    .len()
+                    .to_string(),
+                _ => "-".to_owned(),
+            };
+            msg.push_str(&format!(" {:>7}", size));
+        }
+        msg.push_str(&format!("\t{}\n", quote_path(&path, quote)));
+    }
+
+    Ok(msg)
+}
+
+/// Collect the entries of a tree (or a commit's tree) under `prefix` that `ls_tree` should show,
+/// descending into subtrees when recursing or when they contain one of the given paths.
+fn list_tree(
+    database: &Database,
+    oid: &ObjectId,
+    prefix: &Path,
+    recursive: bool,
+    paths: &[String],
+    entries: &mut Vec<(PathBuf, DatabaseEntry)>,
+) -> anyhow::Result<()> {
+    let tree = match database.load(oid)? {
+        nit::database::ParsedObject::Commit(commit) => database.load_tree(commit.tree())?,
+        nit::database::ParsedObject::Tree(tree) => tree,
+        object => return Err(anyhow!("not a tree object: {} is a {}", oid, object.kind())),
+    };
+
+    for (name, entry) in tree.entries() {
+        let entry = match entry {
+            TreeEntry::Stored(entry) => entry,
+            _ => continue,
+        };
+        let path = prefix.join(name);
+
+        // Whether to list the entry itself, or what's in it.
+        let (show, descend) = if paths.is_empty() {
+            (true, recursive)
+        } else {
+            paths.iter().fold((false, false), |(show, descend), spec| {
+                let wanted = Path::new(spec.trim_end_matches('/'));
+                if path == wanted {
+                    (true, descend || recursive || spec.ends_with('/'))
+                } else if path.starts_with(wanted) {
+                    (true, descend || recursive)
+                } else if wanted.starts_with(&path) {
+                    (show, true)
+                } else {
+                    (show, descend)
+                }
+            })
+        };
+
+        if entry.is_tree() && descend {
+            list_tree(database, entry.oid(), &path, recursive, paths, entries)?;
+        } else if show {
+            entries.push((path, entry.clone()));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::test::{init, write_and_commit};
+
+    #[test]
+    fn lists_tree_entries() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        write_and_commit(
+            tmp_path,
+            &[
+                ("hello.txt", "hello\n"),
+                ("src/main.rs", "fn main() {}\n"),
+                ("src/lib/mod.rs", ""),
+            ],
+            "First",
+        );
+
+        let hello = "ce013625030ba8dba906f756967f9e9ca394464a";
+        let main = "f328e4d9d04c31d0d70d16d21a07d1613be9d577";
+        let empty = "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391";
+
+        let ls = |recursive, long, paths: &[&str]| {
+            let paths: Vec<_> = paths.iter().map(|path| path.to_string()).collect();
+            ls_tree("HEAD", recursive, long, &paths, tmp_path).unwrap()
+        };
+
+        let listing = ls(false, false, &[]);
+        let lines: Vec<_> = listing.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], format!("100644 blob {}\thello.txt", hello));
+        assert!(lines[1].starts_with("040000 tree ") && lines[1].ends_with("\tsrc"));
+
+        assert_eq!(
+            ls(true, true, &[]),
+            format!(
+                "100644 blob {}       6\thello.txt\n\
+                 100644 blob {}       0\tsrc/lib/mod.rs\n\
+                 100644 blob {}      13\tsrc/main.rs\n",
+                hello, empty, main
+            )
+        );
+
+        assert_eq!(
+            ls(false, false, &["src/main.rs"]),
+            format!("100644 blob {}\tsrc/main.rs\n", main)
+        );
+        let listing = ls(false, false, &["src/"]);
+        assert!(listing.lines().next().unwrap().ends_with("\tsrc/lib"));
+        assert!(listing.ends_with("\tsrc/main.rs\n"));
+    }
+}
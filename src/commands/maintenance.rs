@@ -0,0 +1,410 @@
+use anyhow::anyhow;
+use nit::{
+    commit_graph::CommitGraph,
+    lockfile::{Lockfile, LockfileError},
+    pack::{ObjectType, Pack, PackEntry},
+    progress::{Progress, StderrProgress},
+    remote::Remote,
+    repository::{git_dir, Repository},
+};
+use std::collections::HashSet;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use structopt::StructOpt;
+
+use super::fetch::fetch_refspecs;
+use super::gc::{config_number, gc, reachable_history};
+use super::repack::{pack_options, write_pack_files};
+use super::CommandContext;
+
+#[derive(Debug, StructOpt)]
+pub enum MaintenanceCommand {
+    /// Run the tasks given with --task, in that order, or else those turned on with
+    /// `maintenance.<task>.enabled`. With neither, only gc is run
+    Run {
+        /// A task to run: commit-graph, loose-objects, incremental-repack, prefetch or gc
+        #[structopt(long = "task", number_of_values = 1)]
+        tasks: Vec<MaintenanceTask>,
+        /// Don't report progress
+        #[structopt(short = "q", long = "quiet")]
+        quiet: bool,
+    },
+}
+
+pub fn run(command: MaintenanceCommand, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let MaintenanceCommand::Run { quiet, tasks } = command;
+    let mut progress = StderrProgress::new(quiet || ctx.is_quiet());
+    let msg = maintenance_run(&tasks, &mut progress, &ctx.root_path)?;
+    write!(ctx.info_stderr(), "{}", msg)?;
+
+    Ok(())
+}
+
+/// The tasks `maintenance run` can do, in the order they're run when picked by config.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MaintenanceTask {
+    Prefetch,
+    LooseObjects,
+    IncrementalRepack,
+    Gc,
+    CommitGraph,
+}
+
+impl MaintenanceTask {
+    const ALL: [MaintenanceTask; 5] = [
+        MaintenanceTask::Prefetch,
+        MaintenanceTask::LooseObjects,
+        MaintenanceTask::IncrementalRepack,
+        MaintenanceTask::Gc,
+        MaintenanceTask::CommitGraph,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            MaintenanceTask::Prefetch => "prefetch",
+            MaintenanceTask::LooseObjects => "loose-objects",
+            MaintenanceTask::IncrementalRepack => "incremental-repack",
+            MaintenanceTask::Gc => "gc",
+            MaintenanceTask::CommitGraph => "commit-graph",
+        }
+    }
+}
+
+impl std::str::FromStr for MaintenanceTask {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        MaintenanceTask::ALL
+            .iter()
+            .find(|task| task.name() == s)
+            .copied()
+            .ok_or_else(|| anyhow!("'{}' is not a valid task", s))
+    }
+}
+
+/// How many loose objects the loose-objects task packs at once, unless
+/// `maintenance.loose-objects.batchSize` says otherwise.
+const LOOSE_OBJECTS_BATCH_SIZE: usize = 50_000;
+
+/// Run the given maintenance tasks, or if none are given, those enabled in the config.
+/// Only one maintenance run works on a repository at a time: if another holds
+/// `objects/maintenance.lock`, nothing is done.
+pub fn maintenance_run(
+    tasks: &[MaintenanceTask],
+    progress: &mut dyn Progress,
+    root_path: &Path,
+) -> anyhow::Result<String> {
+    let repo = Repository::new(git_dir(root_path));
+    let config = repo.config()?;
+
+    let tasks = match tasks {
+        [] => {
+            let mut enabled = Vec::new();
+            for task in MaintenanceTask::ALL.iter().copied() {
+                let key = format!("maintenance.{}.enabled", task.name());
+                if config
+                    .get_bool(&key)?
+                    .unwrap_or(task == MaintenanceTask::Gc)
+                {
+                    enabled.push(task);
+                }
+            }
+            enabled
+        }
+        tasks => {
+            for (i, task) in tasks.iter().enumerate() {
+                if tasks[..i].contains(task) {
+                    return Err(anyhow!(
+                        "task '{}' cannot be selected multiple times",
+                        task.name()
+                    ));
+                }
+            }
+            tasks.to_vec()
+        }
+    };
+
+    let mut lock = Lockfile::new(&repo.database().pathname().join("maintenance"));
+    match lock.hold_for_update() {
+        Err(nit::Error::Lockfile(LockfileError::LockDenied(path))) => {
+            return Ok(format!(
+                "lock file '{}' exists, skipping maintenance\n",
+                path.display()
+            ));
+        }
+        result => result?,
+    }
+
+    let mut msg = String::new();
+    let mut result = Ok(());
+    for task in tasks {
+        let output = match task {
+            MaintenanceTask::Prefetch => prefetch(&repo, progress, root_path),
+            MaintenanceTask::LooseObjects => {
+                let batch_size = config_number(
+                    &config,
+                    "maintenance.loose-objects.batchSize",
+                    LOOSE_OBJECTS_BATCH_SIZE,
+                );
+                batch_size.and_then(|size| pack_loose_objects(&repo, size))
+            }
+            MaintenanceTask::IncrementalRepack => consolidate_packs(&repo),
+            MaintenanceTask::Gc => gc(false, root_path),
+            MaintenanceTask::CommitGraph => write_commit_graph(&repo),
+        };
+        match output {
+            Ok(output) => msg.push_str(&output),
+            Err(e) => {
+                result = Err(e.context(format!("task '{}' failed", task.name())));
+                break;
+            }
+        }
+    }
+    lock.rollback()?;
+
+    result.map(|_| msg)
+}
+
+/// Fetch every remote's refs into `refs/prefetch/`, along with the objects they need, so
+/// that later fetches have less to download. The remote-tracking refs are left alone.
+fn prefetch(
+    repo: &Repository,
+    progress: &mut dyn Progress,
+    root_path: &Path,
+) -> anyhow::Result<String> {
+    let config = repo.config()?;
+
+    for remote_name in config.subsections("remote") {
+        let url = match config.get(&format!("remote.{}.url", remote_name))? {
+            Some(url) => url,
+            None => continue,
+        };
+        let remote = Remote::open(&url, root_path)?;
+        let specs = fetch_refspecs(&config, &remote_name)?;
+
+        let mut updates = Vec::new();
+        for (name, oid) in remote.list_refs()? {
+            let local_name = specs.iter().find_map(|spec| spec.map(&name));
+            let local_name = local_name
+                .as_deref()
+                .and_then(|name| name.strip_prefix("refs/"));
+            if let Some(local_name) = local_name {
+                updates.push((format!("refs/prefetch/{}", local_name), oid));
+            }
+        }
+
+        let tips: Vec<_> = updates.iter().map(|(_, oid)| *oid).collect();
+        remote.fetch_objects(repo, &tips, progress)?;
+
+        let mut transaction = repo.refs().transaction();
+        for (name, new) in &updates {
+            let old = repo.refs().read_ref(name)?;
+            if old.as_ref() != Some(new) {
+                transaction.update(name, old.as_ref(), Some(new), "maintenance: prefetch");
+            }
+        }
+        transaction.commit()?;
+    }
+
+    Ok(String::new())
+}
+
+/// Pack up to `batch_size` loose objects (or all of them, if it's 0), after deleting the
+/// loose copies of objects that are already packed. Objects packed by one run are only
+/// deleted by the next, in case something was still reading them.
+fn pack_loose_objects(repo: &Repository, batch_size: usize) -> anyhow::Result<String> {
+    let database = repo.database();
+    database.prune_packed()?;
+
+    let mut oids = database.loose_objects()?;
+    if batch_size > 0 {
+        oids.truncate(batch_size);
+    }
+    if oids.is_empty() {
+        return Ok(String::new());
+    }
+
+    let mut entries = Vec::with_capacity(oids.len());
+    for oid in oids {
+        let (kind, data) = database.read_object(&oid)?;
+        let kind = ObjectType::from_name(&kind)
+            .ok_or_else(|| anyhow!("object {} has unknown type '{}'", oid, kind))?;
+        entries.push(PackEntry {
+            oid,
+            kind,
+            data,
+            name: String::new(),
+        });
+    }
+
+    write_pack_files(
+        database,
+        &entries,
+        &pack_options(&repo.config()?, None, None)?,
+    )?;
+    Ok(String::new())
+}
+
+/// Combine every pack but the largest into a single pack, so there are fewer packs to look
+/// through without rewriting most of the repository's objects as gc does.
+fn consolidate_packs(repo: &Repository) -> anyhow::Result<String> {
+    let database = repo.database();
+
+    let mut packs = Vec::new();
+    for (index_path, _) in database.packs()? {
+        let size = fs::metadata(index_path.with_extension("pack"))?.len();
+        packs.push((size, index_path));
+    }
+    packs.sort();
+    packs.pop();
+    if packs.len() < 2 {
+        return Ok(String::new());
+    }
+
+    let mut seen = HashSet::new();
+    let mut entries = Vec::new();
+    for (_, index_path) in &packs {
+        let pack = Pack::parse(&fs::read(index_path.with_extension("pack"))?)?;
+        for object in pack.objects() {
+            if seen.insert(object.oid) {
+                entries.push(PackEntry {
+                    oid: object.oid,
+                    kind: object.kind,
+                    data: object.data.clone(),
+                    name: String::new(),
+                });
+            }
+        }
+    }
+
+    let options = pack_options(&repo.config()?, None, None)?;
+    let (path, _) = write_pack_files(database, &entries, &options)?;
+    for (_, index_path) in packs.iter().filter(|(_, old)| *old != path) {
+        fs::remove_file(index_path)?;
+        fs::remove_file(index_path.with_extension("pack"))?;
+    }
+
+    Ok(String::new())
+}
+
+/// Write `objects/info/commit-graph` for every commit reachable from HEAD or any ref,
+/// including through annotated tags, walked just as gc walks them. As
+/// in git, no graph is written while there are replacements, since it would record the
+/// history as it is with them, which isn't what every reader sees.
+fn write_commit_graph(repo: &Repository) -> anyhow::Result<String> {
+    let database = repo.database();
+    let refs = repo.refs();
+    if !refs.list(Some("refs/replace/"))?.is_empty() {
+        return Ok(String::new());
+    }
+
+    let tips = std::iter::once(refs.read_head()?)
+        .chain(refs.list(None)?.into_iter().map(|r| r.oid))
+        .flatten()
+        .collect();
+    let (history, _) = reachable_history(database, tips)?;
+    let commits: Vec<_> = history
+        .into_iter()
+        .filter(|entry| entry.kind == ObjectType::Commit)
+        .map(|entry| entry.oid)
+        .collect();
+
+    let graph = CommitGraph::build(database, &commits)?;
+    let info_dir = database.pathname().join("info");
+    fs::create_dir_all(&info_dir)?;
+    let mut lock = Lockfile::new(&info_dir.join("commit-graph"));
+    lock.hold_for_update()?;
+    lock.write_all(&graph.to_bytes())?;
+    lock.commit()?;
+
+    Ok(String::new())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::test::{annotate_tag, init, write_and_commit};
+    use nit::database::ObjectId;
+
+    #[test]
+    fn runs_maintenance_tasks() {
+        let (local, origin) = (init().unwrap(), init().unwrap());
+        let (local_path, origin_path) = (local.path(), origin.path());
+        std::fs::write(
+            local_path.join(".git/config"),
+            format!("[remote \"origin\"]\n\turl = {}\n", origin_path.display()),
+        )
+        .unwrap();
+        write_and_commit(origin_path, &[("a.txt", "a")], "First");
+
+        let repo = Repository::new(local_path.join(".git"));
+        let database = repo.database();
+        let run_tasks =
+            |tasks: &[MaintenanceTask]| maintenance_run(tasks, &mut Vec::new(), local_path);
+
+        // Loose objects are packed by one run and deleted by the next.
+        for n in 1..=3 {
+            write_and_commit(local_path, &[("a.txt", &n.to_string())], "Change");
+            run_tasks(&[MaintenanceTask::LooseObjects]).unwrap();
+            assert!(!database.loose_objects().unwrap().is_empty());
+            run_tasks(&[MaintenanceTask::LooseObjects]).unwrap();
+            assert!(database.loose_objects().unwrap().is_empty());
+            assert_eq!(database.packs().unwrap().len(), n);
+        }
+
+        // All but the largest pack are combined.
+        run_tasks(&[MaintenanceTask::IncrementalRepack]).unwrap();
+        assert_eq!(database.packs().unwrap().len(), 2);
+        let head = repo.refs().read_head().unwrap().unwrap();
+        assert_eq!(database.load_tree_list(Some(&head)).unwrap().len(), 1);
+
+        run_tasks(&[MaintenanceTask::Prefetch, MaintenanceTask::CommitGraph]).unwrap();
+        let prefetched = repo.refs().read_ref("refs/prefetch/remotes/origin/master");
+        assert!(prefetched.unwrap().is_some());
+        assert_eq!(repo.refs().read_ref("origin/master").unwrap(), None);
+        let graph = std::fs::read(local_path.join(".git/objects/info/commit-graph")).unwrap();
+        assert_eq!(&graph[..4], b"CGPH");
+
+        assert!(run_tasks(&[MaintenanceTask::Gc, MaintenanceTask::Gc]).is_err());
+        let lock = local_path.join(".git/objects/maintenance.lock");
+        std::fs::write(&lock, "").unwrap();
+        assert!(run_tasks(&[]).unwrap().starts_with("lock file"));
+        std::fs::remove_file(&lock).unwrap();
+
+        // With no tasks given or configured, only gc runs.
+        run_tasks(&[]).unwrap();
+        assert_eq!(database.packs().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn graphs_and_keeps_commits_reachable_only_through_tags() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        write_and_commit(tmp_path, &[("a.txt", "a")], "First");
+        let repo = Repository::new(tmp_path.join(".git"));
+        let first = repo.refs().read_head().unwrap().unwrap();
+        write_and_commit(tmp_path, &[("a.txt", "b")], "Tagged");
+        let tagged = repo.refs().read_head().unwrap().unwrap();
+        let tag = annotate_tag(tmp_path, "v1.0", "HEAD", "Version 1.0");
+        repo.refs()
+            .update_ref("refs/heads/master", &first, "reset: moving to HEAD^")
+            .unwrap();
+        std::fs::write(
+            tmp_path.join(".git/config"),
+            "[gc]\n\tpruneExpire = now\n\treflogExpire = now\n",
+        )
+        .unwrap();
+
+        let tasks = [MaintenanceTask::CommitGraph, MaintenanceTask::Gc];
+        maintenance_run(&tasks, &mut Vec::new(), tmp_path).unwrap();
+
+        let graph = std::fs::read(tmp_path.join(".git/objects/info/commit-graph")).unwrap();
+        assert!(graph
+            .windows(ObjectId::LEN)
+            .any(|oid| oid == tagged.bytes()));
+        assert!(repo.database().contains(&tagged).unwrap());
+        assert!(repo.database().contains(&tag).unwrap());
+    }
+}
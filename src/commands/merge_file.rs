@@ -0,0 +1,111 @@
+use anyhow::anyhow;
+use anyhow::Context;
+use nit::merge::diff3::{self, ConflictStyle, Favor, Labels};
+use std::fs;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+use super::{CommandContext, ExitStatus};
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Settle conflicts by taking our side
+    #[structopt(long = "ours", conflicts_with_all = &["theirs", "union"])]
+    ours: bool,
+    /// Settle conflicts by taking their side
+    #[structopt(long = "theirs", conflicts_with = "union")]
+    theirs: bool,
+    /// Settle conflicts by taking both sides
+    #[structopt(long = "union")]
+    union: bool,
+    /// Show the base's lines in conflicts, too
+    #[structopt(long = "diff3")]
+    diff3: bool,
+    /// Use conflict markers this many characters long
+    #[structopt(long = "marker-size", default_value = "7")]
+    marker_size: usize,
+    /// Use these names on conflict markers, for <current>, <base> and <other> in turn
+    #[structopt(short = "L", number_of_values = 1, max_values = 3)]
+    labels: Vec<String>,
+    /// Print the result instead of overwriting <current>
+    #[structopt(short = "p", long = "stdout")]
+    stdout: bool,
+    /// Our version of the file, which the result is written to
+    current: PathBuf,
+    /// The version both sides started from
+    base: PathBuf,
+    /// Their version of the file
+    other: PathBuf,
+}
+
+pub fn run(args: Args, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let Args {
+        ours,
+        theirs,
+        union,
+        diff3,
+        marker_size,
+        labels,
+        stdout,
+        current,
+        base,
+        other,
+    } = args;
+    let favor = match (ours, theirs, union) {
+        (true, _, _) => Some(Favor::Ours),
+        (_, true, _) => Some(Favor::Theirs),
+        (_, _, true) => Some(Favor::Union),
+        _ => None,
+    };
+    let style = ConflictStyle {
+        favor,
+        marker_size,
+        diff3,
+    };
+    let paths = [&current, &base, &other];
+    let (result, conflicts) = merge_file(paths, &labels, &style)?;
+    if stdout {
+        ctx.stdout.write_all(&result)?;
+    } else {
+        fs::write(&current, result)?;
+    }
+    if conflicts > 0 {
+        return Err(ExitStatus::Code(conflicts.min(127) as i32).into());
+    }
+
+    Ok(())
+}
+
+/// Merge the changes from a base file to two others, as `merge-file <current> <base>
+/// <other>`. Conflicts are labelled with the given names, or the files' paths. Returns the
+/// merged file, and how many conflicts it had.
+fn merge_file(
+    paths: [&PathBuf; 3],
+    labels: &[String],
+    style: &ConflictStyle,
+) -> anyhow::Result<(Vec<u8>, usize)> {
+    let mut contents = Vec::with_capacity(3);
+    for path in paths {
+        let data = fs::read(path)
+            .with_context(|| format!("could not open '{}' for reading", path.display()))?;
+        if data.contains(&0) {
+            return Err(anyhow!("Cannot merge binary files: {}", path.display()));
+        }
+        contents.push(data);
+    }
+
+    let names: Vec<_> = paths.iter().map(|path| path.to_string_lossy()).collect();
+    let label = |i: usize| labels.get(i).map_or(&*names[i], String::as_str);
+    let labels = Labels {
+        ours: label(0),
+        base: label(1),
+        theirs: label(2),
+    };
+
+    let merge = diff3::merge(&contents[1], &contents[0], &contents[2], style.diff3);
+    let conflicts = match style.favor {
+        Some(_) => 0,
+        None => merge.conflicts(),
+    };
+    Ok((merge.to_bytes(labels, style), conflicts))
+}
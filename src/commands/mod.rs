@@ -0,0 +1,545 @@
+//! The subcommands of the `nit` command line, one module each. Every command is run with a
+//! [`CommandContext`], which says where its input comes from and where its output goes,
+//! so that commands can be run, and what they say captured, without starting a process.
+
+use anyhow::{anyhow, Context};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::iter::FromIterator;
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+use thiserror::Error;
+
+use nit::config::Config;
+use nit::lockfile::LockfileError;
+use nit::repository::{set_git_env, Repository, RepositoryError};
+use nit::workspace::Workspace;
+
+pub mod add;
+pub mod blame;
+pub mod branch;
+pub mod cat_file;
+pub mod check_ignore;
+pub mod check_ref_format;
+pub mod checkout;
+pub mod cherry;
+pub mod commit;
+pub mod commit_tree;
+pub mod daemon;
+pub mod diff;
+pub mod diff_files;
+pub mod diff_index;
+pub mod diff_tree;
+pub mod fetch;
+pub mod for_each_ref;
+pub mod gc;
+pub mod index_pack;
+pub mod init;
+pub mod interpret_trailers;
+pub mod log;
+pub mod ls_remote;
+pub mod ls_tree;
+pub mod maintenance;
+pub mod merge_file;
+pub mod name_rev;
+pub mod prompt;
+pub mod push;
+pub mod range_diff;
+pub mod read_tree;
+pub mod rebase;
+pub mod repack;
+pub mod replace;
+pub mod restore;
+pub mod rev_list;
+pub mod shortlog;
+pub mod show_branch;
+pub mod stash;
+pub mod status;
+pub mod switch;
+pub mod symbolic_ref;
+pub mod tag;
+pub mod update_index;
+pub mod var;
+pub mod verify_pack;
+pub mod write_tree;
+
+use gc::auto_gc;
+
+/// What a command runs with: the repository, the environment, and where its input comes
+/// from and its output goes.
+pub struct CommandContext<'a> {
+    /// The top of the working tree, which is the current directory for `init`.
+    pub root_path: PathBuf,
+    pub repo: Repository,
+    pub env: Env,
+    pub stdin: &'a mut dyn BufRead,
+    pub stdout: &'a mut dyn Write,
+    pub stderr: &'a mut dyn Write,
+    pub verbosity: Verbosity,
+    /// Where informational output goes when asked to be quiet.
+    sink: io::Sink,
+}
+
+impl<'a> CommandContext<'a> {
+    /// A context for the repository whose working tree is at `root_path`, with the
+    /// process's environment.
+    pub fn new(
+        root_path: impl Into<PathBuf>,
+        stdin: &'a mut dyn BufRead,
+        stdout: &'a mut dyn Write,
+        stderr: &'a mut dyn Write,
+    ) -> Self {
+        let root_path = root_path.into();
+        Self {
+            repo: Repository::open(&root_path),
+            root_path,
+            env: Env::current(),
+            stdin,
+            stdout,
+            stderr,
+            verbosity: Verbosity::Normal,
+            sink: io::sink(),
+        }
+    }
+
+    /// Where to write informational output that goes to stdout, such as the summary of a
+    /// new commit: nowhere, if asked to be quiet.
+    pub fn info(&mut self) -> &mut dyn Write {
+        if self.is_quiet() {
+            &mut self.sink
+        } else {
+            self.stdout
+        }
+    }
+
+    /// Where to write informational output that goes to stderr, as git's reports of
+    /// switching branches and talking to remotes do: nowhere, if asked to be quiet.
+    pub fn info_stderr(&mut self) -> &mut dyn Write {
+        if self.is_quiet() {
+            &mut self.sink
+        } else {
+            self.stderr
+        }
+    }
+
+    pub fn is_quiet(&self) -> bool {
+        self.verbosity == Verbosity::Quiet
+    }
+
+    pub fn is_verbose(&self) -> bool {
+        self.verbosity == Verbosity::Verbose
+    }
+}
+
+/// How much commands say about what they've done, as set by `--quiet` and `--verbose`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose,
+}
+
+/// The environment variables a command sees.
+#[derive(Debug, Clone, Default)]
+pub struct Env {
+    vars: HashMap<String, String>,
+}
+
+impl Env {
+    /// The process's environment. Variables that aren't valid unicode are left out.
+    pub fn current() -> Self {
+        let vars = env::vars_os()
+            .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+            .collect();
+        Self { vars }
+    }
+
+    pub fn var(&self, name: &str) -> Option<&str> {
+        self.vars.get(name).map(String::as_str)
+    }
+}
+
+/// An environment with just the given variables, e.g. to give a command its editor or author.
+impl<K: Into<String>, V: Into<String>> FromIterator<(K, V)> for Env {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let vars = iter
+            .into_iter()
+            .map(|(name, value)| (name.into(), value.into()))
+            .collect();
+        Self { vars }
+    }
+}
+
+/// How a command exits when it doesn't simply succeed, with the statuses git uses, so that
+/// scripts can tell what happened. A command returns one of these as its error once it has
+/// said all it has to; any other error is reported, then given a status by [`ExitStatus::of`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum ExitStatus {
+    /// The answer was no: there were differences, nothing was ignored, or there was nothing
+    /// to commit.
+    #[error("exit status 1")]
+    Failure,
+    /// A status with a meaning of its own, such as how many conflicts `merge-file` left.
+    #[error("exit status {0}")]
+    Code(i32),
+    /// The command couldn't do its job at all, because there's no repository or another
+    /// process holds a lock it needs.
+    #[error("exit status 128")]
+    Fatal,
+}
+
+impl ExitStatus {
+    pub fn code(self) -> i32 {
+        match self {
+            Self::Failure => 1,
+            Self::Code(code) => code,
+            Self::Fatal => 128,
+        }
+    }
+
+    /// The status to exit with after a command fails.
+    pub fn of(error: &anyhow::Error) -> Self {
+        if let Some(status) = error.downcast_ref::<Self>() {
+            return *status;
+        }
+
+        let fatal = error.chain().any(|cause| {
+            matches!(cause.downcast_ref(), Some(LockfileError::LockDenied(_)))
+                || matches!(
+                    cause.downcast_ref(),
+                    Some(RepositoryError::NotARepository(_))
+                )
+        });
+        if fatal {
+            Self::Fatal
+        } else {
+            Self::Failure
+        }
+    }
+}
+
+/// The workspace at `root_path`, set up according to the repository's config.
+pub fn open_workspace(root_path: &Path) -> anyhow::Result<Workspace> {
+    Ok(Repository::open(root_path).workspace()?)
+}
+
+/// Turn paths given on the command line into paths relative to the root of the workspace.
+/// The paths don't have to exist, so that deleted files can still be named.
+pub fn workspace_paths(paths: &[&Path], root_path: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let root = fs::canonicalize(root_path)?;
+
+    paths
+        .iter()
+        .map(|path| {
+            let path = env::current_dir()?.join(path);
+            let path = match (fs::canonicalize(&path), path.parent(), path.file_name()) {
+                (Ok(path), _, _) => path,
+                (Err(_), Some(parent), Some(name)) => fs::canonicalize(parent)
+                    .with_context(|| {
+                        format!("pathspec '{}' did not match any files", path.display())
+                    })?
+                    .join(name),
+                (Err(e), _, _) => return Err(e.into()),
+            };
+
+            Ok(path
+                .strip_prefix(&root)
+                .with_context(|| format!("'{}' is outside repository", path.display()))?
+                .to_owned())
+        })
+        .collect()
+}
+
+pub fn plural(count: usize) -> &'static str {
+    if count == 1 {
+        ""
+    } else {
+        "s"
+    }
+}
+
+/// The editor to use, as git chooses it: the first of the given environment variables that
+/// is set, then `core.editor`, then `VISUAL` and `EDITOR`, or else `vi`.
+pub fn editor(env: &Env, config: Option<&Config>, variables: &[&str]) -> anyhow::Result<String> {
+    if let Some(editor) = variables.iter().find_map(|name| env.var(name)) {
+        return Ok(editor.to_owned());
+    }
+    if let Some(editor) = config
+        .map(|config| config.get("core.editor"))
+        .transpose()?
+        .flatten()
+    {
+        return Ok(editor);
+    }
+
+    Ok(["VISUAL", "EDITOR"]
+        .iter()
+        .find_map(|name| env.var(name))
+        .unwrap_or("vi")
+        .to_owned())
+}
+
+/// Open a file in the editor chosen as [`editor`] does, and wait for it to exit. The editor
+/// is pointed at `repo`, in case it runs git or nit itself.
+pub fn run_editor(
+    env: &Env,
+    repo: &Repository,
+    variables: &[&str],
+    path: &Path,
+) -> anyhow::Result<()> {
+    let editor = editor(env, Some(&repo.config()?), variables)?;
+    let editor = editor.as_str();
+    let mut command = std::process::Command::new("sh");
+    set_git_env(&mut command, &repo.git_env());
+    let status = command
+        .arg("-c")
+        .arg(format!("{} \"$@\"", editor))
+        .arg(editor)
+        .arg(path)
+        .status()?;
+
+    if !status.success() {
+        return Err(anyhow!("There was a problem with the editor '{}'.", editor));
+    }
+
+    Ok(())
+}
+
+/// Tidy up after a command if the repository needs it. This is never worth failing the command
+/// over, so errors are only reported.
+pub fn run_auto_gc(ctx: &mut CommandContext) {
+    // Make sure the command's own output comes first.
+    let _ = ctx.stdout.flush();
+    let mut sink = io::sink();
+    let stderr: &mut dyn Write = if ctx.is_quiet() {
+        &mut sink
+    } else {
+        ctx.stderr
+    };
+    if let Err(e) = auto_gc(&ctx.repo, &ctx.root_path, stderr) {
+        let _ = writeln!(ctx.stderr, "warning: auto gc failed: {:#}", e);
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::commands::{add::*, commit::*, init::*, log::*};
+    use nit::{
+        database::{ObjectId, Tag},
+        hooks,
+        refs::Refs,
+        repository::CommitOptions,
+        revision::Revision,
+    };
+    use std::os::unix::fs::PermissionsExt;
+    use structopt::StructOpt;
+    use tempfile::TempDir;
+
+    /// A new repository in a directory of its own, which is deleted along with it.
+    pub(crate) fn init() -> anyhow::Result<TempDir> {
+        let dir = tempfile::tempdir()?;
+        init_repository(dir.path())?;
+        Ok(dir)
+    }
+
+    /// The environment commands run with in tests, which says who their author is.
+    pub(crate) fn author_env() -> Env {
+        vec![
+            ("GIT_AUTHOR_NAME", "A. U. Thor"),
+            ("GIT_AUTHOR_EMAIL", "author@example.com"),
+        ]
+        .into_iter()
+        .collect()
+    }
+
+    /// Options for a commit by the author in [`author_env`].
+    pub(crate) fn author_options() -> CommitOptions {
+        commit_options(None, None, &author_env(), None).unwrap()
+    }
+
+    /// A context for the repository at `root_path`, with [`author_env`] as its environment.
+    pub(crate) fn context<'a>(
+        root_path: impl Into<PathBuf>,
+        stdin: &'a mut dyn BufRead,
+        stdout: &'a mut dyn Write,
+        stderr: &'a mut dyn Write,
+    ) -> CommandContext<'a> {
+        let mut ctx = CommandContext::new(root_path, stdin, stdout, stderr);
+        ctx.env = author_env();
+        ctx
+    }
+
+    pub(crate) const LOG_ONELINE: LogOptions = LogOptions {
+        oneline: true,
+        max_count: None,
+        follow: false,
+    };
+
+    /// Write `files`, given as names and contents, and commit them with `message`.
+    pub(crate) fn write_and_commit(root: &Path, files: &[(&str, &str)], message: &str) {
+        let paths: Vec<_> = files.iter().map(|(name, _)| root.join(name)).collect();
+        for ((_, contents), path) in files.iter().zip(&paths) {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+
+        add_files_to_repository(paths.iter().map(PathBuf::as_path).collect(), root).unwrap();
+        create_commit(message.to_owned(), false, &[], &author_options(), root).unwrap();
+    }
+
+    /// Move every loose ref that isn't symbolic into `packed-refs`, as `git pack-refs --all`
+    /// does.
+    pub(crate) fn pack_refs(root: &Path) {
+        let git_path = root.join(".git");
+        let mut packed = String::from("# pack-refs with: peeled fully-peeled sorted\n");
+        for r in Refs::new(&git_path).list(Some("refs/")).unwrap() {
+            if r.symref.is_none() {
+                packed.push_str(&format!("{} {}\n", r.oid.unwrap(), r.name));
+                let _ = std::fs::remove_file(git_path.join(&r.name));
+            }
+        }
+        std::fs::write(git_path.join("packed-refs"), packed).unwrap();
+    }
+
+    /// Make an annotated tag of `rev` called `name`, by the author in [`author_env`], and
+    /// return the tag object's ObjectId.
+    pub(crate) fn annotate_tag(root: &Path, name: &str, rev: &str, message: &str) -> ObjectId {
+        let repo = Repository::new(root.join(".git"));
+        let database = repo.database();
+        let target = Revision::resolve_object(rev, database, repo.refs()).unwrap();
+        let kind = database.load(&target).unwrap().kind().to_owned();
+        let tagger = author_options().author.at(chrono::Local::now());
+        let tag = Tag::new(target, &kind, name, tagger, format!("{}\n", message));
+
+        let oid = database.store(&tag).unwrap();
+        repo.refs().create_tag(name, &oid).unwrap();
+        oid
+    }
+
+    #[test]
+    fn runs_commands_with_a_context() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+        std::fs::write(tmp_path.join("a.txt"), "a").unwrap();
+        std::fs::write(tmp_path.join(".gitignore"), "*.log\n").unwrap();
+
+        let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+        let mut stdin = std::io::empty();
+        let mut ctx = CommandContext::new(tmp_path, &mut stdin, &mut stdout, &mut stderr);
+        let args = add::Args::from_iter(&["add", tmp_path.join("a.txt").to_str().unwrap()]);
+        add::run(args, &mut ctx).unwrap();
+
+        ctx.env = vec![
+            ("GIT_AUTHOR_NAME", "Someone Else"),
+            ("GIT_AUTHOR_EMAIL", "else@example.com"),
+        ]
+        .into_iter()
+        .collect();
+        commit::run(
+            commit::Args::from_iter(&["commit", "-m", "First"]),
+            &mut ctx,
+        )
+        .unwrap();
+
+        let log = tmp_path.join("b.log");
+        let args = check_ignore::Args::from_iter(&["check-ignore", log.to_str().unwrap()]);
+        check_ignore::run(args, &mut ctx).unwrap();
+
+        // Nothing is ignored, so check-ignore fails without saying anything.
+        let path = tmp_path.join("b.txt");
+        let args = check_ignore::Args::from_iter(&["check-ignore", path.to_str().unwrap()]);
+        let err = check_ignore::run(args, &mut ctx).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(ExitStatus::Failure)));
+        drop(ctx);
+
+        let stdout = String::from_utf8(stdout).unwrap();
+        assert!(stdout.starts_with("[(root-commit) "), "{}", stdout);
+        assert!(stdout.ends_with(&format!("] First{}\n", log.display())));
+        assert!(stderr.is_empty());
+
+        let repo = Repository::new(tmp_path.join(".git"));
+        let head = repo.refs().read_head().unwrap().unwrap();
+        let commit = repo.database().load_commit(&head).unwrap();
+        assert_eq!(commit.author().name(), "Someone Else");
+    }
+
+    #[test]
+    fn respects_quiet_and_verbose() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+        std::fs::write(tmp_path.join("a.txt"), "a").unwrap();
+        std::fs::write(tmp_path.join("b.txt"), "b").unwrap();
+
+        let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+        let mut stdin = std::io::empty();
+        let mut ctx = context(tmp_path, &mut stdin, &mut stdout, &mut stderr);
+        ctx.verbosity = Verbosity::Verbose;
+        let args = add::Args::from_iter(&["add", tmp_path.to_str().unwrap()]);
+        add::run(args, &mut ctx).unwrap();
+
+        // Adding the same files again changes nothing, so there's nothing to report.
+        let args = add::Args::from_iter(&["add", tmp_path.to_str().unwrap()]);
+        add::run(args, &mut ctx).unwrap();
+
+        std::fs::remove_file(tmp_path.join("b.txt")).unwrap();
+        let args = add::Args::from_iter(&["add", "-u"]);
+        add::run(args, &mut ctx).unwrap();
+
+        ctx.verbosity = Verbosity::Quiet;
+        let args = commit::Args::from_iter(&["commit", "-m", "First"]);
+        commit::run(args, &mut ctx).unwrap();
+        let args = branch::Args::from_iter(&["branch", "topic"]);
+        branch::run(args, &mut ctx).unwrap();
+        drop(ctx);
+
+        let stdout = String::from_utf8(stdout).unwrap();
+        assert_eq!(stdout, "add 'a.txt'\nadd 'b.txt'\nremove 'b.txt'\n");
+        assert!(stderr.is_empty());
+    }
+
+    #[test]
+    fn points_hooks_and_editors_at_their_repository() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+        let git_path = tmp_path.join(".git");
+
+        let script = |path: &Path, body: &str| {
+            std::fs::write(path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        };
+        let show_env = "echo \"$GIT_DIR ${GIT_WORK_TREE-unset} ${GIT_INDEX_FILE-unset}\"";
+        std::fs::create_dir(git_path.join("hooks")).unwrap();
+        script(&git_path.join("hooks/pre-receive"), show_env);
+        let hook_env = |repo: &Repository| {
+            let hook = hooks::run_hook(repo, "pre-receive", &[], &[]).unwrap();
+            String::from_utf8(hook.unwrap().output).unwrap()
+        };
+
+        // A repository without a working tree, as a push is received into, clears it, and
+        // the index is only given if it isn't the usual one...
+        assert_eq!(
+            hook_env(&Repository::new(&git_path)),
+            format!("{} unset unset\n", git_path.display())
+        );
+        let index_path = tmp_path.join("other-index");
+        let repo = Repository::open(tmp_path).with_index_path(&index_path);
+        let expected = format!(
+            "{} {} {}\n",
+            git_path.display(),
+            tmp_path.display(),
+            index_path.display()
+        );
+        assert_eq!(hook_env(&repo), expected);
+
+        // ...and the editor is told the same.
+        let editor = git_path.join("editor.sh");
+        script(&editor, &format!("{} > \"$1\"", show_env));
+        let env: Env = vec![("GIT_EDITOR", editor.to_string_lossy().into_owned())]
+            .into_iter()
+            .collect();
+        let edited = tmp_path.join("EDITED");
+        run_editor(&env, &repo, &["GIT_EDITOR"], &edited).unwrap();
+        assert_eq!(std::fs::read_to_string(&edited).unwrap(), expected);
+    }
+}
@@ -0,0 +1,113 @@
+use anyhow::anyhow;
+use nit::{
+    name_rev::{NameRev, NameRevOptions},
+    repository::{git_dir, Repository},
+    revision::Revision,
+};
+use std::io::Write;
+use std::path::Path;
+use structopt::StructOpt;
+
+use super::CommandContext;
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Only name commits after tags
+    #[structopt(long = "tags")]
+    tags: bool,
+    /// Only use refs matching this pattern
+    #[structopt(long = "refs", value_name = "pattern", number_of_values = 1)]
+    refs: Vec<String>,
+    /// Don't use refs matching this pattern
+    #[structopt(long = "exclude", value_name = "pattern", number_of_values = 1)]
+    exclude: Vec<String>,
+    /// Print only the names, not the commits they're for
+    #[structopt(long = "name-only")]
+    name_only: bool,
+    /// Fail rather than print "undefined" for a commit with no name
+    #[structopt(long = "no-undefined")]
+    no_undefined: bool,
+    /// With --no-undefined, print a commit with no name as its abbreviated id
+    #[structopt(long = "always")]
+    always: bool,
+    /// The commits to name
+    #[structopt(required = true)]
+    revisions: Vec<String>,
+}
+
+pub fn run(args: Args, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let Args {
+        tags,
+        refs,
+        exclude,
+        name_only,
+        no_undefined,
+        always,
+        revisions,
+    } = args;
+    let options = NameRevOptions {
+        tags_only: tags,
+        refs,
+        exclude,
+        short_names: tags && name_only,
+    };
+    let output = NameRevOutput {
+        name_only,
+        undefined: !no_undefined,
+        always,
+    };
+    let msg = name_rev(&revisions, &options, &output, ctx.stderr, &ctx.root_path)?;
+    write!(ctx.stdout, "{}", msg)?;
+
+    Ok(())
+}
+
+/// How `name-rev` prints the names it finds.
+struct NameRevOutput {
+    name_only: bool,
+    /// Print "undefined" for commits that have no name, rather than failing.
+    undefined: bool,
+    /// Print commits that have no name as their abbreviated ids, rather than failing.
+    always: bool,
+}
+
+/// Name each of the revisions after a ref it can be reached from, each on a line of its
+/// own after the revision as given. Revisions that can't be found are skipped.
+fn name_rev(
+    revisions: &[String],
+    options: &NameRevOptions,
+    output: &NameRevOutput,
+    stderr: &mut dyn Write,
+    root_path: &Path,
+) -> anyhow::Result<String> {
+    let repo = Repository::new(git_dir(root_path));
+    let database = repo.database();
+    let refs = repo.refs();
+
+    let mut targets = Vec::new();
+    for revision in revisions {
+        match Revision::resolve_object(revision, database, refs) {
+            Ok(oid) => targets.push((revision, oid)),
+            Err(_) => writeln!(stderr, "Could not get sha1 for {}. Skipping.", revision)?,
+        }
+    }
+    let oids: Vec<_> = targets.iter().map(|(_, oid)| *oid).collect();
+    let names = NameRev::build(database, &refs.list(None)?, options, &oids)?;
+
+    let mut msg = String::new();
+    for (revision, oid) in targets {
+        if !output.name_only {
+            msg.push_str(&format!("{} ", revision));
+        }
+        let name = match names.name(&oid) {
+            Some(name) => name,
+            None if output.undefined => "undefined".to_owned(),
+            None if output.always => oid.short(7),
+            None => return Err(anyhow!("cannot describe '{}'", oid)),
+        };
+        msg.push_str(&name);
+        msg.push('\n');
+    }
+
+    Ok(msg)
+}
@@ -0,0 +1,161 @@
+use nit::{
+    database::DatabaseEntry, ignore::Ignores, inspector::Inspector, repository::Repository,
+    workspace::Workspace,
+};
+use std::path::Path;
+use structopt::StructOpt;
+
+use super::CommandContext;
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Don't look for untracked files, which can take a while in a large working tree
+    #[structopt(long = "no-untracked")]
+    no_untracked: bool,
+}
+
+pub fn run(args: Args, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let Args { no_untracked } = args;
+    // A prompt is shown everywhere, so outside a repository there's just nothing to say.
+    if ctx.repo.ensure_exists().is_err() {
+        return Ok(());
+    }
+
+    let msg = prompt(!no_untracked, &ctx.root_path)?;
+    writeln!(ctx.stdout, "{}", msg)?;
+
+    Ok(())
+}
+
+/// A one-line summary of the repository for a shell prompt, like `main *+% u+1-2`: the
+/// branch, or the abbreviated commit in parentheses if HEAD is detached, then `*` if there
+/// are unstaged changes, `+` if there are staged ones and `%` if there are untracked files,
+/// then how far the branch is ahead of and behind its upstream.
+///
+/// Each check stops at the first change it finds, and files whose stat info matches the
+/// index aren't read, so that it's quick enough to run before every prompt.
+pub fn prompt(untracked: bool, root_path: &Path) -> anyhow::Result<String> {
+    let repo = Repository::open(root_path);
+    let refs = repo.refs();
+    let head_oid = refs.read_head()?;
+    let current_ref = refs.current_ref()?;
+
+    let branch = current_ref.strip_prefix("refs/heads/");
+    let mut msg = match (branch, &head_oid) {
+        (Some(branch), _) => branch.to_owned(),
+        (None, Some(oid)) => format!("({})", oid.short(7)),
+        (None, None) => "(unknown)".to_owned(),
+    };
+
+    let workspace = repo.workspace()?;
+    let mut index = repo.configured_index()?;
+    index.load()?;
+    let inspector = Inspector::new(repo.database(), &index, &workspace);
+
+    let mut markers = String::new();
+    for entry in index.entries().values() {
+        let indexed = DatabaseEntry::new(*entry.oid(), entry.mode());
+        if inspector.workspace_entry(entry)? != Some(indexed) {
+            markers.push('*');
+            break;
+        }
+    }
+    let head = repo.database().load_tree_list(head_oid.as_ref())?;
+    if !inspector.tree_changes(&head, true)?.is_empty() {
+        markers.push('+');
+    }
+    if untracked {
+        let ignores = Ignores::load(root_path)?;
+        if has_untracked_files(&inspector, &workspace, &ignores, Path::new(""))? {
+            markers.push('%');
+        }
+    }
+    if !markers.is_empty() {
+        msg.push(' ');
+        msg.push_str(&markers);
+    }
+
+    let divergence = match branch {
+        Some(branch) if head_oid.is_some() => repo.divergence(branch)?,
+        _ => None,
+    };
+    if let Some(divergence) = divergence.filter(|divergence| divergence.upstream_exists) {
+        match (divergence.ahead, divergence.behind) {
+            (0, 0) => msg.push_str(" u="),
+            (ahead, 0) => msg.push_str(&format!(" u+{}", ahead)),
+            (0, behind) => msg.push_str(&format!(" u-{}", behind)),
+            (ahead, behind) => msg.push_str(&format!(" u+{}-{}", ahead, behind)),
+        }
+    }
+
+    Ok(msg)
+}
+
+/// Whether there's a file the index doesn't track, and that isn't ignored, anywhere in a
+/// directory. Ignored directories aren't looked in at all.
+fn has_untracked_files(
+    inspector: &Inspector,
+    workspace: &Workspace,
+    ignores: &Ignores,
+    dir: &Path,
+) -> anyhow::Result<bool> {
+    for (path, stat) in workspace.list_dir(dir)? {
+        if ignores.is_ignored(&path, stat.is_dir()) {
+            continue;
+        }
+        let found = match stat.is_dir() {
+            true => has_untracked_files(inspector, workspace, ignores, &path)?,
+            false => inspector.is_trackable_file(&path, &stat)?,
+        };
+        if found {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::test::{author_options, init, write_and_commit};
+    use crate::commands::{add::*, branch::*, commit::*};
+
+    #[test]
+    fn summarises_the_repository_for_a_prompt() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+        assert_eq!(prompt(true, tmp_path).unwrap(), "master");
+
+        write_and_commit(tmp_path, &[("a.txt", "one\n")], "First");
+        std::fs::write(tmp_path.join("a.txt"), "two\n").unwrap();
+        std::fs::write(tmp_path.join("b.txt"), "new\n").unwrap();
+        assert_eq!(prompt(true, tmp_path).unwrap(), "master *%");
+        assert_eq!(prompt(false, tmp_path).unwrap(), "master *");
+
+        add_files_to_repository(vec![&tmp_path.join("b.txt")], tmp_path).unwrap();
+        std::fs::write(tmp_path.join(".gitignore"), "a.txt\n.gitignore\n").unwrap();
+        assert_eq!(prompt(true, tmp_path).unwrap(), "master *+");
+
+        std::fs::write(
+            tmp_path.join(".git/config"),
+            "[branch \"master\"]\n\tremote = .\n\tmerge = refs/heads/base\n",
+        )
+        .unwrap();
+        let head = Repository::open(tmp_path)
+            .refs()
+            .read_head()
+            .unwrap()
+            .unwrap();
+        create_commit("Second".to_owned(), false, &[], &author_options(), tmp_path).unwrap();
+        create_branch("base", Some(&head.to_string()), false, tmp_path).unwrap();
+        assert_eq!(prompt(true, tmp_path).unwrap(), "master * u+1");
+
+        let repo = Repository::open(tmp_path);
+        repo.refs().detach_head(&head, "checkout").unwrap();
+        assert_eq!(
+            prompt(true, tmp_path).unwrap(),
+            format!("({}) *+", head.short(7))
+        );
+    }
+}
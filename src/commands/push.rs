@@ -0,0 +1,605 @@
+use anyhow::anyhow;
+use nit::{
+    database::ObjectId,
+    merge::{self},
+    progress::{Progress, StderrProgress},
+    refs::{Refs, HEAD},
+    remote::{self, RefCommand, Refspec, Remote},
+    repository::{git_dir, Repository},
+    revision::Revision,
+};
+use std::path::Path;
+use structopt::StructOpt;
+
+use super::fetch::remote_name;
+use super::CommandContext;
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Allow updates that aren't fast-forwards, losing whatever the remote ref had
+    #[structopt(short = "f", long = "force")]
+    force: bool,
+    /// Don't report progress
+    #[structopt(short = "q", long = "quiet")]
+    quiet: bool,
+    /// Allow updates that aren't fast-forwards, but only of remote refs that are still
+    /// where our remote-tracking ref says, or at <expect> if given
+    #[structopt(
+        long = "force-with-lease",
+        value_name = "ref[:expect]",
+        require_equals = true
+    )]
+    force_with_lease: Option<Option<String>>,
+    /// Update every ref or none of them: if any can't be updated, the rest are left as
+    /// they are
+    #[structopt(long = "atomic")]
+    atomic: bool,
+    /// The remote, or the path to a repository (the current branch's remote, or origin,
+    /// by default)
+    remote: Option<String>,
+    /// The refs to update, e.g. "main" or "HEAD:refs/heads/topic", with a leading "+" to
+    /// force the update, or ":<ref>" to delete a ref (the current branch by default)
+    refspecs: Vec<String>,
+}
+
+pub fn run(args: Args, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let Args {
+        force,
+        quiet,
+        force_with_lease,
+        atomic,
+        remote,
+        refspecs,
+    } = args;
+    let lease = force_with_lease.as_ref().map(Option::as_deref);
+    let mut progress = StderrProgress::new(quiet || ctx.is_quiet());
+    let msg = push(
+        remote.as_deref(),
+        &refspecs,
+        force,
+        lease,
+        atomic,
+        &mut progress,
+        &ctx.root_path,
+    )?;
+    write!(ctx.info_stderr(), "{}", msg)?;
+
+    Ok(())
+}
+
+/// What happened to one ref in a push or fetch.
+pub enum RefStatus {
+    UpToDate,
+    New,
+    FastForward(ObjectId, ObjectId),
+    Forced(ObjectId, ObjectId),
+    Deleted,
+    Rejected(&'static str),
+    /// Refused by the remote, for the reason it gave.
+    RemoteRejected(String),
+}
+
+/// Push refs to a remote, given by name or by path. With a lease, given as `None` for every
+/// ref or `Some("<ref>[:<expect>]")` for one, a ref may be forced only if the remote still
+/// has it where our remote-tracking ref, or `<expect>`, says. With `atomic`, either every
+/// ref is updated or none are.
+pub fn push(
+    remote: Option<&str>,
+    refspecs: &[String],
+    force: bool,
+    lease: Option<Option<&str>>,
+    atomic: bool,
+    progress: &mut dyn Progress,
+    root_path: &Path,
+) -> anyhow::Result<String> {
+    let repo = Repository::new(git_dir(root_path));
+    let config = repo.config()?;
+    let current_ref = repo.refs().current_ref()?;
+    let current_branch = current_ref.strip_prefix("refs/heads/");
+
+    let remote_name = remote_name(remote, &repo)?;
+    let configured_url = config.get(&format!("remote.{}.url", remote_name))?;
+    let url = configured_url
+        .clone()
+        .unwrap_or_else(|| remote_name.clone());
+    let remote = Remote::open(&url, root_path)?;
+    let remote_refs = remote.repository()?.refs();
+
+    let specs: Vec<Refspec> = match refspecs {
+        [] => {
+            let branch = current_branch
+                .ok_or_else(|| anyhow!("fatal: You are not currently on a branch."))?;
+            let destination = match repo.get_upstream(branch)? {
+                Some(upstream) if upstream.remote == remote_name => upstream.merge,
+                _ => current_ref.clone(),
+            };
+            vec![Refspec {
+                force: false,
+                source: current_ref.clone(),
+                destination,
+            }]
+        }
+        specs => specs
+            .iter()
+            .map(|spec| spec.parse())
+            .collect::<Result<_, _>>()?,
+    };
+
+    let mut updates = Vec::new();
+    for spec in specs {
+        let new = match spec.source.as_str() {
+            "" => None,
+            source => Some(Revision::resolve_object(
+                source,
+                repo.database(),
+                repo.refs(),
+            )?),
+        };
+        let destination = push_destination(&spec, repo.refs(), remote_refs);
+        let old = remote_refs.read_ref(&destination)?;
+
+        let lease_expects = match lease {
+            None => None,
+            Some(None) => Some(None),
+            Some(Some(lease)) => {
+                let (name, expect) = match lease.split_once(':') {
+                    Some((name, expect)) => (name, Some(expect)),
+                    None => (lease, None),
+                };
+                let applies = name == destination || name == Refs::short_name(&destination);
+                Some(expect).filter(|_| applies)
+            }
+        };
+        let expected = match lease_expects {
+            Some(Some("")) => Some(None),
+            // The remote may have moved to an object we don't have, so a full ObjectId
+            // is taken as it is.
+            Some(Some(expect)) => Some(Some(match ObjectId::from_hex(expect) {
+                Ok(oid) => oid,
+                Err(_) => Revision::resolve_object(expect, repo.database(), repo.refs())?,
+            })),
+            Some(None) if configured_url.is_some() => {
+                let tracking = repo.tracking_ref(&remote_name, &destination)?;
+                Some(repo.refs().read_ref(&tracking)?)
+            }
+            Some(None) => Some(None),
+            None => None,
+        };
+
+        let status = match (&old, &new) {
+            _ if old == new => RefStatus::UpToDate,
+            _ if expected.as_ref().is_some_and(|expected| *expected != old) => {
+                RefStatus::Rejected("stale info")
+            }
+            (None, _) => RefStatus::New,
+            (Some(_), None) => RefStatus::Deleted,
+            (Some(old), Some(new)) => {
+                let has_old = repo.database().contains(old)?;
+                let fast_forward = has_old && merge::is_ancestor(repo.database(), old, new)?;
+
+                if fast_forward {
+                    RefStatus::FastForward(*old, *new)
+                } else if force || spec.force || expected.is_some() {
+                    RefStatus::Forced(*old, *new)
+                } else if has_old {
+                    RefStatus::Rejected("non-fast-forward")
+                } else {
+                    RefStatus::Rejected("fetch first")
+                }
+            }
+        };
+
+        if let RefStatus::UpToDate = status {
+            continue;
+        }
+        updates.push((spec.source, destination, old, new, status));
+    }
+    if updates.is_empty() {
+        return Ok("Everything up-to-date\n".to_owned());
+    }
+
+    let is_rejected = |status: &RefStatus| matches!(status, RefStatus::Rejected(_));
+    if atomic && updates.iter().any(|(.., status)| is_rejected(status)) {
+        for (.., status) in &mut updates {
+            if !is_rejected(status) {
+                *status = RefStatus::Rejected("atomic push failed");
+            }
+        }
+    }
+
+    let accepted: Vec<_> = updates
+        .iter()
+        .filter(|(.., status)| !is_rejected(status))
+        .collect();
+    let tips: Vec<_> = accepted
+        .iter()
+        .filter_map(|(_, _, _, new, _)| *new)
+        .collect();
+    remote::copy_objects(
+        repo.database(),
+        remote.repository()?.database(),
+        &tips,
+        progress,
+    )?;
+    let commands: Vec<_> = accepted
+        .iter()
+        .map(|(_, destination, old, new, _)| RefCommand {
+            name: destination.clone(),
+            old: *old,
+            new: *new,
+        })
+        .collect();
+    let report = remote::receive_pack(remote.repository()?, &commands, atomic)?;
+    let mut results = report.results.into_iter();
+
+    let mut msg = String::from_utf8_lossy(&report.output)
+        .lines()
+        .map(|line| format!("remote: {}\n", line))
+        .collect::<String>();
+    msg.push_str(&format!("To {}\n", url));
+    let mut rejected = false;
+    for (source, destination, _, new, mut status) in updates {
+        if !is_rejected(&status) {
+            if let Some(Some(reason)) = results.next() {
+                status = RefStatus::RemoteRejected(reason);
+            }
+        }
+        msg.push_str(&ref_status_line(&status, &source, &destination));
+        if let RefStatus::Rejected(_) | RefStatus::RemoteRejected(_) = status {
+            rejected = true;
+            continue;
+        }
+
+        if configured_url.is_some() && destination.starts_with("refs/heads/") {
+            let tracking = repo.tracking_ref(&remote_name, &destination)?;
+            match &new {
+                Some(new) => repo.refs().update_ref(&tracking, new, "update by push")?,
+                None => {
+                    repo.refs().delete_ref(&tracking)?;
+                }
+            }
+        }
+    }
+
+    if rejected {
+        return Err(anyhow!(
+            "{}error: failed to push some refs to '{}'",
+            msg,
+            url
+        ));
+    }
+
+    Ok(msg)
+}
+
+/// Describe what happened to a ref in a push or fetch, as
+/// `<flag> <summary> <source> -> <target>`.
+pub fn ref_status_line(status: &RefStatus, source: &str, destination: &str) -> String {
+    let source = Refs::short_name(source);
+    let target = Refs::short_name(destination);
+
+    let (flag, summary, note) = match status {
+        RefStatus::UpToDate => (' ', "[up to date]".to_owned(), String::new()),
+        RefStatus::New => {
+            let kind = match destination {
+                name if name.starts_with("refs/tags/") => "tag",
+                name if name.starts_with("refs/heads/") || name.starts_with("refs/remotes/") => {
+                    "branch"
+                }
+                _ => "reference",
+            };
+            ('*', format!("[new {}]", kind), String::new())
+        }
+        RefStatus::FastForward(old, new) => (
+            ' ',
+            format!("{}..{}", old.short(7), new.short(7)),
+            String::new(),
+        ),
+        RefStatus::Forced(old, new) => (
+            '+',
+            format!("{}...{}", old.short(7), new.short(7)),
+            " (forced update)".to_owned(),
+        ),
+        RefStatus::Deleted if source.is_empty() => {
+            return format!(" - {:<17} {}\n", "[deleted]", target)
+        }
+        RefStatus::Deleted => ('-', "[deleted]".to_owned(), String::new()),
+        RefStatus::Rejected(reason) => ('!', "[rejected]".to_owned(), format!(" ({})", reason)),
+        RefStatus::RemoteRejected(reason) => (
+            '!',
+            "[remote rejected]".to_owned(),
+            format!(" ({})", reason),
+        ),
+    };
+
+    format!(
+        " {} {:<17} {} -> {}{}\n",
+        flag, summary, source, target, note
+    )
+}
+
+/// The full name of the ref a push updates on the remote. A short destination names a ref
+/// the remote already has, or else one of the same kind as the source.
+fn push_destination(spec: &Refspec, local: &Refs, remote: &Refs) -> String {
+    if spec.destination.starts_with("refs/") {
+        return spec.destination.clone();
+    }
+    if let Some(name) = remote
+        .expand_name(&spec.destination)
+        .filter(|name| name != HEAD)
+    {
+        return name;
+    }
+
+    let source = local.expand_name(&spec.source).unwrap_or_default();
+    match source.strip_prefix("refs/tags/") {
+        Some(_) => format!("refs/tags/{}", spec.destination),
+        None => format!("refs/heads/{}", spec.destination),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::test::{author_options, init, write_and_commit};
+    use crate::commands::{add::*, branch::*, commit::*};
+    use std::io::Write;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn pushes_only_fast_forwards_unless_forced() {
+        let (local, origin) = (init().unwrap(), init().unwrap());
+        let (local_path, origin_path) = (local.path(), origin.path());
+
+        let mut config = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(local_path.join(".git/config"))
+            .unwrap();
+        writeln!(
+            config,
+            "[remote \"origin\"]\n\turl = {}\n\tfetch = +refs/heads/*:refs/remotes/origin/*",
+            origin_path.display()
+        )
+        .unwrap();
+
+        write_and_commit(local_path, &[("a.txt", "a")], "First");
+        let msg = push(None, &[], false, None, false, &mut Vec::new(), local_path).unwrap();
+        assert_eq!(
+            msg,
+            format!(
+                "To {}\n * [new branch]      master -> master\n",
+                origin_path.display()
+            )
+        );
+        assert_eq!(
+            push(None, &[], false, None, false, &mut Vec::new(), local_path).unwrap(),
+            "Everything up-to-date\n"
+        );
+
+        let local_refs = Refs::new(&local_path.join(".git"));
+        let origin_refs = Refs::new(&origin_path.join(".git"));
+        let first = local_refs.read_head().unwrap().unwrap();
+        assert_eq!(origin_refs.read_ref("master").unwrap(), Some(first));
+        assert_eq!(local_refs.read_ref("origin/master").unwrap(), Some(first));
+
+        // Someone else pushes to origin, so our next push isn't a fast-forward.
+        std::fs::write(origin_path.join("b.txt"), "b").unwrap();
+        add_files_to_repository(vec![&origin_path.join("b.txt")], origin_path).unwrap();
+        create_commit(
+            "Theirs".to_owned(),
+            false,
+            &[],
+            &author_options(),
+            origin_path,
+        )
+        .unwrap();
+        let theirs = origin_refs.read_head().unwrap().unwrap();
+
+        write_and_commit(local_path, &[("a.txt", "b")], "Ours");
+        let err = push(None, &[], false, None, false, &mut Vec::new(), local_path).unwrap_err();
+        assert!(err.to_string().contains("(fetch first)"));
+
+        let err = push(
+            None,
+            &[],
+            false,
+            Some(None),
+            false,
+            &mut Vec::new(),
+            local_path,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("(stale info)"));
+        assert_eq!(origin_refs.read_ref("master").unwrap(), Some(theirs));
+
+        let lease = format!("master:{}", theirs);
+        let msg = push(
+            None,
+            &[],
+            false,
+            Some(Some(&lease)),
+            false,
+            &mut Vec::new(),
+            local_path,
+        )
+        .unwrap();
+        assert!(msg.ends_with("master -> master (forced update)\n"));
+        assert_eq!(
+            origin_refs.read_ref("master").unwrap(),
+            local_refs.read_head().unwrap()
+        );
+
+        let spec = [format!("{}:refs/heads/old", first)];
+        push(
+            Some("origin"),
+            &spec,
+            false,
+            None,
+            false,
+            &mut Vec::new(),
+            local_path,
+        )
+        .unwrap();
+        let msg = push(
+            Some("origin"),
+            &[":old".to_owned()],
+            false,
+            None,
+            false,
+            &mut Vec::new(),
+            local_path,
+        )
+        .unwrap();
+        assert!(msg.contains(" - [deleted]         old\n"));
+        assert_eq!(origin_refs.read_ref("refs/heads/old").unwrap(), None);
+    }
+
+    #[test]
+    fn pushes_every_ref_or_none_with_atomic() {
+        let (local, origin) = (init().unwrap(), init().unwrap());
+        let (local_path, origin_path) = (local.path(), origin.path());
+        std::fs::write(
+            local_path.join(".git/config"),
+            format!("[remote \"origin\"]\n\turl = {}\n", origin_path.display()),
+        )
+        .unwrap();
+
+        write_and_commit(origin_path, &[("a.txt", "a")], "Theirs");
+        write_and_commit(local_path, &[("a.txt", "b")], "Ours");
+        create_branch("topic", None, false, local_path).unwrap();
+        let specs = ["master".to_owned(), "topic".to_owned()];
+        let push_both = |atomic| {
+            push(
+                None,
+                &specs,
+                false,
+                None,
+                atomic,
+                &mut Vec::new(),
+                local_path,
+            )
+        };
+
+        // master can't be pushed, so with --atomic neither is topic.
+        let err = push_both(true).unwrap_err().to_string();
+        assert!(err.contains(" ! [rejected]        master -> master (fetch first)\n"));
+        assert!(err.contains(" ! [rejected]        topic -> topic (atomic push failed)\n"));
+        let origin_repo = Repository::new(origin_path.join(".git"));
+        assert_eq!(origin_repo.refs().read_ref("topic").unwrap(), None);
+
+        let err = push_both(false).unwrap_err().to_string();
+        assert!(err.contains(" * [new branch]      topic -> topic\n"));
+        let topic = origin_repo.refs().read_ref("topic").unwrap();
+        assert!(topic.is_some());
+
+        // On the receiving end, an update whose ref has moved takes the others with it.
+        let theirs = origin_repo.refs().read_ref("master").unwrap();
+        let commands = [
+            RefCommand {
+                name: "refs/heads/master".to_owned(),
+                old: theirs,
+                new: topic,
+            },
+            RefCommand {
+                name: "refs/heads/topic".to_owned(),
+                old: None,
+                new: theirs,
+            },
+        ];
+        let report = remote::receive_pack(&origin_repo, &commands, true).unwrap();
+        assert_eq!(
+            report.results,
+            vec![
+                Some("atomic transaction failed".to_owned()),
+                Some("failed to update ref".to_owned())
+            ]
+        );
+        assert_eq!(origin_repo.refs().read_ref("master").unwrap(), theirs);
+
+        let report = remote::receive_pack(&origin_repo, &commands, false).unwrap();
+        assert_eq!(
+            report.results,
+            vec![None, Some("failed to update ref".to_owned())]
+        );
+        assert_eq!(origin_repo.refs().read_ref("master").unwrap(), topic);
+    }
+
+    #[test]
+    fn runs_hooks_when_receiving_a_push() {
+        let (local, origin) = (init().unwrap(), init().unwrap());
+        let (local_path, origin_path) = (local.path(), origin.path());
+        std::fs::write(
+            local_path.join(".git/config"),
+            format!("[remote \"origin\"]\n\turl = {}\n", origin_path.display()),
+        )
+        .unwrap();
+
+        let hooks = origin_path.join(".git/hooks");
+        let hook = |name: &str, script: &str| {
+            std::fs::create_dir_all(&hooks).unwrap();
+            std::fs::write(hooks.join(name), format!("#!/bin/sh\n{}", script)).unwrap();
+            std::fs::set_permissions(hooks.join(name), std::fs::Permissions::from_mode(0o755))
+                .unwrap();
+        };
+        hook("pre-receive", "cat > pre-receive.log\n");
+        hook(
+            "update",
+            "if [ \"$1\" = refs/heads/blocked ]; then echo \"$1 is blocked\"; exit 1; fi\n",
+        );
+        hook("post-receive", "cat > post-receive.log\necho thanks\n");
+
+        write_and_commit(local_path, &[("a.txt", "a")], "First");
+        create_branch("blocked", None, false, local_path).unwrap();
+        let head = Refs::new(&local_path.join(".git"))
+            .read_head()
+            .unwrap()
+            .unwrap();
+        let specs = ["master".to_owned(), "blocked".to_owned()];
+
+        let err = push(
+            None,
+            &specs,
+            false,
+            None,
+            false,
+            &mut Vec::new(),
+            local_path,
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.starts_with("remote: refs/heads/blocked is blocked\nremote: thanks\n"));
+        assert!(err.contains(" * [new branch]      master -> master\n"));
+        assert!(err.contains(" ! [remote rejected] blocked -> blocked (hook declined)\n"));
+
+        let null = ObjectId::NULL;
+        let log = |name| std::fs::read_to_string(origin_path.join(".git").join(name)).unwrap();
+        assert_eq!(
+            log("pre-receive.log"),
+            format!(
+                "{0} {1} refs/heads/master\n{0} {1} refs/heads/blocked\n",
+                null, head
+            )
+        );
+        assert_eq!(
+            log("post-receive.log"),
+            format!("{} {} refs/heads/master\n", null, head)
+        );
+
+        hook("pre-receive", "exit 1\n");
+        let err = push(
+            None,
+            &specs,
+            false,
+            None,
+            false,
+            &mut Vec::new(),
+            local_path,
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("blocked -> blocked (pre-receive hook declined)\n"));
+        let origin_refs = Refs::new(&origin_path.join(".git"));
+        assert_eq!(origin_refs.read_ref("refs/heads/blocked").unwrap(), None);
+    }
+}
@@ -0,0 +1,303 @@
+use anyhow::anyhow;
+use nit::{
+    database::{Database, ObjectId},
+    diff::{self, DiffOptions, EditKind},
+    merge,
+    refs::Refs,
+    repository::{git_dir, Repository},
+    revision::Revision,
+};
+use std::path::Path;
+use structopt::StructOpt;
+
+use super::CommandContext;
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// How different two patches may be, as a percentage of their size, and still be
+    /// considered versions of each other
+    #[structopt(long = "creation-factor", default_value = "60")]
+    creation_factor: usize,
+    /// Only list how the commits match up, without showing how modified patches changed
+    #[structopt(short = "s", long = "no-patch")]
+    no_patch: bool,
+    /// The ranges to compare: `<base> <old> <new>`, `<old-base>..<old> <new-base>..<new>`,
+    /// or `<old>...<new>`
+    #[structopt(required = true, max_values = 3)]
+    ranges: Vec<String>,
+}
+
+pub fn run(args: Args, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let Args {
+        creation_factor,
+        no_patch,
+        ranges,
+    } = args;
+    let msg = range_diff(&ranges, creation_factor, !no_patch, &ctx.root_path)?;
+    write!(ctx.stdout, "{}", msg)?;
+
+    Ok(())
+}
+
+/// One commit in a range, along with what it changes.
+struct Patch {
+    oid: ObjectId,
+    title: String,
+    patch_id: ObjectId,
+    /// The commit's message and patch, without anything that changes just because the
+    /// commit was moved: blob ids and line numbers.
+    text: String,
+}
+
+/// Compare two versions of a series of commits, such as a branch before and after a
+/// rebase. Commits are paired up first by patch id, then with whichever remaining commit
+/// their patch is closest to, as long as the two are closer than `creation_factor` percent
+/// of their size. Each pair is listed with `=` if the patches are the same and `!` if not,
+/// followed by the diff between them if `show_patches` is set; commits only in the old
+/// range are listed with `<` and those only in the new one with `>`.
+pub fn range_diff(
+    ranges: &[String],
+    creation_factor: usize,
+    show_patches: bool,
+    root_path: &Path,
+) -> anyhow::Result<String> {
+    let repo = Repository::new(git_dir(root_path));
+    let database = repo.database();
+    let refs = repo.refs();
+
+    let (old, new) = match ranges {
+        [range] if range.contains("...") => {
+            let (a, b) = range.split_once("...").unwrap_or_default();
+            (format!("{}..{}", b, a), format!("{}..{}", a, b))
+        }
+        [old, new] if old.contains("..") && new.contains("..") => (old.clone(), new.clone()),
+        [base, old, new] => (format!("{}..{}", base, old), format!("{}..{}", base, new)),
+        _ => {
+            return Err(anyhow!(
+                "usage: nit range-diff <base> <old> <new>, <old-range> <new-range> or <old>...<new>"
+            ))
+        }
+    };
+
+    let old = range_patches(&old, database, refs)?;
+    let new = range_patches(&new, database, refs)?;
+    let matches = match_patches(&old, &new, creation_factor);
+
+    let width = (old.len().max(new.len()) + 1).to_string().len();
+    let side = |i: Option<usize>, patches: &[Patch]| match i {
+        Some(i) => format!(
+            "{:>width$}:  {}",
+            i + 1,
+            patches[i].oid.short(7),
+            width = width
+        ),
+        None => format!("{:>width$}:  {}", "-", "-".repeat(7), width = width),
+    };
+
+    let mut msg = String::new();
+    let mut pair = |i: Option<usize>, j: Option<usize>| {
+        let (status, title) = match (i, j) {
+            (Some(i), Some(j)) if old[i].text == new[j].text => ('=', &new[j].title),
+            (Some(_), Some(j)) => ('!', &new[j].title),
+            (Some(i), _) => ('<', &old[i].title),
+            (_, Some(j)) => ('>', &new[j].title),
+            (None, None) => unreachable!("a pair always has at least one commit"),
+        };
+        msg.push_str(&format!(
+            "{} {} {} {}\n",
+            side(i, &old),
+            status,
+            side(j, &new),
+            title
+        ));
+
+        if let (Some(i), Some(j), '!', true) = (i, j, status, show_patches) {
+            let hunks = diff::diff_hunks(
+                old[i].text.as_bytes(),
+                new[j].text.as_bytes(),
+                Path::new(""),
+                &DiffOptions::default(),
+            );
+            for hunk in hunks {
+                for line in hunk.to_string().lines() {
+                    msg.push_str(&format!("    {}\n", line));
+                }
+            }
+        }
+    };
+
+    // Follow the new range, but show each dropped commit where it used to be, and each
+    // commit that was moved where it is now.
+    let mut shown = vec![false; old.len()];
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && shown[i] {
+            i += 1;
+        } else if i < old.len() && !matches.contains(&Some(i)) {
+            pair(Some(i), None);
+            i += 1;
+        } else if let Some(matched) = matches.get(j) {
+            if let Some(matched) = *matched {
+                shown[matched] = true;
+            }
+            pair(*matched, Some(j));
+            j += 1;
+        }
+    }
+
+    Ok(msg)
+}
+
+/// List the commits in `range`, oldest first.
+fn range_patches(range: &str, database: &Database, refs: &Refs) -> anyhow::Result<Vec<Patch>> {
+    let (base, tip) = range
+        .split_once("..")
+        .ok_or_else(|| anyhow!("not a commit range: '{}'", range))?;
+    let base = Revision::resolve(base, database, refs)?;
+    let tip = Revision::resolve(tip, database, refs)?;
+    let (commits, _) = merge::unique_commits(database, &tip, &base)?;
+
+    commits
+        .into_iter()
+        .rev()
+        .map(|oid| {
+            let commit = database.load_commit(&oid)?;
+            let mut text = String::new();
+            for line in commit.message().lines() {
+                text.push_str(&format!("    {}\n", line));
+            }
+            text.push('\n');
+
+            let patch = diff::tree_patch(
+                database,
+                commit.parent(),
+                Some(&oid),
+                &DiffOptions::default(),
+            )?;
+            for line in patch.lines().filter(|line| !line.starts_with("index ")) {
+                match line.strip_prefix("@@ ") {
+                    Some(header) => {
+                        let function = header.split_once(" @@").map_or("", |(_, rest)| rest);
+                        text.push_str(&format!("@@{}\n", function));
+                    }
+                    None => text.push_str(&format!("{}\n", line)),
+                }
+            }
+
+            Ok(Patch {
+                oid,
+                title: commit.title_line().to_owned(),
+                patch_id: diff::patch_id(database, &oid)?,
+                text,
+            })
+        })
+        .collect()
+}
+
+/// Pair each new patch with the old one it's a version of, if there is one, returning the
+/// position in `old` that each patch in `new` was matched with.
+fn match_patches(old: &[Patch], new: &[Patch], creation_factor: usize) -> Vec<Option<usize>> {
+    let mut matches = vec![None; new.len()];
+    let mut taken = vec![false; old.len()];
+
+    for (j, patch) in new.iter().enumerate() {
+        if let Some(i) = (0..old.len()).find(|i| !taken[*i] && old[*i].patch_id == patch.patch_id) {
+            matches[j] = Some(i);
+            taken[i] = true;
+        }
+    }
+
+    // The patches that have changed are matched with whichever of the rest they're closest
+    // to, if it's cheaper than dropping one and adding the other.
+    for (j, patch) in new.iter().enumerate() {
+        if matches[j].is_some() {
+            continue;
+        }
+        let closest = (0..old.len())
+            .filter(|i| !taken[*i])
+            .map(|i| (distance(&old[i].text, &patch.text), i))
+            .min();
+        if let Some((cost, i)) = closest {
+            let size = old[i].text.lines().count() + patch.text.lines().count();
+            if cost * 100 < size * creation_factor {
+                matches[j] = Some(i);
+                taken[i] = true;
+            }
+        }
+    }
+
+    matches
+}
+
+/// How many lines have to be added or removed to turn one patch into another.
+fn distance(a: &str, b: &str) -> usize {
+    let a = diff::lines(a.as_bytes());
+    let b = diff::lines(b.as_bytes());
+    diff::diff(&a, &b, diff::Algorithm::Myers)
+        .iter()
+        .filter(|edit| edit.kind != EditKind::Equal)
+        .count()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::test::{init, write_and_commit, LOG_ONELINE};
+    use crate::commands::{branch::*, checkout::*, log::*};
+
+    #[test]
+    fn compares_commit_ranges() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+        write_and_commit(tmp_path, &[("base.txt", "base\n")], "Base");
+        create_branch("old", None, false, tmp_path).unwrap();
+        create_branch("new", None, false, tmp_path).unwrap();
+
+        checkout("old", CheckoutMode::Auto, tmp_path).unwrap();
+        write_and_commit(tmp_path, &[("a.txt", "one\ntwo\nthree\n")], "Add a");
+        write_and_commit(tmp_path, &[("b.txt", "one\ntwo\nthree\nfour\n")], "Add b");
+        write_and_commit(
+            tmp_path,
+            &[("c.txt", "something\nelse\nentirely\n")],
+            "Add c",
+        );
+
+        // Reorder the first two commits, change one of them, and swap the last for
+        // something new.
+        checkout("new", CheckoutMode::Auto, tmp_path).unwrap();
+        write_and_commit(tmp_path, &[("b.txt", "one\ntwo\nTHREE\nfour\n")], "Add b");
+        write_and_commit(tmp_path, &[("a.txt", "one\ntwo\nthree\n")], "Add a");
+        write_and_commit(
+            tmp_path,
+            &[("d.txt", "a\nnew\nfile\nwith\nmore\nlines\nin\nit\n")],
+            "Add d",
+        );
+
+        let oids = |branch| -> Vec<_> {
+            log(Some(branch), &[], &LOG_ONELINE, tmp_path)
+                .unwrap()
+                .lines()
+                .rev()
+                .skip(1)
+                .map(|line| line[..7].to_owned())
+                .collect()
+        };
+        let (old, new) = (oids("old"), oids("new"));
+
+        let ranges = ["master".to_owned(), "old".to_owned(), "new".to_owned()];
+        let msg = range_diff(&ranges, 60, false, tmp_path).unwrap();
+        assert_eq!(
+            msg,
+            format!(
+                "2:  {} ! 1:  {} Add b\n\
+                 1:  {} = 2:  {} Add a\n\
+                 3:  {} < -:  ------- Add c\n\
+                 -:  ------- > 3:  {} Add d\n",
+                old[1], new[0], old[0], new[1], old[2], new[2]
+            )
+        );
+
+        let msg = range_diff(&["old...new".to_owned()], 60, true, tmp_path).unwrap();
+        assert!(msg.contains("    -+three\n    ++THREE\n"));
+    }
+}
@@ -0,0 +1,260 @@
+use anyhow::anyhow;
+use nit::{
+    database::{Database, DatabaseEntry},
+    index::Index,
+    lockfile::LockfileError,
+    refs::Refs,
+    repository::{git_dir, Repository},
+    revision::Revision,
+};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+
+use super::CommandContext;
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Merge the trees into the index: `<head> <target>` moves the index from one tree
+    /// to the other, keeping staged changes; `<base> <ours> <theirs>` merges two trees
+    #[structopt(short = "m")]
+    merge: bool,
+    /// The tree to read, or with -m, the trees to merge
+    #[structopt(required = true, max_values = 3)]
+    trees: Vec<String>,
+}
+
+pub fn run(args: Args, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let Args { merge, trees } = args;
+    read_tree(&trees, merge, &ctx.root_path)?;
+
+    Ok(())
+}
+
+/// Replace the index with the contents of a tree, or with `merge`, with the result of merging
+/// two or three trees. Entries that don't change keep their stat information.
+pub fn read_tree(trees: &[String], merge: bool, root_path: &Path) -> anyhow::Result<()> {
+    let git_path = git_dir(root_path);
+    let mut index = Repository::new(&git_path).configured_index()?;
+    let database = Database::new(git_path.join("objects"));
+    let refs = Refs::new(&git_path);
+
+    (|| -> anyhow::Result<()> {
+        index.load_for_update()?;
+
+        let trees = trees
+            .iter()
+            .map(|tree| {
+                let oid = Revision::resolve_tree_ish(tree, &database, &refs)?;
+                Ok(database.load_tree_list(Some(&oid))?)
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let result = match (merge, trees.as_slice()) {
+            (_, [tree]) => tree.clone(),
+            (true, [head, target]) => two_tree_merge(&index, head, target)?,
+            (true, [base, ours, theirs]) => three_tree_merge(base, ours, theirs)?,
+            _ => return Err(anyhow!("reading more than one tree needs -m")),
+        };
+
+        let stale: Vec<_> = index
+            .entries()
+            .keys()
+            .filter(|path| !result.contains_key(*path))
+            .cloned()
+            .collect();
+        for path in stale {
+            index.remove(&path);
+        }
+
+        for (path, entry) in &result {
+            if index_entry(&index, path).as_ref() != Some(entry) {
+                index.add_from_db(path, entry);
+            }
+        }
+
+        index.write_updates()?;
+        Ok(())
+    })()
+    .or_else(|e| {
+        if let Some(nit::Error::Lockfile(LockfileError::LockDenied(_))) = e.downcast_ref() {
+            // We couldn't get the lock, so leave it in place.
+        } else {
+            index.lockfile_mut().rollback()?;
+        }
+
+        Err(e)
+    })
+}
+
+fn index_entry(index: &Index, path: &Path) -> Option<DatabaseEntry> {
+    index
+        .entry_for_path(&path)
+        .map(|entry| DatabaseEntry::new(*entry.oid(), entry.mode()))
+}
+
+/// Move the index from `head` to `target`, carrying forward any path staged with changes
+/// that the move doesn't touch.
+fn two_tree_merge(
+    index: &Index,
+    head: &BTreeMap<PathBuf, DatabaseEntry>,
+    target: &BTreeMap<PathBuf, DatabaseEntry>,
+) -> anyhow::Result<BTreeMap<PathBuf, DatabaseEntry>> {
+    let paths: BTreeSet<&PathBuf> = index
+        .entries()
+        .keys()
+        .chain(head.keys())
+        .chain(target.keys())
+        .collect();
+
+    let mut result = BTreeMap::new();
+    let mut conflicts = Vec::new();
+
+    for path in paths {
+        let staged = index_entry(index, path);
+        let (head, target) = (head.get(path), target.get(path));
+
+        let entry = if head == target {
+            staged
+        } else if staged.as_ref() == head || staged.as_ref() == target {
+            target.cloned()
+        } else {
+            conflicts.push(format!(
+                "Entry '{}' would be overwritten by merge. Cannot merge.",
+                path.display()
+            ));
+            continue;
+        };
+
+        if let Some(entry) = entry {
+            result.insert(path.clone(), entry);
+        }
+    }
+
+    if !conflicts.is_empty() {
+        return Err(anyhow!("{}", conflicts.join("\n")));
+    }
+
+    Ok(result)
+}
+
+/// Merge two trees with a common base, path by path. Paths changed differently on both
+/// sides are conflicts, and since the index can't record them, nothing is merged.
+fn three_tree_merge(
+    base: &BTreeMap<PathBuf, DatabaseEntry>,
+    ours: &BTreeMap<PathBuf, DatabaseEntry>,
+    theirs: &BTreeMap<PathBuf, DatabaseEntry>,
+) -> anyhow::Result<BTreeMap<PathBuf, DatabaseEntry>> {
+    let paths: BTreeSet<&PathBuf> = base
+        .keys()
+        .chain(ours.keys())
+        .chain(theirs.keys())
+        .collect();
+
+    let mut result = BTreeMap::new();
+    let mut conflicts = Vec::new();
+
+    for path in paths {
+        let (base, ours, theirs) = (base.get(path), ours.get(path), theirs.get(path));
+
+        let entry = if ours == theirs || base == theirs {
+            ours
+        } else if base == ours {
+            theirs
+        } else {
+            conflicts.push(format!("CONFLICT: {}", path.display()));
+            continue;
+        };
+
+        if let Some(entry) = entry {
+            result.insert(path.clone(), entry.clone());
+        }
+    }
+
+    if !conflicts.is_empty() {
+        return Err(anyhow!(
+            "{}\nAutomatic merge failed; the index was not changed",
+            conflicts.join("\n")
+        ));
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::test::{init, write_and_commit};
+    use crate::commands::{add::*, write_tree::*};
+    use nit::database::Blob;
+
+    #[test]
+    fn writes_and_reads_trees() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        write_and_commit(tmp_path, &[("a.txt", "a"), ("b.txt", "b")], "First");
+        let head_tree = write_tree_from_index(tmp_path).unwrap();
+        write_and_commit(tmp_path, &[("a.txt", "a2"), ("c.txt", "c")], "Second");
+
+        let staged = || {
+            let mut index = Index::new(tmp_path.join(".git").join("index"));
+            index.load().unwrap();
+            index
+                .entries()
+                .keys()
+                .map(|path| path.to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+        };
+
+        read_tree(&[head_tree.to_string()], false, tmp_path).unwrap();
+        assert_eq!(staged(), vec!["a.txt", "b.txt"]);
+        assert_eq!(write_tree_from_index(tmp_path).unwrap(), head_tree);
+
+        // Moving from the first tree to the second keeps a staged change to b.txt.
+        std::fs::write(tmp_path.join("b.txt"), "b2").unwrap();
+        add_files_to_repository(vec![&tmp_path.join("b.txt")], tmp_path).unwrap();
+        read_tree(&[head_tree.to_string(), "HEAD".to_owned()], true, tmp_path).unwrap();
+        assert_eq!(staged(), vec!["a.txt", "b.txt", "c.txt"]);
+
+        let database = Database::new(tmp_path.join(".git").join("objects"));
+        let mut index = Index::new(tmp_path.join(".git").join("index"));
+        index.load().unwrap();
+        assert_eq!(
+            index.entry_for_path(&"b.txt").unwrap().oid(),
+            &database.hash_object(&Blob::new(b"b2".to_vec())).unwrap()
+        );
+
+        // But not over a staged change to a file the move changes.
+        std::fs::write(tmp_path.join("c.txt"), "c2").unwrap();
+        add_files_to_repository(vec![&tmp_path.join("c.txt")], tmp_path).unwrap();
+        let err =
+            read_tree(&["HEAD".to_owned(), head_tree.to_string()], true, tmp_path).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Entry 'c.txt' would be overwritten by merge. Cannot merge."
+        );
+
+        // Merging three trees fails where both sides added c.txt differently...
+        let merged = write_tree_from_index(tmp_path).unwrap();
+        let trees = [head_tree.to_string(), "HEAD".to_owned(), merged.to_string()];
+        let err = read_tree(&trees, true, tmp_path).unwrap_err();
+        assert!(err.to_string().starts_with("CONFLICT: c.txt\n"));
+
+        // ...but takes changes from either side where only one side made them.
+        read_tree(&[head_tree.to_string()], false, tmp_path).unwrap();
+        std::fs::write(tmp_path.join("b.txt"), "b3").unwrap();
+        add_files_to_repository(vec![&tmp_path.join("b.txt")], tmp_path).unwrap();
+        let theirs = write_tree_from_index(tmp_path).unwrap();
+
+        let trees = [head_tree.to_string(), "HEAD".to_owned(), theirs.to_string()];
+        read_tree(&trees, true, tmp_path).unwrap();
+        assert_eq!(staged(), vec!["a.txt", "b.txt", "c.txt"]);
+        let mut index = Index::new(tmp_path.join(".git").join("index"));
+        index.load().unwrap();
+        assert_eq!(
+            index.entry_for_path(&"b.txt").unwrap().oid(),
+            &database.hash_object(&Blob::new(b"b3".to_vec())).unwrap()
+        );
+    }
+}
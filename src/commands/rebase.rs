@@ -0,0 +1,207 @@
+use anyhow::anyhow;
+use nit::{
+    database::{Database, ObjectId},
+    lockfile::LockfileError,
+    merge::{self},
+    migration::Migration,
+    rebase::{self, TodoList},
+    refs::{Refs, ORIG_HEAD},
+    repository::{git_dir, Repository},
+    revision::Revision,
+};
+use std::fs;
+use std::path::Path;
+use structopt::StructOpt;
+
+use super::{open_workspace, run_auto_gc, run_editor, CommandContext, Env};
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Edit the list of commits to replay before starting
+    #[structopt(short = "i", long = "interactive")]
+    interactive: bool,
+    /// Move fixup! and squash! commits after the commits they refer to, and meld them in
+    #[structopt(long = "autosquash")]
+    autosquash: bool,
+    /// The commit to replay the current branch on top of
+    upstream: String,
+}
+
+pub fn run(args: Args, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let Args {
+        interactive,
+        autosquash,
+        upstream,
+    } = args;
+    let msg = rebase(&upstream, interactive, autosquash, &ctx.env, &ctx.root_path)?;
+    write!(ctx.info_stderr(), "{}", msg)?;
+    run_auto_gc(ctx);
+
+    Ok(())
+}
+
+/// Replay the commits on the current branch that aren't in `upstream` on top of it, then
+/// move the branch and the workspace to the result. Where the branch was before is saved
+/// in ORIG_HEAD.
+pub fn rebase(
+    upstream: &str,
+    interactive: bool,
+    autosquash: bool,
+    env: &Env,
+    root_path: &Path,
+) -> anyhow::Result<String> {
+    let git_path = git_dir(root_path);
+    let repo = Repository::open(root_path);
+    let mut index = repo.configured_index()?;
+    let workspace = open_workspace(root_path)?;
+    let database = Database::new(git_path.join("objects"));
+    let refs = Refs::new(&git_path);
+
+    (|| -> anyhow::Result<String> {
+        index.load_for_update()?;
+
+        let head = refs
+            .read_head()?
+            .ok_or_else(|| anyhow!("fatal: no commits to rebase"))?;
+        let onto = Revision::resolve(upstream, &database, &refs)?;
+
+        let (mut commits, _) = merge::unique_commits(&database, &head, &onto)?;
+        commits.reverse();
+
+        let mut todo = TodoList::new(&database, &commits)?;
+        if autosquash {
+            todo.autosquash();
+        }
+        if interactive {
+            todo = edit_todo_list(&todo, &onto, &head, &database, env, &repo)?;
+        }
+
+        let tip = rebase::replay(&database, &onto, &todo)?;
+
+        let diff = database.tree_diff(Some(&head), Some(&tip))?;
+        Migration::new(&database, &mut index, &workspace, diff).apply_changes()?;
+        index.write_updates()?;
+        refs.update_ref(ORIG_HEAD, &head, "rebase (start)")?;
+        refs.update_head(
+            &tip,
+            &format!("rebase (finish): {} onto {}", refs.current_ref()?, onto),
+        )?;
+
+        Ok(format!(
+            "Successfully rebased and updated {}.\n",
+            refs.current_ref()?
+        ))
+    })()
+    .or_else(|e| {
+        if let Some(nit::Error::Lockfile(LockfileError::LockDenied(_))) = e.downcast_ref() {
+            // We couldn't get the lock, so leave it in place.
+        } else {
+            index.lockfile_mut().rollback()?;
+        }
+
+        Err(e)
+    })
+}
+
+/// Let the user edit the todo list in their editor, and read back what they left.
+fn edit_todo_list(
+    todo: &TodoList,
+    onto: &ObjectId,
+    head: &ObjectId,
+    database: &Database,
+    env: &Env,
+    repo: &Repository,
+) -> anyhow::Result<TodoList> {
+    let dir = repo.git_path().join("rebase-merge");
+    let path = dir.join("git-rebase-todo");
+    fs::create_dir_all(&dir)?;
+    fs::write(
+        &path,
+        format!(
+            "{}\n# Rebase {}..{} onto {} ({} commands)\n{}",
+            todo,
+            onto.short(7),
+            head.short(7),
+            onto.short(7),
+            todo.steps().len(),
+            TODO_LIST_HELP
+        ),
+    )?;
+
+    let variables = ["GIT_SEQUENCE_EDITOR", "GIT_EDITOR"];
+    let edited = run_editor(env, repo, &variables, &path);
+    let text = edited.and_then(|_| Ok(fs::read_to_string(&path)?));
+    fs::remove_dir_all(&dir)?;
+
+    let todo = TodoList::parse(&text?, database)?;
+    if todo.steps().is_empty() {
+        return Err(anyhow!("Nothing to do"));
+    }
+
+    Ok(todo)
+}
+
+const TODO_LIST_HELP: &str = "\
+#
+# Commands:
+# p, pick <commit> = use commit
+# s, squash <commit> = use commit, but meld into previous commit
+# f, fixup <commit> = like \"squash\", but discard this commit's log message
+# d, drop <commit> = remove commit
+#
+# These lines can be re-ordered; they are executed from top to bottom.
+# If you remove everything, the rebase will be aborted.
+";
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::test::{author_env, author_options, init, write_and_commit};
+    use crate::commands::{add::*, commit::*};
+
+    #[test]
+    fn autosquashes_fixup_commits() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        write_and_commit(tmp_path, &[("base.txt", "base\n")], "Base");
+        write_and_commit(tmp_path, &[("a.txt", "one\n")], "Add a");
+        write_and_commit(tmp_path, &[("b.txt", "two\n")], "Add b");
+
+        std::fs::write(tmp_path.join("a.txt"), "one, fixed\n").unwrap();
+        add_files_to_repository(vec![&tmp_path.join("a.txt")], tmp_path).unwrap();
+        let message = fixup_message("fixup", "HEAD^", None, tmp_path).unwrap();
+        assert_eq!(message, "fixup! Add a\n");
+        create_commit(message, false, &[], &author_options(), tmp_path).unwrap();
+
+        std::fs::write(tmp_path.join("b.txt"), "two, more\n").unwrap();
+        add_files_to_repository(vec![&tmp_path.join("b.txt")], tmp_path).unwrap();
+        let message =
+            fixup_message("squash", "HEAD^", Some("More b.".to_owned()), tmp_path).unwrap();
+        create_commit(message, false, &[], &author_options(), tmp_path).unwrap();
+
+        let msg = rebase("HEAD~4", false, true, &author_env(), tmp_path).unwrap();
+        assert_eq!(msg, "Successfully rebased and updated refs/heads/master.\n");
+
+        let repo = Repository::new(tmp_path.join(".git"));
+        let database = repo.database();
+        let head = repo.refs().read_head().unwrap().unwrap();
+        let b = database.load_commit(&head).unwrap();
+        assert_eq!(b.message(), "Add b\n\nMore b.\n");
+        let a = database.load_commit(b.parent().unwrap()).unwrap();
+        assert_eq!(a.message(), "Add a");
+        assert_eq!(
+            database.load_commit(a.parent().unwrap()).unwrap().message(),
+            "Base"
+        );
+
+        assert_eq!(
+            std::fs::read_to_string(tmp_path.join("a.txt")).unwrap(),
+            "one, fixed\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(tmp_path.join("b.txt")).unwrap(),
+            "two, more\n"
+        );
+    }
+}
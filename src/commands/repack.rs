@@ -0,0 +1,196 @@
+use nit::{
+    config::Config,
+    database::Database,
+    pack::{self, Pack, PackEntry, PackOptions},
+    repository::{git_dir, Repository},
+};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+
+use super::gc::{config_number, reachable_objects};
+use super::CommandContext;
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Put every reachable object into a single pack, rather than only those not yet packed
+    #[structopt(short = "a")]
+    all: bool,
+    /// Like -a, but with -d the unreachable objects in the old packs are kept as loose
+    /// objects rather than dropped, so that `gc` can prune them once they're old enough
+    #[structopt(short = "A")]
+    loosen: bool,
+    /// Delete the packs and loose objects made redundant by the new pack
+    #[structopt(short = "d")]
+    delete: bool,
+    /// How many similar objects to try as the base of each delta (defaults to the
+    /// `pack.window` config, or 10)
+    #[structopt(long = "window")]
+    window: Option<usize>,
+    /// The longest chain of deltas to allow (defaults to the `pack.depth` config, or 50)
+    #[structopt(long = "depth")]
+    depth: Option<usize>,
+}
+
+pub fn run(args: Args, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let Args {
+        all,
+        loosen,
+        delete,
+        window,
+        depth,
+    } = args;
+    let msg = repack(all || loosen, loosen, delete, window, depth, &ctx.root_path)?;
+    write!(ctx.info_stderr(), "{}", msg)?;
+
+    Ok(())
+}
+
+/// Pack the objects reachable from any ref into a new pack: all of them with `all`, or
+/// otherwise only those not already in a pack. With `delete`, loose objects that are now
+/// packed are deleted, along with the old packs if `all` was given. With `loosen`, any objects
+/// in those old packs that aren't reachable are written out loose before the packs go.
+pub fn repack(
+    all: bool,
+    loosen: bool,
+    delete: bool,
+    window: Option<usize>,
+    depth: Option<usize>,
+    root_path: &Path,
+) -> anyhow::Result<String> {
+    let repo = Repository::new(git_dir(root_path));
+    let database = repo.database();
+    let options = pack_options(&repo.config()?, window, depth)?;
+
+    let old_packs = database.packs()?;
+    let mut entries = reachable_objects(database, repo.refs())?;
+    if !all {
+        entries.retain(|entry| {
+            old_packs
+                .iter()
+                .all(|(_, index)| index.find(&entry.oid).is_none())
+        });
+    }
+    if entries.is_empty() {
+        return Ok("Nothing new to pack.\n".to_owned());
+    }
+
+    let (path, pack) = write_pack_files(database, &entries, &options)?;
+
+    if delete {
+        if all {
+            if loosen {
+                let reachable: HashSet<_> = entries.iter().map(|entry| entry.oid).collect();
+                for (_, index) in &old_packs {
+                    for entry in index.entries() {
+                        if !reachable.contains(&entry.oid) {
+                            let (kind, data) = database.read_object(&entry.oid)?;
+                            database.store_raw(&kind, &data)?;
+                        }
+                    }
+                }
+            }
+            for (old_path, _) in old_packs.iter().filter(|(old, _)| *old != path) {
+                fs::remove_file(old_path)?;
+                fs::remove_file(old_path.with_extension("pack"))?;
+            }
+        }
+        database.prune_packed()?;
+    }
+
+    let deltas = pack
+        .objects()
+        .iter()
+        .filter(|object| object.base.is_some())
+        .count();
+    Ok(format!(
+        "Total {} (delta {})\n",
+        pack.objects().len(),
+        deltas
+    ))
+}
+
+/// How hard to look for deltas: as given, or else as set by `pack.window` and `pack.depth`.
+pub fn pack_options(
+    config: &Config,
+    window: Option<usize>,
+    depth: Option<usize>,
+) -> anyhow::Result<PackOptions> {
+    let defaults = PackOptions::default();
+    Ok(PackOptions {
+        window: match window {
+            Some(window) => window,
+            None => config_number(config, "pack.window", defaults.window)?,
+        },
+        depth: match depth {
+            Some(depth) => depth,
+            None => config_number(config, "pack.depth", defaults.depth)?,
+        },
+    })
+}
+
+/// Write the entries into a new pack in the database, along with its index. Returns the
+/// index's path and the pack as written.
+pub fn write_pack_files(
+    database: &Database,
+    entries: &[PackEntry],
+    options: &PackOptions,
+) -> anyhow::Result<(PathBuf, Pack)> {
+    let bytes = pack::write_pack(entries, options);
+    let pack = Pack::parse(&bytes)?;
+    let path = database.store_pack(&bytes, &pack)?;
+
+    Ok((path, pack))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::test::{init, write_and_commit};
+    use crate::commands::verify_pack::*;
+
+    #[test]
+    fn repacks_objects_with_deltas() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        let numbers = |n: usize| (1..=n).map(|i| format!("line {}\n", i)).collect::<String>();
+        for n in 1..=4 {
+            write_and_commit(tmp_path, &[("numbers.txt", &numbers(n * 100))], "Count");
+        }
+
+        let repo = Repository::new(tmp_path.join(".git"));
+        let database = repo.database();
+        let head = repo.refs().read_head().unwrap().unwrap();
+        let files = database.load_tree_list(Some(&head)).unwrap();
+
+        let msg = repack(true, false, true, None, None, tmp_path).unwrap();
+        assert!(msg.starts_with("Total 12 (delta "), "{}", msg);
+        assert_ne!(msg, "Total 12 (delta 0)\n");
+        assert!(database.loose_objects().unwrap().is_empty());
+        assert_eq!(database.load_tree_list(Some(&head)).unwrap(), files);
+        let blob = database
+            .load_blob(files[Path::new("numbers.txt")].oid())
+            .unwrap();
+        assert_eq!(nit::database::Object::data(&blob), numbers(400).as_bytes());
+
+        write_and_commit(tmp_path, &[("numbers.txt", "Gone\n")], "Empty");
+        assert_eq!(
+            repack(false, false, true, None, None, tmp_path).unwrap(),
+            "Total 3 (delta 0)\n"
+        );
+        assert_eq!(database.packs().unwrap().len(), 2);
+        assert_eq!(
+            repack(false, false, false, None, None, tmp_path).unwrap(),
+            "Nothing new to pack.\n"
+        );
+
+        repack(true, false, true, Some(0), None, tmp_path).unwrap();
+        let packs = database.packs().unwrap();
+        assert_eq!(packs.len(), 1);
+        let stats = verify_pack(&packs[0].0, true).unwrap();
+        assert!(stats.contains("non delta: 15 objects\n"));
+        assert!(!stats.contains("chain length"));
+    }
+}
@@ -0,0 +1,212 @@
+use anyhow::anyhow;
+use nit::{
+    database::Commit,
+    repository::{git_dir, Repository},
+    revision::Revision,
+};
+use std::path::Path;
+use structopt::StructOpt;
+
+use super::CommandContext;
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Overwrite an existing replacement
+    #[structopt(short = "f", long = "force")]
+    force: bool,
+    /// Delete the replacements of the given objects
+    #[structopt(short = "d", long = "delete", conflicts_with_all = &["list", "graft"])]
+    delete: bool,
+    /// List the objects that have been replaced
+    #[structopt(short = "l", long = "list", conflicts_with = "graft")]
+    list: bool,
+    /// Replace a commit with a copy of it that has the given parents instead
+    #[structopt(long = "graft")]
+    graft: bool,
+    /// The object to replace and its replacement; with -d, the objects whose
+    /// replacements to delete; with --graft, the commit and its new parents
+    objects: Vec<String>,
+}
+
+pub fn run(args: Args, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let Args {
+        force,
+        delete,
+        list,
+        graft,
+        objects,
+    } = args;
+    let msg = match (&objects[..], delete, graft) {
+        (_, true, _) => delete_replacements(&objects, &ctx.root_path)?,
+        ([commit, parents @ ..], _, true) => {
+            graft_commit(commit, parents, force, &ctx.root_path)?;
+            String::new()
+        }
+        (_, _, true) => return Err(anyhow!("--graft needs a commit to replace")),
+        ([], _, _) => list_replacements(&ctx.root_path)?,
+        _ if list => list_replacements(&ctx.root_path)?,
+        ([object, replacement], _, _) => {
+            replace_object(object, replacement, force, &ctx.root_path)?;
+            String::new()
+        }
+        _ => return Err(anyhow!("expected an object and its replacement")),
+    };
+    write!(ctx.stdout, "{}", msg)?;
+
+    Ok(())
+}
+
+/// Record `replacement` as the object to load in place of `object`. Both must be of the
+/// same type, and with `force`, an existing replacement is overwritten.
+pub fn replace_object(
+    object: &str,
+    replacement: &str,
+    force: bool,
+    root_path: &Path,
+) -> anyhow::Result<()> {
+    let repo = Repository::new(git_dir(root_path));
+    let database = repo.database();
+    let refs = repo.refs();
+
+    let object = Revision::resolve_object(object, database, refs)?;
+    let replacement = Revision::resolve_object(replacement, database, refs)?;
+    let (object_kind, _) = database.read_object(&object)?;
+    let (replacement_kind, _) = database.read_object(&replacement)?;
+
+    if object_kind != replacement_kind {
+        return Err(anyhow!(
+            "Objects must be of the same type.\n\
+             '{}' points to a replaced object of type '{}'\n\
+             while '{}' points to a replacement object of type '{}'.",
+            object,
+            object_kind,
+            replacement,
+            replacement_kind
+        ));
+    }
+
+    let name = format!("refs/replace/{}", object);
+    if !force && refs.read_ref(&name)?.is_some() {
+        return Err(anyhow!("replace ref '{}' already exists", name));
+    }
+
+    Ok(refs.update_ref(&name, &replacement, "replace")?)
+}
+
+/// Replace a commit with a copy of it that has different parents.
+pub fn graft_commit(
+    commit: &str,
+    parents: &[String],
+    force: bool,
+    root_path: &Path,
+) -> anyhow::Result<()> {
+    let repo = Repository::new(git_dir(root_path));
+    let database = repo.database();
+
+    let oid = Revision::resolve(commit, database, repo.refs())?;
+    let commit = database.load_commit(&oid)?;
+    let parents = parents
+        .iter()
+        .map(|parent| Revision::resolve(parent, database, repo.refs()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let graft = Commit::with_parents(
+        parents,
+        *commit.tree(),
+        commit.author().clone(),
+        commit.message().to_owned(),
+    )
+    .with_committer(commit.committer().clone());
+    let graft = database.store(&graft)?;
+    if graft == oid {
+        return Err(anyhow!("new commit is the same as the old one: '{}'", oid));
+    }
+
+    replace_object(&oid.to_string(), &graft.to_string(), force, root_path)
+}
+
+/// Delete the replacements of the given objects.
+pub fn delete_replacements(objects: &[String], root_path: &Path) -> anyhow::Result<String> {
+    let repo = Repository::new(git_dir(root_path));
+    let mut msg = String::new();
+
+    for object in objects {
+        let oid = Revision::resolve_object(object, repo.database(), repo.refs())?;
+        match repo.refs().delete_ref(&format!("refs/replace/{}", oid))? {
+            Some(_) => msg.push_str(&format!("Deleted replace ref '{}'\n", oid)),
+            None => return Err(anyhow!("replace ref '{}' not found", oid)),
+        }
+    }
+
+    Ok(msg)
+}
+
+/// List the ObjectIds of the objects that have been replaced.
+pub fn list_replacements(root_path: &Path) -> anyhow::Result<String> {
+    let repo = Repository::new(git_dir(root_path));
+
+    Ok(repo
+        .refs()
+        .list(Some("refs/replace/"))?
+        .iter()
+        .filter_map(|r| r.name.strip_prefix("refs/replace/"))
+        .map(|oid| format!("{}\n", oid))
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::repack::*;
+    use crate::commands::test::{init, write_and_commit};
+    use nit::database::{Blob, ObjectId};
+
+    #[test]
+    fn loads_replacements_in_place_of_objects() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        write_and_commit(tmp_path, &[("a.txt", "a")], "First");
+        write_and_commit(tmp_path, &[("a.txt", "b")], "Second");
+
+        let repo = Repository::new(tmp_path.join(".git"));
+        let database = repo.database();
+        let head = repo.refs().read_head().unwrap().unwrap();
+        let parent = database.load_commit(&head).unwrap().parent().copied();
+        assert!(parent.is_some());
+
+        graft_commit("HEAD", &[], false, tmp_path).unwrap();
+        assert_eq!(database.load_commit(&head).unwrap().parent(), None);
+        let (_, raw) = database.read_object(&head).unwrap();
+        assert_eq!(Commit::parse(&raw).unwrap().parent(), parent.as_ref());
+        assert_eq!(list_replacements(tmp_path).unwrap(), format!("{}\n", head));
+        assert!(graft_commit("HEAD", &[], false, tmp_path).is_err());
+
+        let files = database.load_tree_list(parent.as_ref()).unwrap();
+        let a = files[Path::new("a.txt")].oid().to_string();
+        let b = database
+            .hash_object(&Blob::new(b"b".to_vec()))
+            .unwrap()
+            .to_string();
+        assert!(replace_object(&a, "HEAD", false, tmp_path).is_err());
+        replace_object(&a, &b, false, tmp_path).unwrap();
+        let blob = database
+            .load_blob(&ObjectId::from_hex(&a).unwrap())
+            .unwrap();
+        assert_eq!(blob.to_bytestr(), b"b");
+
+        repack(true, false, true, None, None, tmp_path).unwrap();
+        assert!(database.loose_objects().unwrap().is_empty());
+        assert_eq!(database.load_commit(&head).unwrap().parent(), None);
+
+        assert_eq!(
+            delete_replacements(&[head.to_string()], tmp_path).unwrap(),
+            format!("Deleted replace ref '{}'\n", head)
+        );
+        assert_eq!(
+            database.load_commit(&head).unwrap().parent(),
+            parent.as_ref()
+        );
+        assert!(delete_replacements(&[head.to_string()], tmp_path).is_err());
+    }
+}
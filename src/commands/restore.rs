@@ -0,0 +1,178 @@
+use anyhow::anyhow;
+use nit::{
+    database::{Database, DatabaseEntry},
+    lockfile::LockfileError,
+    refs::Refs,
+    repository::{git_dir, Repository},
+    revision::Revision,
+};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+
+use super::{open_workspace, CommandContext};
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Restore from the given commit instead of the index (or HEAD, with --staged)
+    #[structopt(short = "s", long = "source")]
+    source: Option<String>,
+    /// Restore the index
+    #[structopt(short = "S", long = "staged")]
+    staged: bool,
+    /// Restore the working tree (the default, unless --staged is given)
+    #[structopt(short = "W", long = "worktree")]
+    worktree: bool,
+    #[structopt(required = true)]
+    paths: Vec<String>,
+}
+
+pub fn run(args: Args, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let Args {
+        source,
+        staged,
+        worktree,
+        paths,
+    } = args;
+    let paths = paths.iter().map(Path::new).collect();
+    restore_files(paths, source.as_deref(), staged, worktree, &ctx.root_path)?;
+
+    Ok(())
+}
+
+pub fn restore_files(
+    paths: Vec<&Path>,
+    source: Option<&str>,
+    staged: bool,
+    worktree: bool,
+    root_path: &Path,
+) -> anyhow::Result<()> {
+    let git_path = git_dir(root_path);
+    let mut index = Repository::new(&git_path).configured_index()?;
+    let workspace = open_workspace(root_path)?;
+    let database = Database::new(git_path.join("objects"));
+    let refs = Refs::new(&git_path);
+    let worktree = worktree || !staged;
+
+    (|| -> anyhow::Result<()> {
+        index.load_for_update()?;
+
+        // The worktree is restored from the index by default, and the index from HEAD.
+        let source_entries = match (source, staged) {
+            (Some(revision), _) => {
+                let oid = Revision::resolve(revision, &database, &refs)?;
+                database.load_tree_list(Some(&oid))?
+            }
+            (None, true) => database.load_tree_list(refs.read_head()?.as_ref())?,
+            (None, false) => index
+                .entries()
+                .iter()
+                .map(|(path, entry)| {
+                    let item = DatabaseEntry::new(*entry.oid(), entry.mode());
+                    (path.to_owned(), item)
+                })
+                .collect(),
+        };
+
+        let mut matched = BTreeSet::new();
+        for pathspec in paths {
+            let pathspec = pathspec.strip_prefix(root_path).unwrap_or(pathspec);
+            let matches =
+                |path: &&PathBuf| pathspec == Path::new(".") || path.starts_with(pathspec);
+
+            let before = matched.len();
+            matched.extend(source_entries.keys().filter(matches).cloned());
+            matched.extend(index.entries().keys().filter(matches).cloned());
+
+            if matched.len() == before {
+                return Err(anyhow!(
+                    "pathspec '{}' did not match any file(s) known to nit",
+                    pathspec.display()
+                ));
+            }
+        }
+
+        for path in matched {
+            let item = source_entries.get(&path);
+
+            if worktree {
+                match item {
+                    Some(item) => {
+                        let blob = database.load_blob(item.oid())?;
+                        workspace.write_file(&path, blob.to_bytestr(), item.mode())?;
+                    }
+                    None => workspace.remove(&path)?,
+                }
+            }
+
+            if staged {
+                match (item, worktree) {
+                    (Some(item), true) => {
+                        let stat = workspace.stat_file(&path)?;
+                        index.add_with_mode(&path, *item.oid(), stat, item.mode());
+                    }
+                    (Some(item), false) => index.add_from_db(&path, item),
+                    (None, _) => index.remove(&path),
+                }
+            }
+        }
+
+        index.write_updates()?;
+        Ok(())
+    })()
+    .or_else(|e| {
+        // Cleanup lockfile if we had issues
+        if let Some(nit::Error::Lockfile(LockfileError::LockDenied(_))) = e.downcast_ref() {
+            // We couldn't get the lock, so leave it in place.
+        } else {
+            index.lockfile_mut().rollback()?;
+        }
+
+        Err(e)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::add::*;
+    use crate::commands::test::{init, write_and_commit};
+
+    #[test]
+    fn restores_files_from_the_index_and_head() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        write_and_commit(tmp_path, &[("a.txt", "a"), ("dir/b.txt", "b")], "First");
+
+        std::fs::write(tmp_path.join("a.txt"), "staged").unwrap();
+        add_files_to_repository(vec![&tmp_path.join("a.txt")], tmp_path).unwrap();
+        std::fs::write(tmp_path.join("a.txt"), "unstaged").unwrap();
+        std::fs::remove_file(tmp_path.join("dir/b.txt")).unwrap();
+
+        restore_files(
+            vec![Path::new("a.txt"), Path::new("dir")],
+            None,
+            false,
+            false,
+            tmp_path,
+        )
+        .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(tmp_path.join("a.txt")).unwrap(),
+            "staged"
+        );
+        assert_eq!(
+            std::fs::read_to_string(tmp_path.join("dir/b.txt")).unwrap(),
+            "b"
+        );
+
+        restore_files(vec![Path::new("a.txt")], None, true, true, tmp_path).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(tmp_path.join("a.txt")).unwrap(),
+            "a"
+        );
+
+        assert!(restore_files(vec![Path::new("nope")], None, false, false, tmp_path).is_err());
+    }
+}
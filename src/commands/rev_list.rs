@@ -0,0 +1,123 @@
+use nit::{
+    repository::{git_dir, Repository},
+    revision::Revision,
+    revwalk,
+};
+use std::path::Path;
+use structopt::StructOpt;
+
+use super::CommandContext;
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// List the trees and blobs the commits need too, each with its path
+    #[structopt(long = "objects")]
+    objects: bool,
+    /// The commits to list the history of. `^<rev>` leaves out everything reachable from
+    /// <rev>, and `<a>..<b>` lists what's reachable from <b> but not <a>
+    #[structopt(required = true)]
+    revisions: Vec<String>,
+}
+
+pub fn run(args: Args, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let Args { objects, revisions } = args;
+    let msg = rev_list(&revisions, objects, &ctx.root_path)?;
+    write!(ctx.stdout, "{}", msg)?;
+
+    Ok(())
+}
+
+/// List the commits reachable from the given revisions but not from any excluded ones,
+/// newest first, one id per line. With `objects`, the trees and blobs they need follow,
+/// each with its path after it; as in git, a root tree's path is empty.
+pub fn rev_list(revisions: &[String], objects: bool, root_path: &Path) -> anyhow::Result<String> {
+    let repo = Repository::new(git_dir(root_path));
+    let database = repo.database();
+    let refs = repo.refs();
+    let resolve = |expr: &str| Revision::resolve(expr, database, refs);
+
+    let mut include = Vec::new();
+    let mut exclude = Vec::new();
+    for revision in revisions {
+        if let Some((a, b)) = revision.split_once("..") {
+            exclude.push(resolve(a)?);
+            include.push(resolve(b)?);
+        } else if let Some(revision) = revision.strip_prefix('^') {
+            exclude.push(resolve(revision)?);
+        } else {
+            include.push(resolve(revision)?);
+        }
+    }
+
+    let mut msg = String::new();
+    for object in revwalk::list_objects(database, &include, &exclude)? {
+        match object.path {
+            Some(path) if objects => {
+                msg.push_str(&format!("{} {}\n", object.oid, path.display()));
+            }
+            Some(_) => break,
+            None => msg.push_str(&format!("{}\n", object.oid)),
+        }
+    }
+
+    Ok(msg)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::test::{init, write_and_commit};
+
+    #[test]
+    fn lists_objects_reachable_from_some_commits_but_not_others() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+        write_and_commit(tmp_path, &[("a.txt", "a"), ("dir/b.txt", "b")], "First");
+        write_and_commit(tmp_path, &[("dir/c.txt", "c")], "Second");
+
+        let repo = Repository::new(tmp_path.join(".git"));
+        let database = repo.database();
+        let head = repo.refs().read_head().unwrap().unwrap();
+        let first = *database.load_commit(&head).unwrap().parent().unwrap();
+        let entries = database.load_tree_list(Some(&head)).unwrap();
+        let blob = |path: &str| entries[Path::new(path)].oid().to_string();
+        let tree = |oid| database.load_commit(oid).unwrap().tree().to_string();
+        let dir_tree = |oid| {
+            database
+                .entry_at_path(oid, Path::new("dir"))
+                .unwrap()
+                .unwrap()
+                .oid()
+                .to_string()
+        };
+
+        let revisions =
+            |revisions: &[&str]| -> Vec<_> { revisions.iter().map(|r| r.to_string()).collect() };
+        assert_eq!(
+            rev_list(&revisions(&["HEAD"]), false, tmp_path).unwrap(),
+            format!("{}\n{}\n", head, first)
+        );
+
+        // Only what the second commit added is listed once the first is left out.
+        let expected = format!(
+            "{}\n{} \n{} dir\n{} dir/c.txt\n",
+            head,
+            tree(&head),
+            dir_tree(&head),
+            blob("dir/c.txt")
+        );
+        assert_eq!(
+            rev_list(&revisions(&["HEAD^..HEAD"]), true, tmp_path).unwrap(),
+            expected
+        );
+        assert_eq!(
+            rev_list(&revisions(&["HEAD", "^HEAD^"]), true, tmp_path).unwrap(),
+            expected
+        );
+
+        let all = rev_list(&revisions(&["HEAD"]), true, tmp_path).unwrap();
+        assert_eq!(all.lines().count(), 9);
+        assert!(all.contains(&format!("{} dir\n", dir_tree(&first))));
+        assert!(all.contains(&format!("{} dir/b.txt\n", blob("dir/b.txt"))));
+    }
+}
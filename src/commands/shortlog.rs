@@ -0,0 +1,194 @@
+use anyhow::Context;
+use nit::{
+    database::Commit,
+    mailmap::Mailmap,
+    refs::HEAD,
+    repository::{git_dir, Repository},
+    revision::Revision,
+    revwalk::RevWalk,
+};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+
+use super::CommandContext;
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Show only how many commits each person made
+    #[structopt(short = "s", long = "summary")]
+    summary: bool,
+    /// Sort by how many commits each person made, rather than by name
+    #[structopt(short = "n", long = "numbered")]
+    numbered: bool,
+    /// Show each person's email as well as their name
+    #[structopt(short = "e", long = "email")]
+    email: bool,
+    /// Group commits by committer rather than author
+    #[structopt(short = "c", long = "committer")]
+    committer: bool,
+    /// The commit to start from (defaults to HEAD)
+    revision: Option<String>,
+}
+
+pub fn run(args: Args, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let Args {
+        summary,
+        numbered,
+        email,
+        committer,
+        revision,
+    } = args;
+    let options = ShortlogOptions {
+        summary,
+        numbered,
+        email,
+        committer,
+    };
+    let msg = shortlog(revision.as_deref(), &options, &ctx.root_path)?;
+    write!(ctx.stdout, "{}", msg)?;
+
+    Ok(())
+}
+
+/// How `shortlog` should group and show commits.
+pub struct ShortlogOptions {
+    pub summary: bool,
+    pub numbered: bool,
+    pub email: bool,
+    pub committer: bool,
+}
+
+/// Group the commits reachable from a revision by their authors, with the names and emails
+/// mapped by the repository's mailmap, and list each author's commit subjects, oldest first.
+pub fn shortlog(
+    revision: Option<&str>,
+    options: &ShortlogOptions,
+    root_path: &Path,
+) -> anyhow::Result<String> {
+    let repo = Repository::new(git_dir(root_path));
+    let database = repo.database();
+    let start = Revision::resolve(revision.unwrap_or(HEAD), database, repo.refs())?;
+    let mailmap = load_mailmap(&repo, root_path)?;
+
+    let mut groups: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for commit in RevWalk::new(database, &[start])? {
+        let (_, commit) = commit?;
+        let person = match options.committer {
+            true => commit.committer(),
+            false => commit.author(),
+        };
+        let (name, email) = mailmap.map(person.name(), person.email());
+        let key = match options.email {
+            true => format!("{} <{}>", name, email),
+            false => name.to_owned(),
+        };
+        groups
+            .entry(key)
+            .or_default()
+            .push(shortlog_subject(&commit));
+    }
+
+    let mut groups: Vec<_> = groups.into_iter().collect();
+    if options.numbered {
+        groups.sort_by(|(a, a_subjects), (b, b_subjects)| {
+            b_subjects
+                .len()
+                .cmp(&a_subjects.len())
+                .then_with(|| a.cmp(b))
+        });
+    }
+
+    let mut msg = String::new();
+    for (key, subjects) in groups {
+        if options.summary {
+            msg.push_str(&format!("{:6}\t{}\n", subjects.len(), key));
+            continue;
+        }
+
+        msg.push_str(&format!("{} ({}):\n", key, subjects.len()));
+        for subject in subjects.iter().rev() {
+            msg.push_str(&format!("      {}\n", subject));
+        }
+        msg.push('\n');
+    }
+
+    Ok(msg)
+}
+
+/// A commit's subject as `shortlog` shows it: the first paragraph of its message on one
+/// line, without any `[PATCH]` prefix.
+fn shortlog_subject(commit: &Commit) -> String {
+    let paragraph = commit
+        .message()
+        .trim_start()
+        .split("\n\n")
+        .next()
+        .unwrap_or("");
+    let subject = paragraph.split_whitespace().collect::<Vec<_>>().join(" ");
+    match subject
+        .strip_prefix("[PATCH")
+        .and_then(|rest| rest.split_once("] "))
+    {
+        Some((_, rest)) => rest.to_owned(),
+        None => subject,
+    }
+}
+
+/// Read the mailmap from `.mailmap` at the top of the working tree, and then the file named
+/// by `mailmap.file`, if either exists.
+fn load_mailmap(repo: &Repository, root_path: &Path) -> anyhow::Result<Mailmap> {
+    let mut mailmap = Mailmap::default();
+    let files = std::iter::once(PathBuf::from(".mailmap"))
+        .chain(repo.config()?.get("mailmap.file")?.map(PathBuf::from));
+    for file in files {
+        match fs::read_to_string(root_path.join(&file)) {
+            Ok(text) => mailmap.extend(&text),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).with_context(|| format!("unable to read {}", file.display())),
+        }
+    }
+    Ok(mailmap)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::test::{init, write_and_commit};
+
+    #[test]
+    fn summarizes_history_by_author() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+        write_and_commit(tmp_path, &[("a.txt", "a")], "[PATCH] First\nof two\n\nBody");
+        write_and_commit(tmp_path, &[("a.txt", "b")], "Second");
+
+        let options = ShortlogOptions {
+            summary: false,
+            numbered: false,
+            email: false,
+            committer: false,
+        };
+        assert_eq!(
+            shortlog(None, &options, tmp_path).unwrap(),
+            "A. U. Thor (2):\n      First of two\n      Second\n\n"
+        );
+
+        std::fs::write(
+            tmp_path.join(".mailmap"),
+            "Proper Name <proper@example.com> <AUTHOR@example.com>\n",
+        )
+        .unwrap();
+        let options = ShortlogOptions {
+            summary: true,
+            numbered: true,
+            email: true,
+            ..options
+        };
+        assert_eq!(
+            shortlog(Some("HEAD^"), &options, tmp_path).unwrap(),
+            "     1\tProper Name <proper@example.com>\n"
+        );
+    }
+}
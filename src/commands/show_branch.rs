@@ -0,0 +1,213 @@
+use anyhow::anyhow;
+use nit::{
+    database::ObjectId,
+    merge,
+    refs::Refs,
+    repository::{git_dir, Repository},
+    revision::Revision,
+};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::Path;
+use structopt::StructOpt;
+
+use super::CommandContext;
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Instead of the commits, show where each pair of branches diverged: their best common
+    /// ancestor
+    #[structopt(long = "merge-base")]
+    merge_base: bool,
+    /// The branches to compare (defaults to every local branch)
+    branches: Vec<String>,
+}
+
+pub fn run(args: Args, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let Args {
+        merge_base,
+        branches,
+    } = args;
+    let msg = show_branch(&branches, merge_base, &ctx.root_path)?;
+    write!(ctx.stdout, "{}", msg)?;
+
+    Ok(())
+}
+
+/// Compare several branches, or every local branch if none are given. Each branch gets a
+/// column, marked `*` for the current branch and `!` for the others, and each commit that
+/// isn't on all of them is listed, newest first, with a `+` (or `*`, or `-` for a merge) in
+/// the column of each branch it's on. The list ends with the newest commit on all of them.
+pub fn show_branch(
+    branches: &[String],
+    merge_base: bool,
+    root_path: &Path,
+) -> anyhow::Result<String> {
+    let repo = Repository::new(git_dir(root_path));
+    let database = repo.database();
+    let refs = repo.refs();
+
+    let names: Vec<String> = match branches {
+        [] => refs
+            .list(Some("refs/heads/"))?
+            .into_iter()
+            .map(|r| Refs::short_name(&r.name).to_owned())
+            .collect(),
+        branches => branches.to_vec(),
+    };
+    if names.is_empty() {
+        return Err(anyhow!("No revs to be shown."));
+    }
+
+    let current_ref = refs.current_ref()?;
+    let mut tips = Vec::new();
+    let mut current = Vec::new();
+    for name in &names {
+        tips.push(Revision::resolve(name, database, refs)?);
+        current.push(refs.expand_name(name).as_deref() == Some(current_ref.as_str()));
+    }
+
+    let comparison = merge::compare_branches(database, &tips)?;
+    let title = |oid: &ObjectId| -> anyhow::Result<String> {
+        Ok(database.load_commit(oid)?.title_line().to_owned())
+    };
+
+    let mut msg = String::new();
+    if merge_base {
+        for (i, j, base) in &comparison.merge_bases {
+            match base {
+                Some(base) => msg.push_str(&format!(
+                    "{}...{}: {} {}\n",
+                    names[*i],
+                    names[*j],
+                    base.short(7),
+                    title(base)?
+                )),
+                None => msg.push_str(&format!(
+                    "{}...{}: no common history\n",
+                    names[*i], names[*j]
+                )),
+            }
+        }
+        return Ok(msg);
+    }
+
+    for (i, (name, tip)) in names.iter().zip(&tips).enumerate() {
+        let marker = if current[i] { '*' } else { '!' };
+        msg.push_str(&format!(
+            "{}{} [{}] {}\n",
+            " ".repeat(i),
+            marker,
+            name,
+            title(tip)?
+        ));
+    }
+    msg.push_str(&format!("{}\n", "-".repeat(names.len())));
+
+    let shown: HashSet<_> = comparison.commits.iter().map(|(oid, _)| *oid).collect();
+    let commit_names = name_commits(database, &names, &tips, &shown)?;
+    for (oid, on) in &comparison.commits {
+        let commit = database.load_commit(oid)?;
+        let columns: String = on
+            .iter()
+            .zip(&current)
+            .map(|(on, current)| match (on, current) {
+                (false, _) => ' ',
+                (true, _) if commit.parents().len() > 1 => '-',
+                (true, true) => '*',
+                (true, false) => '+',
+            })
+            .collect();
+        let name = commit_names
+            .get(oid)
+            .cloned()
+            .unwrap_or_else(|| oid.short(7));
+        msg.push_str(&format!("{} [{}] {}\n", columns, name, commit.title_line()));
+    }
+
+    Ok(msg)
+}
+
+/// Name each commit to be shown after the first branch it was found on, as git does:
+/// `topic` for the tip, `topic^` for its parent, `topic~2` for its grandparent, and
+/// `topic^2` for the second parent of a merge.
+fn name_commits(
+    database: &nit::database::Database,
+    names: &[String],
+    tips: &[ObjectId],
+    shown: &HashSet<ObjectId>,
+) -> anyhow::Result<HashMap<ObjectId, String>> {
+    let display = |base: &str, generation: usize| match generation {
+        0 => base.to_owned(),
+        1 => format!("{}^", base),
+        n => format!("{}~{}", base, n),
+    };
+
+    let mut named = HashMap::new();
+    for (name, tip) in names.iter().zip(tips) {
+        let mut queue = VecDeque::from([(*tip, name.clone(), 0)]);
+        while let Some((oid, base, generation)) = queue.pop_front() {
+            if !shown.contains(&oid) || named.contains_key(&oid) {
+                continue;
+            }
+            named.insert(oid, display(&base, generation));
+
+            let commit = database.load_commit(&oid)?;
+            for (n, parent) in commit.parents().iter().enumerate() {
+                match n {
+                    0 => queue.push_back((*parent, base.clone(), generation + 1)),
+                    n => {
+                        let base = format!("{}^{}", display(&base, generation), n + 1);
+                        queue.push_back((*parent, base, 0));
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(named)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::test::{init, write_and_commit};
+    use crate::commands::{branch::*, checkout::*};
+
+    #[test]
+    fn shows_how_branches_compare() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+        write_and_commit(tmp_path, &[("a.txt", "a")], "Base");
+        create_branch("topic", None, false, tmp_path).unwrap();
+        write_and_commit(tmp_path, &[("b.txt", "b")], "Main");
+        checkout("topic", CheckoutMode::Auto, tmp_path).unwrap();
+        write_and_commit(tmp_path, &[("c.txt", "c")], "Topic one");
+        write_and_commit(tmp_path, &[("d.txt", "d")], "Topic two");
+
+        let msg = show_branch(&[], false, tmp_path).unwrap();
+        let lines: Vec<_> = msg.lines().collect();
+        assert_eq!(
+            &lines[..3],
+            ["! [master] Main", " * [topic] Topic two", "--"]
+        );
+        let mut commits = lines[3..6].to_vec();
+        commits.sort_unstable();
+        assert_eq!(
+            commits,
+            [
+                " * [topic] Topic two",
+                " * [topic^] Topic one",
+                "+  [master] Main"
+            ]
+        );
+        assert_eq!(lines[6], "+* [master^] Base");
+        assert_eq!(lines.len(), 7);
+
+        let repo = Repository::new(tmp_path.join(".git"));
+        let master = repo.refs().read_ref("master").unwrap().unwrap();
+        let base = repo.database().load_commit(&master).unwrap().parents()[0].to_string();
+        let branches = ["master".to_owned(), "topic".to_owned()];
+        let msg = show_branch(&branches, true, tmp_path).unwrap();
+        assert_eq!(msg, format!("master...topic: {} Base\n", &base[..7]));
+    }
+}
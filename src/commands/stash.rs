@@ -0,0 +1,587 @@
+use anyhow::anyhow;
+use nit::{
+    database::{Author, Blob, Commit, Database, DatabaseEntry, ObjectId},
+    ignore::Ignores,
+    index::Index,
+    lockfile::LockfileError,
+    refs::{Refs, HEAD},
+    repository::{git_dir, Repository},
+    workspace::Workspace,
+};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+
+use super::checkout::{checkout, CheckoutMode, NewBranch};
+use super::commit::author_from_env;
+use super::{open_workspace, workspace_paths, CommandContext};
+
+/// The ref the newest stash is kept in. Its reflog holds the rest, newest last.
+const STASH: &str = "refs/stash";
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    #[structopt(flatten)]
+    options: PushOptions,
+    #[structopt(subcommand)]
+    command: Option<StashCommand>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct PushOptions {
+    /// Stash untracked files too, removing them from the working tree
+    #[structopt(short = "u", long = "include-untracked")]
+    include_untracked: bool,
+    /// Describe the stash with a message, rather than by the commit it was made on
+    #[structopt(short = "m", long = "message")]
+    message: Option<String>,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum StashCommand {
+    /// Save the local changes, or only those under the given paths, and put them back as
+    /// they are in HEAD. This is what `stash` with no command does
+    Push {
+        #[structopt(flatten)]
+        options: PushOptions,
+        paths: Vec<String>,
+    },
+    /// List the stashes, newest first
+    List,
+    /// Apply a stash's changes to the working tree, restoring any untracked files it saved.
+    /// The newest stash is applied unless another is given, as `stash@{<n>}` or `<n>`
+    Apply { stash: Option<String> },
+    /// Apply a stash, as apply does, and then drop it
+    Pop { stash: Option<String> },
+    /// Drop a stash, the newest unless another is given
+    Drop { stash: Option<String> },
+    /// Create a branch at the commit a stash was made on and check it out, then pop the
+    /// stash onto it. Useful when the stash no longer applies to HEAD
+    Branch { name: String, stash: Option<String> },
+}
+
+pub fn run(args: Args, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let Args { options, command } = args;
+    let (options, paths) = match command {
+        None => (options, Vec::new()),
+        Some(StashCommand::Push { options, paths }) => (options, paths),
+        Some(StashCommand::List) => {
+            let msg = stash_list(&ctx.root_path)?;
+            write!(ctx.stdout, "{}", msg)?;
+            return Ok(());
+        }
+        Some(StashCommand::Apply { stash }) => {
+            return stash_apply(stash_index(stash.as_deref())?, &ctx.root_path);
+        }
+        Some(StashCommand::Pop { stash }) => {
+            let n = stash_index(stash.as_deref())?;
+            stash_apply(n, &ctx.root_path)?;
+            let msg = stash_drop(n, &ctx.root_path)?;
+            write!(ctx.info(), "{}", msg)?;
+            return Ok(());
+        }
+        Some(StashCommand::Drop { stash }) => {
+            let msg = stash_drop(stash_index(stash.as_deref())?, &ctx.root_path)?;
+            write!(ctx.info(), "{}", msg)?;
+            return Ok(());
+        }
+        Some(StashCommand::Branch { name, stash }) => {
+            let msg = stash_branch(&name, stash_index(stash.as_deref())?, &ctx.root_path)?;
+            write!(ctx.info(), "{}", msg)?;
+            return Ok(());
+        }
+    };
+
+    let PushOptions {
+        include_untracked,
+        message,
+    } = options;
+    let paths = paths.iter().map(Path::new).collect();
+    let author = author_from_env(&ctx.env, Some(&ctx.repo.config()?))?;
+    let msg = stash_push(
+        paths,
+        include_untracked,
+        message.as_deref(),
+        author,
+        &ctx.root_path,
+    )?;
+    write!(ctx.info(), "{}", msg)?;
+
+    Ok(())
+}
+
+/// Which stash a command line argument names, counting back from the newest, which is 0.
+fn stash_index(stash: Option<&str>) -> anyhow::Result<usize> {
+    let stash = match stash {
+        Some(stash) => stash,
+        None => return Ok(0),
+    };
+
+    stash
+        .strip_prefix("stash@{")
+        .and_then(|rest| rest.strip_suffix('}'))
+        .unwrap_or(stash)
+        .parse()
+        .map_err(|_| anyhow!("'{}' is not a stash reference", stash))
+}
+
+/// The stash commit `n` stashes back from the newest.
+fn stash_commit(refs: &Refs, n: usize) -> anyhow::Result<ObjectId> {
+    let entries = refs.reflog(STASH)?;
+    if entries.is_empty() {
+        return Err(anyhow!("No stash entries found."));
+    }
+
+    match entries.iter().rev().nth(n) {
+        Some(entry) => Ok(entry.new),
+        None => Err(anyhow!("stash@{{{}}} is not a valid reference", n)),
+    }
+}
+
+/// List the stashes, newest first, each with the message it was saved with.
+pub fn stash_list(root_path: &Path) -> anyhow::Result<String> {
+    let refs = Refs::new(&git_dir(root_path));
+
+    Ok(refs
+        .reflog(STASH)?
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(n, entry)| format!("stash@{{{}}}: {}\n", n, entry.message))
+        .collect())
+}
+
+/// Save the changes to tracked files under the given paths (or the whole workspace), and
+/// with `include_untracked`, any untracked files there too, as a new stash; then put those
+/// paths back as they are in HEAD.
+///
+/// Like git's, a stash is a commit of the working tree whose parents are HEAD, a commit of
+/// the index, and with `include_untracked`, a commit of the untracked files on their own.
+pub fn stash_push(
+    paths: Vec<&Path>,
+    include_untracked: bool,
+    message: Option<&str>,
+    author: Author,
+    root_path: &Path,
+) -> anyhow::Result<String> {
+    let git_path = git_dir(root_path);
+    let mut index = Repository::new(&git_path).configured_index()?;
+    let workspace = open_workspace(root_path)?;
+    let database = Database::new(git_path.join("objects"));
+    let refs = Refs::new(&git_path);
+
+    (|| -> anyhow::Result<String> {
+        index.load_for_update()?;
+
+        let head = refs
+            .read_head()?
+            .ok_or_else(|| anyhow!("You do not have the initial commit yet"))?;
+
+        let prefixes = workspace_paths(&paths, root_path)?;
+        let matches =
+            |path: &Path| prefixes.is_empty() || prefixes.iter().any(|p| path.starts_with(p));
+
+        // The index's and working tree's versions of the paths being stashed, and HEAD's
+        // version of everything else.
+        let head_entries = database.load_tree_list(Some(&head))?;
+        let staged = index_entries(&index);
+        let mut index_tree = head_entries.clone();
+        index_tree.retain(|path, _| !matches(path));
+        index_tree.extend(
+            staged
+                .iter()
+                .filter(|(path, _)| matches(path))
+                .map(|(path, entry)| (path.clone(), entry.clone())),
+        );
+
+        let mut work_tree = index_tree.clone();
+        for (path, entry) in staged.iter().filter(|(path, _)| matches(path)) {
+            match store_file(&database, &workspace, path, Some(entry.mode()))? {
+                Some(entry) => work_tree.insert(path.clone(), entry),
+                None => work_tree.remove(path),
+            };
+        }
+
+        let mut untracked = BTreeMap::new();
+        if include_untracked {
+            let ignores = Ignores::load(root_path)?;
+            for path in untracked_files(&workspace, &index, &prefixes, root_path)? {
+                if !ignores.is_ignored(&path, false) {
+                    if let Some(entry) = store_file(&database, &workspace, &path, None)? {
+                        untracked.insert(path, entry);
+                    }
+                }
+            }
+        }
+
+        if index_tree == head_entries && work_tree == head_entries && untracked.is_empty() {
+            index.lockfile_mut().rollback()?;
+            return Ok("No local changes to save\n".to_owned());
+        }
+
+        let branch = match refs.current_ref()? {
+            name if name == HEAD => "(no branch)".to_owned(),
+            name => Refs::short_name(&name).to_owned(),
+        };
+        let on = format!(
+            "{}: {} {}",
+            branch,
+            head.short(7),
+            database.load_commit(&head)?.title_line()
+        );
+
+        let mut parents = vec![head];
+        let tree = database.store_tree_list(&index_tree)?;
+        let title = format!("index on {}\n", on);
+        parents.push(database.store(&Commit::new(Some(head), tree, author.clone(), title))?);
+
+        if !untracked.is_empty() {
+            let tree = database.store_tree_list(&untracked)?;
+            let title = format!("untracked files on {}\n", on);
+            let commit = Commit::with_parents(Vec::new(), tree, author.clone(), title);
+            parents.push(database.store(&commit)?);
+        }
+
+        let message = match message {
+            Some(message) => format!("On {}: {}", branch, message),
+            None => format!("WIP on {}", on),
+        };
+        let tree = database.store_tree_list(&work_tree)?;
+        let commit = Commit::with_parents(parents, tree, author, format!("{}\n", message));
+        let oid = database.store(&commit)?;
+        refs.update_ref(STASH, &oid, &message)?;
+
+        // Put everything that was stashed back as it is in HEAD.
+        let stashed: BTreeSet<_> = head_entries
+            .keys()
+            .chain(staged.keys())
+            .filter(|path| matches(path))
+            .cloned()
+            .collect();
+        for path in stashed {
+            match head_entries.get(&path) {
+                Some(entry)
+                    if index_tree.get(&path) == Some(entry)
+                        && work_tree.get(&path) == Some(entry) => {}
+                Some(entry) => {
+                    let blob = database.load_blob(entry.oid())?;
+                    workspace.write_file(&path, blob.to_bytestr(), entry.mode())?;
+                    let stat = workspace.stat_file(&path)?;
+                    index.add_with_mode(&path, *entry.oid(), stat, entry.mode());
+                }
+                None => {
+                    index.remove(&path);
+                    remove_file(&workspace, &path)?;
+                }
+            }
+        }
+        for path in untracked.keys() {
+            remove_file(&workspace, path)?;
+        }
+
+        index.write_updates()?;
+        Ok(format!(
+            "Saved working directory and index state {}\n",
+            message
+        ))
+    })()
+    .or_else(|e| {
+        if let Some(nit::Error::Lockfile(LockfileError::LockDenied(_))) = e.downcast_ref() {
+            // We couldn't get the lock, so leave it in place.
+        } else {
+            index.lockfile_mut().rollback()?;
+        }
+
+        Err(e)
+    })
+}
+
+/// Apply the changes stash `n` made to the commit it was made on to the working tree, and
+/// restore the untracked files it saved. Files the stash added are staged; other changes
+/// are left unstaged. Nothing is changed if any of the paths it touches have local changes
+/// that differ from the stash's, or if an untracked file it saved is in the way.
+pub fn stash_apply(n: usize, root_path: &Path) -> anyhow::Result<()> {
+    let git_path = git_dir(root_path);
+    let mut index = Repository::new(&git_path).configured_index()?;
+    let workspace = open_workspace(root_path)?;
+    let database = Database::new(git_path.join("objects"));
+    let refs = Refs::new(&git_path);
+
+    (|| -> anyhow::Result<()> {
+        index.load_for_update()?;
+
+        let oid = stash_commit(&refs, n)?;
+        let commit = database.load_commit(&oid)?;
+        let changes = database.tree_diff(commit.parent(), Some(&oid))?;
+        let untracked = match commit.parents().get(2) {
+            Some(oid) => database.load_tree_list(Some(oid))?,
+            None => BTreeMap::new(),
+        };
+
+        let mut to_apply = Vec::new();
+        let mut conflicts = Vec::new();
+        for (path, (before, after)) in changes {
+            let staged = index_entries(&index).remove(&path);
+            let mode = staged.as_ref().map(DatabaseEntry::mode);
+            let current = hash_file(&database, &workspace, &path, mode)?;
+
+            if current == after {
+                continue;
+            }
+            if current != staged || (staged != before && staged != after) {
+                conflicts.push(path);
+                continue;
+            }
+            to_apply.push((path, before, after));
+        }
+
+        if !conflicts.is_empty() {
+            let paths: String = conflicts
+                .iter()
+                .map(|path| format!("\t{}\n", path.display()))
+                .collect();
+            return Err(anyhow!(
+                "Your local changes to the following files would be overwritten by stash apply:\n{}\
+                 Please commit your changes or stash them before you apply a stash.",
+                paths
+            ));
+        }
+        for path in untracked.keys() {
+            if workspace.try_stat_file(path)?.is_some() {
+                return Err(anyhow!("{} already exists, no checkout", path.display()));
+            }
+        }
+
+        for (path, before, after) in to_apply {
+            match after {
+                Some(entry) => {
+                    let blob = database.load_blob(entry.oid())?;
+                    workspace.write_file(&path, blob.to_bytestr(), entry.mode())?;
+                    if before.is_none() {
+                        let stat = workspace.stat_file(&path)?;
+                        index.add_with_mode(&path, *entry.oid(), stat, entry.mode());
+                    }
+                }
+                None => remove_file(&workspace, &path)?,
+            }
+        }
+        for (path, entry) in untracked {
+            let blob = database.load_blob(entry.oid())?;
+            workspace.write_file(&path, blob.to_bytestr(), entry.mode())?;
+        }
+
+        index.write_updates()?;
+        Ok(())
+    })()
+    .or_else(|e| {
+        if let Some(nit::Error::Lockfile(LockfileError::LockDenied(_))) = e.downcast_ref() {
+            // We couldn't get the lock, so leave it in place.
+        } else {
+            index.lockfile_mut().rollback()?;
+        }
+
+        Err(e)
+    })
+}
+
+/// Drop stash `n`, counting back from the newest.
+pub fn stash_drop(n: usize, root_path: &Path) -> anyhow::Result<String> {
+    let refs = Refs::new(&git_dir(root_path));
+    stash_commit(&refs, n)?;
+
+    match refs.drop_reflog_entry(STASH, n)? {
+        Some(entry) => Ok(format!("Dropped refs/stash@{{{}}} ({})\n", n, entry.new)),
+        None => Err(anyhow!("stash@{{{}}} is not a valid reference", n)),
+    }
+}
+
+/// Check out a new branch at the commit stash `n` was made on, apply the stash there, where
+/// it can't conflict with anything committed since, and drop it if that worked.
+pub fn stash_branch(name: &str, n: usize, root_path: &Path) -> anyhow::Result<String> {
+    let refs = Refs::new(&git_dir(root_path));
+    let oid = stash_commit(&refs, n)?;
+    let database = Database::new(git_dir(root_path).join("objects"));
+    let base = database
+        .load_commit(&oid)?
+        .parent()
+        .copied()
+        .ok_or_else(|| anyhow!("stash@{{{}}} has no base commit", n))?;
+
+    let branch = NewBranch { name, track: false };
+    let mut msg = checkout(
+        &base.to_string(),
+        CheckoutMode::NewBranch(branch),
+        root_path,
+    )?;
+    stash_apply(n, root_path)?;
+    msg.push_str(&stash_drop(n, root_path)?);
+
+    Ok(msg)
+}
+
+/// The blob and mode the index has at each path.
+fn index_entries(index: &Index) -> BTreeMap<PathBuf, DatabaseEntry> {
+    index
+        .entries()
+        .iter()
+        .map(|(path, entry)| (path.clone(), DatabaseEntry::new(*entry.oid(), entry.mode())))
+        .collect()
+}
+
+/// Store the file at a path in the workspace as a blob, returning it with the file's mode,
+/// or `None` if there's no file there.
+fn store_file(
+    database: &Database,
+    workspace: &Workspace,
+    path: &Path,
+    tracked: Option<u32>,
+) -> anyhow::Result<Option<DatabaseEntry>> {
+    match workspace.try_stat_file(path)? {
+        Some(stat) if !stat.is_dir() => {
+            let oid = database.store(&Blob::new(workspace.read_file(path)?))?;
+            let mode = workspace.mode_for_stat(&stat, tracked);
+            Ok(Some(DatabaseEntry::new(oid, mode)))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// What the file at a path in the workspace would be stored as, without storing it.
+fn hash_file(
+    database: &Database,
+    workspace: &Workspace,
+    path: &Path,
+    tracked: Option<u32>,
+) -> anyhow::Result<Option<DatabaseEntry>> {
+    match workspace.try_stat_file(path)? {
+        Some(stat) if !stat.is_dir() => {
+            let oid = database.hash_object(&Blob::new(workspace.read_file(path)?))?;
+            let mode = workspace.mode_for_stat(&stat, tracked);
+            Ok(Some(DatabaseEntry::new(oid, mode)))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// The files under the given paths (or the whole workspace) that the index doesn't track.
+fn untracked_files(
+    workspace: &Workspace,
+    index: &Index,
+    prefixes: &[PathBuf],
+    root_path: &Path,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let root = [PathBuf::new()];
+    let prefixes = if prefixes.is_empty() { &root } else { prefixes };
+
+    for prefix in prefixes {
+        if workspace.try_stat_file(prefix)?.is_none() {
+            continue;
+        }
+        for path in workspace.list_files(root_path.join(prefix))? {
+            if !index.is_tracked(&Path::new(&path)) {
+                files.push(PathBuf::from(path));
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+/// Remove a file from the workspace, along with any directories that leaves empty.
+fn remove_file(workspace: &Workspace, path: &Path) -> anyhow::Result<()> {
+    workspace.remove(path)?;
+    for dir in path.ancestors().skip(1) {
+        if dir.as_os_str().is_empty() {
+            break;
+        }
+        workspace.remove_dir_if_empty(dir)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::branch::*;
+    use crate::commands::test::{author_env, init, write_and_commit};
+
+    #[test]
+    fn stashes_untracked_files_and_paths() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+        write_and_commit(tmp_path, &[("a.txt", "a"), ("dir/b.txt", "b")], "First");
+        let read = |path: &str| std::fs::read_to_string(tmp_path.join(path)).ok();
+        let author = || author_from_env(&author_env(), None).unwrap();
+
+        // Only the changes under the given paths are stashed, untracked files included.
+        std::fs::write(tmp_path.join("a.txt"), "a2").unwrap();
+        std::fs::write(tmp_path.join("dir/b.txt"), "b2").unwrap();
+        std::fs::write(tmp_path.join("dir/new.txt"), "new").unwrap();
+        std::fs::write(tmp_path.join("other.txt"), "other").unwrap();
+        let dir = tmp_path.join("dir");
+        let msg = stash_push(vec![&dir], true, Some("dir"), author(), tmp_path).unwrap();
+        assert_eq!(
+            msg,
+            "Saved working directory and index state On master: dir\n"
+        );
+        assert_eq!(read("a.txt").as_deref(), Some("a2"));
+        assert_eq!(read("dir/b.txt").as_deref(), Some("b"));
+        assert_eq!(read("dir/new.txt"), None);
+        assert_eq!(read("other.txt").as_deref(), Some("other"));
+
+        // Without -u, untracked files are left where they are.
+        stash_push(vec![], false, None, author(), tmp_path).unwrap();
+        assert_eq!(read("a.txt").as_deref(), Some("a"));
+        assert_eq!(read("other.txt").as_deref(), Some("other"));
+        let msg = stash_push(vec![], false, None, author(), tmp_path).unwrap();
+        assert_eq!(msg, "No local changes to save\n");
+
+        let list = stash_list(tmp_path).unwrap();
+        let lines: Vec<_> = list.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("stash@{0}: WIP on master: "));
+        assert_eq!(lines[1], "stash@{1}: On master: dir");
+
+        stash_apply(1, tmp_path).unwrap();
+        assert_eq!(read("dir/b.txt").as_deref(), Some("b2"));
+        assert_eq!(read("dir/new.txt").as_deref(), Some("new"));
+        stash_drop(1, tmp_path).unwrap();
+
+        // A file with changes of its own can't be overwritten.
+        std::fs::write(tmp_path.join("a.txt"), "a3").unwrap();
+        assert!(stash_apply(0, tmp_path).is_err());
+        std::fs::write(tmp_path.join("a.txt"), "a").unwrap();
+        stash_apply(0, tmp_path).unwrap();
+        assert_eq!(read("a.txt").as_deref(), Some("a2"));
+        stash_drop(0, tmp_path).unwrap();
+
+        assert_eq!(stash_list(tmp_path).unwrap(), "");
+        assert!(!tmp_path.join(".git/refs/stash").exists());
+        assert!(stash_drop(0, tmp_path).is_err());
+    }
+
+    #[test]
+    fn pops_a_stash_onto_a_new_branch() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+        write_and_commit(tmp_path, &[("a.txt", "a")], "First");
+        let read = |path: &str| std::fs::read_to_string(tmp_path.join(path)).ok();
+        let author = || author_from_env(&author_env(), None).unwrap();
+
+        std::fs::write(tmp_path.join("a.txt"), "stashed").unwrap();
+        stash_push(vec![], false, None, author(), tmp_path).unwrap();
+        write_and_commit(tmp_path, &[("a.txt", "committed")], "Second");
+        assert!(stash_apply(0, tmp_path).is_err());
+
+        let msg = stash_branch("stashed", 0, tmp_path).unwrap();
+        assert!(msg.starts_with("Switched to a new branch 'stashed'\n"));
+        assert!(msg.contains("Dropped refs/stash@{0}"));
+        assert_eq!(read("a.txt").as_deref(), Some("stashed"));
+        assert_eq!(stash_list(tmp_path).unwrap(), "");
+        assert!(list_branches(0, tmp_path).unwrap().contains("* stashed"));
+
+        assert!(stash_branch("again", 0, tmp_path).is_err());
+    }
+}
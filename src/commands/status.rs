@@ -0,0 +1,487 @@
+#[cfg(not(target_os = "wasi"))]
+use nit::watch::{initial_changes, StatusChange, StatusStream};
+use nit::{
+    ignore::Ignores,
+    quote::{quote_bytes, quote_non_ascii, quote_path},
+    refs::Refs,
+    repository::{git_dir, Divergence, Repository},
+};
+use std::collections::BTreeSet;
+use std::path::Path;
+use structopt::StructOpt;
+
+use super::{plural, CommandContext};
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Give the output in the long format
+    #[structopt(long = "long")]
+    long: bool,
+    /// Show the branch and its tracking info, even in the short format
+    #[structopt(short = "b", long = "branch")]
+    branch: bool,
+    /// Show ignored files as well
+    #[structopt(long = "ignored")]
+    ignored: bool,
+    /// Keep watching the working tree after showing the status, and show each path whose
+    /// status changes as it does. A path that's no longer changed is shown with a blank
+    /// status
+    #[structopt(long = "watch")]
+    watch: bool,
+}
+
+pub fn run(args: Args, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let Args {
+        long,
+        branch,
+        ignored,
+        watch,
+    } = args;
+    ctx.repo.ensure_exists()?;
+    if watch {
+        return watch_status(ctx);
+    }
+    let files = get_repository_status(ignored, &ctx.root_path)?;
+    if long {
+        write!(ctx.stdout, "{}", status_header(&ctx.root_path, true)?)?;
+        let sections = [
+            (0, "Changes to be committed"),
+            (1, "Changes not staged for commit"),
+        ];
+        for (column, section) in sections {
+            let changes: Vec<_> = files
+                .lines()
+                .filter(|line| !line.starts_with("??") && !line.starts_with("!!"))
+                .filter_map(|line| Some((line.chars().nth(column)?, line.get(3..)?)))
+                .filter(|(letter, _)| *letter != ' ')
+                .collect();
+            if !changes.is_empty() {
+                writeln!(ctx.stdout, "\n{}:", section)?;
+                for (letter, path) in changes {
+                    writeln!(ctx.stdout, "\t{}{}", change_label(letter), path)?;
+                }
+            }
+        }
+        for (prefix, section) in [("?? ", "Untracked files"), ("!! ", "Ignored files")] {
+            let paths: Vec<_> = files
+                .lines()
+                .filter_map(|line| line.strip_prefix(prefix))
+                .collect();
+            if !paths.is_empty() {
+                writeln!(ctx.stdout, "\n{}:", section)?;
+                for path in paths {
+                    writeln!(ctx.stdout, "\t{}", path)?;
+                }
+            }
+        }
+    } else {
+        if branch {
+            write!(ctx.stdout, "{}", status_header(&ctx.root_path, false)?)?;
+        }
+        write!(ctx.stdout, "{}", files)?;
+    }
+
+    Ok(())
+}
+
+/// Show the status in full, then each change to it as it happens, until interrupted.
+#[cfg(not(target_os = "wasi"))]
+fn watch_status(ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let repo = Repository::open(&ctx.root_path);
+    let quote = quote_non_ascii(&repo.config()?)?;
+    let stream = StatusStream::new(repo)?;
+    let initial = initial_changes(stream.status());
+    write!(ctx.stdout, "{}", format_changes(&initial, quote))?;
+    ctx.stdout.flush()?;
+
+    for update in stream {
+        write!(ctx.stdout, "{}", format_changes(&update?.changes, quote))?;
+        ctx.stdout.flush()?;
+    }
+
+    Ok(())
+}
+
+#[cfg(target_os = "wasi")]
+fn watch_status(_ctx: &mut CommandContext) -> anyhow::Result<()> {
+    anyhow::bail!("status --watch is not supported on this platform")
+}
+
+/// Changes to the status as `status --short` lines, with a blank status for paths that are
+/// no longer changed.
+#[cfg(not(target_os = "wasi"))]
+pub fn format_changes(changes: &[StatusChange], quote_non_ascii: bool) -> String {
+    changes
+        .iter()
+        .map(|change| {
+            let code = change.code.as_deref().unwrap_or("  ");
+            let path = quote_bytes(change.path.as_bytes(), quote_non_ascii);
+            format!("{} {}\n", code, path)
+        })
+        .collect()
+}
+
+/// List the status of the working tree as `status --short` does: tracked files that have
+/// changed, with how the index differs from HEAD and then how the working tree differs from
+/// the index, like `M  path` or ` M path`, then files that aren't tracked, and with
+/// `show_ignored`, ignored ones. A tracked file whose size and times still match the index
+/// isn't read; one whose times alone differ is hashed to see whether its content has changed.
+pub fn get_repository_status(show_ignored: bool, root_path: &Path) -> anyhow::Result<String> {
+    let repo = Repository::open(root_path);
+    let quote = quote_non_ascii(&repo.config()?)?;
+    let status = repo.status()?;
+
+    let mut msg = String::new();
+    for (path, code) in status.tracked_codes() {
+        msg.push_str(&format!("{} {}\n", code, quote_path(&path, quote)));
+    }
+    for path in &status.untracked {
+        msg.push_str(&format!("?? {}\n", quote_bytes(path.as_bytes(), quote)));
+    }
+    if show_ignored {
+        for path in ignored_paths(&repo, root_path)? {
+            msg.push_str(&format!("!! {}\n", quote_bytes(path.as_bytes(), quote)));
+        }
+    }
+
+    Ok(msg)
+}
+
+/// The files the index doesn't track that are ignored. Files in an ignored directory are
+/// given as the directory, with a `/` after it.
+fn ignored_paths(repo: &Repository, root_path: &Path) -> anyhow::Result<BTreeSet<String>> {
+    let workspace = repo.workspace()?;
+    let mut index = repo.configured_index()?;
+    index.load()?;
+    let ignores = Ignores::load(root_path)?;
+
+    let mut ignored = BTreeSet::new();
+    for file in workspace.list_files_in_root()? {
+        if index.is_tracked(&file) {
+            continue;
+        }
+        if let Some(found) = ignores.matching(Path::new(&file), false) {
+            if !found.negated {
+                let mut path = found.path.to_string_lossy().into_owned();
+                if path != file {
+                    path.push('/');
+                }
+                ignored.insert(path);
+            }
+        }
+    }
+
+    Ok(ignored)
+}
+
+/// How the long format describes a change, given its letter in the short format, padded so
+/// that the paths after it line up.
+fn change_label(letter: char) -> &'static str {
+    match letter {
+        'A' => "new file:   ",
+        'D' => "deleted:    ",
+        'T' => "typechange: ",
+        _ => "modified:   ",
+    }
+}
+
+/// Describe the current branch and how it compares with its upstream, either as git's
+/// long format status does or as the `## branch...upstream [ahead N]` short format line.
+pub fn status_header(root_path: &Path, long: bool) -> anyhow::Result<String> {
+    let repo = Repository::new(git_dir(root_path));
+    let refs = repo.refs();
+    let head_oid = refs.read_head()?;
+    let current_ref = refs.current_ref()?;
+
+    let branch = match current_ref.strip_prefix("refs/heads/") {
+        Some(branch) => branch,
+        None => {
+            let short_oid = head_oid
+                .as_ref()
+                .map(|oid| oid.short(7))
+                .unwrap_or_default();
+            return Ok(match long {
+                true => format!("HEAD detached at {}\n", short_oid),
+                false => "## HEAD (no branch)\n".to_owned(),
+            });
+        }
+    };
+
+    if head_oid.is_none() {
+        return Ok(match long {
+            true => format!("On branch {}\n\nNo commits yet\n", branch),
+            false => format!("## No commits yet on {}\n", branch),
+        });
+    }
+
+    let divergence = repo.divergence(branch)?;
+
+    if long {
+        let mut msg = format!("On branch {}\n", branch);
+        if let Some(divergence) = divergence {
+            msg.push_str(&divergence_message(&divergence));
+        }
+        return Ok(msg);
+    }
+
+    Ok(match divergence {
+        Some(divergence) => {
+            let upstream = Refs::short_name(&divergence.upstream);
+            match divergence_counts(&divergence) {
+                Some(counts) => format!("## {}...{} [{}]\n", branch, upstream, counts),
+                None => format!("## {}...{}\n", branch, upstream),
+            }
+        }
+        None => format!("## {}\n", branch),
+    })
+}
+
+fn divergence_message(divergence: &Divergence) -> String {
+    let upstream = Refs::short_name(&divergence.upstream);
+
+    match divergence {
+        Divergence {
+            upstream_exists: false,
+            ..
+        } => format!(
+            "Your branch is based on '{}', but the upstream is gone.\n  \
+             (use \"nit branch --unset-upstream\" to fixup)\n",
+            upstream
+        ),
+        Divergence {
+            ahead: 0,
+            behind: 0,
+            ..
+        } => format!("Your branch is up to date with '{}'.\n", upstream),
+        Divergence {
+            ahead, behind: 0, ..
+        } => format!(
+            "Your branch is ahead of '{}' by {} commit{}.\n  \
+             (use \"nit push\" to publish your local commits)\n",
+            upstream,
+            ahead,
+            plural(*ahead)
+        ),
+        Divergence {
+            ahead: 0, behind, ..
+        } => format!(
+            "Your branch is behind '{}' by {} commit{}, and can be fast-forwarded.\n  \
+             (use \"nit pull\" to update your local branch)\n",
+            upstream,
+            behind,
+            plural(*behind)
+        ),
+        Divergence { ahead, behind, .. } => format!(
+            "Your branch and '{}' have diverged,\n\
+             and have {} and {} different commits each, respectively.\n  \
+             (use \"nit pull\" to merge the remote branch into yours)\n",
+            upstream, ahead, behind
+        ),
+    }
+}
+
+/// The `ahead 2, behind 1` summary shown by `status -b` and `branch -v`, if the branch
+/// isn't level with its upstream.
+pub fn divergence_counts(divergence: &Divergence) -> Option<String> {
+    if !divergence.upstream_exists {
+        return Some("gone".to_owned());
+    }
+
+    match (divergence.ahead, divergence.behind) {
+        (0, 0) => None,
+        (ahead, 0) => Some(format!("ahead {}", ahead)),
+        (0, behind) => Some(format!("behind {}", behind)),
+        (ahead, behind) => Some(format!("ahead {}, behind {}", ahead, behind)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::test::{init, write_and_commit};
+    use crate::commands::{add::*, diff_files::*};
+    use nit::index::Index;
+    use std::fs::File;
+    use std::io::Write;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn lists_untracked_files_in_name_order() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        let file_path = &tmp_path.join("hello.txt");
+        let mut file = File::create(file_path).unwrap();
+        file.write_all("Hello, world".as_bytes()).unwrap();
+
+        let file_path = &tmp_path.join("goodbye.txt");
+        let mut file = File::create(file_path).unwrap();
+        file.write_all("Hello, world".as_bytes()).unwrap();
+
+        let status = get_repository_status(false, tmp_path).unwrap();
+
+        assert_eq!(status, "?? goodbye.txt\n?? hello.txt\n");
+    }
+
+    #[test]
+    fn lists_tracked_files_that_have_changed() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+        write_and_commit(
+            tmp_path,
+            &[("a.txt", "one\n"), ("b.txt", "two\n"), ("c.txt", "three\n")],
+            "First",
+        );
+        assert_eq!(get_repository_status(false, tmp_path).unwrap(), "");
+
+        // The same size, so only the content gives it away.
+        std::fs::write(tmp_path.join("a.txt"), "ONE\n").unwrap();
+        std::fs::remove_file(tmp_path.join("b.txt")).unwrap();
+        // Rewritten with the same content, so it's only its times that differ.
+        std::fs::write(tmp_path.join("c.txt"), "three\n").unwrap();
+        std::fs::write(tmp_path.join(".gitignore"), "*.txt\n").unwrap();
+
+        assert_eq!(
+            get_repository_status(false, tmp_path).unwrap(),
+            " M a.txt\n D b.txt\n?? .gitignore\n"
+        );
+    }
+
+    #[test]
+    fn lists_changes_staged_since_head() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+        std::fs::write(tmp_path.join("new.txt"), "new\n").unwrap();
+        add_files_to_repository(vec![&tmp_path.join("new.txt")], tmp_path).unwrap();
+        // With no commits yet, everything in the index is new.
+        assert_eq!(
+            get_repository_status(false, tmp_path).unwrap(),
+            "A  new.txt\n"
+        );
+
+        write_and_commit(tmp_path, &[("a.txt", "one\n"), ("b.txt", "two\n")], "First");
+        std::fs::write(tmp_path.join("a.txt"), "ONE\n").unwrap();
+        std::fs::write(tmp_path.join("c.txt"), "three\n").unwrap();
+        let (a, c) = (tmp_path.join("a.txt"), tmp_path.join("c.txt"));
+        add_files_to_repository(vec![&a, &c], tmp_path).unwrap();
+        std::fs::write(tmp_path.join("a.txt"), "one more\n").unwrap();
+        let repo = Repository::open(tmp_path);
+        let mut index = repo.index();
+        index.load_for_update().unwrap();
+        index.remove(&"b.txt");
+        index.write_updates().unwrap();
+
+        assert_eq!(
+            get_repository_status(false, tmp_path).unwrap(),
+            "MM a.txt\nD  b.txt\nA  c.txt\n?? b.txt\n"
+        );
+    }
+
+    #[test]
+    fn quotes_unusual_paths_as_core_quote_path_says() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+        write_and_commit(tmp_path, &[("café.txt", "one\n")], "First");
+        std::fs::write(tmp_path.join("café.txt"), "two\n").unwrap();
+        std::fs::write(tmp_path.join("tab\there"), "new\n").unwrap();
+
+        assert_eq!(
+            get_repository_status(false, tmp_path).unwrap(),
+            " M \"caf\\303\\251.txt\"\n?? \"tab\\there\"\n"
+        );
+        assert_eq!(
+            diff_files(true, tmp_path).unwrap(),
+            "M\t\"caf\\303\\251.txt\"\n"
+        );
+
+        std::fs::write(
+            tmp_path.join(".git/config"),
+            "[core]\n\tquotePath = false\n",
+        )
+        .unwrap();
+        assert_eq!(
+            get_repository_status(false, tmp_path).unwrap(),
+            " M café.txt\n?? \"tab\\there\"\n"
+        );
+        assert_eq!(diff_files(true, tmp_path).unwrap(), "M\tcafé.txt\n");
+    }
+
+    #[test]
+    fn follows_the_status_as_files_change() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+        write_and_commit(tmp_path, &[("a.txt", "one\n")], "First");
+
+        let mut stream = StatusStream::new(Repository::open(tmp_path)).unwrap();
+        assert_eq!(initial_changes(stream.status()), vec![]);
+        let mut next_update = || {
+            stream
+                .next_update(Some(std::time::Duration::from_secs(5)))
+                .unwrap()
+                .map(|update| format_changes(&update.changes, true))
+        };
+
+        std::fs::write(tmp_path.join("a.txt"), "two\n").unwrap();
+        std::fs::write(tmp_path.join("b.txt"), "new\n").unwrap();
+        assert_eq!(next_update().unwrap(), " M a.txt\n?? b.txt\n");
+
+        std::fs::write(tmp_path.join("a.txt"), "one\n").unwrap();
+        assert_eq!(next_update().unwrap(), "   a.txt\n");
+
+        // Nothing in .git but the index and refs can change the status.
+        std::fs::write(tmp_path.join(".git/description"), "watched\n").unwrap();
+        assert_eq!(next_update(), None);
+    }
+
+    #[test]
+    fn only_looks_at_what_the_fsmonitor_hook_reports() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+        write_and_commit(tmp_path, &[("a.txt", "a"), ("b.txt", "b")], "First");
+
+        // The hook logs the tokens it's given, and reports whatever is in `.git/changed`.
+        let hook = tmp_path.join(".git/hook.sh");
+        std::fs::write(
+            &hook,
+            "#!/bin/sh\necho \"$2\" >> .git/hook.log\nprintf 'token%s\\0' $(wc -l < .git/hook.log)\n\
+             tr '\\n' '\\0' < .git/changed\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&hook, std::fs::Permissions::from_mode(0o755)).unwrap();
+        std::fs::write(
+            tmp_path.join(".git/config"),
+            format!("[core]\n\tfsmonitor = {}\n", hook.display()),
+        )
+        .unwrap();
+        let report = |paths: &str| std::fs::write(tmp_path.join(".git/changed"), paths).unwrap();
+        let status = || get_repository_status(false, tmp_path).unwrap();
+
+        report("");
+        assert_eq!(status(), "");
+
+        // A change the hook doesn't report isn't looked for...
+        std::fs::write(tmp_path.join("a.txt"), "A").unwrap();
+        assert_eq!(status(), "");
+        report("a.txt\n");
+        assert_eq!(status(), " M a.txt\n");
+
+        // ...and a file that's only been touched has its entry refreshed, which doesn't
+        // stop the hook being trusted next time.
+        std::fs::write(tmp_path.join("b.txt"), "b").unwrap();
+        report("b.txt\n");
+        assert_eq!(status(), " M a.txt\n");
+        let mut index = Index::new(tmp_path.join(".git/index"));
+        index.load().unwrap();
+        let stat = std::fs::symlink_metadata(tmp_path.join("b.txt")).unwrap();
+        assert!(index.entries()[Path::new("b.txt")].times_match(&stat.into()));
+
+        std::fs::write(tmp_path.join("b.txt"), "B").unwrap();
+        report("");
+        assert_eq!(status(), " M a.txt\n");
+
+        assert_eq!(
+            std::fs::read_to_string(tmp_path.join(".git/hook.log")).unwrap(),
+            "\ntoken1\ntoken2\ntoken3\ntoken4\n"
+        );
+    }
+}
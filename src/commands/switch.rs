@@ -0,0 +1,123 @@
+use anyhow::anyhow;
+use nit::{
+    refs::{Refs, HEAD},
+    repository::git_dir,
+};
+use std::path::Path;
+use structopt::StructOpt;
+
+use super::checkout::{checkout, CheckoutMode, NewBranch};
+use super::CommandContext;
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Create a new branch at <revision> (or HEAD) and switch to it
+    #[structopt(short = "c", long = "create")]
+    create: Option<String>,
+    /// Switch to a commit for inspection, leaving HEAD detached
+    #[structopt(short = "d", long = "detach", conflicts_with = "create")]
+    detach: bool,
+    /// Set up the new branch to track the branch it starts from
+    #[structopt(short = "t", long = "track", requires = "create")]
+    track: bool,
+    revision: Option<String>,
+}
+
+pub fn run(args: Args, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let Args {
+        create,
+        detach,
+        track,
+        revision,
+    } = args;
+    let msg = switch(
+        revision.as_deref(),
+        create.as_deref(),
+        detach,
+        track,
+        &ctx.root_path,
+    )?;
+    write!(ctx.info_stderr(), "{}", msg)?;
+
+    Ok(())
+}
+
+pub fn switch(
+    revision: Option<&str>,
+    create: Option<&str>,
+    detach: bool,
+    track: bool,
+    root_path: &Path,
+) -> anyhow::Result<String> {
+    let mode = match (create, detach) {
+        (Some(name), _) => CheckoutMode::NewBranch(NewBranch { name, track }),
+        (None, true) => CheckoutMode::Detach,
+        (None, false) => CheckoutMode::Auto,
+    };
+
+    let revision = match (revision, &mode) {
+        (Some(revision), _) => revision,
+        (None, CheckoutMode::Auto) => return Err(anyhow!("missing branch or commit argument")),
+        (None, _) => HEAD,
+    };
+
+    // Without --detach or --create, switch only moves between branches.
+    if let CheckoutMode::Auto = mode {
+        let refs = Refs::new(&git_dir(root_path));
+        let is_branch = refs
+            .expand_name(revision)
+            .is_some_and(|name| name.starts_with("refs/heads/"));
+
+        if !is_branch {
+            return Err(anyhow!(
+                "a branch is expected, got '{}'\n\
+                 If you want to detach HEAD at the commit, try again with the --detach option.",
+                revision
+            ));
+        }
+    }
+
+    checkout(revision, mode, root_path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::branch::create_branch;
+    use crate::commands::test::{init, pack_refs, write_and_commit};
+
+    #[test]
+    fn switches_only_to_branches_unless_detaching() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        write_and_commit(tmp_path, &[("hello.txt", "one")], "First");
+        write_and_commit(tmp_path, &[("hello.txt", "two")], "Second");
+
+        assert!(switch(Some("HEAD^"), None, false, false, tmp_path).is_err());
+
+        let msg = switch(Some("master"), None, true, false, tmp_path).unwrap();
+        assert!(msg.starts_with("Note: checking out 'master'."));
+        assert!(!std::fs::read_to_string(tmp_path.join(".git").join("HEAD"))
+            .unwrap()
+            .starts_with("ref: "));
+
+        let msg = switch(Some("master"), None, false, false, tmp_path).unwrap();
+        assert!(msg.ends_with("Switched to branch 'master'\n"));
+    }
+
+    #[test]
+    fn switches_to_packed_branches() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        write_and_commit(tmp_path, &[("hello.txt", "one")], "First");
+        create_branch("topic", None, false, tmp_path).unwrap();
+        pack_refs(tmp_path);
+
+        let msg = switch(Some("topic"), None, false, false, tmp_path).unwrap();
+        assert!(msg.ends_with("Switched to branch 'topic'\n"));
+        let refs = Refs::new(&tmp_path.join(".git"));
+        assert_eq!(refs.current_ref().unwrap(), "refs/heads/topic");
+    }
+}
@@ -0,0 +1,89 @@
+use anyhow::anyhow;
+use nit::{refs::Refs, repository::git_dir};
+use std::path::Path;
+use structopt::StructOpt;
+
+use super::{CommandContext, ExitStatus};
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Print nothing if the ref isn't a symbolic ref, just exit with status 1
+    #[structopt(short = "q", long = "quiet")]
+    quiet: bool,
+    /// Print the short name of the ref pointed at, e.g. `master` for `refs/heads/master`
+    #[structopt(long = "short")]
+    short: bool,
+    /// The symbolic ref, e.g. HEAD
+    name: String,
+    /// The ref to point it at, which must start with `refs/`
+    target: Option<String>,
+}
+
+pub fn run(args: Args, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let Args {
+        quiet,
+        short,
+        name,
+        target,
+    } = args;
+    match target {
+        Some(target) => set_symbolic_ref(&name, &target, &ctx.root_path)?,
+        None => match read_symbolic_ref(&name, short, &ctx.root_path)? {
+            Some(target) => writeln!(ctx.stdout, "{}", target)?,
+            None if quiet => return Err(ExitStatus::Failure.into()),
+            None => return Err(anyhow!("ref {} is not a symbolic ref", name)),
+        },
+    };
+
+    Ok(())
+}
+
+/// The ref a symbolic ref points at, shortened with `short`, or `None` if the ref isn't a
+/// symbolic ref.
+pub fn read_symbolic_ref(
+    name: &str,
+    short: bool,
+    root_path: &Path,
+) -> anyhow::Result<Option<String>> {
+    let refs = Refs::new(&git_dir(root_path));
+
+    Ok(refs.read_symref(name)?.map(|target| match short {
+        true => Refs::short_name(&target).to_owned(),
+        false => target,
+    }))
+}
+
+/// Point a symbolic ref at another ref.
+pub fn set_symbolic_ref(name: &str, target: &str, root_path: &Path) -> anyhow::Result<()> {
+    let refs = Refs::new(&git_dir(root_path));
+    Ok(refs.set_symref(name, target)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::test::{init, write_and_commit};
+
+    #[test]
+    fn reads_and_sets_symbolic_refs() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        write_and_commit(tmp_path, &[("a.txt", "a")], "First");
+        let symref = |short| read_symbolic_ref("HEAD", short, tmp_path).unwrap();
+        assert_eq!(symref(false).as_deref(), Some("refs/heads/master"));
+        assert_eq!(symref(true).as_deref(), Some("master"));
+
+        set_symbolic_ref("HEAD", "refs/heads/main", tmp_path).unwrap();
+        assert_eq!(symref(false).as_deref(), Some("refs/heads/main"));
+        assert!(set_symbolic_ref("HEAD", "main", tmp_path).is_err());
+
+        let refs = Refs::new(&tmp_path.join(".git"));
+        assert_eq!(refs.read_head().unwrap(), None);
+        set_symbolic_ref("HEAD", "refs/heads/master", tmp_path).unwrap();
+        let head = refs.read_head().unwrap().unwrap();
+        refs.detach_head(&head, "checkout: moving from master to HEAD")
+            .unwrap();
+        assert_eq!(symref(false), None);
+    }
+}
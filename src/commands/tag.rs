@@ -0,0 +1,159 @@
+use anyhow::anyhow;
+use nit::{
+    database::ParsedObject,
+    merge::{self},
+    ref_format::{self, SortKey},
+    refs::HEAD,
+    repository::{git_dir, Repository},
+    revision::Revision,
+};
+use std::path::Path;
+use structopt::StructOpt;
+
+use super::CommandContext;
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// List tags matching the given patterns, e.g. "v1.*", rather than creating one
+    #[structopt(short = "l", long = "list")]
+    list: bool,
+    /// A field to sort the listing by, such as "v:refname" to sort tag names as versions
+    #[structopt(long = "sort", number_of_values = 1)]
+    sort: Vec<SortKey>,
+    /// Only list tags that contain the given commit
+    #[structopt(long = "contains", value_name = "commit")]
+    contains: Option<String>,
+    /// The name of the tag to create and the commit to point it at (HEAD by default), or
+    /// the patterns to list
+    args: Vec<String>,
+}
+
+pub fn run(args: Args, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let Args {
+        list,
+        sort,
+        contains,
+        args,
+    } = args;
+    if list || contains.is_some() || args.is_empty() {
+        let msg = list_tags(&args, &sort, contains.as_deref(), &ctx.root_path)?;
+        write!(ctx.stdout, "{}", msg)?;
+    } else {
+        match args.as_slice() {
+            [name] => create_tag(name, None, &ctx.root_path)?,
+            [name, commit] => create_tag(name, Some(commit), &ctx.root_path)?,
+            _ => return Err(anyhow!("too many arguments")),
+        }
+    }
+
+    Ok(())
+}
+
+/// Create a lightweight tag pointing at `commit`, or at HEAD.
+pub fn create_tag(name: &str, commit: Option<&str>, root_path: &Path) -> anyhow::Result<()> {
+    let repo = Repository::new(git_dir(root_path));
+    let oid = Revision::resolve_object(commit.unwrap_or(HEAD), repo.database(), repo.refs())?;
+
+    Ok(repo.refs().create_tag(name, &oid)?)
+}
+
+/// List the names of the tags matching any of the patterns, and containing `contains` if
+/// it's given, sorted by name unless other keys are given.
+pub fn list_tags(
+    patterns: &[String],
+    sort: &[SortKey],
+    contains: Option<&str>,
+    root_path: &Path,
+) -> anyhow::Result<String> {
+    let repo = Repository::new(git_dir(root_path));
+    let database = repo.database();
+    let contains = contains
+        .map(|commit| Revision::resolve(commit, database, repo.refs()))
+        .transpose()?;
+
+    let mut tags = Vec::new();
+    for tag in repo.refs().list(Some("refs/tags/"))? {
+        let matches = patterns.is_empty()
+            || patterns.iter().any(|pattern| {
+                ref_format::pattern_matches(&format!("refs/tags/{}", pattern), &tag.name)
+            });
+        if !matches {
+            continue;
+        }
+
+        if let (Some(commit), Some(oid)) = (&contains, &tag.oid) {
+            // An annotated tag contains whatever the commit it's a tag of does.
+            let oid = database.peel(oid)?;
+            let is_commit = matches!(database.load(&oid)?, ParsedObject::Commit(_));
+            if !is_commit || !merge::is_ancestor(database, commit, &oid)? {
+                continue;
+            }
+        }
+        tags.push(tag);
+    }
+
+    let format = "%(refname:short)".parse()?;
+    let sort: Vec<_> = sort.iter().rev().copied().collect();
+    Ok(ref_format::format_refs(tags, &format, &sort, database)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::test::{annotate_tag, init, write_and_commit};
+
+    #[test]
+    fn lists_tags_by_pattern_version_and_commit() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        write_and_commit(tmp_path, &[("a.txt", "a")], "First");
+        create_tag("v1.9", None, tmp_path).unwrap();
+        create_tag("v1.10", None, tmp_path).unwrap();
+        write_and_commit(tmp_path, &[("a.txt", "b")], "Second");
+        create_tag("v2.0", None, tmp_path).unwrap();
+        create_tag("nightly", Some("HEAD~1"), tmp_path).unwrap();
+        assert!(create_tag("v2.0", None, tmp_path).is_err());
+
+        assert_eq!(
+            list_tags(&[], &[], None, tmp_path).unwrap(),
+            "nightly\nv1.10\nv1.9\nv2.0\n"
+        );
+
+        let by_version = ["v:refname".parse().unwrap()];
+        assert_eq!(
+            list_tags(&["v*".to_owned()], &by_version, None, tmp_path).unwrap(),
+            "v1.9\nv1.10\nv2.0\n"
+        );
+
+        assert_eq!(
+            list_tags(&[], &[], Some("HEAD"), tmp_path).unwrap(),
+            "v2.0\n"
+        );
+        assert_eq!(
+            list_tags(&["v1.*".to_owned()], &[], Some("HEAD~1"), tmp_path).unwrap(),
+            "v1.10\nv1.9\n"
+        );
+    }
+
+    #[test]
+    fn lists_annotated_tags_containing_a_commit() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        write_and_commit(tmp_path, &[("a.txt", "a")], "First");
+        annotate_tag(tmp_path, "v1.0", "HEAD", "Version 1.0");
+        write_and_commit(tmp_path, &[("a.txt", "b")], "Second");
+        annotate_tag(tmp_path, "v2.0", "HEAD", "Version 2.0");
+        annotate_tag(tmp_path, "v2.0-signed", "v2.0", "Signed 2.0");
+
+        assert_eq!(
+            list_tags(&[], &[], Some("HEAD~1"), tmp_path).unwrap(),
+            "v1.0\nv2.0\nv2.0-signed\n"
+        );
+        assert_eq!(
+            list_tags(&[], &[], Some("HEAD"), tmp_path).unwrap(),
+            "v2.0\nv2.0-signed\n"
+        );
+    }
+}
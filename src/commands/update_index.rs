@@ -0,0 +1,302 @@
+use anyhow::anyhow;
+use nit::{
+    database::{DatabaseEntry, ObjectId},
+    index::entry,
+    inspector::Inspector,
+    lockfile::LockfileError,
+    repository::Repository,
+};
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+
+use super::{workspace_paths, CommandContext, ExitStatus};
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Let files that aren't in the index yet be added
+    #[structopt(long = "add")]
+    add: bool,
+    /// Remove the entries of files that are missing from the working tree
+    #[structopt(long = "remove")]
+    remove: bool,
+    /// Bring the stat information of unchanged files' entries up to date, listing the
+    /// files that have changed
+    #[structopt(long = "refresh")]
+    refresh: bool,
+    /// Add an entry for an object directly, without looking at the working tree. Can be
+    /// given more than once
+    #[structopt(
+        long = "cacheinfo",
+        value_name = "mode>,<object>,<path",
+        number_of_values = 1
+    )]
+    cacheinfo: Vec<String>,
+    /// Set or clear the executable bit of the files' entries
+    #[structopt(long = "chmod", value_name = "(+|-)x", allow_hyphen_values = true)]
+    chmod: Option<String>,
+    /// The files whose entries to update from the working tree
+    paths: Vec<String>,
+}
+
+pub fn run(args: Args, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let Args {
+        add,
+        remove,
+        refresh,
+        cacheinfo,
+        chmod,
+        paths,
+    } = args;
+    let chmod = match chmod.as_deref() {
+        Some("+x") => Some(true),
+        Some("-x") => Some(false),
+        Some(_) => return Err(anyhow!("option 'chmod' expects \"+x\" or \"-x\"")),
+        None => None,
+    };
+    let options = UpdateIndexOptions {
+        add,
+        remove,
+        refresh,
+        chmod,
+    };
+    let paths: Vec<_> = paths.iter().map(Path::new).collect();
+    let needs_update = update_index(&cacheinfo, &paths, &options, &ctx.root_path)?;
+    for path in &needs_update {
+        writeln!(ctx.stdout, "{}: needs update", path.display())?;
+    }
+    if !needs_update.is_empty() {
+        return Err(ExitStatus::Failure.into());
+    }
+
+    Ok(())
+}
+
+/// How `update-index` changes the entries it's given.
+pub struct UpdateIndexOptions {
+    pub add: bool,
+    pub remove: bool,
+    pub refresh: bool,
+    /// Set the executable bit of each file's entry, or clear it.
+    pub chmod: Option<bool>,
+}
+
+/// Change the index directly, as scripts need to: bring the stat information of entries
+/// for unchanged files up to date, add entries for objects given as
+/// `<mode>,<object>,<path>`, then update the entries of files from the working tree. Unlike
+/// `add`, files aren't added or removed unless asked. Returns the files that refreshing
+/// found had changed.
+pub fn update_index(
+    cacheinfo: &[String],
+    paths: &[&Path],
+    options: &UpdateIndexOptions,
+    root_path: &Path,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let repo = Repository::open(root_path);
+    let mut index = repo.configured_index()?;
+    let workspace = repo.workspace()?;
+    let database = repo.database();
+
+    (|| -> anyhow::Result<Vec<PathBuf>> {
+        index.load_for_update()?;
+
+        let mut needs_update = Vec::new();
+        if options.refresh {
+            let inspector = Inspector::new(database, &index, &workspace);
+            let mut refreshed = Vec::new();
+            for (path, entry) in index.entries() {
+                if entry.skip_worktree() {
+                    continue;
+                }
+                let stat = workspace.try_stat_file(path)?;
+                match (
+                    inspector.compare_index_to_workspace(Some(entry), stat.as_ref())?,
+                    stat,
+                ) {
+                    (Some(_), _) => needs_update.push(path.clone()),
+                    (None, Some(stat)) if !entry.times_match(&stat) => {
+                        refreshed.push((path.clone(), stat))
+                    }
+                    (None, _) => {}
+                }
+            }
+            for (path, stat) in refreshed {
+                index.update_entry_stat(&path, &stat);
+            }
+        }
+
+        for info in cacheinfo {
+            let (mode, oid, path) = parse_cacheinfo(info)?;
+            if !entry::is_valid_path(path.as_bytes())
+                || (!options.add && index.entry_for_path(&path).is_none())
+            {
+                return Err(anyhow!("--cacheinfo cannot add {}", path));
+            }
+            index.add_from_db(&path, &DatabaseEntry::new(oid, mode));
+        }
+
+        let mut to_add = Vec::new();
+        for path in workspace_paths(paths, root_path)? {
+            match workspace.try_stat_file(&path)? {
+                None if options.remove => index.remove(&path),
+                None => {
+                    return Err(anyhow!(
+                        "{}: does not exist and --remove not passed",
+                        path.display()
+                    ))
+                }
+                Some(stat) if stat.is_dir() => {
+                    return Err(anyhow!(
+                        "{}: is a directory - add individual files instead",
+                        path.display()
+                    ))
+                }
+                Some(_) if !options.add && index.entry_for_path(&path).is_none() => {
+                    return Err(anyhow!(
+                        "{}: cannot add to the index - missing --add option?",
+                        path.display()
+                    ))
+                }
+                Some(_) => to_add.push(path),
+            }
+        }
+        repo.add_to_index(&mut index, &workspace, &to_add)?;
+
+        if let Some(executable) = options.chmod {
+            for path in &to_add {
+                let mode = match index.entry_for_path(path).map(|entry| entry.mode()) {
+                    Some(0o100644) | Some(0o100755) if executable => 0o100755,
+                    Some(0o100644) | Some(0o100755) => 0o100644,
+                    _ => {
+                        let flip = if executable { '+' } else { '-' };
+                        return Err(anyhow!("cannot chmod {}x '{}'", flip, path.display()));
+                    }
+                };
+                index.set_mode(path, mode);
+            }
+        }
+
+        index.write_updates()?;
+        Ok(needs_update)
+    })()
+    .or_else(|e| {
+        if let Some(nit::Error::Lockfile(LockfileError::LockDenied(_))) = e.downcast_ref() {
+            // We couldn't get the lock, so leave it in place.
+        } else {
+            index.lockfile_mut().rollback()?;
+        }
+
+        Err(e)
+    })
+}
+
+/// Parse `update-index --cacheinfo`'s `<mode>,<object>,<path>`. The mode is made into one
+/// the index can hold, as git does: a symlink, a submodule for a directory, or a file
+/// that's executable or not.
+fn parse_cacheinfo(info: &str) -> anyhow::Result<(u32, ObjectId, &str)> {
+    let invalid = || anyhow!("option 'cacheinfo' expects <mode>,<sha1>,<path>");
+    let mut parts = info.splitn(3, ',');
+    let (mode, oid, path) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(mode), Some(oid), Some(path)) => (mode, oid, path),
+        _ => return Err(invalid()),
+    };
+    let mode = u32::from_str_radix(mode, 8).map_err(|_| invalid())?;
+    let oid = ObjectId::from_hex(oid).map_err(|_| invalid())?;
+
+    let mode = match mode & 0o170000 {
+        0o120000 => 0o120000,
+        0o040000 | 0o160000 => 0o160000,
+        _ if mode & 0o100 != 0 => 0o100755,
+        _ => 0o100644,
+    };
+    Ok((mode, oid, path))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::test::{init, write_and_commit};
+    use nit::{
+        database::{Blob, Database},
+        index::Index,
+    };
+
+    #[test]
+    fn updates_index_entries_directly() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+        write_and_commit(tmp_path, &[("a.txt", "a"), ("b.txt", "b")], "First");
+
+        let options = UpdateIndexOptions {
+            add: false,
+            remove: false,
+            refresh: false,
+            chmod: None,
+        };
+        let entries = || {
+            let mut index = Index::new(tmp_path.join(".git").join("index"));
+            index.load().unwrap();
+            index
+                .entries()
+                .values()
+                .map(|entry| (entry.path().to_string_lossy().into_owned(), entry.mode()))
+                .collect::<Vec<_>>()
+        };
+
+        // New and deleted files are left alone unless asked for.
+        std::fs::write(tmp_path.join("c.txt"), "c").unwrap();
+        let c = tmp_path.join("c.txt");
+        let err = update_index(&[], &[&c], &options, tmp_path).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "c.txt: cannot add to the index - missing --add option?"
+        );
+        std::fs::remove_file(tmp_path.join("b.txt")).unwrap();
+        let b = tmp_path.join("b.txt");
+        let err = update_index(&[], &[&b], &options, tmp_path).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "b.txt: does not exist and --remove not passed"
+        );
+
+        let options = UpdateIndexOptions {
+            add: true,
+            remove: true,
+            chmod: Some(true),
+            ..options
+        };
+        update_index(&[], &[&b, &c], &options, tmp_path).unwrap();
+        assert_eq!(
+            entries(),
+            vec![
+                ("a.txt".to_owned(), 0o100644),
+                ("c.txt".to_owned(), 0o100755)
+            ]
+        );
+
+        let blob = Database::new(tmp_path.join(".git").join("objects"))
+            .hash_object(&Blob::new(b"a".to_vec()))
+            .unwrap();
+        let cacheinfo = [format!("100600,{},d/e.txt", blob)];
+        update_index(&cacheinfo, &[], &options, tmp_path).unwrap();
+        assert_eq!(entries()[1], ("c.txt".to_owned(), 0o100755));
+        assert_eq!(entries()[2], ("d/e.txt".to_owned(), 0o100644));
+
+        // Refreshing lists the files that have changed, or that aren't there. c.txt's entry
+        // is executable but the file isn't, which counts as a change.
+        std::fs::write(tmp_path.join("a.txt"), "a2").unwrap();
+        let options = UpdateIndexOptions {
+            refresh: true,
+            chmod: None,
+            ..options
+        };
+        let needs_update = update_index(&[], &[], &options, tmp_path).unwrap();
+        assert_eq!(
+            needs_update,
+            vec![
+                PathBuf::from("a.txt"),
+                PathBuf::from("c.txt"),
+                PathBuf::from("d/e.txt")
+            ]
+        );
+    }
+}
@@ -0,0 +1,123 @@
+use anyhow::anyhow;
+use chrono::Local;
+use nit::repository::Repository;
+use std::path::Path;
+use structopt::StructOpt;
+
+use super::commit::{committer_identity, resolve_identity};
+use super::{editor, CommandContext, Env};
+
+/// The variables `var` knows about, in the order `--list` shows them.
+const VARIABLES: &[&str] = &["GIT_AUTHOR_IDENT", "GIT_COMMITTER_IDENT", "GIT_EDITOR"];
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Show every variable, and its value, as `NAME=value`
+    #[structopt(short = "l", long = "list", conflicts_with = "variable")]
+    list: bool,
+    /// The variable to show: GIT_AUTHOR_IDENT, GIT_COMMITTER_IDENT or GIT_EDITOR
+    #[structopt(required_unless = "list")]
+    variable: Option<String>,
+}
+
+pub fn run(args: Args, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let Args { list, variable } = args;
+    match variable {
+        Some(variable) => {
+            let value = var(&variable, &ctx.env, &ctx.root_path)?;
+            writeln!(ctx.stdout, "{}", value)?;
+        }
+        None if list => {
+            for variable in VARIABLES {
+                let value = var(variable, &ctx.env, &ctx.root_path)?;
+                writeln!(ctx.stdout, "{}={}", variable, value)?;
+            }
+        }
+        None => {}
+    }
+
+    Ok(())
+}
+
+/// The value nit would use for one of git's logical variables, once the environment, the
+/// config and the defaults have all been taken into account: who commits are by, as `Name
+/// <email> timestamp offset` for now, or the editor that messages are edited in. Outside a
+/// repository, there's no config to look at.
+pub fn var(variable: &str, env: &Env, root_path: &Path) -> anyhow::Result<String> {
+    let repo = Repository::open(root_path);
+    let config = match repo.ensure_exists() {
+        Ok(()) => Some(repo.config()?),
+        Err(_) => None,
+    };
+    let config = config.as_ref();
+
+    Ok(match variable {
+        "GIT_AUTHOR_IDENT" => resolve_identity(env, config, "AUTHOR")?
+            .at(Local::now())
+            .to_string(),
+        "GIT_COMMITTER_IDENT" => committer_identity(env, config)?
+            .at(Local::now())
+            .to_string(),
+        "GIT_EDITOR" => editor(env, config, &["GIT_EDITOR"])?,
+        _ => return Err(anyhow!("unknown variable: {}", variable)),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::test::init;
+
+    #[test]
+    fn resolves_identities_and_the_editor_for_var() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+        let ident = |variable: &str, env: &Env| {
+            let value = var(variable, env, tmp_path).map_err(|e| e.to_string())?;
+            // Leave off the timestamp, which is whenever it's asked for.
+            Ok::<_, String>(value.rsplitn(3, ' ').last().unwrap().to_owned())
+        };
+
+        let env = Env::default();
+        assert_eq!(
+            ident("GIT_AUTHOR_IDENT", &env).unwrap_err(),
+            "author identity unknown: set GIT_AUTHOR_NAME or user.name"
+        );
+        assert_eq!(var("GIT_EDITOR", &env, tmp_path).unwrap(), "vi");
+
+        std::fs::write(
+            tmp_path.join(".git/config"),
+            "[user]\n\tname = U. Ser\n\temail = user@example.com\n\
+             [committer]\n\temail = committer@example.com\n[core]\n\teditor = nano\n",
+        )
+        .unwrap();
+        assert_eq!(
+            ident("GIT_AUTHOR_IDENT", &env).unwrap(),
+            "U. Ser <user@example.com>"
+        );
+        assert_eq!(
+            ident("GIT_COMMITTER_IDENT", &env).unwrap(),
+            "U. Ser <committer@example.com>"
+        );
+        assert_eq!(var("GIT_EDITOR", &env, tmp_path).unwrap(), "nano");
+
+        // The environment wins over the config.
+        let env: Env = vec![
+            ("GIT_AUTHOR_NAME", "A. U. Thor"),
+            ("GIT_COMMITTER_EMAIL", "c@example.com"),
+            ("GIT_EDITOR", "ed"),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(
+            ident("GIT_AUTHOR_IDENT", &env).unwrap(),
+            "A. U. Thor <user@example.com>"
+        );
+        assert_eq!(
+            ident("GIT_COMMITTER_IDENT", &env).unwrap(),
+            "U. Ser <c@example.com>"
+        );
+        assert_eq!(var("GIT_EDITOR", &env, tmp_path).unwrap(), "ed");
+        assert!(var("GIT_PAGER", &env, tmp_path).is_err());
+    }
+}
@@ -0,0 +1,79 @@
+use anyhow::Context;
+use nit::pack::{Pack, PackIndex};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+
+use super::CommandContext;
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// List every object in each pack, and how long its chains of deltas are
+    #[structopt(short = "v", long = "verbose")]
+    verbose: bool,
+    /// The indexes to check
+    #[structopt(required = true)]
+    paths: Vec<PathBuf>,
+}
+
+pub fn run(args: Args, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let Args { verbose, paths } = args;
+    for path in paths {
+        let msg = verify_pack(&path, verbose)?;
+        write!(ctx.stdout, "{}", msg)?;
+    }
+
+    Ok(())
+}
+
+/// Check a pack against its index. With `verbose`, list each object as
+/// `oid type size size-in-pack offset [depth base]`, followed by how many objects have
+/// delta chains of each length.
+pub fn verify_pack(path: &Path, verbose: bool) -> anyhow::Result<String> {
+    let pack_path = path.with_extension("pack");
+    let read = |path: PathBuf| {
+        fs::read(&path).with_context(|| format!("Could not read {}", path.display()))
+    };
+
+    let index = PackIndex::parse(&read(path.with_extension("idx"))?)?;
+    let pack = Pack::parse(&read(pack_path.clone())?)?;
+    index
+        .verify(&pack)
+        .with_context(|| format!("{}: bad", pack_path.display()))?;
+
+    if !verbose {
+        return Ok(String::new());
+    }
+
+    let mut msg = String::new();
+    let mut chains = BTreeMap::new();
+
+    for object in pack.objects() {
+        msg.push_str(&format!(
+            "{} {:<6} {} {} {}",
+            object.oid, object.kind, object.size, object.packed_size, object.offset
+        ));
+        if let Some(base) = &object.base {
+            msg.push_str(&format!(" {} {}", object.depth, base));
+        }
+        msg.push('\n');
+        *chains.entry(object.depth).or_insert(0) += 1;
+    }
+
+    let plural = |count: usize| if count == 1 { "object" } else { "objects" };
+    for (depth, count) in chains {
+        match depth {
+            0 => msg.push_str(&format!("non delta: {} {}\n", count, plural(count))),
+            _ => msg.push_str(&format!(
+                "chain length = {}: {} {}\n",
+                depth,
+                count,
+                plural(count)
+            )),
+        }
+    }
+    msg.push_str(&format!("{}: ok\n", pack_path.display()));
+
+    Ok(msg)
+}
@@ -0,0 +1,22 @@
+use nit::{
+    database::ObjectId,
+    repository::{git_dir, Repository},
+};
+use std::path::Path;
+
+use super::CommandContext;
+
+pub fn run(ctx: &mut CommandContext) -> anyhow::Result<()> {
+    let oid = write_tree_from_index(&ctx.root_path)?;
+    writeln!(ctx.stdout, "{}", oid)?;
+
+    Ok(())
+}
+
+pub fn write_tree_from_index(root_path: &Path) -> anyhow::Result<ObjectId> {
+    let repo = Repository::new(git_dir(root_path));
+    let mut index = repo.configured_index()?;
+
+    index.load()?;
+    Ok(repo.write_tree(&index)?)
+}
@@ -0,0 +1,262 @@
+//! Commit-graph files, which record each commit's tree, parents, date and generation
+//! number, so that walking history doesn't have to read and parse every commit object.
+//! The format is git's: <https://git-scm.com/docs/commit-graph-format>.
+
+use std::collections::{BTreeMap, HashMap};
+
+use sha1::{Digest, Sha1};
+
+use crate::database::{Database, ObjectId};
+use crate::vfs::Vfs;
+use crate::Result;
+
+const SIGNATURE: &[u8; 4] = b"CGPH";
+const VERSION: u8 = 1;
+const SHA1_VERSION: u8 = 1;
+
+const OID_FANOUT: &[u8; 4] = b"OIDF";
+const OID_LOOKUP: &[u8; 4] = b"OIDL";
+const COMMIT_DATA: &[u8; 4] = b"CDAT";
+const EXTRA_EDGES: &[u8; 4] = b"EDGE";
+
+/// Written in place of a parent's position when there's no such parent.
+const NO_PARENT: u32 = 0x7000_0000;
+/// Set on the second parent's entry when the rest of the parents are in the extra edges
+/// chunk, and on the last of those.
+const EDGE_BIT: u32 = 0x8000_0000;
+/// The largest generation number that fits; commits further from a root are given this.
+const MAX_GENERATION: u32 = 0x3fff_ffff;
+
+/// What a commit-graph records about one commit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GraphCommit {
+    pub tree: ObjectId,
+    pub parents: Vec<ObjectId>,
+    /// The number of commits in the longest path from this commit back to a root, counting
+    /// both ends.
+    pub generation: u32,
+    /// When the commit was made, in seconds since the epoch.
+    pub time: i64,
+}
+
+/// A commit-graph: every commit reachable from some starting points.
+#[derive(Debug, Default, PartialEq)]
+pub struct CommitGraph {
+    commits: BTreeMap<ObjectId, GraphCommit>,
+}
+
+impl CommitGraph {
+    /// Read every commit reachable from the given ones out of the database.
+    pub fn build<F: Vfs>(database: &Database<F>, tips: &[ObjectId]) -> Result<Self> {
+        let mut commits = BTreeMap::new();
+        let mut queue = tips.to_vec();
+        while let Some(oid) = queue.pop() {
+            if commits.contains_key(&oid) {
+                continue;
+            }
+
+            let commit = database.load_commit(&oid)?;
+            queue.extend(commit.parents().iter().copied());
+            let entry = GraphCommit {
+                tree: *commit.tree(),
+                parents: commit.parents().to_vec(),
+                generation: 0,
+                time: commit.committer().time().timestamp(),
+            };
+            commits.insert(oid, entry);
+        }
+
+        let mut graph = Self { commits };
+        graph.number_generations();
+        Ok(graph)
+    }
+
+    /// Give every commit its generation number, parents first. This keeps its own stack
+    /// rather than recursing, as histories can be very long.
+    fn number_generations(&mut self) {
+        let mut generations: HashMap<ObjectId, u32> = HashMap::new();
+        for oid in self.commits.keys() {
+            let mut stack = vec![*oid];
+            while let Some(&oid) = stack.last() {
+                if generations.contains_key(&oid) {
+                    stack.pop();
+                    continue;
+                }
+
+                let parents = &self.commits[&oid].parents;
+                let pending: Vec<_> = parents
+                    .iter()
+                    .filter(|parent| !generations.contains_key(parent))
+                    .copied()
+                    .collect();
+                if pending.is_empty() {
+                    let highest = parents.iter().map(|p| generations[p]).max();
+                    let generation = highest.unwrap_or(0).saturating_add(1).min(MAX_GENERATION);
+                    generations.insert(oid, generation);
+                    stack.pop();
+                } else {
+                    stack.extend(pending);
+                }
+            }
+        }
+
+        for (oid, commit) in &mut self.commits {
+            commit.generation = generations[oid];
+        }
+    }
+
+    /// The commits in the graph, by ObjectId.
+    pub fn commits(&self) -> &BTreeMap<ObjectId, GraphCommit> {
+        &self.commits
+    }
+
+    /// The graph as it's written to `objects/info/commit-graph`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let positions: HashMap<_, _> = self
+            .commits
+            .keys()
+            .enumerate()
+            .map(|(i, oid)| (*oid, i as u32))
+            .collect();
+
+        let mut fanout = Vec::with_capacity(256 * 4);
+        let mut count = 0;
+        let mut oids = self.commits.keys().peekable();
+        for byte in 0..=255 {
+            while oids.next_if(|oid| oid.bytes()[0] == byte).is_some() {
+                count += 1;
+            }
+            fanout.extend_from_slice(&(count as u32).to_be_bytes());
+        }
+
+        let mut lookup = Vec::with_capacity(self.commits.len() * ObjectId::LEN);
+        let mut data = Vec::with_capacity(self.commits.len() * (ObjectId::LEN + 16));
+        let mut edges = Vec::new();
+        for (oid, commit) in &self.commits {
+            lookup.extend_from_slice(oid.bytes());
+            data.extend_from_slice(commit.tree.bytes());
+
+            let position = |parent: Option<&ObjectId>| match parent {
+                Some(parent) => positions[parent],
+                None => NO_PARENT,
+            };
+            data.extend_from_slice(&position(commit.parents.first()).to_be_bytes());
+            let second = match commit.parents.len() {
+                0..=2 => position(commit.parents.get(1)),
+                _ => {
+                    let start = (edges.len() / 4) as u32;
+                    let rest = &commit.parents[1..];
+                    for (i, parent) in rest.iter().enumerate() {
+                        let mut edge = positions[parent];
+                        if i == rest.len() - 1 {
+                            edge |= EDGE_BIT;
+                        }
+                        edges.extend_from_slice(&edge.to_be_bytes());
+                    }
+                    EDGE_BIT | start
+                }
+            };
+            data.extend_from_slice(&second.to_be_bytes());
+
+            let time = commit.time.clamp(0, (1 << 34) - 1) as u64;
+            let high = (commit.generation << 2) | (time >> 32) as u32;
+            data.extend_from_slice(&high.to_be_bytes());
+            data.extend_from_slice(&(time as u32).to_be_bytes());
+        }
+
+        let mut chunks = vec![
+            (OID_FANOUT, fanout),
+            (OID_LOOKUP, lookup),
+            (COMMIT_DATA, data),
+        ];
+        if !edges.is_empty() {
+            chunks.push((EXTRA_EDGES, edges));
+        }
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(SIGNATURE);
+        bytes.extend_from_slice(&[VERSION, SHA1_VERSION, chunks.len() as u8, 0]);
+
+        let mut offset = (bytes.len() + (chunks.len() + 1) * 12) as u64;
+        for (id, chunk) in &chunks {
+            bytes.extend_from_slice(*id);
+            bytes.extend_from_slice(&offset.to_be_bytes());
+            offset += chunk.len() as u64;
+        }
+        bytes.extend_from_slice(&[0; 4]);
+        bytes.extend_from_slice(&offset.to_be_bytes());
+
+        for (_, chunk) in &chunks {
+            bytes.extend_from_slice(chunk);
+        }
+        let checksum = Sha1::digest(&bytes);
+        bytes.extend_from_slice(&checksum);
+
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::{Author, Commit};
+    use crate::vfs::MemoryFs;
+    use chrono::{TimeZone, Utc};
+    use std::convert::TryInto;
+    use std::path::Path;
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn writes_git_commit_graphs() {
+        let fs = MemoryFs::new();
+        fs.create_dir_all(Path::new("/objects")).unwrap();
+        let database = Database::with_vfs(fs, "/objects");
+        let tree = database.store_raw("tree", b"").unwrap();
+
+        let commit = |parents: Vec<ObjectId>, time: i64| {
+            let author = Author::new("A".into(), "a@example.com".into(), Utc.timestamp(time, 0));
+            let commit = Commit::with_parents(parents, tree, author, "Commit\n".into());
+            database.store(&commit).unwrap()
+        };
+        let root = commit(vec![], 1_000);
+        let a = commit(vec![root], 2_000);
+        let b = commit(vec![root], 3_000);
+        let c = commit(vec![a], 4_000);
+        let octopus = commit(vec![c, a, b], 5_000);
+
+        let graph = CommitGraph::build(&database, &[octopus]).unwrap();
+        let generations: Vec<_> = [root, a, b, c, octopus]
+            .iter()
+            .map(|oid| graph.commits()[oid].generation)
+            .collect();
+        assert_eq!(generations, vec![1, 2, 2, 3, 4]);
+
+        let bytes = graph.to_bytes();
+        assert_eq!(&bytes[..8], b"CGPH\x01\x01\x04\x00");
+        assert_eq!(&bytes[8..12], OID_FANOUT);
+        assert_eq!(&bytes[56..60], b"\0\0\0\0");
+
+        let checksum = Sha1::digest(&bytes[..bytes.len() - 20]);
+        assert_eq!(&bytes[bytes.len() - 20..], &checksum[..]);
+
+        // The fanout's last entry counts every commit.
+        let fanout = 8 + 5 * 12;
+        assert_eq!(u32_at(&bytes, fanout + 255 * 4), 5);
+
+        // The octopus points into the extra edges for its second and third parents.
+        let position = graph
+            .commits()
+            .keys()
+            .position(|oid| *oid == octopus)
+            .unwrap();
+        let data = fanout + 256 * 4 + 5 * 20 + position * 36;
+        assert_eq!(u32_at(&bytes, data + 24), EDGE_BIT);
+        assert_eq!(u32_at(&bytes, data + 28) >> 2, 4);
+        assert_eq!(u32_at(&bytes, data + 32), 5_000);
+        let edges = fanout + 256 * 4 + 5 * 20 + 5 * 36;
+        assert_eq!(u32_at(&bytes, edges + 4) & EDGE_BIT, EDGE_BIT);
+    }
+}
@@ -0,0 +1,495 @@
+use std::{
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use thiserror::Error;
+
+use crate::lockfile::Lockfile;
+use crate::vfs::{RealFs, Vfs};
+use crate::Result;
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum ConfigError {
+    #[error("key does not contain a section: {0}")]
+    MissingSection(String),
+    #[error("bad config line {0} in file {1}")]
+    BadLine(usize, PathBuf),
+    #[error("Couldn't read config file")]
+    CouldNotRead(#[from] std::io::Error),
+    #[error("bad numeric config value '{1}' for '{0}'")]
+    BadNumber(String, String),
+}
+
+/// A config key, split into its (case-insensitive) section, optional (case-sensitive)
+/// subsection and (case-insensitive) variable name.
+#[derive(Debug, PartialEq)]
+struct Key {
+    section: String,
+    subsection: Option<String>,
+    name: String,
+}
+
+impl Key {
+    fn parse(key: &str) -> Result<Self> {
+        let missing = || ConfigError::MissingSection(key.to_owned());
+        let (section, rest) = key.split_once('.').ok_or_else(missing)?;
+        let (subsection, name) = match rest.rsplit_once('.') {
+            Some((subsection, name)) => (Some(subsection.to_owned()), name),
+            None => (None, rest),
+        };
+
+        if section.is_empty() || name.is_empty() {
+            return Err(missing().into());
+        }
+
+        Ok(Self {
+            section: section.to_ascii_lowercase(),
+            subsection,
+            name: name.to_ascii_lowercase(),
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Variable {
+    name: String,
+    value: Option<String>,
+}
+
+/// A line in the config file. Lines are kept as they were read so that
+/// comments and formatting survive being written back out.
+#[derive(Debug)]
+struct Line {
+    text: String,
+    variable: Option<Variable>,
+}
+
+#[derive(Debug)]
+struct Section {
+    name: String,
+    subsection: Option<String>,
+    header: String,
+    lines: Vec<Line>,
+}
+
+impl Section {
+    fn matches(&self, key: &Key) -> bool {
+        self.name == key.section && self.subsection == key.subsection
+    }
+}
+
+/// A git-style config file, e.g. `.git/config`.
+pub struct Config<F: Vfs = RealFs> {
+    fs: F,
+    pathname: PathBuf,
+    lockfile: Lockfile<F>,
+    /// Lines that appear before the first section header.
+    preamble: Vec<String>,
+    sections: Vec<Section>,
+}
+
+impl Config {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self::with_vfs(RealFs, path)
+    }
+}
+
+impl<F: Vfs> Config<F> {
+    pub fn with_vfs(fs: F, path: impl AsRef<Path>) -> Self {
+        Self {
+            lockfile: Lockfile::with_vfs(fs.clone(), path.as_ref()),
+            fs,
+            pathname: path.as_ref().to_owned(),
+            preamble: Vec::new(),
+            sections: Vec::new(),
+        }
+    }
+
+    /// Read the config file. A missing file is treated as empty.
+    pub fn load(&mut self) -> Result<()> {
+        self.preamble.clear();
+        self.sections.clear();
+
+        let content = match self.fs.read(&self.pathname) {
+            Ok(content) => String::from_utf8(content)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+                .map_err(ConfigError::CouldNotRead)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(ConfigError::CouldNotRead(e).into()),
+        };
+
+        let pathname = self.pathname.clone();
+        for (i, text) in content.lines().enumerate() {
+            let bad_line = || ConfigError::BadLine(i + 1, pathname.clone());
+            let trimmed = text.trim();
+
+            if trimmed.starts_with('[') {
+                let (name, subsection) = parse_section_header(trimmed).ok_or_else(bad_line)?;
+                self.sections.push(Section {
+                    name,
+                    subsection,
+                    header: text.to_owned(),
+                    lines: Vec::new(),
+                });
+                continue;
+            }
+
+            let variable = if trimmed.is_empty() || trimmed.starts_with(['#', ';'].as_ref()) {
+                None
+            } else {
+                Some(parse_variable(trimmed).ok_or_else(bad_line)?)
+            };
+
+            match self.sections.last_mut() {
+                Some(section) => section.lines.push(Line {
+                    text: text.to_owned(),
+                    variable,
+                }),
+                None if variable.is_none() => self.preamble.push(text.to_owned()),
+                None => return Err(bad_line().into()),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Take the config file's lock and read it, ready to be changed and saved.
+    pub fn load_for_update(&mut self) -> Result<()> {
+        self.lockfile.hold_for_update()?;
+        self.load()
+    }
+
+    /// Write the config back out, releasing the lock.
+    pub fn save(&mut self) -> Result<()> {
+        let mut content = String::new();
+        for line in &self.preamble {
+            content.push_str(line);
+            content.push('\n');
+        }
+        for section in &self.sections {
+            content.push_str(&section.header);
+            content.push('\n');
+            for line in &section.lines {
+                content.push_str(&line.text);
+                content.push('\n');
+            }
+        }
+
+        self.lockfile.write_all(content.as_bytes())?;
+        self.lockfile.commit()?;
+
+        Ok(())
+    }
+
+    /// Get the last value set for a key, e.g. `core.bare` or `branch.master.remote`.
+    pub fn get(&self, key: &str) -> Result<Option<String>> {
+        Ok(self.get_all(key)?.pop())
+    }
+
+    /// Get every value set for a key, in the order they appear.
+    pub fn get_all(&self, key: &str) -> Result<Vec<String>> {
+        let key = Key::parse(key)?;
+
+        Ok(self
+            .sections
+            .iter()
+            .filter(|section| section.matches(&key))
+            .flat_map(|section| section.lines.iter())
+            .filter_map(|line| line.variable.as_ref())
+            .filter(|variable| variable.name == key.name)
+            .map(|variable| variable.value.clone().unwrap_or_else(|| "true".to_owned()))
+            .collect())
+    }
+
+    /// The distinct subsections of a section, in the order they first appear, e.g. the
+    /// names of the remotes for `remote`.
+    pub fn subsections(&self, section: &str) -> Vec<String> {
+        let section = section.to_ascii_lowercase();
+        let mut names: Vec<String> = Vec::new();
+
+        for subsection in self
+            .sections
+            .iter()
+            .filter(|s| s.name == section)
+            .filter_map(|s| s.subsection.as_ref())
+        {
+            if !names.contains(subsection) {
+                names.push(subsection.clone());
+            }
+        }
+
+        names
+    }
+
+    /// The distinct names of the variables set in a section that has no subsection, in the
+    /// order they first appear, e.g. the extensions a repository needs, for `extensions`.
+    pub fn names(&self, section: &str) -> Vec<String> {
+        let section = section.to_ascii_lowercase();
+        let mut names: Vec<String> = Vec::new();
+
+        for variable in self
+            .sections
+            .iter()
+            .filter(|s| s.name == section && s.subsection.is_none())
+            .flat_map(|s| s.lines.iter())
+            .filter_map(|line| line.variable.as_ref())
+        {
+            if !names.contains(&variable.name) {
+                names.push(variable.name.clone());
+            }
+        }
+
+        names
+    }
+
+    /// Get a key's value interpreted as a boolean. A key with no value counts as true.
+    pub fn get_bool(&self, key: &str) -> Result<Option<bool>> {
+        Ok(self.get(key)?.map(|value| {
+            matches!(
+                value.to_ascii_lowercase().as_str(),
+                "true" | "yes" | "on" | "1"
+            )
+        }))
+    }
+
+    /// Get a key's value interpreted as a non-negative number.
+    pub fn get_usize(&self, key: &str) -> Result<Option<usize>> {
+        match self.get(key)? {
+            Some(value) => match value.trim().parse() {
+                Ok(number) => Ok(Some(number)),
+                Err(_) => Err(ConfigError::BadNumber(key.to_owned(), value).into()),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Set a key to a single value, replacing the last existing value if there is one.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        let key = Key::parse(key)?;
+        let text = format!("\t{} = {}", key.name, quote_value(value));
+        let variable = Variable {
+            name: key.name.clone(),
+            value: Some(value.to_owned()),
+        };
+
+        let existing = self
+            .sections
+            .iter_mut()
+            .filter(|section| section.matches(&key))
+            .flat_map(|section| section.lines.iter_mut())
+            .filter(|line| matches!(&line.variable, Some(v) if v.name == key.name))
+            .last();
+
+        match existing {
+            Some(line) => {
+                *line = Line {
+                    text,
+                    variable: Some(variable),
+                }
+            }
+            None => self.section_mut(&key).lines.push(Line {
+                text,
+                variable: Some(variable),
+            }),
+        }
+
+        Ok(())
+    }
+
+    /// Remove every value for a key. Sections left empty are removed too.
+    pub fn unset(&mut self, key: &str) -> Result<()> {
+        let key = Key::parse(key)?;
+
+        for section in self.sections.iter_mut().filter(|s| s.matches(&key)) {
+            section
+                .lines
+                .retain(|line| !matches!(&line.variable, Some(v) if v.name == key.name));
+        }
+
+        self.sections
+            .retain(|section| !section.matches(&key) || !section.lines.is_empty());
+
+        Ok(())
+    }
+
+    fn section_mut(&mut self, key: &Key) -> &mut Section {
+        let position = self.sections.iter().position(|s| s.matches(key));
+
+        let index = match position {
+            Some(index) => index,
+            None => {
+                let header = match &key.subsection {
+                    Some(subsection) => format!(
+                        "[{} \"{}\"]",
+                        key.section,
+                        subsection.replace('\\', "\\\\").replace('"', "\\\"")
+                    ),
+                    None => format!("[{}]", key.section),
+                };
+                self.sections.push(Section {
+                    name: key.section.clone(),
+                    subsection: key.subsection.clone(),
+                    header,
+                    lines: Vec::new(),
+                });
+                self.sections.len() - 1
+            }
+        };
+
+        &mut self.sections[index]
+    }
+}
+
+fn parse_section_header(line: &str) -> Option<(String, Option<String>)> {
+    let inner = &line[1..line.find(']')?];
+
+    match inner.split_once(|c: char| c.is_whitespace()) {
+        Some((name, subsection)) => {
+            let subsection = subsection.trim().strip_prefix('"')?.strip_suffix('"')?;
+            let subsection = subsection.replace("\\\"", "\"").replace("\\\\", "\\");
+            Some((name.to_ascii_lowercase(), Some(subsection)))
+        }
+        None => {
+            // The deprecated `[section.subsection]` syntax.
+            match inner.split_once('.') {
+                Some((name, subsection)) => {
+                    Some((name.to_ascii_lowercase(), Some(subsection.to_owned())))
+                }
+                None => Some((inner.to_ascii_lowercase(), None)),
+            }
+        }
+    }
+}
+
+fn parse_variable(line: &str) -> Option<Variable> {
+    let (name, value) = match line.split_once('=') {
+        Some((name, value)) => (name.trim(), Some(parse_value(value.trim()))),
+        None => (strip_comment(line).trim(), None),
+    };
+
+    let valid = name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+
+    if valid {
+        Some(Variable {
+            name: name.to_ascii_lowercase(),
+            value,
+        })
+    } else {
+        None
+    }
+}
+
+fn strip_comment(text: &str) -> &str {
+    text.split(['#', ';'].as_ref()).next().unwrap_or("")
+}
+
+/// Unquote and unescape a value, stopping at any comment that isn't inside quotes.
+fn parse_value(text: &str) -> String {
+    let mut value = String::new();
+    let mut in_quotes = false;
+    let mut chars = text.chars();
+    // Whitespace is only kept if something other than whitespace follows it.
+    let mut pending_space = String::new();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            '#' | ';' if !in_quotes => break,
+            '\\' => {
+                value.push_str(&pending_space);
+                pending_space.clear();
+                match chars.next() {
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    Some('b') => {
+                        value.pop();
+                    }
+                    Some(c) => value.push(c),
+                    None => {}
+                }
+            }
+            c if c.is_whitespace() && !in_quotes => pending_space.push(c),
+            c => {
+                value.push_str(&pending_space);
+                pending_space.clear();
+                value.push(c);
+            }
+        }
+    }
+
+    value
+}
+
+fn quote_value(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t");
+
+    let needs_quotes =
+        value.starts_with(' ') || value.ends_with(' ') || value.contains(['#', ';'].as_ref());
+
+    if needs_quotes {
+        format!("\"{}\"", escaped)
+    } else {
+        escaped
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::vfs::MemoryFs;
+
+    fn config_with(content: &str) -> (MemoryFs, Config<MemoryFs>) {
+        let fs = MemoryFs::new();
+        fs.write(Path::new("/config"), content.as_bytes()).unwrap();
+        let config = Config::with_vfs(fs.clone(), "/config");
+        (fs, config)
+    }
+
+    #[test]
+    fn lists_the_names_set_in_a_section() {
+        let (_, mut config) = config_with(
+            "[extensions]\n\tnoop\n\tObjectFormat = sha1\n[extensions \"sub\"]\n\tother = 1\n[Extensions]\n\tnoop = true\n",
+        );
+        config.load().unwrap();
+        assert_eq!(config.names("extensions"), vec!["noop", "objectformat"]);
+        assert!(config.names("core").is_empty());
+    }
+
+    #[test]
+    fn reads_and_writes_values() {
+        let (fs, mut config) = config_with(
+            "# A comment\n[core]\n\tbare = false ; not bare\n[Branch \"Topic\"]\n\tremote = origin\n",
+        );
+        config.load_for_update().unwrap();
+
+        assert_eq!(config.get_bool("core.bare").unwrap(), Some(false));
+        assert_eq!(
+            config.get("branch.Topic.remote").unwrap(),
+            Some("origin".to_owned())
+        );
+        assert_eq!(config.get("branch.topic.remote").unwrap(), None);
+
+        config.set("branch.Topic.merge", "refs/heads/main").unwrap();
+        config.set("user.name", "A \"quoted\" name").unwrap();
+        config.unset("core.bare").unwrap();
+        config.save().unwrap();
+
+        assert_eq!(
+            fs.read(Path::new("/config")).unwrap(),
+            b"# A comment\n[Branch \"Topic\"]\n\tremote = origin\n\tmerge = refs/heads/main\n[user]\n\tname = A \\\"quoted\\\" name\n"
+        );
+
+        config.load().unwrap();
+        assert_eq!(
+            config.get("user.name").unwrap(),
+            Some("A \"quoted\" name".to_owned())
+        );
+    }
+}
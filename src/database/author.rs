@@ -1,18 +1,144 @@
 use std::fmt::Display;
+use std::str::FromStr;
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, FixedOffset, Offset, TimeZone};
 
-#[derive(Clone, Debug)]
+use super::DatabaseError;
+use crate::Result;
+
+/// Who made a change and when, as recorded on a commit's `author` and `committer` lines
+/// and in reflogs. The time keeps the offset from UTC it was recorded with.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Author {
     name: String,
     email: String,
-    time: DateTime<Utc>,
+    time: DateTime<FixedOffset>,
 }
 
 impl Author {
-    pub fn new(name: String, email: String, time: DateTime<Utc>) -> Self {
+    pub fn new<Tz: TimeZone>(name: String, email: String, time: DateTime<Tz>) -> Self {
+        let time = time.with_timezone(&time.offset().fix());
         Self { name, email, time }
     }
+
+    /// Parse an author line of the form `Name <email> 1234567890 +0100`.
+    pub fn parse(line: &[u8]) -> Result<Self> {
+        let bad = || DatabaseError::BadObject("author");
+        let line = std::str::from_utf8(line).map_err(|_| bad())?;
+
+        let (name, rest) = line.split_once('<').ok_or_else(bad)?;
+        let (email, rest) = rest.split_once('>').ok_or_else(bad)?;
+        let (timestamp, offset) = rest.trim_start().split_once(' ').ok_or_else(bad)?;
+
+        let timestamp = timestamp.parse().map_err(|_| bad())?;
+        let offset = parse_offset(offset).ok_or_else(bad)?;
+        let time = offset
+            .timestamp_opt(timestamp, 0)
+            .single()
+            .ok_or_else(bad)?;
+
+        Ok(Self {
+            name: name.trim_end().to_owned(),
+            email: email.to_owned(),
+            time,
+        })
+    }
+
+    /// Get a reference to the author's name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get a reference to the author's email.
+    pub fn email(&self) -> &str {
+        &self.email
+    }
+
+    /// Get the time the author made their change, in the time zone they made it in.
+    pub fn time(&self) -> DateTime<FixedOffset> {
+        self.time
+    }
+}
+
+/// Who someone is, without a time: the name and email an author or committer is recorded
+/// with.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Identity {
+    pub name: String,
+    pub email: String,
+}
+
+impl Identity {
+    pub fn new(name: impl Into<String>, email: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            email: email.into(),
+        }
+    }
+
+    /// Record this identity as having made a change at the given time.
+    pub fn at<Tz: TimeZone>(&self, time: DateTime<Tz>) -> Author {
+        Author::new(self.name.clone(), self.email.clone(), time)
+    }
+}
+
+impl FromStr for Identity {
+    type Err = DatabaseError;
+
+    /// Parse an identity of the form `Name <email>`, as given to `commit --author`.
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let bad = || DatabaseError::BadIdentity(s.to_owned());
+        let (name, rest) = s.split_once('<').ok_or_else(bad)?;
+        let email = rest.strip_suffix('>').ok_or_else(bad)?;
+        let name = name.trim();
+        if name.is_empty() || email.contains(['<', '>']) {
+            return Err(bad());
+        }
+
+        Ok(Self::new(name, email.trim()))
+    }
+}
+
+/// Parse a date given for a commit: git's own `1234567890 +0100`, `@1234567890`, an RFC 2822
+/// date like `Thu, 1 Jan 2021 12:00:00 +0100`, or an ISO 8601 one like
+/// `2021-01-01T12:00:00+01:00` or `2021-01-01 12:00:00 +0100`.
+pub fn parse_date(text: &str) -> std::result::Result<DateTime<FixedOffset>, DatabaseError> {
+    let text = text.trim();
+    let raw = || {
+        let (timestamp, offset) = match text.strip_prefix('@') {
+            Some(timestamp) => (timestamp, "+0000"),
+            None => text.split_once(' ')?,
+        };
+        parse_offset(offset)?
+            .timestamp_opt(timestamp.parse().ok()?, 0)
+            .single()
+    };
+
+    raw()
+        .or_else(|| DateTime::parse_from_rfc2822(text).ok())
+        .or_else(|| DateTime::parse_from_rfc3339(text).ok())
+        .or_else(|| DateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S %z").ok())
+        .ok_or_else(|| DatabaseError::BadDate(text.to_owned()))
+}
+
+/// Parse a time zone offset like `+0100` or `-0530`.
+fn parse_offset(offset: &str) -> Option<FixedOffset> {
+    let (sign, digits) = match offset.as_bytes() {
+        [b'+', digits @ ..] => (1, digits),
+        [b'-', digits @ ..] => (-1, digits),
+        _ => return None,
+    };
+    if digits.len() != 4 || !digits.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+
+    let hours: i32 = offset[1..3].parse().ok()?;
+    let minutes: i32 = offset[3..5].parse().ok()?;
+    if minutes >= 60 {
+        return None;
+    }
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
 }
 
 impl Display for Author {
@@ -26,3 +152,90 @@ impl Display for Author {
         )
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::Utc;
+
+    #[test]
+    fn keeps_the_time_zone() {
+        for line in [
+            "A. U. Thor <author@example.com> 1600000000 +0000",
+            "A. U. Thor <author@example.com> 1600000000 +0530",
+            "A. U. Thor <author@example.com> 1600000000 -0800",
+            "A. U. Thor <author@example.com> 1600000000 +1400",
+            " <> 0 -0100",
+        ] {
+            let author = Author::parse(line.as_bytes()).unwrap();
+            assert_eq!(author.to_string(), line);
+        }
+
+        let author = Author::parse(b"A. U. Thor <author@example.com> 1600000000 -0230").unwrap();
+        assert_eq!(author.name(), "A. U. Thor");
+        assert_eq!(author.email(), "author@example.com");
+        assert_eq!(author.time().timestamp(), 1600000000);
+        assert_eq!(
+            author.time().offset().local_minus_utc(),
+            -(2 * 3600 + 30 * 60)
+        );
+        assert_eq!(
+            author.time().format("%Y-%m-%d %H:%M").to_string(),
+            "2020-09-13 09:56"
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        for line in [
+            "A. U. Thor author@example.com 1600000000 +0000",
+            "A. U. Thor <author@example.com 1600000000 +0000",
+            "A. U. Thor <author@example.com> 1600000000",
+            "A. U. Thor <author@example.com> soon +0000",
+            "A. U. Thor <author@example.com> 1600000000 0000",
+            "A. U. Thor <author@example.com> 1600000000 +000",
+            "A. U. Thor <author@example.com> 1600000000 +0060",
+            "A. U. Thor <author@example.com> 1600000000 +2400",
+            "A. U. Thor <author@example.com> 1600000000 +01:00",
+        ] {
+            assert!(Author::parse(line.as_bytes()).is_err(), "{:?} parsed", line);
+        }
+    }
+
+    #[test]
+    fn parses_identities_and_dates() {
+        let identity: Identity = "A. U. Thor <author@example.com>".parse().unwrap();
+        assert_eq!(identity, Identity::new("A. U. Thor", "author@example.com"));
+        for text in ["A. U. Thor", "<author@example.com>", "A <a> b", "A <<a>"] {
+            assert!(text.parse::<Identity>().is_err(), "{:?} parsed", text);
+        }
+
+        let author = identity.at(parse_date("1600000000 -0230").unwrap());
+        assert_eq!(
+            author.to_string(),
+            "A. U. Thor <author@example.com> 1600000000 -0230"
+        );
+        for text in [
+            "1609498800 +0100",
+            "Fri, 1 Jan 2021 12:00:00 +0100",
+            "2021-01-01T12:00:00+01:00",
+            "2021-01-01 12:00:00 +0100",
+        ] {
+            let date = parse_date(text).unwrap();
+            assert_eq!(date.format("%s %z").to_string(), "1609498800 +0100");
+        }
+        assert_eq!(parse_date("@1609498800").unwrap().timestamp(), 1609498800);
+        assert!(parse_date("yesterday").is_err());
+        assert!(parse_date("1609498800").is_err());
+    }
+
+    #[test]
+    fn new_authors_keep_their_offset() {
+        let time = FixedOffset::east(3600).ymd(2021, 1, 1).and_hms(12, 0, 0);
+        let author = Author::new("A".into(), "a@example.com".into(), time);
+        assert_eq!(author.to_string(), "A <a@example.com> 1609498800 +0100");
+
+        let author = Author::new("A".into(), "a@example.com".into(), time.with_timezone(&Utc));
+        assert_eq!(author.to_string(), "A <a@example.com> 1609498800 +0000");
+    }
+}
@@ -17,7 +17,7 @@ impl Blob {
 }
 
 impl Object for Blob {
-    fn data(&self) -> Cow<[u8]> {
+    fn data(&self) -> Cow<'_, [u8]> {
         Cow::Borrowed(self.to_bytestr())
     }
 
@@ -1,38 +1,132 @@
 use std::borrow::Cow;
 
-use super::{Author, Object, ObjectId};
+use super::{Author, DatabaseError, Object, ObjectId};
+use crate::trailers::{self, Trailer};
+use crate::Result;
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Commit {
     author: Author,
+    committer: Author,
     message: String,
     tree: ObjectId,
-    parent: Option<String>,
+    parents: Vec<ObjectId>,
 }
 
 impl Commit {
-    pub fn new(parent: Option<&str>, tree_oid: ObjectId, author: Author, message: String) -> Self {
+    pub fn new(
+        parent: Option<ObjectId>,
+        tree_oid: ObjectId,
+        author: Author,
+        message: String,
+    ) -> Self {
+        Self::with_parents(parent.into_iter().collect(), tree_oid, author, message)
+    }
+
+    /// Make a commit with any number of parents, e.g. none for a root commit or several for
+    /// a merge.
+    pub fn with_parents(
+        parents: Vec<ObjectId>,
+        tree_oid: ObjectId,
+        author: Author,
+        message: String,
+    ) -> Self {
         Self {
-            parent: parent.map(|s| s.to_owned()),
+            parents,
+            committer: author.clone(),
             author,
             tree: tree_oid,
             message,
         }
     }
 
+    /// Record someone other than the author as having made the commit.
+    pub fn with_committer(mut self, committer: Author) -> Self {
+        self.committer = committer;
+        self
+    }
+
+    /// Parse a commit object's data, as read from the database.
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let bad = || DatabaseError::BadObject("commit");
+        let data = std::str::from_utf8(data).map_err(|_| bad())?;
+
+        let (headers, message) = data.split_once("\n\n").unwrap_or((data, ""));
+
+        let mut tree = None;
+        let mut parents = Vec::new();
+        let mut author = None;
+        let mut committer = None;
+
+        for line in headers.lines() {
+            let (key, value) = line.split_once(' ').ok_or_else(bad)?;
+            match key {
+                "tree" => tree = Some(ObjectId::from_hex(value)?),
+                "parent" => parents.push(ObjectId::from_hex(value)?),
+                "author" => author = Some(Author::parse(value.as_bytes())?),
+                "committer" => committer = Some(Author::parse(value.as_bytes())?),
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            tree: tree.ok_or_else(bad)?,
+            parents,
+            author: author.ok_or_else(bad)?,
+            committer: committer.ok_or_else(bad)?,
+            message: message.to_owned(),
+        })
+    }
+
     pub fn message(&self) -> &str {
         &self.message
     }
+
+    /// The `Key: value` trailers at the end of the commit's message.
+    pub fn trailers(&self) -> Vec<Trailer> {
+        trailers::parse(&self.message)
+    }
+
+    /// The first line of the commit's message.
+    pub fn title_line(&self) -> &str {
+        self.message.lines().next().unwrap_or("")
+    }
+
+    /// Get a reference to the ObjectId of the commit's tree.
+    pub fn tree(&self) -> &ObjectId {
+        &self.tree
+    }
+
+    /// Get a reference to the ObjectId of the commit's first parent, if it has one.
+    pub fn parent(&self) -> Option<&ObjectId> {
+        self.parents.first()
+    }
+
+    /// Get the ObjectIds of all the commit's parents, in order.
+    pub fn parents(&self) -> &[ObjectId] {
+        &self.parents
+    }
+
+    /// Get a reference to the commit's author.
+    pub fn author(&self) -> &Author {
+        &self.author
+    }
+
+    /// Get a reference to whoever made the commit, who may not be its author.
+    pub fn committer(&self) -> &Author {
+        &self.committer
+    }
 }
 
 impl Object for Commit {
-    fn data(&self) -> Cow<[u8]> {
+    fn data(&self) -> Cow<'_, [u8]> {
         let mut data = vec![format!("tree {}", self.tree)];
-        if let Some(p) = &self.parent {
-            data.push(format!("parent {}", p));
+        for parent in &self.parents {
+            data.push(format!("parent {}", parent));
         }
         data.push(format!("author {}", self.author));
-        data.push(format!("committer {}", self.author));
-        data.push(String::from("\n"));
+        data.push(format!("committer {}", self.committer));
+        data.push(String::new());
         data.push(self.message.to_owned());
 
         Cow::Owned(data.join("\n").into_bytes())
@@ -42,3 +136,29 @@ impl Object for Commit {
         "commit"
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trips_byte_for_byte() {
+        let data = b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+parent e69de29bb2d1d6434b8b29ae775ad8c2e48c5391\n\
+author A. U. Thor <author@example.com> 1600000000 +0530\n\
+committer C. O. Mitter <committer@example.com> 1600003600 -0700\n\
+\n\
+Subject\n\
+\n\
+Body\n";
+
+        let commit = Commit::parse(data).unwrap();
+        assert_eq!(commit.author().name(), "A. U. Thor");
+        assert_eq!(commit.committer().name(), "C. O. Mitter");
+        assert_eq!(
+            commit.committer().time().offset().local_minus_utc(),
+            -7 * 3600
+        );
+        assert_eq!(&commit.data()[..], &data[..]);
+    }
+}
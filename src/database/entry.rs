@@ -0,0 +1,30 @@
+use super::{tree::DIRECTORY_MODE, ObjectId};
+
+/// An entry read back out of a stored tree: just an ObjectId and the mode it was stored with.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DatabaseEntry {
+    oid: ObjectId,
+    mode: u32,
+}
+
+impl DatabaseEntry {
+    pub fn new(oid: ObjectId, mode: u32) -> Self {
+        Self { oid, mode }
+    }
+
+    /// Get a reference to the entry's ObjectId.
+    pub fn oid(&self) -> &ObjectId {
+        &self.oid
+    }
+
+    /// Get the entry's mode.
+    pub fn mode(&self) -> u32 {
+        self.mode
+    }
+
+    /// Whether this entry points at a subtree rather than a blob.
+    pub fn is_tree(&self) -> bool {
+        self.mode == DIRECTORY_MODE
+    }
+}
@@ -1,29 +1,45 @@
 use std::{
     borrow::Cow,
+    collections::{BTreeMap, BTreeSet, HashSet},
+    env,
     fmt::{Debug, Display},
-    fs::{self, File},
-    io::{self, Write},
-    path::PathBuf,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
     string::FromUtf8Error,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
 };
 
+use crate::hash;
+use crate::index::entry::Entry as IndexEntry;
+use crate::pack::{MappedPack, ObjectType, Pack, PackIndex};
+use crate::refs::Refs;
 use crate::utils::bytes_to_hex_string;
+use crate::vfs::{FileKind, RealFs, Vfs};
 use crate::Result;
 
-use flate2::{write::ZlibEncoder, Compression};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
-use sha1::{Digest, Sha1};
 use thiserror::Error;
 
 mod author;
 mod blob;
 mod commit;
+mod entry;
+mod tag;
 mod tree;
+mod tree_diff;
 
 pub use author::*;
 pub use blob::*;
 pub use commit::*;
+pub use entry::*;
+pub use tag::*;
 pub use tree::*;
+pub use tree_diff::*;
 
 #[derive(Debug, Error)]
 #[non_exhaustive]
@@ -36,15 +52,69 @@ pub enum DatabaseError {
     CouldNotWrite(#[from] std::io::Error),
     #[error(transparent)]
     Utf8BadParse(FromUtf8Error),
+    #[error("Not a valid object name: {0}")]
+    InvalidObjectId(String),
+    #[error("Object not found: {0}")]
+    NoSuchObject(String),
+    #[error("Could not parse {0} object")]
+    BadObject(&'static str),
+    #[error("'{0}' is not 'Name <email>'")]
+    BadIdentity(String),
+    #[error("invalid date format: {0}")]
+    BadDate(String),
+    #[error("replace depth too high for object {0}")]
+    ReplaceDepth(String),
+    #[error("SHA-1 collision attack detected in {0} object")]
+    Collision(String),
+    #[error("loose object {0} is corrupt: {1}")]
+    Corrupt(String, &'static str),
 }
-#[derive(PartialEq, Clone)]
-pub struct ObjectId([u8; 20]);
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct ObjectId([u8; ObjectId::LEN]);
 
 impl ObjectId {
+    /// The number of bytes in an ObjectId.
+    pub const LEN: usize = 20;
+
+    /// The number of characters in an ObjectId written out in hex.
+    pub const HEX_LEN: usize = 40;
+
+    /// The ObjectId of nothing at all, written where there's no object, such as the old
+    /// value of a ref that has just been created.
+    pub const NULL: Self = Self([0; 20]);
+
+    /// Parse a full 40-character hex string into an ObjectId.
+    pub fn from_hex(s: &str) -> Result<Self> {
+        let invalid = || DatabaseError::InvalidObjectId(s.to_owned());
+        if s.len() != Self::HEX_LEN || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(invalid().into());
+        }
+
+        let mut bytes = [0; 20];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).map_err(|_| invalid())?;
+        }
+
+        Ok(Self(bytes))
+    }
+
     pub fn as_str(&self) -> Result<String, std::fmt::Error> {
         bytes_to_hex_string(&self.0)
     }
 
+    /// The first `len` hex characters of this ObjectId, or all 40 if `len` is longer.
+    pub fn short(&self, len: usize) -> String {
+        let len = len.min(Self::HEX_LEN);
+        let mut s = bytes_to_hex_string(&self.0[..len.div_ceil(2)]).unwrap_or_default();
+        s.truncate(len);
+        s
+    }
+
+    /// Whether this is the null ObjectId.
+    pub fn is_null(&self) -> bool {
+        *self == Self::NULL
+    }
+
     pub fn bytes(&self) -> &[u8; 20] {
         &self.0
     }
@@ -66,73 +136,656 @@ impl Display for ObjectId {
     }
 }
 
+impl std::str::FromStr for ObjectId {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::from_hex(s)
+    }
+}
+
 impl From<[u8; 20]> for ObjectId {
     fn from(arr: [u8; 20]) -> Self {
         Self(arr)
     }
 }
 
+/// ObjectIds are serialized as their 40-character hex strings.
+#[cfg(feature = "serde")]
+impl serde::Serialize for ObjectId {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for ObjectId {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+        ObjectId::from_hex(&s).map_err(serde::de::Error::custom)
+    }
+}
+
 pub trait Object {
-    fn data(&self) -> Cow<[u8]>;
+    fn data(&self) -> Cow<'_, [u8]>;
     fn kind(&self) -> &str;
 }
 
-pub struct Database {
+/// An object that has been read back out of the database.
+pub enum ParsedObject {
+    Blob(Blob),
+    Tree(Tree),
+    Commit(Commit),
+    Tag(Tag),
+}
+
+impl ParsedObject {
+    pub fn kind(&self) -> &str {
+        match self {
+            ParsedObject::Blob(blob) => blob.kind(),
+            ParsedObject::Tree(tree) => tree.kind(),
+            ParsedObject::Commit(commit) => commit.kind(),
+            ParsedObject::Tag(tag) => tag.kind(),
+        }
+    }
+}
+
+/// Set to stop objects being swapped for their replacements when they're loaded.
+pub const NO_REPLACE_OBJECTS: &str = "GIT_NO_REPLACE_OBJECTS";
+/// How many replacements can be followed, one after another, before giving up.
+const MAX_REPLACE_DEPTH: usize = 5;
+/// How many new objects a batch needs before it's worth compressing them on more than one
+/// thread.
+const PARALLEL_STORE_THRESHOLD: usize = 64;
+
+pub struct Database<F: Vfs = RealFs> {
+    fs: F,
     pathname: PathBuf,
+    /// The refs of the repository the database is in, where replacements for objects are
+    /// recorded under `refs/replace/<oid>`. `None` if replacements are turned off.
+    replace_refs: Option<Refs<F>>,
+    /// Packs that have been mapped into memory, keyed by the path to their index.
+    mapped_packs: Mutex<BTreeMap<PathBuf, Arc<MappedPack>>>,
 }
 
 impl Database {
     pub fn new<P: Into<PathBuf>>(pathname: P) -> Self {
+        Self::with_vfs(RealFs, pathname)
+    }
+}
+
+impl<F: Vfs> Database<F> {
+    pub fn with_vfs<P: Into<PathBuf>>(fs: F, pathname: P) -> Self {
+        let pathname = pathname.into();
+        let replace_refs = match env::var_os(NO_REPLACE_OBJECTS) {
+            Some(_) => None,
+            None => pathname
+                .parent()
+                .map(|git_path| Refs::with_vfs(fs.clone(), git_path)),
+        };
+
         Self {
-            pathname: pathname.into(),
+            fs,
+            pathname,
+            replace_refs,
+            mapped_packs: Mutex::new(BTreeMap::new()),
         }
     }
 
     pub fn store<O: Object>(&self, object: &O) -> Result<ObjectId> {
+        let content = Self::serialize_object(object);
+        let oid = Self::hash_content(&content)?;
+        self.write_object(&oid, &content)?;
+
+        Ok(oid)
+    }
+
+    /// Store many objects at once, returning their ObjectIds in the same order. Objects
+    /// that are already in the database, or that come up more than once, are only checked
+    /// for once; the directories the new ones go in are each made once; and when there are
+    /// enough of them, they're compressed and written on several threads, each of which
+    /// keeps its own encoder and buffers from one object to the next.
+    pub fn store_batch<'o, I>(&self, objects: I) -> Result<Vec<ObjectId>>
+    where
+        I: IntoIterator<Item = &'o dyn Object>,
+    {
+        let packs = self.mapped_packs()?;
+        let mut oids = Vec::new();
+        let mut seen = HashSet::new();
+        let mut pending = Vec::new();
+
+        for object in objects {
+            let content = Self::serialize_object(object);
+            let oid = Self::hash_content(&content)?;
+            oids.push(oid);
+
+            if !seen.insert(oid) || packs.iter().any(|pack| pack.index().find(&oid).is_some()) {
+                continue;
+            }
+
+            let object_path = self.object_path(&oid)?;
+            if !self.fs.exists(&object_path) {
+                pending.push((object_path, content));
+            }
+        }
+
+        let dirs: BTreeSet<_> = pending
+            .iter()
+            .filter_map(|(path, _)| path.parent())
+            .collect();
+        for dir in dirs {
+            self.fs.create_dir_all(dir)?;
+        }
+
+        let workers = match pending.len() {
+            n if n < PARALLEL_STORE_THRESHOLD => 1,
+            n => thread::available_parallelism().map_or(1, |workers| workers.get().min(n)),
+        };
+        let next = AtomicUsize::new(0);
+        let worker = || -> Result<()> {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::fast());
+            let mut spare = Vec::new();
+            while let Some((path, content)) = pending.get(next.fetch_add(1, Ordering::Relaxed)) {
+                encoder.write_all(content)?;
+                let compressed = encoder.reset(std::mem::take(&mut spare))?;
+                self.write_compressed(path, &compressed)?;
+                spare = compressed;
+                spare.clear();
+            }
+            Ok(())
+        };
+
+        if workers == 1 {
+            worker()?;
+        } else {
+            thread::scope(|scope| {
+                let handles: Vec<_> = (0..workers).map(|_| scope.spawn(worker)).collect();
+                handles
+                    .into_iter()
+                    .try_for_each(|handle| handle.join().expect("store worker panicked"))
+            })?;
+        }
+
+        Ok(oids)
+    }
+
+    /// Write an object given as its type and contents, such as one read out of another
+    /// database with `read_object`.
+    pub fn store_raw(&self, kind: &str, data: &[u8]) -> Result<ObjectId> {
+        let mut content = format!("{} {}\0", kind, data.len()).into_bytes();
+        content.extend_from_slice(data);
+        let oid = Self::hash_content(&content)?;
+        self.write_object(&oid, &content)?;
+
+        Ok(oid)
+    }
+
+    /// Whether the database has an object, either loose or in a pack.
+    pub fn contains(&self, oid: &ObjectId) -> Result<bool> {
+        if self.fs.exists(&self.object_path(oid)?) {
+            return Ok(true);
+        }
+
+        Ok(self
+            .mapped_packs()?
+            .iter()
+            .any(|pack| pack.index().find(oid).is_some()))
+    }
+
+    /// Work out what an object's ObjectId would be, without writing it to the database.
+    pub fn hash_object<O: Object>(&self, object: &O) -> Result<ObjectId> {
+        Self::hash_content(&Self::serialize_object(object))
+    }
+
+    /// Read an object back out of the database and parse it. If the object has been
+    /// replaced, its replacement is loaded instead.
+    pub fn load(&self, oid: &ObjectId) -> Result<ParsedObject> {
+        let (kind, data) = self.read_object(&self.replacement(oid)?)?;
+
+        let object = match kind.as_str() {
+            "blob" => ParsedObject::Blob(Blob::new(data)),
+            "tree" => ParsedObject::Tree(Tree::parse(&data)?),
+            "commit" => ParsedObject::Commit(Commit::parse(&data)?),
+            "tag" => ParsedObject::Tag(Tag::parse(&data)?),
+            _ => return Err(DatabaseError::BadObject("unknown").into()),
+        };
+
+        Ok(object)
+    }
+
+    /// Load an object that is expected to be a commit.
+    pub fn load_commit(&self, oid: &ObjectId) -> Result<Commit> {
+        match self.load(oid)? {
+            ParsedObject::Commit(commit) => Ok(commit),
+            _ => Err(DatabaseError::BadObject("commit").into()),
+        }
+    }
+
+    /// Load an object that is expected to be a tree.
+    pub fn load_tree(&self, oid: &ObjectId) -> Result<Tree> {
+        match self.load(oid)? {
+            ParsedObject::Tree(tree) => Ok(tree),
+            _ => Err(DatabaseError::BadObject("tree").into()),
+        }
+    }
+
+    /// Follow annotated tags from an object, and any tags they're tags of, to the first
+    /// object that isn't a tag, as `<rev>^{}` does. Anything else is its own peel.
+    pub fn peel(&self, oid: &ObjectId) -> Result<ObjectId> {
+        let mut oid = *oid;
+        while let ParsedObject::Tag(tag) = self.load(&oid)? {
+            oid = *tag.object();
+        }
+        Ok(oid)
+    }
+
+    /// Load an object that is expected to be a blob.
+    pub fn load_blob(&self, oid: &ObjectId) -> Result<Blob> {
+        match self.load(oid)? {
+            ParsedObject::Blob(blob) => Ok(blob),
+            _ => Err(DatabaseError::BadObject("blob").into()),
+        }
+    }
+
+    /// The object to load in place of the given one: the last in the chain of replacements
+    /// recorded for it, or the object itself if there are none.
+    pub fn replacement(&self, oid: &ObjectId) -> Result<ObjectId> {
+        let refs = match &self.replace_refs {
+            Some(refs) => refs,
+            None => return Ok(*oid),
+        };
+
+        let mut current = *oid;
+        for _ in 0..MAX_REPLACE_DEPTH {
+            match refs.read_ref(&format!("refs/replace/{}", current))? {
+                Some(replacement) => current = replacement,
+                None => return Ok(current),
+            }
+        }
+
+        Err(DatabaseError::ReplaceDepth(oid.to_string()).into())
+    }
+
+    /// Find all the ObjectIds in the database which start with the given (hex) prefix.
+    pub fn prefix_match(&self, prefix: &str) -> Result<Vec<ObjectId>> {
+        if prefix.len() < 2 {
+            return Ok(Vec::new());
+        }
+
+        let mut oids = Vec::new();
+
+        let dirname = self.pathname.join(&prefix[0..2]);
+        match self.fs.read_dir(&dirname) {
+            Ok(files) => {
+                for (file, _) in files {
+                    let name = format!("{}{}", &prefix[0..2], file.to_string_lossy());
+                    if name.starts_with(prefix) {
+                        if let Ok(oid) = ObjectId::from_hex(&name) {
+                            oids.push(oid);
+                        }
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        };
+
+        for (_, index) in self.packs()? {
+            for entry in index.entries() {
+                if entry.oid.as_str()?.starts_with(prefix) && !oids.contains(&entry.oid) {
+                    oids.push(entry.oid);
+                }
+            }
+        }
+
+        Ok(oids)
+    }
+
+    /// List every blob in a tree (or a commit's tree), keyed by path.
+    pub fn load_tree_list(
+        &self,
+        oid: Option<&ObjectId>,
+    ) -> Result<BTreeMap<PathBuf, DatabaseEntry>> {
+        let list = self
+            .tree_diff(None, oid)?
+            .into_iter()
+            .filter_map(|(path, (_, entry))| entry.map(|entry| (path, entry)))
+            .collect();
+
+        Ok(list)
+    }
+
+    /// Store the tree holding the given blobs, keyed by path, along with all its subtrees:
+    /// the reverse of `load_tree_list`.
+    pub fn store_tree_list(&self, entries: &BTreeMap<PathBuf, DatabaseEntry>) -> Result<ObjectId> {
+        let mut root = Tree::build(
+            entries
+                .iter()
+                .map(|(path, entry)| IndexEntry::from_db(path, *entry.oid(), entry.mode()))
+                .collect(),
+        );
+        root.traverse(&mut |tree| self.store(tree))?;
+        self.store(&root)
+    }
+
+    /// Find the entry at a path in a tree (or a commit's tree), if there is one there.
+    pub fn entry_at_path(&self, oid: &ObjectId, path: &Path) -> Result<Option<DatabaseEntry>> {
+        let mut entry = match self.load(oid)? {
+            ParsedObject::Commit(commit) => DatabaseEntry::new(*commit.tree(), DIRECTORY_MODE),
+            _ => DatabaseEntry::new(*oid, DIRECTORY_MODE),
+        };
+
+        for name in path.iter() {
+            if !entry.is_tree() {
+                return Ok(None);
+            }
+            entry = match self.load_tree(entry.oid())?.entries().get(name) {
+                Some(TreeEntry::Stored(child)) => child.clone(),
+                _ => return Ok(None),
+            };
+        }
+
+        Ok(Some(entry))
+    }
+
+    /// Compare two trees, returning every path whose entry differs between them.
+    pub fn tree_diff(&self, a: Option<&ObjectId>, b: Option<&ObjectId>) -> Result<TreeDiffChanges> {
+        let mut diff = TreeDiff::new(self);
+        diff.compare_oids(a, b, Path::new(""))?;
+        Ok(diff.into_changes())
+    }
+
+    fn serialize_object<O: Object + ?Sized>(object: &O) -> Vec<u8> {
         let mut content = Vec::new();
         let data = object.data();
         content.extend_from_slice(object.kind().as_bytes());
         content.extend_from_slice(b" ");
-        content.extend_from_slice(&data.len().to_string().as_bytes());
+        content.extend_from_slice(data.len().to_string().as_bytes());
         content.extend_from_slice(b"\0");
         content.extend_from_slice(&data);
+        content
+    }
 
-        let hash = Sha1::digest(&content);
-        let oid = ObjectId(hash.into());
-        self.write_object(&oid, &content)?;
-
-        Ok(oid)
+    /// An object's name, from its serialized content. An object that holds a SHA-1
+    /// collision attack, and so could be made to look like some other object, is refused.
+    fn hash_content(content: &[u8]) -> Result<ObjectId> {
+        hash::digest(content).map(ObjectId).map_err(|_| {
+            let kind = content.split(|&b| b == b' ').next().unwrap_or_default();
+            DatabaseError::Collision(String::from_utf8_lossy(kind).into_owned()).into()
+        })
     }
 
-    fn write_object(&self, oid: &ObjectId, content: &[u8]) -> Result<()> {
+    fn object_path(&self, oid: &ObjectId) -> Result<PathBuf> {
         let hash = oid.as_str()?;
-        let dir = &hash[0..2];
-        let obj = &hash[2..];
+        Ok(self.pathname.join(&hash[0..2]).join(&hash[2..]))
+    }
+
+    /// Read an object's type and contents, without parsing them. Objects are looked for
+    /// loose first, and then in packs.
+    pub fn read_object(&self, oid: &ObjectId) -> Result<(String, Vec<u8>)> {
+        let file = match self.fs.read(&self.object_path(oid)?) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return self
+                    .read_packed_object(oid)?
+                    .ok_or_else(|| DatabaseError::NoSuchObject(oid.to_string()).into());
+            }
+            Err(e) => return Err(DatabaseError::CouldNotWrite(e).into()),
+        };
 
-        let object_path = self.pathname.join(dir).join(obj);
+        let corrupt = |reason| DatabaseError::Corrupt(oid.to_string(), reason);
 
-        if object_path.exists() {
-            return Ok(());
+        let mut content = Vec::new();
+        ZlibDecoder::new(&file[..])
+            .read_to_end(&mut content)
+            .map_err(|_| corrupt("could not inflate it"))?;
+
+        // Check the content really is the object asked for before anything tries to parse
+        // it, so a damaged file is reported as such rather than as a malformed object.
+        if Self::hash_content(&content)? != *oid {
+            return Err(corrupt("its hash does not match its name").into());
         }
 
-        let dirname = object_path
-            .parent()
-            .ok_or_else(|| DatabaseError::NoParent(object_path.clone()))?;
+        let space = content.iter().position(|&b| b == b' ');
+        let null = content.iter().position(|&b| b == b'\0');
+        let (space, null) = match (space, null) {
+            (Some(space), Some(null)) if space < null => (space, null),
+            _ => return Err(corrupt("bad header").into()),
+        };
+
+        let kind = String::from_utf8_lossy(&content[..space]).into_owned();
+        if !["blob", "tree", "commit", "tag"].contains(&kind.as_str()) {
+            return Err(corrupt("unknown object type").into());
+        }
+
+        let size = std::str::from_utf8(&content[space + 1..null])
+            .ok()
+            .and_then(|size| size.parse::<usize>().ok());
+        let data = content.split_off(null + 1);
+        if size != Some(data.len()) {
+            return Err(corrupt("size in header does not match its content").into());
+        }
+
+        Ok((kind, data))
+    }
+
+    /// Read an object if the database has it, as the base for a delta in a thin pack.
+    pub fn find_object(&self, oid: &ObjectId) -> Result<Option<(ObjectType, Vec<u8>)>> {
+        if !self.contains(oid)? {
+            return Ok(None);
+        }
+
+        let (kind, data) = self.read_object(oid)?;
+        Ok(ObjectType::from_name(&kind).map(|kind| (kind, data)))
+    }
+
+    fn read_packed_object(&self, oid: &ObjectId) -> Result<Option<(String, Vec<u8>)>> {
+        for pack in self.mapped_packs()? {
+            if let Some(entry) = pack.index().find(oid) {
+                let (kind, data) = pack.read_object(entry.offset)?;
+                return Ok(Some((kind.name().to_owned(), data)));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Every pack in the database, mapped into memory. Packs stay mapped between calls, so
+    /// their indexes are only parsed once; packs that have since been deleted are dropped.
+    fn mapped_packs(&self) -> Result<Vec<Arc<MappedPack>>> {
+        let paths = self.pack_index_paths()?;
+        let mut mapped = self.mapped_packs.lock().unwrap();
+        mapped.retain(|path, _| paths.contains(path));
+
+        paths
+            .into_iter()
+            .map(|path| match mapped.get(&path) {
+                Some(pack) => Ok(Arc::clone(pack)),
+                None => {
+                    let pack = Arc::new(MappedPack::open(&self.fs, &path)?);
+                    mapped.insert(path, Arc::clone(&pack));
+                    Ok(pack)
+                }
+            })
+            .collect()
+    }
+
+    /// The directory objects are stored in.
+    pub fn pathname(&self) -> &Path {
+        &self.pathname
+    }
+
+    /// The directory packs are kept in.
+    pub fn pack_dir(&self) -> PathBuf {
+        self.pathname.join("pack")
+    }
+
+    /// Add a pack that has been read, and so checked, to the database, along with its
+    /// index. Returns the index's path.
+    pub fn store_pack(&self, bytes: &[u8], pack: &Pack) -> Result<PathBuf> {
+        let pack_dir = self.pack_dir();
+        self.fs.create_dir_all(&pack_dir)?;
+
+        let path = pack_dir.join(format!("pack-{}.idx", pack.checksum()));
+        self.fs.write(&path.with_extension("pack"), bytes)?;
+        self.fs.write(&path, &PackIndex::new(pack).to_bytes())?;
+
+        Ok(path)
+    }
+
+    /// Load the index of every pack in the database, along with the path to the index.
+    pub fn packs(&self) -> Result<Vec<(PathBuf, PackIndex)>> {
+        self.pack_index_paths()?
+            .into_iter()
+            .map(|path| {
+                let index = PackIndex::parse(&self.fs.read(&path)?)?;
+                Ok((path, index))
+            })
+            .collect()
+    }
+
+    /// The path to the index of every pack in the database, in order.
+    fn pack_index_paths(&self) -> Result<Vec<PathBuf>> {
+        let pack_dir = self.pack_dir();
+        let files = match self.fs.read_dir(&pack_dir) {
+            Ok(files) => files,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut paths = Vec::new();
+        for (file, _) in files {
+            let path = pack_dir.join(file);
+            if path.extension().is_some_and(|ext| ext == "idx") {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+
+        Ok(paths)
+    }
+
+    /// List the ObjectIds of every object stored loose, rather than in a pack.
+    pub fn loose_objects(&self) -> Result<Vec<ObjectId>> {
+        let mut oids = Vec::new();
+
+        for (dir, kind) in self.fs.read_dir(&self.pathname)? {
+            let prefix = dir.to_string_lossy().into_owned();
+            if prefix.len() != 2 || kind != FileKind::Dir {
+                continue;
+            }
+
+            for (file, _) in self.fs.read_dir(&self.pathname.join(dir))? {
+                let name = format!("{}{}", prefix, file.to_string_lossy());
+                if let Ok(oid) = ObjectId::from_hex(&name) {
+                    oids.push(oid);
+                }
+            }
+        }
+
+        oids.sort_by(|a, b| a.bytes().cmp(b.bytes()));
+        Ok(oids)
+    }
+
+    /// Estimate how many loose objects there are from how many are in one of the 256
+    /// directories they're spread over, which is much quicker than counting them all.
+    pub fn estimate_loose_objects(&self) -> Result<usize> {
+        let files = match self.fs.read_dir(&self.pathname.join("17")) {
+            Ok(files) => files,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut count = 0;
+        for (name, _) in files {
+            if ObjectId::from_hex(&format!("17{}", name.to_string_lossy())).is_ok() {
+                count += 1;
+            }
+        }
 
-        let temp_path = dirname.join(Database::generate_temp_name());
+        Ok(count * 256)
+    }
+
+    /// Delete the loose copies of objects that are also in a pack, returning how many were
+    /// deleted.
+    pub fn prune_packed(&self) -> Result<usize> {
+        let packs = self.packs()?;
+        let mut pruned = 0;
 
-        let file = File::create(&temp_path).or_else(|e| match e.kind() {
-            io::ErrorKind::NotFound => {
-                fs::create_dir_all(dirname).and_then(|_| File::create(&temp_path))
+        for oid in self.loose_objects()? {
+            if packs.iter().any(|(_, index)| index.find(&oid).is_some()) {
+                let path = self.object_path(&oid)?;
+                self.fs.remove_file(&path)?;
+                if let Some(dir) = path.parent() {
+                    // Leave the directory if there are other objects in it.
+                    let _ = self.fs.remove_dir(dir);
+                }
+                pruned += 1;
             }
-            _ => Err(e),
-        })?;
-        let mut encoder = ZlibEncoder::new(file, Compression::fast());
+        }
+
+        Ok(pruned)
+    }
+
+    /// Delete the loose objects that aren't in `keep` and were last written at or before
+    /// `expire`, given in seconds since the epoch, returning how many were deleted. Newer
+    /// objects are left alone, since another process may be about to refer to them.
+    pub fn prune_loose(&self, keep: &HashSet<ObjectId>, expire: i64) -> Result<usize> {
+        let mut pruned = 0;
+
+        for oid in self.loose_objects()? {
+            if keep.contains(&oid) {
+                continue;
+            }
+
+            let path = self.object_path(&oid)?;
+            if self.fs.stat(&path)?.mtime > expire {
+                continue;
+            }
+
+            self.fs.remove_file(&path)?;
+            if let Some(dir) = path.parent() {
+                let _ = self.fs.remove_dir(dir);
+            }
+            pruned += 1;
+        }
 
+        Ok(pruned)
+    }
+
+    fn write_object(&self, oid: &ObjectId, content: &[u8]) -> Result<()> {
+        let object_path = self.object_path(oid)?;
+        if self.fs.exists(&object_path) {
+            return Ok(());
+        }
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::fast());
         encoder.write_all(content)?;
-        encoder.finish()?;
+        let compressed = encoder.finish()?;
+
+        self.write_compressed(&object_path, &compressed)
+    }
+
+    /// Write an object's compressed content to a temporary file beside where it belongs,
+    /// then move it into place, so that the object is never seen half-written.
+    fn write_compressed(&self, object_path: &Path, compressed: &[u8]) -> Result<()> {
+        let dirname = object_path
+            .parent()
+            .ok_or_else(|| DatabaseError::NoParent(object_path.to_owned()))?;
+
+        let temp_path = dirname.join(Self::generate_temp_name());
+
+        self.fs
+            .write(&temp_path, compressed)
+            .or_else(|e| match e.kind() {
+                io::ErrorKind::NotFound => self
+                    .fs
+                    .create_dir_all(dirname)
+                    .and_then(|_| self.fs.write(&temp_path, compressed)),
+                _ => Err(e),
+            })?;
 
-        std::fs::rename(temp_path, object_path)?;
+        self.fs.rename(&temp_path, object_path)?;
 
         Ok(())
     }
@@ -143,3 +796,64 @@ impl Database {
         String::from_utf8(blah).unwrap()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::vfs::MemoryFs;
+
+    #[test]
+    fn stores_a_batch() {
+        let fs = MemoryFs::new();
+        fs.create_dir_all(Path::new("/repo/.git/objects")).unwrap();
+        let database = Database::with_vfs(fs.clone(), "/repo/.git/objects");
+
+        let already = database.store(&Blob::new(b"blob 0".to_vec())).unwrap();
+        let blobs: Vec<_> = (0..PARALLEL_STORE_THRESHOLD * 2)
+            .map(|i| Blob::new(format!("blob {}", i % 100).into_bytes()))
+            .collect();
+
+        let oids = database
+            .store_batch(blobs.iter().map(|blob| blob as &dyn Object))
+            .unwrap();
+
+        assert_eq!(oids.len(), blobs.len());
+        assert_eq!(oids[0], already);
+        assert_eq!(oids[1], oids[101]);
+        for (blob, oid) in blobs.iter().zip(&oids) {
+            assert_eq!(database.hash_object(blob).unwrap(), *oid);
+            assert_eq!(
+                database.load_blob(oid).unwrap().to_bytestr(),
+                blob.to_bytestr()
+            );
+        }
+        assert_eq!(database.loose_objects().unwrap().len(), 100);
+    }
+
+    #[test]
+    fn parses_and_abbreviates_hex() {
+        let hex = "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391";
+        let oid: ObjectId = hex.parse().unwrap();
+        assert_eq!(oid.to_string(), hex);
+        assert_eq!(ObjectId::from_hex(&hex.to_uppercase()).unwrap(), oid);
+        assert_eq!(oid.short(7), "e69de29");
+        assert_eq!(oid.short(8), "e69de29b");
+        assert_eq!(oid.short(100), hex);
+        assert_eq!(oid.short(0), "");
+
+        for bad in [
+            "",
+            &hex[..39],
+            &format!("{}0", hex),
+            &format!("+{}", &hex[1..]),
+            &format!("g{}", &hex[1..]),
+            &format!("é{}", &hex[2..]),
+        ] {
+            assert!(ObjectId::from_hex(bad).is_err(), "{:?} parsed", bad);
+        }
+
+        assert!(ObjectId::NULL.is_null());
+        assert_eq!(ObjectId::NULL.to_string(), "0".repeat(ObjectId::HEX_LEN));
+        assert!(!oid.is_null());
+    }
+}
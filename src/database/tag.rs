@@ -0,0 +1,140 @@
+use std::borrow::Cow;
+
+use super::{Author, DatabaseError, Object, ObjectId};
+use crate::Result;
+
+/// An annotated tag: a named, signed-off pointer at another object, usually a commit.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Tag {
+    object: ObjectId,
+    target_kind: String,
+    name: String,
+    tagger: Option<Author>,
+    message: String,
+}
+
+impl Tag {
+    pub fn new(
+        object: ObjectId,
+        target_kind: &str,
+        name: &str,
+        tagger: Author,
+        message: String,
+    ) -> Self {
+        Self {
+            object,
+            target_kind: target_kind.to_owned(),
+            name: name.to_owned(),
+            tagger: Some(tagger),
+            message,
+        }
+    }
+
+    /// Parse a tag object's data, as read from the database.
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let bad = || DatabaseError::BadObject("tag");
+        let data = std::str::from_utf8(data).map_err(|_| bad())?;
+
+        let (headers, message) = data.split_once("\n\n").unwrap_or((data, ""));
+
+        let mut object = None;
+        let mut target_kind = None;
+        let mut name = None;
+        let mut tagger = None;
+
+        for line in headers.lines() {
+            let (key, value) = line.split_once(' ').ok_or_else(bad)?;
+            match key {
+                "object" => object = Some(ObjectId::from_hex(value)?),
+                "type" => target_kind = Some(value.to_owned()),
+                "tag" => name = Some(value.to_owned()),
+                "tagger" => tagger = Some(Author::parse(value.as_bytes())?),
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            object: object.ok_or_else(bad)?,
+            target_kind: target_kind.ok_or_else(bad)?,
+            name: name.ok_or_else(bad)?,
+            // Very old tags have no tagger.
+            tagger,
+            message: message.to_owned(),
+        })
+    }
+
+    /// Get a reference to the ObjectId of the object the tag points at, which may itself be
+    /// a tag.
+    pub fn object(&self) -> &ObjectId {
+        &self.object
+    }
+
+    /// The kind of object the tag points at, e.g. `commit`.
+    pub fn target_kind(&self) -> &str {
+        &self.target_kind
+    }
+
+    /// The tag's name, without `refs/tags/`.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get a reference to whoever made the tag, if it says.
+    pub fn tagger(&self) -> Option<&Author> {
+        self.tagger.as_ref()
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl Object for Tag {
+    fn data(&self) -> Cow<'_, [u8]> {
+        let mut data = vec![
+            format!("object {}", self.object),
+            format!("type {}", self.target_kind),
+            format!("tag {}", self.name),
+        ];
+        if let Some(tagger) = &self.tagger {
+            data.push(format!("tagger {}", tagger));
+        }
+        data.push(String::new());
+        data.push(self.message.to_owned());
+
+        Cow::Owned(data.join("\n").into_bytes())
+    }
+
+    fn kind(&self) -> &str {
+        "tag"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trips_byte_for_byte() {
+        let data = b"object e69de29bb2d1d6434b8b29ae775ad8c2e48c5391\n\
+type commit\n\
+tag v1.0\n\
+tagger T. Agger <tagger@example.com> 1600000000 +0100\n\
+\n\
+Version 1.0\n";
+
+        let tag = Tag::parse(data).unwrap();
+        assert_eq!(tag.target_kind(), "commit");
+        assert_eq!(tag.name(), "v1.0");
+        assert_eq!(tag.tagger().unwrap().name(), "T. Agger");
+        assert_eq!(tag.message(), "Version 1.0\n");
+        assert_eq!(&tag.data()[..], &data[..]);
+
+        let untagged = Tag::parse(
+            b"object e69de29bb2d1d6434b8b29ae775ad8c2e48c5391\ntype blob\ntag old\n\nOld\n",
+        )
+        .unwrap();
+        assert!(untagged.tagger().is_none());
+        assert!(Tag::parse(b"type commit\ntag v1.0\n\nNo object\n").is_err());
+    }
+}
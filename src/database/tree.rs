@@ -1,13 +1,15 @@
+use std::ffi::{OsStr, OsString};
+use std::io::Write;
 use std::{borrow::Cow, collections::BTreeMap, fs};
-use std::{ffi::OsString, os::unix::prelude::OsStrExt};
-use std::{os::unix::prelude::MetadataExt, path::PathBuf};
 
-use crate::database::{Object, ObjectId};
+use crate::database::{DatabaseEntry, DatabaseError, Object, ObjectId};
 use crate::index::entry::Entry;
+use crate::sys::{MetadataExt, OsStrExt};
 
 use crate::Result;
 
 #[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum EntryMode {
     Executable,
     Regular,
@@ -24,12 +26,16 @@ impl From<fs::Metadata> for EntryMode {
 }
 
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TreeEntry {
     Tree(Tree, Option<ObjectId>),
     Object(Entry),
+    /// An entry parsed from a tree that was loaded from the database.
+    Stored(DatabaseEntry),
 }
 
 #[derive(Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Tree {
     entries: BTreeMap<OsString, TreeEntry>,
 }
@@ -41,86 +47,187 @@ impl Tree {
         }
     }
 
+    /// Store every subtree, deepest first, by passing each to `func` once all of its own
+    /// subtrees have their ids, and return the id `func` gives this tree. It keeps its own
+    /// stack rather than recursing, so however deep the tree goes it can't overflow.
     pub fn traverse<F>(&mut self, func: &mut F) -> Result<ObjectId>
     where
         F: FnMut(&Tree) -> Result<ObjectId>,
     {
-        for entry in self.entries.values_mut() {
-            if let TreeEntry::Tree(tree, oid) = entry {
-                let tree_oid = tree.traverse(func)?;
-                *oid = Some(tree_oid);
+        // Each subtree is taken out of its parent while it's being worked on, and put back
+        // with its id once it has one. The frames hold the names of the subtrees still to do.
+        let root = std::mem::take(self);
+        let names = root.subtree_names();
+        let mut stack = vec![(root, None, names)];
+
+        loop {
+            let (tree, _, pending) = stack.last_mut().unwrap();
+            if let Some(name) = pending.pop() {
+                if let Some(TreeEntry::Tree(child, _)) = tree.entries.get_mut(&name) {
+                    let child = std::mem::take(child);
+                    let names = child.subtree_names();
+                    stack.push((child, Some(name), names));
+                }
+                continue;
+            }
+
+            let (mut tree, mut name, _) = stack.pop().unwrap();
+            let oid = match func(&tree) {
+                Ok(oid) => oid,
+                Err(e) => {
+                    // Put the tree back together as it was before giving up.
+                    while let Some((mut parent, parent_name, _)) = stack.pop() {
+                        if let Some(name) = name {
+                            parent.entries.insert(name, TreeEntry::Tree(tree, None));
+                        }
+                        tree = parent;
+                        name = parent_name;
+                    }
+                    *self = tree;
+                    return Err(e);
+                }
+            };
+
+            match (stack.last_mut(), name) {
+                (Some((parent, _, _)), Some(name)) => {
+                    parent
+                        .entries
+                        .insert(name, TreeEntry::Tree(tree, Some(oid)));
+                }
+                _ => {
+                    *self = tree;
+                    return Ok(oid);
+                }
             }
         }
+    }
 
-        func(self)
+    fn subtree_names(&self) -> Vec<OsString> {
+        self.entries
+            .iter()
+            .filter(|(_, entry)| matches!(entry, TreeEntry::Tree(..)))
+            .map(|(name, _)| name.clone())
+            .collect()
     }
 
-    pub fn build(mut entries: Vec<Entry>) -> Self {
-        entries.sort_by(|a, b| a.path().cmp(&b.path()));
-        let mut root = Tree::new();
+    /// Parse a tree object's data, as read from the database.
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let mut entries = BTreeMap::new();
+        let mut rest = data;
+
+        while !rest.is_empty() {
+            let space = rest
+                .iter()
+                .position(|&b| b == b' ')
+                .ok_or(DatabaseError::BadObject("tree"))?;
+            let mode = std::str::from_utf8(&rest[..space])
+                .ok()
+                .and_then(|s| u32::from_str_radix(s, 8).ok())
+                .ok_or(DatabaseError::BadObject("tree"))?;
+            rest = &rest[space + 1..];
+
+            let null = rest
+                .iter()
+                .position(|&b| b == b'\0')
+                .ok_or(DatabaseError::BadObject("tree"))?;
+            let name = OsStr::from_bytes(&rest[..null]).to_owned();
+            rest = &rest[null + 1..];
+
+            if rest.len() < 20 {
+                return Err(DatabaseError::BadObject("tree").into());
+            }
+            let mut oid = [0; 20];
+            oid.copy_from_slice(&rest[..20]);
+            rest = &rest[20..];
 
+            entries.insert(
+                name,
+                TreeEntry::Stored(DatabaseEntry::new(oid.into(), mode)),
+            );
+        }
+
+        Ok(Self { entries })
+    }
+
+    /// Get a reference to the tree's entries, keyed by name.
+    pub fn entries(&self) -> &BTreeMap<OsString, TreeEntry> {
+        &self.entries
+    }
+
+    pub fn build(entries: Vec<Entry>) -> Self {
+        let mut root = Tree::new();
         for entry in entries {
-            let parents = entry.parent_directories();
-            root.add_entry(parents, entry);
+            root.add_entry(entry);
         }
 
         root
     }
 
-    pub fn add_entry(&mut self, parents: Vec<PathBuf>, entry: Entry) {
-        if parents.is_empty() {
-            self.entries.insert(
-                entry.path().as_os_str().to_owned(),
-                TreeEntry::Object(entry),
-            );
-        } else {
-            let tree = self
-                .entries
-                .entry(parents[0].file_name().unwrap().to_owned())
-                .or_insert_with(|| TreeEntry::Tree(Tree::new(), None));
-
-            if let TreeEntry::Tree(tree, _) = tree {
-                tree.add_entry(
-                    parents.iter().skip(1).map(|c| c.to_owned()).collect(),
-                    entry,
-                )
+    /// Add an index entry under its path, making the directories above it as needed. An
+    /// entry whose parent directory is already a file here is left out.
+    pub fn add_entry(&mut self, entry: Entry) {
+        let path = entry.path().to_owned();
+        let mut components = path.iter().peekable();
+        let mut tree = self;
+
+        while let Some(name) = components.next() {
+            if components.peek().is_none() {
+                tree.entries
+                    .insert(name.to_owned(), TreeEntry::Object(entry));
+                return;
             }
+
+            if !tree.entries.contains_key(name) {
+                tree.entries
+                    .insert(name.to_owned(), TreeEntry::Tree(Tree::new(), None));
+            }
+            tree = match tree.entries.get_mut(name) {
+                Some(TreeEntry::Tree(subtree, _)) => subtree,
+                _ => return,
+            };
         }
     }
 }
 
-const DIRECTORY_MODE: u32 = 0o40000;
+pub(crate) const DIRECTORY_MODE: u32 = 0o40000;
 
-impl Object for Tree {
-    fn data(&self) -> Cow<[u8]> {
-        let data: Vec<u8> = self
-            .entries
-            .iter()
-            .flat_map(|(name, entry)| match &entry {
-                TreeEntry::Object(entry) => {
-                    let mut bytes = Vec::new();
-                    bytes.extend_from_slice(format!("{:o}", entry.mode()).as_bytes());
-                    bytes.extend_from_slice(b" ");
-                    bytes.extend_from_slice(name.as_bytes());
-                    bytes.push(b'\0');
-                    bytes.extend_from_slice(entry.oid().bytes());
-                    bytes
-                }
-                TreeEntry::Tree(_, oid) => {
-                    let mut bytes = Vec::new();
-                    bytes.extend_from_slice(format!("{:o}", DIRECTORY_MODE).as_bytes());
-                    bytes.extend_from_slice(b" ");
-                    bytes.extend_from_slice(name.as_bytes());
-                    bytes.push(b'\0');
-                    bytes.extend_from_slice(
-                        oid.as_ref()
-                            .expect("Fatal: Couldn't unwrap Tree's ObjectID")
-                            .bytes(),
-                    );
-                    bytes
+impl Drop for Tree {
+    /// Take the subtrees apart one level at a time, as dropping them in the usual way would
+    /// recurse once for every level, and overflow the stack on a deep enough tree.
+    fn drop(&mut self) {
+        if self.entries.is_empty() {
+            return;
+        }
+
+        let mut stack = vec![std::mem::take(&mut self.entries)];
+        while let Some(entries) = stack.pop() {
+            for entry in entries.into_values() {
+                if let TreeEntry::Tree(mut tree, _) = entry {
+                    stack.push(std::mem::take(&mut tree.entries));
                 }
-            })
-            .collect();
+            }
+        }
+    }
+}
+
+impl Object for Tree {
+    fn data(&self) -> Cow<'_, [u8]> {
+        let mut data = Vec::with_capacity(self.entries.len() * 48);
+        for (name, entry) in &self.entries {
+            let (mode, oid) = match entry {
+                TreeEntry::Object(entry) => (entry.mode(), entry.oid()),
+                TreeEntry::Stored(entry) => (entry.mode(), entry.oid()),
+                TreeEntry::Tree(_, oid) => (
+                    DIRECTORY_MODE,
+                    oid.as_ref()
+                        .expect("Fatal: Couldn't unwrap Tree's ObjectID"),
+                ),
+            };
+            write!(data, "{:o} ", mode).unwrap();
+            data.extend_from_slice(name.as_bytes());
+            data.push(b'\0');
+            data.extend_from_slice(oid.bytes());
+        }
         Cow::Owned(data)
     }
 
@@ -132,11 +239,19 @@ impl Object for Tree {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::database::Database;
+    use crate::vfs::{MemoryFs, Vfs};
+    use std::path::{Path, PathBuf};
+
+    fn entry(path: &str) -> Entry {
+        let metadata = fs::metadata("./Cargo.toml").unwrap().into();
+        Entry::new(&path, ObjectId([1; 20]), metadata)
+    }
 
     #[test]
     fn parent_directories() {
         {
-            let metadata = fs::metadata("./Cargo.toml").unwrap();
+            let metadata = fs::metadata("./Cargo.toml").unwrap().into();
             let entry = Entry::new(&r"bin/nested/jit", ObjectId([0; 20]), metadata);
 
             let parents = entry.parent_directories();
@@ -147,4 +262,75 @@ mod test {
             );
         }
     }
+
+    #[test]
+    fn builds_and_stores_nested_trees() {
+        let fs = MemoryFs::new();
+        fs.create_dir_all(Path::new("/objects")).unwrap();
+        let database = Database::with_vfs(fs, "/objects");
+
+        let mut root = Tree::build(vec![
+            entry("b/c/d.txt"),
+            entry("a.txt"),
+            entry("b/e.txt"),
+            entry("b/c/f.txt"),
+        ]);
+        let mut stored = Vec::new();
+        let oid = root
+            .traverse(&mut |tree| {
+                let oid = database.store(tree)?;
+                stored.push(oid);
+                Ok(oid)
+            })
+            .unwrap();
+
+        // Subtrees come before the trees that hold them, and the root comes last.
+        assert_eq!(stored.len(), 3);
+        assert_eq!(stored.last(), Some(&oid));
+        let listed: Vec<_> = database
+            .load_tree_list(Some(&oid))
+            .unwrap()
+            .into_keys()
+            .collect();
+        assert_eq!(
+            listed,
+            vec![
+                PathBuf::from("a.txt"),
+                PathBuf::from("b/c/d.txt"),
+                PathBuf::from("b/c/f.txt"),
+                PathBuf::from("b/e.txt"),
+            ]
+        );
+    }
+
+    #[test]
+    fn traverses_very_deep_trees() {
+        let path = vec!["d"; 20_000].join("/") + "/file";
+        let mut root = Tree::build(vec![entry(&path)]);
+
+        let mut count = 0;
+        root.traverse(&mut |_| {
+            count += 1;
+            Ok(ObjectId([count as u8; 20]))
+        })
+        .unwrap();
+        assert_eq!(count, 20_001);
+    }
+
+    #[test]
+    fn keeps_the_tree_whole_when_storing_fails() {
+        let mut root = Tree::build(vec![entry("a/b/c"), entry("a/d"), entry("e")]);
+        let result = root.traverse(
+            &mut |tree| match tree.entries().contains_key(OsStr::new("c")) {
+                true => Err(DatabaseError::BadObject("tree").into()),
+                false => Ok(ObjectId([2; 20])),
+            },
+        );
+
+        assert!(result.is_err());
+        assert_eq!(
+            root,
+            Tree::build(vec![entry("a/b/c"), entry("a/d"), entry("e")])
+        );
+    }
 }
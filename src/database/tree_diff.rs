@@ -0,0 +1,143 @@
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+use super::{Database, DatabaseEntry, ObjectId, ParsedObject, TreeEntry};
+use crate::vfs::{RealFs, Vfs};
+use crate::Result;
+
+/// Every path that differs between two trees, mapped to its entry on each side.
+/// Only blobs appear here, unless the diff was made with `top_level`: otherwise differing
+/// subtrees are expanded into the files they contain.
+pub type TreeDiffChanges = BTreeMap<PathBuf, (Option<DatabaseEntry>, Option<DatabaseEntry>)>;
+
+pub struct TreeDiff<'a, F: Vfs = RealFs> {
+    database: &'a Database<F>,
+    changes: TreeDiffChanges,
+    recursive: bool,
+}
+
+impl<'a, F: Vfs> TreeDiff<'a, F> {
+    pub fn new(database: &'a Database<F>) -> Self {
+        Self {
+            database,
+            changes: BTreeMap::new(),
+            recursive: true,
+        }
+    }
+
+    /// Record differing subtrees themselves, rather than the files in them, so that only
+    /// the entries at the top of the trees are compared.
+    pub fn top_level(mut self) -> Self {
+        self.recursive = false;
+        self
+    }
+
+    /// Compare two trees (or the trees of two commits), recording any differences
+    /// under the given path prefix.
+    pub fn compare_oids(
+        &mut self,
+        a: Option<&ObjectId>,
+        b: Option<&ObjectId>,
+        prefix: &Path,
+    ) -> Result<()> {
+        if a == b {
+            return Ok(());
+        }
+
+        let a_entries = match a {
+            Some(oid) => self.oid_to_entries(oid)?,
+            None => BTreeMap::new(),
+        };
+        let b_entries = match b {
+            Some(oid) => self.oid_to_entries(oid)?,
+            None => BTreeMap::new(),
+        };
+
+        self.detect_deletions(&a_entries, &b_entries, prefix)?;
+        self.detect_additions(&a_entries, &b_entries, prefix)?;
+
+        Ok(())
+    }
+
+    pub fn into_changes(self) -> TreeDiffChanges {
+        self.changes
+    }
+
+    fn oid_to_entries(&self, oid: &ObjectId) -> Result<BTreeMap<OsString, DatabaseEntry>> {
+        let tree = match self.database.load(oid)? {
+            ParsedObject::Commit(commit) => self.database.load_tree(commit.tree())?,
+            ParsedObject::Tree(tree) => tree,
+            ParsedObject::Tag(tag) => return self.oid_to_entries(tag.object()),
+            ParsedObject::Blob(_) => return Ok(BTreeMap::new()),
+        };
+
+        let entries = tree
+            .entries()
+            .iter()
+            .filter_map(|(name, entry)| match entry {
+                TreeEntry::Stored(entry) => Some((name.to_owned(), entry.clone())),
+                _ => None,
+            })
+            .collect();
+
+        Ok(entries)
+    }
+
+    fn detect_deletions(
+        &mut self,
+        a: &BTreeMap<OsString, DatabaseEntry>,
+        b: &BTreeMap<OsString, DatabaseEntry>,
+        prefix: &Path,
+    ) -> Result<()> {
+        for (name, entry) in a {
+            let path = prefix.join(name);
+            let other = b.get(name);
+
+            if Some(entry) == other {
+                continue;
+            }
+            if !self.recursive {
+                self.changes
+                    .insert(path, (Some(entry.clone()), other.cloned()));
+                continue;
+            }
+
+            let tree_a = Some(entry).filter(|e| e.is_tree()).map(|e| e.oid());
+            let tree_b = other.filter(|e| e.is_tree()).map(|e| e.oid());
+            self.compare_oids(tree_a, tree_b, &path)?;
+
+            let blob_a = Some(entry).filter(|e| !e.is_tree()).cloned();
+            let blob_b = other.filter(|e| !e.is_tree()).cloned();
+            if blob_a.is_some() || blob_b.is_some() {
+                self.changes.insert(path, (blob_a, blob_b));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn detect_additions(
+        &mut self,
+        a: &BTreeMap<OsString, DatabaseEntry>,
+        b: &BTreeMap<OsString, DatabaseEntry>,
+        prefix: &Path,
+    ) -> Result<()> {
+        for (name, entry) in b {
+            if a.contains_key(name) {
+                continue;
+            }
+
+            let path = prefix.join(name);
+            if entry.is_tree() && self.recursive {
+                self.compare_oids(None, Some(entry.oid()), &path)?;
+            } else {
+                self.changes.insert(path, (None, Some(entry.clone())));
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,152 @@
+use std::{collections::HashMap, path::Path};
+
+use regex::Regex;
+
+use super::DiffError;
+use crate::attributes::{AttributeValue, Attributes};
+use crate::config::Config;
+use crate::Result;
+
+/// Header patterns for the diff drivers nit knows about without any configuration.
+fn builtin_pattern(driver: &str) -> Option<&'static str> {
+    let pattern = match driver {
+        "rust" => concat!(
+            r#"^[\t ]*((pub(\([^)]+\))?[\t ]+)?((async|const|unsafe|extern([\t ]+"[^"]+"))[\t ]+)?"#,
+            r#"(struct|enum|union|mod|trait|fn|impl|macro_rules!)[<\t ]+[^;]*)$"#
+        ),
+        "python" => r"^[\t ]*((class|(async[\t ]+)?def)[\t ].*)$",
+        "ruby" => r"^[\t ]*((class|module|def)[\t ].*)$",
+        "golang" => r"^[\t ]*((func|type)[\t ].*)$",
+        _ => return None,
+    };
+
+    Some(pattern)
+}
+
+/// Recognises the lines that start a function (or class, or section), which are shown after
+/// each hunk's header and mark the edges of the hunks shown with `--function-context`.
+#[derive(Debug, Clone, Default)]
+pub struct FunctionMatcher {
+    /// Patterns from `diff.<driver>.xfuncname`, one per line. Lines matching a negated
+    /// pattern (starting with `!`) are never headers. With no patterns, any line starting
+    /// with a letter, `_` or `$` is a header.
+    patterns: Vec<(bool, Regex)>,
+}
+
+impl FunctionMatcher {
+    fn parse(driver: &str, source: &str) -> Result<Self> {
+        let patterns = source
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                let (negated, line) = match line.strip_prefix('!') {
+                    Some(line) => (true, line),
+                    None => (false, line),
+                };
+                Regex::new(line)
+                    .map(|regex| (negated, regex))
+                    .map_err(|e| DiffError::BadFunctionPattern(driver.to_owned(), e.to_string()))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self { patterns })
+    }
+
+    /// If the line is a function header, the text to show for it.
+    pub fn header(&self, text: &str) -> Option<String> {
+        let text = text.trim_end_matches(['\r', '\n'].as_ref());
+
+        if self.patterns.is_empty() {
+            let starts_function = text
+                .chars()
+                .next()
+                .is_some_and(|c| c.is_alphabetic() || c == '_' || c == '$');
+            return Some(text.to_owned()).filter(|_| starts_function);
+        }
+
+        for (negated, regex) in &self.patterns {
+            if let Some(captures) = regex.captures(text) {
+                if *negated {
+                    return None;
+                }
+                let matched = captures.get(1).or_else(|| captures.get(0))?;
+                return Some(matched.as_str().trim_end().to_owned());
+            }
+        }
+
+        None
+    }
+
+    pub fn matches(&self, text: &str) -> bool {
+        self.header(text).is_some()
+    }
+}
+
+/// Picks the function header patterns for each path from its `diff` attribute, using
+/// `diff.<driver>.xfuncname` from the config or nit's builtin patterns for the driver.
+#[derive(Debug, Clone, Default)]
+pub struct FunctionNames {
+    attributes: Attributes,
+    drivers: HashMap<String, FunctionMatcher>,
+}
+
+impl FunctionNames {
+    pub fn new(attributes: Attributes, config: &Config) -> Result<Self> {
+        let mut drivers = HashMap::new();
+
+        for driver in attributes.values_of("diff") {
+            if drivers.contains_key(driver) {
+                continue;
+            }
+
+            let source = match config.get(&format!("diff.{}.xfuncname", driver))? {
+                Some(source) => source,
+                None => match builtin_pattern(driver) {
+                    Some(source) => source.to_owned(),
+                    None => continue,
+                },
+            };
+
+            drivers.insert(driver.to_owned(), FunctionMatcher::parse(driver, &source)?);
+        }
+
+        Ok(Self {
+            attributes,
+            drivers,
+        })
+    }
+
+    /// The matcher to use for a path, relative to the workspace root.
+    pub fn matcher_for(&self, path: &Path) -> FunctionMatcher {
+        match self.attributes.get(path, "diff") {
+            Some(AttributeValue::Value(driver)) => {
+                self.drivers.get(driver).cloned().unwrap_or_default()
+            }
+            _ => FunctionMatcher::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn recognises_function_headers() {
+        let rust = FunctionMatcher::parse("rust", builtin_pattern("rust").unwrap()).unwrap();
+        assert_eq!(
+            rust.header("    pub fn new(path: &Path) -> Self {\n")
+                .as_deref(),
+            Some("pub fn new(path: &Path) -> Self {")
+        );
+        assert_eq!(rust.header("    let x = 1;\n"), None);
+
+        let custom = FunctionMatcher::parse("custom", "!^skip\n^(s.*)$").unwrap();
+        assert_eq!(custom.header("section one").as_deref(), Some("section one"));
+        assert_eq!(custom.header("skip this"), None);
+
+        let default = FunctionMatcher::default();
+        assert!(default.matches("int main(void)\n"));
+        assert!(!default.matches("\treturn 0;\n"));
+    }
+}
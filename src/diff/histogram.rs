@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+
+use super::{diff_trimmed, equal, myers::Myers, Edit, Line};
+
+/// Lines that appear more often than this are too common to be useful anchors.
+const MAX_CHAIN_LENGTH: usize = 64;
+
+/// Histogram diff: find the longest run of matching lines that contains the rarest line
+/// the two versions share, keep it, and diff the lines either side of it the same way.
+pub(super) fn diff(a: &[Line], b: &[Line]) -> Vec<Edit> {
+    let mut edits = Vec::new();
+    histogram(a, b, &mut edits);
+    edits
+}
+
+fn histogram(a: &[Line], b: &[Line], edits: &mut Vec<Edit>) {
+    diff_trimmed(a, b, edits, |a, b, edits| match best_region(a, b) {
+        Some(region) => {
+            histogram(&a[..region.a_start], &b[..region.b_start], edits);
+            for i in 0..region.length {
+                edits.push(equal(&a[region.a_start + i], &b[region.b_start + i]));
+            }
+            histogram(
+                &a[region.a_start + region.length..],
+                &b[region.b_start + region.length..],
+                edits,
+            );
+        }
+        None => edits.extend(Myers::new(a, b).diff()),
+    });
+}
+
+/// A run of lines that match on both sides.
+struct Region {
+    a_start: usize,
+    b_start: usize,
+    length: usize,
+    /// How often the rarest line in the run appears in `a`.
+    rarity: usize,
+}
+
+fn best_region(a: &[Line], b: &[Line]) -> Option<Region> {
+    let mut positions: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (i, line) in a.iter().enumerate() {
+        positions.entry(&line.key).or_default().push(i);
+    }
+    let count = |line: &Line| positions.get(line.key.as_str()).map_or(0, Vec::len);
+
+    let mut best: Option<Region> = None;
+
+    for (b_index, line) in b.iter().enumerate() {
+        let a_indexes = match positions.get(line.key.as_str()) {
+            Some(a_indexes) if a_indexes.len() <= MAX_CHAIN_LENGTH => a_indexes,
+            _ => continue,
+        };
+
+        for &a_index in a_indexes {
+            let (mut a_start, mut b_start) = (a_index, b_index);
+            while a_start > 0 && b_start > 0 && a[a_start - 1].key == b[b_start - 1].key {
+                a_start -= 1;
+                b_start -= 1;
+            }
+
+            let (mut a_end, mut b_end) = (a_index + 1, b_index + 1);
+            while a_end < a.len() && b_end < b.len() && a[a_end].key == b[b_end].key {
+                a_end += 1;
+                b_end += 1;
+            }
+
+            let region = Region {
+                a_start,
+                b_start,
+                length: a_end - a_start,
+                rarity: a[a_start..a_end].iter().map(count).min().unwrap_or(0),
+            };
+
+            let better = best.as_ref().is_none_or(|best| {
+                region.rarity < best.rarity
+                    || (region.rarity == best.rarity && region.length > best.length)
+            });
+            if better {
+                best = Some(region);
+            }
+        }
+    }
+
+    best
+}
@@ -0,0 +1,285 @@
+use std::fmt::Display;
+
+use super::{DiffOptions, Edit, EditKind, FunctionMatcher, Line};
+
+/// How many unchanged lines to show either side of a change, unless told otherwise.
+pub const HUNK_CONTEXT: usize = 3;
+
+/// A run of edits that are close enough together to be shown as one block,
+/// along with the unchanged lines around them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hunk {
+    pub a_start: usize,
+    pub b_start: usize,
+    pub edits: Vec<Edit>,
+    /// The header of the function the hunk starts in, if one could be found.
+    pub function: Option<String>,
+}
+
+impl Hunk {
+    /// Group a full edit script into hunks, dropping unchanged lines that are more than
+    /// `options.context` lines from any change. With `options.function_context`, every
+    /// change is shown along with the whole of the function it's in.
+    pub fn filter(
+        edits: Vec<Edit>,
+        options: &DiffOptions,
+        functions: &FunctionMatcher,
+    ) -> Vec<Hunk> {
+        let context = options.context;
+        let is_header = |edit: &Edit| functions.matches(&edit.line().text);
+        let mut keep = vec![false; edits.len()];
+
+        for (i, edit) in edits.iter().enumerate() {
+            if edit.kind == EditKind::Equal {
+                continue;
+            }
+            // Blank lines coming and going can still be shown as context for other changes,
+            // but don't make a hunk of their own.
+            if options.ignore_blank_lines && edit.line().is_blank() {
+                continue;
+            }
+
+            let mut start = i.saturating_sub(context);
+            let mut end = (i + context + 1).min(edits.len());
+
+            if options.function_context {
+                let function_start = edits[..=i].iter().rposition(is_header).unwrap_or(0);
+                let function_end = edits[i + 1..]
+                    .iter()
+                    .position(is_header)
+                    .map_or(edits.len(), |offset| i + 1 + offset);
+                start = start.min(function_start);
+                end = end.max(function_end);
+            }
+
+            keep[start..end].iter_mut().for_each(|kept| *kept = true);
+        }
+
+        let mut hunks = Vec::new();
+        let mut offset = 0;
+
+        while offset < edits.len() {
+            if !keep[offset] {
+                offset += 1;
+                continue;
+            }
+
+            let end = keep[offset..]
+                .iter()
+                .position(|kept| !kept)
+                .map_or(edits.len(), |length| offset + length);
+
+            hunks.push(Hunk::build(&edits, offset, end, functions));
+            offset = end;
+        }
+
+        hunks
+    }
+
+    fn build(edits: &[Edit], start: usize, end: usize, functions: &FunctionMatcher) -> Hunk {
+        // A side with no lines in the hunk is numbered from the line before it.
+        let start_line = |line: fn(&Edit) -> Option<usize>| {
+            edits[start..end]
+                .iter()
+                .find_map(line)
+                .or_else(|| edits[..start].iter().rev().find_map(line))
+                .unwrap_or(0)
+        };
+        let a_start = start_line(|edit| edit.a_line.as_ref().map(|line| line.number));
+        let b_start = start_line(|edit| edit.b_line.as_ref().map(|line| line.number));
+
+        let function = edits[..start]
+            .iter()
+            .rev()
+            .filter(|edit| edit.kind != EditKind::Insert)
+            .find_map(|edit| functions.header(&edit.line().text));
+
+        Hunk {
+            a_start,
+            b_start,
+            edits: edits[start..end].to_vec(),
+            function,
+        }
+    }
+
+    /// The `@@ -a,b +c,d @@` line that introduces the hunk, followed by the function it's in.
+    pub fn header(&self) -> String {
+        let a_lines = self.edits.iter().filter(|e| e.a_line.is_some()).count();
+        let b_lines = self.edits.iter().filter(|e| e.b_line.is_some()).count();
+
+        let header = format!(
+            "@@ -{} +{} @@",
+            Hunk::range(self.a_start, a_lines),
+            Hunk::range(self.b_start, b_lines)
+        );
+
+        match &self.function {
+            Some(function) => format!("{} {}", header, function),
+            None => header,
+        }
+    }
+
+    /// Split the hunk into one hunk for each run of changes in it, each keeping the unchanged
+    /// lines either side of it. Unchanged lines between two runs appear in both hunks.
+    pub fn split(&self) -> Vec<Hunk> {
+        let mut runs: Vec<(usize, usize)> = Vec::new();
+        for (i, edit) in self.edits.iter().enumerate() {
+            match runs.last_mut() {
+                _ if edit.kind == EditKind::Equal => {}
+                Some((_, end)) if *end == i => *end = i + 1,
+                _ => runs.push((i, i + 1)),
+            }
+        }
+
+        if runs.len() < 2 {
+            return vec![self.clone()];
+        }
+
+        runs.iter()
+            .enumerate()
+            .map(|(i, _)| {
+                let from = if i == 0 { 0 } else { runs[i - 1].1 };
+                let to = runs.get(i + 1).map_or(self.edits.len(), |run| run.0);
+                let start_line = |line: fn(&Edit) -> Option<usize>, fallback: usize| {
+                    self.edits[from..]
+                        .iter()
+                        .find_map(line)
+                        .or_else(|| self.edits[..from].iter().rev().find_map(line))
+                        .unwrap_or(fallback)
+                };
+
+                Hunk {
+                    a_start: start_line(|e| e.a_line.as_ref().map(|l| l.number), self.a_start),
+                    b_start: start_line(|e| e.b_line.as_ref().map(|l| l.number), self.b_start),
+                    edits: self.edits[from..to].to_vec(),
+                    function: self.function.clone(),
+                }
+            })
+            .collect()
+    }
+
+    /// Rebuild the hunk from a copy of it that has been edited by hand, as `Display` shows it.
+    /// Lines can be added, removed or turned into context, but the old side of the hunk must
+    /// be left as it was, so `None` is returned if it has changed.
+    pub fn edited(&self, text: &str) -> Option<Hunk> {
+        let mut lines: Vec<(EditKind, String)> = Vec::new();
+        for text in text.split_inclusive('\n') {
+            let kind = match text.chars().next() {
+                Some(' ') | Some('\n') => EditKind::Equal,
+                Some('-') => EditKind::Delete,
+                Some('+') => EditKind::Insert,
+                Some('\\') => {
+                    // "\ No newline at end of file" applies to the line before it.
+                    if let Some((_, text)) = lines.last_mut() {
+                        text.truncate(text.trim_end_matches('\n').len());
+                    }
+                    continue;
+                }
+                _ => continue,
+            };
+            let text = text.strip_prefix(kind.symbol()).unwrap_or(text);
+            lines.push((kind, text.to_owned()));
+        }
+
+        let mut a_lines = self.edits.iter().filter_map(|edit| edit.a_line.as_ref());
+        let mut b_number = self.b_start;
+        let mut edits = Vec::new();
+
+        for (kind, text) in lines {
+            let a_line = match kind {
+                EditKind::Insert => None,
+                _ => Some(a_lines.next().filter(|line| line.text == text)?.clone()),
+            };
+            let b_line = match kind {
+                EditKind::Delete => None,
+                _ => {
+                    b_number += 1;
+                    Some(Line {
+                        number: b_number - 1,
+                        key: text.clone(),
+                        text,
+                    })
+                }
+            };
+
+            edits.push(Edit {
+                kind,
+                a_line,
+                b_line,
+            });
+        }
+
+        if a_lines.next().is_some() {
+            return None;
+        }
+
+        Some(Hunk {
+            edits,
+            ..self.clone()
+        })
+    }
+
+    fn range(start: usize, count: usize) -> String {
+        match count {
+            0 => format!("{},0", start),
+            1 => format!("{}", start),
+            _ => format!("{},{}", start, count),
+        }
+    }
+}
+
+impl Display for Hunk {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}", self.header())?;
+        for edit in &self.edits {
+            writeln!(f, "{}", edit)?;
+            if !edit.line().text.ends_with('\n') {
+                writeln!(f, "\\ No newline at end of file")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Apply a set of hunks, in order, to the old version of a file. Hunks that overlap
+/// because they share unchanged lines are applied as if they were one.
+pub fn apply_hunks(a: &[Line], hunks: &[Hunk]) -> String {
+    let mut result = String::new();
+    let mut next = 0;
+
+    for hunk in hunks {
+        let start = hunk
+            .edits
+            .iter()
+            .find_map(|edit| edit.a_line.as_ref().map(|line| line.number - 1))
+            .unwrap_or(hunk.a_start);
+
+        for line in a.get(next..start).unwrap_or_default() {
+            result.push_str(&line.text);
+        }
+        next = next.max(start);
+
+        for edit in &hunk.edits {
+            match (&edit.a_line, &edit.b_line) {
+                (Some(line), _) if line.number <= next => {}
+                (Some(line), b_line) => {
+                    for line in &a[next..line.number - 1] {
+                        result.push_str(&line.text);
+                    }
+                    next = line.number;
+                    if let Some(b_line) = b_line {
+                        result.push_str(&b_line.text);
+                    }
+                }
+                (None, Some(line)) => result.push_str(&line.text),
+                (None, None) => {}
+            }
+        }
+    }
+
+    for line in a.get(next..).unwrap_or_default() {
+        result.push_str(&line.text);
+    }
+
+    result
+}
@@ -0,0 +1,499 @@
+use std::{fmt::Display, path::Path, str::FromStr};
+
+use thiserror::Error;
+
+mod function;
+mod histogram;
+mod hunk;
+mod myers;
+mod patch;
+mod patch_id;
+mod patience;
+mod raw;
+
+pub use function::*;
+pub use hunk::*;
+pub use patch::*;
+pub use patch_id::*;
+pub use raw::*;
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum DiffError {
+    #[error("unknown diff algorithm: '{0}'")]
+    UnknownAlgorithm(String),
+    #[error("invalid function header pattern for diff driver '{0}': {1}")]
+    BadFunctionPattern(String, String),
+}
+
+/// The strategy used to line up the two versions of a file.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Algorithm {
+    /// The classic shortest-edit-script search. Fast, but matches up blank lines and
+    /// braces eagerly, which makes a mess of moved blocks.
+    #[default]
+    Myers,
+    /// Anchor the diff on lines that appear exactly once in each version.
+    Patience,
+    /// Like patience, but anchor on the rarest lines even if they aren't unique.
+    Histogram,
+}
+
+impl FromStr for Algorithm {
+    type Err = DiffError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "myers" | "default" | "minimal" => Ok(Algorithm::Myers),
+            "patience" => Ok(Algorithm::Patience),
+            "histogram" => Ok(Algorithm::Histogram),
+            _ => Err(DiffError::UnknownAlgorithm(s.to_owned())),
+        }
+    }
+}
+
+/// Settings that change how diffs are computed and displayed.
+#[derive(Debug, Clone)]
+pub struct DiffOptions {
+    pub algorithm: Algorithm,
+    /// How many unchanged lines to show around each change.
+    pub context: usize,
+    /// Whether to show the whole function around each change.
+    pub function_context: bool,
+    pub functions: FunctionNames,
+    /// Which differences in whitespace to treat as changes.
+    pub whitespace: Whitespace,
+    /// Whether to hide changes that only add or remove blank lines.
+    pub ignore_blank_lines: bool,
+    /// Whether to quote paths with bytes outside ASCII in them, as `core.quotePath` says.
+    pub quote_non_ascii: bool,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        Self {
+            algorithm: Algorithm::default(),
+            context: HUNK_CONTEXT,
+            function_context: false,
+            functions: FunctionNames::default(),
+            whitespace: Whitespace::default(),
+            ignore_blank_lines: false,
+            quote_non_ascii: true,
+        }
+    }
+}
+
+/// How much attention to pay to whitespace when deciding whether two lines are the same.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Whitespace {
+    /// Lines must match exactly.
+    #[default]
+    Exact,
+    /// Ignore whitespace at the end of lines, and treat runs of whitespace as equal
+    /// to each other, as `-b` does.
+    IgnoreChange,
+    /// Ignore whitespace altogether, as `-w` does.
+    IgnoreAll,
+}
+
+impl Whitespace {
+    fn normalize(&self, text: &str) -> String {
+        match self {
+            Whitespace::Exact => text.to_owned(),
+            Whitespace::IgnoreChange => {
+                let mut key = String::with_capacity(text.len());
+                for word in text.split_whitespace() {
+                    if !key.is_empty() {
+                        key.push(' ');
+                    }
+                    key.push_str(word);
+                }
+                if text.starts_with(char::is_whitespace) && !key.is_empty() {
+                    key.insert(0, ' ');
+                }
+                key
+            }
+            Whitespace::IgnoreAll => text.chars().filter(|c| !c.is_whitespace()).collect(),
+        }
+    }
+}
+
+/// A single line of a file being diffed, numbered from 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    pub number: usize,
+    /// The line's text, including its trailing newline if it has one.
+    pub text: String,
+    /// What the line is compared by: its text, normalized according to the whitespace rules.
+    key: String,
+}
+
+impl Line {
+    /// Whether the line has nothing but whitespace in it.
+    pub fn is_blank(&self) -> bool {
+        self.text.trim().is_empty()
+    }
+}
+
+/// Split a file's contents into lines. Invalid UTF-8 is replaced rather than rejected.
+pub fn lines(data: &[u8]) -> Vec<Line> {
+    lines_with(data, Whitespace::Exact)
+}
+
+/// Split a file's contents into lines that will be compared according to the given rules.
+pub fn lines_with(data: &[u8], whitespace: Whitespace) -> Vec<Line> {
+    String::from_utf8_lossy(data)
+        .split_inclusive('\n')
+        .enumerate()
+        .map(|(i, text)| Line {
+            number: i + 1,
+            text: text.to_owned(),
+            key: whitespace.normalize(text),
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EditKind {
+    Equal,
+    Insert,
+    Delete,
+}
+
+impl EditKind {
+    pub fn symbol(&self) -> char {
+        match self {
+            EditKind::Equal => ' ',
+            EditKind::Insert => '+',
+            EditKind::Delete => '-',
+        }
+    }
+}
+
+/// One step in turning the old version of a file into the new one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edit {
+    pub kind: EditKind,
+    pub a_line: Option<Line>,
+    pub b_line: Option<Line>,
+}
+
+impl Edit {
+    /// The line this edit refers to: the new line for insertions, otherwise the old one.
+    pub fn line(&self) -> &Line {
+        match (&self.a_line, &self.b_line) {
+            (_, Some(line)) if self.kind == EditKind::Insert => line,
+            (Some(line), _) => line,
+            (None, Some(line)) => line,
+            (None, None) => unreachable!("an edit always has at least one line"),
+        }
+    }
+}
+
+impl Display for Edit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = &self.line().text;
+        write!(f, "{}{}", self.kind.symbol(), text.trim_end_matches('\n'))
+    }
+}
+
+/// Find a list of edits that turns `a` into `b`, using the given algorithm.
+pub fn diff(a: &[Line], b: &[Line], algorithm: Algorithm) -> Vec<Edit> {
+    match algorithm {
+        Algorithm::Myers => myers::Myers::new(a, b).diff(),
+        Algorithm::Patience => patience::diff(a, b),
+        Algorithm::Histogram => histogram::diff(a, b),
+    }
+}
+
+/// How alike two versions of a file are, as a percentage: the share of the larger one's
+/// bytes that are in lines the two have in common. Files that are both empty are the same.
+pub fn similarity(a: &[u8], b: &[u8]) -> usize {
+    let larger = a.len().max(b.len());
+    if larger == 0 {
+        return 100;
+    }
+
+    let common: usize = diff(&lines(a), &lines(b), Algorithm::Myers)
+        .iter()
+        .filter(|edit| edit.kind == EditKind::Equal)
+        .map(|edit| edit.line().text.len())
+        .sum();
+    common * 100 / larger
+}
+
+/// Diff two versions of the file at `path` and group the changes into hunks.
+pub fn diff_hunks(a: &[u8], b: &[u8], path: &Path, options: &DiffOptions) -> Vec<Hunk> {
+    let a = lines_with(a, options.whitespace);
+    let b = lines_with(b, options.whitespace);
+
+    Hunk::filter(
+        diff(&a, &b, options.algorithm),
+        options,
+        &options.functions.matcher_for(path),
+    )
+}
+
+fn equal(a_line: &Line, b_line: &Line) -> Edit {
+    Edit {
+        kind: EditKind::Equal,
+        a_line: Some(a_line.clone()),
+        b_line: Some(b_line.clone()),
+    }
+}
+
+/// Split off the lines the two versions start and end with in common, returning the
+/// number of leading and trailing matches.
+fn common_ends(a: &[Line], b: &[Line]) -> (usize, usize) {
+    let prefix = a.iter().zip(b).take_while(|(a, b)| a.key == b.key).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(a, b)| a.key == b.key)
+        .count();
+
+    (prefix, suffix)
+}
+
+/// Diff the middle of two ranges with `middle`, keeping their common ends as they are.
+fn diff_trimmed(
+    a: &[Line],
+    b: &[Line],
+    edits: &mut Vec<Edit>,
+    middle: impl FnOnce(&[Line], &[Line], &mut Vec<Edit>),
+) {
+    let (prefix, suffix) = common_ends(a, b);
+
+    for (a_line, b_line) in a[..prefix].iter().zip(b) {
+        edits.push(equal(a_line, b_line));
+    }
+
+    middle(
+        &a[prefix..a.len() - suffix],
+        &b[prefix..b.len() - suffix],
+        edits,
+    );
+
+    for (a_line, b_line) in a[a.len() - suffix..].iter().zip(&b[b.len() - suffix..]) {
+        edits.push(equal(a_line, b_line));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn render_with(a: &str, b: &str, algorithm: Algorithm) -> Vec<String> {
+        diff(&lines(a.as_bytes()), &lines(b.as_bytes()), algorithm)
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    fn render(a: &str, b: &str) -> Vec<String> {
+        render_with(a, b, Algorithm::Myers)
+    }
+
+    #[test]
+    fn scores_similarity() {
+        assert_eq!(similarity(b"", b""), 100);
+        assert_eq!(similarity(b"a\nb\n", b"a\nb\n"), 100);
+        assert_eq!(similarity(b"a\nb\n", b""), 0);
+        assert_eq!(similarity(b"a\nb\nc\nd\n", b"a\nb\nX\nY\n"), 50);
+        assert_eq!(similarity(b"a\nb\n", b"a\nb\nc\nd\n"), 50);
+    }
+
+    #[test]
+    fn finds_the_shortest_edit_script() {
+        let a = "A\nB\nC\nA\nB\nB\nA\n";
+        let b = "C\nB\nA\nB\nA\nC\n";
+
+        assert_eq!(
+            render(a, b),
+            vec!["-A", "-B", " C", "+B", " A", " B", "-B", " A", "+C"]
+        );
+    }
+
+    #[test]
+    fn groups_edits_into_hunks() {
+        let a: String = (1..=20).map(|n| format!("line {}\n", n)).collect();
+        let b = a
+            .replace("line 3\n", "line three\n")
+            .replace("line 18\n", "");
+
+        let headers = |options: &DiffOptions| {
+            diff_hunks(a.as_bytes(), b.as_bytes(), Path::new("lines"), options)
+                .iter()
+                .map(Hunk::header)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            headers(&DiffOptions::default()),
+            vec!["@@ -1,6 +1,6 @@", "@@ -15,6 +15,5 @@ line 14"]
+        );
+
+        let options = DiffOptions {
+            context: 0,
+            ..DiffOptions::default()
+        };
+        assert_eq!(
+            headers(&options),
+            vec!["@@ -3 +3 @@ line 2", "@@ -18 +17,0 @@ line 17"]
+        );
+
+        let options = DiffOptions {
+            context: 7,
+            ..DiffOptions::default()
+        };
+        assert_eq!(headers(&options), vec!["@@ -1,20 +1,19 @@"]);
+    }
+
+    #[test]
+    fn shows_whole_functions_with_function_context() {
+        let a = "fn one() {\n    1\n    2\n    3\n    4\n    5\n}\n\nfn two() {\n    2\n}\n";
+        let b = a.replace("    4\n", "    four\n");
+
+        let options = DiffOptions {
+            context: 1,
+            function_context: true,
+            ..DiffOptions::default()
+        };
+        let hunks = diff_hunks(a.as_bytes(), b.as_bytes(), Path::new("lines"), &options);
+
+        assert_eq!(hunks.len(), 1);
+        assert_eq!(hunks[0].header(), "@@ -1,8 +1,8 @@");
+    }
+
+    #[test]
+    fn anchors_on_unique_lines() {
+        let a = "fn one() {\n    1\n}\n\nfn two() {\n    2\n}\n";
+        let b = "fn two() {\n    2\n}\n\nfn one() {\n    1\n}\n";
+
+        for algorithm in [Algorithm::Patience, Algorithm::Histogram].iter() {
+            assert_eq!(
+                render_with(a, b, *algorithm),
+                vec![
+                    "-fn one() {",
+                    "-    1",
+                    "-}",
+                    "-",
+                    " fn two() {",
+                    "     2",
+                    "+}",
+                    "+",
+                    "+fn one() {",
+                    "+    1",
+                    " }",
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn every_algorithm_produces_a_valid_script() {
+        let cases = [
+            ("A\nB\nC\nA\nB\nB\nA\n", "C\nB\nA\nB\nA\nC\n"),
+            ("", "new\n"),
+            ("old\n", ""),
+            ("x\ny\nx\ny\n", "y\nx\ny\nx\n"),
+            ("a\nb\nc\n", "a\nb\nc"),
+        ];
+        let algorithms = [Algorithm::Myers, Algorithm::Patience, Algorithm::Histogram];
+
+        for (a, b) in cases.iter() {
+            for algorithm in algorithms.iter() {
+                let edits = diff(&lines(a.as_bytes()), &lines(b.as_bytes()), *algorithm);
+
+                let old: String = edits
+                    .iter()
+                    .filter_map(|e| e.a_line.as_ref().filter(|_| e.kind != EditKind::Insert))
+                    .map(|line| line.text.as_str())
+                    .collect();
+                let new: String = edits
+                    .iter()
+                    .filter_map(|e| e.b_line.as_ref().filter(|_| e.kind != EditKind::Delete))
+                    .map(|line| line.text.as_str())
+                    .collect();
+
+                assert_eq!((&old, &new), (&a.to_string(), &b.to_string()));
+            }
+        }
+    }
+
+    #[test]
+    fn ignores_whitespace_when_asked() {
+        let a = "fn main() {\n    call(a, b);\n    x = 1;\n\n    done();\n}\n";
+        let b = "fn main() {\n\tcall(a,  b);   \n    x=1;\n    done();\n\n}\n";
+
+        let changes = |options: &DiffOptions| {
+            let mut changes: Vec<_> =
+                diff_hunks(a.as_bytes(), b.as_bytes(), Path::new("main.c"), options)
+                    .iter()
+                    .flat_map(|hunk| hunk.edits.iter())
+                    .filter(|edit| edit.kind != EditKind::Equal)
+                    .map(ToString::to_string)
+                    .collect();
+            changes.sort();
+            changes
+        };
+
+        let mut options = DiffOptions::default();
+        assert_eq!(changes(&options).len(), 6);
+
+        options.whitespace = Whitespace::IgnoreChange;
+        assert_eq!(
+            changes(&options),
+            vec!["+", "+    x=1;", "-", "-    x = 1;"]
+        );
+
+        options.whitespace = Whitespace::IgnoreAll;
+        assert_eq!(changes(&options), vec!["+", "-"]);
+
+        options.ignore_blank_lines = true;
+        assert!(changes(&options).is_empty());
+    }
+
+    #[test]
+    fn splits_and_applies_hunks() {
+        let a: String = (1..=10).map(|n| format!("line {}\n", n)).collect();
+        let b = a
+            .replace("line 3\n", "line three\n")
+            .replace("line 7\n", "line 7\nline 7.5\n");
+
+        let hunks = diff_hunks(
+            a.as_bytes(),
+            b.as_bytes(),
+            Path::new("lines"),
+            &DiffOptions::default(),
+        );
+        assert_eq!(hunks.len(), 1);
+
+        let pieces = hunks[0].split();
+        let headers: Vec<_> = pieces.iter().map(Hunk::header).collect();
+        assert_eq!(headers, vec!["@@ -1,7 +1,7 @@", "@@ -4,7 +4,8 @@"]);
+
+        let a_lines = lines(a.as_bytes());
+        assert_eq!(apply_hunks(&a_lines, &pieces), b);
+        assert_eq!(
+            apply_hunks(&a_lines, &pieces[1..]),
+            a.replace("line 7\n", "line 7\nline 7.5\n")
+        );
+        assert_eq!(
+            apply_hunks(&a_lines, &pieces[..1]),
+            a.replace("line 3\n", "line three\n")
+        );
+
+        let edited = pieces[0]
+            .edited(
+                " line 1\n line 2\n-line 3\n+line 3, edited\n line 4\n line 5\n line 6\n line 7\n",
+            )
+            .unwrap();
+        assert_eq!(
+            apply_hunks(&a_lines, &[edited]),
+            a.replace("line 3\n", "line 3, edited\n")
+        );
+        assert!(pieces[0].edited(" line 1\n-line 3\n").is_none());
+    }
+}
@@ -0,0 +1,122 @@
+use super::{Edit, EditKind, Line};
+
+/// Myers' greedy algorithm: search outwards for the furthest-reaching path on each
+/// diagonal of the edit graph, then backtrack through the saved frontiers.
+pub(super) struct Myers<'a> {
+    a: &'a [Line],
+    b: &'a [Line],
+}
+
+impl<'a> Myers<'a> {
+    pub(super) fn new(a: &'a [Line], b: &'a [Line]) -> Self {
+        Self { a, b }
+    }
+
+    pub(super) fn diff(&self) -> Vec<Edit> {
+        let mut edits = Vec::new();
+
+        for (prev_x, prev_y, x, y) in self.backtrack() {
+            let a_line = self.a.get(prev_x).cloned();
+            let b_line = self.b.get(prev_y).cloned();
+
+            if x == prev_x {
+                edits.push(Edit {
+                    kind: EditKind::Insert,
+                    a_line: None,
+                    b_line,
+                });
+            } else if y == prev_y {
+                edits.push(Edit {
+                    kind: EditKind::Delete,
+                    a_line,
+                    b_line: None,
+                });
+            } else {
+                edits.push(Edit {
+                    kind: EditKind::Equal,
+                    a_line,
+                    b_line,
+                });
+            }
+        }
+
+        edits.reverse();
+        edits
+    }
+
+    /// The x-coordinate of the furthest point reached on each diagonal, for each edit distance.
+    fn shortest_edit(&self) -> Vec<Vec<isize>> {
+        let n = self.a.len() as isize;
+        let m = self.b.len() as isize;
+        let max = n + m;
+        let offset = max as usize + 1;
+
+        let mut v = vec![0isize; 2 * offset + 1];
+        let mut trace = Vec::new();
+
+        for d in 0..=max {
+            trace.push(v.clone());
+
+            for k in (-d..=d).step_by(2) {
+                let index = (k + offset as isize) as usize;
+
+                let mut x = if k == -d || (k != d && v[index - 1] < v[index + 1]) {
+                    v[index + 1]
+                } else {
+                    v[index - 1] + 1
+                };
+                let mut y = x - k;
+
+                while x < n && y < m && self.a[x as usize].key == self.b[y as usize].key {
+                    x += 1;
+                    y += 1;
+                }
+
+                v[index] = x;
+
+                if x >= n && y >= m {
+                    return trace;
+                }
+            }
+        }
+
+        trace
+    }
+
+    /// Walk back from the end of both files, yielding each step as `(prev_x, prev_y, x, y)`.
+    fn backtrack(&self) -> Vec<(usize, usize, usize, usize)> {
+        let mut x = self.a.len() as isize;
+        let mut y = self.b.len() as isize;
+        let offset = (self.a.len() + self.b.len()) as isize + 1;
+        let mut steps = Vec::new();
+
+        for (d, v) in self.shortest_edit().iter().enumerate().rev() {
+            let d = d as isize;
+            let k = x - y;
+            let at = |k: isize| v[(k + offset) as usize];
+
+            let prev_k = if k == -d || (k != d && at(k - 1) < at(k + 1)) {
+                k + 1
+            } else {
+                k - 1
+            };
+            let prev_x = at(prev_k);
+            let prev_y = prev_x - prev_k;
+
+            while x > prev_x && y > prev_y {
+                steps.push((x as usize - 1, y as usize - 1, x as usize, y as usize));
+                x -= 1;
+                y -= 1;
+            }
+
+            if d > 0 {
+                steps.push((prev_x as usize, prev_y as usize, x as usize, y as usize));
+            }
+
+            x = prev_x;
+            y = prev_y;
+        }
+
+        steps
+    }
+}
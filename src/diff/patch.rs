@@ -0,0 +1,130 @@
+use std::path::{Path, PathBuf};
+
+use super::{diff_hunks, raw_changes, DiffOptions};
+use crate::database::{Database, DatabaseEntry, ObjectId};
+use crate::quote::quote_bytes;
+use crate::sys::OsStrExt;
+use crate::Result;
+
+const NULL_OID: &str = "0000000";
+const NULL_PATH: &str = "/dev/null";
+
+/// One side of a file's diff: the path it's shown under, and its blob and mode if the
+/// file exists on that side.
+pub struct Target {
+    pub path: PathBuf,
+    pub oid: Option<ObjectId>,
+    pub mode: Option<u32>,
+    pub data: Vec<u8>,
+}
+
+impl Target {
+    /// Build a target from an entry in a stored tree, loading its blob.
+    pub fn from_entry(
+        database: &Database,
+        path: &Path,
+        entry: Option<&DatabaseEntry>,
+    ) -> Result<Self> {
+        let data = match entry {
+            Some(entry) => database.load_blob(entry.oid())?.to_bytestr().to_vec(),
+            None => Vec::new(),
+        };
+
+        Ok(Self {
+            path: path.to_owned(),
+            oid: entry.map(|entry| *entry.oid()),
+            mode: entry.map(DatabaseEntry::mode),
+            data,
+        })
+    }
+
+    fn short_oid(&self) -> String {
+        self.oid
+            .as_ref()
+            .map(|oid| oid.short(7))
+            .unwrap_or_else(|| NULL_OID.to_owned())
+    }
+
+    /// The path after `side`'s prefix, as in `a/file`, quoted as a whole if it needs to be.
+    fn prefixed_path(&self, side: &str, quote_non_ascii: bool) -> String {
+        let mut path = format!("{}/", side).into_bytes();
+        path.extend_from_slice(self.path.as_os_str().as_bytes());
+        quote_bytes(&path, quote_non_ascii)
+    }
+
+    fn diff_path(&self, side: &str, quote_non_ascii: bool) -> String {
+        match self.oid {
+            Some(_) => self.prefixed_path(side, quote_non_ascii),
+            None => NULL_PATH.to_owned(),
+        }
+    }
+}
+
+/// Render the patch between two versions of a file, in git's unified format.
+pub fn file_patch(a: &Target, b: &Target, options: &DiffOptions) -> String {
+    if a.oid == b.oid && a.mode == b.mode {
+        return String::new();
+    }
+
+    let quote = options.quote_non_ascii;
+    let mut patch = format!(
+        "diff --git {} {}\n",
+        a.prefixed_path("a", quote),
+        b.prefixed_path("b", quote)
+    );
+
+    match (a.mode, b.mode) {
+        (None, Some(mode)) => patch.push_str(&format!("new file mode {:o}\n", mode)),
+        (Some(mode), None) => patch.push_str(&format!("deleted file mode {:o}\n", mode)),
+        (Some(a_mode), Some(b_mode)) if a_mode != b_mode => {
+            patch.push_str(&format!("old mode {:o}\nnew mode {:o}\n", a_mode, b_mode));
+        }
+        _ => {}
+    }
+
+    if a.oid == b.oid {
+        return patch;
+    }
+
+    let hunks = diff_hunks(&a.data, &b.data, &b.path, options);
+
+    // Files whose only changes are being ignored aren't shown at all.
+    if hunks.is_empty() && a.mode == b.mode {
+        return String::new();
+    }
+
+    patch.push_str(&format!("index {}..{}", a.short_oid(), b.short_oid()));
+    if let (Some(a_mode), Some(b_mode)) = (a.mode, b.mode) {
+        if a_mode == b_mode {
+            patch.push_str(&format!(" {:o}", a_mode));
+        }
+    }
+    patch.push('\n');
+
+    patch.push_str(&format!("--- {}\n", a.diff_path("a", quote)));
+    patch.push_str(&format!("+++ {}\n", b.diff_path("b", quote)));
+
+    for hunk in hunks {
+        patch.push_str(&hunk.to_string());
+    }
+
+    patch
+}
+
+/// Render the patch between two trees (or the trees of two commits).
+pub fn tree_patch(
+    database: &Database,
+    a: Option<&ObjectId>,
+    b: Option<&ObjectId>,
+    options: &DiffOptions,
+) -> Result<String> {
+    let mut patch = String::new();
+
+    for change in raw_changes(database, a, b, true)? {
+        let a = Target::from_entry(database, &change.path, change.old.as_ref())?;
+        let b = Target::from_entry(database, &change.path, change.new.as_ref())?;
+        patch.push_str(&file_patch(&a, &b, options));
+    }
+
+    Ok(patch)
+}
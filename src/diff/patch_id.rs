@@ -0,0 +1,42 @@
+use sha1::{Digest, Sha1};
+
+use super::{diff_hunks, DiffOptions, EditKind};
+use crate::database::{Database, DatabaseEntry, ObjectId};
+use crate::Result;
+
+/// Hash the changes a commit makes to its parent, ignoring whitespace and line numbers, so
+/// that the same change applied on top of different histories gets the same id.
+pub fn patch_id(database: &Database, oid: &ObjectId) -> Result<ObjectId> {
+    let commit = database.load_commit(oid)?;
+    let changes = database.tree_diff(commit.parent(), Some(oid))?;
+    let mut hasher = Sha1::new();
+
+    for (path, (a, b)) in changes {
+        let name = path.to_string_lossy();
+        hash_stripped(&mut hasher, &format!("diff --git a/{} b/{}", name, name));
+
+        let a = blob_data(database, a.as_ref())?;
+        let b = blob_data(database, b.as_ref())?;
+
+        for hunk in diff_hunks(&a, &b, &path, &DiffOptions::default()) {
+            for edit in hunk.edits.iter().filter(|e| e.kind != EditKind::Equal) {
+                hash_stripped(&mut hasher, &edit.to_string());
+            }
+        }
+    }
+
+    let hash: [u8; 20] = hasher.finalize().into();
+    Ok(ObjectId::from(hash))
+}
+
+fn blob_data(database: &Database, entry: Option<&DatabaseEntry>) -> Result<Vec<u8>> {
+    match entry {
+        Some(entry) => Ok(database.load_blob(entry.oid())?.to_bytestr().to_vec()),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn hash_stripped(hasher: &mut Sha1, text: &str) {
+    let stripped: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+    hasher.update(stripped.as_bytes());
+}
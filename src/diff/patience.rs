@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+
+use super::{diff_trimmed, equal, myers::Myers, Edit, Line};
+
+/// Patience diff: line the two versions up on the longest sequence of lines that appear
+/// exactly once in each, in the same order, then diff the gaps between them the same way.
+pub(super) fn diff(a: &[Line], b: &[Line]) -> Vec<Edit> {
+    let mut edits = Vec::new();
+    patience(a, b, &mut edits);
+    edits
+}
+
+fn patience(a: &[Line], b: &[Line], edits: &mut Vec<Edit>) {
+    diff_trimmed(a, b, edits, |a, b, edits| {
+        let anchors = unique_common_lines(a, b);
+
+        if anchors.is_empty() {
+            edits.extend(Myers::new(a, b).diff());
+            return;
+        }
+
+        let (mut a_next, mut b_next) = (0, 0);
+        for (a_index, b_index) in anchors {
+            patience(&a[a_next..a_index], &b[b_next..b_index], edits);
+            edits.push(equal(&a[a_index], &b[b_index]));
+            a_next = a_index + 1;
+            b_next = b_index + 1;
+        }
+        patience(&a[a_next..], &b[b_next..], edits);
+    });
+}
+
+/// Pairs of indexes of lines that are unique to both sides, forming the longest sequence
+/// that is in order on both sides.
+fn unique_common_lines(a: &[Line], b: &[Line]) -> Vec<(usize, usize)> {
+    // For each line text: how often it appears on each side, and where it last appeared.
+    let mut counts: HashMap<&str, (usize, usize, usize, usize)> = HashMap::new();
+    for (i, line) in a.iter().enumerate() {
+        let entry = counts.entry(&line.key).or_default();
+        entry.0 += 1;
+        entry.2 = i;
+    }
+    for (i, line) in b.iter().enumerate() {
+        let entry = counts.entry(&line.key).or_default();
+        entry.1 += 1;
+        entry.3 = i;
+    }
+
+    let mut pairs: Vec<(usize, usize)> = counts
+        .values()
+        .filter(|(a_count, b_count, _, _)| *a_count == 1 && *b_count == 1)
+        .map(|&(_, _, a_index, b_index)| (a_index, b_index))
+        .collect();
+    pairs.sort_unstable();
+
+    longest_increasing_run(&pairs)
+}
+
+/// Find the longest subsequence of pairs whose second elements are increasing, using
+/// patience sorting: each pair goes on the leftmost pile whose top is greater than it.
+fn longest_increasing_run(pairs: &[(usize, usize)]) -> Vec<(usize, usize)> {
+    let mut pile_tops: Vec<usize> = Vec::new();
+    let mut back_links: Vec<Option<usize>> = Vec::with_capacity(pairs.len());
+
+    for (i, &(_, b_index)) in pairs.iter().enumerate() {
+        let pile = pile_tops.partition_point(|&top| pairs[top].1 < b_index);
+        back_links.push(if pile > 0 {
+            Some(pile_tops[pile - 1])
+        } else {
+            None
+        });
+
+        if pile == pile_tops.len() {
+            pile_tops.push(i);
+        } else {
+            pile_tops[pile] = i;
+        }
+    }
+
+    let mut run = Vec::new();
+    let mut next = pile_tops.last().copied();
+    while let Some(i) = next {
+        run.push(pairs[i]);
+        next = back_links[i];
+    }
+    run.reverse();
+    run
+}
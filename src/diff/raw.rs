@@ -0,0 +1,208 @@
+use std::path::PathBuf;
+
+use crate::database::{Database, DatabaseEntry, ObjectId, TreeDiff};
+use crate::quote::quote_path;
+use crate::vfs::Vfs;
+use crate::Result;
+
+/// The bits of a mode that give the kind of entry: file, symlink, directory or submodule.
+const TYPE_MASK: u32 = 0o170000;
+
+/// How a path changed between two trees, as shown by the letter in git's raw format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeStatus {
+    Added,
+    Deleted,
+    Modified,
+    /// The path changed kind, between a file, a symlink or a submodule.
+    TypeChanged,
+}
+
+impl ChangeStatus {
+    pub fn letter(self) -> char {
+        match self {
+            ChangeStatus::Added => 'A',
+            ChangeStatus::Deleted => 'D',
+            ChangeStatus::Modified => 'M',
+            ChangeStatus::TypeChanged => 'T',
+        }
+    }
+}
+
+/// One path that differs between two trees, with its entry on each side.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawChange {
+    pub path: PathBuf,
+    pub old: Option<DatabaseEntry>,
+    pub new: Option<DatabaseEntry>,
+}
+
+impl RawChange {
+    pub fn status(&self) -> ChangeStatus {
+        match (&self.old, &self.new) {
+            (None, _) => ChangeStatus::Added,
+            (_, None) => ChangeStatus::Deleted,
+            (Some(old), Some(new)) if old.mode() & TYPE_MASK != new.mode() & TYPE_MASK => {
+                ChangeStatus::TypeChanged
+            }
+            _ => ChangeStatus::Modified,
+        }
+    }
+
+    /// The change as a line of git's raw format, e.g. `:100644 100644 <old> <new> M\tpath`.
+    /// ObjectIds are abbreviated to `abbrev` characters, if given; a missing side has a mode
+    /// and ObjectId of all zeros. The path is quoted if it needs to be.
+    pub fn to_raw(&self, abbrev: Option<usize>, quote_non_ascii: bool) -> String {
+        let mode = |entry: &Option<DatabaseEntry>| entry.as_ref().map_or(0, DatabaseEntry::mode);
+        let oid = |entry: &Option<DatabaseEntry>| {
+            let oid = entry.as_ref().map_or(ObjectId::NULL, |entry| *entry.oid());
+            oid.short(abbrev.unwrap_or(ObjectId::HEX_LEN))
+        };
+
+        format!(
+            ":{:06o} {:06o} {} {} {}\t{}\n",
+            mode(&self.old),
+            mode(&self.new),
+            oid(&self.old),
+            oid(&self.new),
+            self.status().letter(),
+            quote_path(&self.path, quote_non_ascii)
+        )
+    }
+
+    /// The change as shown by `--name-status`: just its status letter and path.
+    pub fn to_name_status(&self, quote_non_ascii: bool) -> String {
+        let path = quote_path(&self.path, quote_non_ascii);
+        format!("{}\t{}\n", self.status().letter(), path)
+    }
+}
+
+/// List the paths that differ between two trees (or the trees of two commits), in order.
+/// Unless `recursive`, differing subtrees are listed rather than the files in them. As in
+/// git, a path that's a file on one side and a directory on the other is listed as the
+/// file being deleted or added, and separately, the directory.
+pub fn raw_changes<F: Vfs>(
+    database: &Database<F>,
+    a: Option<&ObjectId>,
+    b: Option<&ObjectId>,
+    recursive: bool,
+) -> Result<Vec<RawChange>> {
+    let mut diff = TreeDiff::new(database);
+    if !recursive {
+        diff = diff.top_level();
+    }
+    diff.compare_oids(a, b, "".as_ref())?;
+
+    let mut changes = Vec::new();
+    for (path, (old, new)) in diff.into_changes() {
+        match (old, new) {
+            (Some(old), Some(new)) if old.is_tree() != new.is_tree() => {
+                let deleted = RawChange {
+                    path: path.clone(),
+                    old: Some(old),
+                    new: None,
+                };
+                let added = RawChange {
+                    path,
+                    old: None,
+                    new: Some(new),
+                };
+                // A file sorts before a directory of the same name.
+                match deleted.old.as_ref().is_some_and(DatabaseEntry::is_tree) {
+                    true => changes.extend([added, deleted]),
+                    false => changes.extend([deleted, added]),
+                }
+            }
+            (old, new) => changes.push(RawChange { path, old, new }),
+        }
+    }
+
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::Blob;
+    use crate::vfs::MemoryFs;
+    use std::path::Path;
+
+    fn tree(database: &Database<MemoryFs>, entries: &[(&str, &str, ObjectId)]) -> ObjectId {
+        let mut data = Vec::new();
+        for (mode, name, oid) in entries {
+            data.extend_from_slice(format!("{} {}\0", mode, name).as_bytes());
+            data.extend_from_slice(oid.bytes());
+        }
+        database.store_raw("tree", &data).unwrap()
+    }
+
+    #[test]
+    fn lists_changes_in_raw_format() {
+        let fs = MemoryFs::new();
+        fs.create_dir_all(Path::new("/objects")).unwrap();
+        let database = Database::with_vfs(fs, "/objects");
+        let one = database.store(&Blob::new("one\n".into())).unwrap();
+        let two = database.store(&Blob::new("two\n".into())).unwrap();
+
+        let dir_a = tree(&database, &[("100644", "file", one)]);
+        let dir_b = tree(&database, &[("100644", "file", two)]);
+        let a = tree(
+            &database,
+            &[
+                ("40000", "dir", dir_a),
+                ("100644", "gone", one),
+                ("100644", "link", one),
+                ("100644", "swap", one),
+            ],
+        );
+        let b = tree(
+            &database,
+            &[
+                ("40000", "dir", dir_b),
+                ("120000", "link", one),
+                ("100644", "new", two),
+                ("40000", "swap", dir_a),
+            ],
+        );
+
+        let names = |recursive| -> Vec<_> {
+            raw_changes(&database, Some(&a), Some(&b), recursive)
+                .unwrap()
+                .iter()
+                .map(|change| change.to_name_status(true))
+                .collect()
+        };
+        assert_eq!(
+            names(false),
+            vec![
+                "M\tdir\n",
+                "D\tgone\n",
+                "T\tlink\n",
+                "A\tnew\n",
+                "D\tswap\n",
+                "A\tswap\n"
+            ]
+        );
+        assert_eq!(
+            names(true),
+            vec![
+                "M\tdir/file\n",
+                "D\tgone\n",
+                "T\tlink\n",
+                "A\tnew\n",
+                "D\tswap\n",
+                "A\tswap/file\n"
+            ]
+        );
+
+        let changes = raw_changes(&database, Some(&a), Some(&b), false).unwrap();
+        assert_eq!(
+            changes[1].to_raw(Some(7), true),
+            format!(":100644 000000 {} 0000000 D\tgone\n", one.short(7))
+        );
+        assert_eq!(
+            changes[0].to_raw(None, true),
+            format!(":040000 040000 {} {} M\tdir\n", dir_a, dir_b)
+        );
+    }
+}
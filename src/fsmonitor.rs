@@ -0,0 +1,210 @@
+use std::{
+    collections::BTreeSet,
+    io,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use thiserror::Error;
+
+use crate::repository::{set_git_env, Repository};
+use crate::sys::OsStrExt;
+use crate::vfs::{RealFs, Vfs};
+use crate::Result;
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum FsMonitorError {
+    #[error("could not run fsmonitor hook '{0}'")]
+    CouldNotRun(String, #[source] io::Error),
+    #[error("fsmonitor hook '{0}' failed")]
+    Failed(String),
+    #[error("fsmonitor hook '{0}' gave no token")]
+    NoToken(String),
+}
+
+/// The protocol version passed to the hook: it's given the last token it handed out, and
+/// answers with a new token followed by the paths that have changed since the old one.
+const HOOK_VERSION: &str = "2";
+
+/// An external command, set as `core.fsmonitor`, that watches the workspace and says which
+/// paths have changed, so that checking for changes doesn't have to look at every file.
+pub struct FsMonitor<F: Vfs = RealFs> {
+    fs: F,
+    command: String,
+    state_path: PathBuf,
+    index_path: PathBuf,
+    git_env: Vec<(&'static str, Option<PathBuf>)>,
+}
+
+impl<F: Vfs> FsMonitor<F> {
+    /// The hook configured for `repo`, for checks against its index, if there is one. `true`
+    /// and `false` are taken to be about git's built-in daemon, which there isn't one of.
+    pub fn from_repo(repo: &Repository<F>) -> Result<Option<Self>> {
+        let command = match repo.config()?.get("core.fsmonitor")? {
+            Some(command) => command,
+            None => return Ok(None),
+        };
+        let is_bool = ["", "true", "false", "yes", "no", "on", "off", "1", "0"]
+            .contains(&command.to_ascii_lowercase().as_str());
+        if is_bool {
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            fs: repo.fs().clone(),
+            command,
+            state_path: repo.git_path().join("fsmonitor-state"),
+            index_path: repo.index_path().to_owned(),
+            git_env: repo.git_env(),
+        }))
+    }
+
+    /// Ask the hook what has changed since the last check, and start a new one.
+    pub fn begin(&self, root_path: &Path) -> Result<Scan<F>> {
+        let previous = self.load_state()?;
+        let stamp = index_stamp(&self.fs, &self.index_path)?;
+        let last_token = previous.as_ref().map_or("", |state| state.token.as_str());
+        let (token, changed) = self.query(last_token, root_path)?;
+
+        // What was found last time is only any use if the index is as it was then, since
+        // an entry can change without its file doing so.
+        let (changed, previous_dirty) = match previous {
+            Some(state) if state.stamp == stamp => (changed, state.dirty),
+            _ => (None, BTreeSet::new()),
+        };
+
+        Ok(Scan {
+            fs: self.fs.clone(),
+            state_path: self.state_path.clone(),
+            index_path: self.index_path.clone(),
+            changed,
+            previous_dirty,
+            state: State {
+                token,
+                stamp,
+                dirty: BTreeSet::new(),
+            },
+        })
+    }
+
+    /// Run the hook, returning its new token and the paths it says have changed. `None`
+    /// means it can't tell, and anything may have changed.
+    fn query(&self, token: &str, root_path: &Path) -> Result<(String, Option<BTreeSet<PathBuf>>)> {
+        let mut command = Command::new("sh");
+        set_git_env(&mut command, &self.git_env);
+        let output = command
+            .arg("-c")
+            .arg(format!("{} \"$@\"", self.command))
+            .arg(&self.command)
+            .arg(HOOK_VERSION)
+            .arg(token)
+            .current_dir(root_path)
+            .stdin(Stdio::null())
+            .stderr(Stdio::inherit())
+            .output()
+            .map_err(|e| FsMonitorError::CouldNotRun(self.command.clone(), e))?;
+        if !output.status.success() {
+            return Err(FsMonitorError::Failed(self.command.clone()).into());
+        }
+
+        let mut fields = output.stdout.split(|&b| b == b'\0');
+        let token = match fields.next() {
+            Some(token) if !token.is_empty() => String::from_utf8_lossy(token).into_owned(),
+            _ => return Err(FsMonitorError::NoToken(self.command.clone()).into()),
+        };
+
+        let mut paths = BTreeSet::new();
+        for path in fields.filter(|path| !path.is_empty()) {
+            if path == b"/" {
+                return Ok((token, None));
+            }
+            let path = path.strip_suffix(b"/").unwrap_or(path);
+            paths.insert(PathBuf::from(std::ffi::OsStr::from_bytes(path)));
+        }
+
+        Ok((token, Some(paths)))
+    }
+
+    fn load_state(&self) -> Result<Option<State>> {
+        let text = match self.fs.read(&self.state_path) {
+            Ok(text) => String::from_utf8_lossy(&text).into_owned(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut lines = text.lines();
+        Ok(match (lines.next(), lines.next()) {
+            (Some(token), Some(stamp)) => Some(State {
+                token: token.to_owned(),
+                stamp: stamp.to_owned(),
+                dirty: lines.map(PathBuf::from).collect(),
+            }),
+            _ => None,
+        })
+    }
+}
+
+/// What was found by the last check: the hook's token at the time, the state of the index,
+/// and the paths that didn't match it.
+struct State {
+    token: String,
+    stamp: String,
+    dirty: BTreeSet<PathBuf>,
+}
+
+/// A check of the workspace against the index, with the hook's help.
+pub struct Scan<F: Vfs = RealFs> {
+    fs: F,
+    state_path: PathBuf,
+    index_path: PathBuf,
+    /// The paths changed since the last check, or `None` if every path has to be looked at.
+    changed: Option<BTreeSet<PathBuf>>,
+    previous_dirty: BTreeSet<PathBuf>,
+    state: State,
+}
+
+impl<F: Vfs> Scan<F> {
+    /// Whether a path can be taken to match the index without looking at it: it did at
+    /// the last check, and the hook says it hasn't changed since.
+    pub fn is_unchanged(&self, path: &Path) -> bool {
+        match &self.changed {
+            Some(changed) => {
+                !self.previous_dirty.contains(path)
+                    && !path.ancestors().any(|path| changed.contains(path))
+            }
+            None => false,
+        }
+    }
+
+    /// Record that a path doesn't match the index.
+    pub fn mark_dirty(&mut self, path: &Path) {
+        self.state.dirty.insert(path.to_owned());
+    }
+
+    /// Save what was found, for the next check to start from. Every path that wasn't
+    /// skipped has to have been looked at, or the next check would skip it wrongly. The
+    /// index can have been written since the check began, to refresh the entries it found
+    /// to be stale, so it's stamped as it is now.
+    pub fn finish(mut self) -> Result<()> {
+        self.state.stamp = index_stamp(&self.fs, &self.index_path)?;
+        let mut text = format!("{}\n{}\n", self.state.token, self.state.stamp);
+        for path in &self.state.dirty {
+            text.push_str(&format!("{}\n", path.display()));
+        }
+        self.fs.write(&self.state_path, text.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Something that changes whenever the index at `index_path` is written.
+fn index_stamp<F: Vfs>(fs: &F, index_path: &Path) -> Result<String> {
+    match fs.stat(index_path) {
+        Ok(stat) => Ok(format!(
+            "{}.{} {} {}",
+            stat.mtime, stat.mtime_nsec, stat.ino, stat.size
+        )),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(e.into()),
+    }
+}
@@ -0,0 +1,99 @@
+//! SHA-1, which objects are named by and the index is checked with. With the `sha1dc`
+//! feature, which is on by default, it's the collision-detecting SHA-1 that git uses: it
+//! spots the disturbed blocks that every known attack on SHA-1 relies on, so that a file
+//! built to collide with another is refused rather than mistaken for it. Without the
+//! feature it's plain SHA-1, which never finds a collision.
+
+#[cfg(not(feature = "sha1dc"))]
+use sha1::{Digest, Sha1};
+#[cfg(feature = "sha1dc")]
+use sha1collisiondetection::Sha1CD;
+
+/// What was hashed contains a SHA-1 collision attack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Collision;
+
+/// Hashes data given a piece at a time.
+#[derive(Clone, Default)]
+pub struct Hasher {
+    #[cfg(feature = "sha1dc")]
+    inner: Sha1CD,
+    #[cfg(not(feature = "sha1dc"))]
+    inner: Sha1,
+}
+
+impl Hasher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, data: impl AsRef<[u8]>) {
+        self.inner.update(data.as_ref());
+    }
+
+    /// The hash of everything given so far, unless it contains a collision attack.
+    pub fn finalize(self) -> Result<[u8; 20], Collision> {
+        let mut hash = [0; 20];
+        #[cfg(feature = "sha1dc")]
+        hash.copy_from_slice(&self.inner.finalize_cd().map_err(|_| Collision)?);
+        #[cfg(not(feature = "sha1dc"))]
+        hash.copy_from_slice(&self.inner.finalize());
+
+        Ok(hash)
+    }
+}
+
+/// The hash of `data`, unless it contains a collision attack.
+pub fn digest(data: impl AsRef<[u8]>) -> Result<[u8; 20], Collision> {
+    let mut hasher = Hasher::new();
+    hasher.update(data);
+    hasher.finalize()
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    /// The first 320 bytes of shattered-1.pdf, from the first SHA-1 collision found, which
+    /// hold the blocks that make it collide.
+    pub(crate) fn shattered_prefix() -> Vec<u8> {
+        let hex = concat!(
+            "255044462d312e330a25e2e3cfd30a0a0a312030206f626a0a3c3c2f57696474682032203020522f",
+            "4865696768742033203020522f547970652034203020522f537562747970652035203020522f4669",
+            "6c7465722036203020522f436f6c6f7253706163652037203020522f4c656e677468203820302052",
+            "2f42697473506572436f6d706f6e656e7420383e3e0a73747265616d0affd8fffe00245348412d31",
+            "20697320646561642121212121852fec092339759c39b1a1c63c4c97e1fffe017346dc9166b67e11",
+            "8f029ab621b2560ff9ca67cca8c7f85ba84c79030c2b3de218f86db3a90901d5df45c14f26fedfb3",
+            "dc38e96ac22fe7bd728f0e45bce046d23c570feb141398bb552ef5a0a82be331fea48037b8b5d71f",
+            "0e332edf93ac3500eb4ddc0decc1a864790c782c76215660dd309791d06bd0af3f98cda4bc4629b1",
+        );
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn hashes_as_sha1_does() {
+        let mut hasher = Hasher::new();
+        hasher.update("a");
+        hasher.update("bc");
+        assert_eq!(hasher.finalize(), digest("abc"));
+        assert_eq!(
+            crate::utils::bytes_to_hex_string(&digest("abc").unwrap()).unwrap(),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "sha1dc")]
+    fn refuses_collision_attacks() {
+        assert_eq!(digest(shattered_prefix()), Err(Collision));
+    }
+
+    #[test]
+    #[cfg(not(feature = "sha1dc"))]
+    fn hashes_collision_attacks_like_anything_else() {
+        assert!(digest(shattered_prefix()).is_ok());
+    }
+}
@@ -0,0 +1,91 @@
+//! Running a repository's hooks: the programs in `.git/hooks`, or in the directory set by
+//! `core.hooksPath`, that are run at certain points so that a repository can check or
+//! follow up on what's done to it.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::thread;
+
+use thiserror::Error;
+
+use crate::repository::{set_git_env, Repository};
+use crate::sys::MetadataExt;
+use crate::utils::is_executable;
+use crate::Result;
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum HookError {
+    #[error("cannot run hook '{0}': {1}")]
+    CouldNotRun(String, io::Error),
+}
+
+/// What a hook did once it had run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HookOutput {
+    pub success: bool,
+    /// What the hook wrote to stdout, followed by what it wrote to stderr.
+    pub output: Vec<u8>,
+}
+
+/// The hook called `name` in `repo`, if there's one there that can be run. A relative
+/// `core.hooksPath` is taken to be relative to the `.git` directory.
+pub fn find_hook(repo: &Repository, name: &str) -> Result<Option<PathBuf>> {
+    let dir = match repo.config()?.get("core.hooksPath")? {
+        Some(dir) => repo.git_path().join(dir),
+        None => repo.git_path().join("hooks"),
+    };
+
+    let path = dir.join(name);
+    Ok(match fs::metadata(&path) {
+        Ok(stat) if stat.is_file() && is_executable(stat.mode()) => Some(path),
+        _ => None,
+    })
+}
+
+/// Run the hook called `name` in `repo` with `args`, writing `stdin` to it, as
+/// receive-pack does: in the `.git` directory, with `GIT_DIR` set to it, and the working
+/// tree and index set or cleared to match `repo`. Returns `None` if there's no such hook.
+pub fn run_hook(
+    repo: &Repository,
+    name: &str,
+    args: &[&str],
+    stdin: &[u8],
+) -> Result<Option<HookOutput>> {
+    let path = match find_hook(repo, name)? {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    let could_not_run = |e| HookError::CouldNotRun(name.to_owned(), e);
+
+    let mut command = Command::new(&path);
+    set_git_env(&mut command, &repo.git_env());
+    let mut child = command
+        .args(args)
+        .current_dir(repo.git_path())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(could_not_run)?;
+
+    // A hook needn't read all it's given, or any of it, so a broken pipe is no problem.
+    let mut input = child.stdin.take();
+    let stdin = stdin.to_vec();
+    let writer = thread::spawn(move || {
+        if let Some(input) = &mut input {
+            let _ = input.write_all(&stdin);
+        }
+    });
+    let output = child.wait_with_output().map_err(could_not_run)?;
+    let _ = writer.join();
+
+    let mut combined = output.stdout;
+    combined.extend_from_slice(&output.stderr);
+    Ok(Some(HookOutput {
+        success: output.status.success(),
+        output: combined,
+    }))
+}
@@ -0,0 +1,427 @@
+//! Fetching over git's smart HTTP protocol. The refs come from `info/refs`, and a fetch is
+//! a single request to `git-upload-pack` that says what's wanted and what's had, which is
+//! answered with a pack. Connections are made as the `http.*` config says, by way of
+//! [`HttpOptions`].
+
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
+use ureq::http::Response;
+use ureq::tls::{Certificate, PemItem, RootCerts, TlsConfig};
+use ureq::{Agent, Body, Proxy};
+
+use crate::database::{Database, ObjectId};
+use crate::progress::Progress;
+use crate::protocol;
+use crate::remote::{HttpOptions, RemoteError};
+use crate::Result;
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum HttpError {
+    #[error("unable to access '{0}': {1}")]
+    Request(String, String),
+    #[error("'{0}' doesn't speak smart HTTP, and the dumb protocol isn't supported")]
+    NotSmart(String),
+    #[error("no certificates found in {0}")]
+    NoCertificates(PathBuf),
+    #[error("operation too slow: less than {0} bytes/sec transferred the last {1} seconds")]
+    TooSlow(u64, u64),
+}
+
+const SERVICE: &str = "git-upload-pack";
+
+/// How much of a response body to read at a time when watching how fast it arrives.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// A repository served over smart HTTP.
+pub struct HttpClient {
+    url: String,
+    agent: Agent,
+    /// The least a transfer has to average, in bytes a second, over the given time.
+    low_speed: Option<(u64, Duration)>,
+}
+
+impl HttpClient {
+    /// Talk to the repository at `url`, as `options` say to.
+    pub fn new(url: &str, options: &HttpOptions) -> Result<Self> {
+        let proxy = match &options.proxy {
+            Some(proxy) => Some(
+                Proxy::new(proxy).map_err(|e| HttpError::Request(url.to_owned(), e.to_string()))?,
+            ),
+            None => None,
+        };
+
+        let mut tls = TlsConfig::builder().disable_verification(!options.ssl_verify);
+        let mut certificates = Vec::new();
+        if let Some(file) = &options.ssl_ca_info {
+            certificates.extend(read_certificates(file)?);
+        }
+        if let Some(dir) = &options.ssl_ca_path {
+            for entry in fs::read_dir(dir)? {
+                let path = entry?.path();
+                if path.is_file() {
+                    certificates.extend(read_certificates(&path).unwrap_or_default());
+                }
+            }
+        }
+        if !certificates.is_empty() {
+            tls = tls.root_certs(RootCerts::new_with_certs(&certificates));
+        } else if let Some(path) = options
+            .ssl_ca_info
+            .as_ref()
+            .or(options.ssl_ca_path.as_ref())
+        {
+            return Err(HttpError::NoCertificates(path.clone()).into());
+        }
+
+        // Like curl, low-speed limits only apply once both are set.
+        let low_speed = match (options.low_speed_limit, options.low_speed_time) {
+            (Some(limit), Some(time)) if limit > 0 && time > 0 => {
+                Some((limit, Duration::from_secs(time)))
+            }
+            _ => None,
+        };
+
+        // The proxy is always set, even to none, so that ureq doesn't look for one in the
+        // environment itself: `HttpOptions` already has.
+        let agent = Agent::config_builder()
+            .proxy(proxy)
+            .tls_config(tls.build())
+            .http_status_as_error(false)
+            .timeout_recv_response(low_speed.map(|(_, time)| time))
+            .build()
+            .new_agent();
+
+        Ok(Self {
+            url: url.trim_end_matches('/').to_owned(),
+            agent,
+            low_speed,
+        })
+    }
+
+    /// The refs upload-pack advertises, as `Remote::advertised_refs` lists them.
+    pub fn advertised_refs(&self) -> Result<Vec<(String, ObjectId)>> {
+        let url = format!("{}/info/refs?service={}", self.url, SERVICE);
+        let response = self.agent.get(&url).call().map_err(|e| self.error(e))?;
+        let mut body = self.body(response, "application/x-git-upload-pack-advertisement")?;
+
+        // The refs are preceded by a packet naming the service, which ends with a flush.
+        match protocol::read_line(&mut body)? {
+            Some(line) if line == format!("# service={}", SERVICE) => {}
+            _ => return Err(HttpError::NotSmart(self.url.clone()).into()),
+        }
+        while protocol::read_packet(&mut body)?.is_some() {}
+
+        protocol::read_advertisement(&mut body)
+    }
+
+    /// Fetch the objects needed for `wants`, telling upload-pack that the client already
+    /// has `haves`, and store them in `database`. Returns how many objects arrived.
+    pub fn fetch_pack(
+        &self,
+        wants: &[ObjectId],
+        haves: &[ObjectId],
+        database: &Database,
+        progress: &mut dyn Progress,
+    ) -> Result<usize> {
+        let mut request = Vec::new();
+        protocol::write_fetch_request(&mut request, wants, haves)?;
+
+        let url = format!("{}/{}", self.url, SERVICE);
+        let response = self
+            .agent
+            .post(&url)
+            .content_type("application/x-git-upload-pack-request")
+            .header("Accept", "application/x-git-upload-pack-result")
+            .send(&request[..])
+            .map_err(|e| self.error(e))?;
+        let mut body = self.body(response, "application/x-git-upload-pack-result")?;
+
+        protocol::read_fetch_response(&mut body, database, progress)
+    }
+
+    fn error(&self, error: ureq::Error) -> crate::Error {
+        HttpError::Request(self.url.clone(), error.to_string()).into()
+    }
+
+    /// The body of a response from a smart server, which has to say it's of the given type.
+    fn body(&self, response: Response<Body>, content_type: &str) -> Result<Box<dyn Read>> {
+        match response.status().as_u16() {
+            200..=299 => {}
+            404 => return Err(RemoteError::NotARepository(self.url.clone()).into()),
+            status => {
+                let message = format!("the requested URL returned error: {}", status);
+                return Err(HttpError::Request(self.url.clone(), message).into());
+            }
+        }
+
+        let actual = response
+            .headers()
+            .get("content-type")
+            .and_then(|value| value.to_str().ok());
+        if actual != Some(content_type) {
+            return Err(HttpError::NotSmart(self.url.clone()).into());
+        }
+
+        let body = response.into_body().into_reader();
+        Ok(match self.low_speed {
+            Some((limit, time)) => Box::new(LowSpeedLimit::new(body, limit, time)),
+            None => Box::new(body),
+        })
+    }
+}
+
+/// The certificates in a PEM file.
+fn read_certificates(path: &Path) -> Result<Vec<Certificate<'static>>> {
+    let pem = fs::read(path)?;
+    Ok(ureq::tls::parse_pem(&pem)
+        .filter_map(|item| match item {
+            Ok(PemItem::Certificate(certificate)) => Some(certificate),
+            _ => None,
+        })
+        .collect())
+}
+
+/// A response body that's given up on once it has averaged less than `limit` bytes a second
+/// for `time`, as curl does for git. It's read on a thread of its own, so that a transfer
+/// that stalls altogether is noticed as well as one that's just slow.
+struct LowSpeedLimit {
+    chunks: Receiver<io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    position: usize,
+    limit: u64,
+    time: Duration,
+    since: Instant,
+    received: u64,
+}
+
+impl LowSpeedLimit {
+    fn new(mut input: impl Read + Send + 'static, limit: u64, time: Duration) -> Self {
+        let (sender, chunks) = mpsc::sync_channel(1);
+        thread::spawn(move || loop {
+            let mut chunk = vec![0; CHUNK_SIZE];
+            let result = input.read(&mut chunk).map(|n| {
+                chunk.truncate(n);
+                chunk
+            });
+            let done = !matches!(&result, Ok(chunk) if !chunk.is_empty());
+            if sender.send(result).is_err() || done {
+                break;
+            }
+        });
+
+        Self {
+            chunks,
+            chunk: Vec::new(),
+            position: 0,
+            limit,
+            time,
+            since: Instant::now(),
+            received: 0,
+        }
+    }
+}
+
+impl Read for LowSpeedLimit {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.chunk.len() {
+            let wait = (self.since + self.time).saturating_duration_since(Instant::now());
+            match self.chunks.recv_timeout(wait) {
+                Ok(chunk) => {
+                    self.chunk = chunk?;
+                    self.position = 0;
+                    if self.chunk.is_empty() {
+                        return Ok(0);
+                    }
+                    self.received += self.chunk.len() as u64;
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+            }
+
+            if self.since.elapsed() >= self.time {
+                if (self.received as f64) < self.limit as f64 * self.time.as_secs_f64() {
+                    let error = HttpError::TooSlow(self.limit, self.time.as_secs());
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, error));
+                }
+                self.since = Instant::now();
+                self.received = 0;
+            }
+        }
+
+        let n = (&self.chunk[self.position..]).read(buf)?;
+        self.position += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::Config;
+    use crate::database::Identity;
+    use crate::remote::Remote;
+    use crate::repository::{CommitOptions, Repository};
+    use crate::vfs::{MemoryFs, Vfs};
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::{Arc, Mutex};
+
+    /// Serve `repo` over smart HTTP on a port of its own, also acting as a proxy for itself.
+    /// Returns the port and the request lines seen so far.
+    fn serve(repo: Repository) -> (u16, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let seen = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let _ = answer(&repo, stream.unwrap(), &seen);
+            }
+        });
+
+        (port, requests)
+    }
+
+    fn answer(repo: &Repository, stream: TcpStream, seen: &Mutex<Vec<String>>) -> io::Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut stream = stream;
+        loop {
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line)?;
+            let request_line = request_line.trim_end().to_owned();
+            seen.lock().unwrap().push(request_line.clone());
+
+            let mut length = 0;
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header)?;
+                if header.trim_end().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = header.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body)?;
+
+            // A tunnel through the proxy goes straight back to the same server.
+            if request_line.starts_with("CONNECT ") {
+                stream.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")?;
+                continue;
+            }
+
+            let mut response = Vec::new();
+            let content_type = if request_line.starts_with("GET ") {
+                let line = format!("# service={}\n", SERVICE);
+                protocol::write_packet(&mut response, line.as_bytes()).unwrap();
+                protocol::write_flush(&mut response).unwrap();
+                protocol::upload_pack(repo, &mut &b"0000"[..], &mut response).unwrap();
+                "application/x-git-upload-pack-advertisement"
+            } else {
+                let mut output = Vec::new();
+                protocol::upload_pack(repo, &mut &body[..], &mut output).unwrap();
+                let mut output = &output[..];
+                protocol::read_advertisement(&mut output).unwrap();
+                response.extend_from_slice(output);
+                "application/x-git-upload-pack-result"
+            };
+
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+                 Connection: close\r\n\r\n",
+                content_type,
+                response.len()
+            )?;
+            stream.write_all(&response)?;
+            return stream.flush();
+        }
+    }
+
+    fn repository(path: &Path) -> Repository {
+        fs::create_dir_all(path.join(".git/objects")).unwrap();
+        fs::create_dir_all(path.join(".git/refs/heads")).unwrap();
+        fs::write(path.join(".git/HEAD"), "ref: refs/heads/master\n").unwrap();
+        Repository::open(path)
+    }
+
+    #[test]
+    fn fetches_through_a_proxy_unless_its_turned_off() {
+        let origin_dir = tempfile::tempdir().unwrap();
+        let origin = repository(origin_dir.path());
+        fs::write(origin_dir.path().join("hello.txt"), "hello\n").unwrap();
+        origin.add(&["hello.txt"]).unwrap();
+        let options = CommitOptions::new(Identity::new("A. U. Thor", "author@example.com"));
+        let commit = origin.commit("Initial commit\n", &options).unwrap();
+        let (port, requests) = serve(origin);
+
+        let fs = MemoryFs::new();
+        let config = format!(
+            "[http]\n\tproxy = 127.0.0.1:{0}\n[http \"http://127.0.0.1:{0}/direct/\"]\n\tproxy =\n",
+            port
+        );
+        fs.write(Path::new("/config"), config.as_bytes()).unwrap();
+        let mut config = Config::with_vfs(fs, "/config");
+        config.load().unwrap();
+
+        for (path, proxied) in [("proxied", true), ("direct", false)] {
+            let url = format!("http://127.0.0.1:{}/{}/repo.git", port, path);
+            let options = HttpOptions::for_url(&config, &url, |_| None).unwrap();
+            let remote = Remote::http(&url, &options).unwrap();
+            requests.lock().unwrap().clear();
+
+            let refs = remote.list_refs().unwrap();
+            assert_eq!(refs, vec![("refs/heads/master".to_owned(), commit)]);
+
+            let local_dir = tempfile::tempdir().unwrap();
+            let local = repository(local_dir.path());
+            let mut progress = Vec::new();
+            let count = remote
+                .fetch_objects(&local, &[commit], &mut progress)
+                .unwrap();
+            assert_eq!(count, 3);
+            assert!(local.database().contains(&commit).unwrap());
+
+            let requests = requests.lock().unwrap();
+            let connects = requests
+                .iter()
+                .filter(|r| r.starts_with("CONNECT "))
+                .count();
+            assert_eq!(connects, if proxied { 2 } else { 0 }, "{:?}", requests);
+            assert!(requests
+                .iter()
+                .any(|r| r.starts_with(&format!("POST /{}/repo.git/git-upload-pack ", path))));
+        }
+    }
+
+    #[test]
+    fn gives_up_on_a_stalled_transfer() {
+        struct Stalls;
+        impl Read for Stalls {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                thread::sleep(Duration::from_secs(60));
+                Ok(0)
+            }
+        }
+
+        let mut body = LowSpeedLimit::new(Stalls, 1, Duration::from_millis(50));
+        let error = body.read(&mut [0; 16]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+
+        let mut body = LowSpeedLimit::new(&b"fast enough"[..], 1, Duration::from_secs(60));
+        let mut data = String::new();
+        body.read_to_string(&mut data).unwrap();
+        assert_eq!(data, "fast enough");
+    }
+}
@@ -0,0 +1,238 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::repository::git_dir;
+use crate::utils::glob_matches;
+use crate::vfs::{FileKind, RealFs, Vfs};
+use crate::Result;
+
+/// One line of an ignore file.
+#[derive(Debug, Clone)]
+struct Pattern {
+    /// The file the pattern was read from, relative to the workspace root.
+    source: PathBuf,
+    line: usize,
+    /// The line as it was written.
+    text: String,
+    /// The directory of the `.gitignore` file, which anchored patterns are relative to.
+    base: String,
+    glob: String,
+    negated: bool,
+    dir_only: bool,
+    anchored: bool,
+}
+
+impl Pattern {
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+
+        let path = match self.base.as_str() {
+            "" => path,
+            base => match path
+                .strip_prefix(base)
+                .and_then(|path| path.strip_prefix('/'))
+            {
+                Some(path) => path,
+                None => return false,
+            },
+        };
+
+        if self.anchored {
+            glob_matches(self.glob.as_bytes(), path.as_bytes())
+        } else {
+            let name = path.rsplit('/').next().unwrap_or(path);
+            glob_matches(self.glob.as_bytes(), name.as_bytes())
+        }
+    }
+}
+
+/// The pattern that decided whether a path is ignored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match<'a> {
+    /// The ignore file the pattern is in, relative to the workspace root.
+    pub source: &'a Path,
+    pub line: usize,
+    pub pattern: &'a str,
+    /// Whether the pattern starts with `!`, re-including the path rather than ignoring it.
+    pub negated: bool,
+    /// The path the pattern matched: either the path asked about, or a directory it's in.
+    pub path: PathBuf,
+}
+
+/// The ignore rules for a workspace: `.git/info/exclude`, and a `.gitignore` in any directory.
+/// Patterns in deeper directories take precedence, and within a file later patterns win.
+#[derive(Debug, Clone, Default)]
+pub struct Ignores {
+    patterns: Vec<Pattern>,
+}
+
+impl Ignores {
+    /// Read every ignore file in a workspace. Missing files are treated as empty.
+    pub fn load(root_path: &Path) -> Result<Self> {
+        Self::load_from(&RealFs, root_path, &git_dir(root_path))
+    }
+
+    /// Read every ignore file in a workspace on some filesystem, whose `.git` directory is
+    /// at `git_path`.
+    pub fn load_from<F: Vfs>(fs: &F, root_path: &Path, git_path: &Path) -> Result<Self> {
+        let mut ignores = Self::default();
+
+        // Shown relative to the working tree, like every other ignore file, where it can be.
+        let exclude = git_path.join("info").join("exclude");
+        let exclude = match exclude.strip_prefix(root_path) {
+            Ok(relative) => relative.to_owned(),
+            Err(_) => exclude,
+        };
+        let mut sources = vec![(exclude, String::new())];
+        find_gitignores(fs, root_path, Path::new(""), &mut sources)?;
+        sources[1..].sort_by_key(|(_, base)| Path::new(base).components().count());
+
+        for (source, base) in sources {
+            match fs.read(&root_path.join(&source)) {
+                Ok(content) => {
+                    let content = String::from_utf8(content)
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                    ignores.parse(&content, &source, &base);
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(ignores)
+    }
+
+    /// Add the patterns in an ignore file, found in the directory `base`.
+    pub fn parse(&mut self, content: &str, source: &Path, base: &str) {
+        for (i, text) in content.lines().enumerate() {
+            let pattern = text.trim_end();
+            if pattern.is_empty() || pattern.starts_with('#') {
+                continue;
+            }
+
+            let (negated, pattern) = match pattern.strip_prefix('!') {
+                Some(pattern) => (true, pattern),
+                None => (false, pattern.strip_prefix('\\').unwrap_or(pattern)),
+            };
+            let (dir_only, pattern) = match pattern.strip_suffix('/') {
+                Some(pattern) => (true, pattern),
+                None => (false, pattern),
+            };
+            let anchored = pattern.contains('/');
+
+            self.patterns.push(Pattern {
+                source: source.to_owned(),
+                line: i + 1,
+                text: text.trim_end().to_owned(),
+                base: base.to_owned(),
+                glob: pattern.trim_start_matches('/').to_owned(),
+                negated,
+                dir_only,
+                anchored,
+            });
+        }
+    }
+
+    /// Find the pattern that decides whether a path, relative to the workspace root, is
+    /// ignored. A path inside an ignored directory is ignored whatever its own patterns say.
+    pub fn matching(&self, path: &Path, is_dir: bool) -> Option<Match<'_>> {
+        let ancestors: Vec<_> = path.ancestors().skip(1).collect();
+
+        for dir in ancestors.into_iter().rev() {
+            if dir.as_os_str().is_empty() {
+                continue;
+            }
+            if let Some(found) = self.last_match(dir, true).filter(|found| !found.negated) {
+                return Some(found);
+            }
+        }
+
+        self.last_match(path, is_dir)
+    }
+
+    /// Whether a path, relative to the workspace root, is ignored.
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        self.matching(path, is_dir)
+            .is_some_and(|found| !found.negated)
+    }
+
+    fn last_match(&self, path: &Path, is_dir: bool) -> Option<Match<'_>> {
+        let text = path.to_string_lossy();
+
+        self.patterns
+            .iter()
+            .rev()
+            .find(|pattern| pattern.matches(&text, is_dir))
+            .map(|pattern| Match {
+                source: &pattern.source,
+                line: pattern.line,
+                pattern: &pattern.text,
+                negated: pattern.negated,
+                path: path.to_owned(),
+            })
+    }
+}
+
+/// Collect the `.gitignore` files under a directory, along with the directory each is in.
+fn find_gitignores<F: Vfs>(
+    fs: &F,
+    root_path: &Path,
+    dir: &Path,
+    found: &mut Vec<(PathBuf, String)>,
+) -> Result<()> {
+    let mut entries = fs.read_dir(&root_path.join(dir))?;
+    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    for (name, kind) in entries {
+        let path = dir.join(&name);
+        if name == ".git" {
+            continue;
+        } else if name == ".gitignore" {
+            found.push((path, dir.to_string_lossy().into_owned()));
+        } else if kind == FileKind::Dir {
+            find_gitignores(fs, root_path, &path, found)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn matches_ignore_patterns() {
+        let mut ignores = Ignores::default();
+        ignores.parse(
+            "# Build output\n/target\n*.log\n!keep.log\nbuild/\ndocs/*.html\n",
+            Path::new(".gitignore"),
+            "",
+        );
+        ignores.parse("*.tmp\n", Path::new("src/.gitignore"), "src");
+
+        let ignored = |path: &str, is_dir| ignores.is_ignored(Path::new(path), is_dir);
+
+        assert!(ignored("target", true));
+        assert!(ignored("target/debug/nit", false));
+        assert!(!ignored("src/target", true));
+        assert!(ignored("src/debug.log", false));
+        assert!(!ignored("keep.log", false));
+        assert!(ignored("build", true));
+        assert!(!ignored("build", false));
+        assert!(ignored("docs/index.html", false));
+        assert!(!ignored("docs/api/index.html", false));
+        assert!(ignored("src/scratch.tmp", false));
+        assert!(!ignored("scratch.tmp", false));
+
+        let found = ignores.matching(Path::new("keep.log"), false).unwrap();
+        assert_eq!(found.source, Path::new(".gitignore"));
+        assert_eq!(found.line, 4);
+        assert_eq!(found.pattern, "!keep.log");
+        assert!(found.negated);
+    }
+}
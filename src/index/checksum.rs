@@ -1,8 +1,8 @@
-use std::io::{Read, Write};
+use std::io::Write;
 
-use sha1::{Digest, Sha1};
 use thiserror::Error;
 
+use crate::hash::{self, Hasher};
 use crate::Result;
 
 #[derive(Debug, Error)]
@@ -14,63 +14,140 @@ pub enum ChecksumError {
     CouldNotWriteFile(std::io::Error),
     #[error("Index contents did not match checksum")]
     BadChecksum,
+    #[error("Index contains a SHA-1 collision attack")]
+    Collision,
 }
 
 const CHECKSUM_SIZE: usize = 20;
+
+/// Check that a file ends with the SHA-1 of everything before it, returning everything
+/// before it.
+pub fn verify(data: &[u8]) -> Result<&[u8]> {
+    if data.len() < CHECKSUM_SIZE {
+        return Err(ChecksumError::BadChecksum.into());
+    }
+
+    let (content, checksum) = data.split_at(data.len() - CHECKSUM_SIZE);
+    let digest = hash::digest(content).map_err(|_| ChecksumError::Collision)?;
+    if digest != checksum {
+        return Err(ChecksumError::BadChecksum.into());
+    }
+
+    Ok(content)
+}
+
+/// How much is written at a time. Writes are gathered up to this size, then hashed and
+/// passed on to the file together.
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// Writes to a file through a buffer, hashing everything as it goes, so the checksum can
+/// be written at the end without holding the whole file in memory first.
 pub struct Checksum<'a, T>
 where
-    T: Read + Write,
+    T: Write,
 {
     file: &'a mut T,
-    digest: Sha1,
+    digest: Hasher,
+    buffer: Vec<u8>,
 }
 
 impl<'a, T> Checksum<'a, T>
 where
-    T: Read + Write,
+    T: Write,
 {
     pub fn new(file: &'a mut T) -> Self {
-        let digest = Sha1::new();
-        Self { file, digest }
-    }
-
-    pub fn read(&mut self, size: usize) -> Result<Vec<u8>> {
-        let mut data = vec![0; size];
-        self.file
-            .read_exact(&mut data)
-            .map_err(ChecksumError::CouldNotReadFile)?;
-
-        self.digest.update(&data);
-        Ok(data)
+        let digest = Hasher::new();
+        Self {
+            file,
+            digest,
+            buffer: Vec::with_capacity(BUFFER_SIZE),
+        }
     }
 
-    pub fn verify_checksum(&mut self) -> Result<()> {
-        let mut data = vec![0; CHECKSUM_SIZE];
-        self.file
-            .read_exact(&mut data)
-            .map_err(ChecksumError::CouldNotReadFile)?;
+    pub fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        if self.buffer.len() + bytes.len() > BUFFER_SIZE {
+            self.flush_buffer()?;
+        }
 
-        if self.digest.clone().finalize().as_slice() != data {
-            Err(ChecksumError::BadChecksum.into())
+        if bytes.len() >= BUFFER_SIZE {
+            self.write_through(bytes)
         } else {
+            self.buffer.extend_from_slice(bytes);
             Ok(())
         }
     }
 
-    pub fn write(&mut self, bytes: &[u8]) -> Result<()> {
+    pub fn write_checksum(mut self) -> Result<()> {
+        self.flush_buffer()?;
+        let digest = self
+            .digest
+            .finalize()
+            .map_err(|_| ChecksumError::Collision)?;
+
         self.file
-            .write_all(bytes)
+            .write_all(&digest)
             .map_err(ChecksumError::CouldNotWriteFile)?;
-        self.digest.update(bytes);
         Ok(())
     }
 
-    pub fn write_checksum(self) -> Result<()> {
-        let digest = self.digest.finalize();
+    fn flush_buffer(&mut self) -> Result<()> {
+        let buffer = std::mem::take(&mut self.buffer);
+        let result = self.write_through(&buffer);
+        self.buffer = buffer;
+        self.buffer.clear();
+        result
+    }
 
+    fn write_through(&mut self, bytes: &[u8]) -> Result<()> {
         self.file
-            .write_all(&digest)
+            .write_all(bytes)
             .map_err(ChecksumError::CouldNotWriteFile)?;
+        self.digest.update(bytes);
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn checksums_writes_of_any_size() {
+        let writes = [
+            vec![1; 10],
+            vec![2; BUFFER_SIZE - 5],
+            vec![3; BUFFER_SIZE * 2 + 1],
+            vec![],
+            vec![4; 7],
+        ];
+
+        let mut file = Vec::new();
+        let mut checksum = Checksum::new(&mut file);
+        for bytes in &writes {
+            checksum.write(bytes).unwrap();
+        }
+        checksum.write_checksum().unwrap();
+
+        assert_eq!(verify(&file).unwrap(), &writes.concat()[..]);
+    }
+
+    #[test]
+    #[cfg(feature = "sha1dc")]
+    fn refuses_files_holding_a_collision_attack() {
+        let mut file = Vec::new();
+        let mut checksum = Checksum::new(&mut file);
+        checksum
+            .write(&crate::hash::test::shattered_prefix())
+            .unwrap();
+        assert!(matches!(
+            checksum.write_checksum(),
+            Err(crate::Error::Checksum(ChecksumError::Collision))
+        ));
+
+        file.extend_from_slice(&[0; CHECKSUM_SIZE]);
+        assert!(matches!(
+            verify(&file),
+            Err(crate::Error::Checksum(ChecksumError::Collision))
+        ));
+    }
+}
@@ -1,24 +1,74 @@
-use crate::utils::{drain_to_array, is_executable};
+use super::{corrupt, read_array};
+use crate::utils::is_executable;
 use std::{
     ffi::OsString,
-    fs::Metadata,
-    os::unix::prelude::{MetadataExt, OsStrExt, OsStringExt},
     path::{Path, PathBuf},
 };
 
 use crate::database::ObjectId;
+use crate::sys::{OsStrExt, OsStringExt};
+use crate::vfs::Stat;
 use crate::Result;
 
+/// The low bits of an entry's flags hold the length of its path, or this if it's longer.
 const MAX_PATH_SIZE: u16 = 0xfff;
+/// Set in an entry's flags when a second word of flags follows them, which only version 3
+/// indexes have.
+const EXTENDED_FLAG: u16 = 0x4000;
+/// The extended flags that git gives a meaning to.
+const SKIP_WORKTREE_FLAG: u16 = 0x4000;
+const INTENT_TO_ADD_FLAG: u16 = 0x2000;
+/// The size of everything in an entry before its path, without any extended flags.
+const ENTRY_FIXED_SIZE: usize = 62;
+/// Entries are padded to a multiple of this many bytes.
+const ENTRY_BLOCK: usize = 8;
 const REGULAR_MODE: u32 = 0o100644;
 const EXECUTABLE_MODE: u32 = 0o100755;
+pub const SYMLINK_MODE: u32 = 0o120000;
+
+/// A file's change or modification time, to the nanosecond.
+///
+/// The index only has room for the low 32 bits of the seconds, which it reads as unsigned,
+/// so times between 1970 and 2106 survive being written and read back exactly, and times
+/// either side of that are compared by what the index can hold of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Timestamp {
+    pub seconds: i64,
+    pub nanoseconds: u32,
+}
+
+impl Timestamp {
+    pub fn new(seconds: i64, nanoseconds: i64) -> Self {
+        Self {
+            seconds,
+            nanoseconds: nanoseconds as u32,
+        }
+    }
+
+    /// Whether two times are the same, as far as the index can tell.
+    pub fn matches(&self, other: &Timestamp) -> bool {
+        self.on_disk() == other.on_disk()
+    }
+
+    /// The seconds and nanoseconds as the index stores them.
+    fn on_disk(&self) -> (u32, u32) {
+        (self.seconds as u32, self.nanoseconds)
+    }
+
+    fn from_disk(seconds: u32, nanoseconds: u32) -> Self {
+        Self {
+            seconds: seconds.into(),
+            nanoseconds,
+        }
+    }
+}
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Entry {
-    ctime: u32,
-    ctime_nsec: u32,
-    mtime: u32,
-    mtime_nsec: u32,
+    ctime: Timestamp,
+    mtime: Timestamp,
     dev: u32,
     ino: u32,
     mode: u32,
@@ -27,35 +77,28 @@ pub struct Entry {
     size: u32,
     oid: ObjectId,
     flags: u16,
+    extended_flags: u16,
     path: PathBuf,
 }
 
 impl Entry {
-    pub fn new(path: &impl AsRef<Path>, oid: ObjectId, stat: Metadata) -> Self {
-        let ctime = stat.ctime() as u32;
-        let ctime_nsec = stat.ctime_nsec() as u32;
-        let mtime = stat.mtime() as u32;
-        let mtime_nsec = stat.mtime_nsec() as u32;
-        let dev = stat.dev() as u32;
-        let ino = stat.ino() as u32;
-        let uid = stat.uid() as u32;
-        let gid = stat.gid() as u32;
-        let size = stat.size() as u32;
-        let mode = if is_executable(stat.mode()) {
-            EXECUTABLE_MODE
-        } else {
-            REGULAR_MODE
-        };
+    pub fn new(path: &impl AsRef<Path>, oid: ObjectId, stat: Stat) -> Self {
+        let ctime = Timestamp::new(stat.ctime, stat.ctime_nsec);
+        let mtime = Timestamp::new(stat.mtime, stat.mtime_nsec);
+        let dev = stat.dev as u32;
+        let ino = stat.ino as u32;
+        let uid = stat.uid;
+        let gid = stat.gid;
+        let size = stat.size as u32;
+        let mode = Entry::mode_for_stat(&stat);
 
         let path = path.as_ref().to_owned();
 
-        let flags = u16::min(path.as_os_str().as_bytes().len() as u16, MAX_PATH_SIZE);
+        let flags = path_length_flags(&path);
 
         Self {
             ctime,
-            ctime_nsec,
             mtime,
-            mtime_nsec,
             dev,
             ino,
             mode,
@@ -64,10 +107,86 @@ impl Entry {
             size,
             oid,
             flags,
+            extended_flags: 0,
+            path,
+        }
+    }
+
+    /// Create an entry for a blob read from the database, with no stat information.
+    pub fn from_db(path: &impl AsRef<Path>, oid: ObjectId, mode: u32) -> Self {
+        let path = path.as_ref().to_owned();
+        let flags = path_length_flags(&path);
+
+        Self {
+            ctime: Timestamp::default(),
+            mtime: Timestamp::default(),
+            dev: 0,
+            ino: 0,
+            mode,
+            uid: 0,
+            gid: 0,
+            size: 0,
+            oid,
+            flags,
+            extended_flags: 0,
             path,
         }
     }
 
+    /// The entry under another path. In a split index, entries that replace ones in the
+    /// shared index are written with an empty path, and take the path of the one they
+    /// replace.
+    pub fn with_path(mut self, path: PathBuf) -> Self {
+        self.flags = (self.flags & !MAX_PATH_SIZE) | path_length_flags(&path);
+        self.path = path;
+        self
+    }
+
+    /// Give the entry a mode other than the one its metadata suggested.
+    pub fn with_mode(mut self, mode: u32) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Bring the entry's stat information up to date with its file's, for when the file's
+    /// contents are known not to have changed.
+    pub fn update_stat(&mut self, stat: &Stat) {
+        self.ctime = Timestamp::new(stat.ctime, stat.ctime_nsec);
+        self.mtime = Timestamp::new(stat.mtime, stat.mtime_nsec);
+        self.dev = stat.dev as u32;
+        self.ino = stat.ino as u32;
+        self.uid = stat.uid;
+        self.gid = stat.gid;
+        self.size = stat.size as u32;
+    }
+
+    /// The mode an index entry for a file with this metadata would have.
+    pub fn mode_for_stat(stat: &Stat) -> u32 {
+        if stat.is_symlink() {
+            SYMLINK_MODE
+        } else if is_executable(stat.mode) {
+            EXECUTABLE_MODE
+        } else {
+            REGULAR_MODE
+        }
+    }
+
+    /// Whether a file's size and mode match this entry, where `mode` is the mode the
+    /// workspace gives the file. A size of zero means the size is unknown, so it's treated
+    /// as matching.
+    pub fn stat_match(&self, stat: &Stat, mode: u32) -> bool {
+        (self.size == 0 || self.size == stat.size as u32) && self.mode == mode
+    }
+
+    /// Whether a file's ctime and mtime match this entry's.
+    pub fn times_match(&self, stat: &Stat) -> bool {
+        self.ctime
+            .matches(&Timestamp::new(stat.ctime, stat.ctime_nsec))
+            && self
+                .mtime
+                .matches(&Timestamp::new(stat.mtime, stat.mtime_nsec))
+    }
+
     pub fn parent_directories(&self) -> Vec<PathBuf> {
         let path = PathBuf::from(&self.path);
         let mut directories: Vec<_> = path.ancestors().map(|c| c.to_owned()).skip(1).collect();
@@ -77,16 +196,20 @@ impl Entry {
         directories.into_iter().rev().collect()
     }
 
+    /// The entry as it's written in the index.
     pub fn bytes(&self) -> Vec<u8> {
-        const ENTRY_BLOCK: usize = 8;
-
         let mut bytes = Vec::new();
+        self.write_to(&mut bytes);
+        bytes
+    }
+
+    /// Add the entry, as it's written in the index, to the end of `bytes`.
+    pub fn write_to(&self, bytes: &mut Vec<u8>) {
+        let start = bytes.len();
 
         let Self {
             ctime,
-            ctime_nsec,
             mtime,
-            mtime_nsec,
             dev,
             ino,
             mode,
@@ -95,53 +218,116 @@ impl Entry {
             size,
             oid,
             flags,
+            extended_flags,
             path,
         } = &self;
 
+        let (ctime, ctime_nsec) = ctime.on_disk();
+        let (mtime, mtime_nsec) = mtime.on_disk();
         for &item in &[
-            ctime, ctime_nsec, mtime, mtime_nsec, dev, ino, mode, uid, gid, size,
+            &ctime,
+            &ctime_nsec,
+            &mtime,
+            &mtime_nsec,
+            dev,
+            ino,
+            mode,
+            uid,
+            gid,
+            size,
         ] {
             let bs = item.to_be_bytes();
             bytes.extend_from_slice(&bs);
         }
 
         bytes.extend_from_slice(oid.bytes());
-        bytes.extend_from_slice(&flags.to_be_bytes());
+        if *extended_flags == 0 {
+            bytes.extend_from_slice(&(flags & !EXTENDED_FLAG).to_be_bytes());
+        } else {
+            bytes.extend_from_slice(&(flags | EXTENDED_FLAG).to_be_bytes());
+            bytes.extend_from_slice(&extended_flags.to_be_bytes());
+        }
         bytes.extend_from_slice(path.as_os_str().as_bytes());
         bytes.extend_from_slice(b"\0");
 
-        while bytes.len() % ENTRY_BLOCK != 0 {
+        while !(bytes.len() - start).is_multiple_of(ENTRY_BLOCK) {
             bytes.push(b'\0');
         }
-
-        bytes
     }
 
-    pub fn parse(mut data: Vec<u8>) -> Result<Self> {
-        let ctime = u32::from_be_bytes(drain_to_array(&mut data));
-        let ctime_nsec = u32::from_be_bytes(drain_to_array(&mut data));
-        let mtime = u32::from_be_bytes(drain_to_array(&mut data));
-        let mtime_nsec = u32::from_be_bytes(drain_to_array(&mut data));
-        let dev = u32::from_be_bytes(drain_to_array(&mut data));
-        let ino = u32::from_be_bytes(drain_to_array(&mut data));
-        let mode = u32::from_be_bytes(drain_to_array(&mut data));
-        let uid = u32::from_be_bytes(drain_to_array(&mut data));
-        let gid = u32::from_be_bytes(drain_to_array(&mut data));
-        let size = u32::from_be_bytes(drain_to_array(&mut data));
+    /// Parse the entry that starts `offset` bytes into the contents of an index of the
+    /// given version, returning it along with the offset of the entry after it.
+    pub fn parse(data: &[u8], offset: usize, version: u32) -> Result<(Self, usize)> {
+        let word = |n: usize| -> Result<u32> {
+            Ok(u32::from_be_bytes(read_array(
+                data,
+                offset + n * 4,
+                "entry is truncated",
+            )?))
+        };
+
+        let ctime = Timestamp::from_disk(word(0)?, word(1)?);
+        let mtime = Timestamp::from_disk(word(2)?, word(3)?);
+        let dev = word(4)?;
+        let ino = word(5)?;
+        let mode = word(6)?;
+        let uid = word(7)?;
+        let gid = word(8)?;
+        let size = word(9)?;
+
+        let oid: [u8; 20] = read_array(data, offset + 40, "entry is truncated")?;
+        let oid = oid.into();
+
+        let flags = u16::from_be_bytes(read_array(data, offset + 60, "entry is truncated")?);
 
-        let oid = drain_to_array(&mut data).into();
+        let mut fixed_size = ENTRY_FIXED_SIZE;
+        let mut extended_flags = 0;
+        if flags & EXTENDED_FLAG != 0 {
+            if version < 3 {
+                return Err(corrupt(
+                    offset + 60,
+                    "entry has extended flags, which need index version 3",
+                ));
+            }
+            extended_flags =
+                u16::from_be_bytes(read_array(data, offset + fixed_size, "entry is truncated")?);
+            fixed_size += 2;
+        }
+
+        // Paths too long to fit their length in the flags are only ended by a null byte,
+        // as git writes them.
+        let path_start = offset + fixed_size;
+        let path_len = data[path_start..]
+            .iter()
+            .position(|&b| b == b'\0')
+            .ok_or_else(|| corrupt(path_start, "entry's path isn't terminated"))?;
+        let path = &data[path_start..path_start + path_len];
+
+        if path_len.min(MAX_PATH_SIZE as usize) != (flags & MAX_PATH_SIZE) as usize {
+            return Err(corrupt(
+                offset + 60,
+                "entry's path length doesn't match its flags",
+            ));
+        }
+        // Only a split index has entries with empty paths, which it checks for itself.
+        if !path.is_empty() && !is_valid_path(path) {
+            return Err(corrupt(path_start, "entry has an invalid path"));
+        }
 
-        let arr = drain_to_array(&mut data);
-        let flags = u16::from_be_bytes(arr);
+        // Entries are padded with between one and eight null bytes, to a multiple of eight.
+        let path_end = path_start + path_len;
+        let next = offset + (fixed_size + path_len + ENTRY_BLOCK) / ENTRY_BLOCK * ENTRY_BLOCK;
+        match data.get(path_end..next) {
+            Some(padding) if padding.iter().all(|&b| b == b'\0') => {}
+            Some(_) => return Err(corrupt(path_end, "entry's padding isn't null bytes")),
+            None => return Err(corrupt(path_end, "entry is truncated")),
+        }
 
-        let path: Vec<_> = data.into_iter().take_while(|&b| b != b'\0').collect();
-        let path = PathBuf::from(OsString::from_vec(path));
+        let path = PathBuf::from(OsString::from_vec(path.to_vec()));
 
-        Ok(Self {
+        let entry = Self {
             ctime,
-            ctime_nsec,
             mtime,
-            mtime_nsec,
             dev,
             ino,
             mode,
@@ -150,10 +336,28 @@ impl Entry {
             size,
             oid,
             flags,
+            extended_flags,
             path,
-        })
+        };
+
+        Ok((entry, next))
     }
 
+    /// Whether the entry has flags that only a version 3 index can hold.
+    pub fn is_extended(&self) -> bool {
+        self.extended_flags != 0
+    }
+
+    /// Whether the file should be left out of the working tree, as in a sparse checkout.
+    pub fn skip_worktree(&self) -> bool {
+        self.extended_flags & SKIP_WORKTREE_FLAG != 0
+    }
+
+    /// Whether the path was added with `git add -N`, recording that it'll be added without
+    /// its contents yet.
+    pub fn intent_to_add(&self) -> bool {
+        self.extended_flags & INTENT_TO_ADD_FLAG != 0
+    }
     /// Get a reference to the entry's path.
     pub fn path(&self) -> &Path {
         &self.path
@@ -164,8 +368,35 @@ impl Entry {
         self.mode
     }
 
+    /// When the file was last changed, as of when the entry was made.
+    pub fn ctime(&self) -> Timestamp {
+        self.ctime
+    }
+
+    /// When the file was last modified, as of when the entry was made.
+    pub fn mtime(&self) -> Timestamp {
+        self.mtime
+    }
+
     /// Get a reference to the entry's ObjectId.
     pub fn oid(&self) -> &ObjectId {
         &self.oid
     }
 }
+
+/// The path-length bits of an entry's flags.
+fn path_length_flags(path: &Path) -> u16 {
+    path.as_os_str()
+        .as_bytes()
+        .len()
+        .min(MAX_PATH_SIZE as usize) as u16
+}
+
+/// Whether a path is one that can be stored in the index: relative, with no empty, `.` or
+/// `..` components, and nothing inside a `.git` directory.
+pub fn is_valid_path(path: &[u8]) -> bool {
+    !path.is_empty()
+        && path
+            .split(|&b| b == b'/')
+            .all(|component| !matches!(component, b"" | b"." | b".." | b".git"))
+}
@@ -0,0 +1,155 @@
+//! EWAH-compressed bitmaps, as git stores them in index extensions. A bitmap is a list of
+//! 64-bit words, where each run of words starts with a marker word saying how many words
+//! of all zeros or all ones it stands for, and how many literal words follow it.
+
+use std::collections::BTreeSet;
+use std::convert::TryInto;
+
+use super::corrupt;
+use crate::Result;
+
+/// How many bits of a marker word hold the length of its run of clean words.
+const RUNNING_BITS: u32 = 32;
+const MAX_RUNNING_LENGTH: u64 = (1 << RUNNING_BITS) - 1;
+/// How many bits of a marker word hold the number of literal words that follow it.
+const MAX_LITERAL_WORDS: u64 = (1 << (64 - 1 - RUNNING_BITS)) - 1;
+
+/// Serialize a set of bit positions, all less than `size`.
+pub fn write(bits: &BTreeSet<usize>, size: usize, output: &mut Vec<u8>) {
+    let mut words = vec![0u64; size.div_ceil(64)];
+    for &bit in bits {
+        words[bit / 64] |= 1 << (bit % 64);
+    }
+
+    let mut buffer = Vec::new();
+    let mut i = 0;
+    let marker = loop {
+        let run_word = words.get(i).copied().filter(|&w| w == 0 || w == u64::MAX);
+        let run_bit = run_word.map_or(0, |w| w & 1);
+        let mut run = 0;
+        if let Some(clean) = run_word {
+            while run < MAX_RUNNING_LENGTH && words.get(i) == Some(&clean) {
+                run += 1;
+                i += 1;
+            }
+        }
+
+        let start = i;
+        while ((i - start) as u64) < MAX_LITERAL_WORDS
+            && words.get(i).is_some_and(|&w| w != 0 && w != u64::MAX)
+        {
+            i += 1;
+        }
+
+        let marker = buffer.len();
+        buffer.push(run_bit | (run << 1) | (((i - start) as u64) << (RUNNING_BITS + 1)));
+        buffer.extend_from_slice(&words[start..i]);
+        if i >= words.len() {
+            break marker;
+        }
+    };
+
+    output.extend_from_slice(&(size as u32).to_be_bytes());
+    output.extend_from_slice(&(buffer.len() as u32).to_be_bytes());
+    for word in &buffer {
+        output.extend_from_slice(&word.to_be_bytes());
+    }
+    output.extend_from_slice(&(marker as u32).to_be_bytes());
+}
+
+/// Read a bitmap from the start of `data`, which is `offset` bytes into the index, returning
+/// the positions of its set bits and how many bytes it took up. Every set bit has to be
+/// below `limit`.
+pub fn read(data: &[u8], offset: usize, limit: usize) -> Result<(BTreeSet<usize>, usize)> {
+    let truncated = || corrupt(offset, "bitmap is truncated");
+    let u32_at = |at: usize| -> Result<usize> {
+        let bytes = data.get(at..at + 4).ok_or_else(truncated)?;
+        Ok(u32::from_be_bytes(bytes.try_into().unwrap()) as usize)
+    };
+
+    let word_count = u32_at(4)?;
+    let words_end = word_count
+        .checked_mul(8)
+        .and_then(|len| len.checked_add(8))
+        .filter(|&end| end + 4 <= data.len())
+        .ok_or_else(truncated)?;
+    let word = |i: usize| u64::from_be_bytes(data[8 + i * 8..16 + i * 8].try_into().unwrap());
+    let too_big = || corrupt(offset, "bitmap has bits set past the end of the index");
+
+    let mut bits = BTreeSet::new();
+    // The number of the bitmap word that's next, and of the stored word that's next.
+    let mut position: usize = 0;
+    let mut i = 0;
+    while i < word_count {
+        let marker = word(i);
+        let run = ((marker >> 1) & MAX_RUNNING_LENGTH) as usize;
+        let literals = (marker >> (RUNNING_BITS + 1)) as usize;
+        i += 1;
+
+        let run_end = position.saturating_add(run);
+        if marker & 1 == 1 && run > 0 {
+            if run_end.saturating_mul(64) > limit {
+                return Err(too_big());
+            }
+            bits.extend(position * 64..run_end * 64);
+        }
+        position = run_end;
+
+        if i + literals > word_count {
+            return Err(truncated());
+        }
+        for literal in (i..i + literals).map(word) {
+            let start = position.saturating_mul(64);
+            for bit in (0..64).filter(|bit| literal & (1 << bit) != 0) {
+                if start.saturating_add(bit) >= limit {
+                    return Err(too_big());
+                }
+                bits.insert(start + bit);
+            }
+            position = position.saturating_add(1);
+        }
+        i += literals;
+    }
+
+    Ok((bits, words_end + 4))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trips_bitmaps() {
+        let cases: Vec<(BTreeSet<usize>, usize)> = vec![
+            (BTreeSet::new(), 0),
+            (BTreeSet::new(), 100),
+            ([0, 5, 63, 64].iter().copied().collect(), 70),
+            ((0..640).collect(), 700),
+            ((200..1000).chain([5000, 5003]).collect(), 6000),
+        ];
+
+        for (bits, size) in cases {
+            let mut bytes = Vec::new();
+            write(&bits, size, &mut bytes);
+            let (read_bits, len) = read(&bytes, 0, size).unwrap();
+            assert_eq!(read_bits, bits);
+            assert_eq!(len, bytes.len());
+        }
+    }
+
+    #[test]
+    fn writes_gits_layout() {
+        let mut bytes = Vec::new();
+        write(&[1, 3].iter().copied().collect(), 4, &mut bytes);
+        // Four bits, two words: a marker for no clean words and one literal, then the
+        // literal, and the marker's position.
+        let mut expected = vec![0, 0, 0, 4, 0, 0, 0, 2];
+        expected.extend_from_slice(&(1u64 << 33).to_be_bytes());
+        expected.extend_from_slice(&0b1010u64.to_be_bytes());
+        expected.extend_from_slice(&[0, 0, 0, 0]);
+        assert_eq!(bytes, expected);
+
+        assert!(read(&bytes, 0, 3).is_err());
+        assert!(read(&bytes[..bytes.len() - 1], 0, 4).is_err());
+    }
+}
@@ -1,19 +1,30 @@
-use crate::{database::ObjectId, lockfile::Lockfile, utils::drain_to_array};
+use crate::{
+    database::{DatabaseEntry, ObjectId},
+    lockfile::Lockfile,
+    vfs::{RealFs, Stat, Vfs},
+};
 
 use crate::Result;
 use std::{
-    collections::{BTreeMap, HashMap, HashSet},
-    fs::{File, Metadata},
-    io::{Read, Write},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    convert::TryInto,
+    io::Write,
     path::{Path, PathBuf},
+    thread,
+    time::{Duration, SystemTime},
 };
 use thiserror::Error;
 
 pub mod checksum;
 pub mod entry;
+mod ewah;
+pub mod offsets;
+pub mod split;
 
 use checksum::Checksum;
 use entry::Entry;
+use offsets::{Block, END_OF_ENTRIES_SIGNATURE, OFFSET_TABLE_SIGNATURE, THREAD_COST};
+use split::{Link, DEFAULT_MAX_PERCENT_CHANGE, SHARED_INDEX_PREFIX};
 
 #[derive(Debug, Error)]
 #[non_exhaustive]
@@ -24,92 +35,481 @@ pub enum IndexError {
     DigestError,
     #[error("Could not parse index header")]
     BadHeader,
-    #[error("Incorrect version, expected {}, got {0}", VERSION)]
+    #[error(
+        "Incorrect version, expected {} or {}, got {0}",
+        VERSION,
+        EXTENDED_VERSION
+    )]
     IncorrectVersion(u32),
     #[error("Incorrect signature, expected {}, got {0}", SIGNATURE)]
     IncorrectSignature(String),
+    #[error("index file corrupt at byte {offset}: {reason}")]
+    Corrupt { offset: usize, reason: &'static str },
+    #[error("could not read shared index file '{0}'")]
+    MissingSharedIndex(PathBuf),
 }
 
-pub struct Index {
+pub struct Index<F: Vfs = RealFs> {
+    fs: F,
     pathname: PathBuf,
-    lockfile: Lockfile,
+    lockfile: Lockfile<F>,
     entries: BTreeMap<PathBuf, Entry>,
     parents: HashMap<PathBuf, HashSet<PathBuf>>,
     changed: bool,
+    /// The shared index the entries were split from, when the index was read or last
+    /// written as a split index.
+    shared: Option<SharedIndex>,
+    /// Whether to write a split index. If this isn't set, the index is written split if it
+    /// was read split.
+    split: Option<bool>,
+    max_percent_change: usize,
+    /// How many threads to load the index on, where it says how to split up its entries,
+    /// or 0 for one per CPU.
+    threads: usize,
+    end_of_entries: bool,
+    offset_table: bool,
+}
+
+/// The entries of a shared index file, in the order they're in there.
+struct SharedIndex {
+    checksum: ObjectId,
+    entries: Vec<Entry>,
 }
 
 const HEADER_SIZE: usize = 12;
 const SIGNATURE: &str = "DIRC";
 const VERSION: u32 = 2;
+/// The version written when any entry has extended flags.
+const EXTENDED_VERSION: u32 = 3;
+/// How long a shared index no index is using is kept, as git keeps them by default.
+const SHARED_INDEX_EXPIRY: Duration = Duration::from_secs(14 * 24 * 60 * 60);
 
 impl Index {
     pub fn new(path: impl AsRef<Path>) -> Self {
-        let lockfile = Lockfile::new(path.as_ref());
+        Self::with_vfs(RealFs, path)
+    }
+}
+
+impl<F: Vfs> Index<F> {
+    pub fn with_vfs(fs: F, path: impl AsRef<Path>) -> Self {
+        let lockfile = Lockfile::with_vfs(fs.clone(), path.as_ref());
         Self {
+            fs,
             lockfile,
             pathname: path.as_ref().to_owned(),
             entries: BTreeMap::new(),
             parents: HashMap::new(),
             changed: false,
+            shared: None,
+            split: None,
+            max_percent_change: DEFAULT_MAX_PERCENT_CHANGE,
+            threads: 0,
+            end_of_entries: false,
+            offset_table: false,
         }
     }
 
-    pub fn add(&mut self, path: &impl AsRef<Path>, oid: ObjectId, metadata: Metadata) {
-        let entry = Entry::new(&path.as_ref(), oid, metadata);
+    /// Write the index split in two, or whole, however it was read. A split index keeps
+    /// most entries in a shared index that's only rewritten once enough of them have
+    /// changed, so most writes only write the entries that have.
+    pub fn with_split_index(mut self, split: bool) -> Self {
+        self.split = Some(split);
+        self
+    }
+
+    /// Write a new shared index once more than this percentage of the entries aren't in
+    /// the current one.
+    pub fn with_max_percent_change(mut self, percent: usize) -> Self {
+        self.max_percent_change = percent;
+        self
+    }
+
+    /// Load the index on up to this many threads, or on one per CPU if it's 0. The index
+    /// is only loaded on more than one if it was written with an offset table.
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Write an extension saying where the entries end, so that the extensions after them
+    /// can be read without parsing the entries first.
+    pub fn with_end_of_entries(mut self, record: bool) -> Self {
+        self.end_of_entries = record;
+        self
+    }
+
+    /// Write an offset table, saying where blocks of entries start, so that each block
+    /// can be parsed on a thread of its own. It's only written for indexes with enough
+    /// entries to be worth it, and only used if the end of the entries is recorded too.
+    pub fn with_offset_table(mut self, record: bool) -> Self {
+        self.offset_table = record;
+        self
+    }
+
+    pub fn add(&mut self, path: &impl AsRef<Path>, oid: ObjectId, metadata: Stat) {
+        let mode = Entry::mode_for_stat(&metadata);
+        self.add_with_mode(path, oid, metadata, mode);
+    }
+
+    /// Add an entry whose mode isn't the one its metadata suggests, such as a symlink that's
+    /// checked out as a plain file.
+    pub fn add_with_mode(
+        &mut self,
+        path: &impl AsRef<Path>,
+        oid: ObjectId,
+        metadata: Stat,
+        mode: u32,
+    ) {
+        let entry = Entry::new(&path.as_ref(), oid, metadata).with_mode(mode);
         self.discard_conflicts(&entry);
         self.store_entry(entry);
         self.changed = true;
     }
 
+    /// Add an entry for a blob stored in the database, without any stat information.
+    pub fn add_from_db(&mut self, path: &impl AsRef<Path>, item: &DatabaseEntry) {
+        let entry = Entry::from_db(path, *item.oid(), item.mode());
+        self.discard_conflicts(&entry);
+        self.store_entry(entry);
+        self.changed = true;
+    }
+
+    /// Give a path's entry another mode, returning whether it has an entry to change.
+    pub fn set_mode(&mut self, path: &impl AsRef<Path>, mode: u32) -> bool {
+        match self.entries.get_mut(path.as_ref()) {
+            Some(entry) => {
+                *entry = entry.clone().with_mode(mode);
+                self.changed = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Bring a path's entry up to date with its file's stat information, for when the
+    /// file's contents haven't changed.
+    pub fn update_entry_stat(&mut self, path: &impl AsRef<Path>, stat: &Stat) {
+        if let Some(entry) = self.entries.get_mut(path.as_ref()) {
+            entry.update_stat(stat);
+            self.changed = true;
+        }
+    }
+
+    /// Remove a path from the index, along with anything nested under it.
+    pub fn remove(&mut self, path: &impl AsRef<Path>) {
+        let path = path.as_ref();
+        self.remove_entry(path);
+        self.remove_children(path);
+        self.changed = true;
+    }
+
     pub fn entries(&self) -> &BTreeMap<PathBuf, Entry> {
         &self.entries
     }
 
+    /// Get the entry stored for a path, if there is one.
+    pub fn entry_for_path(&self, path: &impl AsRef<Path>) -> Option<&Entry> {
+        self.entries.get(path.as_ref())
+    }
+
+    /// Whether a path is tracked, either as a file or as a directory containing tracked files.
+    pub fn is_tracked(&self, path: &impl AsRef<Path>) -> bool {
+        self.entries.contains_key(path.as_ref()) || self.is_tracked_directory(path)
+    }
+
+    /// Whether a path is a directory containing tracked files.
+    pub fn is_tracked_directory(&self, path: &impl AsRef<Path>) -> bool {
+        self.parents.contains_key(path.as_ref())
+    }
+
     pub fn load(&mut self) -> Result<()> {
         self.clear();
-        let file = self.open_index_file()?;
 
-        if let Some(mut f) = file {
-            let mut reader = Checksum::new(&mut f);
-            let count = self.read_header(&mut reader)?;
-            self.read_entries(&mut reader, count)?;
-            reader.verify_checksum()?;
+        if let Some(data) = self.read_index_file()? {
+            let (version, count) = self.read_header(&data)?;
+            let content = checksum::verify(&data)?;
+            let (entries, extensions) =
+                self.read_entries_and_extensions(content, version, count)?;
+            match extensions.link {
+                Some((offset, link)) if !Link::base(link, offset)?.is_null() => {
+                    self.merge_shared(entries, link, offset)?
+                }
+                _ => {
+                    for (entry, offset) in entries {
+                        if entry.path().as_os_str().is_empty() {
+                            return Err(corrupt(offset, "entry has an invalid path"));
+                        }
+                        self.store_entry(entry);
+                    }
+                }
+            }
         }
 
         Ok(())
     }
 
+    /// Parse the entries and find the extensions that follow them. If the index says where
+    /// its entries end and where blocks of them start, the blocks are parsed on as many
+    /// threads as the index is allowed.
+    fn read_entries_and_extensions<'a>(
+        &self,
+        data: &'a [u8],
+        version: u32,
+        count: usize,
+    ) -> Result<(Vec<(Entry, usize)>, Extensions<'a>)> {
+        if let Some(end) = offsets::find_end_of_entries(data) {
+            let extensions = read_extensions(data, end)?;
+            let blocks = extensions
+                .offset_table
+                .and_then(offsets::parse_offset_table)
+                .filter(|blocks| blocks.iter().map(|block| block.count).sum::<usize>() == count);
+            let threads = self.threads();
+            if let Some(blocks) = blocks.filter(|_| threads > 1) {
+                let entries = read_entry_blocks(data, version, &blocks, end, threads)?;
+                return Ok((entries, extensions));
+            }
+        }
+
+        let (entries, end) = read_entries(data, version, count)?;
+        Ok((entries, read_extensions(data, end)?))
+    }
+
+    /// Put together the entries of a split index from the shared index its link extension
+    /// names and the entries in the index itself: first those that replace entries in the
+    /// shared index, which have no paths of their own, then those that were added.
+    fn merge_shared(
+        &mut self,
+        entries: Vec<(Entry, usize)>,
+        link: &[u8],
+        offset: usize,
+    ) -> Result<()> {
+        let shared = self.read_shared_index(&Link::base(link, offset)?)?;
+        let link = Link::parse(link, offset, shared.entries.len())?;
+
+        let mut entries = entries.into_iter();
+        let mut merged = shared.entries.clone();
+        for &position in &link.replace {
+            let (entry, entry_offset) = entries
+                .next()
+                .ok_or_else(|| corrupt(offset, "link extension replaces missing entries"))?;
+            if !entry.path().as_os_str().is_empty() {
+                return Err(corrupt(entry_offset, "replacing entry has a path"));
+            }
+            let path = merged[position].path().to_owned();
+            merged[position] = entry.with_path(path);
+        }
+
+        for (position, entry) in merged.into_iter().enumerate() {
+            if !link.delete.contains(&position) {
+                self.store_entry(entry);
+            }
+        }
+        for (entry, entry_offset) in entries {
+            if entry.path().as_os_str().is_empty() {
+                return Err(corrupt(entry_offset, "entry has an invalid path"));
+            }
+            self.store_entry(entry);
+        }
+
+        self.shared = Some(shared);
+        Ok(())
+    }
+
+    fn read_shared_index(&self, checksum: &ObjectId) -> Result<SharedIndex> {
+        let path = self.shared_index_path(checksum);
+        let data = self
+            .fs
+            .read(&path)
+            .map_err(|_| IndexError::MissingSharedIndex(path.clone()))?;
+        let (version, count) = self.read_header(&data)?;
+        let content = checksum::verify(&data)?;
+        if data[content.len()..] != checksum.bytes()[..] {
+            return Err(IndexError::MissingSharedIndex(path).into());
+        }
+
+        let (entries, end) = read_entries(content, version, count)?;
+        if read_extensions(content, end)?.link.is_some() {
+            return Err(corrupt(end, "shared index is itself split"));
+        }
+        let mut shared = Vec::with_capacity(entries.len());
+        for (entry, offset) in entries {
+            if entry.path().as_os_str().is_empty() {
+                return Err(corrupt(offset, "entry has an invalid path"));
+            }
+            shared.push(entry);
+        }
+
+        Ok(SharedIndex {
+            checksum: *checksum,
+            entries: shared,
+        })
+    }
+
+    fn shared_index_path(&self, checksum: &ObjectId) -> PathBuf {
+        self.pathname
+            .with_file_name(format!("{}{}", SHARED_INDEX_PREFIX, checksum))
+    }
+
     pub fn load_for_update(&mut self) -> Result<()> {
         self.load()
     }
 
     pub fn write_updates(&mut self) -> Result<()> {
         if !self.changed {
-            self.lockfile.rollback()?;
+            return self.lockfile.rollback();
         }
 
         self.lockfile.hold_for_update()?;
 
-        let mut writer = Checksum::new(&mut self.lockfile);
+        if self.split.unwrap_or(self.shared.is_some()) {
+            self.write_split()?;
+        } else {
+            self.shared = None;
+            let entries: Vec<_> = self.entries.values().collect();
+            let layout = self.layout(entries.len());
+            write_index(&mut self.lockfile, &entries, None, layout)?;
+        }
 
-        let mut header: Vec<u8> = Vec::new();
-        header.extend_from_slice(SIGNATURE.as_bytes());
-        header.extend_from_slice(&VERSION.to_be_bytes());
-        header.extend_from_slice(&(self.entries.len() as u32).to_be_bytes());
+        self.lockfile.commit()?;
+        self.changed = false;
 
-        writer.write(&header)?;
+        Ok(())
+    }
 
-        let mut body = Vec::new();
-        for entry in self.entries.values() {
-            body.extend_from_slice(&entry.bytes());
+    /// Write the index as a split index: the entries that differ from those in the shared
+    /// index, and a link extension saying which of those to replace or remove. If too many
+    /// entries aren't in the shared index, a new one is written with all of them first.
+    fn write_split(&mut self) -> Result<()> {
+        let mut split = self.shared.as_ref().map(|shared| self.split_from(shared));
+        let too_many =
+            |entries: &[Entry]| entries.len() * 100 > self.entries.len() * self.max_percent_change;
+        if split.as_ref().is_none_or(|(_, entries)| too_many(entries)) {
+            let shared = self.write_shared_index()?;
+            split = Some(self.split_from(&shared));
+            self.shared = Some(shared);
         }
 
-        writer.write(&body)?;
+        let (link, entries) = split.expect("the index has been split");
+        let size = self
+            .shared
+            .as_ref()
+            .map_or(0, |shared| shared.entries.len());
+        let entries: Vec<_> = entries.iter().collect();
+        let layout = self.layout(entries.len());
+        write_index(
+            &mut self.lockfile,
+            &entries,
+            Some(link.to_bytes(size)),
+            layout,
+        )
+    }
 
-        writer.write_checksum()?;
+    /// How many threads the index can be loaded on.
+    fn threads(&self) -> usize {
+        match self.threads {
+            0 => thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        }
+    }
 
-        self.lockfile.commit()?;
-        self.changed = false;
+    /// How to lay out an index of `count` entries to be loaded on several threads. Like
+    /// git, there's a block of entries for each thread asked for, or if it's left to the
+    /// number of CPUs, for each thread there are enough entries for, leaving one CPU to
+    /// read the extensions.
+    fn layout(&self, count: usize) -> Layout {
+        let blocks = match self.threads {
+            _ if !self.offset_table => 1,
+            0 => (count / THREAD_COST).min(self.threads().saturating_sub(1)),
+            threads => threads.min(count),
+        };
+
+        Layout {
+            end_of_entries: self.end_of_entries,
+            blocks: blocks.max(1),
+        }
+    }
+
+    /// Work out how the entries differ from those in a shared index, returning the link
+    /// extension that says so and the entries to write alongside it: those that replace
+    /// shared ones, with their paths left out, and then those that were added.
+    fn split_from(&self, shared: &SharedIndex) -> (Link, Vec<Entry>) {
+        let mut link = Link {
+            base: shared.checksum,
+            delete: BTreeSet::new(),
+            replace: BTreeSet::new(),
+        };
+        let mut entries = Vec::new();
+        let mut paths = HashSet::with_capacity(shared.entries.len());
+        for (position, entry) in shared.entries.iter().enumerate() {
+            paths.insert(entry.path());
+            match self.entries.get(entry.path()) {
+                Some(current) if current == entry => {}
+                Some(current) => {
+                    link.replace.insert(position);
+                    entries.push(current.clone().with_path(PathBuf::new()));
+                }
+                None => {
+                    link.delete.insert(position);
+                }
+            }
+        }
+
+        let added = self
+            .entries
+            .values()
+            .filter(|entry| !paths.contains(entry.path()));
+        entries.extend(added.cloned());
+        (link, entries)
+    }
+
+    /// Write every entry to a new shared index, next to the index file, and clear away
+    /// any old shared indexes that have gone unused for long enough.
+    fn write_shared_index(&self) -> Result<SharedIndex> {
+        let entries: Vec<_> = self.entries.values().cloned().collect();
+        let mut data = Vec::new();
+        let entries_to_write: Vec<_> = entries.iter().collect();
+        write_index(&mut data, &entries_to_write, None, Layout::default())?;
+
+        let checksum: [u8; ObjectId::LEN] = data[data.len() - ObjectId::LEN..].try_into().unwrap();
+        let checksum = ObjectId::from(checksum);
+        let path = self.shared_index_path(&checksum);
+        let temp_path = path.with_extension("lock");
+        self.fs.write(&temp_path, &data).map_err(IndexError::from)?;
+        self.fs
+            .rename(&temp_path, &path)
+            .map_err(IndexError::from)?;
+
+        self.remove_expired_shared_indexes(&checksum)?;
+        Ok(SharedIndex { checksum, entries })
+    }
+
+    fn remove_expired_shared_indexes(&self, keep: &ObjectId) -> Result<()> {
+        let dir = match self.pathname.parent() {
+            Some(dir) => dir,
+            None => return Ok(()),
+        };
+        let expiry = SystemTime::now()
+            .checked_sub(SHARED_INDEX_EXPIRY)
+            .and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map_or(0, |time| time.as_secs() as i64);
+
+        let keep = self.shared_index_path(keep);
+        let in_use = self
+            .shared
+            .as_ref()
+            .map(|shared| self.shared_index_path(&shared.checksum));
+        for (name, _) in self.fs.read_dir(dir).map_err(IndexError::from)? {
+            let path = dir.join(&name);
+            let is_shared = name.to_str().is_some_and(|name| {
+                name.starts_with(SHARED_INDEX_PREFIX) && !name.ends_with(".lock")
+            });
+            if !is_shared || path == keep || Some(&path) == in_use.as_ref() {
+                continue;
+            }
+            if self.fs.stat(&path).is_ok_and(|stat| stat.mtime < expiry) {
+                self.fs.remove_file(&path).map_err(IndexError::from)?;
+            }
+        }
 
         Ok(())
     }
@@ -118,11 +518,12 @@ impl Index {
         self.entries.clear();
         self.parents.clear();
         self.changed = false;
+        self.shared = None;
     }
 
-    fn open_index_file(&self) -> Result<Option<File>> {
-        let res: Result<_, IndexError> = match File::open(&self.pathname) {
-            Ok(f) => Ok(Some(f)),
+    fn read_index_file(&self) -> Result<Option<Vec<u8>>> {
+        let res: Result<_, IndexError> = match self.fs.read(&self.pathname) {
+            Ok(data) => Ok(Some(data)),
             Err(e) => {
                 if e.kind() == std::io::ErrorKind::NotFound {
                     Ok(None)
@@ -135,57 +536,30 @@ impl Index {
         Ok(res?)
     }
 
-    fn read_header<T: Read + Write>(&self, reader: &mut Checksum<T>) -> Result<usize> {
-        let mut data = reader.read(HEADER_SIZE)?;
-        let signature: [u8; 4] = drain_to_array(&mut data);
+    fn read_header(&self, data: &[u8]) -> Result<(u32, usize)> {
+        let signature: [u8; 4] = read_array(data, 0, "header is truncated")?;
         let signature = std::str::from_utf8(&signature).map_err(|_| IndexError::BadHeader)?;
 
-        let version = u32::from_be_bytes(drain_to_array(&mut data));
+        let version = u32::from_be_bytes(read_array(data, 4, "header is truncated")?);
 
-        let count = u32::from_be_bytes(drain_to_array(&mut data));
+        let count = u32::from_be_bytes(read_array(data, 8, "header is truncated")?);
 
         if signature != SIGNATURE {
             return Err(IndexError::IncorrectSignature(signature.to_owned()).into());
         }
 
-        if version != VERSION {
+        if version != VERSION && version != EXTENDED_VERSION {
             return Err(IndexError::IncorrectVersion(version).into());
         }
 
-        Ok(count as usize)
-    }
-
-    fn read_entries<T: Read + Write>(
-        &mut self,
-        reader: &mut Checksum<T>,
-        count: usize,
-    ) -> Result<()> {
-        // Entries are at least 64 bytes...
-        const ENTRY_MIN_SIZE: usize = 64;
-        // ...and are padded with null bytes to always have a length divisible by 8.
-        const ENTRY_BLOCK: usize = 8;
-
-        for _ in 0..count {
-            let mut entry = reader.read(ENTRY_MIN_SIZE)?;
-
-            // Entries are null-terminated.
-            // We just read 64 bytes into this vector so we can safely unwrap .last().
-            while entry.last().unwrap() != &b'\0' {
-                entry.extend_from_slice(&reader.read(ENTRY_BLOCK)?);
-            }
-
-            let entry = Entry::parse(entry)?;
-            self.store_entry(entry);
-        }
-
-        Ok(())
+        Ok((version, count as usize))
     }
 
     fn store_entry(&mut self, entry: Entry) {
         for dirname in &entry.parent_directories() {
             self.parents
                 .entry(dirname.to_owned())
-                .or_insert_with(HashSet::new)
+                .or_default()
                 .insert(entry.path().to_owned());
         }
         self.entries.insert(entry.path().to_owned(), entry);
@@ -222,33 +596,241 @@ impl Index {
     }
 
     /// Get a mutable reference to the index's lockfile.
-    pub fn lockfile_mut(&mut self) -> &mut Lockfile {
+    pub fn lockfile_mut(&mut self) -> &mut Lockfile<F> {
         &mut self.lockfile
     }
 }
 
+/// Parse the entries that follow the header, returning each with the offset it starts at,
+/// and the offset of whatever comes after them. `data` is the whole index, without its
+/// checksum, so an entry that runs off the end of it is reported as corrupt rather than
+/// read into the checksum.
+fn read_entries(data: &[u8], version: u32, count: usize) -> Result<(Vec<(Entry, usize)>, usize)> {
+    let mut entries = Vec::with_capacity(count.min(data.len() / 64));
+    let mut offset = HEADER_SIZE;
+
+    for _ in 0..count {
+        let (entry, next) = Entry::parse(data, offset, version)?;
+        entries.push((entry, offset));
+        offset = next;
+    }
+
+    Ok((entries, offset))
+}
+
+/// Parse the entries in each block of an offset table, spreading the blocks over up to
+/// `threads` threads. Each block has to end where the next starts, and the last where the
+/// entries end.
+fn read_entry_blocks(
+    data: &[u8],
+    version: u32,
+    blocks: &[Block],
+    end: usize,
+    threads: usize,
+) -> Result<Vec<(Entry, usize)>> {
+    if blocks[0].offset != HEADER_SIZE {
+        return Err(corrupt(
+            blocks[0].offset,
+            "offset table doesn't match the entries",
+        ));
+    }
+    let ends: Vec<_> = blocks[1..]
+        .iter()
+        .map(|block| block.offset)
+        .chain(std::iter::once(end))
+        .collect();
+    let bounds: Vec<_> = blocks.iter().zip(ends).collect();
+
+    let read_blocks = |bounds: &[(&Block, usize)]| -> Result<Vec<(Entry, usize)>> {
+        let mut entries = Vec::with_capacity(bounds.iter().map(|(block, _)| block.count).sum());
+        for &(block, end) in bounds {
+            let mut offset = block.offset;
+            for _ in 0..block.count {
+                let (entry, next) = Entry::parse(data, offset, version)?;
+                entries.push((entry, offset));
+                offset = next;
+            }
+            if offset != end {
+                return Err(corrupt(offset, "offset table doesn't match the entries"));
+            }
+        }
+        Ok(entries)
+    };
+
+    let per_thread = bounds.len().div_ceil(threads);
+    let entries = thread::scope(|scope| {
+        let handles: Vec<_> = bounds
+            .chunks(per_thread)
+            .map(|bounds| scope.spawn(move || read_blocks(bounds)))
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("index worker panicked"))
+            .collect::<Result<Vec<_>>>()
+    })?;
+
+    Ok(entries.into_iter().flatten().collect())
+}
+
+/// The extensions after an index's entries that change how it's read, with where their
+/// data starts.
+#[derive(Default)]
+struct Extensions<'a> {
+    link: Option<(usize, &'a [u8])>,
+    offset_table: Option<&'a [u8]>,
+}
+
+/// Read the extensions that follow the entries, from `offset` to the end of `data`.
+/// Extensions whose names start with a capital letter, such as git's cached trees, only
+/// speed things up, so those that aren't needed are skipped; any others can't be.
+fn read_extensions(data: &[u8], mut offset: usize) -> Result<Extensions<'_>> {
+    let mut extensions = Extensions::default();
+    while offset < data.len() {
+        let reason = "unexpected data after the last entry";
+        let signature: [u8; 4] = read_array(data, offset, reason)?;
+        let size = u32::from_be_bytes(read_array(data, offset + 4, reason)?) as usize;
+        let start = offset + 8;
+        let end = start
+            .checked_add(size)
+            .filter(|&end| end <= data.len())
+            .ok_or_else(|| corrupt(offset, "extension is truncated"))?;
+
+        match &signature {
+            split::SIGNATURE => extensions.link = Some((start, &data[start..end])),
+            OFFSET_TABLE_SIGNATURE => extensions.offset_table = Some(&data[start..end]),
+            [b'A'..=b'Z', ..] => {}
+            _ => {
+                return Err(corrupt(
+                    offset,
+                    "index uses an extension that isn't understood",
+                ))
+            }
+        }
+        offset = end;
+    }
+
+    Ok(extensions)
+}
+
+/// Which of the extensions that let an index be loaded on several threads to write: the
+/// end of the entries, and an offset table if there's more than one block of entries.
+#[derive(Debug, Clone, Copy)]
+struct Layout {
+    end_of_entries: bool,
+    blocks: usize,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self {
+            end_of_entries: false,
+            blocks: 1,
+        }
+    }
+}
+
+/// Write an index holding `entries`, in order, followed by a link extension, if given,
+/// any extensions the layout asks for, and a checksum of it all.
+fn write_index(
+    file: &mut impl Write,
+    entries: &[&Entry],
+    link: Option<Vec<u8>>,
+    layout: Layout,
+) -> Result<()> {
+    let mut writer = Checksum::new(file);
+
+    // Like git, only use version 3 when something needs it.
+    let version = if entries.iter().any(|entry| entry.is_extended()) {
+        EXTENDED_VERSION
+    } else {
+        VERSION
+    };
+
+    let mut header: Vec<u8> = Vec::new();
+    header.extend_from_slice(SIGNATURE.as_bytes());
+    header.extend_from_slice(&version.to_be_bytes());
+    header.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+
+    writer.write(&header)?;
+
+    let per_block = entries.len().div_ceil(layout.blocks);
+    let mut blocks = Vec::with_capacity(layout.blocks);
+    let mut offset = HEADER_SIZE;
+    let mut bytes = Vec::new();
+    for (i, entry) in entries.iter().enumerate() {
+        if layout.blocks > 1 && i % per_block == 0 {
+            blocks.push(Block {
+                offset,
+                count: per_block.min(entries.len() - i),
+            });
+        }
+        bytes.clear();
+        entry.write_to(&mut bytes);
+        writer.write(&bytes)?;
+        offset += bytes.len();
+    }
+
+    // Git writes the offset table first, so it can be found early.
+    let mut extensions = Vec::new();
+    if blocks.len() > 1 {
+        extensions.push((OFFSET_TABLE_SIGNATURE, offsets::offset_table(&blocks)));
+    }
+    if let Some(link) = link {
+        extensions.push((split::SIGNATURE, link));
+    }
+
+    let mut headers = Vec::new();
+    for (signature, data) in extensions {
+        let start = headers.len();
+        headers.extend_from_slice(signature);
+        headers.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        writer.write(&headers[start..])?;
+        writer.write(&data)?;
+    }
+
+    if layout.end_of_entries {
+        let data = offsets::end_of_entries(offset, &headers);
+        writer.write(END_OF_ENTRIES_SIGNATURE)?;
+        writer.write(&(data.len() as u32).to_be_bytes())?;
+        writer.write(&data)?;
+    }
+
+    writer.write_checksum()
+}
+
+/// Read `N` bytes of the index starting at `offset`, failing with `reason` if the index
+/// ends first.
+fn read_array<const N: usize>(data: &[u8], offset: usize, reason: &'static str) -> Result<[u8; N]> {
+    data.get(offset..)
+        .and_then(|rest| rest.get(..N))
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| corrupt(offset, reason))
+}
+
+fn corrupt(offset: usize, reason: &'static str) -> crate::Error {
+    IndexError::Corrupt { offset, reason }.into()
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
-    use std::path::PathBuf;
+    use crate::vfs::MemoryFs;
 
     struct Scaffold {
-        index: Index,
+        index: Index<MemoryFs>,
         oid: ObjectId,
-        stat: Metadata,
+        stat: Stat,
     }
 
     fn startup() -> Scaffold {
-        let f = PathBuf::from(file!());
-        let current_dir = f.parent().unwrap();
-        let tmp_path = std::fs::canonicalize(PathBuf::from(current_dir).join("../../tmp")).unwrap();
-        let index_path = tmp_path.join("index");
+        let fs = MemoryFs::new();
+        fs.write(Path::new("/file"), b"hello").unwrap();
 
-        let stat = std::fs::metadata(file!()).unwrap();
+        let stat = fs.stat(Path::new("/file")).unwrap();
         let oid = ObjectId::from([12; 20]);
 
         Scaffold {
-            index: Index::new(index_path),
+            index: Index::with_vfs(fs, "/index"),
             stat,
             oid,
         }
@@ -277,8 +859,8 @@ mod test {
             oid,
         } = startup();
 
-        index.add(&"alice.txt", oid.clone(), stat.clone());
-        index.add(&"bob.txt", oid.clone(), stat.clone());
+        index.add(&"alice.txt", oid, stat.clone());
+        index.add(&"bob.txt", oid, stat.clone());
 
         index.add(&"alice.txt/nested.txt", oid, stat);
 
@@ -299,8 +881,8 @@ mod test {
             oid,
         } = startup();
 
-        index.add(&"alice.txt", oid.clone(), stat.clone());
-        index.add(&"nested/bob.txt", oid.clone(), stat.clone());
+        index.add(&"alice.txt", oid, stat.clone());
+        index.add(&"nested/bob.txt", oid, stat.clone());
 
         index.add(&"nested", oid, stat);
 
@@ -321,10 +903,10 @@ mod test {
             oid,
         } = startup();
 
-        index.add(&"alice.txt", oid.clone(), stat.clone());
-        index.add(&"nested/bob.txt", oid.clone(), stat.clone());
-        index.add(&"nested/inner/claire.txt", oid.clone(), stat.clone());
-        index.add(&"nested/another_inner/eve.txt", oid.clone(), stat.clone());
+        index.add(&"alice.txt", oid, stat.clone());
+        index.add(&"nested/bob.txt", oid, stat.clone());
+        index.add(&"nested/inner/claire.txt", oid, stat.clone());
+        index.add(&"nested/another_inner/eve.txt", oid, stat.clone());
 
         index.add(&"nested", oid, stat);
 
@@ -336,4 +918,391 @@ mod test {
             index.entries().keys().cloned().collect::<Vec<PathBuf>>()
         );
     }
+
+    /// The bytes of an index holding a few entries, as written to disk.
+    fn written_index() -> (MemoryFs, Vec<u8>) {
+        let Scaffold {
+            mut index,
+            stat,
+            oid,
+        } = startup();
+
+        index.add(&"alice.txt", oid, stat.clone());
+        index.add(&"nested/bob.txt", oid, stat.clone());
+        index.add(&"a-much-longer-name-for-a-file", oid, stat);
+        index.write_updates().unwrap();
+
+        let data = index.fs.read(Path::new("/index")).unwrap();
+        (index.fs, data)
+    }
+
+    /// Load an index made of `content` followed by its checksum, so the parser sees it
+    /// rather than the checksum catching the damage.
+    fn load_content(fs: &MemoryFs, content: &[u8]) -> Result<Index<MemoryFs>> {
+        use sha1::{Digest, Sha1};
+
+        let mut data = content.to_vec();
+        data.extend_from_slice(&Sha1::digest(content));
+        fs.write(Path::new("/corrupt"), &data).unwrap();
+
+        let mut index = Index::with_vfs(fs.clone(), "/corrupt");
+        index.load()?;
+        Ok(index)
+    }
+
+    fn corrupt_reason(result: Result<Index<MemoryFs>>) -> &'static str {
+        match result {
+            Err(crate::Error::Index(IndexError::Corrupt { reason, .. })) => reason,
+            Err(e) => panic!("expected a corrupt index, got {:?}", e),
+            Ok(_) => panic!("expected a corrupt index, but it loaded"),
+        }
+    }
+
+    #[test]
+    fn reads_back_what_it_writes() {
+        let (fs, data) = written_index();
+        let index = load_content(&fs, &data[..data.len() - 20]).unwrap();
+        assert_eq!(
+            index.entries().keys().cloned().collect::<Vec<_>>(),
+            vec![
+                PathBuf::from("a-much-longer-name-for-a-file"),
+                PathBuf::from("alice.txt"),
+                PathBuf::from("nested/bob.txt")
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_a_bad_checksum() {
+        let (fs, mut data) = written_index();
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        fs.write(Path::new("/index"), &data).unwrap();
+
+        let mut index = Index::with_vfs(fs, "/index");
+        assert!(matches!(
+            index.load(),
+            Err(crate::Error::Checksum(checksum::ChecksumError::BadChecksum))
+        ));
+    }
+
+    #[test]
+    fn rejects_every_truncation() {
+        let (fs, data) = written_index();
+        let content = &data[..data.len() - 20];
+
+        for len in HEADER_SIZE..content.len() {
+            corrupt_reason(load_content(&fs, &content[..len]));
+        }
+
+        for len in 0..HEADER_SIZE {
+            fs.write(Path::new("/corrupt"), &content[..len]).unwrap();
+            let mut index = Index::with_vfs(fs.clone(), "/corrupt");
+            assert!(index.load().is_err());
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_entries() {
+        let (fs, data) = written_index();
+        let content = &data[..data.len() - 20];
+        // The first entry, for a-much-longer-name-for-a-file, starts after the header, and
+        // its path is followed by five null bytes.
+        let flags = HEADER_SIZE + 60;
+        let path = HEADER_SIZE + 62;
+
+        let mut wrong_length = content.to_vec();
+        wrong_length[flags + 1] -= 1;
+        assert_eq!(
+            corrupt_reason(load_content(&fs, &wrong_length)),
+            "entry's path length doesn't match its flags"
+        );
+
+        let mut unterminated = content.to_vec();
+        for byte in &mut unterminated[path..] {
+            *byte = b'a';
+        }
+        assert_eq!(
+            corrupt_reason(load_content(&fs, &unterminated)),
+            "entry's path isn't terminated"
+        );
+
+        let mut escaping = content.to_vec();
+        escaping[path..path + 3].copy_from_slice(b"../");
+        assert_eq!(
+            corrupt_reason(load_content(&fs, &escaping)),
+            "entry has an invalid path"
+        );
+
+        let mut bad_padding = content.to_vec();
+        let padding = path + "a-much-longer-name-for-a-file".len() + 1;
+        bad_padding[padding] = b'x';
+        assert_eq!(
+            corrupt_reason(load_content(&fs, &bad_padding)),
+            "entry's padding isn't null bytes"
+        );
+
+        let mut too_many = content.to_vec();
+        too_many[11] += 1;
+        assert_eq!(
+            corrupt_reason(load_content(&fs, &too_many)),
+            "entry is truncated"
+        );
+
+        let mut trailing = content.to_vec();
+        trailing.extend_from_slice(b"junk");
+        assert_eq!(
+            corrupt_reason(load_content(&fs, &trailing)),
+            "unexpected data after the last entry"
+        );
+    }
+
+    /// Damage random bytes of a valid index, many times over, to check that whatever the
+    /// damage, loading it fails cleanly or succeeds, and never panics.
+    #[test]
+    fn fuzz_random_corruption() {
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+
+        let (fs, data) = written_index();
+        let content = &data[..data.len() - 20];
+        let mut rng = StdRng::seed_from_u64(0x6e6974);
+
+        for _ in 0..5000 {
+            let mut damaged = content.to_vec();
+            for _ in 0..rng.gen_range(1..=4) {
+                let at = rng.gen_range(0..damaged.len());
+                damaged[at] = rng.gen();
+            }
+            if rng.gen_bool(0.25) {
+                damaged.truncate(rng.gen_range(0..damaged.len()));
+            }
+
+            let _ = load_content(&fs, &damaged);
+        }
+    }
+
+    #[test]
+    fn round_trips_long_paths() {
+        let Scaffold {
+            mut index,
+            stat,
+            oid,
+        } = startup();
+
+        // Either side of the longest length the flags can hold, and far beyond it.
+        let paths: Vec<_> = [0xffe, 0xfff, 0x1000, 0x10010]
+            .iter()
+            .map(|&len| format!("{}/{}", "d".repeat(len - 2), "f"))
+            .collect();
+        for path in &paths {
+            index.add(path, oid, stat.clone());
+        }
+        index.write_updates().unwrap();
+        let written = index.fs.read(Path::new("/index")).unwrap();
+
+        let mut index = Index::with_vfs(index.fs.clone(), "/index");
+        index.load().unwrap();
+        let loaded: Vec<_> = index.entries().keys().cloned().collect();
+        let mut expected: Vec<_> = paths.iter().map(PathBuf::from).collect();
+        expected.sort();
+        assert_eq!(loaded, expected);
+
+        index.changed = true;
+        index.write_updates().unwrap();
+        assert_eq!(index.fs.read(Path::new("/index")).unwrap(), written);
+    }
+
+    #[test]
+    fn round_trips_extended_flags() {
+        let (fs, data) = written_index();
+        let content = &data[..data.len() - 20];
+
+        // Mark the first entry skip-worktree the way git does, which makes it a version 3
+        // index: the extended bit goes in the flags, followed by a second word of flags,
+        // and the path, 29 bytes long, moves along with its padding shrinking to match.
+        let flags = HEADER_SIZE + 60;
+        let mut extended = content.to_vec();
+        extended[4..8].copy_from_slice(&3u32.to_be_bytes());
+        extended[flags] |= 0x40;
+        extended.splice(flags + 2..flags + 2, [0x40, 0x00]);
+        extended.drain(flags + 4 + 29 + 1..flags + 4 + 29 + 3);
+
+        let mut index = load_content(&fs, &extended).unwrap();
+        let entries: Vec<_> = index.entries().values().collect();
+        assert!(entries[0].skip_worktree());
+        assert!(!entries[0].intent_to_add());
+        assert!(!entries[1].skip_worktree());
+
+        index.changed = true;
+        index.write_updates().unwrap();
+        let written = fs.read(Path::new("/corrupt")).unwrap();
+        assert_eq!(&written[..written.len() - 20], &extended[..]);
+
+        extended[4..8].copy_from_slice(&2u32.to_be_bytes());
+        assert_eq!(
+            corrupt_reason(load_content(&fs, &extended)),
+            "entry has extended flags, which need index version 3"
+        );
+    }
+
+    #[test]
+    fn keeps_times_past_2038_to_the_nanosecond() {
+        let Scaffold { mut index, oid, .. } = startup();
+
+        let stat = Stat {
+            kind: crate::vfs::FileKind::File,
+            mode: 0o100644,
+            size: 5,
+            mtime: 3_000_000_000,
+            mtime_nsec: 123_456_789,
+            ctime: 3_000_000_001,
+            ctime_nsec: 987_654_321,
+            dev: 0,
+            ino: 0,
+            uid: 0,
+            gid: 0,
+        };
+        index.add(&"future.txt", oid, stat.clone());
+        index.write_updates().unwrap();
+
+        let mut index = Index::with_vfs(index.fs.clone(), "/index");
+        index.load().unwrap();
+        let entry = index.entry_for_path(&"future.txt").unwrap();
+        assert_eq!(entry.mtime().seconds, 3_000_000_000);
+        assert_eq!(entry.mtime().nanoseconds, 123_456_789);
+        assert_eq!(entry.ctime().seconds, 3_000_000_001);
+        assert!(entry.times_match(&stat));
+
+        let touched = Stat {
+            mtime_nsec: 123_456_790,
+            ..stat
+        };
+        assert!(!entry.times_match(&touched));
+    }
+
+    fn shared_indexes(fs: &MemoryFs) -> Vec<String> {
+        let mut names: Vec<_> = fs
+            .read_dir(Path::new("/"))
+            .unwrap()
+            .into_iter()
+            .filter_map(|(name, _)| name.into_string().ok())
+            .filter(|name| name.starts_with(SHARED_INDEX_PREFIX))
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn round_trips_split_indexes() {
+        let Scaffold {
+            mut index,
+            stat,
+            oid,
+        } = startup();
+        index = index.with_split_index(true);
+        for name in &["a", "b", "c", "d", "e", "f", "g", "h", "i", "j"] {
+            index.add(name, oid, stat.clone());
+        }
+        index.write_updates().unwrap();
+        let shared = shared_indexes(&index.fs);
+        assert_eq!(shared.len(), 1);
+
+        // Replace one entry, remove another and add a third: few enough changes that they
+        // go in the index itself, leaving the shared index as it was.
+        let mut index = Index::with_vfs(index.fs.clone(), "/index");
+        index.load_for_update().unwrap();
+        index.add(&"b", ObjectId::from([13; 20]), stat.clone());
+        index.remove(&"c");
+        index.add(&"k", oid, stat.clone());
+        index.write_updates().unwrap();
+        assert_eq!(shared_indexes(&index.fs), shared);
+
+        let mut loaded = Index::with_vfs(index.fs.clone(), "/index");
+        loaded.load().unwrap();
+        assert_eq!(loaded.entries(), index.entries());
+        let names: Vec<_> = loaded.entries().keys().cloned().collect();
+        let expected: Vec<_> = ["a", "b", "d", "e", "f", "g", "h", "i", "j", "k"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        assert_eq!(names, expected);
+        assert_eq!(
+            loaded.entry_for_path(&"b").unwrap().oid(),
+            &ObjectId::from([13; 20])
+        );
+
+        // Enough new entries that a new shared index is written with all of them.
+        loaded.load_for_update().unwrap();
+        for name in &["l", "m", "n"] {
+            loaded.add(name, oid, stat.clone());
+        }
+        loaded.write_updates().unwrap();
+        assert_eq!(shared_indexes(&loaded.fs).len(), 2);
+
+        // A split index can't be read without its shared index, unlike one written whole.
+        for name in shared_indexes(&loaded.fs) {
+            loaded.fs.remove_file(&Path::new("/").join(name)).unwrap();
+        }
+        assert!(matches!(
+            loaded.load(),
+            Err(crate::Error::Index(IndexError::MissingSharedIndex(_)))
+        ));
+
+        let mut unsplit = Index::with_vfs(index.fs.clone(), "/index").with_split_index(false);
+        unsplit.add(&"a", oid, stat);
+        unsplit.write_updates().unwrap();
+        unsplit.load().unwrap();
+        assert_eq!(unsplit.entries().len(), 1);
+        assert!(unsplit.shared.is_none());
+    }
+
+    #[test]
+    fn loads_blocks_of_entries_on_several_threads() {
+        use sha1::{Digest, Sha1};
+
+        let Scaffold {
+            mut index,
+            stat,
+            oid,
+        } = startup();
+        index = index
+            .with_threads(3)
+            .with_end_of_entries(true)
+            .with_offset_table(true);
+        let names: Vec<_> = (0..20).map(|i| format!("file-{:02}", i)).collect();
+        for name in &names {
+            index.add(name, oid, stat.clone());
+        }
+        index.write_updates().unwrap();
+
+        let data = index.fs.read(Path::new("/index")).unwrap();
+        let content = &data[..data.len() - 20];
+        let end = offsets::find_end_of_entries(content).unwrap();
+        let extensions = read_extensions(content, end).unwrap();
+        let blocks = offsets::parse_offset_table(extensions.offset_table.unwrap()).unwrap();
+        assert_eq!(
+            blocks.iter().map(|block| block.count).collect::<Vec<_>>(),
+            vec![7, 7, 6]
+        );
+
+        let mut loaded = Index::with_vfs(index.fs.clone(), "/index").with_threads(3);
+        loaded.load().unwrap();
+        assert_eq!(loaded.entries(), index.entries());
+
+        // A table that doesn't line up with the entries is caught, rather than entries
+        // being parsed from the middle of others.
+        // The second block's offset follows the extension's header, its version and the
+        // first block.
+        let second_block = end + 8 + 4 + 8;
+        let mut misaligned = content.to_vec();
+        misaligned[second_block + 3] += 1;
+        let fs = index.fs.clone();
+        misaligned.extend_from_slice(&Sha1::digest(&misaligned));
+        fs.write(Path::new("/misaligned"), &misaligned).unwrap();
+        let mut loaded = Index::with_vfs(fs, "/misaligned").with_threads(3);
+        assert!(matches!(
+            loaded.load(),
+            Err(crate::Error::Index(IndexError::Corrupt { .. }))
+        ));
+    }
 }
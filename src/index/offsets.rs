@@ -0,0 +1,152 @@
+//! The extensions that let an index be loaded on several threads. The end of index entries
+//! extension (`EOIE`), the last in the file, says where the entries end, so the extensions
+//! can be found without parsing every entry first. The index entry offset table (`IEOT`)
+//! says where blocks of entries start, so each block can be parsed on a thread of its own.
+
+use sha1::{Digest, Sha1};
+use std::convert::TryInto;
+
+use super::HEADER_SIZE;
+use crate::database::ObjectId;
+
+pub const END_OF_ENTRIES_SIGNATURE: &[u8; 4] = b"EOIE";
+pub const OFFSET_TABLE_SIGNATURE: &[u8; 4] = b"IEOT";
+const OFFSET_TABLE_VERSION: u32 = 1;
+/// The size of the end of index entries extension's data: the offset where the entries
+/// end, and a hash of the headers of the extensions that follow them.
+const END_OF_ENTRIES_SIZE: usize = 4 + ObjectId::LEN;
+/// Roughly how many entries it's worth starting a thread for, as git reckons it.
+pub const THREAD_COST: usize = 10_000;
+
+/// A run of entries in the offset table: the offset of its first entry, and how many
+/// entries it has.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Block {
+    pub offset: usize,
+    pub count: usize,
+}
+
+/// The end of index entries extension's data, for entries that end at `offset` and are
+/// followed by extensions with the given headers: each one's signature and size.
+pub fn end_of_entries(offset: usize, headers: &[u8]) -> Vec<u8> {
+    let mut data = (offset as u32).to_be_bytes().to_vec();
+    data.extend_from_slice(&Sha1::digest(headers));
+    data
+}
+
+/// Find where the entries end from the end of index entries extension at the end of
+/// `data`, the index without its checksum. Like git, an extension that's missing, or
+/// whose hash doesn't match the extensions it should be followed by, is passed over and
+/// the entries are parsed to find their end instead.
+pub fn find_end_of_entries(data: &[u8]) -> Option<usize> {
+    let start = data.len().checked_sub(8 + END_OF_ENTRIES_SIZE)?;
+    if start < HEADER_SIZE || &data[start..start + 4] != END_OF_ENTRIES_SIGNATURE {
+        return None;
+    }
+    let u32_at = |at: usize| u32::from_be_bytes(data[at..at + 4].try_into().unwrap()) as usize;
+    if u32_at(start + 4) != END_OF_ENTRIES_SIZE {
+        return None;
+    }
+
+    let end = u32_at(start + 8);
+    if end < HEADER_SIZE || end > start {
+        return None;
+    }
+
+    let mut hasher = Sha1::new();
+    let mut offset = end;
+    while offset < start {
+        let header = data.get(offset..offset + 8)?;
+        hasher.update(header);
+        offset = offset.checked_add(8 + u32_at(offset + 4))?;
+    }
+    if offset != start || hasher.finalize()[..] != data[start + 12..] {
+        return None;
+    }
+
+    Some(end)
+}
+
+/// The offset table extension's data, for the given blocks of entries.
+pub fn offset_table(blocks: &[Block]) -> Vec<u8> {
+    let mut data = OFFSET_TABLE_VERSION.to_be_bytes().to_vec();
+    for block in blocks {
+        data.extend_from_slice(&(block.offset as u32).to_be_bytes());
+        data.extend_from_slice(&(block.count as u32).to_be_bytes());
+    }
+    data
+}
+
+/// Parse the offset table extension's data. A table of a version that isn't understood,
+/// or that's the wrong size, is ignored, as git ignores it.
+pub fn parse_offset_table(data: &[u8]) -> Option<Vec<Block>> {
+    let (version, table) = data.split_at_checked(4)?;
+    if u32::from_be_bytes(version.try_into().unwrap()) != OFFSET_TABLE_VERSION
+        || table.is_empty()
+        || table.len() % 8 != 0
+    {
+        return None;
+    }
+
+    let u32_at = |at: &[u8]| u32::from_be_bytes(at.try_into().unwrap()) as usize;
+    Some(
+        table
+            .chunks(8)
+            .map(|block| Block {
+                offset: u32_at(&block[..4]),
+                count: u32_at(&block[4..]),
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trips_offset_tables() {
+        let blocks = vec![
+            Block {
+                offset: 12,
+                count: 3,
+            },
+            Block {
+                offset: 400,
+                count: 2,
+            },
+        ];
+        let data = offset_table(&blocks);
+        assert_eq!(data.len(), 4 + 16);
+        assert_eq!(parse_offset_table(&data), Some(blocks));
+
+        assert_eq!(parse_offset_table(&data[..data.len() - 1]), None);
+        assert_eq!(parse_offset_table(&data[..4]), None);
+        let mut future = data;
+        future[3] = 2;
+        assert_eq!(parse_offset_table(&future), None);
+    }
+
+    #[test]
+    fn finds_the_end_of_the_entries() {
+        // A header, some entries, and an extension with four bytes of data.
+        let mut data = vec![0; 40];
+        let extension = [b'T', b'R', b'E', b'E', 0, 0, 0, 4, 1, 2, 3, 4];
+        data.extend_from_slice(&extension);
+        data.extend_from_slice(END_OF_ENTRIES_SIGNATURE);
+        data.extend_from_slice(&(END_OF_ENTRIES_SIZE as u32).to_be_bytes());
+        data.extend_from_slice(&end_of_entries(40, &extension[..8]));
+        assert_eq!(find_end_of_entries(&data), Some(40));
+
+        let mut wrong_hash = data.clone();
+        wrong_hash[40] = b'X';
+        assert_eq!(find_end_of_entries(&wrong_hash), None);
+
+        let mut wrong_offset = data.clone();
+        let at = data.len() - END_OF_ENTRIES_SIZE;
+        wrong_offset[at + 3] = 44;
+        assert_eq!(find_end_of_entries(&wrong_offset), None);
+
+        assert_eq!(find_end_of_entries(&data[..40]), None);
+    }
+}
@@ -0,0 +1,80 @@
+//! The `link` extension of a split index. A split index keeps most of its entries in a
+//! shared index file, `sharedindex.<checksum>`, which is left alone while only the entries
+//! that have changed since are written to the index itself.
+
+use std::collections::BTreeSet;
+use std::convert::TryInto;
+
+use super::{corrupt, ewah};
+use crate::database::ObjectId;
+use crate::Result;
+
+pub const SIGNATURE: &[u8; 4] = b"link";
+/// The prefix of a shared index file's name, which is followed by its checksum.
+pub const SHARED_INDEX_PREFIX: &str = "sharedindex.";
+/// How many of the index's entries can be missing from the shared index, as a percentage,
+/// before a new shared index is written. This is git's default for
+/// `splitIndex.maxPercentChange`.
+pub const DEFAULT_MAX_PERCENT_CHANGE: usize = 20;
+
+/// Which shared index a split index is based on, and how it differs from it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Link {
+    /// The checksum of the shared index.
+    pub base: ObjectId,
+    /// The positions of the shared index's entries that have been removed.
+    pub delete: BTreeSet<usize>,
+    /// The positions of the shared index's entries that are replaced by entries with no
+    /// path at the start of the split index, in order.
+    pub replace: BTreeSet<usize>,
+}
+
+impl Link {
+    /// Parse the extension's data, which starts `offset` bytes into the index. Its bitmaps
+    /// can only refer to the first `limit` entries, which are all the shared index has.
+    /// Without bitmaps, the extension has nothing deleted or replaced.
+    pub fn parse(data: &[u8], offset: usize, limit: usize) -> Result<Self> {
+        let mut link = Self {
+            base: Self::base(data, offset)?,
+            delete: BTreeSet::new(),
+            replace: BTreeSet::new(),
+        };
+        let mut start = ObjectId::LEN;
+        if start == data.len() {
+            return Ok(link);
+        }
+
+        let (delete, len) = ewah::read(&data[start..], offset + start, limit)?;
+        start += len;
+        let (replace, len) = ewah::read(&data[start..], offset + start, limit)?;
+        start += len;
+        if start != data.len() {
+            return Err(corrupt(
+                offset + start,
+                "garbage at the end of link extension",
+            ));
+        }
+
+        link.delete = delete;
+        link.replace = replace;
+        Ok(link)
+    }
+
+    /// The checksum of the shared index named by the extension's data, which is null if
+    /// the index isn't really split.
+    pub fn base(data: &[u8], offset: usize) -> Result<ObjectId> {
+        let base: [u8; ObjectId::LEN] = data
+            .get(..ObjectId::LEN)
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| corrupt(offset, "link extension is too short"))?;
+        Ok(base.into())
+    }
+
+    /// The extension's data, for a shared index of `size` entries.
+    pub fn to_bytes(&self, size: usize) -> Vec<u8> {
+        let mut bytes = self.base.bytes().to_vec();
+        ewah::write(&self.delete, size, &mut bytes);
+        ewah::write(&self.replace, size, &mut bytes);
+        bytes
+    }
+}
@@ -0,0 +1,214 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use crate::database::{Blob, Database, DatabaseEntry, ObjectId};
+use crate::diff::RawChange;
+use crate::fsmonitor::Scan;
+use crate::index::{entry::Entry, Index};
+use crate::vfs::{RealFs, Stat, Vfs};
+use crate::workspace::Workspace;
+use crate::Result;
+
+/// The ways a path can differ between two of the tree, index and workspace.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, PartialOrd, Ord)]
+pub enum ChangeKind {
+    Added,
+    Deleted,
+    Modified,
+    Untracked,
+}
+
+/// What a look over the workspace found: the paths whose files differ from the index, and
+/// the files whose contents match the index but whose times don't, with their stat
+/// information, so their entries can be refreshed and they needn't be read again.
+pub struct WorkspaceScan {
+    pub changes: Vec<RawChange>,
+    pub stale: Vec<(PathBuf, Stat)>,
+}
+
+/// Answers questions about how the workspace, index and stored trees differ from each other.
+pub struct Inspector<'a, F: Vfs = RealFs> {
+    database: &'a Database<F>,
+    index: &'a Index<F>,
+    workspace: &'a Workspace<F>,
+}
+
+impl<'a, F: Vfs> Inspector<'a, F> {
+    pub fn new(
+        database: &'a Database<F>,
+        index: &'a Index<F>,
+        workspace: &'a Workspace<F>,
+    ) -> Self {
+        Self {
+            database,
+            index,
+            workspace,
+        }
+    }
+
+    /// Whether a path is, or contains, a file that isn't tracked by the index.
+    pub fn is_trackable_file(&self, path: &Path, stat: &Stat) -> Result<bool> {
+        if stat.is_file() || stat.is_symlink() {
+            return Ok(!self.index.is_tracked(&path));
+        }
+
+        if !stat.is_dir() {
+            return Ok(false);
+        }
+
+        for (child, stat) in self.workspace.list_dir(path)? {
+            if self.is_trackable_file(&child, &stat)? {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Compare an index entry against the file in the workspace.
+    pub fn compare_index_to_workspace(
+        &self,
+        entry: Option<&Entry>,
+        stat: Option<&Stat>,
+    ) -> Result<Option<ChangeKind>> {
+        let (entry, stat) = match (entry, stat) {
+            (None, _) => return Ok(Some(ChangeKind::Untracked)),
+            (Some(_), None) => return Ok(Some(ChangeKind::Deleted)),
+            (Some(entry), Some(stat)) => (entry, stat),
+        };
+
+        let mode = self.workspace.mode_for_stat(stat, Some(entry.mode()));
+        if !entry.stat_match(stat, mode) {
+            return Ok(Some(ChangeKind::Modified));
+        }
+
+        if entry.times_match(stat) {
+            return Ok(None);
+        }
+
+        let data = self.workspace.read_file(entry.path())?;
+        let oid = self.database.hash_object(&Blob::new(data))?;
+
+        if &oid != entry.oid() {
+            Ok(Some(ChangeKind::Modified))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// The entry the workspace has for an index entry's path: the index entry itself if the
+    /// file is unchanged, or `None` if it's gone. A changed file is given a null ObjectId
+    /// rather than being hashed, as git does.
+    pub fn workspace_entry(&self, entry: &Entry) -> Result<Option<DatabaseEntry>> {
+        let stat = self.workspace.try_stat_file(entry.path())?;
+        self.workspace_entry_for_stat(entry, stat.as_ref())
+    }
+
+    /// The entry the workspace has for an index entry's path, as for `workspace_entry`,
+    /// given the file's stat information, if it has any.
+    fn workspace_entry_for_stat(
+        &self,
+        entry: &Entry,
+        stat: Option<&Stat>,
+    ) -> Result<Option<DatabaseEntry>> {
+        let stat = match stat {
+            Some(stat) if !stat.is_dir() => stat,
+            _ => return Ok(None),
+        };
+
+        let indexed = DatabaseEntry::new(*entry.oid(), entry.mode());
+        match self.compare_index_to_workspace(Some(entry), Some(stat))? {
+            None => Ok(Some(indexed)),
+            Some(_) => {
+                let mode = self.workspace.mode_for_stat(stat, Some(entry.mode()));
+                Ok(Some(DatabaseEntry::new(ObjectId::NULL, mode)))
+            }
+        }
+    }
+
+    /// Every path whose file in the workspace differs from its entry in the index. Files
+    /// that aren't tracked aren't included.
+    pub fn workspace_changes(&self) -> Result<Vec<RawChange>> {
+        Ok(self.scan_workspace(None)?.changes)
+    }
+
+    /// Compare every file the index tracks with its entry, as for `workspace_changes`,
+    /// skipping those an fsmonitor scan says are unchanged and marking the rest that differ
+    /// as dirty for its next check.
+    pub fn scan_workspace(&self, mut scan: Option<&mut Scan<F>>) -> Result<WorkspaceScan> {
+        let mut changes = Vec::new();
+        let mut stale = Vec::new();
+        for (path, entry) in self.index.entries() {
+            if scan.as_ref().is_some_and(|scan| scan.is_unchanged(path)) {
+                continue;
+            }
+
+            let stat = self.workspace.try_stat_file(path)?;
+            let old = Some(DatabaseEntry::new(*entry.oid(), entry.mode()));
+            let new = self.workspace_entry_for_stat(entry, stat.as_ref())?;
+            if old != new {
+                if let Some(scan) = scan.as_deref_mut() {
+                    scan.mark_dirty(path);
+                }
+                changes.push(RawChange {
+                    path: path.clone(),
+                    old,
+                    new,
+                });
+            } else if let Some(stat) = stat.filter(|stat| !entry.times_match(stat)) {
+                stale.push((path.clone(), stat));
+            }
+        }
+
+        Ok(WorkspaceScan { changes, stale })
+    }
+
+    /// Every path whose entry in a tree's list of files differs from the index, or with
+    /// `cached` false, from the workspace's copy of the files the index tracks.
+    pub fn tree_changes(
+        &self,
+        tree: &BTreeMap<PathBuf, DatabaseEntry>,
+        cached: bool,
+    ) -> Result<Vec<RawChange>> {
+        let paths: BTreeSet<&PathBuf> = tree.keys().chain(self.index.entries().keys()).collect();
+
+        let mut changes = Vec::new();
+        for path in paths {
+            let old = tree.get(path).cloned();
+            let new = match self.index.entry_for_path(path) {
+                Some(entry) if !cached => self.workspace_entry(entry)?,
+                Some(entry) => Some(DatabaseEntry::new(*entry.oid(), entry.mode())),
+                None => None,
+            };
+            if old != new {
+                changes.push(RawChange {
+                    path: path.clone(),
+                    old,
+                    new,
+                });
+            }
+        }
+
+        Ok(changes)
+    }
+
+    /// Compare an item from a stored tree against the index.
+    pub fn compare_tree_to_index(
+        &self,
+        item: Option<&DatabaseEntry>,
+        entry: Option<&Entry>,
+    ) -> Option<ChangeKind> {
+        match (item, entry) {
+            (None, None) => None,
+            (None, Some(_)) => Some(ChangeKind::Added),
+            (Some(_), None) => Some(ChangeKind::Deleted),
+            (Some(item), Some(entry)) => {
+                if item.mode() == entry.mode() && item.oid() == entry.oid() {
+                    None
+                } else {
+                    Some(ChangeKind::Modified)
+                }
+            }
+        }
+    }
+}
@@ -1,14 +1,50 @@
 use thiserror::Error;
+pub mod attributes;
+pub mod blame;
+pub mod commit_graph;
+pub mod config;
 pub mod database;
+pub mod diff;
+pub mod fsmonitor;
+pub mod hash;
+pub mod hooks;
+#[cfg(not(target_os = "wasi"))]
+pub mod http;
+pub mod ignore;
 pub mod index;
+pub mod inspector;
 pub mod lockfile;
+pub mod mailmap;
+pub mod merge;
+pub mod migration;
+pub mod name_rev;
+pub mod pack;
+pub mod progress;
+pub mod protocol;
+pub mod quote;
+pub mod rebase;
+pub mod ref_format;
+pub mod reflog;
 pub mod refs;
+pub mod remote;
+pub mod repository;
+pub mod revision;
+pub mod revwalk;
+pub mod sys;
+pub mod trailers;
+pub mod vfs;
+#[cfg(not(target_os = "wasi"))]
+pub mod watch;
 pub mod workspace;
 
 mod utils;
 
 #[derive(Debug, Error)]
 pub enum Error {
+    #[error("Blame error")]
+    Blame(#[from] blame::BlameError),
+    #[error("Config error")]
+    Config(#[from] config::ConfigError),
     #[error("Workspace error")]
     Workspace(#[from] workspace::WorkspaceError),
     #[error("Index error")]
@@ -19,8 +55,38 @@ pub enum Error {
     Lockfile(#[from] lockfile::LockfileError),
     #[error("Database error")]
     Database(#[from] database::DatabaseError),
+    #[error("Diff error")]
+    Diff(#[from] diff::DiffError),
+    #[error("fsmonitor error")]
+    FsMonitor(#[from] fsmonitor::FsMonitorError),
+    #[error("Hook error")]
+    Hook(#[from] hooks::HookError),
+    #[cfg(not(target_os = "wasi"))]
+    #[error("HTTP error")]
+    Http(#[from] http::HttpError),
     #[error("Ref error")]
     Ref(#[from] refs::RefError),
+    #[error("Revision error")]
+    Revision(#[from] revision::RevisionError),
+    #[error("Remote error")]
+    Remote(#[from] remote::RemoteError),
+    #[error("Repository error")]
+    Repository(#[from] repository::RepositoryError),
+    #[error("Migration error")]
+    Migration(#[from] migration::MigrationError),
+    #[error("Pack error")]
+    Pack(#[from] pack::PackError),
+    #[error("Ref format error")]
+    RefFormat(#[from] ref_format::RefFormatError),
+    #[error("Reflog error")]
+    Reflog(#[from] reflog::ReflogError),
+    #[error("Rebase error")]
+    Rebase(#[from] rebase::RebaseError),
+    #[error("Trailer error")]
+    Trailer(#[from] trailers::TrailerError),
+    #[cfg(not(target_os = "wasi"))]
+    #[error("Watch error")]
+    Watch(#[from] watch::WatchError),
     #[error(transparent)]
     IoError(#[from] std::io::Error),
     #[error(transparent)]
@@ -31,9 +97,6 @@ pub type Result<T, E = Error> = core::result::Result<T, E>;
 
 impl From<crate::Error> for std::io::Error {
     fn from(err: crate::Error) -> Self {
-        std::io::Error::new(
-            std::io::ErrorKind::Other,
-            format!("Could get lock for file: {}", err),
-        )
+        std::io::Error::other(format!("Could get lock for file: {}", err))
     }
 }
@@ -1,12 +1,10 @@
 use crate::utils::add_extension;
+use crate::vfs::{RealFs, Vfs};
 use crate::Result;
 use std::io;
-use std::io::{Read, Write};
+use std::io::Write;
+use std::path::Path;
 use std::path::PathBuf;
-use std::{
-    fs::{File, OpenOptions},
-    path::Path,
-};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -31,21 +29,32 @@ If it still fails, a git process may have crashed in this repository earlier: re
 
 // TODO: This API could be better. A call to hold_for_update() should return a struct with a write function.
 // Dropping the struct would commit and close the file.
+//
+// What's written is kept in memory until the lock is committed, when it's written to the
+// lock file and moved into place.
 #[derive(Debug)]
-pub struct Lockfile {
+pub struct Lockfile<F: Vfs = RealFs> {
+    fs: F,
     file_path: PathBuf,
     lock_path: PathBuf,
 
-    lock: Option<File>,
+    lock: Option<Vec<u8>>,
 }
 
 impl Lockfile {
     pub fn new(path: &Path) -> Self {
+        Self::with_vfs(RealFs, path)
+    }
+}
+
+impl<F: Vfs> Lockfile<F> {
+    pub fn with_vfs(fs: F, path: &Path) -> Self {
         let file_path = path.to_owned();
         let mut lock_path = path.to_owned();
         add_extension(&mut lock_path, "lock");
 
         Self {
+            fs,
             lock: None,
             file_path,
             lock_path,
@@ -54,11 +63,8 @@ impl Lockfile {
 
     pub fn hold_for_update(&mut self) -> Result<()> {
         if self.lock.is_none() {
-            let f = OpenOptions::new()
-                .read(true)
-                .write(true)
-                .create_new(true)
-                .open(&self.lock_path)
+            self.fs
+                .create_new(&self.lock_path)
                 .map_err(|e| match e.kind() {
                     io::ErrorKind::NotFound => LockfileError::MissingParent,
                     io::ErrorKind::PermissionDenied => LockfileError::NoPermission,
@@ -67,44 +73,39 @@ impl Lockfile {
                     }
 
                     _ => LockfileError::IoError(e),
-                });
+                })?;
 
-            self.lock = Some(f?);
+            self.lock = Some(Vec::new());
         }
 
         Ok(())
     }
 
-    fn lock(&mut self) -> Result<&mut File> {
+    fn lock(&mut self) -> Result<&mut Vec<u8>> {
         self.lock
             .as_mut()
             .ok_or_else(|| LockfileError::StaleLock.into())
     }
 
     pub fn commit(&mut self) -> Result<()> {
-        let lock = self.lock.take().ok_or(LockfileError::StaleLock);
-        drop(lock);
-        std::fs::rename(&self.lock_path, &self.file_path)?;
+        let content = self.lock.take().ok_or(LockfileError::StaleLock)?;
+        self.fs.write(&self.lock_path, &content)?;
+        self.fs.rename(&self.lock_path, &self.file_path)?;
 
         Ok(())
     }
 
+    /// Release the lock without touching the locked file. Does nothing if the lock isn't held.
     pub fn rollback(&mut self) -> Result<()> {
-        let lock = self.lock.take().ok_or(LockfileError::StaleLock);
-        drop(lock);
-        std::fs::remove_file(&self.lock_path)?;
+        if self.lock.take().is_some() {
+            self.fs.remove_file(&self.lock_path)?;
+        }
 
         Ok(())
     }
 }
 
-impl Read for Lockfile {
-    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        self.lock()?.read(buf)
-    }
-}
-
-impl Write for Lockfile {
+impl<F: Vfs> Write for Lockfile<F> {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
         self.lock()?.write(buf)
     }
@@ -0,0 +1,130 @@
+//! Mapping the names and emails recorded on commits to the ones people would rather be
+//! known by, from `.mailmap` files.
+
+use std::collections::HashMap;
+
+/// What the names and emails recorded with one email are mapped to.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Mapping {
+    /// The proper name and email for the email, whatever name it's recorded with.
+    name: Option<String>,
+    email: Option<String>,
+    /// Proper names and emails for the email when it's recorded with a particular name,
+    /// keyed by that name in lowercase.
+    by_name: HashMap<String, (Option<String>, Option<String>)>,
+}
+
+/// A `.mailmap`: lines of the form `Proper Name <proper@email> Commit Name <commit@email>`,
+/// where the proper name or email can be left out, as can the commit name. Emails and
+/// names are matched without regard to case.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Mailmap {
+    mappings: HashMap<String, Mapping>,
+}
+
+impl Mailmap {
+    pub fn parse(text: &str) -> Self {
+        let mut mailmap = Self::default();
+        mailmap.extend(text);
+        mailmap
+    }
+
+    /// Add the lines of another mailmap file to this one. Where they map the same name or
+    /// email as an earlier one, they take its place.
+    pub fn extend(&mut self, text: &str) {
+        for line in text.lines() {
+            if line.starts_with('#') {
+                continue;
+            }
+
+            let (name1, email1, rest) = match split_name_and_email(line) {
+                Some(parts) => parts,
+                None => continue,
+            };
+            let (proper_name, proper_email, old_name, old_email) = match split_name_and_email(rest)
+            {
+                Some((name2, email2, _)) => (name1, Some(email1), name2, email2),
+                None => (name1, None, None, email1),
+            };
+
+            let mapping = self.mappings.entry(old_email.to_lowercase()).or_default();
+            let proper_name = proper_name.map(str::to_owned);
+            let proper_email = proper_email.map(str::to_owned);
+            match old_name {
+                Some(old_name) => {
+                    let entry = mapping.by_name.entry(old_name.to_lowercase()).or_default();
+                    entry.0 = proper_name.or(entry.0.take());
+                    entry.1 = proper_email.or(entry.1.take());
+                }
+                None => {
+                    mapping.name = proper_name.or(mapping.name.take());
+                    mapping.email = proper_email.or(mapping.email.take());
+                }
+            }
+        }
+    }
+
+    /// The proper name and email for someone recorded with the given ones. Whatever the
+    /// mailmap doesn't say is kept as it is.
+    pub fn map<'a>(&'a self, name: &'a str, email: &'a str) -> (&'a str, &'a str) {
+        let mapping = match self.mappings.get(&email.to_lowercase()) {
+            Some(mapping) => mapping,
+            None => return (name, email),
+        };
+
+        let (proper_name, proper_email) = match mapping.by_name.get(&name.to_lowercase()) {
+            Some((name, email)) => (name, email),
+            None => (&mapping.name, &mapping.email),
+        };
+        (
+            proper_name.as_deref().unwrap_or(name),
+            proper_email.as_deref().unwrap_or(email),
+        )
+    }
+}
+
+/// Split a name and an email in angle brackets off the front of some text, returning the
+/// name, if there is one, the email, and the rest of the text.
+fn split_name_and_email(text: &str) -> Option<(Option<&str>, &str, &str)> {
+    let (name, rest) = text.split_once('<')?;
+    let (email, rest) = rest.split_once('>')?;
+    let name = Some(name.trim()).filter(|name| !name.is_empty());
+    Some((name, email.trim(), rest))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn maps_names_and_emails() {
+        let mailmap = Mailmap::parse(
+            "# A comment\n\
+             Proper Name <commit@example.com>\n\
+             <proper@example.com> <Other@Example.com>\n\
+             Joe <joe@example.com> Joseph <old@example.com>\n\
+             Jo <jo@example.com> <old@example.com>\n",
+        );
+
+        assert_eq!(
+            mailmap.map("Someone", "commit@example.com"),
+            ("Proper Name", "commit@example.com")
+        );
+        assert_eq!(
+            mailmap.map("Other", "other@example.com"),
+            ("Other", "proper@example.com")
+        );
+        assert_eq!(
+            mailmap.map("joseph", "old@example.com"),
+            ("Joe", "joe@example.com")
+        );
+        assert_eq!(
+            mailmap.map("Jo", "old@example.com"),
+            ("Jo", "jo@example.com")
+        );
+        assert_eq!(
+            mailmap.map("Nobody", "nobody@example.com"),
+            ("Nobody", "nobody@example.com")
+        );
+    }
+}
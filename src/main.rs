@@ -1,505 +1,633 @@
-use anyhow::anyhow;
 use anyhow::Context;
-use chrono::Utc;
 use nit::{
-    database::{Author, Blob, Commit, Database, Tree},
-    index::Index,
-    lockfile::LockfileError,
-    refs::Refs,
-    workspace::Workspace,
+    database::NO_REPLACE_OBJECTS,
+    repository::{git_dir, Repository, GIT_DIR, WORK_TREE},
 };
+use std::env;
 use std::fs;
-use std::path::Path;
-use std::{env, io::Read};
+use std::path::{Path, PathBuf};
 use structopt::StructOpt;
 
+mod commands;
+
+use commands::{
+    add, blame, branch, cat_file, check_ignore, check_ref_format, checkout, cherry, commit,
+    commit_tree, daemon, diff, diff_files, diff_index, diff_tree, fetch, for_each_ref, gc,
+    index_pack, init, interpret_trailers, log, ls_remote, ls_tree, maintenance, merge_file,
+    name_rev, prompt, push, range_diff, read_tree, rebase, repack, replace, restore, rev_list,
+    shortlog, show_branch, stash, status, switch, symbolic_ref, tag, update_index, var,
+    verify_pack, write_tree, CommandContext, ExitStatus, Verbosity,
+};
+
+#[derive(Debug, StructOpt)]
+struct Cli {
+    /// Run as if started in <path>. If given more than once, each is relative to the last
+    #[structopt(short = "C", value_name = "path", number_of_values = 1)]
+    directories: Vec<PathBuf>,
+    /// Use <path> as the `.git` directory, rather than the one in the working tree
+    #[structopt(long = "git-dir", value_name = "path")]
+    git_dir: Option<PathBuf>,
+    /// Load objects as they are, rather than their replacements under `refs/replace`
+    #[structopt(long = "no-replace-objects")]
+    no_replace_objects: bool,
+    /// Use <path> as the working tree, rather than the directory that holds `.git`
+    #[structopt(long = "work-tree", value_name = "path")]
+    work_tree: Option<PathBuf>,
+    /// Say nothing but what was asked for, leaving out summaries of what was done
+    #[structopt(short = "q", long = "quiet", conflicts_with = "verbose")]
+    quiet: bool,
+    /// Say more about what was done, such as each file that was added
+    #[structopt(short = "v", long = "verbose")]
+    verbose: bool,
+    #[structopt(subcommand)]
+    command: Opt,
+}
+
 #[derive(Debug, StructOpt)]
 enum Opt {
     /// Creates a new repository
-    Init {
-        #[structopt(default_value = ".")]
-        path: String,
-    },
+    Init(init::Args),
+
     /// Record changes to the repository
-    Commit {
-        #[structopt(long = "message", short = "m")]
-        message: Option<String>,
-    },
+    Commit(commit::Args),
+
     /// Add file contents to the index
-    Add { paths: Vec<String> },
+    Add(add::Args),
 
     /// Show the working tree status
-    Status,
-}
+    Status(status::Args),
 
-fn handle_opt(opt: Opt, root_path: &Path) -> anyhow::Result<()> {
-    match opt {
-        Opt::Init { path } => init_repository(&path.as_ref())?,
-        Opt::Add { paths } => {
-            let paths = paths.iter().map(Path::new).collect();
-            add_files_to_repository(paths, &root_path)?;
-        }
-        Opt::Commit { message } => {
-            let msg = create_commit(message, &std::env::current_dir()?)?;
-            print!("{}", msg);
-        }
-        Opt::Status => {
-            let msg = get_repository_status(&root_path)?;
-            print!("{}", msg);
-        }
-    };
+    /// Switch branches or restore working tree files
+    Checkout(checkout::Args),
 
-    Ok(())
-}
+    /// Switch branches
+    Switch(switch::Args),
 
-fn main() -> anyhow::Result<()> {
-    let opt = Opt::from_args();
-    let root_path = std::env::current_dir()?;
+    /// Restore working tree files
+    Restore(restore::Args),
 
-    handle_opt(opt, &root_path)
-}
+    /// Save local changes away and put the working tree back as it is in HEAD, or list,
+    /// apply, pop or drop the changes saved
+    Stash(stash::Args),
 
-fn init_repository(path: &Path) -> anyhow::Result<()> {
-    let root_path = fs::canonicalize(Path::new(path))?;
-    let git_path = root_path.join(".git");
-    for &dir in ["objects", "refs"].iter() {
-        fs::create_dir_all(git_path.join(dir))?;
-    }
+    /// Create or delete branches
+    Branch(branch::Args),
 
-    println!(
-        "Initialised empty Nit repository in {}",
-        git_path.to_str().unwrap_or("Unknown")
-    );
+    /// Show changes between commits or trees
+    Diff(diff::Args),
 
-    Ok(())
-}
+    /// Show the changes a commit made against its parent, or between two trees, listing
+    /// each changed path with its modes, ObjectIds and status
+    DiffTree(diff_tree::Args),
 
-fn add_files_to_repository(paths: Vec<&Path>, root_path: &Path) -> anyhow::Result<()> {
-    let git_path = root_path.join(".git");
-    let mut index = Index::new(git_path.join("index"));
-    let workspace = Workspace::new(&root_path);
-    let database = Database::new(git_path.join("objects"));
+    /// Show how the index, or with --cached, the files the index tracks, differ from a tree,
+    /// in the same format as diff-tree
+    DiffIndex(diff_index::Args),
 
-    // Please, try-blocks, please.
-    (|| -> anyhow::Result<()> {
-        index
-            .load_for_update()
-            .context("Couldn't load for update")?;
+    /// Show how the files in the working tree differ from the index, in the same format as
+    /// diff-tree
+    DiffFiles(diff_files::Args),
 
-        let paths: Result<Vec<_>, anyhow::Error> = paths
-            .into_iter()
-            .map(|path| {
-                let path = std::fs::canonicalize(&path)
-                    .with_context(|| format!("Couldn't add file: {:?}", &path))?;
+    /// Show the history of commits leading up to one
+    Log(log::Args),
 
-                let res = workspace
-                    .list_files(&path)
-                    .with_context(|| format!("Couldn't add file: {:?}", &path))?;
+    /// Summarize the history leading up to a commit by who made each commit
+    Shortlog(shortlog::Args),
 
-                Ok(res)
-            })
-            .collect();
+    /// Show which commit last changed each line of a file
+    Blame(blame::Args),
 
-        let paths: Vec<_> = paths?.into_iter().flatten().collect();
+    /// Add or parse structured information in commit messages
+    InterpretTrailers(interpret_trailers::Args),
 
-        for pathname in paths {
-            let data = workspace.read_file(&pathname).context("No data")?;
-            let stat = workspace.stat_file(&pathname).context("No stat")?;
-            let blob = Blob::new(data);
-            let blob_oid = database.store(&blob).context("No oid")?;
+    /// Find commits yet to be applied to upstream
+    Cherry(cherry::Args),
 
-            index.add(&pathname, blob_oid, stat);
-        }
+    /// List the commits reachable from some revisions but not others, and with --objects,
+    /// everything they need
+    RevList(rev_list::Args),
 
-        index.write_updates()?;
-        Ok(())
-    })()
-    .or_else(|e| {
-        // Cleanup lockfile if we had issues
-        if let Some(nit::Error::Lockfile(LockfileError::LockDenied(_))) = e.downcast_ref() {
-            // We couldn't get the lock, so leave it in place.
-        } else {
-            index.lockfile_mut().rollback()?;
-        }
+    /// Compare two versions of a series of commits, such as before and after a rebase
+    RangeDiff(range_diff::Args),
 
-        Err(e)
-    })
-}
+    /// Show which commits are on which of several branches, back to where they all meet
+    ShowBranch(show_branch::Args),
 
-fn get_repository_status(root_path: &Path) -> anyhow::Result<String> {
-    let workspace = Workspace::new(&root_path);
-    let status = workspace
-        .list_files_in_root()?
-        .iter()
-        .fold(String::new(), |mut acc, next| {
-            acc.push_str(&format!("?? {}\n", next));
-            acc
-        });
-
-    Ok(status)
-}
+    /// Summarise the repository's state in one line, quickly enough for a shell prompt
+    Prompt(prompt::Args),
 
-fn create_commit(message: Option<String>, root_path: &Path) -> anyhow::Result<String> {
-    let git_path = root_path.join(".git");
-    let mut index = Index::new(git_path.join("index"));
-    let database = Database::new(git_path.join("objects"));
-    let refs = Refs::new(&git_path);
+    /// Show who commits would be by, or which editor would be used, once the environment,
+    /// the config and the defaults have all been taken into account
+    Var(var::Args),
 
-    (|| -> anyhow::Result<String> {
-        index.load()?;
+    /// Merge the changes made to a file from a common base with another file's changes
+    MergeFile(merge_file::Args),
 
-        let mut root = Tree::build(index.entries().values().cloned().collect());
-        root.traverse(&mut |tree| {
-            let oid = database.store(tree)?;
-            Ok(oid)
-        })?;
+    /// Name commits after the refs they can be reached from, such as master~3
+    NameRev(name_rev::Args),
 
-        let root_oid = database.store(&root)?;
+    /// Create a tree object from the index, and print its id
+    WriteTree,
 
-        let parent = refs.read_head();
-        let name = env::var("GIT_AUTHOR_NAME")
-            .context("Could not load GIT_AUTHOR_NAME environment variable")?;
-        let email = env::var("GIT_AUTHOR_EMAIL")
-            .context("Could not load GIT_AUTHOR_EMAIL environment variable")?;
+    /// Create a commit object from a tree, without touching HEAD or the index, and print its id
+    CommitTree(commit_tree::Args),
 
-        let author = Author::new(name, email, Utc::now());
+    /// Read tree information into the index, leaving the working tree alone
+    ReadTree(read_tree::Args),
 
-        let msg = message
-            .or_else(|| {
-                let mut msg = Vec::new();
-                std::io::stdin().read_to_end(&mut msg).ok()?;
-                let str = String::from_utf8(msg).ok()?;
-                Some(str)
-            })
-            .ok_or_else(|| anyhow!("No commit message, aborting"))?;
+    /// Change entries in the index directly
+    UpdateIndex(update_index::Args),
 
-        let commit = Commit::new(parent.as_deref(), root_oid, author, msg);
-        let commit_oid = database.store(&commit)?;
+    /// List the contents of a tree
+    LsTree(ls_tree::Args),
 
-        refs.update_head(&commit_oid)?;
+    /// Show an object's type, size or contents; or with --batch or --batch-check, those of
+    /// each object named on a line of stdin
+    CatFile(cat_file::Args),
 
-        let root_msg = match parent {
-            Some(_) => "",
-            None => "(root-commit) ",
-        };
+    /// Debug ignore rules: show which of the given paths are ignored
+    CheckIgnore(check_ignore::Args),
 
-        let msg = format!(
-            "[{}{}] {}",
-            root_msg,
-            commit_oid,
-            commit.message().lines().next().unwrap_or("")
-        );
+    /// Build the index for a pack file, checking every object in it, and print its checksum
+    IndexPack(index_pack::Args),
 
-        Ok(msg)
-    })()
-    .or_else(|e| {
-        // Cleanup lockfile if we had issues
-        if let Some(nit::Error::Lockfile(LockfileError::LockDenied(_))) = e.downcast_ref() {
-            // We couldn't get the lock, so leave it in place.
-        } else {
-            index.lockfile_mut().rollback()?;
-        }
+    /// Check that packs match their indexes
+    VerifyPack(verify_pack::Args),
 
-        Err(e)
-    })
-}
+    /// Pack the objects reachable from any ref, storing similar objects as deltas
+    Repack(repack::Args),
 
-#[cfg(test)]
-mod test {
-    use std::os::unix::fs::PermissionsExt;
-    use std::{fs::File, io::prelude::*, path::PathBuf};
-    const REGULAR_MODE: u32 = 0o100644;
-    const EXECUTABLE_MODE: u32 = 0o100755;
+    /// Pack the repository's objects into a single pack, deleting loose objects and old packs
+    Gc(gc::Args),
 
-    use super::*;
+    /// Keep a repository fast to work with through smaller tasks than a full gc
+    Maintenance(maintenance::MaintenanceCommand),
 
-    fn tmp_path(subdir: &dyn AsRef<Path>) -> PathBuf {
-        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-            .join("tmp")
-            .join(subdir.as_ref())
-    }
+    /// Record that an object should be loaded in place of another, without rewriting history.
+    /// With no arguments, list the objects that have been replaced
+    Replace(replace::Args),
 
-    fn init(subdir: &dyn AsRef<Path>) -> anyhow::Result<()> {
-        std::fs::create_dir(tmp_path(subdir))?;
-        let path = tmp_path(subdir);
-        init_repository(&path)
-    }
+    /// Read which ref a symbolic ref such as HEAD points at, or point it at another ref
+    SymbolicRef(symbolic_ref::Args),
 
-    fn cleanup(subdir: &dyn AsRef<Path>) -> anyhow::Result<()> {
-        let path = tmp_path(subdir);
-        std::fs::remove_dir_all(path)?;
-        Ok(())
-    }
+    /// Check that a name is allowed for a ref, exiting with status 1 if it isn't
+    CheckRefFormat(check_ref_format::Args),
 
-    #[test]
-    fn inits_a_repository() {
-        let subdir = "inits";
-        init(&subdir).unwrap();
-        let dirs: Vec<_> = std::fs::read_dir(tmp_path(&subdir).join(".git"))
-            .unwrap()
-            .map(|p| {
-                let p = p.unwrap();
-                p.file_name()
-            })
-            .collect();
+    /// Create a tag, or list the tags there are
+    Tag(tag::Args),
 
-        assert_eq!(dirs, vec!["refs", "objects"]);
+    /// List refs and information about what they point at, in a format of your choice
+    ForEachRef(for_each_ref::Args),
 
-        cleanup(&subdir).unwrap();
-    }
+    /// Download objects and refs from another repository, updating the remote-tracking refs
+    Fetch(fetch::Args),
 
-    #[test]
-    fn adds_a_file_to_the_index() {
-        let subdir = "adds";
-        init(&subdir).unwrap();
-        let git_dir = tmp_path(&subdir).join(".git");
-        let index_dir = git_dir.join("index");
-        let mut index = Index::new(index_dir);
+    /// List the refs another repository has, and the objects they point at, without
+    /// fetching anything
+    LsRemote(ls_remote::Args),
 
-        let file_path = tmp_path(&subdir).join("hello.txt");
-        let mut file = File::create(&file_path).unwrap();
-        file.write_all("Hello, world".as_bytes()).unwrap();
+    /// Update refs in another repository, sending the objects they need. Only updates that
+    /// are fast-forwards are allowed, unless forced
+    Push(push::Args),
 
-        add_files_to_repository(vec![&file_path], &tmp_path(&subdir)).unwrap();
+    /// Reapply commits on top of another base commit. Changes are merged file by file, and the
+    /// rebase is abandoned if a commit can't be applied cleanly
+    Rebase(rebase::Args),
 
-        index.load_for_update().unwrap();
+    /// Serve repositories over the git:// protocol, for fetching only. Unless --export-all is
+    /// given, only repositories with a `git-daemon-export-ok` file are served
+    Daemon(daemon::Args),
+}
 
-        let entries: Vec<_> = index
-            .entries()
-            .values()
-            .map(|entry| (entry.mode(), entry.path()))
-            .collect();
+fn handle_opt(opt: Opt, ctx: &mut CommandContext) -> anyhow::Result<()> {
+    // Better to refuse to work on a repository than to corrupt one in a format nit doesn't
+    // understand. `init` leaves an existing repository's format alone.
+    if !matches!(opt, Opt::Init(_)) && ctx.repo.ensure_exists().is_ok() {
+        ctx.repo.check_format()?;
+    }
 
-        assert_eq!(entries, vec![(REGULAR_MODE, Path::new("hello.txt"))]);
-        cleanup(&subdir).unwrap();
+    match opt {
+        Opt::Init(args) => init::run(args, ctx),
+        Opt::Commit(args) => commit::run(args, ctx),
+        Opt::Add(args) => add::run(args, ctx),
+        Opt::Status(args) => status::run(args, ctx),
+        Opt::Checkout(args) => checkout::run(args, ctx),
+        Opt::Switch(args) => switch::run(args, ctx),
+        Opt::Restore(args) => restore::run(args, ctx),
+        Opt::Stash(args) => stash::run(args, ctx),
+        Opt::Branch(args) => branch::run(args, ctx),
+        Opt::Diff(args) => diff::run(args, ctx),
+        Opt::DiffTree(args) => diff_tree::run(args, ctx),
+        Opt::DiffIndex(args) => diff_index::run(args, ctx),
+        Opt::DiffFiles(args) => diff_files::run(args, ctx),
+        Opt::Log(args) => log::run(args, ctx),
+        Opt::Shortlog(args) => shortlog::run(args, ctx),
+        Opt::Blame(args) => blame::run(args, ctx),
+        Opt::InterpretTrailers(args) => interpret_trailers::run(args, ctx),
+        Opt::Cherry(args) => cherry::run(args, ctx),
+        Opt::RangeDiff(args) => range_diff::run(args, ctx),
+        Opt::RevList(args) => rev_list::run(args, ctx),
+        Opt::ShowBranch(args) => show_branch::run(args, ctx),
+        Opt::Prompt(args) => prompt::run(args, ctx),
+        Opt::Var(args) => var::run(args, ctx),
+        Opt::MergeFile(args) => merge_file::run(args, ctx),
+        Opt::NameRev(args) => name_rev::run(args, ctx),
+        Opt::WriteTree => write_tree::run(ctx),
+        Opt::CommitTree(args) => commit_tree::run(args, ctx),
+        Opt::ReadTree(args) => read_tree::run(args, ctx),
+        Opt::UpdateIndex(args) => update_index::run(args, ctx),
+        Opt::LsTree(args) => ls_tree::run(args, ctx),
+        Opt::CatFile(args) => cat_file::run(args, ctx),
+        Opt::CheckIgnore(args) => check_ignore::run(args, ctx),
+        Opt::IndexPack(args) => index_pack::run(args, ctx),
+        Opt::VerifyPack(args) => verify_pack::run(args, ctx),
+        Opt::Repack(args) => repack::run(args, ctx),
+        Opt::Gc(args) => gc::run(args, ctx),
+        Opt::Maintenance(args) => maintenance::run(args, ctx),
+        Opt::Replace(args) => replace::run(args, ctx),
+        Opt::SymbolicRef(args) => symbolic_ref::run(args, ctx),
+        Opt::CheckRefFormat(args) => check_ref_format::run(args, ctx),
+        Opt::Tag(args) => tag::run(args, ctx),
+        Opt::ForEachRef(args) => for_each_ref::run(args, ctx),
+        Opt::Fetch(args) => fetch::run(args, ctx),
+        Opt::LsRemote(args) => ls_remote::run(args, ctx),
+        Opt::Push(args) => push::run(args, ctx),
+        Opt::Rebase(args) => rebase::run(args, ctx),
+        Opt::Daemon(args) => daemon::run(args, ctx),
     }
+}
 
-    #[test]
-    fn adds_an_executable_file_to_the_index() {
-        let subdir = "adds_executable";
-        init(&subdir).unwrap();
-        let git_dir = tmp_path(&subdir).join(".git");
-        let mut index = Index::new(git_dir.join("index"));
-        let file_path = tmp_path(&subdir).join("hello.txt");
-        let mut file = File::create(&file_path).unwrap();
-        file.write_all("Hello, world".as_bytes()).unwrap();
-
-        // Set it to executable.
-        let mut permissions = file.metadata().unwrap().permissions();
-        permissions.set_mode(0o755);
-        file.set_permissions(permissions).unwrap();
-
-        add_files_to_repository(vec![&file_path], &tmp_path(&subdir)).unwrap();
-
-        index.load_for_update().unwrap();
-
-        let entries: Vec<_> = index
-            .entries()
-            .values()
-            .map(|entry| (entry.mode(), entry.path()))
-            .collect();
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::from_args();
+    let mut cwd = std::env::current_dir()?;
+    for dir in &cli.directories {
+        cwd = fs::canonicalize(cwd.join(dir))
+            .with_context(|| format!("cannot change to {}", dir.display()))?;
+        env::set_current_dir(&cwd)?;
+    }
+    if let Some(path) = &cli.git_dir {
+        // Set for any commands that are run from this one, too.
+        env::set_var(GIT_DIR, cwd.join(path));
+    }
 
-        assert_eq!(entries, vec![(EXECUTABLE_MODE, Path::new("hello.txt"))]);
-        cleanup(&subdir).unwrap();
+    if cli.no_replace_objects {
+        // Set for any commands that are run from this one, too.
+        env::set_var(NO_REPLACE_OBJECTS, "1");
     }
 
-    #[test]
-    fn adds_multiple_files_to_index() {
-        let subdir = "adds_multiple";
-        init(&subdir).unwrap();
-        let git_dir = tmp_path(&subdir).join(".git");
-        let mut index = Index::new(git_dir.join("index"));
+    let root_path = match &cli.command {
+        Opt::Init { .. } => cwd,
+        _ => match find_work_tree(&cwd, cli.work_tree)? {
+            Some(work_tree) => {
+                // Everything else finds `.git` from the working tree, so say where it really
+                // is, for any commands that are run from this one too. Paths given on the
+                // command line are relative to the top of the working tree, as they are
+                // everywhere else.
+                env::set_var(GIT_DIR, git_dir(&cwd));
+                env::set_var(WORK_TREE, &work_tree);
+                env::set_current_dir(&work_tree)?;
+                work_tree
+            }
+            None => cwd,
+        },
+    };
 
-        let file_path = tmp_path(&subdir).join("hello.txt");
-        let mut file = File::create(&file_path).unwrap();
-        file.write_all("Hello, world".as_bytes()).unwrap();
+    let stdin = std::io::stdin();
+    let mut stdin = stdin.lock();
+    let mut stdout = std::io::stdout();
+    let mut stderr = std::io::stderr();
+    let mut ctx = CommandContext::new(root_path, &mut stdin, &mut stdout, &mut stderr);
+    ctx.verbosity = match (cli.quiet, cli.verbose) {
+        (true, _) => Verbosity::Quiet,
+        (_, true) => Verbosity::Verbose,
+        _ => Verbosity::Normal,
+    };
+    if let Err(e) = handle_opt(cli.command, &mut ctx) {
+        if !e.is::<ExitStatus>() {
+            writeln!(ctx.stderr, "Error: {:?}", e)?;
+        }
+        ctx.stdout.flush()?;
+        std::process::exit(ExitStatus::of(&e).code());
+    }
 
-        let file_path_2 = tmp_path(&subdir).join("hohoho.txt");
-        let mut file = File::create(&file_path_2).unwrap();
-        file.write_all("Merry christmas!".as_bytes()).unwrap();
+    Ok(())
+}
 
-        add_files_to_repository(vec![&file_path, &file_path_2], &tmp_path(&subdir)).unwrap();
+/// Where the working tree is, if it isn't the current directory: the one given on the command
+/// line, then `GIT_WORK_TREE`, then `core.worktree`. Relative paths are relative to the
+/// current directory, except for `core.worktree`, which is relative to the `.git` directory.
+fn find_work_tree(cwd: &Path, flag: Option<PathBuf>) -> anyhow::Result<Option<PathBuf>> {
+    let work_tree = match flag.or_else(|| env::var_os(WORK_TREE).map(PathBuf::from)) {
+        Some(path) => Some(cwd.join(path)),
+        None => Repository::new(git_dir(cwd)).work_tree()?,
+    };
 
-        index.load_for_update().unwrap();
+    work_tree
+        .map(|path| {
+            fs::canonicalize(&path)
+                .with_context(|| format!("cannot use {} as the working tree", path.display()))
+        })
+        .transpose()
+}
 
-        let entries: Vec<_> = index
-            .entries()
-            .values()
-            .map(|entry| (entry.mode(), entry.path()))
-            .collect();
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commands::checkout::*;
+    use crate::commands::test::{context, init, write_and_commit};
+    use nit::{
+        database::{Blob, Database, Identity, ObjectId},
+        diff,
+        refs::Refs,
+        repository::CommitOptions,
+        revision::Revision,
+    };
+    use std::io::Write;
 
+    #[test]
+    fn finds_a_separate_work_tree() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+        std::fs::create_dir_all(tmp_path.join("tree")).unwrap();
+        let tree = std::fs::canonicalize(tmp_path.join("tree")).unwrap();
+
+        assert_eq!(find_work_tree(tmp_path, None).unwrap(), None);
         assert_eq!(
-            entries,
-            vec![
-                (REGULAR_MODE, Path::new("hello.txt")),
-                (REGULAR_MODE, Path::new("hohoho.txt"))
-            ]
+            find_work_tree(tmp_path, Some(PathBuf::from("tree"))).unwrap(),
+            Some(tree.clone())
         );
-        cleanup(&subdir).unwrap();
+        assert!(find_work_tree(tmp_path, Some(PathBuf::from("missing"))).is_err());
+
+        // `core.worktree` is relative to `.git`.
+        std::fs::write(
+            tmp_path.join(".git/config"),
+            "[core]\n\tworktree = ../tree\n",
+        )
+        .unwrap();
+        assert_eq!(find_work_tree(tmp_path, None).unwrap(), Some(tree));
     }
 
     #[test]
-    fn incrementally_add_files_to_index() {
-        let subdir = "adds_incrementally";
-        init(&subdir).unwrap();
-        let git_dir = tmp_path(&subdir).join(".git");
-        let mut index = Index::new(git_dir.join("index"));
-        let file_path = tmp_path(&subdir).join("hello.txt");
-
-        let mut file = File::create(&file_path).unwrap();
-        file.write_all("Hello, world".as_bytes()).unwrap();
-        add_files_to_repository(vec![&file_path], &tmp_path(&subdir)).unwrap();
-
-        index.load_for_update().unwrap();
-
-        let entries: Vec<_> = index
-            .entries()
-            .values()
-            .map(|entry| (entry.mode(), entry.path()))
-            .collect();
-
-        assert_eq!(entries, vec![(REGULAR_MODE, Path::new("hello.txt"))]);
-
-        // Add another file, reload and reread entries
-
-        let file_path_2 = tmp_path(&subdir).join("hohoho.txt");
-        let mut file = File::create(&file_path_2).unwrap();
-        file.write_all("Merry christmas!".as_bytes()).unwrap();
-
-        add_files_to_repository(vec![&file_path_2], &tmp_path(&subdir)).unwrap();
-
-        index.load_for_update().unwrap();
+    fn refuses_repositories_in_formats_it_does_not_understand() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+        let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+        let mut stdin = std::io::empty();
+        let mut ctx = context(tmp_path, &mut stdin, &mut stdout, &mut stderr);
+        let mut status_with_config = |config: &str| {
+            std::fs::write(tmp_path.join(".git/config"), config).unwrap();
+            let opt = Opt::from_iter(["nit", "status"]);
+            handle_opt(opt, &mut ctx).map_err(|e| e.root_cause().to_string())
+        };
 
-        let entries: Vec<_> = index
-            .entries()
-            .values()
-            .map(|entry| (entry.mode(), entry.path()))
-            .collect();
+        assert!(status_with_config("[core]\n\trepositoryformatversion = 0\n").is_ok());
+        // Extensions mean nothing to a version 0 repository.
+        let config =
+            "[core]\n\trepositoryformatversion = 0\n[extensions]\n\tpartialClone = origin\n";
+        assert!(status_with_config(config).is_ok());
+        let config = "[core]\n\trepositoryformatversion = 1\n[extensions]\n\tobjectFormat = sha1\n";
+        assert!(status_with_config(config).is_ok());
 
+        let config =
+            "[core]\n\trepositoryformatversion = 1\n[extensions]\n\tpartialClone = origin\n";
         assert_eq!(
-            entries,
-            vec![
-                (REGULAR_MODE, Path::new("hello.txt")),
-                (REGULAR_MODE, Path::new("hohoho.txt"))
-            ]
+            status_with_config(config).unwrap_err(),
+            "unknown repository extension found: partialclone"
         );
-
-        cleanup(&subdir).unwrap();
+        let config =
+            "[core]\n\trepositoryformatversion = 1\n[extensions]\n\tobjectFormat = sha256\n";
+        assert_eq!(
+            status_with_config(config).unwrap_err(),
+            "unknown repository extension found: objectformat"
+        );
+        assert_eq!(
+            status_with_config("[core]\n\trepositoryformatversion = 2\n").unwrap_err(),
+            "expected git repo version <= 1, found 2"
+        );
+        drop(ctx);
     }
 
     #[test]
-    fn adds_a_directory_to_the_index() {
-        let subdir = "adds_dir";
-        let tmp_path = tmp_path(&subdir);
-        let git_dir = tmp_path.join(".git");
-        let mut index = Index::new(git_dir.join("index"));
-
-        init(&subdir).unwrap();
-
-        std::fs::create_dir(tmp_path.join("a")).unwrap();
-
-        let file_path = tmp_path.join("hello.txt");
-        let mut file = File::create(&file_path).unwrap();
-        file.write_all("Hello, world".as_bytes()).unwrap();
-
-        let file_path_2 = tmp_path.join("hohoho.txt");
-        let mut file = File::create(&file_path_2).unwrap();
-        file.write_all("Merry christmas!".as_bytes()).unwrap();
-
-        let file_path_3 = tmp_path.join("a").join("b.txt");
-        let mut file = File::create(&file_path_3).unwrap();
-        file.write_all("bbbb".as_bytes()).unwrap();
-
-        let file_path_4 = tmp_path.join("a").join("c.txt");
-        let mut file = File::create(&file_path_4).unwrap();
-        file.write_all("cccc".as_bytes()).unwrap();
-
-        add_files_to_repository(vec![&tmp_path.join("a")], &tmp_path).unwrap();
+    fn exits_with_gits_statuses() {
+        fn status_of(args: &[&str], ctx: &mut CommandContext) -> i32 {
+            let opt = Opt::from_iter(std::iter::once("nit").chain(args.iter().copied()));
+            match handle_opt(opt, ctx) {
+                Ok(()) => 0,
+                Err(e) => ExitStatus::of(&e).code(),
+            }
+        }
 
-        index.load_for_update().unwrap();
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+        write_and_commit(tmp_path, &[("a.txt", "a")], "First");
+        write_and_commit(tmp_path, &[("a.txt", "b")], "Second");
 
-        let entries: Vec<_> = index
-            .entries()
-            .values()
-            .map(|entry| (entry.mode(), entry.path()))
-            .collect();
+        let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+        let mut stdin = std::io::empty();
+        let mut ctx = context(tmp_path, &mut stdin, &mut stdout, &mut stderr);
+        assert_eq!(status_of(&["status"], &mut ctx), 0);
 
+        assert_eq!(status_of(&["diff", "HEAD~1", "HEAD"], &mut ctx), 0);
+        let args = ["diff", "--exit-code", "HEAD~1", "HEAD"];
+        assert_eq!(status_of(&args, &mut ctx), 1);
         assert_eq!(
-            entries,
-            vec![
-                (REGULAR_MODE, Path::new("a/b.txt")),
-                (REGULAR_MODE, Path::new("a/c.txt"))
-            ]
+            status_of(&["diff", "--quiet", "HEAD~1", "HEAD"], &mut ctx),
+            1
         );
-        cleanup(&subdir).unwrap();
+        assert_eq!(status_of(&["diff", "--quiet", "HEAD", "HEAD"], &mut ctx), 0);
+
+        assert_eq!(status_of(&["commit", "-m", "Nothing"], &mut ctx), 1);
+        std::fs::write(tmp_path.join(".git/index.lock"), "").unwrap();
+        std::fs::write(tmp_path.join("a.txt"), "c").unwrap();
+        let a = tmp_path.join("a.txt");
+        assert_eq!(status_of(&["add", a.to_str().unwrap()], &mut ctx), 128);
+
+        // Outside a repository there's no status to give.
+        let elsewhere = tmp_path.join("elsewhere");
+        std::fs::create_dir(&elsewhere).unwrap();
+        ctx.root_path = elsewhere.clone();
+        ctx.repo = Repository::open(&elsewhere);
+        assert_eq!(status_of(&["status"], &mut ctx), 128);
+        drop(ctx);
+
+        let stdout = String::from_utf8(stdout).unwrap();
+        let (patch, summary) = stdout.split_once("On branch").expect(&stdout);
+        assert_eq!(patch.matches("diff --git").count(), 2);
+        assert!(summary.ends_with("nothing to commit, working tree clean\n"));
     }
 
     #[test]
-    fn fails_for_non_existent_files() {
-        let subdir = "non_existent";
-        let tmp_path = tmp_path(&subdir);
-
-        init(&subdir).unwrap();
+    fn drives_a_repository_through_the_library() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+        let repo = Repository::open(tmp_path);
+        let author = CommitOptions::new(Identity::new("A", "a@example.com"));
+        let paths = |changes: &[diff::RawChange]| -> Vec<PathBuf> {
+            changes.iter().map(|change| change.path.clone()).collect()
+        };
 
-        assert!(add_files_to_repository(vec![&tmp_path.join("a")], &tmp_path).is_err());
+        std::fs::create_dir(tmp_path.join("dir")).unwrap();
+        std::fs::write(tmp_path.join("dir").join("a.txt"), "a").unwrap();
+        std::fs::write(tmp_path.join("b.txt"), "b").unwrap();
+        assert_eq!(repo.log().unwrap().count(), 0);
+        assert_eq!(repo.status().unwrap().untracked, vec!["b.txt", "dir/a.txt"]);
+
+        repo.add(&["dir"]).unwrap();
+        let status = repo.status().unwrap();
+        assert_eq!(paths(&status.staged), vec![PathBuf::from("dir/a.txt")]);
+        assert_eq!(status.untracked, vec!["b.txt"]);
+
+        let first = repo.commit("First", &author).unwrap();
+        assert!(repo.commit(" \n", &author).is_err());
+        repo.refs()
+            .create_branch("topic", &first, "branch: Created from HEAD")
+            .unwrap();
+
+        std::fs::write(tmp_path.join("dir").join("a.txt"), "changed").unwrap();
+        let status = repo.status().unwrap();
+        assert!(status.staged.is_empty());
+        assert_eq!(paths(&status.unstaged), vec![PathBuf::from("dir/a.txt")]);
+
+        repo.add(&[tmp_path.join("dir").join("a.txt")]).unwrap();
+        let second = repo.commit("Second", &author).unwrap();
+        let log: Vec<_> = repo.log().unwrap().map(|c| c.unwrap().0).collect();
+        assert_eq!(log, vec![second, first]);
+
+        assert_eq!(repo.checkout("topic").unwrap(), first);
+        assert_eq!(repo.refs().current_ref().unwrap(), "refs/heads/topic");
+        assert_eq!(
+            std::fs::read_to_string(tmp_path.join("dir").join("a.txt")).unwrap(),
+            "a"
+        );
 
-        cleanup(&subdir).unwrap();
+        let bare = Repository::new(tmp_path.join(".git"));
+        assert!(bare.status().is_err());
     }
-    #[test]
-    fn fails_for_unreadable_existent_files() {
-        let subdir = "unreadable";
-        let tmp_path = tmp_path(&subdir);
 
-        init(&subdir).unwrap();
-
-        let file = File::create(tmp_path.join("shhh.txt")).unwrap();
-
-        let mut permissions = file.metadata().unwrap().permissions();
-        let mode = permissions.mode();
-        // Set it to unreadable.
-        permissions.set_mode(mode & 0b1011111111);
-        file.set_permissions(permissions).unwrap();
-
-        // assert!(add_files_to_repository(vec![&tmp_path.join("shhh.txt")], &tmp_path).is_err());
-
-        cleanup(&subdir).unwrap();
+    #[test]
+    fn resolves_previous_positions_from_the_reflog() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        write_and_commit(tmp_path, &[("a.txt", "a")], "First");
+        write_and_commit(tmp_path, &[("a.txt", "b")], "Second");
+        write_and_commit(tmp_path, &[("a.txt", "c")], "Third");
+
+        let repo = Repository::new(tmp_path.join(".git"));
+        let resolve = |expr| Revision::resolve(expr, repo.database(), repo.refs());
+        let third = resolve("HEAD").unwrap();
+        let first = resolve("HEAD~2").unwrap();
+
+        assert_eq!(resolve("master@{0}").unwrap(), third);
+        assert_eq!(resolve("@{2}").unwrap(), first);
+        assert_eq!(resolve("HEAD@{1}^").unwrap(), first);
+        assert!(resolve("master@{3}").is_err());
+        assert_eq!(resolve("master@{1 hour ago}").unwrap(), first);
+        assert_eq!(resolve("master@{now}").unwrap(), third);
+
+        checkout("master~2", CheckoutMode::Detach, tmp_path).unwrap();
+        assert_eq!(resolve("HEAD@{1}").unwrap(), third);
+        assert_eq!(resolve("master@{1}").unwrap(), resolve("master~1").unwrap());
+
+        let log = repo.refs().reflog("HEAD").unwrap();
+        assert_eq!(log[0].message, "commit (initial): First");
+        assert_eq!(log[3].message, "checkout: moving from master to master~2");
     }
 
     #[test]
-    fn makes_a_commit() {
-        let subdir = "commits";
-        let tmp_path = tmp_path(&subdir);
-
-        init(&subdir).unwrap();
-        let file_path = &tmp_path.join("hello.txt");
-        let mut file = File::create(&file_path).unwrap();
-        file.write_all("Hello, world".as_bytes()).unwrap();
-
-        add_files_to_repository(vec![&file_path], &tmp_path).unwrap();
-
-        create_commit(Some("Commit message is here".to_owned()), &tmp_path).unwrap();
+    fn lists_loose_and_packed_refs() {
+        let tmp = init().unwrap();
+        let tmp_path = tmp.path();
+
+        write_and_commit(tmp_path, &[("a.txt", "a")], "First");
+        let refs = Refs::new(&tmp_path.join(".git"));
+        let head = refs.read_head().unwrap().unwrap();
+
+        std::fs::write(
+            tmp_path.join(".git/packed-refs"),
+            format!(
+                "# pack-refs with: peeled fully-peeled sorted\n{0} refs/heads/master\n{0} refs/tags/v1.0\n^{0}\n",
+                "1".repeat(40)
+            ),
+        )
+        .unwrap();
+        refs.update_ref("refs/remotes/origin/main", &head, "fetch")
+            .unwrap();
+        refs.set_symref("refs/remotes/origin/HEAD", "refs/remotes/origin/main")
+            .unwrap();
+
+        let listed: Vec<_> = refs
+            .list(None)
+            .unwrap()
+            .into_iter()
+            .map(|r| (r.name, r.oid.map(|oid| oid.to_string()), r.symref))
+            .collect();
+        let tag = Some("1".repeat(40));
+        assert_eq!(
+            listed,
+            vec![
+                ("refs/heads/master".to_owned(), Some(head.to_string()), None),
+                (
+                    "refs/remotes/origin/HEAD".to_owned(),
+                    Some(head.to_string()),
+                    Some("refs/remotes/origin/main".to_owned())
+                ),
+                (
+                    "refs/remotes/origin/main".to_owned(),
+                    Some(head.to_string()),
+                    None
+                ),
+                ("refs/tags/v1.0".to_owned(), tag.clone(), None),
+            ]
+        );
 
-        cleanup(&subdir).unwrap();
+        assert_eq!(refs.list(Some("refs/tags/")).unwrap().len(), 1);
+        assert_eq!(
+            refs.read_ref("v1.0").unwrap().map(|oid| oid.to_string()),
+            tag
+        );
     }
 
     #[test]
-    fn lists_untracked_files_in_name_order() {
-        let subdir = "commits_stuff";
-        let tmp_path = tmp_path(&subdir);
-
-        init(&subdir).unwrap();
-
-        let file_path = &tmp_path.join("hello.txt");
-        let mut file = File::create(&file_path).unwrap();
-        file.write_all("Hello, world".as_bytes()).unwrap();
-
-        let file_path = &tmp_path.join("goodbye.txt");
-        let mut file = File::create(&file_path).unwrap();
-        file.write_all("Hello, world".as_bytes()).unwrap();
-
-        let status = get_repository_status(&tmp_path).unwrap();
+    fn reports_corrupt_loose_objects() {
+        let tmp = init().unwrap();
+        let path = tmp.path();
+        let database = Database::new(path.join(".git/objects"));
+
+        let write_loose = |content: &[u8]| {
+            let oid = ObjectId::from(<[u8; 20]>::from(<sha1::Sha1 as sha1::Digest>::digest(
+                content,
+            )));
+            let hex = oid.to_string();
+            let dir = path.join(".git/objects").join(&hex[..2]);
+            std::fs::create_dir_all(&dir).unwrap();
+            let mut encoder =
+                flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::fast());
+            encoder.write_all(content).unwrap();
+            std::fs::write(dir.join(&hex[2..]), encoder.finish().unwrap()).unwrap();
+            oid
+        };
+        let reason = |oid: &ObjectId| match database.load(oid) {
+            Err(nit::Error::Database(nit::database::DatabaseError::Corrupt(name, reason))) => {
+                assert_eq!(name, oid.to_string());
+                reason
+            }
+            other => panic!("expected a corrupt object, got {:?}", other.map(|_| ())),
+        };
 
-        assert_eq!(status, "?? goodbye.txt\n?? hello.txt\n");
-        cleanup(&subdir).unwrap();
+        let oid = write_loose(b"blob 9\0short");
+        assert_eq!(reason(&oid), "size in header does not match its content");
+        let oid = write_loose(b"bogus 5\0hello");
+        assert_eq!(reason(&oid), "unknown object type");
+
+        // Damage a good object, so it no longer hashes to its name.
+        let oid = database.store(&Blob::new(b"hello".to_vec())).unwrap();
+        let hex = oid.to_string();
+        let object_path = path.join(".git/objects").join(&hex[..2]).join(&hex[2..]);
+        let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::fast());
+        encoder.write_all(b"blob 5\0jello").unwrap();
+        std::fs::remove_file(&object_path).unwrap();
+        std::fs::write(&object_path, encoder.finish().unwrap()).unwrap();
+        assert_eq!(reason(&oid), "its hash does not match its name");
     }
 }
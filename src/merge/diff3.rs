@@ -0,0 +1,343 @@
+use std::collections::HashMap;
+
+use crate::diff::{self, Algorithm, EditKind, Line};
+
+/// The length of conflict markers unless asked otherwise, as in `<<<<<<<`.
+pub const DEFAULT_MARKER_SIZE: usize = 7;
+
+/// A part of a three-way merge: either lines the two sides agree on, after taking any
+/// change just one of them made, or a conflict between changes both made.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Chunk {
+    Clean(Vec<String>),
+    Conflict {
+        base: Vec<String>,
+        ours: Vec<String>,
+        theirs: Vec<String>,
+    },
+}
+
+/// How to settle the conflicts in a merge, rather than marking them in the result.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Favor {
+    /// Take our side of each conflict.
+    Ours,
+    /// Take their side of each conflict.
+    Theirs,
+    /// Take both sides of each conflict, ours first.
+    Union,
+}
+
+/// How a merge's conflicts are written out.
+#[derive(Debug, Clone)]
+pub struct ConflictStyle {
+    /// Settle conflicts this way instead of marking them.
+    pub favor: Option<Favor>,
+    /// How many characters long each conflict marker is.
+    pub marker_size: usize,
+    /// Show the base's version of the lines between the two sides, as git's `diff3`
+    /// conflict style does.
+    pub diff3: bool,
+}
+
+impl Default for ConflictStyle {
+    fn default() -> Self {
+        Self {
+            favor: None,
+            marker_size: DEFAULT_MARKER_SIZE,
+            diff3: false,
+        }
+    }
+}
+
+/// What the names of the base and each side are, as shown on conflict markers.
+#[derive(Debug, Clone, Copy)]
+pub struct Labels<'a> {
+    pub ours: &'a str,
+    pub base: &'a str,
+    pub theirs: &'a str,
+}
+
+/// The result of merging two versions of a file that came from a common base.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Merge {
+    chunks: Vec<Chunk>,
+}
+
+impl Merge {
+    pub fn chunks(&self) -> &[Chunk] {
+        &self.chunks
+    }
+
+    /// How many conflicts the merge has.
+    pub fn conflicts(&self) -> usize {
+        self.chunks
+            .iter()
+            .filter(|chunk| matches!(chunk, Chunk::Conflict { .. }))
+            .count()
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.conflicts() == 0
+    }
+
+    /// The merged file, with each conflict settled or marked as the style says.
+    pub fn to_bytes(&self, labels: Labels<'_>, style: &ConflictStyle) -> Vec<u8> {
+        let mut output = String::new();
+        let marker = |output: &mut String, c: char, label: &str| {
+            ensure_newline(output);
+            output.extend(std::iter::repeat_n(c, style.marker_size));
+            if !label.is_empty() {
+                output.push(' ');
+                output.push_str(label);
+            }
+            output.push('\n');
+        };
+
+        for chunk in &self.chunks {
+            let (base, ours, theirs) = match chunk {
+                Chunk::Clean(lines) => {
+                    output.extend(lines.iter().map(String::as_str));
+                    continue;
+                }
+                Chunk::Conflict { base, ours, theirs } => (base, ours, theirs),
+            };
+
+            match style.favor {
+                Some(Favor::Ours) => output.extend(ours.iter().map(String::as_str)),
+                Some(Favor::Theirs) => output.extend(theirs.iter().map(String::as_str)),
+                Some(Favor::Union) => {
+                    output.extend(ours.iter().map(String::as_str));
+                    if !ours.is_empty() && !theirs.is_empty() {
+                        ensure_newline(&mut output);
+                    }
+                    output.extend(theirs.iter().map(String::as_str));
+                }
+                None => {
+                    marker(&mut output, '<', labels.ours);
+                    output.extend(ours.iter().map(String::as_str));
+                    if style.diff3 {
+                        marker(&mut output, '|', labels.base);
+                        output.extend(base.iter().map(String::as_str));
+                    }
+                    marker(&mut output, '=', "");
+                    output.extend(theirs.iter().map(String::as_str));
+                    marker(&mut output, '>', labels.theirs);
+                }
+            }
+        }
+
+        output.into_bytes()
+    }
+}
+
+/// End the output with a newline, unless it's empty or already does, so that a marker
+/// can start a line of its own.
+fn ensure_newline(output: &mut String) {
+    if !output.is_empty() && !output.ends_with('\n') {
+        output.push('\n');
+    }
+}
+
+/// Merge the changes made from `base` to `ours`, and from `base` to `theirs`. Changes to
+/// different lines are both taken, but where both sides changed the same lines differently
+/// there's a conflict. Unless `diff3` conflicts are wanted, lines at the start and end of a
+/// conflict that both sides agree on are taken out of it.
+pub fn merge(base: &[u8], ours: &[u8], theirs: &[u8], diff3: bool) -> Merge {
+    let merger = Diff3 {
+        base: diff::lines(base),
+        ours: diff::lines(ours),
+        theirs: diff::lines(theirs),
+    };
+    let mut merge = Merge {
+        chunks: merger.chunks(),
+    };
+    if !diff3 {
+        merge.chunks = merge.chunks.into_iter().flat_map(shrink_conflict).collect();
+    }
+    merge
+}
+
+/// Splits three versions of a file into chunks, where each is either the same in all three
+/// or differs in at least one. Lines are lined up by diffing each side with the base.
+struct Diff3 {
+    base: Vec<Line>,
+    ours: Vec<Line>,
+    theirs: Vec<Line>,
+}
+
+impl Diff3 {
+    fn chunks(&self) -> Vec<Chunk> {
+        let match_ours = match_set(&self.base, &self.ours);
+        let match_theirs = match_set(&self.base, &self.theirs);
+
+        let mut chunks = Vec::new();
+        // How many lines of each version have been put into chunks so far.
+        let (mut o, mut a, mut b) = (0, 0, 0);
+        loop {
+            let in_bounds = |i: usize| {
+                o + i <= self.base.len() || a + i <= self.ours.len() || b + i <= self.theirs.len()
+            };
+
+            // Find the first line, counting from 1, where the versions stop agreeing.
+            let mut i = 1;
+            while in_bounds(i)
+                && match_ours.get(&(o + i)) == Some(&(a + i))
+                && match_theirs.get(&(o + i)) == Some(&(b + i))
+            {
+                i += 1;
+            }
+
+            let (end_o, end_a, end_b) = match i {
+                _ if !in_bounds(i) => break,
+                1 => {
+                    // The versions differ right away: find where they agree again.
+                    let next = (o + 1..=self.base.len()).find(|line| {
+                        match_ours.contains_key(line) && match_theirs.contains_key(line)
+                    });
+                    match next {
+                        Some(line) => (line, match_ours[&line], match_theirs[&line]),
+                        None => break,
+                    }
+                }
+                i => (o + i, a + i, b + i),
+            };
+
+            chunks.push(chunk(
+                &self.base[o..end_o - 1],
+                &self.ours[a..end_a - 1],
+                &self.theirs[b..end_b - 1],
+            ));
+            o = end_o - 1;
+            a = end_a - 1;
+            b = end_b - 1;
+        }
+
+        chunks.push(chunk(&self.base[o..], &self.ours[a..], &self.theirs[b..]));
+        chunks.retain(|chunk| !matches!(chunk, Chunk::Clean(lines) if lines.is_empty()));
+        chunks
+    }
+}
+
+/// Map the number of each line of `base` that `other` left alone to its number there.
+fn match_set(base: &[Line], other: &[Line]) -> HashMap<usize, usize> {
+    diff::diff(base, other, Algorithm::Myers)
+        .into_iter()
+        .filter(|edit| edit.kind == EditKind::Equal)
+        .filter_map(|edit| Some((edit.a_line?.number, edit.b_line?.number)))
+        .collect()
+}
+
+/// A chunk from lines of each version: clean if either side left the base alone or both
+/// made the same change.
+fn chunk(base: &[Line], ours: &[Line], theirs: &[Line]) -> Chunk {
+    let texts = |lines: &[Line]| -> Vec<String> { lines.iter().map(|l| l.text.clone()).collect() };
+    let (base, ours, theirs) = (texts(base), texts(ours), texts(theirs));
+
+    if ours == base || ours == theirs {
+        Chunk::Clean(theirs)
+    } else if theirs == base {
+        Chunk::Clean(ours)
+    } else {
+        Chunk::Conflict { base, ours, theirs }
+    }
+}
+
+/// Take the lines both sides of a conflict start and end with out of it.
+fn shrink_conflict(chunk: Chunk) -> Vec<Chunk> {
+    let (base, mut ours, mut theirs) = match chunk {
+        Chunk::Conflict { base, ours, theirs } => (base, ours, theirs),
+        clean => return vec![clean],
+    };
+
+    let prefix = ours.iter().zip(&theirs).take_while(|(a, b)| a == b).count();
+    let suffix = ours[prefix..]
+        .iter()
+        .rev()
+        .zip(theirs[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let tail = ours.split_off(ours.len() - suffix);
+    theirs.truncate(theirs.len() - suffix);
+    let head: Vec<_> = ours.drain(..prefix).collect();
+    theirs.drain(..prefix);
+
+    let mut chunks = Vec::with_capacity(3);
+    if !head.is_empty() {
+        chunks.push(Chunk::Clean(head));
+    }
+    chunks.push(Chunk::Conflict { base, ours, theirs });
+    if !tail.is_empty() {
+        chunks.push(Chunk::Clean(tail));
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const LABELS: Labels<'static> = Labels {
+        ours: "ours",
+        base: "base",
+        theirs: "theirs",
+    };
+
+    fn merged(base: &str, ours: &str, theirs: &str, style: &ConflictStyle) -> String {
+        let merge = merge(
+            base.as_bytes(),
+            ours.as_bytes(),
+            theirs.as_bytes(),
+            style.diff3,
+        );
+        String::from_utf8(merge.to_bytes(LABELS, style)).unwrap()
+    }
+
+    #[test]
+    fn takes_changes_to_different_lines_from_both_sides() {
+        let style = ConflictStyle::default();
+        let merge = merged("a\nb\nc\n", "A\nb\nc\n", "a\nb\nC\n", &style);
+        assert_eq!(merge, "A\nb\nC\n");
+
+        let merge = merged("a\nb\n", "a\nb\nc\n", "a\nb\nc\n", &style);
+        assert_eq!(merge, "a\nb\nc\n");
+    }
+
+    #[test]
+    fn marks_conflicts() {
+        let (base, ours, theirs) = ("a\nb\nc\n", "a\nx\ny\nc\n", "a\nx\nz\nc\n");
+        assert!(!merge(base.as_bytes(), ours.as_bytes(), theirs.as_bytes(), false).is_clean());
+
+        let style = ConflictStyle::default();
+        assert_eq!(
+            merged(base, ours, theirs, &style),
+            "a\nx\n<<<<<<< ours\ny\n=======\nz\n>>>>>>> theirs\nc\n"
+        );
+
+        let style = ConflictStyle {
+            diff3: true,
+            marker_size: 3,
+            ..Default::default()
+        };
+        assert_eq!(
+            merged(base, ours, theirs, &style),
+            "a\n<<< ours\nx\ny\n||| base\nb\n===\nx\nz\n>>> theirs\nc\n"
+        );
+    }
+
+    #[test]
+    fn can_settle_conflicts() {
+        let (base, ours, theirs) = ("a\nb\nc\n", "a\ny\nc\n", "a\nz\nc\n");
+        let favor = |favor| ConflictStyle {
+            favor: Some(favor),
+            ..Default::default()
+        };
+        assert_eq!(merged(base, ours, theirs, &favor(Favor::Ours)), ours);
+        assert_eq!(merged(base, ours, theirs, &favor(Favor::Theirs)), theirs);
+        assert_eq!(
+            merged(base, ours, theirs, &favor(Favor::Union)),
+            "a\ny\nz\nc\n"
+        );
+    }
+}
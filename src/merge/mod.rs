@@ -0,0 +1,283 @@
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use chrono::{DateTime, FixedOffset};
+
+use crate::database::{Database, ObjectId};
+use crate::revwalk::RevWalk;
+use crate::vfs::{RealFs, Vfs};
+use crate::Result;
+
+pub mod diff3;
+
+const PARENT_1: u8 = 0b0001;
+const PARENT_2: u8 = 0b0010;
+const BOTH_PARENTS: u8 = PARENT_1 | PARENT_2;
+const RESULT: u8 = 0b0100;
+const STALE: u8 = 0b1000;
+
+/// Finds the best common ancestors of one commit and a set of others, by walking back through
+/// their histories newest-first and marking which side each commit is reachable from.
+pub struct CommonAncestors<'a, F: Vfs = RealFs> {
+    database: &'a Database<F>,
+    flags: HashMap<ObjectId, u8>,
+    queue: Vec<(DateTime<FixedOffset>, ObjectId)>,
+    results: Vec<(DateTime<FixedOffset>, ObjectId)>,
+}
+
+impl<'a, F: Vfs> CommonAncestors<'a, F> {
+    pub fn new(database: &'a Database<F>, one: &ObjectId, twos: &[ObjectId]) -> Result<Self> {
+        let mut ancestors = Self {
+            database,
+            flags: HashMap::new(),
+            queue: Vec::new(),
+            results: Vec::new(),
+        };
+
+        ancestors.enqueue(one, PARENT_1)?;
+        for two in twos {
+            ancestors.enqueue(two, PARENT_2)?;
+        }
+
+        Ok(ancestors)
+    }
+
+    /// Walk the histories until every commit left to visit is known to be a common ancestor
+    /// of a better candidate, and return the candidates that were found.
+    pub fn find(mut self) -> Result<Vec<ObjectId>> {
+        self.walk()?;
+
+        let flags = &self.flags;
+        Ok(self
+            .results
+            .into_iter()
+            .map(|(_, oid)| oid)
+            .filter(|oid| flags.get(oid).is_none_or(|f| f & STALE == 0))
+            .collect())
+    }
+
+    fn walk(&mut self) -> Result<()> {
+        while !self.all_stale() {
+            self.process_queue()?;
+        }
+
+        Ok(())
+    }
+
+    /// How many of the visited commits were reachable from exactly one side.
+    fn count(&self, side: u8) -> usize {
+        self.flags.values().filter(|&&flags| flags == side).count()
+    }
+
+    /// Follow parents back from `tip` for as long as the commits are reachable from `side` alone.
+    fn commits_only_on(&self, tip: &ObjectId, side: u8) -> Result<Vec<ObjectId>> {
+        let mut commits = Vec::new();
+        let mut next = Some(*tip);
+
+        while let Some(oid) = next {
+            if self.flags.get(&oid) != Some(&side) {
+                break;
+            }
+            next = self.database.load_commit(&oid)?.parent().copied();
+            commits.push(oid);
+        }
+
+        Ok(commits)
+    }
+
+    fn enqueue(&mut self, oid: &ObjectId, flags: u8) -> Result<()> {
+        let time = self.database.load_commit(oid)?.author().time();
+        *self.flags.entry(*oid).or_default() |= flags;
+        insert_by_date(&mut self.queue, time, *oid);
+        Ok(())
+    }
+
+    fn all_stale(&self) -> bool {
+        self.queue
+            .iter()
+            .all(|(_, oid)| self.flags.get(oid).is_some_and(|f| f & STALE != 0))
+    }
+
+    fn process_queue(&mut self) -> Result<()> {
+        let (time, oid) = self.queue.remove(0);
+        let flags = self.flags.get(&oid).copied().unwrap_or_default();
+
+        let commit = self.database.load_commit(&oid)?;
+
+        let parent_flags = if flags & BOTH_PARENTS == BOTH_PARENTS {
+            if flags & RESULT == 0 {
+                *self.flags.entry(oid).or_default() |= RESULT;
+                insert_by_date(&mut self.results, time, oid);
+            }
+            (flags & !RESULT) | STALE
+        } else {
+            flags
+        };
+
+        for parent in commit.parents() {
+            let existing = self.flags.get(parent).copied().unwrap_or_default();
+            if existing & parent_flags != parent_flags {
+                self.enqueue(parent, parent_flags)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn insert_by_date(
+    list: &mut Vec<(DateTime<FixedOffset>, ObjectId)>,
+    time: DateTime<FixedOffset>,
+    oid: ObjectId,
+) {
+    let index = list
+        .iter()
+        .position(|(other, _)| other < &time)
+        .unwrap_or(list.len());
+    list.insert(index, (time, oid));
+}
+
+/// Find the best common ancestor of two commits, if they have one.
+pub fn merge_base<F: Vfs>(
+    database: &Database<F>,
+    one: &ObjectId,
+    two: &ObjectId,
+) -> Result<Option<ObjectId>> {
+    let bases = CommonAncestors::new(database, one, std::slice::from_ref(two))?.find()?;
+    Ok(bases.into_iter().next())
+}
+
+/// Whether `ancestor` is reachable by following parents back from `descendant`.
+pub fn is_ancestor<F: Vfs>(
+    database: &Database<F>,
+    ancestor: &ObjectId,
+    descendant: &ObjectId,
+) -> Result<bool> {
+    Ok(merge_base(database, ancestor, descendant)?.as_ref() == Some(ancestor))
+}
+
+/// Count the commits reachable from `one` but not `two`, and from `two` but not `one`.
+pub fn ahead_behind<F: Vfs>(
+    database: &Database<F>,
+    one: &ObjectId,
+    two: &ObjectId,
+) -> Result<(usize, usize)> {
+    let mut common = CommonAncestors::new(database, one, std::slice::from_ref(two))?;
+    common.walk()?;
+    Ok((common.count(PARENT_1), common.count(PARENT_2)))
+}
+
+/// List the commits reachable from `one` but not `two`, and from `two` but not `one`,
+/// each newest first.
+pub fn unique_commits<F: Vfs>(
+    database: &Database<F>,
+    one: &ObjectId,
+    two: &ObjectId,
+) -> Result<(Vec<ObjectId>, Vec<ObjectId>)> {
+    let mut common = CommonAncestors::new(database, one, std::slice::from_ref(two))?;
+    common.walk()?;
+    Ok((
+        common.commits_only_on(one, PARENT_1)?,
+        common.commits_only_on(two, PARENT_2)?,
+    ))
+}
+
+/// How several branches compare, as found by `compare_branches`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BranchComparison {
+    /// The commits on some of the branches but not all of them, children before parents
+    /// and otherwise newest first, each with whether it's on each branch, in the order the
+    /// branches were given. The newest commit on all of them comes last, if there is one.
+    pub commits: Vec<(ObjectId, Vec<bool>)>,
+    /// The best common ancestor of each pair of branches, given by their positions.
+    pub merge_bases: Vec<(usize, usize, Option<ObjectId>)>,
+}
+
+/// Compare the histories of several branches: which commits are on which of them, back to
+/// where they all meet, and where each pair of them diverged.
+pub fn compare_branches<F: Vfs>(
+    database: &Database<F>,
+    tips: &[ObjectId],
+) -> Result<BranchComparison> {
+    let mut merge_bases = Vec::new();
+    for (i, one) in tips.iter().enumerate() {
+        for (j, two) in tips.iter().enumerate().skip(i + 1) {
+            merge_bases.push((i, j, merge_base(database, one, two)?));
+        }
+    }
+
+    // Where they all meet: the merge base of the first two, then of that and the third,
+    // and so on.
+    let mut common = tips.first().copied();
+    for tip in tips.iter().skip(1) {
+        common = match common {
+            Some(oid) => merge_base(database, &oid, tip)?,
+            None => None,
+        };
+    }
+
+    let mut reachable = Vec::new();
+    for tip in tips {
+        let commits: HashSet<_> = match &common {
+            Some(common) => unique_commits(database, tip, common)?
+                .0
+                .into_iter()
+                .collect(),
+            None => RevWalk::new(database, &[*tip])?
+                .map(|commit| commit.map(|(oid, _)| oid))
+                .collect::<Result<_>>()?,
+        };
+        reachable.push(commits);
+    }
+
+    // Put children before their parents, picking the newest commit whenever there's a
+    // choice.
+    let mut children: HashMap<ObjectId, usize> =
+        reachable.iter().flatten().map(|oid| (*oid, 0)).collect();
+    let mut commits = HashMap::new();
+    for oid in children.keys().copied().collect::<Vec<_>>() {
+        let commit = database.load_commit(&oid)?;
+        for parent in commit.parents() {
+            if let Some(count) = children.get_mut(parent) {
+                *count += 1;
+            }
+        }
+        commits.insert(oid, commit);
+    }
+
+    let mut ready: BinaryHeap<_> = children
+        .iter()
+        .filter(|(_, count)| **count == 0)
+        .map(|(oid, _)| (commits[oid].committer().time(), *oid))
+        .collect();
+    let mut order = Vec::new();
+    while let Some((_, oid)) = ready.pop() {
+        for parent in commits[&oid].parents() {
+            if let Some(count) = children.get_mut(parent) {
+                *count -= 1;
+                if *count == 0 {
+                    ready.push((commits[parent].committer().time(), *parent));
+                }
+            }
+        }
+        order.push(oid);
+    }
+
+    let mut commits: Vec<_> = order
+        .into_iter()
+        .map(|oid| {
+            let on = reachable
+                .iter()
+                .map(|commits| commits.contains(&oid))
+                .collect();
+            (oid, on)
+        })
+        .collect();
+    if let Some(common) = common {
+        commits.push((common, vec![true; tips.len()]));
+    }
+
+    Ok(BranchComparison {
+        commits,
+        merge_bases,
+    })
+}
@@ -0,0 +1,282 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+};
+
+use thiserror::Error;
+
+use crate::database::{Database, DatabaseEntry, ObjectId, TreeDiffChanges};
+use crate::index::Index;
+use crate::inspector::Inspector;
+use crate::vfs::{RealFs, Vfs};
+use crate::workspace::Workspace;
+use crate::Result;
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum MigrationError {
+    #[error("{0}")]
+    Conflict(String),
+}
+
+/// What needs to happen to a path in the workspace to carry out a migration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Action {
+    Create,
+    Update,
+    Delete,
+}
+
+/// The reasons a migration can refuse to touch a path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Conflict {
+    StaleFile,
+    StaleDirectory,
+    UntrackedOverwritten,
+    UntrackedRemoved,
+}
+
+impl Conflict {
+    fn header(self) -> &'static str {
+        match self {
+            Conflict::StaleFile => {
+                "Your local changes to the following files would be overwritten by checkout:"
+            }
+            Conflict::StaleDirectory => {
+                "Updating the following directories would lose untracked files in them:"
+            }
+            Conflict::UntrackedOverwritten => {
+                "The following untracked working tree files would be overwritten by checkout:"
+            }
+            Conflict::UntrackedRemoved => {
+                "The following untracked working tree files would be removed by checkout:"
+            }
+        }
+    }
+
+    fn footer(self) -> &'static str {
+        match self {
+            Conflict::StaleFile => {
+                "Please commit your changes or stash them before you switch branches."
+            }
+            Conflict::StaleDirectory => "",
+            Conflict::UntrackedOverwritten | Conflict::UntrackedRemoved => {
+                "Please move or remove them before you switch branches."
+            }
+        }
+    }
+}
+
+/// Moves the workspace and index from one tree to another, given the differences between them.
+pub struct Migration<'a, F: Vfs = RealFs> {
+    database: &'a Database<F>,
+    index: &'a mut Index<F>,
+    workspace: &'a Workspace<F>,
+    diff: TreeDiffChanges,
+    changes: BTreeMap<Action, Vec<(PathBuf, Option<DatabaseEntry>)>>,
+    mkdirs: BTreeSet<PathBuf>,
+    rmdirs: BTreeSet<PathBuf>,
+    conflicts: BTreeMap<Conflict, BTreeSet<PathBuf>>,
+}
+
+impl<'a, F: Vfs> Migration<'a, F> {
+    pub fn new(
+        database: &'a Database<F>,
+        index: &'a mut Index<F>,
+        workspace: &'a Workspace<F>,
+        diff: TreeDiffChanges,
+    ) -> Self {
+        Self {
+            database,
+            index,
+            workspace,
+            diff,
+            changes: BTreeMap::new(),
+            mkdirs: BTreeSet::new(),
+            rmdirs: BTreeSet::new(),
+            conflicts: BTreeMap::new(),
+        }
+    }
+
+    /// Apply the migration to the workspace and the index.
+    ///
+    /// If any path the migration would touch has local changes that would be lost, nothing is
+    /// written and every such path is reported in a single error.
+    pub fn apply_changes(&mut self) -> Result<()> {
+        self.plan_changes()?;
+        self.workspace.apply_migration(self)?;
+        self.update_index()?;
+
+        Ok(())
+    }
+
+    /// The changes planned for a particular action, in path order.
+    pub fn changes(&self, action: Action) -> &[(PathBuf, Option<DatabaseEntry>)] {
+        self.changes.get(&action).map_or(&[], |c| c.as_slice())
+    }
+
+    /// Directories that should be created before files are written.
+    pub fn mkdirs(&self) -> &BTreeSet<PathBuf> {
+        &self.mkdirs
+    }
+
+    /// Directories that may be left empty once files have been deleted.
+    pub fn rmdirs(&self) -> &BTreeSet<PathBuf> {
+        &self.rmdirs
+    }
+
+    /// Load the contents of a blob that is to be written to the workspace.
+    pub fn blob_data(&self, oid: &ObjectId) -> Result<Vec<u8>> {
+        Ok(self.database.load_blob(oid)?.to_bytestr().to_vec())
+    }
+
+    fn plan_changes(&mut self) -> Result<()> {
+        let diff = std::mem::take(&mut self.diff);
+
+        for (path, (old_item, new_item)) in &diff {
+            if let Some((conflict, path)) =
+                self.check_for_conflict(path, old_item.as_ref(), new_item.as_ref())?
+            {
+                self.conflicts.entry(conflict).or_default().insert(path);
+            }
+            self.record_change(path, old_item.as_ref(), new_item.as_ref());
+        }
+
+        self.diff = diff;
+        self.collect_errors()
+    }
+
+    fn record_change(
+        &mut self,
+        path: &Path,
+        old_item: Option<&DatabaseEntry>,
+        new_item: Option<&DatabaseEntry>,
+    ) {
+        let parents = path
+            .ancestors()
+            .skip(1)
+            .filter(|p| !p.as_os_str().is_empty())
+            .map(Path::to_owned);
+
+        let action = match (old_item, new_item) {
+            (_, None) => {
+                self.rmdirs.extend(parents);
+                Action::Delete
+            }
+            (None, Some(_)) => {
+                self.mkdirs.extend(parents);
+                Action::Create
+            }
+            (Some(_), Some(_)) => {
+                self.mkdirs.extend(parents);
+                Action::Update
+            }
+        };
+
+        self.changes
+            .entry(action)
+            .or_default()
+            .push((path.to_owned(), new_item.cloned()));
+    }
+
+    fn check_for_conflict(
+        &self,
+        path: &Path,
+        old_item: Option<&DatabaseEntry>,
+        new_item: Option<&DatabaseEntry>,
+    ) -> Result<Option<(Conflict, PathBuf)>> {
+        let inspector = Inspector::new(self.database, self.index, self.workspace);
+        let entry = self.index.entry_for_path(&path);
+
+        if inspector.compare_tree_to_index(old_item, entry).is_some()
+            && inspector.compare_tree_to_index(new_item, entry).is_some()
+        {
+            return Ok(Some((Conflict::StaleFile, path.to_owned())));
+        }
+
+        let stat = self.workspace.try_stat_file(path)?;
+        let conflict = match (&stat, entry, new_item) {
+            (_, Some(_), _) => Conflict::StaleFile,
+            (Some(stat), _, _) if stat.is_dir() => Conflict::StaleDirectory,
+            (_, _, Some(_)) => Conflict::UntrackedOverwritten,
+            _ => Conflict::UntrackedRemoved,
+        };
+
+        let conflicted = match &stat {
+            None => match self.untracked_parent(&inspector, path)? {
+                Some(parent) if entry.is_none() => Some(parent),
+                Some(_) => Some(path.to_owned()),
+                None => None,
+            },
+            Some(stat) if !stat.is_dir() => inspector
+                .compare_index_to_workspace(entry, Some(stat))?
+                .map(|_| path.to_owned()),
+            Some(stat) if stat.is_dir() && inspector.is_trackable_file(path, stat)? => {
+                Some(path.to_owned())
+            }
+            Some(_) => None,
+        };
+
+        Ok(conflicted.map(|conflicted| (conflict, conflicted)))
+    }
+
+    /// Find an untracked file sitting where one of a path's parent directories needs to be.
+    fn untracked_parent(
+        &self,
+        inspector: &Inspector<'_, F>,
+        path: &Path,
+    ) -> Result<Option<PathBuf>> {
+        for parent in path.ancestors().skip(1) {
+            if parent.as_os_str().is_empty() {
+                continue;
+            }
+
+            match self.workspace.try_stat_file(parent)? {
+                Some(stat) if !stat.is_dir() && inspector.is_trackable_file(parent, &stat)? => {
+                    return Ok(Some(parent.to_owned()));
+                }
+                _ => {}
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn collect_errors(&self) -> Result<()> {
+        if self.conflicts.is_empty() {
+            return Ok(());
+        }
+
+        let mut message = String::new();
+        for (conflict, paths) in &self.conflicts {
+            message.push_str(conflict.header());
+            message.push('\n');
+            for path in paths {
+                message.push_str(&format!("\t{}\n", path.display()));
+            }
+            message.push_str(conflict.footer());
+            message.push('\n');
+        }
+        message.push_str("Aborting");
+
+        Err(MigrationError::Conflict(message).into())
+    }
+
+    fn update_index(&mut self) -> Result<()> {
+        for (path, _) in self.changes(Action::Delete).to_vec() {
+            self.index.remove(&path);
+        }
+
+        for action in &[Action::Create, Action::Update] {
+            for (path, entry) in self.changes(*action).to_vec() {
+                if let Some(entry) = entry {
+                    let stat = self.workspace.stat_file(&path)?;
+                    self.index
+                        .add_with_mode(&path, *entry.oid(), stat, entry.mode());
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
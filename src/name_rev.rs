@@ -0,0 +1,433 @@
+//! Naming commits after the refs they can be reached from, such as `master~3` or
+//! `tags/v1.0~2^2`, as `name-rev` and `describe --contains` do.
+
+use std::collections::HashMap;
+
+use crate::database::{Author, Database, DatabaseError, ObjectId};
+use crate::ref_format;
+use crate::refs::Ref;
+use crate::vfs::Vfs;
+use crate::Result;
+
+/// How far a step to a second or later parent counts for, compared with a step to a first
+/// parent, when picking between names. This is git's weighting: any path that stays on
+/// first parents wins over one that takes a merge's other side.
+const MERGE_TRAVERSAL_WEIGHT: u64 = 65535;
+/// How much older than the oldest commit being named a commit can be and still be walked.
+/// Commits older than this can't usually lead to one being named, so they're skipped.
+const CUTOFF_DATE_SLOP: i64 = 86400;
+
+/// Which refs commits are named after, and how.
+#[derive(Debug, Clone, Default)]
+pub struct NameRevOptions {
+    /// Only name commits after tags.
+    pub tags_only: bool,
+    /// Only name commits after refs that match one of these, if any are given. A pattern
+    /// can match the ref's full name or any tail of it, such as `v*` for `refs/tags/v1`;
+    /// refs matched by a tail are given their shortest name.
+    pub refs: Vec<String>,
+    /// Don't name commits after refs that match any of these.
+    pub exclude: Vec<String>,
+    /// Give every ref its shortest name, so that `refs/tags/v1` is `v1` rather than
+    /// `tags/v1`.
+    pub short_names: bool,
+}
+
+impl NameRevOptions {
+    /// The options `describe --contains` names a commit with: the first tag that contains
+    /// it, by its short name.
+    pub fn contains() -> Self {
+        Self {
+            tags_only: true,
+            short_names: true,
+            ..Default::default()
+        }
+    }
+}
+
+/// The best name found so far for a commit.
+#[derive(Debug, Clone)]
+struct RevName {
+    /// The name the walk that reached this commit started from, with any merges along the
+    /// way, such as `master~2^2`.
+    tip: String,
+    /// How many first parents have been followed since `tip`.
+    generation: usize,
+    /// How far the commit is from the ref, weighting merges by `MERGE_TRAVERSAL_WEIGHT`.
+    distance: u64,
+    /// When the ref was tagged, or when its commit was made if it isn't an annotated tag.
+    tagger_time: i64,
+    from_tag: bool,
+}
+
+impl RevName {
+    /// Whether a name with these properties would be better than this one. Tags win over
+    /// other refs, and then older tags win over newer ones, however far away they are;
+    /// between other refs, the nearest wins.
+    fn is_worse_than(&self, tagger_time: i64, distance: u64, from_tag: bool) -> bool {
+        if from_tag && self.from_tag {
+            return self.tagger_time > tagger_time
+                || (self.tagger_time == tagger_time && self.distance > distance);
+        }
+        if self.from_tag != from_tag {
+            return from_tag;
+        }
+        if self.distance != distance {
+            return self.distance > distance;
+        }
+        self.tagger_time > tagger_time
+    }
+
+    fn to_name(&self) -> String {
+        match self.generation {
+            0 => self.tip.clone(),
+            generation => format!("{}~{}", strip_peel(&self.tip), generation),
+        }
+    }
+}
+
+/// A ref a walk starts from, with the commit it leads to.
+struct Tip {
+    name: String,
+    commit: ObjectId,
+    tagger_time: i64,
+    from_tag: bool,
+}
+
+/// Names for every commit that can be reached from a set of refs.
+#[derive(Debug, Default)]
+pub struct NameRev {
+    names: HashMap<ObjectId, RevName>,
+}
+
+impl NameRev {
+    /// Walk back from each of the refs, naming the commits on the way. Only commits that
+    /// could lead to one of `targets` are walked, so naming a few recent commits doesn't
+    /// need the whole history to be read.
+    pub fn build<F: Vfs>(
+        database: &Database<F>,
+        refs: &[Ref],
+        options: &NameRevOptions,
+        targets: &[ObjectId],
+    ) -> Result<Self> {
+        let mut oldest = None;
+        for target in targets {
+            if let (_, Some(commit)) = peel(database, target)? {
+                let time = database
+                    .load_commit(&commit)?
+                    .committer()
+                    .time()
+                    .timestamp();
+                oldest = Some(oldest.map_or(time, |oldest: i64| oldest.min(time)));
+            }
+        }
+        let cutoff = oldest.map_or(i64::MIN, |time| time - CUTOFF_DATE_SLOP);
+
+        let mut tips = Vec::new();
+        for r in refs {
+            if let (Some(oid), Some(name)) = (r.oid, tip_name(&r.name, options)) {
+                let (tagger_time, commit) = match peel(database, &oid)? {
+                    (tagger_time, Some(commit)) => (tagger_time, commit),
+                    (_, None) => continue,
+                };
+                let tagger_time = match tagger_time {
+                    Some(time) => time,
+                    None => database
+                        .load_commit(&commit)?
+                        .committer()
+                        .time()
+                        .timestamp(),
+                };
+                let name = match commit == oid {
+                    true => name,
+                    false => format!("{}^0", name),
+                };
+                tips.push(Tip {
+                    name,
+                    commit,
+                    tagger_time,
+                    from_tag: r.name.starts_with("refs/tags/"),
+                });
+            }
+        }
+        // Tags are walked first, oldest first, so they keep the names they win ties with.
+        tips.sort_by_key(|tip| (!tip.from_tag, tip.tagger_time));
+
+        let mut name_rev = Self::default();
+        for tip in tips {
+            name_rev.walk(database, tip, cutoff)?;
+        }
+        Ok(name_rev)
+    }
+
+    /// The name of a commit, if it can be reached from any of the refs.
+    pub fn name(&self, oid: &ObjectId) -> Option<String> {
+        self.names.get(oid).map(RevName::to_name)
+    }
+
+    /// Name the commits reachable from one ref, wherever that gives them a better name
+    /// than they already have.
+    fn walk<F: Vfs>(&mut self, database: &Database<F>, tip: Tip, cutoff: i64) -> Result<()> {
+        let time = |oid: &ObjectId| -> Result<i64> {
+            Ok(database.load_commit(oid)?.committer().time().timestamp())
+        };
+        if time(&tip.commit)? < cutoff {
+            return Ok(());
+        }
+
+        let name = RevName {
+            tip: tip.name,
+            generation: 0,
+            distance: 0,
+            tagger_time: tip.tagger_time,
+            from_tag: tip.from_tag,
+        };
+        if !self.update(tip.commit, name) {
+            return Ok(());
+        }
+
+        // Depth first, following first parents before the others.
+        let mut stack = vec![tip.commit];
+        while let Some(oid) = stack.pop() {
+            let name = self.names[&oid].clone();
+            let commit = database.load_commit(&oid)?;
+
+            let mut updated = Vec::new();
+            for (i, parent) in commit.parents().iter().enumerate() {
+                if time(parent)? < cutoff {
+                    continue;
+                }
+
+                let parent_name = match i {
+                    0 => RevName {
+                        tip: name.tip.clone(),
+                        generation: name.generation + 1,
+                        distance: name.distance + 1,
+                        ..name.clone()
+                    },
+                    _ => {
+                        let tip = match name.generation {
+                            0 => format!("{}^{}", strip_peel(&name.tip), i + 1),
+                            generation => {
+                                format!("{}~{}^{}", strip_peel(&name.tip), generation, i + 1)
+                            }
+                        };
+                        RevName {
+                            tip,
+                            generation: 0,
+                            distance: name.distance + MERGE_TRAVERSAL_WEIGHT,
+                            ..name.clone()
+                        }
+                    }
+                };
+                if self.update(*parent, parent_name) {
+                    updated.push(*parent);
+                }
+            }
+            stack.extend(updated.into_iter().rev());
+        }
+
+        Ok(())
+    }
+
+    /// Give a commit a name, if it has none yet or the new one is better. Returns whether
+    /// it took the new name.
+    fn update(&mut self, oid: ObjectId, name: RevName) -> bool {
+        match self.names.get(&oid) {
+            Some(existing)
+                if !existing.is_worse_than(name.tagger_time, name.distance, name.from_tag) =>
+            {
+                false
+            }
+            _ => {
+                self.names.insert(oid, name);
+                true
+            }
+        }
+    }
+}
+
+/// What commits reached from a ref are named after, if the options allow naming them after
+/// it at all.
+fn tip_name(name: &str, options: &NameRevOptions) -> Option<String> {
+    if options.tags_only && !name.starts_with("refs/tags/") {
+        return None;
+    }
+
+    let mut short = options.short_names;
+    if !options.refs.is_empty() {
+        let starts: Vec<_> = options
+            .refs
+            .iter()
+            .filter_map(|pattern| ref_format::tail_match(pattern, name))
+            .collect();
+        if starts.is_empty() {
+            return None;
+        }
+        // Matching a pattern by a tail, rather than the full name, asks for short names.
+        short |= starts.iter().any(|&start| start > 0);
+    }
+    if options
+        .exclude
+        .iter()
+        .any(|pattern| ref_format::tail_matches(pattern, name))
+    {
+        return None;
+    }
+
+    let name = match short {
+        true => crate::refs::Refs::short_name(name),
+        false => name
+            .strip_prefix("refs/heads/")
+            .or_else(|| name.strip_prefix("refs/"))
+            .unwrap_or(name),
+    };
+    Some(name.to_owned())
+}
+
+/// A name without the `^0` that marks a commit pointed at by an annotated tag.
+fn strip_peel(name: &str) -> &str {
+    name.strip_suffix("^0").unwrap_or(name)
+}
+
+/// Follow any annotated tags from an object to the commit they're for. Returns the time
+/// the outermost tag was made, if the object is a tag, and the commit, if it's a commit
+/// or a tag of one.
+fn peel<F: Vfs>(database: &Database<F>, oid: &ObjectId) -> Result<(Option<i64>, Option<ObjectId>)> {
+    let mut tagger_time = None;
+    let mut oid = *oid;
+    loop {
+        let (kind, data) = database.read_object(&oid)?;
+        match kind.as_str() {
+            "commit" => return Ok((tagger_time, Some(oid))),
+            "tag" => {}
+            _ => return Ok((tagger_time, None)),
+        }
+
+        let mut target = None;
+        for line in data.split(|&b| b == b'\n') {
+            if line.is_empty() {
+                break;
+            }
+            if let Some(hex) = line.strip_prefix(b"object ") {
+                let hex = std::str::from_utf8(hex).map_err(|_| DatabaseError::BadObject("tag"))?;
+                target = Some(ObjectId::from_hex(hex)?);
+            } else if let (Some(tagger), None) = (line.strip_prefix(b"tagger "), tagger_time) {
+                tagger_time = Some(Author::parse(tagger)?.time().timestamp());
+            }
+        }
+        oid = target.ok_or(DatabaseError::BadObject("tag"))?;
+        // A tag with no tagger, as very old ones are, counts as older than any other.
+        tagger_time.get_or_insert(0);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::Commit;
+    use crate::vfs::MemoryFs;
+    use chrono::{TimeZone, Utc};
+    use std::path::Path;
+
+    struct History {
+        database: Database<MemoryFs>,
+        refs: Vec<Ref>,
+        time: i64,
+    }
+
+    impl History {
+        fn new() -> Self {
+            let fs = MemoryFs::new();
+            fs.create_dir_all(Path::new("/objects")).unwrap();
+            Self {
+                database: Database::with_vfs(fs, "/objects"),
+                refs: Vec::new(),
+                time: 1_600_000_000,
+            }
+        }
+
+        fn commit(&mut self, parents: &[ObjectId]) -> ObjectId {
+            self.time += 1;
+            let tree = self.database.store_raw("tree", b"").unwrap();
+            let author = Author::new(
+                "A".into(),
+                "a@example.com".into(),
+                Utc.timestamp(self.time, 0),
+            );
+            let commit = Commit::with_parents(parents.to_vec(), tree, author, "Change\n".into());
+            self.database.store(&commit).unwrap()
+        }
+
+        fn point(&mut self, name: &str, oid: ObjectId) {
+            self.refs.push(Ref {
+                name: name.to_owned(),
+                oid: Some(oid),
+                symref: None,
+            });
+        }
+
+        fn tag(&mut self, name: &str, oid: ObjectId) {
+            self.time += 1;
+            let tag = format!(
+                "object {}\ntype commit\ntag {}\ntagger A <a@example.com> {} +0000\n\n{}\n",
+                oid, name, self.time, name
+            );
+            let tag = self.database.store_raw("tag", tag.as_bytes()).unwrap();
+            self.point(&format!("refs/tags/{}", name), tag);
+        }
+
+        fn name(&self, options: &NameRevOptions, oid: &ObjectId) -> Option<String> {
+            let name_rev = NameRev::build(&self.database, &self.refs, options, &[*oid]).unwrap();
+            name_rev.name(oid)
+        }
+    }
+
+    #[test]
+    fn names_commits_after_the_refs_they_can_be_reached_from() {
+        let mut history = History::new();
+        let root = history.commit(&[]);
+        let first = history.commit(&[root]);
+        let second = history.commit(&[first]);
+        let side = history.commit(&[root]);
+        let merge = history.commit(&[second, side]);
+        let head = history.commit(&[merge]);
+        history.point("refs/heads/master", head);
+        history.tag("v1", first);
+
+        let options = NameRevOptions::default();
+        let name = |oid| history.name(&options, oid);
+        assert_eq!(name(&head).as_deref(), Some("master"));
+        assert_eq!(name(&second).as_deref(), Some("master~2"));
+        assert_eq!(name(&side).as_deref(), Some("master~1^2"));
+        assert_eq!(name(&first).as_deref(), Some("tags/v1^0"));
+        assert_eq!(name(&root).as_deref(), Some("tags/v1~1"));
+
+        let contains = NameRevOptions::contains();
+        assert_eq!(history.name(&contains, &root).as_deref(), Some("v1~1"));
+        assert_eq!(history.name(&contains, &side), None);
+
+        let options = NameRevOptions {
+            exclude: vec!["tags/*".to_owned()],
+            ..Default::default()
+        };
+        assert_eq!(history.name(&options, &root).as_deref(), Some("master~4"));
+        let options = NameRevOptions {
+            refs: vec!["v*".to_owned()],
+            ..Default::default()
+        };
+        assert_eq!(history.name(&options, &root).as_deref(), Some("v1~1"));
+    }
+
+    #[test]
+    fn prefers_older_tags() {
+        let mut history = History::new();
+        let root = history.commit(&[]);
+        let first = history.commit(&[root]);
+        history.tag("v1", first);
+        history.tag("v2", root);
+
+        let name = history.name(&NameRevOptions::contains(), &root);
+        assert_eq!(name.as_deref(), Some("v1~1"));
+    }
+}
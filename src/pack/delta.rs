@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+
+use super::PackError;
+use crate::Result;
+
+/// The length of the pieces of the base that are looked for in the target.
+const BLOCK_SIZE: usize = 16;
+/// The most that can be inserted by one instruction.
+const MAX_INSERT: usize = 0x7f;
+/// The most that can be copied by one instruction, in packs that older readers understand.
+const MAX_COPY: usize = 0x10000;
+
+/// Read a size from the start of a delta: little-endian, seven bits to a byte, with the high
+/// bit set on every byte but the last.
+fn read_size(delta: &[u8], pos: &mut usize) -> Result<usize> {
+    let mut size = 0;
+    let mut shift = 0;
+
+    loop {
+        let byte = *delta.get(*pos).ok_or(PackError::BadDelta)?;
+        *pos += 1;
+        size |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+
+        if byte & 0x80 == 0 {
+            return Ok(size);
+        }
+    }
+}
+
+/// Write a size as `read_size` reads it.
+fn write_size(delta: &mut Vec<u8>, mut size: usize) {
+    while size >= 0x80 {
+        delta.push(0x80 | (size & 0x7f) as u8);
+        size >>= 7;
+    }
+    delta.push(size as u8);
+}
+
+/// Make a delta that rebuilds `target` from `base`, or `None` if it would be bigger than
+/// `max_size`. Runs of the target that start with a block found in the base are copied from
+/// it, and everything else is inserted.
+pub fn create(base: &[u8], target: &[u8], max_size: usize) -> Option<Vec<u8>> {
+    let mut blocks = HashMap::new();
+    for start in (0..base.len().saturating_sub(BLOCK_SIZE - 1)).step_by(BLOCK_SIZE) {
+        blocks
+            .entry(&base[start..start + BLOCK_SIZE])
+            .or_insert(start);
+    }
+
+    let mut delta = Vec::new();
+    write_size(&mut delta, base.len());
+    write_size(&mut delta, target.len());
+
+    let mut insert: Vec<u8> = Vec::new();
+    let mut pos = 0;
+
+    while pos < target.len() {
+        let found = target
+            .get(pos..pos + BLOCK_SIZE)
+            .and_then(|block| blocks.get(block));
+
+        match found {
+            Some(&start) => {
+                // Extend the match backwards into what was going to be inserted, and then
+                // as far forwards as it goes.
+                let (mut start, mut from) = (start, pos);
+                while start > 0 && insert.last() == Some(&base[start - 1]) {
+                    insert.pop();
+                    start -= 1;
+                    from -= 1;
+                }
+                let mut length = pos + BLOCK_SIZE - from;
+                while start + length < base.len()
+                    && from + length < target.len()
+                    && base[start + length] == target[from + length]
+                {
+                    length += 1;
+                }
+
+                write_insert(&mut delta, &mut insert);
+                for copied in (0..length).step_by(MAX_COPY) {
+                    write_copy(&mut delta, start + copied, (length - copied).min(MAX_COPY));
+                }
+                pos = from + length;
+            }
+            None => {
+                insert.push(target[pos]);
+                if insert.len() == MAX_INSERT {
+                    write_insert(&mut delta, &mut insert);
+                }
+                pos += 1;
+            }
+        }
+
+        if delta.len() > max_size {
+            return None;
+        }
+    }
+
+    write_insert(&mut delta, &mut insert);
+    Some(delta).filter(|delta| delta.len() <= max_size)
+}
+
+fn write_insert(delta: &mut Vec<u8>, insert: &mut Vec<u8>) {
+    if !insert.is_empty() {
+        delta.push(insert.len() as u8);
+        delta.append(insert);
+    }
+}
+
+fn write_copy(delta: &mut Vec<u8>, offset: usize, size: usize) {
+    let op = delta.len();
+    delta.push(0x80);
+
+    for (i, byte) in offset.to_le_bytes()[..4].iter().enumerate() {
+        if *byte != 0 {
+            delta[op] |= 1 << i;
+            delta.push(*byte);
+        }
+    }
+    for (i, byte) in size.to_le_bytes()[..3].iter().enumerate() {
+        if *byte != 0 {
+            delta[op] |= 1 << (4 + i);
+            delta.push(*byte);
+        }
+    }
+}
+
+/// Rebuild an object from its base and a delta: a list of instructions to either copy a
+/// range of the base or insert new bytes.
+pub fn apply(base: &[u8], delta: &[u8]) -> Result<Vec<u8>> {
+    let mut pos = 0;
+    let source_size = read_size(delta, &mut pos)?;
+    let target_size = read_size(delta, &mut pos)?;
+    if source_size != base.len() {
+        return Err(PackError::BadDelta.into());
+    }
+
+    let mut target = Vec::with_capacity(target_size);
+
+    while let Some(&op) = delta.get(pos) {
+        pos += 1;
+
+        if op & 0x80 != 0 {
+            // Which of the offset and size bytes are present is given by the low seven bits.
+            let mut fields = [0usize; 2];
+            for (bit, field, shift) in (0..7).map(|bit| (bit, bit / 4, (bit % 4) * 8)) {
+                if op & (1 << bit) != 0 {
+                    let byte = *delta.get(pos).ok_or(PackError::BadDelta)?;
+                    pos += 1;
+                    fields[field] |= (byte as usize) << shift;
+                }
+            }
+
+            let [offset, size] = fields;
+            let size = if size == 0 { 0x10000 } else { size };
+            let copy = base.get(offset..offset + size).ok_or(PackError::BadDelta)?;
+            target.extend_from_slice(copy);
+        } else if op != 0 {
+            let insert = delta
+                .get(pos..pos + op as usize)
+                .ok_or(PackError::BadDelta)?;
+            target.extend_from_slice(insert);
+            pos += op as usize;
+        } else {
+            return Err(PackError::BadDelta.into());
+        }
+    }
+
+    if target.len() != target_size {
+        return Err(PackError::BadDelta.into());
+    }
+
+    Ok(target)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn applies_copies_and_inserts() {
+        let base = b"the quick brown fox";
+        // Sizes 19 and 17, copy 10 bytes from offset 0, insert "red", copy 4 bytes from 15.
+        let delta = [19, 17, 0x90, 10, 3, b'r', b'e', b'd', 0x91, 15, 4];
+
+        assert_eq!(apply(base, &delta).unwrap(), b"the quick red fox".to_vec());
+        assert!(apply(b"short", &delta).is_err());
+    }
+
+    #[test]
+    fn creates_deltas() {
+        let base: Vec<u8> = (1..=200)
+            .flat_map(|n| format!("line {}\n", n).into_bytes())
+            .collect();
+        let mut target = b"a new first line\n".to_vec();
+        target.extend_from_slice(&base[..500]);
+        target.extend_from_slice(b"something in the middle\n");
+        target.extend_from_slice(&base[700..]);
+
+        let delta = create(&base, &target, usize::MAX).unwrap();
+        assert!(delta.len() < 100);
+        assert_eq!(apply(&base, &delta).unwrap(), target);
+
+        assert_eq!(create(&base, &target, 10), None);
+        let unrelated = create(b"nothing alike", b"in these two", usize::MAX).unwrap();
+        assert_eq!(
+            apply(b"nothing alike", &unrelated).unwrap(),
+            b"in these two"
+        );
+    }
+}
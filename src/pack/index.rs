@@ -0,0 +1,214 @@
+use std::convert::{TryFrom, TryInto};
+
+use sha1::{Digest, Sha1};
+
+use super::{Pack, PackError, CHECKSUM_SIZE};
+use crate::database::ObjectId;
+use crate::Result;
+
+const INDEX_SIGNATURE: [u8; 4] = [0xff, 0x74, 0x4f, 0x63];
+const INDEX_VERSION: u32 = 2;
+const FANOUT_SIZE: usize = 256 * 4;
+/// Offsets that don't fit in 31 bits are kept in a table of their own, and the 4-byte
+/// offset has its top bit set and holds the position in that table instead.
+const LARGE_OFFSET: u32 = 0x8000_0000;
+
+/// Where to find an object in a pack.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexEntry {
+    pub oid: ObjectId,
+    pub crc32: u32,
+    pub offset: u64,
+}
+
+/// A version 2 `.idx` file: every object in a pack, sorted by ObjectId.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PackIndex {
+    entries: Vec<IndexEntry>,
+    pack_checksum: ObjectId,
+}
+
+impl PackIndex {
+    pub fn new(pack: &Pack) -> Self {
+        let mut entries: Vec<_> = pack
+            .objects()
+            .iter()
+            .map(|object| IndexEntry {
+                oid: object.oid,
+                crc32: object.crc32,
+                offset: object.offset as u64,
+            })
+            .collect();
+        entries.sort_by(|a, b| a.oid.bytes().cmp(b.oid.bytes()));
+
+        Self {
+            entries,
+            pack_checksum: *pack.checksum(),
+        }
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader { bytes, pos: 0 };
+
+        if reader.take(4)? != INDEX_SIGNATURE {
+            return Err(PackError::BadIndex.into());
+        }
+        let version = reader.u32()?;
+        if version != INDEX_VERSION {
+            return Err(PackError::UnsupportedIndexVersion(version).into());
+        }
+
+        let end = bytes
+            .len()
+            .checked_sub(CHECKSUM_SIZE)
+            .ok_or(PackError::BadIndex)?;
+        if Sha1::digest(&bytes[..end])[..] != bytes[end..] {
+            return Err(PackError::BadIndex.into());
+        }
+
+        reader.take(FANOUT_SIZE - 4)?;
+        let count = reader.u32()? as usize;
+
+        let oids = (0..count)
+            .map(|_| Ok(ObjectId::from(reader.oid()?)))
+            .collect::<Result<Vec<_>>>()?;
+        let crcs = (0..count)
+            .map(|_| reader.u32())
+            .collect::<Result<Vec<_>>>()?;
+        let offsets = (0..count)
+            .map(|_| reader.u32())
+            .collect::<Result<Vec<_>>>()?;
+        let large = offsets.iter().filter(|&&o| o & LARGE_OFFSET != 0).count();
+        let large_offsets = (0..large)
+            .map(|_| Ok(u64::from_be_bytes(reader.take(8)?.try_into().unwrap())))
+            .collect::<Result<Vec<_>>>()?;
+        let pack_checksum = ObjectId::from(reader.oid()?);
+
+        let entries = oids
+            .into_iter()
+            .zip(crcs)
+            .zip(offsets)
+            .map(|((oid, crc32), offset)| {
+                let offset = match offset & LARGE_OFFSET {
+                    0 => offset as u64,
+                    _ => *large_offsets
+                        .get((offset & !LARGE_OFFSET) as usize)
+                        .ok_or(PackError::BadIndex)?,
+                };
+                Ok(IndexEntry { oid, crc32, offset })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            entries,
+            pack_checksum,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&INDEX_SIGNATURE);
+        bytes.extend_from_slice(&INDEX_VERSION.to_be_bytes());
+
+        // Entry n of the fanout table counts the objects whose first byte is at most n.
+        let mut fanout = [0u32; 256];
+        for entry in &self.entries {
+            fanout[entry.oid.bytes()[0] as usize] += 1;
+        }
+        let mut total = 0;
+        for count in fanout.iter_mut() {
+            total += *count;
+            bytes.extend_from_slice(&total.to_be_bytes());
+        }
+
+        for entry in &self.entries {
+            bytes.extend_from_slice(entry.oid.bytes());
+        }
+        for entry in &self.entries {
+            bytes.extend_from_slice(&entry.crc32.to_be_bytes());
+        }
+
+        let mut large_offsets = Vec::new();
+        for entry in &self.entries {
+            let offset = match u32::try_from(entry.offset) {
+                Ok(offset) if offset & LARGE_OFFSET == 0 => offset,
+                _ => {
+                    large_offsets.push(entry.offset);
+                    LARGE_OFFSET | (large_offsets.len() - 1) as u32
+                }
+            };
+            bytes.extend_from_slice(&offset.to_be_bytes());
+        }
+        for offset in large_offsets {
+            bytes.extend_from_slice(&offset.to_be_bytes());
+        }
+
+        bytes.extend_from_slice(self.pack_checksum.bytes());
+        let checksum = Sha1::digest(&bytes);
+        bytes.extend_from_slice(&checksum);
+        bytes
+    }
+
+    /// The objects in the pack, sorted by ObjectId.
+    pub fn entries(&self) -> &[IndexEntry] {
+        &self.entries
+    }
+
+    /// The checksum of the pack this indexes.
+    pub fn pack_checksum(&self) -> &ObjectId {
+        &self.pack_checksum
+    }
+
+    /// Check that this is the index of the given pack.
+    pub fn verify(&self, pack: &Pack) -> Result<()> {
+        let expected = PackIndex::new(pack);
+
+        if self.pack_checksum != expected.pack_checksum {
+            return Err(PackError::IndexMismatch("pack checksums differ".to_owned()).into());
+        }
+        if self.entries.len() != expected.entries.len() {
+            return Err(PackError::IndexMismatch("object counts differ".to_owned()).into());
+        }
+        let mismatch = self
+            .entries
+            .iter()
+            .zip(&expected.entries)
+            .find(|(ours, theirs)| ours != theirs);
+        if let Some((_, entry)) = mismatch {
+            return Err(PackError::IndexMismatch(format!("bad entry for {}", entry.oid)).into());
+        }
+
+        Ok(())
+    }
+
+    pub fn find(&self, oid: &ObjectId) -> Option<&IndexEntry> {
+        self.entries
+            .binary_search_by(|entry| entry.oid.bytes().cmp(oid.bytes()))
+            .ok()
+            .map(|i| &self.entries[i])
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, size: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .bytes
+            .get(self.pos..self.pos + size)
+            .ok_or(PackError::BadIndex)?;
+        self.pos += size;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn oid(&mut self) -> Result<[u8; 20]> {
+        Ok(self.take(20)?.try_into().unwrap())
+    }
+}
@@ -0,0 +1,130 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    path::Path,
+    sync::Mutex,
+};
+
+use super::{delta, inflate, read_header, ObjectType, PackError, PackIndex, RecordKind};
+use crate::vfs::{FileData, Vfs};
+use crate::Result;
+
+/// How many bytes of delta bases each pack keeps around once they've been resolved.
+const BASE_CACHE_LIMIT: usize = 16 * 1024 * 1024;
+
+/// The longest chain of deltas that's followed before the pack is taken to be corrupt, as
+/// a delta can name itself as its base, or one further up its chain. git never writes a
+/// chain longer than 4095.
+const MAX_DELTA_DEPTH: usize = 10_000;
+
+/// A pack mapped into memory along with its index, for reading objects out of it one at a
+/// time. Objects that deltas are made against are cached by their offset, so walking
+/// history doesn't inflate the same bases over and over.
+pub struct MappedPack {
+    index: PackIndex,
+    data: FileData,
+    bases: Mutex<BaseCache>,
+}
+
+impl MappedPack {
+    /// Map the pack that goes with the index at `index_path`.
+    pub fn open<F: Vfs>(fs: &F, index_path: &Path) -> Result<Self> {
+        let index = PackIndex::parse(&fs.read(index_path)?)?;
+        let data = fs.read_shared(&index_path.with_extension("pack"))?;
+
+        Ok(Self {
+            index,
+            data,
+            bases: Mutex::new(BaseCache::default()),
+        })
+    }
+
+    pub fn index(&self) -> &PackIndex {
+        &self.index
+    }
+
+    /// Read the object at an offset in the pack, applying any deltas it's stored as.
+    pub fn read_object(&self, offset: u64) -> Result<(ObjectType, Vec<u8>)> {
+        let mut deltas = Vec::new();
+        let mut offset = offset as usize;
+        let start = offset;
+
+        // Follow the chain of deltas down to a whole object, or a base that's cached.
+        let (kind, mut data) = loop {
+            if !deltas.is_empty() {
+                if let Some(base) = self.bases.lock().unwrap().get(offset) {
+                    break base;
+                }
+            }
+
+            let bytes = self.data.get(offset..).ok_or(PackError::Truncated)?;
+            let (kind, size, header_size) = read_header(bytes, offset)?;
+            let (data, _) = inflate(&bytes[header_size..], size, offset)?;
+
+            let base_offset = match kind {
+                RecordKind::Object(kind) => {
+                    if !deltas.is_empty() {
+                        self.bases.lock().unwrap().insert(offset, kind, &data);
+                    }
+                    break (kind, data);
+                }
+                RecordKind::OffsetDelta(base) => base,
+                RecordKind::RefDelta(oid) => {
+                    self.index
+                        .find(&oid)
+                        .ok_or(PackError::MissingBase(offset))?
+                        .offset as usize
+                }
+            };
+            if deltas.len() == MAX_DELTA_DEPTH {
+                return Err(PackError::CorruptObject(start).into());
+            }
+            deltas.push((offset, data));
+            offset = base_offset;
+        };
+
+        // Every object but the last is the base of the next delta up the chain.
+        while let Some((offset, delta)) = deltas.pop() {
+            data = delta::apply(&data, &delta)?;
+            if !deltas.is_empty() {
+                self.bases.lock().unwrap().insert(offset, kind, &data);
+            }
+        }
+
+        Ok((kind, data))
+    }
+}
+
+/// Resolved objects keyed by their offset in the pack. Once the cache is over its limit,
+/// the objects that were added first are dropped.
+#[derive(Default)]
+struct BaseCache {
+    objects: HashMap<usize, (ObjectType, Vec<u8>)>,
+    order: VecDeque<usize>,
+    size: usize,
+}
+
+impl BaseCache {
+    fn get(&self, offset: usize) -> Option<(ObjectType, Vec<u8>)> {
+        self.objects.get(&offset).cloned()
+    }
+
+    fn insert(&mut self, offset: usize, kind: ObjectType, data: &[u8]) {
+        if data.len() > BASE_CACHE_LIMIT || self.objects.contains_key(&offset) {
+            return;
+        }
+
+        while self.size + data.len() > BASE_CACHE_LIMIT {
+            let oldest = match self.order.pop_front() {
+                Some(oldest) => oldest,
+                None => break,
+            };
+            if let Some((_, evicted)) = self.objects.remove(&oldest) {
+                self.size -= evicted.len();
+            }
+        }
+
+        self.size += data.len();
+        self.order.push_back(offset);
+        self.objects.insert(offset, (kind, data.to_vec()));
+    }
+}
@@ -0,0 +1,98 @@
+use std::{io, ops::Deref, path::Path};
+
+#[cfg(unix)]
+use std::{fs::File, os::unix::io::AsRawFd, ptr, slice};
+
+/// A whole file mapped read-only into memory. Packs are never changed once written, so
+/// the mapping stays valid for as long as it's held, even if the file is deleted.
+#[cfg(unix)]
+pub struct Mmap {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+// The mapping is private and read-only, so nothing can change it from another thread.
+#[cfg(unix)]
+unsafe impl Send for Mmap {}
+#[cfg(unix)]
+unsafe impl Sync for Mmap {}
+
+#[cfg(unix)]
+impl Mmap {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len() as usize;
+
+        // Mapping nothing is an error, but an empty file has nothing to map anyway.
+        if len == 0 {
+            return Ok(Self {
+                ptr: ptr::null_mut(),
+                len,
+            });
+        }
+
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self { ptr, len })
+    }
+}
+
+#[cfg(unix)]
+impl Deref for Mmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        if self.len == 0 {
+            return &[];
+        }
+
+        unsafe { slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            unsafe {
+                libc::munmap(self.ptr, self.len);
+            }
+        }
+    }
+}
+
+/// WASI has no mmap, so there the whole file is read into memory instead.
+#[cfg(target_os = "wasi")]
+pub struct Mmap {
+    data: Vec<u8>,
+}
+
+#[cfg(target_os = "wasi")]
+impl Mmap {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            data: std::fs::read(path)?,
+        })
+    }
+}
+
+#[cfg(target_os = "wasi")]
+impl Deref for Mmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data
+    }
+}
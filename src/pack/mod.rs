@@ -0,0 +1,604 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    io::{BufRead, Read},
+};
+
+use flate2::bufread::ZlibDecoder;
+use sha1::{Digest, Sha1};
+use thiserror::Error;
+
+use crate::database::{DatabaseError, ObjectId};
+use crate::hash::Hasher;
+use crate::Result;
+
+pub mod delta;
+mod index;
+mod mapped;
+mod mmap;
+mod writer;
+
+pub use index::*;
+pub use mapped::*;
+pub use writer::*;
+
+pub(crate) use mmap::Mmap;
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum PackError {
+    #[error("not a pack file: bad signature")]
+    BadSignature,
+    #[error("pack version {0} is not supported")]
+    UnsupportedVersion(u32),
+    #[error("pack file is truncated")]
+    Truncated,
+    #[error("pack has junk at the end")]
+    TrailingData,
+    #[error("pack checksum does not match its contents")]
+    BadChecksum,
+    #[error("unknown object type {1} at offset {0}")]
+    BadObjectType(usize, u8),
+    #[error("corrupt object at offset {0}")]
+    CorruptObject(usize),
+    #[error("invalid delta")]
+    BadDelta,
+    #[error("cannot find the base of the delta at offset {0}")]
+    MissingBase(usize),
+    #[error("not a pack index file")]
+    BadIndex,
+    #[error("pack index version {0} is not supported")]
+    UnsupportedIndexVersion(u32),
+    #[error("pack index does not match the pack: {0}")]
+    IndexMismatch(String),
+}
+
+const SIGNATURE: &[u8; 4] = b"PACK";
+const HEADER_SIZE: usize = 12;
+const CHECKSUM_SIZE: usize = 20;
+
+/// The kinds of object a pack can hold, once any deltas have been resolved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectType {
+    Commit,
+    Tree,
+    Blob,
+    Tag,
+}
+
+impl ObjectType {
+    fn from_code(code: u8) -> Option<Self> {
+        match code {
+            1 => Some(ObjectType::Commit),
+            2 => Some(ObjectType::Tree),
+            3 => Some(ObjectType::Blob),
+            4 => Some(ObjectType::Tag),
+            _ => None,
+        }
+    }
+
+    fn code(self) -> u8 {
+        match self {
+            ObjectType::Commit => 1,
+            ObjectType::Tree => 2,
+            ObjectType::Blob => 3,
+            ObjectType::Tag => 4,
+        }
+    }
+
+    /// The type with the given name, as it appears in a loose object's header.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "commit" => Some(ObjectType::Commit),
+            "tree" => Some(ObjectType::Tree),
+            "blob" => Some(ObjectType::Blob),
+            "tag" => Some(ObjectType::Tag),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ObjectType::Commit => "commit",
+            ObjectType::Tree => "tree",
+            ObjectType::Blob => "blob",
+            ObjectType::Tag => "tag",
+        }
+    }
+}
+
+impl Display for ObjectType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(self.name())
+    }
+}
+
+/// What a record in a pack holds: either a whole object, or a delta against another object
+/// found by its offset in the pack or by its ObjectId.
+#[derive(Debug, Clone, PartialEq)]
+enum RecordKind {
+    Object(ObjectType),
+    OffsetDelta(usize),
+    RefDelta(ObjectId),
+}
+
+/// A record as it appears in the pack, before any delta is applied.
+struct Record {
+    kind: RecordKind,
+    offset: usize,
+    size: usize,
+    packed_size: usize,
+    crc32: u32,
+    data: Vec<u8>,
+}
+
+/// An object read from a pack.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PackedObject {
+    pub oid: ObjectId,
+    pub kind: ObjectType,
+    /// The size given in the record's header: the size of the delta, for a deltified object.
+    pub size: usize,
+    pub offset: usize,
+    /// How many bytes the record takes up in the pack, including its header.
+    pub packed_size: usize,
+    pub crc32: u32,
+    /// How many deltas have to be applied to get the object, and the object the last was
+    /// made against.
+    pub depth: usize,
+    pub base: Option<ObjectId>,
+    pub data: Vec<u8>,
+}
+
+/// An object's type and contents.
+type RawObject = (ObjectType, Vec<u8>);
+
+/// Finds an object outside a pack, for resolving the deltas in a thin pack.
+pub type BaseLookup<'a> = dyn Fn(&ObjectId) -> Result<Option<RawObject>> + 'a;
+
+/// The contents of a pack file, with every delta resolved.
+#[derive(Debug, Clone)]
+pub struct Pack {
+    objects: Vec<PackedObject>,
+    checksum: ObjectId,
+}
+
+impl Pack {
+    /// Read a whole pack, checking its checksum and the size of every object in it.
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let (objects, checksum, _) = Self::parse_with_bases(bytes, &|_| Ok(None))?;
+        Ok(Self { objects, checksum })
+    }
+
+    /// Complete a thin pack, whose deltas can be made against objects that aren't in it, by
+    /// looking up those bases with `lookup` and appending them to the pack. Returns the
+    /// completed pack's bytes, which are the same as those given if nothing was missing,
+    /// along with the pack as they're read.
+    pub fn fix_thin(bytes: &[u8], lookup: &BaseLookup<'_>) -> Result<(Vec<u8>, Self)> {
+        let (_, _, external) = Self::parse_with_bases(bytes, lookup)?;
+        if external.is_empty() {
+            return Ok((bytes.to_vec(), Self::parse(bytes)?));
+        }
+
+        let end = bytes.len() - CHECKSUM_SIZE;
+        let mut fixed = bytes[..end].to_vec();
+        let count = u32::from_be_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]);
+        fixed[8..12].copy_from_slice(&(count + external.len() as u32).to_be_bytes());
+        for (kind, data) in external {
+            writer::write_header(&mut fixed, kind.code(), data.len());
+            fixed.extend_from_slice(&writer::compress(&data));
+        }
+        let checksum = Sha1::digest(&fixed);
+        fixed.extend_from_slice(&checksum);
+
+        let pack = Self::parse(&fixed)?;
+        Ok((fixed, pack))
+    }
+
+    /// Read a pack, resolving deltas against objects that aren't in it with `lookup`.
+    /// Returns the objects in the pack, its checksum, and the bases that had to be looked
+    /// up.
+    fn parse_with_bases(
+        bytes: &[u8],
+        lookup: &BaseLookup<'_>,
+    ) -> Result<(Vec<PackedObject>, ObjectId, Vec<RawObject>)> {
+        if bytes.len() < HEADER_SIZE + CHECKSUM_SIZE {
+            return Err(PackError::Truncated.into());
+        }
+        if &bytes[0..4] != SIGNATURE {
+            return Err(PackError::BadSignature.into());
+        }
+        let version = u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+        if version != 2 && version != 3 {
+            return Err(PackError::UnsupportedVersion(version).into());
+        }
+        let count = u32::from_be_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]) as usize;
+
+        let end = bytes.len() - CHECKSUM_SIZE;
+        let checksum: [u8; 20] = Sha1::digest(&bytes[..end]).into();
+        if checksum[..] != bytes[end..] {
+            return Err(PackError::BadChecksum.into());
+        }
+
+        let mut records = Vec::with_capacity(count);
+        let mut pos = HEADER_SIZE;
+        for _ in 0..count {
+            let record = read_record(&bytes[..end], pos)?;
+            pos += record.packed_size;
+            records.push(record);
+        }
+        if pos != end {
+            return Err(PackError::TrailingData.into());
+        }
+
+        let (objects, external) = resolve(records, lookup)?;
+        Ok((objects, ObjectId::from(checksum), external))
+    }
+
+    /// The objects in the order they appear in the pack.
+    pub fn objects(&self) -> &[PackedObject] {
+        &self.objects
+    }
+
+    /// The checksum at the end of the pack, which packs are named after.
+    pub fn checksum(&self) -> &ObjectId {
+        &self.checksum
+    }
+}
+
+/// Work out an object's ObjectId from its type and contents, refusing one that holds a
+/// SHA-1 collision attack.
+pub fn hash_object(kind: ObjectType, data: &[u8]) -> Result<ObjectId> {
+    let mut hasher = Hasher::new();
+    hasher.update(format!("{} {}\0", kind, data.len()));
+    hasher.update(data);
+    let hash = hasher
+        .finalize()
+        .map_err(|_| DatabaseError::Collision(kind.to_string()))?;
+    Ok(ObjectId::from(hash))
+}
+
+fn read_record(bytes: &[u8], offset: usize) -> Result<Record> {
+    let (kind, size, header_size) = read_header(&bytes[offset..], offset)?;
+    let (data, packed_data_size) = inflate(&bytes[offset + header_size..], size, offset)?;
+
+    let end = offset + header_size + packed_data_size;
+    let mut crc32 = crc32fast::Hasher::new();
+    crc32.update(&bytes[offset..end]);
+
+    Ok(Record {
+        kind,
+        offset,
+        size,
+        packed_size: end - offset,
+        crc32: crc32.finalize(),
+        data,
+    })
+}
+
+/// Read the header of the record at the start of `bytes`, which is at `offset` in the pack.
+/// Returns what the record holds, the size of its data once inflated, and the length of the
+/// header.
+fn read_header(bytes: &[u8], offset: usize) -> Result<(RecordKind, usize, usize)> {
+    let mut pos = 0;
+
+    // The type is in bits 4-6 of the first byte, and the size is a little-endian number
+    // made of the rest of it and seven bits of each byte that follows.
+    let mut byte = read_byte(bytes, &mut pos)?;
+    let code = (byte >> 4) & 0x7;
+    let mut size = (byte & 0xf) as usize;
+    let mut shift = 4;
+    while byte & 0x80 != 0 {
+        byte = read_byte(bytes, &mut pos)?;
+        size |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+    }
+
+    let kind = match code {
+        6 => {
+            // A big-endian number of seven-bit bytes, where every byte but the last also
+            // adds one, so that each length of encoding covers a new range of distances.
+            byte = read_byte(bytes, &mut pos)?;
+            let mut distance = (byte & 0x7f) as usize;
+            while byte & 0x80 != 0 {
+                byte = read_byte(bytes, &mut pos)?;
+                distance = ((distance + 1) << 7) | (byte & 0x7f) as usize;
+            }
+            let base = offset
+                .checked_sub(distance)
+                .ok_or(PackError::CorruptObject(offset))?;
+            RecordKind::OffsetDelta(base)
+        }
+        7 => {
+            let mut oid = [0; 20];
+            for byte in oid.iter_mut() {
+                *byte = read_byte(bytes, &mut pos)?;
+            }
+            RecordKind::RefDelta(ObjectId::from(oid))
+        }
+        _ => RecordKind::Object(
+            ObjectType::from_code(code).ok_or(PackError::BadObjectType(offset, code))?,
+        ),
+    };
+
+    Ok((kind, size, pos))
+}
+
+/// Inflate a record's data, checking it has the size its header gave. Returns the data and
+/// how many compressed bytes were read.
+fn inflate(reader: impl BufRead, size: usize, offset: usize) -> Result<(Vec<u8>, usize)> {
+    let mut decoder = ZlibDecoder::new(reader);
+    let mut data = Vec::with_capacity(size);
+    decoder
+        .read_to_end(&mut data)
+        .map_err(|_| PackError::CorruptObject(offset))?;
+    if data.len() != size {
+        return Err(PackError::CorruptObject(offset).into());
+    }
+
+    Ok((data, decoder.total_in() as usize))
+}
+
+fn read_byte(bytes: &[u8], pos: &mut usize) -> Result<u8> {
+    let byte = *bytes.get(*pos).ok_or(PackError::Truncated)?;
+    *pos += 1;
+    Ok(byte)
+}
+
+/// Apply every delta in the pack. Bases can appear after the deltas made against them, so
+/// this keeps going over the unresolved records until no more can be resolved. Only then
+/// are bases that aren't in the pack looked up, and any that are found are returned too.
+fn resolve(
+    records: Vec<Record>,
+    lookup: &BaseLookup<'_>,
+) -> Result<(Vec<PackedObject>, Vec<RawObject>)> {
+    let mut objects: Vec<Option<PackedObject>> = vec![None; records.len()];
+    let mut by_offset: HashMap<usize, usize> = HashMap::new();
+    let mut by_oid: HashMap<ObjectId, usize> = HashMap::new();
+    let mut external: HashMap<ObjectId, RawObject> = HashMap::new();
+    let mut external_order = Vec::new();
+    let mut remaining = records.len();
+
+    while remaining > 0 {
+        let before = remaining;
+
+        for (i, record) in records.iter().enumerate() {
+            if objects[i].is_some() {
+                continue;
+            }
+
+            let base = match &record.kind {
+                RecordKind::Object(_) => None,
+                RecordKind::OffsetDelta(offset) => match by_offset.get(offset) {
+                    Some(&base) => Some(base),
+                    None => continue,
+                },
+                RecordKind::RefDelta(oid) => match by_oid.get(oid) {
+                    Some(&base) => Some(base),
+                    None if external.contains_key(oid) => None,
+                    None => continue,
+                },
+            };
+
+            let (kind, data, depth, base) = match (&record.kind, base) {
+                (RecordKind::Object(kind), _) => (*kind, record.data.clone(), 0, None),
+                (_, Some(base)) => {
+                    let base: &PackedObject = objects[base].as_ref().unwrap();
+                    let data = delta::apply(&base.data, &record.data)?;
+                    (base.kind, data, base.depth + 1, Some(base.oid))
+                }
+                (RecordKind::RefDelta(oid), None) => {
+                    let (kind, base) = &external[oid];
+                    (*kind, delta::apply(base, &record.data)?, 1, Some(*oid))
+                }
+                (_, None) => unreachable!(),
+            };
+
+            let oid = hash_object(kind, &data)?;
+            by_offset.insert(record.offset, i);
+            by_oid.insert(oid, i);
+            objects[i] = Some(PackedObject {
+                oid,
+                kind,
+                size: record.size,
+                offset: record.offset,
+                packed_size: record.packed_size,
+                crc32: record.crc32,
+                depth,
+                base,
+                data,
+            });
+            remaining -= 1;
+        }
+
+        if remaining == before {
+            // Nothing more can be resolved from inside the pack, so look outside it for
+            // the base of one of the deltas that are left.
+            let unresolved: Vec<_> = records
+                .iter()
+                .zip(&objects)
+                .filter(|(_, object)| object.is_none())
+                .map(|(record, _)| record)
+                .collect();
+            let mut found = None;
+            for record in &unresolved {
+                if let RecordKind::RefDelta(oid) = &record.kind {
+                    if let Some(base) = lookup(oid)? {
+                        found = Some((*oid, base));
+                        break;
+                    }
+                }
+            }
+
+            match found {
+                Some((oid, base)) => {
+                    external.insert(oid, base);
+                    external_order.push(oid);
+                }
+                None => return Err(PackError::MissingBase(unresolved[0].offset).into()),
+            }
+        }
+    }
+
+    let external = external_order
+        .into_iter()
+        .map(|oid| external.remove(&oid).unwrap())
+        .collect();
+    Ok((objects.into_iter().flatten().collect(), external))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::vfs::{MemoryFs, Vfs};
+    use flate2::{write::ZlibEncoder, Compression};
+    use std::io::Write;
+
+    fn compress(data: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// A pack holding the blob "hello world\n" and a delta turning it into "hello there\n".
+    fn build_pack() -> Vec<u8> {
+        let mut pack = Vec::new();
+        pack.extend_from_slice(b"PACK");
+        pack.extend_from_slice(&2u32.to_be_bytes());
+        pack.extend_from_slice(&2u32.to_be_bytes());
+
+        let blob = b"hello world\n";
+        pack.push(0x30 | blob.len() as u8);
+        pack.extend_from_slice(&compress(blob));
+
+        let delta_offset = pack.len();
+        let delta = [12, 12, 0x90, 6, 6, b't', b'h', b'e', b'r', b'e', b'\n'];
+        pack.push(0x60 | delta.len() as u8);
+        pack.push((delta_offset - HEADER_SIZE) as u8);
+        pack.extend_from_slice(&compress(&delta));
+
+        let checksum = Sha1::digest(&pack);
+        pack.extend_from_slice(&checksum);
+        pack
+    }
+
+    #[test]
+    fn reads_packs_and_builds_indexes() {
+        let bytes = build_pack();
+        let pack = Pack::parse(&bytes).unwrap();
+
+        let objects = pack.objects();
+        assert_eq!(objects.len(), 2);
+        assert_eq!(
+            objects[0].oid.to_string(),
+            "3b18e512dba79e4c8300dd08aeb37f8e728b8dad"
+        );
+        assert_eq!(objects[1].data, b"hello there\n");
+        assert_eq!(objects[1].kind, ObjectType::Blob);
+        assert_eq!(objects[1].depth, 1);
+        assert_eq!(objects[1].base.as_ref(), Some(&objects[0].oid));
+
+        let index = PackIndex::new(&pack);
+        let parsed = PackIndex::parse(&index.to_bytes()).unwrap();
+        assert_eq!(parsed, index);
+        assert_eq!(
+            parsed.find(&objects[1].oid).unwrap().offset,
+            objects[1].offset as u64
+        );
+
+        let written = write_pack(
+            &objects
+                .iter()
+                .map(|object| PackEntry {
+                    oid: object.oid,
+                    kind: object.kind,
+                    data: object.data.clone(),
+                    name: String::new(),
+                })
+                .collect::<Vec<_>>(),
+            &PackOptions::default(),
+        );
+        let rewritten = Pack::parse(&written).unwrap();
+        assert_eq!(rewritten.objects().len(), 2);
+        assert_eq!(rewritten.objects()[1].data, b"hello there\n");
+
+        let fs = MemoryFs::new();
+        let dir = std::path::Path::new("/");
+        fs.write(&dir.join("test.pack"), &written).unwrap();
+        fs.write(
+            &dir.join("test.idx"),
+            &PackIndex::new(&rewritten).to_bytes(),
+        )
+        .unwrap();
+        let mapped = MappedPack::open(&fs, &dir.join("test.idx")).unwrap();
+        let offset = mapped.index().find(&objects[1].oid).unwrap().offset;
+        for _ in 0..2 {
+            assert_eq!(
+                mapped.read_object(offset).unwrap(),
+                (ObjectType::Blob, b"hello there\n".to_vec())
+            );
+        }
+
+        let mut corrupt = bytes;
+        corrupt[20] ^= 1;
+        assert!(Pack::parse(&corrupt).is_err());
+    }
+
+    #[test]
+    fn gives_up_on_delta_chains_that_never_end() {
+        // Make the delta's base the delta itself.
+        let bytes = build_pack();
+        let pack = Pack::parse(&bytes).unwrap();
+        let delta_offset = pack.objects()[1].offset;
+        let mut looped = bytes;
+        looped[delta_offset + 1] = 0;
+
+        let fs = MemoryFs::new();
+        let dir = std::path::Path::new("/");
+        fs.write(&dir.join("looped.pack"), &looped).unwrap();
+        fs.write(&dir.join("looped.idx"), &PackIndex::new(&pack).to_bytes())
+            .unwrap();
+        let mapped = MappedPack::open(&fs, &dir.join("looped.idx")).unwrap();
+        assert!(matches!(
+            mapped.read_object(delta_offset as u64),
+            Err(crate::Error::Pack(PackError::CorruptObject(offset))) if offset == delta_offset
+        ));
+    }
+
+    #[test]
+    fn completes_thin_packs() {
+        let entry = |data: &[u8]| PackEntry {
+            oid: hash_object(ObjectType::Blob, data).unwrap(),
+            kind: ObjectType::Blob,
+            data: data.to_vec(),
+            name: "greeting.txt".to_owned(),
+        };
+        let old = entry("hello world\n".repeat(20).as_bytes());
+        let new = entry(format!("{}hello there\n", "hello world\n".repeat(20)).as_bytes());
+
+        let thin = write_thin_pack(
+            std::slice::from_ref(&new),
+            std::slice::from_ref(&old),
+            &PackOptions::default(),
+        );
+        assert!(Pack::parse(&thin).is_err());
+        assert!(Pack::fix_thin(&thin, &|_| Ok(None)).is_err());
+
+        let lookup = |oid: &ObjectId| {
+            Ok(Some(oid)
+                .filter(|oid| **oid == old.oid)
+                .map(|_| (old.kind, old.data.clone())))
+        };
+        let (fixed, pack) = Pack::fix_thin(&thin, &lookup).unwrap();
+        assert_eq!(pack.objects().len(), 2);
+        assert_eq!(pack.objects()[0].oid, new.oid);
+        assert_eq!(pack.objects()[0].data, new.data);
+        assert_eq!(pack.objects()[0].base, Some(old.oid));
+        assert_eq!(pack.objects()[1].oid, old.oid);
+        assert_eq!(Pack::parse(&fixed).unwrap().checksum(), pack.checksum());
+
+        let complete = write_pack(&[new], &PackOptions::default());
+        let (unchanged, _) = Pack::fix_thin(&complete, &lookup).unwrap();
+        assert_eq!(unchanged, complete);
+    }
+}
@@ -0,0 +1,186 @@
+use std::{cmp::Reverse, io::Write};
+
+use flate2::{write::ZlibEncoder, Compression};
+use sha1::{Digest, Sha1};
+
+use super::{delta, ObjectType, SIGNATURE};
+use crate::database::ObjectId;
+
+/// An object to be written to a pack.
+#[derive(Debug, Clone)]
+pub struct PackEntry {
+    pub oid: ObjectId,
+    pub kind: ObjectType,
+    pub data: Vec<u8>,
+    /// The name of the path the object was found at, if any. Objects with the same name are
+    /// likely to be versions of the same file, and so good bases for deltas of each other.
+    pub name: String,
+}
+
+/// How hard to look for deltas when writing a pack.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PackOptions {
+    /// How many similar objects to try as the base of each object's delta.
+    pub window: usize,
+    /// The longest chain of deltas that can be needed to rebuild an object.
+    pub depth: usize,
+}
+
+impl Default for PackOptions {
+    fn default() -> Self {
+        Self {
+            window: 10,
+            depth: 50,
+        }
+    }
+}
+
+/// Write the given objects as a pack, in the order given, storing each as a delta against a
+/// similar object if that saves enough space. Bases are moved forward where needed, so that
+/// every base comes before the deltas made against it.
+pub fn write_pack(entries: &[PackEntry], options: &PackOptions) -> Vec<u8> {
+    write_thin_pack(entries, &[], options)
+}
+
+/// Write a pack as `write_pack` does, but allowing deltas against the `external` objects,
+/// which the pack's reader is known to have, without including them. Such deltas name
+/// their base by its ObjectId, and the pack can't be read on its own until it's fixed by
+/// adding the bases back.
+pub fn write_thin_pack(
+    entries: &[PackEntry],
+    external: &[PackEntry],
+    options: &PackOptions,
+) -> Vec<u8> {
+    let bases = find_deltas(entries, external, options);
+
+    let mut pack = Vec::new();
+    pack.extend_from_slice(SIGNATURE);
+    pack.extend_from_slice(&2u32.to_be_bytes());
+    pack.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+
+    let mut offsets: Vec<Option<usize>> = vec![None; entries.len()];
+
+    for i in 0..entries.len() {
+        let mut chain = vec![i];
+        while let Some((base, _)) = &bases[*chain.last().unwrap()] {
+            if *base >= entries.len() {
+                break;
+            }
+            chain.push(*base);
+        }
+
+        for &object in chain.iter().rev() {
+            if offsets[object].is_some() {
+                continue;
+            }
+
+            let offset = pack.len();
+            match &bases[object] {
+                Some((base, delta)) if *base >= entries.len() => {
+                    write_header(&mut pack, 7, delta.len());
+                    pack.extend_from_slice(external[*base - entries.len()].oid.bytes());
+                    pack.extend_from_slice(&compress(delta));
+                }
+                Some((base, delta)) => {
+                    write_header(&mut pack, 6, delta.len());
+                    write_distance(&mut pack, offset - offsets[*base].unwrap());
+                    pack.extend_from_slice(&compress(delta));
+                }
+                None => {
+                    let entry = &entries[object];
+                    write_header(&mut pack, entry.kind.code(), entry.data.len());
+                    pack.extend_from_slice(&compress(&entry.data));
+                }
+            }
+            offsets[object] = Some(offset);
+        }
+    }
+
+    let checksum = Sha1::digest(&pack);
+    pack.extend_from_slice(&checksum);
+    pack
+}
+
+/// Choose a base for each object that can be stored as a delta. Objects are sorted so that
+/// those most likely to be alike are next to each other, and each is tried against the
+/// `window` before it. A delta must be less than half the size of the object it rebuilds.
+/// External objects can be bases, numbered after the entries, but aren't given bases
+/// themselves, so they're put before the entries with the same name.
+fn find_deltas(
+    entries: &[PackEntry],
+    external: &[PackEntry],
+    options: &PackOptions,
+) -> Vec<Option<(usize, Vec<u8>)>> {
+    let all: Vec<_> = entries.iter().chain(external).collect();
+    let mut order: Vec<usize> = (0..all.len()).collect();
+    order.sort_by_key(|&i| {
+        let entry = all[i];
+        let internal = i < entries.len();
+        (
+            entry.kind.code(),
+            &entry.name,
+            internal,
+            Reverse(entry.data.len()),
+        )
+    });
+
+    let mut bases = vec![None; entries.len()];
+    let mut depths = vec![0; all.len()];
+
+    for (i, &target) in order.iter().enumerate() {
+        if target >= entries.len() {
+            continue;
+        }
+        let entry = all[target];
+        let mut best = None;
+        let mut max_size = entry.data.len() / 2;
+
+        for &base in order[i.saturating_sub(options.window)..i].iter().rev() {
+            if all[base].kind != entry.kind || depths[base] >= options.depth {
+                continue;
+            }
+            if let Some(delta) = delta::create(&all[base].data, &entry.data, max_size) {
+                max_size = delta.len().saturating_sub(1);
+                best = Some((base, delta));
+            }
+        }
+
+        if let Some((base, delta)) = best {
+            depths[target] = depths[base] + 1;
+            bases[target] = Some((base, delta));
+        }
+    }
+
+    bases
+}
+
+pub(super) fn write_header(pack: &mut Vec<u8>, code: u8, size: usize) {
+    let mut byte = (code << 4) | (size & 0xf) as u8;
+    let mut size = size >> 4;
+    while size > 0 {
+        pack.push(byte | 0x80);
+        byte = (size & 0x7f) as u8;
+        size >>= 7;
+    }
+    pack.push(byte);
+}
+
+/// Write the distance back to a delta's base, as `read_header` reads it.
+fn write_distance(pack: &mut Vec<u8>, distance: usize) {
+    let mut bytes = vec![(distance & 0x7f) as u8];
+    let mut distance = distance >> 7;
+    while distance > 0 {
+        distance -= 1;
+        bytes.push(0x80 | (distance & 0x7f) as u8);
+        distance >>= 7;
+    }
+    bytes.reverse();
+    pack.extend_from_slice(&bytes);
+}
+
+pub(super) fn compress(data: &[u8]) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    // Writing to a Vec can't fail.
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
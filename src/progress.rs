@@ -0,0 +1,39 @@
+use std::io::{IsTerminal, Write};
+
+/// Somewhere to report how a long-running operation, such as a fetch, is going. Messages
+/// are passed on as they are: one ending in `\r` is meant to be overwritten by the next,
+/// and one ending in `\n` is final.
+pub trait Progress {
+    fn message(&mut self, text: &str);
+}
+
+/// Writes progress to stderr, but only if it's a terminal.
+pub struct StderrProgress {
+    enabled: bool,
+}
+
+impl StderrProgress {
+    /// Report progress, unless `quiet` is set or stderr isn't a terminal.
+    pub fn new(quiet: bool) -> Self {
+        Self {
+            enabled: !quiet && std::io::stderr().is_terminal(),
+        }
+    }
+}
+
+impl Progress for StderrProgress {
+    fn message(&mut self, text: &str) {
+        if self.enabled {
+            let mut stderr = std::io::stderr();
+            let _ = stderr.write_all(text.as_bytes());
+            let _ = stderr.flush();
+        }
+    }
+}
+
+/// Keeps every message, e.g. to check what would have been shown.
+impl Progress for Vec<String> {
+    fn message(&mut self, text: &str) {
+        self.push(text.to_owned());
+    }
+}
@@ -0,0 +1,334 @@
+//! The native git protocol, as spoken over `git://` connections: pkt-lines, and both sides
+//! of a fetch.
+
+use std::collections::HashSet;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+
+use crate::database::{Database, ObjectId};
+use crate::pack::{self, ObjectType, PackEntry, PackOptions};
+use crate::progress::Progress;
+use crate::remote::{self, RemoteError};
+use crate::repository::Repository;
+use crate::revwalk;
+use crate::Result;
+
+/// The port `git daemon` listens on unless told otherwise.
+pub const DEFAULT_PORT: u16 = 9418;
+
+/// What upload-pack can do, sent after the first ref it advertises.
+const CAPABILITIES: &str = concat!(
+    "side-band side-band-64k thin-pack ofs-delta no-progress agent=nit/",
+    env!("CARGO_PKG_VERSION")
+);
+
+/// What a fetch asks upload-pack for, after the first object it wants.
+const FETCH_CAPABILITIES: &str = concat!(
+    "side-band-64k thin-pack ofs-delta agent=nit/",
+    env!("CARGO_PKG_VERSION")
+);
+
+/// The most data a side-band packet can carry, after its length and band number, with and
+/// without `side-band-64k`.
+const LARGE_BAND: usize = 65515;
+const SMALL_BAND: usize = 995;
+
+/// Write a packet: its length in 4 hex digits, counting themselves, then the data.
+pub fn write_packet(output: &mut impl Write, data: &[u8]) -> Result<()> {
+    write!(output, "{:04x}", data.len() + 4)?;
+    output.write_all(data)?;
+    Ok(())
+}
+
+/// Write a flush packet, `0000`, which ends a list of packets.
+pub fn write_flush(output: &mut impl Write) -> Result<()> {
+    output.write_all(b"0000")?;
+    output.flush()?;
+    Ok(())
+}
+
+/// Read a packet, returning `None` for a flush packet.
+pub fn read_packet(input: &mut impl Read) -> Result<Option<Vec<u8>>> {
+    let mut length = [0; 4];
+    input.read_exact(&mut length)?;
+    let length = std::str::from_utf8(&length)
+        .ok()
+        .and_then(|hex| usize::from_str_radix(hex, 16).ok())
+        .filter(|&length| length == 0 || length > 4)
+        .ok_or_else(|| {
+            RemoteError::BadPacketLength(String::from_utf8_lossy(&length).into_owned())
+        })?;
+    if length == 0 {
+        return Ok(None);
+    }
+
+    let mut packet = vec![0; length - 4];
+    input.read_exact(&mut packet)?;
+    Ok(Some(packet))
+}
+
+/// Read a packet holding a line of text, without its newline. An `ERR` packet is turned
+/// into an error.
+pub fn read_line(input: &mut impl Read) -> Result<Option<String>> {
+    let line = match read_packet(input)? {
+        Some(packet) => String::from_utf8_lossy(&packet).trim_end().to_owned(),
+        None => return Ok(None),
+    };
+
+    match line.strip_prefix("ERR ") {
+        Some(message) => Err(RemoteError::Remote(message.to_owned()).into()),
+        None => Ok(Some(line)),
+    }
+}
+
+/// Send `data` on a side band, split into as many packets as it needs.
+fn write_band(output: &mut impl Write, band: u8, data: &[u8], size: usize) -> Result<()> {
+    for chunk in data.chunks(size) {
+        let mut packet = Vec::with_capacity(chunk.len() + 1);
+        packet.push(band);
+        packet.extend_from_slice(chunk);
+        write_packet(output, &packet)?;
+    }
+    Ok(())
+}
+
+/// Send an `ERR` packet, and return the same message as an error.
+fn send_error(output: &mut impl Write, message: String) -> Result<()> {
+    write_packet(output, format!("ERR {}\n", message).as_bytes())?;
+    output.flush()?;
+    Err(RemoteError::Remote(message).into())
+}
+
+/// Serve a fetch from `repo`, as `git upload-pack` does: advertise its refs, read which of
+/// them the client wants and which commits it already has, then send a pack of everything
+/// it's missing. The pack is thin if the client can complete it, and goes over side bands
+/// if the client asked for them. A client that wants nothing, like `ls-remote`, just gets
+/// the refs.
+pub fn upload_pack(
+    repo: &Repository,
+    input: &mut impl Read,
+    output: &mut impl Write,
+) -> Result<()> {
+    let database = repo.database();
+    let advertised = remote::advertised_refs(repo)?;
+
+    match advertised.split_first() {
+        Some(((name, oid), rest)) => {
+            let first = format!("{} {}\0{}\n", oid, name, CAPABILITIES);
+            write_packet(output, first.as_bytes())?;
+            for (name, oid) in rest {
+                write_packet(output, format!("{} {}\n", oid, name).as_bytes())?;
+            }
+        }
+        None => {
+            let line = format!("{} capabilities^{{}}\0{}\n", ObjectId::NULL, CAPABILITIES);
+            write_packet(output, line.as_bytes())?;
+        }
+    }
+    write_flush(output)?;
+
+    let mut wants = Vec::new();
+    let mut capabilities = HashSet::new();
+    while let Some(line) = read_line(input)? {
+        let mut words = line.split(' ');
+        let oid = match (words.next(), words.next()) {
+            (Some("want"), Some(hex)) => ObjectId::from_hex(hex)?,
+            _ => return Err(RemoteError::UnexpectedPacket(line).into()),
+        };
+        if !advertised.iter().any(|(_, tip)| *tip == oid) {
+            return send_error(output, format!("upload-pack: not our ref {}", oid));
+        }
+        capabilities.extend(words.map(str::to_owned));
+        wants.push(oid);
+    }
+    if wants.is_empty() {
+        return Ok(());
+    }
+
+    // Without multi_ack, only the first commit both sides have is acknowledged, straight
+    // away, and a NAK is sent whenever the client stops to listen without one.
+    let mut common = Vec::new();
+    loop {
+        match read_line(input)? {
+            Some(line) if line == "done" => break,
+            Some(line) => {
+                let hex = line
+                    .strip_prefix("have ")
+                    .ok_or_else(|| RemoteError::UnexpectedPacket(line.clone()))?;
+                let oid = ObjectId::from_hex(hex)?;
+                if let Some((ObjectType::Commit, _)) = database.find_object(&oid)? {
+                    if common.is_empty() {
+                        write_packet(output, format!("ACK {}\n", oid).as_bytes())?;
+                    }
+                    common.push(oid);
+                }
+            }
+            None if common.is_empty() => write_packet(output, b"NAK\n")?,
+            None => {}
+        }
+    }
+    if common.is_empty() {
+        write_packet(output, b"NAK\n")?;
+    }
+
+    let entries = wanted_objects(database, &wants, &common)?;
+    let bases = match capabilities.contains("thin-pack") {
+        true => remote::preferred_bases(database, &common, &entries)?,
+        false => Vec::new(),
+    };
+    let bytes = pack::write_thin_pack(&entries, &bases, &PackOptions::default());
+
+    let band_size = if capabilities.contains("side-band-64k") {
+        Some(LARGE_BAND)
+    } else if capabilities.contains("side-band") {
+        Some(SMALL_BAND)
+    } else {
+        None
+    };
+    match band_size {
+        Some(size) => {
+            if !capabilities.contains("no-progress") {
+                let total = format!("Total {}\n", entries.len());
+                write_band(output, 2, total.as_bytes(), size)?;
+            }
+            write_band(output, 1, &bytes, size)?;
+            write_flush(output)?;
+        }
+        None => {
+            output.write_all(&bytes)?;
+            output.flush()?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Everything reachable from `wants` but not from `common`, ready to be packed. Wanted tags
+/// are sent along with what they're tags of.
+fn wanted_objects(
+    database: &Database,
+    wants: &[ObjectId],
+    common: &[ObjectId],
+) -> Result<Vec<PackEntry>> {
+    let mut entries = Vec::new();
+    let mut commits = Vec::new();
+    for oid in wants {
+        let mut target = *oid;
+        if let Some(peeled) = remote::peel_tag(database, oid)? {
+            let (kind, data) = database.read_object(oid)?;
+            entries.push(PackEntry {
+                oid: *oid,
+                kind: ObjectType::from_name(&kind).unwrap_or(ObjectType::Tag),
+                data,
+                name: String::new(),
+            });
+            target = peeled;
+        }
+        if let Some((ObjectType::Commit, _)) = database.find_object(&target)? {
+            commits.push(target);
+        }
+    }
+
+    for object in revwalk::list_objects(database, &commits, common)? {
+        let (_, data) = database.read_object(&object.oid)?;
+        let name = object
+            .path
+            .as_deref()
+            .and_then(Path::file_name)
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        entries.push(PackEntry {
+            oid: object.oid,
+            kind: object.kind,
+            data,
+            name,
+        });
+    }
+
+    Ok(entries)
+}
+
+/// Connect to the `git daemon` at `address`, as `host:port`, and ask it to serve a fetch
+/// from the repository at `path`. Returns the connection and the refs it advertised.
+pub fn connect(address: &str, path: &str) -> Result<(TcpStream, Vec<(String, ObjectId)>)> {
+    let mut stream = TcpStream::connect(address)?;
+    let host = address.rsplit_once(':').map_or(address, |(host, _)| host);
+    let request = format!("git-upload-pack {}\0host={}\0", path, host);
+    write_packet(&mut stream, request.as_bytes())?;
+    let advertised = read_advertisement(&mut stream)?;
+
+    Ok((stream, advertised))
+}
+
+/// Read the refs upload-pack advertises, up to the flush packet that ends them.
+pub fn read_advertisement(input: &mut impl Read) -> Result<Vec<(String, ObjectId)>> {
+    let mut advertised = Vec::new();
+    while let Some(line) = read_line(input)? {
+        let line = line.split('\0').next().unwrap_or_default();
+        let (hex, name) = line
+            .split_once(' ')
+            .ok_or_else(|| RemoteError::UnexpectedPacket(line.to_owned()))?;
+        if name != "capabilities^{}" {
+            advertised.push((name.to_owned(), ObjectId::from_hex(hex)?));
+        }
+    }
+
+    Ok(advertised)
+}
+
+/// Fetch the objects needed for `wants` over a connection whose refs have been read,
+/// telling upload-pack that the client already has `haves`, and store them in `database`.
+/// Returns how many objects arrived.
+pub fn fetch_pack(
+    stream: &mut (impl Read + Write),
+    wants: &[ObjectId],
+    haves: &[ObjectId],
+    database: &Database,
+    progress: &mut dyn Progress,
+) -> Result<usize> {
+    write_fetch_request(stream, wants, haves)?;
+    read_fetch_response(stream, database, progress)
+}
+
+/// Ask upload-pack for `wants`, saying the client already has `haves`, and that it has
+/// nothing more to say.
+pub fn write_fetch_request(
+    output: &mut impl Write,
+    wants: &[ObjectId],
+    haves: &[ObjectId],
+) -> Result<()> {
+    for (i, oid) in wants.iter().enumerate() {
+        let line = match i {
+            0 => format!("want {} {}\n", oid, FETCH_CAPABILITIES),
+            _ => format!("want {}\n", oid),
+        };
+        write_packet(output, line.as_bytes())?;
+    }
+    write_flush(output)?;
+    for oid in haves {
+        write_packet(output, format!("have {}\n", oid).as_bytes())?;
+    }
+    write_packet(output, b"done\n")?;
+    output.flush()?;
+
+    Ok(())
+}
+
+/// Read upload-pack's answer to a fetch request, passing on its progress, and store the
+/// pack it sends in `database`. Returns how many objects arrived.
+pub fn read_fetch_response(
+    input: &mut impl Read,
+    database: &Database,
+    progress: &mut dyn Progress,
+) -> Result<usize> {
+    match read_line(input)? {
+        Some(line) if line == "NAK" || line.starts_with("ACK ") => {}
+        Some(line) => return Err(RemoteError::UnexpectedPacket(line).into()),
+        None => return Err(RemoteError::UnexpectedPacket("0000".to_owned()).into()),
+    }
+
+    let mut bytes = Vec::new();
+    remote::demux_sideband(input, &mut bytes, progress)?;
+    remote::store_thin_pack(database, &bytes)
+}
@@ -0,0 +1,98 @@
+//! Quoting paths for output as git does: a path with a control character, a double quote
+//! or a backslash in it, or with `core.quotePath` on, any byte outside ASCII, is shown in
+//! double quotes with C-style escapes, e.g. `"caf\303\251.txt"`, so that every path comes
+//! out on one line and can be read back exactly.
+
+use std::path::Path;
+
+use crate::config::Config;
+use crate::sys::OsStrExt;
+use crate::Result;
+
+/// Whether bytes outside ASCII are quoted, as `core.quotePath` says; they are by default.
+pub fn quote_non_ascii(config: &Config) -> Result<bool> {
+    Ok(config.get_bool("core.quotePath")?.unwrap_or(true))
+}
+
+/// A path as git shows it, quoted if it needs to be.
+pub fn quote_path(path: &Path, quote_non_ascii: bool) -> String {
+    quote_bytes(path.as_os_str().as_bytes(), quote_non_ascii)
+}
+
+/// A path given as bytes, like `a/` and a path together in a diff header, as git shows it.
+/// Bytes that aren't valid UTF-8 are always escaped, even with `quote_non_ascii` off.
+pub fn quote_bytes(path: &[u8], quote_non_ascii: bool) -> String {
+    let valid = std::str::from_utf8(path).is_ok();
+    let needs_quoting = |byte: &u8| match byte {
+        0x00..=0x1f | b'"' | b'\\' | 0x7f => true,
+        0x80..=0xff => quote_non_ascii || !valid,
+        _ => false,
+    };
+    if !path.iter().any(needs_quoting) {
+        return String::from_utf8_lossy(path).into_owned();
+    }
+
+    let mut quoted = String::from("\"");
+    for chunk in path.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\u{7}' => quoted.push_str("\\a"),
+                '\u{8}' => quoted.push_str("\\b"),
+                '\t' => quoted.push_str("\\t"),
+                '\n' => quoted.push_str("\\n"),
+                '\u{b}' => quoted.push_str("\\v"),
+                '\u{c}' => quoted.push_str("\\f"),
+                '\r' => quoted.push_str("\\r"),
+                '"' => quoted.push_str("\\\""),
+                '\\' => quoted.push_str("\\\\"),
+                c if c.is_ascii_control() || (!c.is_ascii() && quote_non_ascii) => {
+                    let mut bytes = [0; 4];
+                    push_octal(&mut quoted, c.encode_utf8(&mut bytes).as_bytes());
+                }
+                c => quoted.push(c),
+            }
+        }
+        push_octal(&mut quoted, chunk.invalid());
+    }
+    quoted.push('"');
+
+    quoted
+}
+
+/// Escape bytes as backslashes followed by three octal digits each.
+fn push_octal(quoted: &mut String, bytes: &[u8]) {
+    for byte in bytes {
+        quoted.push_str(&format!("\\{:03o}", byte));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leaves_plain_paths_alone() {
+        assert_eq!(quote_bytes(b"dir/file name.txt", true), "dir/file name.txt");
+    }
+
+    #[test]
+    fn escapes_control_characters_and_quotes() {
+        assert_eq!(
+            quote_bytes(b"a\tb\n\"c\\d\x01", true),
+            "\"a\\tb\\n\\\"c\\\\d\\001\""
+        );
+    }
+
+    #[test]
+    fn quotes_non_ascii_bytes_unless_told_not_to() {
+        let path = "café.txt".as_bytes();
+        assert_eq!(quote_bytes(path, true), "\"caf\\303\\251.txt\"");
+        assert_eq!(quote_bytes(path, false), "café.txt");
+        assert_eq!(quote_bytes("tab\té".as_bytes(), false), "\"tab\\té\"");
+    }
+
+    #[test]
+    fn always_escapes_invalid_utf8() {
+        assert_eq!(quote_bytes(b"bad\xff.txt", false), "\"bad\\377.txt\"");
+    }
+}
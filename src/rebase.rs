@@ -0,0 +1,318 @@
+use std::{fmt::Display, str::FromStr};
+
+use thiserror::Error;
+
+use crate::database::{Commit, Database, ObjectId};
+use crate::Result;
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum RebaseError {
+    #[error("invalid line in the todo list: '{0}'")]
+    InvalidTodo(String),
+    #[error("cannot '{0}' without a previous commit")]
+    NothingToSquash(String),
+    #[error("could not apply {0}... {1}")]
+    Conflict(String, String),
+}
+
+/// What to do with a commit when it's replayed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Use the commit as it is.
+    Pick,
+    /// Meld the commit into the previous one, keeping both messages.
+    Squash,
+    /// Meld the commit into the previous one, keeping only the previous message.
+    Fixup,
+    /// Leave the commit out.
+    Drop,
+}
+
+impl Action {
+    fn name(self) -> &'static str {
+        match self {
+            Action::Pick => "pick",
+            Action::Squash => "squash",
+            Action::Fixup => "fixup",
+            Action::Drop => "drop",
+        }
+    }
+}
+
+impl FromStr for Action {
+    type Err = RebaseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "p" | "pick" => Ok(Action::Pick),
+            "s" | "squash" => Ok(Action::Squash),
+            "f" | "fixup" => Ok(Action::Fixup),
+            "d" | "drop" => Ok(Action::Drop),
+            _ => Err(RebaseError::InvalidTodo(s.to_owned())),
+        }
+    }
+}
+
+/// One line of a todo list: a commit and what to do with it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    pub action: Action,
+    pub oid: ObjectId,
+    pub title: String,
+}
+
+impl Display for Step {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} {}",
+            self.action.name(),
+            self.oid.short(7),
+            self.title
+        )
+    }
+}
+
+/// The commits a rebase will replay, oldest first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TodoList {
+    steps: Vec<Step>,
+}
+
+impl TodoList {
+    /// Pick each of the given commits, in order.
+    pub fn new(database: &Database, commits: &[ObjectId]) -> Result<Self> {
+        let steps = commits
+            .iter()
+            .map(|oid| {
+                Ok(Step {
+                    action: Action::Pick,
+                    oid: *oid,
+                    title: database.load_commit(oid)?.title_line().to_owned(),
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self { steps })
+    }
+
+    /// Read back a todo list, as written by `Display` and then edited. Blank lines and
+    /// lines starting with `#` are ignored.
+    pub fn parse(text: &str, database: &Database) -> Result<Self> {
+        let mut steps = Vec::new();
+
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid = || RebaseError::InvalidTodo(line.to_owned());
+            let mut words = line.splitn(3, ' ');
+            let action = words.next().unwrap_or_default().parse()?;
+            let prefix = words.next().ok_or_else(invalid)?;
+            let oid = match database.prefix_match(prefix)?.as_slice() {
+                [oid] => *oid,
+                _ => return Err(invalid().into()),
+            };
+
+            steps.push(Step {
+                action,
+                title: database.load_commit(&oid)?.title_line().to_owned(),
+                oid,
+            });
+        }
+
+        Ok(Self { steps })
+    }
+
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+
+    /// Move each `fixup! <title>` or `squash! <title>` commit to just after the commit it
+    /// refers to, and mark it to be melded into that commit. The title can also be a prefix
+    /// of the target's title or of its ObjectId.
+    pub fn autosquash(&mut self) {
+        let mut groups: Vec<Vec<Step>> = Vec::new();
+
+        for mut step in std::mem::take(&mut self.steps) {
+            let target = match squash_target(&step.title) {
+                Some((action, subject)) if step.action == Action::Pick => {
+                    find_group(&groups, subject).map(|group| (action, group))
+                }
+                _ => None,
+            };
+
+            match target {
+                Some((action, group)) => {
+                    step.action = action;
+                    groups[group].push(step);
+                }
+                None => groups.push(vec![step]),
+            }
+        }
+
+        self.steps = groups.into_iter().flatten().collect();
+    }
+}
+
+impl Display for TodoList {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for step in &self.steps {
+            writeln!(f, "{}", step)?;
+        }
+        Ok(())
+    }
+}
+
+/// Split a `fixup! ` or `squash! ` title into the action and the title it refers to.
+/// Repeated prefixes, as in `fixup! fixup! Title`, all refer to the same commit.
+fn squash_target(title: &str) -> Option<(Action, &str)> {
+    let (action, mut subject) = strip_squash_prefix(title)?;
+    while let Some((_, rest)) = strip_squash_prefix(subject) {
+        subject = rest;
+    }
+
+    Some((action, subject))
+}
+
+fn strip_squash_prefix(title: &str) -> Option<(Action, &str)> {
+    if let Some(rest) = title.strip_prefix("fixup! ") {
+        Some((Action::Fixup, rest))
+    } else {
+        title
+            .strip_prefix("squash! ")
+            .map(|rest| (Action::Squash, rest))
+    }
+}
+
+fn find_group(groups: &[Vec<Step>], subject: &str) -> Option<usize> {
+    let heads = || groups.iter().map(|group| &group[0]).enumerate();
+
+    heads()
+        .find(|(_, step)| step.title == subject)
+        .or_else(|| heads().find(|(_, step)| step.title.starts_with(subject)))
+        .or_else(|| {
+            heads().find(|(_, step)| {
+                step.oid
+                    .as_str()
+                    .is_ok_and(|hex| subject.len() >= 4 && hex.starts_with(subject))
+            })
+        })
+        .map(|(i, _)| i)
+}
+
+/// Replay the todo list on top of `onto`, returning the ObjectId of the new tip. Commits whose
+/// parent is already the tip are reused as they are; the rest are recreated with their
+/// changes applied to the new tip's tree.
+pub fn replay(database: &Database, onto: &ObjectId, todo: &TodoList) -> Result<ObjectId> {
+    let mut head = *onto;
+    let mut picked = false;
+
+    for step in todo.steps() {
+        let commit = database.load_commit(&step.oid)?;
+
+        match step.action {
+            Action::Drop => continue,
+            Action::Pick if commit.parent() == Some(&head) => head = step.oid,
+            Action::Pick => {
+                let tree = apply_changes(database, &head, step)?;
+                let commit = Commit::new(
+                    Some(head),
+                    tree,
+                    commit.author().clone(),
+                    commit.message().to_owned(),
+                );
+                head = database.store(&commit)?;
+            }
+            Action::Squash | Action::Fixup => {
+                if !picked {
+                    return Err(RebaseError::NothingToSquash(step.action.name().to_owned()).into());
+                }
+
+                let tree = apply_changes(database, &head, step)?;
+                let previous = database.load_commit(&head)?;
+                let message = match step.action {
+                    Action::Squash => squash_message(previous.message(), commit.message()),
+                    _ => previous.message().to_owned(),
+                };
+                let commit = Commit::new(
+                    previous.parent().copied(),
+                    tree,
+                    previous.author().clone(),
+                    message,
+                );
+                head = database.store(&commit)?;
+            }
+        }
+
+        picked = true;
+    }
+
+    Ok(head)
+}
+
+/// Apply the changes a step's commit made to its parent to the tree of `head`. Changes are
+/// merged path by path: a path that `head` has changed differently is a conflict.
+fn apply_changes(database: &Database, head: &ObjectId, step: &Step) -> Result<ObjectId> {
+    let commit = database.load_commit(&step.oid)?;
+    let changes = database.tree_diff(commit.parent(), Some(&step.oid))?;
+
+    let mut entries = database.load_tree_list(Some(head))?;
+
+    for (path, (before, after)) in changes {
+        let current = entries.get(&path);
+        if current == after.as_ref() {
+            continue;
+        }
+        if current != before.as_ref() {
+            return Err(RebaseError::Conflict(step.oid.short(7), step.title.clone()).into());
+        }
+
+        match after {
+            Some(entry) => entries.insert(path, entry),
+            None => entries.remove(&path),
+        };
+    }
+
+    database.store_tree_list(&entries)
+}
+
+/// Combine the message of a commit with that of a `squash!` commit melded into it,
+/// leaving out the `squash!` title.
+fn squash_message(previous: &str, squash: &str) -> String {
+    let body = match squash_target(squash.lines().next().unwrap_or_default()) {
+        Some(_) => squash.split_once('\n').map_or("", |(_, body)| body),
+        None => squash,
+    };
+
+    match body.trim() {
+        "" => previous.to_owned(),
+        body => format!("{}\n\n{}\n", previous.trim_end(), body),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn finds_squash_targets() {
+        assert_eq!(
+            squash_target("fixup! fixup! Add a thing"),
+            Some((Action::Fixup, "Add a thing"))
+        );
+        assert_eq!(
+            squash_target("squash! Add a thing"),
+            Some((Action::Squash, "Add a thing"))
+        );
+        assert_eq!(squash_target("Add a thing"), None);
+
+        assert_eq!(
+            squash_message("Title\n", "squash! Title\n\nMore detail.\n"),
+            "Title\n\nMore detail.\n"
+        );
+        assert_eq!(squash_message("Title\n", "squash! Title\n"), "Title\n");
+    }
+}
@@ -0,0 +1,474 @@
+use std::{cmp::Ordering, fmt::Display, str::FromStr};
+
+use chrono::{DateTime, FixedOffset};
+use thiserror::Error;
+
+use crate::database::{Database, ObjectId, ParsedObject};
+use crate::refs::{Ref, Refs};
+use crate::utils::glob_matches;
+use crate::Result;
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum RefFormatError {
+    #[error("unknown field name: {0}")]
+    UnknownField(String),
+    #[error("malformed format string {0}")]
+    Malformed(String),
+}
+
+/// A field of a ref or the object it points at, named by `%(name)` in a format string.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Field {
+    RefName { short: bool },
+    ObjectName { short: bool },
+    ObjectType,
+    Symref,
+    Subject,
+    Body,
+    AuthorName,
+    AuthorEmail,
+    AuthorDate,
+    CommitterName,
+    CommitterEmail,
+    CommitterDate,
+}
+
+impl FromStr for Field {
+    type Err = RefFormatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let field = match s {
+            "refname" => Field::RefName { short: false },
+            "refname:short" => Field::RefName { short: true },
+            "objectname" => Field::ObjectName { short: false },
+            "objectname:short" => Field::ObjectName { short: true },
+            "objecttype" => Field::ObjectType,
+            "symref" => Field::Symref,
+            "subject" => Field::Subject,
+            "body" => Field::Body,
+            "authorname" => Field::AuthorName,
+            "authoremail" => Field::AuthorEmail,
+            "authordate" => Field::AuthorDate,
+            "committername" => Field::CommitterName,
+            "committeremail" => Field::CommitterEmail,
+            "committerdate" => Field::CommitterDate,
+            _ => return Err(RefFormatError::UnknownField(s.to_owned())),
+        };
+
+        Ok(field)
+    }
+}
+
+/// A field, prefixed with `*` as in `%(*objectname)` to take it from what an annotated tag
+/// peels to rather than from the tag itself. Peeled fields are empty for other refs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Atom {
+    pub field: Field,
+    pub peeled: bool,
+}
+
+impl FromStr for Atom {
+    type Err = RefFormatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (peeled, name) = match s.strip_prefix('*') {
+            Some(name) => (true, name),
+            None => (false, s),
+        };
+
+        Ok(Self {
+            field: name.parse()?,
+            peeled,
+        })
+    }
+}
+
+/// The objects a ref's fields are taken from: what it points at, and what that peels to if
+/// it's an annotated tag. Neither is loaded unless a field needs it.
+#[derive(Default)]
+pub struct Objects {
+    pub object: Option<ParsedObject>,
+    pub peeled: Option<(ObjectId, ParsedObject)>,
+}
+
+impl Objects {
+    /// Load what a ref points at, and what that peels to if `peel` is set and it's a tag.
+    fn load(database: &Database, oid: &ObjectId, peel: bool) -> Result<Self> {
+        let object = database.load(oid)?;
+        let peeled = match &object {
+            ParsedObject::Tag(tag) if peel => {
+                let peeled = database.peel(tag.object())?;
+                Some((peeled, database.load(&peeled)?))
+            }
+            _ => None,
+        };
+
+        Ok(Self {
+            object: Some(object),
+            peeled,
+        })
+    }
+}
+
+/// The value of a field for one ref. Dates sort by time rather than by how they're shown.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Value {
+    Text(String),
+    Date(DateTime<FixedOffset>),
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Text(text) => write!(f, "{}", text),
+            Value::Date(date) => write!(f, "{}", date.format("%a %b %-d %H:%M:%S %Y %z")),
+        }
+    }
+}
+
+impl Field {
+    /// Whether the object the ref points at has to be loaded to get this field.
+    fn needs_object(self) -> bool {
+        !matches!(
+            self,
+            Field::RefName { .. } | Field::ObjectName { .. } | Field::Symref
+        )
+    }
+
+    /// The value of this field for a ref, where `object` is what the ref points at and `oid`
+    /// is its ObjectId. Fields of a commit are empty for refs that point at other kinds of
+    /// object, except for the subject and body, which an annotated tag has too.
+    pub fn value(self, r: &Ref, oid: Option<&ObjectId>, object: Option<&ParsedObject>) -> Value {
+        let commit = match object {
+            Some(ParsedObject::Commit(commit)) => Some(commit),
+            _ => None,
+        };
+        let message = match object {
+            Some(ParsedObject::Commit(commit)) => Some(commit.message()),
+            Some(ParsedObject::Tag(tag)) => Some(tag.message()),
+            _ => None,
+        };
+        let text = |value: Option<String>| Value::Text(value.unwrap_or_default());
+
+        match self {
+            Field::RefName { short: false } => Value::Text(r.name.clone()),
+            Field::RefName { short: true } => Value::Text(Refs::short_name(&r.name).to_owned()),
+            Field::ObjectName { short } => {
+                text(oid.map(|oid| if short { oid.short(7) } else { oid.to_string() }))
+            }
+            Field::ObjectType => text(object.map(|object| object.kind().to_owned())),
+            Field::Symref => text(r.symref.clone()),
+            Field::Subject => text(message.map(|m| m.lines().next().unwrap_or("").to_owned())),
+            Field::Body => text(message.map(|m| match m.split_once("\n\n") {
+                Some((_, body)) => body.to_owned(),
+                None => String::new(),
+            })),
+            Field::AuthorName => text(commit.map(|c| c.author().name().to_owned())),
+            Field::CommitterName => text(commit.map(|c| c.committer().name().to_owned())),
+            Field::AuthorEmail => text(commit.map(|c| format!("<{}>", c.author().email()))),
+            Field::CommitterEmail => text(commit.map(|c| format!("<{}>", c.committer().email()))),
+            Field::AuthorDate => match commit {
+                Some(commit) => Value::Date(commit.author().time()),
+                None => Value::Text(String::new()),
+            },
+            Field::CommitterDate => match commit {
+                Some(commit) => Value::Date(commit.committer().time()),
+                None => Value::Text(String::new()),
+            },
+        }
+    }
+}
+
+impl Atom {
+    /// Whether the object the ref points at has to be loaded to get this atom.
+    fn needs_object(self) -> bool {
+        self.peeled || self.field.needs_object()
+    }
+
+    /// The value of this atom for a ref, with the objects loaded for it.
+    pub fn value(self, r: &Ref, objects: &Objects) -> Value {
+        if !self.peeled {
+            return self.field.value(r, r.oid.as_ref(), objects.object.as_ref());
+        }
+
+        match (self.field, &objects.peeled) {
+            (Field::RefName { .. } | Field::Symref, _) | (_, None) => Value::Text(String::new()),
+            (field, Some((oid, object))) => field.value(r, Some(oid), Some(object)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    Atom(Atom),
+}
+
+/// A format string such as `%(refname) %(objectname:short)`. `%%` is a literal `%`, and
+/// `%xx` is the byte with hex value `xx`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RefFormat {
+    parts: Vec<Part>,
+}
+
+impl Default for RefFormat {
+    fn default() -> Self {
+        "%(objectname) %(objecttype)\t%(refname)".parse().unwrap()
+    }
+}
+
+impl FromStr for RefFormat {
+    type Err = RefFormatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let malformed = || RefFormatError::Malformed(s.to_owned());
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut rest = s;
+
+        while let Some(i) = rest.find('%') {
+            literal.push_str(&rest[..i]);
+            rest = &rest[i + 1..];
+
+            if let Some(after) = rest.strip_prefix('%') {
+                literal.push('%');
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix('(') {
+                let (name, after) = after.split_once(')').ok_or_else(malformed)?;
+                if !literal.is_empty() {
+                    parts.push(Part::Literal(std::mem::take(&mut literal)));
+                }
+                parts.push(Part::Atom(name.parse()?));
+                rest = after;
+            } else {
+                let byte = rest
+                    .get(..2)
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or_else(malformed)?;
+                literal.push(byte as char);
+                rest = &rest[2..];
+            }
+        }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+
+        Ok(Self { parts })
+    }
+}
+
+impl RefFormat {
+    /// Fill in the format for a ref.
+    pub fn expand(&self, r: &Ref, objects: &Objects) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Literal(text) => text.clone(),
+                Part::Atom(atom) => atom.value(r, objects).to_string(),
+            })
+            .collect()
+    }
+
+    /// The atoms in the format.
+    fn atoms(&self) -> impl Iterator<Item = Atom> + '_ {
+        self.parts.iter().filter_map(|part| match part {
+            Part::Atom(atom) => Some(*atom),
+            Part::Literal(_) => None,
+        })
+    }
+}
+
+/// A field to sort refs by, such as `refname`, or `-committerdate` to sort in reverse.
+/// Like a field in a format, it can be prefixed with `*`, as in `*committerdate`.
+/// A `v:` or `version:` prefix, as in `v:refname`, compares the numbers in the field by
+/// value, so that `v1.10` comes after `v1.9`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SortKey {
+    pub atom: Atom,
+    pub reverse: bool,
+    pub version: bool,
+}
+
+impl FromStr for SortKey {
+    type Err = RefFormatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (reverse, name) = match s.strip_prefix('-') {
+            Some(name) => (true, name),
+            None => (false, s),
+        };
+        let (version, name) = match name
+            .strip_prefix("v:")
+            .or_else(|| name.strip_prefix("version:"))
+        {
+            Some(name) => (true, name),
+            None => (false, name),
+        };
+
+        Ok(Self {
+            atom: name.parse()?,
+            reverse,
+            version,
+        })
+    }
+}
+
+impl SortKey {
+    fn compare(&self, a: &(Ref, Objects), b: &(Ref, Objects)) -> Ordering {
+        let a = self.atom.value(&a.0, &a.1);
+        let b = self.atom.value(&b.0, &b.1);
+        let ordering = match (self.version, &a, &b) {
+            (true, Value::Text(a), Value::Text(b)) => version_cmp(a, b),
+            _ => a.cmp(&b),
+        };
+
+        if self.reverse {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    }
+}
+
+/// Compare two strings, treating each run of digits as a number.
+pub fn version_cmp(a: &str, b: &str) -> Ordering {
+    fn runs(s: &str) -> Vec<&str> {
+        let mut runs = Vec::new();
+        let mut start = 0;
+        for (i, c) in s.char_indices().skip(1) {
+            let prev = s[..i].chars().next_back().unwrap();
+            if prev.is_ascii_digit() != c.is_ascii_digit() {
+                runs.push(&s[start..i]);
+                start = i;
+            }
+        }
+        runs.push(&s[start..]);
+        runs
+    }
+
+    for (a, b) in runs(a).into_iter().zip(runs(b)) {
+        let ordering = match (a.parse::<u64>(), b.parse::<u64>()) {
+            (Ok(x), Ok(y)) => x.cmp(&y).then_with(|| a.cmp(b)),
+            _ => a.cmp(b),
+        };
+        if ordering.is_ne() {
+            return ordering;
+        }
+    }
+
+    a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+}
+
+/// Whether a ref's full name matches a pattern: either a prefix of the name that ends at a
+/// `/`, such as `refs/heads`, or a glob such as `refs/tags/v1.*`.
+pub fn pattern_matches(pattern: &str, name: &str) -> bool {
+    let prefix = pattern.trim_end_matches('/');
+    let is_prefix = name
+        .strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'));
+
+    is_prefix || glob_matches(pattern.as_bytes(), name.as_bytes())
+}
+
+/// Whether a ref's full name ends with a pattern, which has to match whole parts of the
+/// name, as `ls-remote` matches refs: `master` matches `refs/heads/master`, but not
+/// `refs/heads/submaster`. The pattern can be a glob.
+pub fn tail_matches(pattern: &str, name: &str) -> bool {
+    tail_match(pattern, name).is_some()
+}
+
+/// Where the longest tail of a ref's name that a pattern matches starts, as for
+/// `tail_matches`: 0 if the pattern matches the whole name.
+pub fn tail_match(pattern: &str, name: &str) -> Option<usize> {
+    std::iter::once(0)
+        .chain(name.match_indices('/').map(|(i, _)| i + 1))
+        .find(|&start| glob_matches(pattern.as_bytes(), &name.as_bytes()[start..]))
+}
+
+/// Format each of the refs, each on a line of its own, sorted by the given keys in turn
+/// and then by name.
+pub fn format_refs(
+    refs: Vec<Ref>,
+    format: &RefFormat,
+    sort: &[SortKey],
+    database: &Database,
+) -> Result<String> {
+    let atoms: Vec<_> = format
+        .atoms()
+        .chain(sort.iter().map(|key| key.atom))
+        .collect();
+    let load = atoms.iter().any(|atom| atom.needs_object());
+    let peel = atoms.iter().any(|atom| atom.peeled);
+
+    let mut refs = refs
+        .into_iter()
+        .map(|r| {
+            let objects = match (&r.oid, load) {
+                (Some(oid), true) => Objects::load(database, oid, peel)?,
+                _ => Objects::default(),
+            };
+            Ok((r, objects))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    refs.sort_by(|a, b| {
+        sort.iter()
+            .map(|key| key.compare(a, b))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or_else(|| a.0.name.cmp(&b.0.name))
+    });
+
+    Ok(refs
+        .iter()
+        .map(|(r, objects)| format!("{}\n", format.expand(r, objects)))
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_format_strings() {
+        let format: RefFormat = "%(refname:short) %%%20x".parse().unwrap();
+        assert_eq!(
+            format.parts,
+            vec![
+                Part::Atom(Atom {
+                    field: Field::RefName { short: true },
+                    peeled: false,
+                }),
+                Part::Literal(" % x".to_owned()),
+            ]
+        );
+
+        assert!("%(nonsense)".parse::<RefFormat>().is_err());
+        assert!(pattern_matches("refs/heads", "refs/heads/topic/one"));
+        assert!(pattern_matches("refs/tags/v1.*", "refs/tags/v1.2"));
+        assert!(!pattern_matches("refs/head", "refs/heads/master"));
+
+        assert!(tail_matches("master", "refs/heads/master"));
+        assert!(tail_matches("heads/m*", "refs/heads/master"));
+        assert!(!tail_matches("master", "refs/heads/submaster"));
+        assert_eq!(tail_match("refs/tags/*", "refs/tags/v1"), Some(0));
+        assert_eq!(tail_match("v*", "refs/tags/v1"), Some(10));
+        assert!("%(refname".parse::<RefFormat>().is_err());
+        assert_eq!(
+            "-committerdate".parse::<SortKey>().unwrap(),
+            SortKey {
+                atom: Atom {
+                    field: Field::CommitterDate,
+                    peeled: false,
+                },
+                reverse: true,
+                version: false,
+            }
+        );
+
+        let mut names = vec!["v1.10", "v1.9", "v1.9.1", "v2.0", "v1.2"];
+        names.sort_by(|a, b| version_cmp(a, b));
+        assert_eq!(names, vec!["v1.2", "v1.9", "v1.9.1", "v1.10", "v2.0"]);
+    }
+}
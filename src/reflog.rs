@@ -0,0 +1,114 @@
+use std::{env, fmt::Display};
+
+use chrono::Local;
+use thiserror::Error;
+
+use crate::database::{Author, ObjectId};
+use crate::Result;
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum ReflogError {
+    #[error("invalid reflog line: '{0}'")]
+    BadEntry(String),
+}
+
+/// One line of a reflog: a ref moving from one ObjectId to another, who moved it, when,
+/// and why.
+#[derive(Debug, Clone)]
+pub struct ReflogEntry {
+    pub old: ObjectId,
+    pub new: ObjectId,
+    pub identity: Author,
+    pub message: String,
+}
+
+impl ReflogEntry {
+    /// A new entry made now, by the committer given in the environment. The message is
+    /// cut down to its first line.
+    pub fn new(old: Option<&ObjectId>, new: &ObjectId, message: &str) -> Self {
+        Self {
+            old: old.copied().unwrap_or(ObjectId::NULL),
+            new: *new,
+            identity: identity_from_env(),
+            message: message.lines().next().unwrap_or_default().to_owned(),
+        }
+    }
+
+    /// Parse a line of the form `<old> <new> Name <email> 1234567890 +0000\t<message>`.
+    pub fn parse(line: &str) -> Result<Self> {
+        let bad = || ReflogError::BadEntry(line.to_owned());
+
+        let (header, message) = line.split_once('\t').unwrap_or((line, ""));
+        let mut parts = header.splitn(3, ' ');
+        let old = ObjectId::from_hex(parts.next().ok_or_else(bad)?)?;
+        let new = ObjectId::from_hex(parts.next().ok_or_else(bad)?)?;
+        let identity = Author::parse(parts.next().ok_or_else(bad)?.as_bytes())?;
+
+        Ok(Self {
+            old,
+            new,
+            identity,
+            message: message.to_owned(),
+        })
+    }
+}
+
+impl Display for ReflogEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} {}\t{}",
+            self.old, self.new, self.identity, self.message
+        )
+    }
+}
+
+/// The committer to record in the reflog. A missing name or email shouldn't stop a ref
+/// from moving, so they fall back to placeholders.
+fn identity_from_env() -> Author {
+    let var = |names: [&str; 2], default: &str| {
+        names
+            .iter()
+            .find_map(|name| env::var(name).ok())
+            .unwrap_or_else(|| default.to_owned())
+    };
+
+    Author::new(
+        var(["GIT_COMMITTER_NAME", "GIT_AUTHOR_NAME"], "unknown"),
+        var(["GIT_COMMITTER_EMAIL", "GIT_AUTHOR_EMAIL"], ""),
+        Local::now(),
+    )
+}
+
+/// Whether changes to a ref, given by its full name, are recorded in a reflog. The stash's
+/// reflog is the list of everything stashed.
+pub fn should_log(name: &str) -> bool {
+    name == crate::refs::HEAD
+        || name == "refs/stash"
+        || name.starts_with("refs/heads/")
+        || name.starts_with("refs/remotes/")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_entries() {
+        let line = format!(
+            "{} {} A. U. Thor <author@example.com> 1600000000 +0000\tcommit (initial): Start",
+            ObjectId::NULL,
+            ObjectId::from([0xab; 20])
+        );
+        let entry = ReflogEntry::parse(&line).unwrap();
+
+        assert_eq!(entry.old, ObjectId::NULL);
+        assert_eq!(entry.new, ObjectId::from([0xab; 20]));
+        assert_eq!(entry.identity.name(), "A. U. Thor");
+        assert_eq!(entry.message, "commit (initial): Start");
+        assert_eq!(entry.to_string(), line);
+
+        assert!(ReflogEntry::parse("not a reflog line").is_err());
+    }
+}
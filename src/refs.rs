@@ -1,7 +1,12 @@
 use crate::lockfile::Lockfile;
+use crate::reflog::{self, ReflogEntry};
+use crate::vfs::{FileKind, RealFs, Vfs};
 use crate::{database::ObjectId, lockfile::LockfileError};
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use std::fmt::Display;
 use std::io::Write;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use thiserror::Error;
 
 use crate::Result;
@@ -15,27 +20,812 @@ pub enum RefError {
     CouldNotWrite(#[from] std::io::Error),
     #[error("Couldn't get lockfile id")]
     BadObjectId(#[from] std::fmt::Error),
+    #[error("'{0}' is not a valid branch name.")]
+    InvalidBranchName(String),
+    #[error("A branch named '{0}' already exists.")]
+    BranchExists(String),
+    #[error("branch '{0}' not found.")]
+    BranchNotFound(String),
+    #[error("'{0}' is not a valid tag name.")]
+    InvalidTagName(String),
+    #[error("tag '{0}' already exists")]
+    TagExists(String),
+    #[error("cannot lock ref '{0}': is at {1} but expected {2}")]
+    Stale(String, String, String),
+    #[error("refusing to point {0} outside of refs/: '{1}'")]
+    SymrefOutsideRefs(String, String),
+    #[error("'{0}' is not a valid ref name")]
+    InvalidRefName(String),
+    #[error("symbolic ref '{0}' goes round in a loop, or through too many other refs")]
+    SymrefTooDeep(String),
 }
 
-pub struct Refs {
-    pathname: PathBuf,
+pub const HEAD: &str = "HEAD";
+/// Where HEAD was before a command that moves it a long way, such as a rebase.
+pub const ORIG_HEAD: &str = "ORIG_HEAD";
+/// What the last fetch fetched, one ref per line.
+pub const FETCH_HEAD: &str = "FETCH_HEAD";
+/// The commits being merged into HEAD while a merge is in progress.
+pub const MERGE_HEAD: &str = "MERGE_HEAD";
+const DEFAULT_BRANCH: &str = "refs/heads/master";
+const SYMREF_PREFIX: &str = "ref: ";
+/// How many symbolic refs are followed in a row before giving up, as git does.
+const MAX_SYMREF_DEPTH: usize = 5;
+
+/// What a ref file contains: either a pointer to another ref, or an ObjectId.
+enum RefContent {
+    SymRef(String),
+    Oid(ObjectId),
 }
 
-impl Refs {
-    pub fn new(pathname: &Path) -> Self {
+/// One line of FETCH_HEAD: a ref that was fetched, and where from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FetchHead {
+    pub oid: ObjectId,
+    /// Whether a pull would merge this ref: it's the current branch's upstream.
+    pub for_merge: bool,
+    /// What the ref was, e.g. `branch 'master' of <url>`.
+    pub description: String,
+}
+
+impl Display for FetchHead {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let merge = if self.for_merge { "" } else { "not-for-merge" };
+        write!(f, "{}\t{}\t{}", self.oid, merge, self.description)
+    }
+}
+
+/// A ref, as listed by `Refs::list`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ref {
+    /// The full name of the ref, e.g. `refs/heads/master`.
+    pub name: String,
+    /// The ObjectId the ref ultimately points at, or `None` if it's a symbolic ref whose
+    /// target doesn't exist.
+    pub oid: Option<ObjectId>,
+    /// The full name of the ref this one points at, if it's a symbolic ref.
+    pub symref: Option<String>,
+}
+
+/// Where each ref is kept. A linked worktree's `.git` directory holds the refs that belong
+/// to that worktree alone, such as HEAD, ORIG_HEAD and the bisect state, while branches,
+/// tags and everything else are shared through the common directory named by its
+/// `commondir` file. For the main worktree, both are the same directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefStore {
+    worktree_path: PathBuf,
+    common_path: PathBuf,
+}
+
+impl RefStore {
+    pub fn new(worktree_path: &Path, common_path: &Path) -> Self {
         Self {
-            pathname: pathname.to_owned(),
+            worktree_path: worktree_path.to_owned(),
+            common_path: common_path.to_owned(),
+        }
+    }
+
+    /// The store for the `.git` directory at `git_path`, which shares refs with the
+    /// directory its `commondir` file names, relative to it, if it has one.
+    pub fn open<F: Vfs>(fs: &F, git_path: &Path) -> Self {
+        let common_path = match fs.read(&git_path.join("commondir")) {
+            Ok(data) => {
+                // The common directory is usually `../..`, which is resolved here so that
+                // refs in it have the same paths whichever worktree they're reached from.
+                let dir = String::from_utf8_lossy(&data);
+                let mut path = PathBuf::new();
+                for component in git_path.join(dir.trim_end()).components() {
+                    match component {
+                        Component::ParentDir => {
+                            path.pop();
+                        }
+                        Component::CurDir => {}
+                        component => path.push(component),
+                    }
+                }
+                path
+            }
+            Err(_) => git_path.to_owned(),
+        };
+        Self::new(git_path, &common_path)
+    }
+
+    /// Whether a ref, given by its full name, belongs to a single worktree: a pseudo-ref
+    /// such as HEAD or ORIG_HEAD, or a ref under `refs/bisect/`, `refs/worktree/` or
+    /// `refs/rewritten/`.
+    pub fn is_per_worktree(name: &str) -> bool {
+        !name.contains('/')
+            || ["refs/bisect/", "refs/worktree/", "refs/rewritten/"]
+                .iter()
+                .any(|prefix| name.starts_with(prefix))
+    }
+
+    /// The directory a ref, given by its full name, is kept in, along with its reflog.
+    pub fn base(&self, name: &str) -> &Path {
+        match Self::is_per_worktree(name) {
+            true => &self.worktree_path,
+            false => &self.common_path,
         }
     }
+
+    /// The file a ref, given by its full name, is kept in when it isn't packed.
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.base(name).join(name)
+    }
+
+    /// The file a ref's reflog is kept in.
+    pub fn log_path(&self, name: &str) -> PathBuf {
+        self.logs_dir(name).join(name)
+    }
+
+    /// The `logs` directory a ref's reflog is kept under.
+    fn logs_dir(&self, name: &str) -> PathBuf {
+        self.base(name).join("logs")
+    }
+
+    /// Packed refs are always shared.
+    fn packed_refs_path(&self) -> PathBuf {
+        self.common_path.join("packed-refs")
+    }
+
+    /// The full name of the ref kept in the file at `path`, if that's where it belongs.
+    fn name(&self, path: &Path) -> Option<String> {
+        self.bases().into_iter().rev().find_map(|base| {
+            let name = path.strip_prefix(base).ok()?.to_string_lossy().into_owned();
+            (self.base(&name) == base).then_some(name)
+        })
+    }
+
+    /// The directories refs are kept in: the common one, and the worktree's own if it has
+    /// one.
+    fn bases(&self) -> Vec<&Path> {
+        if self.worktree_path == self.common_path {
+            vec![&self.common_path]
+        } else {
+            vec![&self.common_path, &self.worktree_path]
+        }
+    }
+}
+
+pub struct Refs<F: Vfs = RealFs> {
+    fs: F,
+    store: RefStore,
+}
+
+impl Refs {
+    pub fn new(pathname: &Path) -> Self {
+        Self::with_vfs(RealFs, pathname)
+    }
+
+    /// Shorten a full ref name for display, e.g. `refs/heads/master` becomes `master`
+    /// and `refs/remotes/origin/main` becomes `origin/main`.
+    pub fn short_name(name: &str) -> &str {
+        ["refs/heads/", "refs/remotes/", "refs/tags/", "refs/"]
+            .iter()
+            .find_map(|prefix| name.strip_prefix(prefix))
+            .unwrap_or(name)
+    }
+}
+
+impl<F: Vfs> Refs<F> {
+    pub fn with_vfs(fs: F, pathname: &Path) -> Self {
+        let store = RefStore::open(&fs, pathname);
+        Self { fs, store }
+    }
+
+    /// Where each ref is kept.
+    pub fn store(&self) -> &RefStore {
+        &self.store
+    }
+
     pub fn head_path(&self) -> PathBuf {
-        self.pathname.join("HEAD")
+        self.store.path(HEAD)
+    }
+
+    fn heads_path(&self) -> PathBuf {
+        self.store.path("refs/heads")
+    }
+
+    /// Point HEAD's current branch at the given ObjectId, or HEAD itself if it is detached.
+    /// The move is logged against both the branch and HEAD.
+    pub fn update_head(&self, oid: &ObjectId, message: &str) -> Result<()> {
+        let name = self.current_ref()?;
+        let old = self.read_head()?;
+
+        self.update_ref_file(&self.store.path(&name), oid)?;
+
+        self.append_reflog(&name, old.as_ref(), oid, message)?;
+        if name != HEAD {
+            self.append_reflog(HEAD, old.as_ref(), oid, message)?;
+        }
+        Ok(())
+    }
+
+    /// Check out a revision: if it names a branch, HEAD becomes a pointer to that branch,
+    /// otherwise HEAD is detached at the given ObjectId.
+    pub fn set_head(&self, revision: &str, oid: &ObjectId, message: &str) -> Result<()> {
+        let old = self.read_head()?;
+        let branch = self.heads_path().join(revision);
+
+        if self.ref_exists(&branch) {
+            self.set_symref(HEAD, &format!("refs/heads/{}", revision))?;
+        } else {
+            self.update_ref_file(&self.head_path(), oid)?;
+        }
+
+        self.append_reflog(HEAD, old.as_ref(), oid, message)
+    }
+
+    /// Detach HEAD at the given ObjectId, even if a branch points at it.
+    pub fn detach_head(&self, oid: &ObjectId, message: &str) -> Result<()> {
+        let old = self.read_head()?;
+        self.update_ref_file(&self.head_path(), oid)?;
+        self.append_reflog(HEAD, old.as_ref(), oid, message)
+    }
+
+    /// Resolve HEAD to the ObjectId it ultimately points at.
+    pub fn read_head(&self) -> Result<Option<ObjectId>> {
+        self.read_ref(HEAD)
+    }
+
+    /// Resolve a ref name, e.g. `HEAD`, `master` or `refs/heads/master`, to an ObjectId.
+    pub fn read_ref(&self, name: &str) -> Result<Option<ObjectId>> {
+        match self.path_for_name(name) {
+            Some(path) => self.follow_ref(&path),
+            None => Ok(None),
+        }
+    }
+
+    /// The full name of the ref HEAD points at, e.g. `refs/heads/master`, or `HEAD` if detached.
+    pub fn current_ref(&self) -> Result<String> {
+        Ok(self.read_symref(HEAD)?.unwrap_or_else(|| HEAD.to_owned()))
+    }
+
+    /// The full name of the ref that a symbolic ref, given by its full name, points at, or
+    /// `None` if it isn't a symbolic ref. The target doesn't have to exist.
+    pub fn read_symref(&self, name: &str) -> Result<Option<String>> {
+        match self.read_ref_file(&self.store.path(name))? {
+            Some(RefContent::SymRef(target)) => Ok(Some(target)),
+            Some(RefContent::Oid(_)) => Ok(None),
+            None if name == HEAD => Ok(Some(DEFAULT_BRANCH.to_owned())),
+            None => Ok(None),
+        }
+    }
+
+    /// Make a ref, given by its full name, a symbolic ref pointing at another ref under
+    /// `refs/`, which doesn't have to exist yet.
+    pub fn set_symref(&self, name: &str, target: &str) -> Result<()> {
+        if !target.starts_with("refs/") {
+            return Err(RefError::SymrefOutsideRefs(name.to_owned(), target.to_owned()).into());
+        }
+
+        let path = self.store.path(name);
+        if let Some(parent) = path.parent() {
+            self.fs
+                .create_dir_all(parent)
+                .map_err(RefError::CouldNotWrite)?;
+        }
+
+        let mut lock = self.lock(&path);
+        lock.hold_for_update()?;
+        lock.write_all(format!("{}{}\n", SYMREF_PREFIX, target).as_bytes())?;
+        lock.commit()?;
+
+        Ok(())
+    }
+
+    /// Check that a branch could be created with the given name.
+    pub fn check_new_branch(&self, name: &str) -> Result<()> {
+        if !is_valid_branch_name(name) {
+            return Err(RefError::InvalidBranchName(name.to_owned()).into());
+        }
+
+        if self.ref_exists(&self.heads_path().join(name)) {
+            return Err(RefError::BranchExists(name.to_owned()).into());
+        }
+
+        Ok(())
+    }
+
+    /// Create a new branch pointing at the given ObjectId.
+    pub fn create_branch(&self, name: &str, oid: &ObjectId, message: &str) -> Result<()> {
+        self.check_new_branch(name)?;
+        self.update_ref_file(&self.heads_path().join(name), oid)?;
+        self.append_reflog(&format!("refs/heads/{}", name), None, oid, message)
+    }
+
+    /// Create a lightweight tag pointing at the given ObjectId.
+    pub fn create_tag(&self, name: &str, oid: &ObjectId) -> Result<()> {
+        if !is_valid_tag_name(name) {
+            return Err(RefError::InvalidTagName(name.to_owned()).into());
+        }
+
+        let path = self.store.path(&format!("refs/tags/{}", name));
+        if self.ref_exists(&path) {
+            return Err(RefError::TagExists(name.to_owned()).into());
+        }
+
+        self.update_ref_file(&path, oid)
+    }
+
+    /// Delete a branch, loose or packed, returning the ObjectId it pointed at.
+    pub fn delete_branch(&self, name: &str) -> Result<ObjectId> {
+        let full_name = format!("refs/heads/{}", name);
+        let oid = self
+            .delete_ref(&full_name)?
+            .ok_or_else(|| RefError::BranchNotFound(name.to_owned()))?;
+        self.delete_reflog(&full_name)?;
+
+        Ok(oid)
+    }
+
+    /// Point a ref, given by its full name such as `refs/replace/<oid>`, at an ObjectId,
+    /// creating it if need be, and record the move in its reflog with the given message,
+    /// if it keeps one.
+    pub fn update_ref(&self, name: &str, oid: &ObjectId, message: &str) -> Result<()> {
+        let old = self.read_ref(name)?;
+        self.write_ref(name, oid)?;
+        self.append_reflog(name, old.as_ref(), oid, message)
+    }
+
+    /// Point a ref, given by its full name, at `new`, or delete it if `new` is `None`, but
+    /// only if it currently points at `expected`, where `None` means it mustn't exist. The
+    /// ref is locked while it's checked, so a concurrent update can't be overwritten.
+    pub fn compare_and_swap(
+        &self,
+        name: &str,
+        expected: Option<&ObjectId>,
+        new: Option<&ObjectId>,
+        message: &str,
+    ) -> Result<()> {
+        let mut transaction = self.transaction();
+        transaction.update(name, expected, new, message);
+        transaction.commit()
+    }
+
+    /// Record what a fetch fetched in FETCH_HEAD, with the refs to be merged first, so
+    /// that FETCH_HEAD resolves to the first of them.
+    pub fn write_fetch_head(&self, heads: &[FetchHead]) -> Result<()> {
+        let mut lock = self.lock(&self.store.path(FETCH_HEAD));
+        lock.hold_for_update()?;
+        for head in heads.iter().filter(|head| head.for_merge) {
+            writeln!(lock, "{}", head)?;
+        }
+        for head in heads.iter().filter(|head| !head.for_merge) {
+            writeln!(lock, "{}", head)?;
+        }
+        lock.commit()?;
+
+        Ok(())
+    }
+
+    /// Start a set of ref updates that are made together.
+    pub fn transaction(&self) -> RefTransaction<'_, F> {
+        RefTransaction {
+            refs: self,
+            updates: Vec::new(),
+        }
+    }
+
+    /// Delete a ref, given by its full name, from wherever it's kept, returning the
+    /// ObjectId it pointed at if it existed. The ref stays locked until it's gone.
+    pub fn delete_ref(&self, name: &str) -> Result<Option<ObjectId>> {
+        let path = self.store.path(name);
+        // A packed ref mightn't have a directory to hold its lock.
+        if let Some(parent) = path.parent() {
+            self.fs
+                .create_dir_all(parent)
+                .map_err(RefError::CouldNotWrite)?;
+        }
+
+        let mut lock = self.lock(&path);
+        lock.hold_for_update()?;
+
+        let result = self.follow_ref(&path).and_then(|oid| {
+            if oid.is_some() {
+                self.remove_ref(name)?;
+            }
+            Ok(oid)
+        });
+        lock.rollback()?;
+        self.delete_ref_directories(name)?;
+
+        result
+    }
+
+    /// Expand a short ref name to the full name of the ref it refers to,
+    /// e.g. `origin/main` might become `refs/remotes/origin/main`.
+    pub fn expand_name(&self, name: &str) -> Option<String> {
+        if name == HEAD {
+            return Some(HEAD.to_owned());
+        }
+
+        let path = self.path_for_name(name)?;
+        self.store.name(&path)
+    }
+
+    /// List every ref under `refs/` whose full name starts with `prefix`, such as
+    /// `refs/tags/`, in name order. Loose refs take the place of packed refs of the same
+    /// name.
+    pub fn list(&self, prefix: Option<&str>) -> Result<Vec<Ref>> {
+        let mut names = Vec::new();
+        for base in self.store.bases() {
+            self.collect_refs(&base.join("refs"), base, base, &mut names)?;
+        }
+
+        let mut refs: BTreeMap<_, _> = self
+            .packed_refs()?
+            .into_iter()
+            .map(|(name, oid)| {
+                let r = Ref {
+                    name: name.clone(),
+                    oid: Some(oid),
+                    symref: None,
+                };
+                (name, r)
+            })
+            .collect();
+
+        for name in names {
+            let path = self.store.path(&name);
+            let symref = match self.read_ref_file(&path)? {
+                Some(RefContent::SymRef(target)) => Some(target),
+                _ => None,
+            };
+            let oid = self.follow_ref(&path)?;
+            refs.insert(name.clone(), Ref { name, oid, symref });
+        }
+
+        Ok(refs
+            .into_values()
+            .filter(|r| prefix.is_none_or(|prefix| r.name.starts_with(prefix)))
+            .collect())
+    }
+
+    /// Collect the full names of the refs (or reflogs) in `dir` and below that are kept in
+    /// `base`, each named by its path relative to `root`.
+    fn collect_refs(
+        &self,
+        dir: &Path,
+        root: &Path,
+        base: &Path,
+        names: &mut Vec<String>,
+    ) -> Result<()> {
+        let entries = match self.fs.read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(RefError::CouldNotWrite(e).into()),
+        };
+
+        for (name, kind) in entries {
+            let path = dir.join(name);
+            if kind == FileKind::Dir {
+                self.collect_refs(&path, root, base, names)?;
+            } else if path.extension().is_none_or(|ext| ext != "lock") {
+                if let Ok(relative) = path.strip_prefix(root) {
+                    let name = relative.to_string_lossy().into_owned();
+                    if self.store.base(&name) == base {
+                        names.push(name);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Remove a ref, given by its full name, which the caller has locked: its loose file,
+    /// if it has one, and its entry in `packed-refs`, if it has one of those.
+    fn remove_ref(&self, name: &str) -> Result<()> {
+        let path = self.store.path(name);
+        match self.fs.remove_file(&path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(RefError::CouldNotWrite(e).into()),
+        }
+        self.remove_packed_refs(&[name])
+    }
+
+    /// Remove the directories left empty by deleting a ref, once it's unlocked. Ones like
+    /// `refs/heads` are kept even when they're empty, as git keeps them.
+    fn delete_ref_directories(&self, name: &str) -> Result<()> {
+        let kind = name.splitn(3, '/').take(2).collect::<Vec<_>>().join("/");
+        self.delete_parent_directories(&self.store.path(name), &self.store.path(&kind))
+    }
+
+    /// Rewrite `packed-refs` without the refs with the given full names, along with the
+    /// lines giving what they peel to. `packed-refs` is locked while it's rewritten, and
+    /// left alone if none of them are in it.
+    fn remove_packed_refs(&self, names: &[&str]) -> Result<()> {
+        let path = self.store.packed_refs_path();
+        let content = match self.read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(RefError::CouldNotWrite(e).into()),
+        };
+
+        let mut kept = String::new();
+        let mut removing = false;
+        for line in content.lines() {
+            if !line.starts_with('^') {
+                let name = line.split_once(' ').map(|(_, name)| name);
+                removing = !line.starts_with('#') && name.is_some_and(|name| names.contains(&name));
+            }
+            if !removing {
+                kept.push_str(line);
+                kept.push('\n');
+            }
+        }
+        if kept.len() == content.len() {
+            return Ok(());
+        }
+
+        let mut lock = self.lock(&path);
+        lock.hold_for_update()?;
+        lock.write_all(kept.as_bytes())?;
+        lock.commit()
+    }
+
+    fn path_for_name(&self, name: &str) -> Option<PathBuf> {
+        if name == HEAD {
+            return Some(self.head_path());
+        }
+
+        [
+            name.to_owned(),
+            format!("refs/{}", name),
+            format!("refs/tags/{}", name),
+            format!("refs/heads/{}", name),
+            format!("refs/remotes/{}", name),
+            format!("refs/remotes/{}/{}", name, HEAD),
+        ]
+        .iter()
+        .map(|name| self.store.path(name))
+        .find(|path| self.ref_exists(path))
+    }
+
+    /// Whether there's a ref at a path, either loose or packed.
+    fn ref_exists(&self, path: &Path) -> bool {
+        self.is_file(path) || self.read_packed_ref(path).is_some()
+    }
+
+    fn follow_ref(&self, path: &Path) -> Result<Option<ObjectId>> {
+        let mut path = path.to_owned();
+        for _ in 0..=MAX_SYMREF_DEPTH {
+            path = match self.read_ref_file(&path)? {
+                Some(RefContent::SymRef(name)) => self.store.path(&name),
+                Some(RefContent::Oid(oid)) => return Ok(Some(oid)),
+                None if path == self.head_path() => self.store.path(DEFAULT_BRANCH),
+                None => return Ok(self.read_packed_ref(&path)),
+            };
+        }
+
+        let name = self
+            .store
+            .name(&path)
+            .unwrap_or_else(|| path.to_string_lossy().into_owned());
+        Err(RefError::SymrefTooDeep(name).into())
+    }
+
+    /// The refs in `packed-refs`, by full name. Lines starting with `^` give the object a
+    /// tag peels to, which isn't needed here.
+    fn packed_refs(&self) -> Result<BTreeMap<String, ObjectId>> {
+        let content = match self.read_to_string(&self.store.packed_refs_path()) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+            Err(e) => return Err(RefError::CouldNotWrite(e).into()),
+        };
+
+        Ok(content
+            .lines()
+            .filter(|line| !line.starts_with('#') && !line.starts_with('^'))
+            .filter_map(|line| line.split_once(' '))
+            .filter_map(|(oid, name)| Some((name.to_owned(), ObjectId::from_hex(oid).ok()?)))
+            .collect())
+    }
+
+    /// The ObjectId of the packed ref at the given path, if there is one.
+    fn read_packed_ref(&self, path: &Path) -> Option<ObjectId> {
+        let name = self.store.name(path)?;
+        self.packed_refs().ok()?.remove(&name)
+    }
+
+    fn read_ref_file(&self, path: &Path) -> Result<Option<RefContent>> {
+        let content = match self.read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(RefError::CouldNotWrite(e).into()),
+        };
+        let content = content.trim();
+
+        // Pseudo-refs like FETCH_HEAD and MERGE_HEAD can name several commits, and say
+        // more about them, but they resolve to the first.
+        match content.strip_prefix(SYMREF_PREFIX) {
+            Some(name) => Ok(Some(RefContent::SymRef(name.to_owned()))),
+            None => {
+                let first = content.split_whitespace().next().unwrap_or_default();
+                Ok(ObjectId::from_hex(first).ok().map(RefContent::Oid))
+            }
+        }
     }
 
-    pub fn update_head(&self, oid: &ObjectId) -> Result<()> {
-        let mut lock = Lockfile::new(&self.head_path());
+    /// Remove any directories left empty under `base` after a file in them is deleted.
+    fn delete_parent_directories(&self, path: &Path, base: &Path) -> Result<()> {
+        for dir in path.ancestors().skip(1) {
+            if dir == base || !dir.starts_with(base) {
+                break;
+            }
+
+            match self.fs.remove_dir(dir) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::DirectoryNotEmpty => break,
+                Err(e) => return Err(RefError::CouldNotWrite(e).into()),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The entries of a ref's reflog, given by the ref's full name, oldest first. A ref
+    /// with no reflog has no entries.
+    pub fn reflog(&self, name: &str) -> Result<Vec<ReflogEntry>> {
+        let content = match self.read_to_string(&self.store.log_path(name)) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(RefError::CouldNotWrite(e).into()),
+        };
+
+        content
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(ReflogEntry::parse)
+            .collect()
+    }
+
+    /// The full names of every ref that has a reflog, such as `HEAD`, `refs/heads/master`
+    /// or `refs/stash`, in name order.
+    pub fn reflogs(&self) -> Result<Vec<String>> {
+        let mut names = Vec::new();
+        for base in self.store.bases() {
+            let logs = base.join("logs");
+            self.collect_refs(&logs, &logs, base, &mut names)?;
+        }
+        names.sort();
+
+        Ok(names)
+    }
+
+    /// Drop the entries of a ref's reflog made before `before`, returning how many were
+    /// dropped. The reflog is locked while it's rewritten.
+    pub fn expire_reflog(&self, name: &str, before: DateTime<Utc>) -> Result<usize> {
+        let path = self.store.log_path(name);
+        let mut lock = self.lock(&path);
         lock.hold_for_update()?;
 
-        lock.write_all(&oid.as_str()?.as_bytes())?;
+        let entries = match self.reflog(name) {
+            Ok(entries) => entries,
+            Err(e) => {
+                lock.rollback()?;
+                return Err(e);
+            }
+        };
+        let (kept, expired): (Vec<_>, Vec<_>) = entries
+            .into_iter()
+            .partition(|entry| entry.identity.time() >= before);
+
+        if expired.is_empty() {
+            lock.rollback()?;
+            return Ok(0);
+        }
+
+        for entry in kept {
+            writeln!(lock, "{}", entry)?;
+        }
+        lock.commit()?;
+
+        Ok(expired.len())
+    }
+
+    /// Drop an entry from a ref's reflog, counting back from the newest, which is 0, and
+    /// return it, or `None` if there aren't that many entries. Dropping the newest entry
+    /// moves the ref back to where the entry before it left it, or deletes the ref and its
+    /// reflog if there are no entries left.
+    pub fn drop_reflog_entry(&self, name: &str, n: usize) -> Result<Option<ReflogEntry>> {
+        let path = self.store.log_path(name);
+        let mut lock = self.lock(&path);
+        lock.hold_for_update()?;
+
+        let mut entries = match self.reflog(name) {
+            Ok(entries) if n < entries.len() => entries,
+            result => {
+                lock.rollback()?;
+                return result.map(|_| None);
+            }
+        };
+        let dropped = entries.remove(entries.len() - 1 - n);
+
+        if n == 0 {
+            match entries.last() {
+                Some(entry) => self.write_ref(name, &entry.new)?,
+                None => {
+                    self.delete_ref(name)?;
+                    lock.rollback()?;
+                    self.delete_reflog(name)?;
+                    return Ok(Some(dropped));
+                }
+            }
+        }
+
+        for entry in &entries {
+            writeln!(lock, "{}", entry)?;
+        }
+        lock.commit()?;
+
+        Ok(Some(dropped))
+    }
+
+    /// Remove a ref's reflog, if it has one, along with any directories that leaves empty.
+    fn delete_reflog(&self, name: &str) -> Result<()> {
+        let path = self.store.log_path(name);
+        match self.fs.remove_file(&path) {
+            Ok(()) => self.delete_parent_directories(&path, &self.store.logs_dir(name)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(RefError::CouldNotWrite(e).into()),
+        }
+    }
+
+    /// Record a ref, given by its full name, moving from `old` to `new`, if it's a ref
+    /// that keeps a reflog.
+    fn append_reflog(
+        &self,
+        name: &str,
+        old: Option<&ObjectId>,
+        new: &ObjectId,
+        message: &str,
+    ) -> Result<()> {
+        if !reflog::should_log(name) {
+            return Ok(());
+        }
+
+        let path = self.store.log_path(name);
+        if let Some(parent) = path.parent() {
+            self.fs
+                .create_dir_all(parent)
+                .map_err(RefError::CouldNotWrite)?;
+        }
+
+        let entry = ReflogEntry::new(old, new, message);
+        self.fs
+            .append(&path, format!("{}\n", entry).as_bytes())
+            .map_err(RefError::CouldNotWrite)?;
+
+        Ok(())
+    }
+
+    /// Point a ref, given by its full name, at an ObjectId without logging the move.
+    fn write_ref(&self, name: &str, oid: &ObjectId) -> Result<()> {
+        let options = RefFormatOptions {
+            allow_onelevel: true,
+            ..Default::default()
+        };
+        if check_ref_format(name, options).is_none() {
+            return Err(RefError::InvalidRefName(name.to_owned()).into());
+        }
+
+        self.update_ref_file(&self.store.path(name), oid)
+    }
+
+    fn update_ref_file(&self, path: &Path, oid: &ObjectId) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            self.fs
+                .create_dir_all(parent)
+                .map_err(RefError::CouldNotWrite)?;
+        }
+
+        let mut lock = self.lock(path);
+        lock.hold_for_update()?;
+
+        lock.write_all(oid.as_str()?.as_bytes())?;
         lock.write_all(b"\n")?;
 
         lock.commit()?;
@@ -43,10 +833,529 @@ impl Refs {
         Ok(())
     }
 
-    pub fn read_head(&self) -> Option<String> {
-        let bytes = std::fs::read(self.head_path()).ok()?;
-        let s = String::from_utf8(bytes).ok()?;
+    fn lock(&self, path: &Path) -> Lockfile<F> {
+        Lockfile::with_vfs(self.fs.clone(), path)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.fs.stat(path).is_ok_and(|stat| stat.is_file())
+    }
+
+    fn read_to_string(&self, path: &Path) -> std::io::Result<String> {
+        String::from_utf8(self.fs.read(path)?)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+}
+
+struct RefUpdate {
+    name: String,
+    expected: Option<ObjectId>,
+    new: Option<ObjectId>,
+    message: String,
+}
+
+/// A set of ref updates that either all happen or, if any ref can't be locked or isn't
+/// where it was expected to be, none do.
+pub struct RefTransaction<'a, F: Vfs = RealFs> {
+    refs: &'a Refs<F>,
+    updates: Vec<RefUpdate>,
+}
+
+impl<F: Vfs> RefTransaction<'_, F> {
+    /// Point a ref, given by its full name, at `new`, or delete it if `new` is `None`, as
+    /// long as it's still at `expected` when the transaction is committed. `None` means
+    /// the ref mustn't exist. The move is logged with `message`, and so is HEAD's if it's
+    /// on the branch being moved; a deleted ref's reflog goes with it.
+    pub fn update(
+        &mut self,
+        name: &str,
+        expected: Option<&ObjectId>,
+        new: Option<&ObjectId>,
+        message: &str,
+    ) {
+        self.updates.push(RefUpdate {
+            name: name.to_owned(),
+            expected: expected.copied(),
+            new: new.copied(),
+            message: message.to_owned(),
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.updates.is_empty()
+    }
+
+    /// Lock every ref, check they're all where they were expected to be, and only then
+    /// make the updates.
+    pub fn commit(self) -> Result<()> {
+        let mut locks = Vec::new();
+        let result = self.lock_and_check(&mut locks);
+        if let Err(e) = result {
+            for lock in &mut locks {
+                lock.rollback()?;
+            }
+            return Err(e);
+        }
+
+        // Deleted refs go from packed-refs first, while they're all still locked.
+        let deleted: Vec<_> = self
+            .updates
+            .iter()
+            .filter(|update| update.new.is_none())
+            .map(|update| update.name.as_str())
+            .collect();
+        if let Err(e) = self.refs.remove_packed_refs(&deleted) {
+            for lock in &mut locks {
+                lock.rollback()?;
+            }
+            return Err(e);
+        }
+
+        let current_ref = self.refs.current_ref()?;
+        for (update, mut lock) in self.updates.iter().zip(locks) {
+            match &update.new {
+                Some(oid) => {
+                    lock.write_all(format!("{}\n", oid).as_bytes())?;
+                    lock.commit()?;
+
+                    let (old, message) = (update.expected.as_ref(), &update.message);
+                    self.refs.append_reflog(&update.name, old, oid, message)?;
+                    if update.name == current_ref && current_ref != HEAD {
+                        self.refs.append_reflog(HEAD, old, oid, message)?;
+                    }
+                }
+                None => {
+                    let result = self.refs.remove_ref(&update.name);
+                    lock.rollback()?;
+                    result?;
+                    self.refs.delete_ref_directories(&update.name)?;
+                    self.refs.delete_reflog(&update.name)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn lock_and_check(&self, locks: &mut Vec<Lockfile<F>>) -> Result<()> {
+        for update in &self.updates {
+            let path = self.refs.store.path(&update.name);
+            if let Some(parent) = path.parent() {
+                self.refs
+                    .fs
+                    .create_dir_all(parent)
+                    .map_err(RefError::CouldNotWrite)?;
+            }
+
+            let mut lock = self.refs.lock(&path);
+            lock.hold_for_update()?;
+            locks.push(lock);
+
+            let current = self.refs.follow_ref(&path)?;
+            if current != update.expected {
+                let describe = |oid: Option<&ObjectId>| match oid {
+                    Some(oid) => oid.to_string(),
+                    None => "nothing".to_owned(),
+                };
+                return Err(RefError::Stale(
+                    update.name.clone(),
+                    describe(current.as_ref()),
+                    describe(update.expected.as_ref()),
+                )
+                .into());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// How strictly `check_ref_format` reads a name, as for `git check-ref-format`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RefFormatOptions {
+    /// Allow names with only one component, like `HEAD` or `master`.
+    pub allow_onelevel: bool,
+    /// Allow a single `*` in the name, as in the patterns on either side of a refspec.
+    pub refspec_pattern: bool,
+    /// Drop any leading slashes and squash runs of slashes into one before checking.
+    pub normalize: bool,
+}
+
+/// Check a ref's full name, e.g. `refs/heads/master`, against git's rules for ref names,
+/// returning the name to use (normalized, if asked for) if it's allowed.
+///
+/// No component of the name may be empty, start with `.` or end with `.lock`, and the name
+/// may not end with `.`, be just `@`, or contain `..`, `@{`, control characters, spaces or
+/// any of `~^:?*[\`.
+pub fn check_ref_format(name: &str, options: RefFormatOptions) -> Option<String> {
+    let name = match options.normalize {
+        true => name
+            .split('/')
+            .filter(|component| !component.is_empty())
+            .collect::<Vec<_>>()
+            .join("/"),
+        false => name.to_owned(),
+    };
+
+    let forbidden_char = |c: char| c < ' ' || c == '\x7f' || " :?[\\^~".contains(c) || c == '*';
+    let mut stars = 0;
+    let mut components = 0;
+    for component in name.split('/') {
+        components += 1;
+        stars += component.matches('*').count();
+        if component.is_empty()
+            || component.starts_with('.')
+            || component.ends_with(".lock")
+            || component.contains("..")
+            || component.contains("@{")
+            || component
+                .chars()
+                .any(|c| forbidden_char(c) && !(c == '*' && options.refspec_pattern))
+        {
+            return None;
+        }
+    }
+
+    let allowed = (components > 1 || options.allow_onelevel)
+        && stars <= 1
+        && !name.ends_with('.')
+        && name != "@";
+    allowed.then_some(name)
+}
+
+/// Whether a name is allowed to be used for a branch, which is `refs/heads/` followed by
+/// the name.
+pub fn is_valid_branch_name(name: &str) -> bool {
+    !name.starts_with('-')
+        && name != HEAD
+        && check_ref_format(&format!("refs/heads/{}", name), Default::default()).is_some()
+}
+
+/// Whether a name is allowed to be used for a tag, which is `refs/tags/` followed by the
+/// name.
+pub fn is_valid_tag_name(name: &str) -> bool {
+    !name.starts_with('-')
+        && check_ref_format(&format!("refs/tags/{}", name), Default::default()).is_some()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::vfs::MemoryFs;
+
+    fn refs() -> (MemoryFs, Refs<MemoryFs>) {
+        let fs = MemoryFs::new();
+        fs.create_dir_all(Path::new("/repo/.git/refs/heads"))
+            .unwrap();
+        let refs = Refs::with_vfs(fs.clone(), Path::new("/repo/.git"));
+        (fs, refs)
+    }
+
+    #[test]
+    fn reads_refs_through_symrefs_and_packed_refs() {
+        let (fs, refs) = refs();
+        let oid = ObjectId::from([0xab; 20]);
+        let write = |path: &str, content: String| {
+            fs.write(&Path::new("/repo/.git").join(path), content.as_bytes())
+                .unwrap()
+        };
+
+        assert_eq!(refs.read_head().unwrap(), None);
+
+        write(
+            "packed-refs",
+            format!("# pack-refs with: peeled\n{} refs/heads/master\n", oid),
+        );
+        write("HEAD", "ref: refs/heads/master\n".to_owned());
+        assert_eq!(refs.read_head().unwrap(), Some(oid));
+
+        let other = ObjectId::from([0xcd; 20]);
+        write("refs/heads/topic", format!("{}\n\n", other));
+        write("refs/heads/alias", "  ref: refs/heads/topic  \n".to_owned());
+        assert_eq!(refs.read_ref("alias").unwrap(), Some(other));
+        assert_eq!(refs.read_ref("refs/heads/topic").unwrap(), Some(other));
+        assert_eq!(refs.read_ref("master").unwrap(), Some(oid));
+        assert_eq!(refs.read_ref("missing").unwrap(), None);
+    }
+
+    #[test]
+    fn logs_why_refs_moved() {
+        let (fs, refs) = refs();
+        let (one, two) = (ObjectId::from([0xab; 20]), ObjectId::from([0xcd; 20]));
+        let messages = |name: &str| -> Vec<_> {
+            let entries = refs.reflog(name).unwrap();
+            entries.into_iter().map(|entry| entry.message).collect()
+        };
+
+        refs.update_head(&one, "commit (initial): One").unwrap();
+        refs.update_ref("refs/remotes/origin/master", &one, "update by push")
+            .unwrap();
+
+        // A transaction logs each ref it moves, and HEAD too if it's on a moved branch.
+        let mut transaction = refs.transaction();
+        transaction.update(
+            "refs/heads/master",
+            Some(&one),
+            Some(&two),
+            "merge two: Fast-forward",
+        );
+        transaction.update(
+            "refs/remotes/origin/master",
+            Some(&one),
+            None,
+            "fetch origin: prune",
+        );
+        transaction.commit().unwrap();
+
+        assert_eq!(
+            messages("refs/heads/master"),
+            ["commit (initial): One", "merge two: Fast-forward"]
+        );
+        assert_eq!(messages(HEAD), messages("refs/heads/master"));
+        assert_eq!(refs.reflog(HEAD).unwrap()[1].old, one);
+        assert!(!fs.exists(Path::new("/repo/.git/logs/refs/remotes/origin/master")));
+    }
+
+    #[test]
+    fn deletes_loose_and_packed_refs() {
+        let (fs, refs) = refs();
+        let (one, two) = (ObjectId::from([0xab; 20]), ObjectId::from([0xcd; 20]));
+        let packed_refs = Path::new("/repo/.git/packed-refs");
+        fs.write(
+            packed_refs,
+            format!(
+                "# pack-refs with: peeled\n{0} refs/heads/master\n{0} refs/heads/topic\n\
+                 {1} refs/tags/v1.0\n^{0}\n{0} refs/tags/v0.9\n",
+                one, two
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+        refs.update_ref("refs/heads/topic", &two, "moved").unwrap();
+
+        // A loose ref goes along with the packed one it hid.
+        assert_eq!(refs.delete_branch("topic").unwrap(), two);
+        assert_eq!(refs.read_ref("refs/heads/topic").unwrap(), None);
+        assert_eq!(refs.delete_branch("master").unwrap(), one);
+        assert!(refs.delete_branch("master").is_err());
+        assert_eq!(refs.delete_ref("refs/tags/v1.0").unwrap(), Some(two));
+        assert_eq!(refs.delete_ref("refs/tags/v1.0").unwrap(), None);
+        assert_eq!(
+            String::from_utf8(fs.read(packed_refs).unwrap()).unwrap(),
+            format!("# pack-refs with: peeled\n{} refs/tags/v0.9\n", one)
+        );
+
+        // Nothing was left locked, so the branch can be made again.
+        assert!(!fs.exists(Path::new("/repo/.git/refs/heads/master.lock")));
+        assert!(fs.exists(Path::new("/repo/.git/refs/heads")));
+        refs.create_branch("master", &two, "branch: Created from HEAD")
+            .unwrap();
+        assert_eq!(refs.read_ref("master").unwrap(), Some(two));
+    }
+
+    #[test]
+    fn deletes_packed_refs_in_a_transaction() {
+        let (fs, refs) = refs();
+        let oid = ObjectId::from([0xab; 20]);
+        fs.write(
+            Path::new("/repo/.git/packed-refs"),
+            format!(
+                "{0} refs/remotes/origin/gone\n{0} refs/remotes/origin/kept\n",
+                oid
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+
+        let mut transaction = refs.transaction();
+        transaction.update("refs/remotes/origin/gone", Some(&oid), None, "prune");
+        transaction.commit().unwrap();
+
+        let names: Vec<_> = refs
+            .list(None)
+            .unwrap()
+            .into_iter()
+            .map(|r| r.name)
+            .collect();
+        assert_eq!(names, ["refs/remotes/origin/kept"]);
+        assert!(!fs.exists(Path::new("/repo/.git/refs/remotes/origin/gone.lock")));
+    }
+
+    #[test]
+    fn attaches_head_to_packed_branches() {
+        let (fs, refs) = refs();
+        let oid = ObjectId::from([0xab; 20]);
+        fs.write(
+            Path::new("/repo/.git/packed-refs"),
+            format!("{0} refs/heads/topic\n{0} refs/tags/v1.0\n", oid).as_bytes(),
+        )
+        .unwrap();
+
+        refs.set_head("topic", &oid, "checkout: moving from master to topic")
+            .unwrap();
+        assert_eq!(refs.current_ref().unwrap(), "refs/heads/topic");
+
+        refs.set_head("v1.0", &oid, "checkout: moving from topic to v1.0")
+            .unwrap();
+        assert_eq!(refs.current_ref().unwrap(), HEAD);
+        assert!(refs.check_new_branch("topic").is_err());
+    }
+
+    #[test]
+    fn stops_following_symref_loops() {
+        let (fs, refs) = refs();
+        let write = |name: &str, target: &str| {
+            let content = format!("ref: refs/heads/{}\n", target);
+            fs.write(
+                &Path::new("/repo/.git/refs/heads").join(name),
+                content.as_bytes(),
+            )
+            .unwrap()
+        };
+        write("a", "b");
+        write("b", "a");
+
+        assert!(matches!(
+            refs.read_ref("a"),
+            Err(crate::Error::Ref(RefError::SymrefTooDeep(_)))
+        ));
+    }
+
+    #[test]
+    fn keeps_per_worktree_refs_apart_from_shared_ones() {
+        let (fs, main) = refs();
+        let linked_path = Path::new("/repo/.git/worktrees/linked");
+        fs.create_dir_all(linked_path).unwrap();
+        fs.write(&linked_path.join("commondir"), b"../..\n")
+            .unwrap();
+        let linked = Refs::with_vfs(fs.clone(), linked_path);
+        let (one, two) = (ObjectId::from([0xab; 20]), ObjectId::from([0xcd; 20]));
+
+        main.update_head(&one, "commit (initial): One").unwrap();
+        linked
+            .create_branch("topic", &one, "branch: Created")
+            .unwrap();
+        linked.set_head("topic", &one, "checkout: moving").unwrap();
+        linked.update_head(&two, "commit: Two").unwrap();
+        linked
+            .update_ref("refs/bisect/bad", &two, "bisect")
+            .unwrap();
+        linked.update_ref("ORIG_HEAD", &one, "commit").unwrap();
+
+        // Branches are shared, but each worktree has its own HEAD and bisect state.
+        assert_eq!(main.read_ref("topic").unwrap(), Some(two));
+        assert_eq!(main.current_ref().unwrap(), "refs/heads/master");
+        assert_eq!(linked.current_ref().unwrap(), "refs/heads/topic");
+        assert_eq!(main.read_ref("ORIG_HEAD").unwrap(), None);
+        assert!(fs.exists(&linked_path.join("refs/bisect/bad")));
+        assert!(fs.exists(Path::new("/repo/.git/refs/heads/topic")));
+
+        let names = |refs: &Refs<MemoryFs>| -> Vec<_> {
+            refs.list(None)
+                .unwrap()
+                .into_iter()
+                .map(|r| r.name)
+                .collect()
+        };
+        assert_eq!(names(&main), ["refs/heads/master", "refs/heads/topic"]);
+        assert_eq!(
+            names(&linked),
+            ["refs/bisect/bad", "refs/heads/master", "refs/heads/topic"]
+        );
+
+        assert_eq!(linked.reflog(HEAD).unwrap().len(), 2);
+        assert_eq!(main.reflog(HEAD).unwrap().len(), 1);
+        assert_eq!(main.reflog("refs/heads/topic").unwrap().len(), 2);
+        assert_eq!(
+            linked.reflogs().unwrap(),
+            ["HEAD", "refs/heads/master", "refs/heads/topic"]
+        );
+    }
+
+    #[test]
+    fn checks_ref_names_like_git() {
+        let check = |name| check_ref_format(name, Default::default()).is_some();
+        for name in [
+            "refs/heads/master",
+            "refs/heads/feature/nested-name",
+            "refs/tags/v1.0",
+            "refs/heads/a@b",
+            "refs/heads/caf\u{e9}",
+            "refs/heads/x.lockx",
+        ] {
+            assert!(check(name), "{:?} was rejected", name);
+        }
+        for name in [
+            "",
+            "master",
+            "refs/heads/",
+            "/refs/heads/master",
+            "refs//heads/master",
+            "refs/heads/.hidden",
+            "refs/heads/a..b",
+            "refs/heads/branch.lock",
+            "refs/heads/branch.lock/child",
+            "refs/heads/ends-with.",
+            "refs/heads/a@{1}",
+            "refs/heads/with space",
+            "refs/heads/tab\there",
+            "refs/heads/del\x7f",
+            "refs/heads/a~1",
+            "refs/heads/a^",
+            "refs/heads/a:b",
+            "refs/heads/a?",
+            "refs/heads/a*",
+            "refs/heads/[a]",
+            "refs/heads/back\\slash",
+            "@",
+        ] {
+            assert!(!check(name), "{:?} was allowed", name);
+        }
+
+        let onelevel = RefFormatOptions {
+            allow_onelevel: true,
+            ..Default::default()
+        };
+        assert_eq!(check_ref_format("HEAD", onelevel), Some("HEAD".to_owned()));
+        assert_eq!(check_ref_format("@", onelevel), None);
+
+        let pattern = RefFormatOptions {
+            refspec_pattern: true,
+            ..Default::default()
+        };
+        assert!(check_ref_format("refs/heads/*", pattern).is_some());
+        assert!(check_ref_format("refs/heads/feat-*", pattern).is_some());
+        assert!(check_ref_format("refs/*/*", pattern).is_none());
+
+        let normalize = RefFormatOptions {
+            normalize: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            check_ref_format("//refs///heads/master", normalize),
+            Some("refs/heads/master".to_owned())
+        );
+        assert_eq!(check_ref_format("/master", normalize), None);
+    }
+
+    #[test]
+    fn checks_branch_and_tag_names() {
+        assert!(is_valid_branch_name("topic"));
+        assert!(is_valid_branch_name("feature/x"));
+        assert!(!is_valid_branch_name("-topic"));
+        assert!(!is_valid_branch_name("HEAD"));
+        assert!(!is_valid_branch_name("a..b"));
+        assert!(is_valid_tag_name("v1.0"));
+        assert!(is_valid_tag_name("HEAD"));
+        assert!(!is_valid_tag_name("-v1"));
 
-        Some(s)
+        let (_, refs) = refs();
+        let oid = ObjectId::from([0xab; 20]);
+        assert!(refs.update_ref("refs/heads/ok", &oid, "branch").is_ok());
+        assert!(matches!(
+            refs.update_ref("refs/heads/bad..name", &oid, "branch"),
+            Err(crate::Error::Ref(RefError::InvalidRefName(_)))
+        ));
+        assert!(refs.create_branch("-x", &oid, "branch: Created").is_err());
     }
 }
@@ -0,0 +1,844 @@
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use thiserror::Error;
+
+use crate::config::Config;
+use crate::database::{Commit, Database, DatabaseError, ObjectId, Tree, TreeEntry};
+use crate::hooks;
+#[cfg(not(target_os = "wasi"))]
+use crate::http::HttpClient;
+use crate::pack::{self, ObjectType, Pack, PackEntry, PackOptions};
+use crate::progress::Progress;
+use crate::protocol;
+use crate::refs::{RefError, HEAD};
+use crate::repository::Repository;
+use crate::vfs::Vfs;
+use crate::Result;
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum RemoteError {
+    #[error("'{0}' does not appear to be a git repository")]
+    NotARepository(String),
+    #[error("invalid refspec '{0}'")]
+    InvalidRefspec(String),
+    #[error("HTTP isn't supported on this platform: '{0}'")]
+    UnsupportedProtocol(String),
+    #[error("bad numeric config value '{1}' for '{0}'")]
+    BadNumber(String, String),
+    #[error("protocol error: bad line length character: {0}")]
+    BadPacketLength(String),
+    #[error("protocol error: bad band #{0}")]
+    BadBand(u8),
+    #[error("remote error: {0}")]
+    Remote(String),
+    #[error("protocol error: unexpected '{0}'")]
+    UnexpectedPacket(String),
+    #[error("'{0}' can only be fetched from")]
+    ReadOnly(String),
+}
+
+/// A repository to push to or fetch from: one on the local filesystem, given by its path
+/// or a `file://` URL, one served by `git daemon`, given by a `git://` URL, or one served
+/// over smart HTTP, given by an `http://` or `https://` URL.
+pub struct Remote {
+    url: String,
+    transport: Transport,
+}
+
+enum Transport {
+    Local(Repository),
+    /// The daemon's `host:port`, and the path of the repository on it.
+    Daemon(String, String),
+    #[cfg(not(target_os = "wasi"))]
+    Http(HttpClient),
+}
+
+impl Remote {
+    /// Open the repository at `url`, relative to `root_path`. This can be a working copy, in
+    /// which case its `.git` directory is used, or a bare repository.
+    pub fn open(url: &str, root_path: &Path) -> Result<Self> {
+        if url.starts_with("http://") || url.starts_with("https://") {
+            #[cfg(target_os = "wasi")]
+            return Err(RemoteError::UnsupportedProtocol(url.to_owned()).into());
+
+            #[cfg(not(target_os = "wasi"))]
+            {
+                let config = Repository::open(root_path).config()?;
+                let options = HttpOptions::for_url(&config, url, |name| std::env::var(name).ok())?;
+                return Self::http(url, &options);
+            }
+        }
+        if let Some(rest) = url.strip_prefix("git://") {
+            let (host, path) = rest
+                .find('/')
+                .map(|slash| rest.split_at(slash))
+                .ok_or_else(|| RemoteError::NotARepository(url.to_owned()))?;
+            let address = match host.contains(':') {
+                true => host.to_owned(),
+                false => format!("{}:{}", host, protocol::DEFAULT_PORT),
+            };
+            return Ok(Self {
+                url: url.to_owned(),
+                transport: Transport::Daemon(address, path.to_owned()),
+            });
+        }
+
+        let path = root_path.join(url.strip_prefix("file://").unwrap_or(url));
+        let git_path = [path.join(".git"), path]
+            .iter()
+            .find(|path| path.join("objects").is_dir())
+            .cloned()
+            .ok_or_else(|| RemoteError::NotARepository(url.to_owned()))?;
+        // Pushing would write to it, so it has to be a repository nit can safely change.
+        let repo = Repository::new(git_path);
+        repo.check_format()?;
+
+        Ok(Self {
+            url: url.to_owned(),
+            transport: Transport::Local(repo),
+        })
+    }
+
+    /// The repository served over smart HTTP at `url`, talked to as `options` say.
+    #[cfg(not(target_os = "wasi"))]
+    pub fn http(url: &str, options: &HttpOptions) -> Result<Self> {
+        Ok(Self {
+            url: url.to_owned(),
+            transport: Transport::Http(HttpClient::new(url, options)?),
+        })
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Get a reference to the remote's repository, which only a remote on the local
+    /// filesystem has.
+    pub fn repository(&self) -> Result<&Repository> {
+        match &self.transport {
+            Transport::Local(repo) => Ok(repo),
+            _ => Err(RemoteError::ReadOnly(self.url.clone()).into()),
+        }
+    }
+
+    /// The refs the remote offers to a fetch, as git advertises them: HEAD, if it points at
+    /// something, and then every ref in order. Each annotated tag is followed by the object
+    /// it peels to, named `<tag>^{}`.
+    pub fn advertised_refs(&self) -> Result<Vec<(String, ObjectId)>> {
+        match &self.transport {
+            Transport::Local(repo) => advertised_refs(repo),
+            Transport::Daemon(address, path) => {
+                let (mut stream, advertised) = protocol::connect(address, path)?;
+                protocol::write_flush(&mut stream)?;
+                Ok(advertised)
+            }
+            #[cfg(not(target_os = "wasi"))]
+            Transport::Http(client) => client.advertised_refs(),
+        }
+    }
+
+    /// The remote's refs under `refs/`, in order.
+    pub fn list_refs(&self) -> Result<Vec<(String, ObjectId)>> {
+        let mut refs = self.advertised_refs()?;
+        refs.retain(|(name, _)| name.starts_with("refs/") && !name.ends_with("^{}"));
+        Ok(refs)
+    }
+
+    /// Copy everything reachable from `tips` that `repo` doesn't have from the remote into
+    /// `repo`'s database, returning how many objects were copied.
+    pub fn fetch_objects(
+        &self,
+        repo: &Repository,
+        tips: &[ObjectId],
+        progress: &mut dyn Progress,
+    ) -> Result<usize> {
+        let mut wants = Vec::new();
+        for tip in tips {
+            if !wants.contains(tip) && !repo.database().contains(tip)? {
+                wants.push(*tip);
+            }
+        }
+        if wants.is_empty() {
+            return Ok(0);
+        }
+
+        let mut haves: Vec<_> = repo.refs().read_head()?.into_iter().collect();
+        for r in repo.refs().list(Some("refs/"))? {
+            haves.extend(r.oid.filter(|oid| !haves.contains(oid)));
+        }
+
+        match &self.transport {
+            Transport::Local(remote) => {
+                // git runs upload-pack for a local remote too, so serve the fetch the same
+                // way, if in this process, and pass on what it says as it would be.
+                let mut request = Vec::new();
+                protocol::write_fetch_request(&mut request, &wants, &haves)?;
+                let mut response = Vec::new();
+                protocol::upload_pack(remote, &mut &request[..], &mut response)?;
+
+                let mut response = &response[..];
+                protocol::read_advertisement(&mut response)?;
+                protocol::read_fetch_response(&mut response, repo.database(), progress)
+            }
+            Transport::Daemon(address, path) => {
+                let (mut stream, _) = protocol::connect(address, path)?;
+                protocol::fetch_pack(&mut stream, &wants, &haves, repo.database(), progress)
+            }
+            #[cfg(not(target_os = "wasi"))]
+            Transport::Http(client) => client.fetch_pack(&wants, &haves, repo.database(), progress),
+        }
+    }
+}
+
+/// The refs a repository offers to a fetch, as `Remote::advertised_refs` lists them.
+pub fn advertised_refs(repo: &Repository) -> Result<Vec<(String, ObjectId)>> {
+    let refs = repo.refs();
+    let mut advertised = Vec::new();
+    if let Some(head) = refs.read_head()? {
+        advertised.push((HEAD.to_owned(), head));
+    }
+
+    for r in refs.list(Some("refs/"))? {
+        let oid = match r.oid {
+            Some(oid) => oid,
+            None => continue,
+        };
+        let peeled = peel_tag(repo.database(), &oid)?;
+        advertised.push((r.name.clone(), oid));
+        if let Some(peeled) = peeled {
+            advertised.push((format!("{}^{{}}", r.name), peeled));
+        }
+    }
+
+    Ok(advertised)
+}
+
+/// Follow an annotated tag, and any tags it's a tag of, to the object it's for. Returns
+/// `None` if the object isn't a tag.
+pub(crate) fn peel_tag(database: &Database, oid: &ObjectId) -> Result<Option<ObjectId>> {
+    let mut peeled = None;
+    let mut oid = *oid;
+    loop {
+        let (kind, data) = database.read_object(&oid)?;
+        if kind != "tag" {
+            return Ok(peeled);
+        }
+
+        let target = data
+            .strip_prefix(b"object ")
+            .and_then(|rest| rest.get(..ObjectId::HEX_LEN))
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .ok_or(DatabaseError::BadObject("tag"))?;
+        oid = ObjectId::from_hex(target)?;
+        peeled = Some(oid);
+    }
+}
+
+/// How to talk to a remote over HTTP, from the `http.*` config variables. Any of them can
+/// be set for just the URLs under a prefix with `http.<url>.*`, and the longest matching
+/// prefix wins. The `GIT_SSL_*` and `GIT_HTTP_LOW_SPEED_*` environment variables override
+/// the config, and the usual proxy environment variables are used if no proxy is set. A
+/// proxy set to nothing turns the proxy off, whatever the environment says.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpOptions {
+    pub proxy: Option<String>,
+    pub ssl_verify: bool,
+    /// A file of certificates to verify the server with, in place of the system's.
+    pub ssl_ca_info: Option<PathBuf>,
+    /// A directory of certificates to verify the server with, in place of the system's.
+    pub ssl_ca_path: Option<PathBuf>,
+    /// Give up on a transfer that's slower than this many bytes a second...
+    pub low_speed_limit: Option<u64>,
+    /// ...for this many seconds.
+    pub low_speed_time: Option<u64>,
+}
+
+impl HttpOptions {
+    /// The options for `url`, with `env` looking up environment variables.
+    pub fn for_url<F: Vfs>(
+        config: &Config<F>,
+        url: &str,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self> {
+        let mut prefixes: Vec<_> = config
+            .subsections("http")
+            .into_iter()
+            .filter(|prefix| url_matches(prefix, url))
+            .collect();
+        prefixes.sort_by_key(|prefix| std::cmp::Reverse(prefix.trim_end_matches('/').len()));
+
+        let get = |name: &str| -> Result<Option<String>> {
+            for prefix in &prefixes {
+                if let Some(value) = config.get(&format!("http.{}.{}", prefix, name))? {
+                    return Ok(Some(value));
+                }
+            }
+            config.get(&format!("http.{}", name))
+        };
+        let number = |name: &str, var: &str| -> Result<Option<u64>> {
+            match env(var).or(get(name)?) {
+                Some(value) => match value.trim().parse() {
+                    Ok(n) => Ok(Some(n)),
+                    Err(_) => Err(RemoteError::BadNumber(format!("http.{}", name), value).into()),
+                },
+                None => Ok(None),
+            }
+        };
+
+        let proxy = match get("proxy")? {
+            Some(proxy) => Some(proxy),
+            None => {
+                let vars: &[&str] = match url.starts_with("https://") {
+                    true => &["https_proxy", "HTTPS_PROXY", "all_proxy", "ALL_PROXY"],
+                    false => &["http_proxy", "all_proxy", "ALL_PROXY"],
+                };
+                vars.iter().find_map(|var| env(var))
+            }
+        };
+        let ssl_verify = match env("GIT_SSL_NO_VERIFY") {
+            Some(_) => false,
+            None => get("sslVerify")?.is_none_or(|value| {
+                !matches!(
+                    value.to_ascii_lowercase().as_str(),
+                    "false" | "no" | "off" | "0"
+                )
+            }),
+        };
+
+        Ok(Self {
+            proxy: proxy.filter(|proxy| !proxy.is_empty()),
+            ssl_verify,
+            ssl_ca_info: env("GIT_SSL_CAINFO")
+                .or(get("sslCAInfo")?)
+                .map(PathBuf::from),
+            ssl_ca_path: env("GIT_SSL_CAPATH")
+                .or(get("sslCAPath")?)
+                .map(PathBuf::from),
+            low_speed_limit: number("lowSpeedLimit", "GIT_HTTP_LOW_SPEED_LIMIT")?,
+            low_speed_time: number("lowSpeedTime", "GIT_HTTP_LOW_SPEED_TIME")?,
+        })
+    }
+}
+
+/// Whether the URL in an `http.<url>.*` key applies to a URL: the scheme and host must be
+/// the same, and the path must be a prefix of the URL's path, ending at a `/`.
+fn url_matches(prefix: &str, url: &str) -> bool {
+    let split = |url: &str| {
+        let (scheme, rest) = url.split_once("://")?;
+        let (host, path) = rest.split_once('/').unwrap_or((rest, ""));
+        Some((
+            scheme.to_ascii_lowercase(),
+            host.to_ascii_lowercase(),
+            path.to_owned(),
+        ))
+    };
+
+    match (split(prefix), split(url)) {
+        (Some((scheme, host, path)), Some((url_scheme, url_host, url_path))) => {
+            let path = path.trim_end_matches('/');
+            scheme == url_scheme
+                && host == url_host
+                && url_path
+                    .strip_prefix(path)
+                    .is_some_and(|rest| path.is_empty() || rest.is_empty() || rest.starts_with('/'))
+        }
+        _ => false,
+    }
+}
+
+/// Which refs to copy from one repository to another, such as `refs/heads/main`,
+/// `main:topic` or `+refs/heads/*:refs/remotes/origin/*`. A leading `+` allows updates
+/// that aren't fast-forwards, and a `*` in both sides matches any part of a name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Refspec {
+    pub force: bool,
+    /// What to copy, or empty to delete the destination.
+    pub source: String,
+    pub destination: String,
+}
+
+impl FromStr for Refspec {
+    type Err = RemoteError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (force, spec) = match s.strip_prefix('+') {
+            Some(spec) => (true, spec),
+            None => (false, s),
+        };
+        let (source, destination) = spec.split_once(':').unwrap_or((spec, spec));
+
+        let wildcards = (
+            source.matches('*').count(),
+            destination.matches('*').count(),
+        );
+        if destination.is_empty() || !matches!(wildcards, (0, 0) | (1, 1)) {
+            return Err(RemoteError::InvalidRefspec(s.to_owned()));
+        }
+
+        Ok(Self {
+            force,
+            source: source.to_owned(),
+            destination: destination.to_owned(),
+        })
+    }
+}
+
+impl Refspec {
+    /// The destination a ref named `name` is copied to, if the source matches it.
+    pub fn map(&self, name: &str) -> Option<String> {
+        match (
+            self.source.split_once('*'),
+            self.destination.split_once('*'),
+        ) {
+            (Some((src_prefix, src_suffix)), Some((dst_prefix, dst_suffix))) => {
+                let middle = name.strip_prefix(src_prefix)?.strip_suffix(src_suffix)?;
+                Some(format!("{}{}{}", dst_prefix, middle, dst_suffix))
+            }
+            (None, None) if self.source == name => Some(self.destination.clone()),
+            _ => None,
+        }
+    }
+
+    /// The refspec that maps the other way, from the destination back to the source.
+    pub fn reverse(&self) -> Refspec {
+        Refspec {
+            force: self.force,
+            source: self.destination.clone(),
+            destination: self.source.clone(),
+        }
+    }
+}
+
+/// How many objects to copy between progress reports.
+const PROGRESS_INTERVAL: usize = 100;
+
+/// How many objects a pack has to have to be kept as a pack once copied, rather than
+/// stored as loose objects, as with git's `transfer.unpackLimit`.
+const UNPACK_LIMIT: usize = 100;
+
+/// Copy every object reachable from `tips` that `to` is missing from `from`, returning how
+/// many were copied. A commit that `to` already has is taken to come with its history.
+///
+/// The objects are sent as a thin pack: files can be stored as deltas against the versions
+/// in the commits `to` already has, which are left out of the pack and filled back in from
+/// `to`'s own database when the pack is stored. Small packs are unpacked into loose objects.
+pub fn copy_objects(
+    from: &Database,
+    to: &Database,
+    tips: &[ObjectId],
+    progress: &mut dyn Progress,
+) -> Result<usize> {
+    let mut queue: Vec<(ObjectId, OsString)> =
+        tips.iter().map(|oid| (*oid, OsString::new())).collect();
+    let mut seen = HashSet::new();
+    let mut entries = Vec::new();
+    let mut boundary = Vec::new();
+
+    while let Some((oid, name)) = queue.pop() {
+        if !seen.insert(oid) {
+            continue;
+        }
+        if to.contains(&oid)? {
+            boundary.push(oid);
+            continue;
+        }
+
+        let (kind, data) = from.read_object(&oid)?;
+        match kind.as_str() {
+            "commit" => {
+                let commit = Commit::parse(&data)?;
+                queue.push((*commit.tree(), OsString::new()));
+                queue.extend(commit.parents().iter().map(|oid| (*oid, OsString::new())));
+            }
+            "tree" => {
+                for (name, entry) in Tree::parse(&data)?.entries() {
+                    if let TreeEntry::Stored(entry) = entry {
+                        queue.push((*entry.oid(), name.clone()));
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        let kind = ObjectType::from_name(&kind)
+            .ok_or_else(|| DatabaseError::Corrupt(oid.to_string(), "unknown object type"))?;
+        entries.push(PackEntry {
+            oid,
+            kind,
+            data,
+            name: name.to_string_lossy().into_owned(),
+        });
+        if entries.len() % PROGRESS_INTERVAL == 0 {
+            progress.message(&format!("Copying objects: {}\r", entries.len()));
+        }
+    }
+
+    if entries.is_empty() {
+        return Ok(0);
+    }
+
+    let bases = preferred_bases(to, &boundary, &entries)?;
+    let bytes = pack::write_thin_pack(&entries, &bases, &PackOptions::default());
+    store_thin_pack(to, &bytes)?;
+
+    progress.message(&format!("Copying objects: {}, done.\n", entries.len()));
+    Ok(entries.len())
+}
+
+/// A ref update asked for by a push: move `name` from `old` to `new`, where `None` is a ref
+/// that doesn't exist, so that a `new` of `None` deletes the ref.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RefCommand {
+    pub name: String,
+    pub old: Option<ObjectId>,
+    pub new: Option<ObjectId>,
+}
+
+impl RefCommand {
+    /// The command as hooks are given it on stdin: `<old> <new> <name>`.
+    fn hook_line(&self) -> String {
+        let old = self.old.unwrap_or(ObjectId::NULL);
+        let new = self.new.unwrap_or(ObjectId::NULL);
+        format!("{} {} {}\n", old, new, self.name)
+    }
+}
+
+/// What receive-pack made of a push.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReceiveReport {
+    /// Why each update was refused, or `None` if it was made.
+    pub results: Vec<Option<String>>,
+    /// Whatever the hooks printed.
+    pub output: Vec<u8>,
+}
+
+/// Make the ref updates a push asks for in `repo`, as receive-pack does once the objects
+/// they need have arrived. Each ref is only moved if it's still at its `old`. With
+/// `atomic`, the updates are made in a single transaction, so that if any of them can't be
+/// made, none are.
+///
+/// The repository's hooks get their say as they do in git. `pre-receive` is given every
+/// update on stdin, one `<old> <new> <ref>` line each, and can refuse them all; `update` is
+/// run for each ref with its name, old and new ids as arguments, and can refuse that one;
+/// and `post-receive` is told about the updates that were made once they have been.
+pub fn receive_pack(
+    repo: &Repository,
+    commands: &[RefCommand],
+    atomic: bool,
+) -> Result<ReceiveReport> {
+    let mut output = Vec::new();
+    let mut results = Vec::new();
+    for command in commands {
+        let missing = match &command.new {
+            Some(new) => !repo.database().contains(new)?,
+            None => false,
+        };
+        results.push(Some("missing necessary objects".to_owned()).filter(|_| missing));
+    }
+
+    // What hooks are told about: the updates that haven't been refused.
+    let pending = |results: &[Option<String>]| -> String {
+        commands
+            .iter()
+            .zip(results)
+            .filter(|(_, result)| result.is_none())
+            .map(|(command, _)| command.hook_line())
+            .collect()
+    };
+    let hook = match pending(&results) {
+        stdin if stdin.is_empty() => None,
+        stdin => hooks::run_hook(repo, "pre-receive", &[], stdin.as_bytes())?,
+    };
+    if let Some(hook) = hook {
+        output.extend(hook.output);
+        if !hook.success {
+            for result in &mut results {
+                result.get_or_insert_with(|| "pre-receive hook declined".to_owned());
+            }
+        }
+    }
+    for (command, result) in commands.iter().zip(&mut results) {
+        if result.is_some() {
+            continue;
+        }
+        let old = command.old.unwrap_or(ObjectId::NULL).to_string();
+        let new = command.new.unwrap_or(ObjectId::NULL).to_string();
+        let args = [command.name.as_str(), &old, &new];
+        if let Some(hook) = hooks::run_hook(repo, "update", &args, &[])? {
+            output.extend(hook.output);
+            if !hook.success {
+                *result = Some("hook declined".to_owned());
+            }
+        }
+    }
+
+    let results = apply_ref_commands(repo, commands, results, atomic)?;
+
+    let made = pending(&results);
+    if !made.is_empty() {
+        if let Some(hook) = hooks::run_hook(repo, "post-receive", &[], made.as_bytes())? {
+            output.extend(hook.output);
+        }
+    }
+
+    Ok(ReceiveReport { results, output })
+}
+
+/// Make the updates that haven't already been refused, noting why any that can't be made
+/// were refused.
+fn apply_ref_commands(
+    repo: &Repository,
+    commands: &[RefCommand],
+    mut results: Vec<Option<String>>,
+    atomic: bool,
+) -> Result<Vec<Option<String>>> {
+    if atomic {
+        if results.iter().any(Option::is_some) {
+            for result in &mut results {
+                result.get_or_insert_with(|| "atomic transaction failed".to_owned());
+            }
+            return Ok(results);
+        }
+
+        let mut transaction = repo.refs().transaction();
+        for command in commands {
+            let (old, new) = (command.old.as_ref(), command.new.as_ref());
+            transaction.update(&command.name, old, new, "push");
+        }
+        match transaction.commit() {
+            Ok(()) => {}
+            Err(crate::Error::Ref(RefError::Stale(name, ..))) => {
+                for (command, result) in commands.iter().zip(&mut results) {
+                    *result = Some(match command.name == name {
+                        true => "failed to update ref".to_owned(),
+                        false => "atomic transaction failed".to_owned(),
+                    });
+                }
+            }
+            Err(e) => return Err(e),
+        }
+        return Ok(results);
+    }
+
+    for (command, result) in commands.iter().zip(&mut results) {
+        if result.is_some() {
+            continue;
+        }
+        let (old, new) = (command.old.as_ref(), command.new.as_ref());
+        match repo
+            .refs()
+            .compare_and_swap(&command.name, old, new, "push")
+        {
+            Ok(()) => {}
+            Err(crate::Error::Ref(RefError::Stale(..))) => {
+                *result = Some("failed to update ref".to_owned());
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(results)
+}
+
+/// Complete a thin pack that's been received with the bases it needs from `database`, and
+/// store its objects there. Returns how many objects the pack held.
+pub(crate) fn store_thin_pack(database: &Database, bytes: &[u8]) -> Result<usize> {
+    let (bytes, pack) = Pack::fix_thin(bytes, &|oid| database.find_object(oid))?;
+    if pack.objects().len() < UNPACK_LIMIT {
+        for object in pack.objects() {
+            database.store_raw(object.kind.name(), &object.data)?;
+        }
+    } else {
+        database.store_pack(&bytes, &pack)?;
+    }
+
+    Ok(pack.objects().len())
+}
+
+/// The files and trees in the commits on the edge of what's being sent, which the
+/// receiving side already has, that share a name with something being sent and so may be
+/// good bases for its delta.
+pub(crate) fn preferred_bases(
+    database: &Database,
+    boundary: &[ObjectId],
+    entries: &[PackEntry],
+) -> Result<Vec<PackEntry>> {
+    let names: HashSet<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+    let mut queue = Vec::new();
+    for oid in boundary {
+        if let Some((ObjectType::Commit, data)) = database.find_object(oid)? {
+            queue.push((*Commit::parse(&data)?.tree(), OsString::new()));
+        }
+    }
+
+    let mut seen = HashSet::new();
+    let mut bases = Vec::new();
+    while let Some((oid, name)) = queue.pop() {
+        if !seen.insert(oid) {
+            continue;
+        }
+
+        let name = name.to_string_lossy().into_owned();
+        let (kind, data) = match database.find_object(&oid)? {
+            Some(object) => object,
+            None => continue,
+        };
+        if kind == ObjectType::Tree {
+            for (name, entry) in Tree::parse(&data)?.entries() {
+                if let TreeEntry::Stored(entry) = entry {
+                    queue.push((*entry.oid(), name.clone()));
+                }
+            }
+        }
+        if names.contains(name.as_str()) {
+            bases.push(PackEntry {
+                oid,
+                kind,
+                data,
+                name,
+            });
+        }
+    }
+
+    Ok(bases)
+}
+
+/// Split a side-band stream from a remote into its bands: band 1 is data, written to
+/// `data`; band 2 is progress, passed on prefixed with `remote: `; and band 3 is an error
+/// that ends the stream. Each packet is a 4-digit hex length, counting itself, followed by
+/// the band number and the payload. The stream ends with a `0000` flush packet.
+pub fn demux_sideband(
+    mut input: impl Read,
+    data: &mut impl Write,
+    progress: &mut dyn Progress,
+) -> Result<()> {
+    while let Some(packet) = protocol::read_packet(&mut input)? {
+        let (band, payload) = packet.split_first().ok_or(RemoteError::BadBand(0))?;
+
+        match band {
+            1 => data.write_all(payload)?,
+            2 => progress.message(&format!("remote: {}", String::from_utf8_lossy(payload))),
+            3 => {
+                let message = String::from_utf8_lossy(payload).trim_end().to_owned();
+                return Err(RemoteError::Remote(message).into());
+            }
+            band => return Err(RemoteError::BadBand(*band).into()),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::vfs::MemoryFs;
+
+    #[test]
+    fn maps_refs_through_refspecs() {
+        let map = |spec: &str, name| spec.parse::<Refspec>().unwrap().map(name);
+
+        assert_eq!(
+            map("+refs/heads/*:refs/remotes/origin/*", "refs/heads/main"),
+            Some("refs/remotes/origin/main".to_owned())
+        );
+        assert_eq!(
+            map(
+                "refs/heads/main:refs/remotes/origin/trunk",
+                "refs/heads/main"
+            ),
+            Some("refs/remotes/origin/trunk".to_owned())
+        );
+        assert_eq!(
+            map(
+                "refs/heads/main:refs/remotes/origin/trunk",
+                "refs/heads/dev"
+            ),
+            None
+        );
+
+        assert!("refs/heads/*:refs/heads/main".parse::<Refspec>().is_err());
+        assert_eq!(":refs/heads/gone".parse::<Refspec>().unwrap().source, "");
+    }
+
+    #[test]
+    fn reads_http_options_for_a_url() {
+        let fs = MemoryFs::new();
+        fs.write(
+            Path::new("/config"),
+            "[http]\n\tproxy = http://proxy:3128\n\tlowSpeedLimit = 1000\n\
+             [http \"https://example.com\"]\n\tsslVerify = false\n\
+             [http \"https://example.com/team/\"]\n\tproxy = \n\tlowSpeedTime = 30\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        let mut config = Config::with_vfs(fs, "/config");
+        config.load().unwrap();
+
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(var, _)| *var == name)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+        let host = env(&[("https_proxy", "http://env-proxy:8080")]);
+
+        // An empty proxy turns the proxy off, rather than falling back to the environment.
+        let options =
+            HttpOptions::for_url(&config, "https://example.com/team/repo.git", host).unwrap();
+        assert_eq!(options.proxy, None);
+        assert!(!options.ssl_verify);
+        assert_eq!(options.low_speed_limit, Some(1000));
+        assert_eq!(options.low_speed_time, Some(30));
+
+        let options =
+            HttpOptions::for_url(&config, "https://example.com/teamwork.git", host).unwrap();
+        assert_eq!(options.proxy.as_deref(), Some("http://proxy:3128"));
+        assert_eq!(options.low_speed_time, None);
+
+        let options = HttpOptions::for_url(&config, "https://example.org/repo.git", host).unwrap();
+        assert!(options.ssl_verify);
+
+        let overrides = env(&[
+            ("GIT_SSL_NO_VERIFY", "1"),
+            ("GIT_HTTP_LOW_SPEED_LIMIT", "10"),
+            ("GIT_HTTP_LOW_SPEED_TIME", "5"),
+        ]);
+        let options =
+            HttpOptions::for_url(&config, "https://example.org/repo.git", overrides).unwrap();
+        assert!(!options.ssl_verify);
+        assert_eq!(options.low_speed_limit, Some(10));
+        assert_eq!(options.low_speed_time, Some(5));
+
+        let mut empty = Config::with_vfs(MemoryFs::new(), "/config");
+        empty.load().unwrap();
+        let options = HttpOptions::for_url(&empty, "https://example.org/repo.git", host).unwrap();
+        assert_eq!(options.proxy.as_deref(), Some("http://env-proxy:8080"));
+        let options = HttpOptions::for_url(&empty, "http://example.org/repo.git", host).unwrap();
+        assert_eq!(options.proxy, None);
+    }
+
+    #[test]
+    fn splits_sideband_streams() {
+        let stream = b"0009\x01data001a\x02Counting objects: 1\r\n0009\x01more0000";
+        let mut data = Vec::new();
+        let mut progress: Vec<String> = Vec::new();
+
+        demux_sideband(&stream[..], &mut data, &mut progress).unwrap();
+        assert_eq!(data, b"datamore");
+        assert_eq!(progress, vec!["remote: Counting objects: 1\r\n"]);
+
+        let err = demux_sideband(&b"0013\x03access denied\n"[..], &mut data, &mut progress);
+        assert!(matches!(
+            err,
+            Err(crate::Error::Remote(RemoteError::Remote(message))) if message == "access denied"
+        ));
+        assert!(demux_sideband(&b"zzzz"[..], &mut data, &mut progress).is_err());
+    }
+}
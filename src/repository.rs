@@ -0,0 +1,748 @@
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::{self, Path, PathBuf},
+    process::Command,
+};
+
+use chrono::{DateTime, FixedOffset, Local};
+use thiserror::Error;
+
+use crate::config::Config;
+use crate::database::{Blob, Commit, Database, Identity, Object, ObjectId, Tree};
+use crate::diff::RawChange;
+use crate::fsmonitor::FsMonitor;
+use crate::ignore::Ignores;
+use crate::index::Index;
+use crate::inspector::{Inspector, WorkspaceScan};
+use crate::lockfile::LockfileError;
+use crate::merge;
+use crate::migration::Migration;
+use crate::refs::{Refs, HEAD};
+use crate::remote::Refspec;
+use crate::revision::Revision;
+use crate::revwalk::RevWalk;
+use crate::vfs::{RealFs, Vfs};
+use crate::workspace::Workspace;
+use crate::Result;
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum RepositoryError {
+    #[error("Cannot setup tracking information; starting point '{0}' is not a branch.")]
+    NotABranch(String),
+    #[error("the requested upstream branch '{0}' does not exist")]
+    NoSuchUpstream(String),
+    #[error("this operation must be run in a work tree")]
+    NoWorkTree,
+    #[error("Aborting commit due to empty commit message.")]
+    EmptyMessage,
+    #[error("not a git repository: {0}")]
+    NotARepository(PathBuf),
+    #[error("expected git repo version <= 1, found {0}")]
+    UnsupportedFormatVersion(usize),
+    #[error("unknown repository extension found: {0}")]
+    UnsupportedExtension(String),
+}
+
+/// The branch another branch tracks: the remote it lives on (`.` for the local repository)
+/// and the name of the branch on that remote.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Upstream {
+    pub remote: String,
+    pub merge: String,
+}
+
+/// How far a branch has moved on from its upstream, and vice versa.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    /// The full name of the upstream's local ref, e.g. `refs/remotes/origin/main`.
+    pub upstream: String,
+    /// Whether the upstream ref actually exists.
+    pub upstream_exists: bool,
+    pub ahead: usize,
+    pub behind: usize,
+}
+
+/// How the index differs from HEAD, and the working tree from the index.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Status {
+    /// Changes that have been added to the index, and would be in the next commit.
+    pub staged: Vec<RawChange>,
+    /// Changes to tracked files that haven't been added to the index.
+    pub unstaged: Vec<RawChange>,
+    /// Files the index doesn't track and that aren't ignored.
+    pub untracked: Vec<String>,
+}
+
+impl Status {
+    /// Every tracked path that has changed, with the two letters `status --short` shows for
+    /// it: how the index differs from HEAD, then how the working tree differs from the index,
+    /// with a space for no change, e.g. `M `, ` D` or `AM`.
+    pub fn tracked_codes(&self) -> BTreeMap<PathBuf, String> {
+        let mut codes: BTreeMap<PathBuf, [char; 2]> = BTreeMap::new();
+        for change in &self.staged {
+            codes.entry(change.path.clone()).or_insert([' ', ' '])[0] = change.status().letter();
+        }
+        for change in &self.unstaged {
+            codes.entry(change.path.clone()).or_insert([' ', ' '])[1] = change.status().letter();
+        }
+
+        codes
+            .into_iter()
+            .map(|(path, code)| (path, code.iter().collect()))
+            .collect()
+    }
+}
+
+/// Who a commit is by, and when it's made. Giving a timestamp makes a commit that comes out
+/// the same every time, as tests and imports need.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommitOptions {
+    pub author: Identity,
+    /// Who made the commit, if not the author.
+    pub committer: Option<Identity>,
+    /// When the change was authored and committed, rather than now.
+    pub timestamp: Option<DateTime<FixedOffset>>,
+}
+
+impl CommitOptions {
+    /// A commit by `author`, made now.
+    pub fn new(author: Identity) -> Self {
+        Self {
+            author,
+            committer: None,
+            timestamp: None,
+        }
+    }
+
+    pub fn with_committer(mut self, committer: Identity) -> Self {
+        self.committer = Some(committer);
+        self
+    }
+
+    pub fn with_timestamp(mut self, timestamp: DateTime<FixedOffset>) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+}
+
+/// Set to use an index file other than `.git/index`.
+pub const INDEX_FILE: &str = "GIT_INDEX_FILE";
+/// Set to the repository's `.git` directory, for when it isn't inside the working tree.
+pub const GIT_DIR: &str = "GIT_DIR";
+/// Set to the working tree, for when it isn't the directory that holds `.git`.
+pub const WORK_TREE: &str = "GIT_WORK_TREE";
+
+/// The highest `core.repositoryformatversion` there is. Version 1 is version 0 with the
+/// `extensions.*` settings that say what else a repository needs.
+const MAX_FORMAT_VERSION: usize = 1;
+
+/// The extensions a version 1 repository can ask for that nit knows what to do with.
+/// `objectFormat` is only allowed if it's `sha1`, which is what a repository uses without it.
+const SUPPORTED_EXTENSIONS: &[&str] = &["noop", "objectformat"];
+
+/// How many files to read and store at once when adding them to the index.
+const ADD_BATCH_SIZE: usize = 1024;
+
+/// What a gitfile starts with: a `.git` file, rather than a directory, that says where the
+/// repository is, as the working trees of submodules have.
+const GITFILE_PREFIX: &[u8] = b"gitdir: ";
+
+/// Set or clear a child process's environment variables, as [`Repository::git_env`] gives
+/// them.
+pub fn set_git_env(command: &mut Command, vars: &[(&str, Option<PathBuf>)]) {
+    for (name, value) in vars {
+        match value {
+            Some(value) => command.env(name, value),
+            None => command.env_remove(name),
+        };
+    }
+}
+
+/// The `.git` directory of the repository whose working tree is at `root_path`: the one
+/// named by `GIT_DIR`, or else the `.git` directory in the working tree, or the one it
+/// points at if it's a gitfile.
+pub fn git_dir(root_path: &Path) -> PathBuf {
+    match env::var_os(GIT_DIR) {
+        Some(path) if !path.is_empty() => root_path.join(path),
+        _ => {
+            let path = root_path.join(".git");
+            match fs::read(&path).ok().and_then(|data| parse_gitfile(&data)) {
+                Some(target) => root_path.join(target),
+                None => path,
+            }
+        }
+    }
+}
+
+/// The path a gitfile's contents point at, which is relative to the directory holding it
+/// unless it's absolute, or `None` if they aren't a gitfile's.
+pub fn parse_gitfile(data: &[u8]) -> Option<PathBuf> {
+    let target = data.strip_prefix(GITFILE_PREFIX)?;
+    let target = std::str::from_utf8(target)
+        .ok()?
+        .trim_end_matches(['\n', '\r']);
+    Some(PathBuf::from(target)).filter(|target| !target.as_os_str().is_empty())
+}
+
+/// Ties together the parts of a repository that live under its `.git` directory.
+pub struct Repository<F: Vfs = RealFs> {
+    fs: F,
+    git_path: PathBuf,
+    database: Database<F>,
+    refs: Refs<F>,
+    index_path: PathBuf,
+    workspace_path: Option<PathBuf>,
+}
+
+impl Repository {
+    pub fn new(git_path: impl AsRef<Path>) -> Self {
+        Self::with_vfs(RealFs, git_path)
+    }
+
+    /// Open the repository whose working tree is at `root_path`.
+    pub fn open(root_path: impl AsRef<Path>) -> Self {
+        let root_path = root_path.as_ref();
+        Self::new(git_dir(root_path)).with_workspace_path(root_path)
+    }
+}
+
+impl<F: Vfs> Repository<F> {
+    pub fn with_vfs(fs: F, git_path: impl AsRef<Path>) -> Self {
+        let git_path = git_path.as_ref().to_owned();
+        let index_path = match env::var_os(INDEX_FILE) {
+            Some(path) if !path.is_empty() => PathBuf::from(path),
+            _ => git_path.join("index"),
+        };
+
+        Self {
+            database: Database::with_vfs(fs.clone(), git_path.join("objects")),
+            refs: Refs::with_vfs(fs.clone(), &git_path),
+            fs,
+            index_path,
+            git_path,
+            workspace_path: None,
+        }
+    }
+
+    /// Use the working tree at the given path, for the operations that need one.
+    pub fn with_workspace_path(mut self, workspace_path: impl Into<PathBuf>) -> Self {
+        self.workspace_path = Some(workspace_path.into());
+        self
+    }
+
+    /// Use a different index file, e.g. a temporary one that leaves the real index alone.
+    pub fn with_index_path(mut self, index_path: impl Into<PathBuf>) -> Self {
+        self.index_path = index_path.into();
+        self
+    }
+
+    /// The path to the repository's `.git` directory.
+    pub fn git_path(&self) -> &Path {
+        &self.git_path
+    }
+
+    /// The filesystem the repository is on.
+    pub(crate) fn fs(&self) -> &F {
+        &self.fs
+    }
+
+    /// The path to the index file: `.git/index`, unless it's been overridden.
+    pub fn index_path(&self) -> &Path {
+        &self.index_path
+    }
+
+    /// The `GIT_DIR`, `GIT_WORK_TREE` and `GIT_INDEX_FILE` for a program run on behalf of
+    /// this repository, as absolute paths, so that any git or nit it runs in turn works on
+    /// this repository rather than on whichever one this process was started in. Those that
+    /// should be unset are `None`: the working tree if there isn't one, and the index if it's
+    /// just `.git/index`.
+    pub fn git_env(&self) -> Vec<(&'static str, Option<PathBuf>)> {
+        let absolute = |path: &Path| path::absolute(path).unwrap_or_else(|_| path.to_owned());
+        let index_path =
+            Some(&self.index_path).filter(|&path| *path != self.git_path.join("index"));
+
+        vec![
+            (GIT_DIR, Some(absolute(&self.git_path))),
+            (WORK_TREE, self.workspace_path.as_deref().map(absolute)),
+            (INDEX_FILE, index_path.map(|path| absolute(path))),
+        ]
+    }
+
+    /// The repository's index, which hasn't been loaded yet.
+    pub fn index(&self) -> Index<F> {
+        Index::with_vfs(self.fs.clone(), &self.index_path)
+    }
+
+    /// Check that the repository's `.git` directory is there at all, for operations that
+    /// would otherwise take a missing one for an empty repository.
+    pub fn ensure_exists(&self) -> Result<()> {
+        match self.fs.stat(&self.git_path) {
+            Ok(stat) if stat.is_dir() => Ok(()),
+            _ => Err(RepositoryError::NotARepository(self.git_path.clone()).into()),
+        }
+    }
+
+    /// Get a reference to the repository's object database.
+    pub fn database(&self) -> &Database<F> {
+        &self.database
+    }
+
+    /// Get a reference to the repository's refs.
+    pub fn refs(&self) -> &Refs<F> {
+        &self.refs
+    }
+
+    /// The working tree set by `core.worktree`, if there is one. A relative path is taken
+    /// to be relative to the `.git` directory.
+    pub fn work_tree(&self) -> Result<Option<PathBuf>> {
+        Ok(self
+            .config()?
+            .get("core.worktree")?
+            .map(|path| self.git_path.join(path)))
+    }
+
+    /// The repository's index, which hasn't been loaded yet, set up as the config says:
+    /// written as a split index or not as `core.splitIndex` says, and otherwise as it was
+    /// read, and loaded on as many threads as `index.threads` says.
+    pub fn configured_index(&self) -> Result<Index<F>> {
+        let config = self.config()?;
+        let mut index = self.index();
+        if let Some(split) = config.get_bool("core.splitIndex")? {
+            index = index.with_split_index(split);
+        }
+        if let Some(percent) = config.get_usize("splitIndex.maxPercentChange")? {
+            index = index.with_max_percent_change(percent);
+        }
+
+        // Like git, the extensions for loading the index on several threads are written
+        // by default when some number of threads other than one is asked for.
+        let threads = match config.get("index.threads")? {
+            Some(value) => match value.trim().parse() {
+                Ok(threads) => Some(threads),
+                Err(_) => Some(match config.get_bool("index.threads")? {
+                    Some(true) => 0,
+                    _ => 1,
+                }),
+            },
+            None => None,
+        };
+        let threaded = threads.is_some_and(|threads| threads != 1);
+        if let Some(threads) = threads {
+            index = index.with_threads(threads);
+        }
+        let end_of_entries = config.get_bool("index.recordEndOfIndexEntries")?;
+        let offset_table = config.get_bool("index.recordOffsetTable")?;
+        Ok(index
+            .with_end_of_entries(end_of_entries.unwrap_or(threaded))
+            .with_offset_table(offset_table.unwrap_or(threaded)))
+    }
+
+    /// The repository's working tree, with symlinks checked out as `core.symlinks` says.
+    pub fn workspace(&self) -> Result<Workspace<F>> {
+        let symlinks = self.config()?.get_bool("core.symlinks")?.unwrap_or(true);
+        Ok(Workspace::with_vfs(self.fs.clone(), self.workspace_path()?).with_symlinks(symlinks))
+    }
+
+    /// The path to the top of the working tree, for repositories that have one.
+    pub fn workspace_path(&self) -> Result<&Path> {
+        Ok(self
+            .workspace_path
+            .as_deref()
+            .ok_or(RepositoryError::NoWorkTree)?)
+    }
+
+    /// How the index differs from HEAD's tree, and the working tree from the index. With a
+    /// `core.fsmonitor` hook, only the files it says have changed are looked at. Entries
+    /// for files whose times have changed but whose contents haven't are refreshed, and
+    /// the index written back if it isn't locked, so they needn't be read next time.
+    pub fn status(&self) -> Result<Status> {
+        self.ensure_exists()?;
+        let workspace = self.workspace()?;
+        let mut index = self.configured_index()?;
+        index.load_for_update()?;
+
+        let head = self
+            .database
+            .load_tree_list(self.refs.read_head()?.as_ref())?;
+        let monitor = FsMonitor::from_repo(self)?;
+        let mut scan = match &monitor {
+            Some(monitor) => Some(monitor.begin(self.workspace_path()?)?),
+            None => None,
+        };
+        let inspector = Inspector::new(&self.database, &index, &workspace);
+        let staged = inspector.tree_changes(&head, true)?;
+        let WorkspaceScan {
+            changes: unstaged,
+            stale,
+        } = inspector.scan_workspace(scan.as_mut())?;
+
+        for (path, stat) in &stale {
+            index.update_entry_stat(path, stat);
+        }
+        match index.write_updates() {
+            Err(crate::Error::Lockfile(LockfileError::LockDenied(_))) => {}
+            result => result?,
+        }
+        if let Some(scan) = scan {
+            scan.finish()?;
+        }
+
+        let ignores = self.ignores()?;
+        let untracked = workspace
+            .list_files_in_root()?
+            .into_iter()
+            .filter(|path| {
+                let path = Path::new(path);
+                !index.is_tracked(&path) && !ignores.is_ignored(path, false)
+            })
+            .collect();
+
+        Ok(Status {
+            staged,
+            unstaged,
+            untracked,
+        })
+    }
+
+    /// Add the files at some paths in the working tree to the index, taking everything
+    /// inside any directories among them. Relative paths are taken to be relative to the
+    /// top of the working tree.
+    pub fn add<P: AsRef<Path>>(&self, paths: &[P]) -> Result<Vec<PathBuf>> {
+        let workspace = self.workspace()?;
+        let mut index = self.configured_index()?;
+
+        with_rollback(&mut index, |index| {
+            index.load_for_update()?;
+
+            let ignores = self.ignores()?;
+            let mut files = Vec::new();
+            for path in paths {
+                files.extend(self.files_to_add(&workspace, index, &ignores, path.as_ref())?);
+            }
+            let added = self.add_to_index(index, &workspace, &files)?;
+
+            index.write_updates()?;
+            Ok(added)
+        })
+    }
+
+    /// The rules for which files in the working tree are ignored, from `.gitignore` files
+    /// and `.git/info/exclude`.
+    pub fn ignores(&self) -> Result<Ignores> {
+        Ignores::load_from(&self.fs, self.workspace_path()?, &self.git_path)
+    }
+
+    /// List the files `add` takes for a path in the working tree, relative to its top: the
+    /// file itself, or the files in a directory that are tracked or aren't ignored, just as
+    /// status leaves ignored files out of the untracked ones.
+    pub fn files_to_add(
+        &self,
+        workspace: &Workspace<F>,
+        index: &Index<F>,
+        ignores: &Ignores,
+        path: &Path,
+    ) -> Result<Vec<String>> {
+        let path = self.workspace_path()?.join(path);
+        let files = workspace.list_files(&path)?;
+        if !self.fs.stat(&path)?.is_dir() {
+            return Ok(files);
+        }
+
+        Ok(files
+            .into_iter()
+            .filter(|file| {
+                let file = Path::new(file);
+                index.is_tracked(&file) || !ignores.is_ignored(file, false)
+            })
+            .collect())
+    }
+
+    /// Store the files at some paths in the working tree, relative to its top, and update
+    /// their entries in the index, which has to have been loaded. Returns the paths whose
+    /// entries were new or changed.
+    pub fn add_to_index<P: AsRef<Path>>(
+        &self,
+        index: &mut Index<F>,
+        workspace: &Workspace<F>,
+        paths: &[P],
+    ) -> Result<Vec<PathBuf>> {
+        let mut added = Vec::new();
+        for batch in paths.chunks(ADD_BATCH_SIZE) {
+            let mut blobs = Vec::new();
+            let mut stats = Vec::new();
+            for path in batch {
+                blobs.push(Blob::new(workspace.read_file(path)?));
+                stats.push(workspace.stat_file(path)?);
+            }
+
+            let oids = self
+                .database
+                .store_batch(blobs.iter().map(|blob| blob as &dyn Object))?;
+
+            for ((path, oid), stat) in batch.iter().zip(oids).zip(stats) {
+                let old = index
+                    .entry_for_path(path)
+                    .map(|entry| (*entry.oid(), entry.mode()));
+                let mode = workspace.mode_for_stat(&stat, old.map(|(_, mode)| mode));
+                if old != Some((oid, mode)) {
+                    added.push(path.as_ref().to_path_buf());
+                }
+                index.add_with_mode(path, oid, stat, mode);
+            }
+        }
+
+        Ok(added)
+    }
+
+    /// Store the tree the index describes, along with all its subtrees.
+    pub fn write_tree(&self, index: &Index<F>) -> Result<ObjectId> {
+        let mut root = Tree::build(index.entries().values().cloned().collect());
+        root.traverse(&mut |tree| self.database.store(tree))?;
+        self.database.store(&root)
+    }
+
+    /// Commit the index's tree on top of HEAD, and move HEAD, or the branch it's on, to the
+    /// new commit. Unlike `commit` on the command line, a commit that changes nothing is
+    /// made all the same.
+    pub fn commit(&self, message: &str, options: &CommitOptions) -> Result<ObjectId> {
+        if message.trim().is_empty() {
+            return Err(RepositoryError::EmptyMessage.into());
+        }
+
+        let mut index = self.configured_index()?;
+        index.load()?;
+        let tree = self.write_tree(&index)?;
+
+        let time = options.timestamp.unwrap_or_else(|| Local::now().into());
+        let committer = options.committer.as_ref().unwrap_or(&options.author);
+
+        let parent = self.refs.read_head()?;
+        let commit = Commit::new(parent, tree, options.author.at(time), message.to_owned())
+            .with_committer(committer.at(time));
+        let oid = self.database.store(&commit)?;
+
+        let action = match parent {
+            Some(_) => "commit",
+            None => "commit (initial)",
+        };
+        self.refs
+            .update_head(&oid, &format!("{}: {}", action, commit.title_line()))?;
+
+        Ok(oid)
+    }
+
+    /// Walk the commits reachable from HEAD, newest first. There are none before the
+    /// first commit.
+    pub fn log(&self) -> Result<RevWalk<'_, F>> {
+        let head: Vec<_> = self.refs.read_head()?.into_iter().collect();
+        RevWalk::new(&self.database, &head)
+    }
+
+    /// Move the working tree and index to a revision, and point HEAD at it: at the branch
+    /// if the revision names one, and otherwise detached at the commit. Nothing is changed
+    /// if local changes would be lost.
+    pub fn checkout(&self, revision: &str) -> Result<ObjectId> {
+        let workspace = self.workspace()?;
+        let mut index = self.configured_index()?;
+
+        with_rollback(&mut index, |index| {
+            index.load_for_update()?;
+
+            let current_ref = self.refs.current_ref()?;
+            let current_oid = self.refs.read_head()?;
+            let target_oid = Revision::resolve(revision, &self.database, &self.refs)?;
+
+            let diff = self
+                .database
+                .tree_diff(current_oid.as_ref(), Some(&target_oid))?;
+            Migration::new(&self.database, index, &workspace, diff).apply_changes()?;
+            index.write_updates()?;
+
+            let from = match &current_oid {
+                Some(oid) if current_ref == HEAD => oid.to_string(),
+                _ => Refs::short_name(&current_ref).to_owned(),
+            };
+            let message = format!("checkout: moving from {} to {}", from, revision);
+            self.refs.set_head(revision, &target_oid, &message)?;
+
+            Ok(target_oid)
+        })
+    }
+
+    /// Check that the repository is in a format nit understands: that its format version
+    /// isn't newer than any there is, and that it doesn't need any extensions nit doesn't
+    /// support, such as partial clones or SHA-256 ObjectIds, which it could corrupt by
+    /// going ahead regardless. As in git, extensions are only heeded from version 1 on.
+    pub fn check_format(&self) -> Result<()> {
+        let config = self.config()?;
+        let version = config
+            .get_usize("core.repositoryFormatVersion")?
+            .unwrap_or(0);
+        if version > MAX_FORMAT_VERSION {
+            return Err(RepositoryError::UnsupportedFormatVersion(version).into());
+        }
+        if version == 0 {
+            return Ok(());
+        }
+
+        for name in config.names("extensions") {
+            let value = config.get(&format!("extensions.{}", name))?;
+            let supported = match name.as_str() {
+                "objectformat" => value.is_some_and(|value| value.eq_ignore_ascii_case("sha1")),
+                name => SUPPORTED_EXTENSIONS.contains(&name),
+            };
+            if !supported {
+                return Err(RepositoryError::UnsupportedExtension(name).into());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Load the repository's config file.
+    pub fn config(&self) -> Result<Config<F>> {
+        let mut config = self.config_file();
+        config.load()?;
+        Ok(config)
+    }
+
+    fn config_file(&self) -> Config<F> {
+        Config::with_vfs(self.fs.clone(), self.git_path.join("config"))
+    }
+
+    /// Work out what a new branch starting at `start_point` should track.
+    ///
+    /// Branches started from a remote-tracking ref track it automatically. With `explicit`
+    /// set, local branches are tracked too, and a start point that isn't a branch is an error.
+    pub fn upstream_for_start_point(
+        &self,
+        start_point: &str,
+        explicit: bool,
+    ) -> Result<Option<Upstream>> {
+        let full_name = self.refs.expand_name(start_point).unwrap_or_default();
+
+        if let Some((remote, branch)) = full_name
+            .strip_prefix("refs/remotes/")
+            .and_then(|name| name.split_once('/'))
+        {
+            if branch != HEAD {
+                return Ok(Some(Upstream {
+                    remote: remote.to_owned(),
+                    merge: format!("refs/heads/{}", branch),
+                }));
+            }
+        }
+
+        if !explicit {
+            return Ok(None);
+        }
+
+        match full_name.strip_prefix("refs/heads/") {
+            Some(branch) => Ok(Some(Upstream {
+                remote: ".".to_owned(),
+                merge: format!("refs/heads/{}", branch),
+            })),
+            None => Err(RepositoryError::NotABranch(start_point.to_owned()).into()),
+        }
+    }
+
+    /// Record a branch's upstream in the repository's config.
+    pub fn set_upstream(&self, branch: &str, upstream: &Upstream) -> Result<()> {
+        let mut config = self.config_file();
+        config.load_for_update()?;
+        config.set(&format!("branch.{}.remote", branch), &upstream.remote)?;
+        config.set(&format!("branch.{}.merge", branch), &upstream.merge)?;
+        config.save()
+    }
+
+    /// Forget a branch's upstream.
+    pub fn unset_upstream(&self, branch: &str) -> Result<()> {
+        let mut config = self.config_file();
+        config.load_for_update()?;
+        config.unset(&format!("branch.{}.remote", branch))?;
+        config.unset(&format!("branch.{}.merge", branch))?;
+        config.save()
+    }
+
+    /// The upstream configured for a branch, if there is one.
+    pub fn get_upstream(&self, branch: &str) -> Result<Option<Upstream>> {
+        let config = self.config()?;
+        let remote = config.get(&format!("branch.{}.remote", branch))?;
+        let merge = config.get(&format!("branch.{}.merge", branch))?;
+
+        match (remote, merge) {
+            (Some(remote), Some(merge)) => Ok(Some(Upstream { remote, merge })),
+            _ => Ok(None),
+        }
+    }
+
+    /// The full name of the local ref that holds a branch's upstream, e.g.
+    /// `refs/remotes/origin/main`, if the branch has an upstream configured.
+    pub fn upstream_of(&self, branch: &str) -> Result<Option<String>> {
+        let upstream = match self.get_upstream(branch)? {
+            Some(upstream) => upstream,
+            None => return Ok(None),
+        };
+
+        if upstream.remote == "." {
+            return Ok(Some(upstream.merge));
+        }
+
+        Ok(Some(self.tracking_ref(&upstream.remote, &upstream.merge)?))
+    }
+
+    /// The full name of the local ref that holds a copy of a ref on a remote, mapped
+    /// through the remote's fetch refspecs, e.g. `refs/heads/main` on `origin` is usually
+    /// kept in `refs/remotes/origin/main`.
+    pub fn tracking_ref(&self, remote: &str, name: &str) -> Result<String> {
+        let config = self.config()?;
+        let fetch_specs = config.get_all(&format!("remote.{}.fetch", remote))?;
+
+        Ok(fetch_specs
+            .iter()
+            .filter_map(|spec| spec.parse::<Refspec>().ok())
+            .find_map(|spec| spec.map(name))
+            .unwrap_or_else(|| format!("refs/remotes/{}/{}", remote, Refs::short_name(name))))
+    }
+
+    /// Compare a branch with its upstream, if it has one.
+    pub fn divergence(&self, branch: &str) -> Result<Option<Divergence>> {
+        let upstream = match self.upstream_of(branch)? {
+            Some(upstream) => upstream,
+            None => return Ok(None),
+        };
+
+        let branch_oid = self.refs.read_ref(&format!("refs/heads/{}", branch))?;
+        let upstream_oid = self.refs.read_ref(&upstream)?;
+
+        let (ahead, behind) = match (&branch_oid, &upstream_oid) {
+            (Some(branch_oid), Some(upstream_oid)) => {
+                merge::ahead_behind(&self.database, branch_oid, upstream_oid)?
+            }
+            _ => (0, 0),
+        };
+
+        Ok(Some(Divergence {
+            upstream,
+            upstream_exists: upstream_oid.is_some(),
+            ahead,
+            behind,
+        }))
+    }
+}
+
+/// Run something that updates the index, rolling back the index's lock if it fails, unless
+/// the lock couldn't be taken because someone else holds it.
+fn with_rollback<F: Vfs, T>(
+    index: &mut Index<F>,
+    f: impl FnOnce(&mut Index<F>) -> Result<T>,
+) -> Result<T> {
+    f(index).or_else(|e| {
+        if !matches!(e, crate::Error::Lockfile(LockfileError::LockDenied(_))) {
+            index.lockfile_mut().rollback()?;
+        }
+        Err(e)
+    })
+}
@@ -0,0 +1,383 @@
+use std::path::Path;
+
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use thiserror::Error;
+
+use crate::database::{Database, ObjectId, ParsedObject};
+use crate::reflog::ReflogEntry;
+use crate::refs::{Refs, HEAD};
+use crate::vfs::Vfs;
+use crate::Result;
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum RevisionError {
+    #[error("Not a valid object name: '{0}'.")]
+    InvalidObject(String),
+    #[error("short SHA1 {0} is ambiguous")]
+    AmbiguousObjectId(String),
+    #[error("object {0} is a {1}, not a commit")]
+    NotACommit(String, String),
+    #[error("log for '{0}' only has {1} entries")]
+    ReflogTooShort(String, usize),
+    #[error("path '{0}' does not exist in '{1}'")]
+    NoSuchPath(String, String),
+}
+
+/// A parsed revision expression, such as `master`, `HEAD^`, `abc1234~3` or `main@{1}`.
+#[derive(Debug, PartialEq)]
+pub enum Revision {
+    Ref(String),
+    Parent(Box<Revision>),
+    Ancestor(Box<Revision>, usize),
+    /// A previous position of a ref, looked up in its reflog. An empty name means the
+    /// current branch.
+    Reflog(String, ReflogSelector),
+}
+
+/// Which entry of a reflog a `@{...}` suffix picks.
+#[derive(Debug, PartialEq)]
+pub enum ReflogSelector {
+    /// The nth previous position, where 0 is the current one.
+    Index(usize),
+    /// Where the ref was at a date, such as `yesterday` or `2021-03-01`.
+    Date(String),
+}
+
+const MIN_PREFIX_LENGTH: usize = 4;
+
+impl Revision {
+    pub fn parse(expr: &str) -> Option<Self> {
+        if let Some(rev) = expr.strip_suffix('^') {
+            return Revision::parse(rev).map(|rev| Revision::Parent(Box::new(rev)));
+        }
+
+        if let Some((rev, n)) = expr.rsplit_once('~') {
+            let n = n.parse().ok()?;
+            return Revision::parse(rev).map(|rev| Revision::Ancestor(Box::new(rev), n));
+        }
+
+        if let Some((name, selector)) = expr.strip_suffix('}').and_then(|e| e.rsplit_once("@{")) {
+            let selector = match selector.parse() {
+                Ok(n) => ReflogSelector::Index(n),
+                Err(_) if !selector.is_empty() => ReflogSelector::Date(selector.to_owned()),
+                Err(_) => return None,
+            };
+            return Some(Revision::Reflog(name.to_owned(), selector));
+        }
+
+        match expr {
+            "" => None,
+            "@" => Some(Revision::Ref(HEAD.to_owned())),
+            _ => Some(Revision::Ref(expr.to_owned())),
+        }
+    }
+
+    /// Resolve a revision expression to the ObjectId of the commit it names. An annotated
+    /// tag names the commit it's a tag of, as `<tag>^{}` does.
+    pub fn resolve<F: Vfs>(expr: &str, database: &Database<F>, refs: &Refs<F>) -> Result<ObjectId> {
+        let invalid = || RevisionError::InvalidObject(expr.to_owned());
+        let revision = Revision::parse(expr).ok_or_else(invalid)?;
+
+        revision
+            .resolve_commit(database, refs)?
+            .ok_or_else(|| invalid().into())
+    }
+
+    /// Resolve a revision expression to a commit, or to a tree if the expression names
+    /// one directly by its ObjectId.
+    pub fn resolve_tree_ish<F: Vfs>(
+        expr: &str,
+        database: &Database<F>,
+        refs: &Refs<F>,
+    ) -> Result<ObjectId> {
+        match Revision::resolve(expr, database, refs) {
+            Err(crate::Error::Revision(RevisionError::NotACommit(oid, kind))) if kind == "tree" => {
+                ObjectId::from_hex(&oid)
+            }
+            result => result,
+        }
+    }
+
+    /// Resolve a revision expression to the object it names, which can be of any type if
+    /// it's named directly by a ref or its ObjectId, or as `<rev>:<path>`, the blob or tree
+    /// at a path in a commit or tree. An annotated tag's name names the tag itself.
+    pub fn resolve_object<F: Vfs>(
+        expr: &str,
+        database: &Database<F>,
+        refs: &Refs<F>,
+    ) -> Result<ObjectId> {
+        if let Some(Revision::Ref(name)) = Revision::parse(expr) {
+            if let Some(oid) = Revision::read_object(&name, database, refs)? {
+                return Ok(oid);
+            }
+        }
+
+        if let Some((rev, path)) = expr.split_once(':') {
+            let oid = Revision::resolve_tree_ish(rev, database, refs)?;
+            let entry = database.entry_at_path(&oid, Path::new(path))?;
+            return entry
+                .map(|entry| *entry.oid())
+                .ok_or_else(|| RevisionError::NoSuchPath(path.to_owned(), rev.to_owned()).into());
+        }
+
+        match Revision::resolve(expr, database, refs) {
+            Err(crate::Error::Revision(RevisionError::NotACommit(oid, _))) => {
+                ObjectId::from_hex(&oid)
+            }
+            result => result,
+        }
+    }
+
+    fn resolve_commit<F: Vfs>(
+        &self,
+        database: &Database<F>,
+        refs: &Refs<F>,
+    ) -> Result<Option<ObjectId>> {
+        match self {
+            Revision::Ref(name) => Revision::read_ref(name, database, refs),
+            Revision::Parent(rev) => match rev.resolve_commit(database, refs)? {
+                Some(oid) => Ok(database.load_commit(&oid)?.parent().copied()),
+                None => Ok(None),
+            },
+            Revision::Ancestor(rev, n) => {
+                let mut oid = rev.resolve_commit(database, refs)?;
+                for _ in 0..*n {
+                    oid = match oid {
+                        Some(oid) => database.load_commit(&oid)?.parent().copied(),
+                        None => break,
+                    };
+                }
+                Ok(oid)
+            }
+            Revision::Reflog(name, selector) => Revision::read_reflog(name, selector, refs),
+        }
+    }
+
+    fn read_reflog<F: Vfs>(
+        name: &str,
+        selector: &ReflogSelector,
+        refs: &Refs<F>,
+    ) -> Result<Option<ObjectId>> {
+        let name = match name {
+            "" => refs.current_ref()?,
+            "@" => HEAD.to_owned(),
+            name => match refs.expand_name(name) {
+                Some(name) => name,
+                None => return Ok(None),
+            },
+        };
+        let entries = refs.reflog(&name)?;
+
+        match selector {
+            ReflogSelector::Index(0) if entries.is_empty() => refs.read_ref(&name),
+            ReflogSelector::Index(n) => {
+                // Each entry gives a position the ref moved to, and the oldest also gives
+                // the one it started from, if it existed.
+                let positions: Vec<_> = entries
+                    .iter()
+                    .rev()
+                    .map(|entry| &entry.new)
+                    .chain(entries.first().map(|entry| &entry.old))
+                    .filter(|oid| !oid.is_null())
+                    .collect();
+
+                match positions.get(*n) {
+                    Some(oid) => Ok(Some(*(*oid))),
+                    None => Err(RevisionError::ReflogTooShort(
+                        Refs::short_name(&name).to_owned(),
+                        entries.len(),
+                    )
+                    .into()),
+                }
+            }
+            ReflogSelector::Date(date) => {
+                let date = match parse_date(date, Utc::now()) {
+                    Some(date) => date,
+                    None => return Ok(None),
+                };
+                Ok(position_at(&entries, date))
+            }
+        }
+    }
+
+    /// Read the commit a name gives, peeling annotated tags: a ref, or a commit's ObjectId
+    /// or an unambiguous prefix of one.
+    fn read_ref<F: Vfs>(
+        name: &str,
+        database: &Database<F>,
+        refs: &Refs<F>,
+    ) -> Result<Option<ObjectId>> {
+        if let Some(oid) = refs.read_ref(name)? {
+            return Ok(Some(database.peel(&oid)?));
+        }
+
+        let oid = match Revision::read_object(name, database, refs)? {
+            Some(oid) => database.peel(&oid)?,
+            None => return Ok(None),
+        };
+        match database.load(&oid)? {
+            ParsedObject::Commit(_) => Ok(Some(oid)),
+            object => {
+                Err(RevisionError::NotACommit(oid.to_string(), object.kind().to_owned()).into())
+            }
+        }
+    }
+
+    /// Read the object a name gives, of whatever type: a ref, or an ObjectId or an
+    /// unambiguous prefix of one.
+    fn read_object<F: Vfs>(
+        name: &str,
+        database: &Database<F>,
+        refs: &Refs<F>,
+    ) -> Result<Option<ObjectId>> {
+        if let Some(oid) = refs.read_ref(name)? {
+            return Ok(Some(oid));
+        }
+
+        if name.len() < MIN_PREFIX_LENGTH || !name.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Ok(None);
+        }
+
+        let mut candidates = database.prefix_match(&name.to_ascii_lowercase())?;
+        match candidates.len() {
+            0 => Ok(None),
+            1 => Ok(Some(candidates.remove(0))),
+            _ => Err(RevisionError::AmbiguousObjectId(name.to_owned()).into()),
+        }
+    }
+}
+
+/// Where the reflog says a ref was at the given date: the position it moved to in the
+/// last entry made by then, or where it started from if every entry is later.
+fn position_at(entries: &[ReflogEntry], date: DateTime<Utc>) -> Option<ObjectId> {
+    match entries
+        .iter()
+        .rev()
+        .find(|entry| entry.identity.time() <= date)
+    {
+        Some(entry) => Some(entry.new),
+        None => entries.first().map(|entry| {
+            if entry.old.is_null() {
+                entry.new
+            } else {
+                entry.old
+            }
+        }),
+    }
+}
+
+/// Parse a date as given in a `@{...}` suffix or an expiry setting such as `gc.pruneExpire`:
+/// `now`, `yesterday`, a relative date such as `3 days ago` or `2.weeks.ago`, a date such as
+/// `2021-03-01`, or a date and time.
+pub fn parse_date(text: &str, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let text = text.trim();
+    let words: Vec<_> = text.split([' ', '.']).collect();
+
+    match words.as_slice() {
+        ["now"] => return Some(now),
+        ["yesterday"] => return Some(now - Duration::days(1)),
+        [n, unit, "ago"] => {
+            let n: i64 = n.parse().ok()?;
+            let unit_seconds = match unit.strip_suffix('s').unwrap_or(unit) {
+                "second" => 1,
+                "minute" => 60,
+                "hour" => 60 * 60,
+                "day" => 24 * 60 * 60,
+                "week" => 7 * 24 * 60 * 60,
+                "month" => 30 * 24 * 60 * 60,
+                "year" => 365 * 24 * 60 * 60,
+                _ => return None,
+            };
+            // `Duration::seconds` panics if given too many, but any i64 of milliseconds fits.
+            let millis = n.checked_mul(unit_seconds)?.checked_mul(1000)?;
+            return now.checked_sub_signed(Duration::milliseconds(millis));
+        }
+        _ => {}
+    }
+
+    ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(text, "%Y-%m-%d")
+                .ok()
+                .map(|date| date.and_hms(0, 0, 0))
+        })
+        .map(|date| Utc.from_utc_datetime(&date))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_revisions() {
+        assert_eq!(
+            Revision::parse("@^"),
+            Some(Revision::Parent(Box::new(Revision::Ref("HEAD".to_owned()))))
+        );
+
+        assert_eq!(
+            Revision::parse("master~3^"),
+            Some(Revision::Parent(Box::new(Revision::Ancestor(
+                Box::new(Revision::Ref("master".to_owned())),
+                3
+            ))))
+        );
+
+        assert_eq!(Revision::parse("~3"), None);
+    }
+
+    #[test]
+    fn parses_reflog_revisions() {
+        assert_eq!(
+            Revision::parse("main@{2}^"),
+            Some(Revision::Parent(Box::new(Revision::Reflog(
+                "main".to_owned(),
+                ReflogSelector::Index(2)
+            ))))
+        );
+        assert_eq!(
+            Revision::parse("@{yesterday}"),
+            Some(Revision::Reflog(
+                "".to_owned(),
+                ReflogSelector::Date("yesterday".to_owned())
+            ))
+        );
+        assert_eq!(Revision::parse("main@{}"), None);
+    }
+
+    #[test]
+    fn parses_dates() {
+        let now = Utc.ymd(2021, 3, 10).and_hms(12, 0, 0);
+
+        assert_eq!(parse_date("now", now), Some(now));
+        assert_eq!(
+            parse_date("yesterday", now),
+            Some(Utc.ymd(2021, 3, 9).and_hms(12, 0, 0))
+        );
+        assert_eq!(
+            parse_date("2.weeks.ago", now),
+            Some(Utc.ymd(2021, 2, 24).and_hms(12, 0, 0))
+        );
+        assert_eq!(
+            parse_date("1 hour ago", now),
+            Some(Utc.ymd(2021, 3, 10).and_hms(11, 0, 0))
+        );
+        assert_eq!(
+            parse_date("2021-03-01", now),
+            Some(Utc.ymd(2021, 3, 1).and_hms(0, 0, 0))
+        );
+        assert_eq!(parse_date("whenever", now), None);
+
+        // Amounts too big for a date are no date at all, rather than a panic.
+        assert_eq!(parse_date("5000000000.years.ago", now), None);
+        assert_eq!(parse_date("9223372036854775807 seconds ago", now), None);
+        assert_eq!(parse_date("99999999999999999999 days ago", now), None);
+        assert_eq!(
+            parse_date("3000000000.seconds.ago", now),
+            Some(now - Duration::seconds(3_000_000_000))
+        );
+    }
+}
@@ -0,0 +1,254 @@
+//! Walking back through the history of a set of commits, newest first, as `log` does.
+
+use std::collections::{BinaryHeap, HashSet};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, FixedOffset};
+
+use crate::database::{Commit, Database, DatabaseEntry, ObjectId, TreeEntry};
+use crate::diff;
+use crate::pack::ObjectType;
+use crate::vfs::{RealFs, Vfs};
+use crate::Result;
+
+/// How alike a deleted file has to be to an added one, as a percentage, for the added one
+/// to count as the deleted one renamed. This is git's default.
+pub const RENAME_THRESHOLD: usize = 50;
+
+/// Visits every commit reachable from the ones it starts at, each once, in order of when
+/// they were committed, newest first.
+pub struct RevWalk<'a, F: Vfs = RealFs> {
+    database: &'a Database<F>,
+    queue: BinaryHeap<(DateTime<FixedOffset>, ObjectId)>,
+    seen: HashSet<ObjectId>,
+}
+
+impl<'a, F: Vfs> RevWalk<'a, F> {
+    pub fn new(database: &'a Database<F>, starts: &[ObjectId]) -> Result<Self> {
+        let mut walk = Self {
+            database,
+            queue: BinaryHeap::new(),
+            seen: HashSet::new(),
+        };
+        for oid in starts {
+            walk.enqueue(oid)?;
+        }
+
+        Ok(walk)
+    }
+
+    fn enqueue(&mut self, oid: &ObjectId) -> Result<()> {
+        if self.seen.insert(*oid) {
+            let time = self.database.load_commit(oid)?.committer().time();
+            self.queue.push((time, *oid));
+        }
+        Ok(())
+    }
+
+    fn next_commit(&mut self) -> Result<Option<(ObjectId, Commit)>> {
+        let oid = match self.queue.pop() {
+            Some((_, oid)) => oid,
+            None => return Ok(None),
+        };
+
+        let commit = self.database.load_commit(&oid)?;
+        for parent in commit.parents() {
+            self.enqueue(parent)?;
+        }
+
+        Ok(Some((oid, commit)))
+    }
+}
+
+impl<F: Vfs> Iterator for RevWalk<'_, F> {
+    type Item = Result<(ObjectId, Commit)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_commit().transpose()
+    }
+}
+
+/// An object found by `list_objects`. Trees and blobs come with the path they were first
+/// found at, which is empty for a commit's root tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListedObject {
+    pub oid: ObjectId,
+    pub kind: ObjectType,
+    pub path: Option<PathBuf>,
+}
+
+/// List every object reachable from the commits in `include` but not from those in
+/// `exclude`, as `rev-list --objects` does: the commits newest first, then the trees and
+/// blobs they need. Like git, only the trees of excluded commits next to included ones are
+/// looked through to leave out what they have, so an object the excluded commits only
+/// reach further back can still be listed, but none is ever missed.
+pub fn list_objects<F: Vfs>(
+    database: &Database<F>,
+    include: &[ObjectId],
+    exclude: &[ObjectId],
+) -> Result<Vec<ListedObject>> {
+    let excluded = RevWalk::new(database, exclude)?
+        .map(|commit| commit.map(|(oid, _)| oid))
+        .collect::<Result<HashSet<_>>>()?;
+
+    let mut objects = Vec::new();
+    let mut trees = Vec::new();
+    let mut boundary: Vec<_> = exclude.to_vec();
+    for commit in RevWalk::new(database, include)? {
+        let (oid, commit) = commit?;
+        if excluded.contains(&oid) {
+            continue;
+        }
+        boundary.extend(commit.parents().iter().filter(|p| excluded.contains(p)));
+        trees.push(*commit.tree());
+        objects.push(ListedObject {
+            oid,
+            kind: ObjectType::Commit,
+            path: None,
+        });
+    }
+
+    let mut seen = HashSet::new();
+    for oid in boundary {
+        let tree = *database.load_commit(&oid)?.tree();
+        walk_tree(database, tree, PathBuf::new(), &mut seen, &mut Vec::new())?;
+    }
+    for tree in trees {
+        walk_tree(database, tree, PathBuf::new(), &mut seen, &mut objects)?;
+    }
+
+    Ok(objects)
+}
+
+/// Add a tree and everything in it to `objects`, depth first, skipping anything already
+/// seen.
+fn walk_tree<F: Vfs>(
+    database: &Database<F>,
+    oid: ObjectId,
+    path: PathBuf,
+    seen: &mut HashSet<ObjectId>,
+    objects: &mut Vec<ListedObject>,
+) -> Result<()> {
+    if !seen.insert(oid) {
+        return Ok(());
+    }
+
+    let tree = database.load_tree(&oid)?;
+    objects.push(ListedObject {
+        oid,
+        kind: ObjectType::Tree,
+        path: Some(path.clone()),
+    });
+
+    for (name, entry) in tree.entries() {
+        let entry = match entry {
+            TreeEntry::Stored(entry) => entry,
+            _ => continue,
+        };
+        if entry.is_tree() {
+            walk_tree(database, *entry.oid(), path.join(name), seen, objects)?;
+        } else if seen.insert(*entry.oid()) {
+            objects.push(ListedObject {
+                oid: *entry.oid(),
+                kind: ObjectType::Blob,
+                path: Some(path.join(name)),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Picks out the commits that changed one file. When following renames, a commit that
+/// added the file under its current name, while taking away a file like it, is taken to
+/// have renamed it, and the file is looked for under its old name from then on.
+pub struct PathFilter {
+    path: PathBuf,
+    follow: bool,
+}
+
+impl PathFilter {
+    pub fn new(path: impl Into<PathBuf>, follow: bool) -> Self {
+        Self {
+            path: path.into(),
+            follow,
+        }
+    }
+
+    /// The path the file has in the commits still to come.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether the commit changed the file, which it did if the file isn't the same in any
+    /// of its parents. A commit that changed the file into a directory counts too.
+    pub fn changed<F: Vfs>(
+        &mut self,
+        database: &Database<F>,
+        oid: &ObjectId,
+        commit: &Commit,
+    ) -> Result<bool> {
+        let entry = database.entry_at_path(oid, &self.path)?;
+        if commit.parents().is_empty() {
+            return Ok(entry.is_some());
+        }
+
+        let mut parent_entries = Vec::with_capacity(commit.parents().len());
+        for parent in commit.parents() {
+            let parent_entry = database.entry_at_path(parent, &self.path)?;
+            if parent_entry == entry {
+                return Ok(false);
+            }
+            parent_entries.push(parent_entry);
+        }
+
+        let added = match (commit.parent(), parent_entries.first(), &entry) {
+            (Some(parent), Some(None), Some(entry)) if !entry.is_tree() => Some((parent, entry)),
+            _ => None,
+        };
+        if let (true, Some((parent, entry))) = (self.follow, added) {
+            if let Some(source) = find_rename_source(database, parent, oid, entry)? {
+                self.path = source;
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+/// Find the file taken away between two commits that the given added entry is most like,
+/// if any is alike enough for the entry to be a rename of it. Files that are exactly the
+/// same win outright.
+fn find_rename_source<F: Vfs>(
+    database: &Database<F>,
+    parent: &ObjectId,
+    oid: &ObjectId,
+    added: &DatabaseEntry,
+) -> Result<Option<PathBuf>> {
+    let deleted: Vec<_> = database
+        .tree_diff(Some(parent), Some(oid))?
+        .into_iter()
+        .filter_map(|(path, (before, after))| match (before, after) {
+            (Some(before), None) => Some((path, before)),
+            _ => None,
+        })
+        .collect();
+
+    if let Some((path, _)) = deleted.iter().find(|(_, entry)| entry.oid() == added.oid()) {
+        return Ok(Some(path.to_owned()));
+    }
+
+    let data = database.load_blob(added.oid())?;
+    let mut best = None;
+    for (path, entry) in deleted {
+        let score = diff::similarity(
+            database.load_blob(entry.oid())?.to_bytestr(),
+            data.to_bytestr(),
+        );
+        if score >= RENAME_THRESHOLD && best.as_ref().is_none_or(|(best, _)| score > *best) {
+            best = Some((score, path));
+        }
+    }
+
+    Ok(best.map(|(_, path)| path))
+}
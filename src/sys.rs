@@ -0,0 +1,131 @@
+//! What nit needs from the operating system beyond what `std` offers everywhere. Unix has
+//! all of it. On WASI (`wasm32-wasip1`) there are portable stand-ins, so that nit still
+//! builds and can read repositories, even if some of what it records is coarser. Nothing
+//! else is supported, and building for anything else stops here rather than somewhere
+//! deep inside nit.
+
+use std::{fs, io, path::Path};
+
+#[cfg(not(any(unix, target_os = "wasi")))]
+compile_error!("nit only builds for unix and WASI targets");
+
+#[cfg(unix)]
+pub use std::os::unix::ffi::{OsStrExt, OsStringExt};
+#[cfg(target_os = "wasi")]
+pub use std::os::wasi::ffi::{OsStrExt, OsStringExt};
+
+#[cfg(unix)]
+pub use std::os::unix::fs::MetadataExt;
+
+/// The parts of unix's `MetadataExt` that nit uses, made up from what `std` knows about
+/// every file. There's no change time, so the modification time stands in for it, and
+/// there are no device, inode or owner numbers at all.
+#[cfg(target_os = "wasi")]
+pub trait MetadataExt {
+    fn dev(&self) -> u64;
+    fn ino(&self) -> u64;
+    fn mode(&self) -> u32;
+    fn uid(&self) -> u32;
+    fn gid(&self) -> u32;
+    fn size(&self) -> u64;
+    fn mtime(&self) -> i64;
+    fn mtime_nsec(&self) -> i64;
+    fn ctime(&self) -> i64;
+    fn ctime_nsec(&self) -> i64;
+}
+
+#[cfg(target_os = "wasi")]
+impl MetadataExt for fs::Metadata {
+    fn dev(&self) -> u64 {
+        0
+    }
+
+    fn ino(&self) -> u64 {
+        0
+    }
+
+    fn mode(&self) -> u32 {
+        if self.file_type().is_symlink() {
+            0o120777
+        } else if self.is_dir() {
+            0o040755
+        } else if self.permissions().readonly() {
+            0o100444
+        } else {
+            0o100644
+        }
+    }
+
+    fn uid(&self) -> u32 {
+        0
+    }
+
+    fn gid(&self) -> u32 {
+        0
+    }
+
+    fn size(&self) -> u64 {
+        self.len()
+    }
+
+    fn mtime(&self) -> i64 {
+        modified(self).as_secs() as i64
+    }
+
+    fn mtime_nsec(&self) -> i64 {
+        modified(self).subsec_nanos() as i64
+    }
+
+    fn ctime(&self) -> i64 {
+        self.mtime()
+    }
+
+    fn ctime_nsec(&self) -> i64 {
+        self.mtime_nsec()
+    }
+}
+
+#[cfg(target_os = "wasi")]
+fn modified(stat: &fs::Metadata) -> std::time::Duration {
+    stat.modified()
+        .ok()
+        .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+        .unwrap_or_default()
+}
+
+/// Whether this platform can make symlinks at all.
+pub const HAS_SYMLINKS: bool = cfg!(unix);
+
+/// Make a symlink at `link` that points at `target`.
+pub fn symlink(target: impl AsRef<Path>, link: impl AsRef<Path>) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink(target, link)
+    }
+
+    #[cfg(target_os = "wasi")]
+    {
+        let _ = (target, link);
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "symlinks aren't supported here",
+        ))
+    }
+}
+
+/// Set a file's permission bits. Where there are none, a file without any write bits is
+/// made read-only.
+pub fn set_mode(path: impl AsRef<Path>, mode: u32) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))
+    }
+
+    #[cfg(target_os = "wasi")]
+    {
+        let mut permissions = fs::metadata(&path)?.permissions();
+        permissions.set_readonly(mode & 0o222 == 0);
+        fs::set_permissions(path, permissions)
+    }
+}
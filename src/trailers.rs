@@ -0,0 +1,160 @@
+use std::{fmt::Display, str::FromStr};
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum TrailerError {
+    #[error("invalid trailer '{0}': expected 'Key: value'")]
+    Invalid(String),
+}
+
+/// A `Key: value` line from the block at the end of a commit message, such as
+/// `Reviewed-by: A. U. Thor <author@example.com>`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trailer {
+    pub key: String,
+    pub value: String,
+}
+
+impl Trailer {
+    fn parse_line(line: &str) -> Option<Self> {
+        let (key, value) = line.split_once(':')?;
+        let valid_key =
+            !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+
+        if valid_key {
+            Some(Self {
+                key: key.to_owned(),
+                value: value.trim().to_owned(),
+            })
+        } else {
+            None
+        }
+    }
+}
+
+impl FromStr for Trailer {
+    type Err = TrailerError;
+
+    /// Parse a trailer given on the command line, as either `Key: value` or `Key=value`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let line = match s.split_once('=') {
+            Some((key, value)) if !key.contains(':') => format!("{}:{}", key.trim(), value),
+            _ => s.to_owned(),
+        };
+
+        Trailer::parse_line(line.trim())
+            .filter(|trailer| !trailer.value.is_empty())
+            .ok_or_else(|| TrailerError::Invalid(s.to_owned()))
+    }
+}
+
+impl Display for Trailer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.key, self.value)
+    }
+}
+
+/// Parse the trailers at the end of a message. Trailers are only recognised in the last
+/// paragraph, when it isn't also the first, and every line in it is a trailer or the
+/// indented continuation of one.
+pub fn parse(message: &str) -> Vec<Trailer> {
+    let block = match trailer_block(message) {
+        Some(block) => block,
+        None => return Vec::new(),
+    };
+
+    let mut trailers: Vec<Trailer> = Vec::new();
+    for line in block.lines() {
+        if line.starts_with(char::is_whitespace) {
+            match trailers.last_mut() {
+                Some(trailer) => {
+                    trailer.value.push(' ');
+                    trailer.value.push_str(line.trim());
+                }
+                None => return Vec::new(),
+            }
+        } else {
+            match Trailer::parse_line(line) {
+                Some(trailer) => trailers.push(trailer),
+                None => return Vec::new(),
+            }
+        }
+    }
+
+    trailers
+}
+
+/// Add trailers to the end of a message, extending its trailer block if it has one.
+pub fn append(message: &str, trailers: &[Trailer]) -> String {
+    let body = message.trim_end();
+    let mut result = body.to_owned();
+
+    if trailers.is_empty() {
+        result.push('\n');
+        return result;
+    }
+
+    if !body.is_empty() {
+        result.push_str(if parse(body).is_empty() { "\n\n" } else { "\n" });
+    }
+
+    for trailer in trailers {
+        result.push_str(&trailer.to_string());
+        result.push('\n');
+    }
+
+    result
+}
+
+fn trailer_block(message: &str) -> Option<&str> {
+    let message = message.trim_end();
+    let start = message.rfind("\n\n")?;
+    let block = message[start..].trim_start_matches('\n');
+
+    Some(block).filter(|block| !block.is_empty())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_trailers() {
+        let message =
+            "Fix the thing\n\nIt was broken.\n\nReviewed-by: A. U. Thor\nFixes: #12,\n  #13\n";
+        assert_eq!(
+            parse(message),
+            vec![
+                Trailer {
+                    key: "Reviewed-by".to_owned(),
+                    value: "A. U. Thor".to_owned()
+                },
+                Trailer {
+                    key: "Fixes".to_owned(),
+                    value: "#12, #13".to_owned()
+                },
+            ]
+        );
+
+        assert!(parse("Title: with a colon\n").is_empty());
+        assert!(parse("Title\n\nNot: a trailer\nbecause of this line\n").is_empty());
+    }
+
+    #[test]
+    fn appends_trailers() {
+        let trailer: Trailer = "Acked-by=Someone".parse().unwrap();
+        assert_eq!(trailer.to_string(), "Acked-by: Someone");
+        assert!("no separator".parse::<Trailer>().is_err());
+
+        assert_eq!(
+            append("Title\n", std::slice::from_ref(&trailer)),
+            "Title\n\nAcked-by: Someone\n"
+        );
+        assert_eq!(
+            append("Title\n\nSigned-off-by: Me\n\n", &[trailer]),
+            "Title\n\nSigned-off-by: Me\nAcked-by: Someone\n"
+        );
+    }
+}
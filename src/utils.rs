@@ -27,19 +27,6 @@ pub fn is_executable(mode: u32) -> bool {
     mode & 0o111 != 0
 }
 
-/// Pull the first N elements from the front of a Vec, returning an array of length N.
-/// Panics if you drain past the end of the vector.
-pub fn drain_to_array<T: Default + Copy, const N: usize>(data: &mut Vec<T>) -> [T; N] {
-    let mut arr = [T::default(); N];
-    let drain = data.drain(0..N);
-
-    for (i, item) in drain.into_iter().enumerate() {
-        arr[i] = item;
-    }
-
-    arr
-}
-
 // https://github.com/Manishearth/pathdiff/blob/master/src/lib.rs
 pub fn diff_paths<P, B>(path: P, base: B) -> Option<PathBuf>
 where
@@ -69,8 +56,8 @@ where
                 }
                 (None, _) => comps.push(Component::ParentDir),
                 (Some(a), Some(b)) if comps.is_empty() && a == b => (),
-                (Some(a), Some(b)) if b == Component::CurDir => comps.push(a),
-                (Some(_), Some(b)) if b == Component::ParentDir => return None,
+                (Some(a), Some(Component::CurDir)) => comps.push(a),
+                (Some(_), Some(Component::ParentDir)) => return None,
                 (Some(a), Some(_)) => {
                     comps.push(Component::ParentDir);
                     for _ in itb {
@@ -86,19 +73,25 @@ where
     }
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    #[test]
-    fn drain_array() {
-        let mut v = vec![0, 1, 2, 3, 4, 5];
-        let arr: [u8; 3] = drain_to_array(&mut v);
-
-        assert_eq!(arr, [0, 1, 2]);
-        assert_eq!(v, vec![3, 4, 5]);
-
-        let arr: [u8; 3] = drain_to_array(&mut v);
-        assert_eq!(arr, [3, 4, 5]);
-        assert_eq!(v, vec![]);
+/// Match a glob where `*` and `?` don't cross directory separators and `**` does.
+pub fn glob_matches(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        [b'*', b'*', rest @ ..] => {
+            let rest = rest.strip_prefix(b"/").unwrap_or(rest);
+            (0..=text.len()).any(|i| glob_matches(rest, &text[i..]))
+        }
+        [b'*', rest @ ..] => {
+            let limit = text.iter().position(|&c| c == b'/').unwrap_or(text.len());
+            (0..=limit).any(|i| glob_matches(rest, &text[i..]))
+        }
+        [b'?', rest @ ..] => match text {
+            [c, text @ ..] if *c != b'/' => glob_matches(rest, text),
+            _ => false,
+        },
+        [c, rest @ ..] => match text {
+            [t, text @ ..] if t == c => glob_matches(rest, text),
+            _ => false,
+        },
     }
 }
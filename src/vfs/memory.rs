@@ -0,0 +1,372 @@
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    io,
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use super::{FileKind, Stat, Vfs};
+
+#[derive(Debug, Clone)]
+enum Content {
+    File(Vec<u8>),
+    Dir,
+    Symlink(PathBuf),
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    content: Content,
+    mode: u32,
+    ino: u64,
+    mtime: i64,
+}
+
+#[derive(Debug, Default)]
+struct Nodes {
+    nodes: BTreeMap<PathBuf, Node>,
+    /// Stands in for the clock and for inode numbers: it goes up by one every time anything
+    /// is written, so a file that's changed never looks the same as it did before.
+    tick: u64,
+}
+
+/// A filesystem held entirely in memory, for throwaway repositories in tests and tools.
+/// It starts out with just an empty root directory. Clones share the same files, so a
+/// `Workspace`, `Database` and `Refs` made from clones of one `MemoryFs` all see each
+/// other's changes.
+///
+/// Paths are taken as they are, without resolving `..` or following symlinks, and should
+/// be absolute.
+#[derive(Debug, Clone)]
+pub struct MemoryFs {
+    inner: Arc<Mutex<Nodes>>,
+}
+
+impl Default for MemoryFs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryFs {
+    pub fn new() -> Self {
+        let mut inner = Nodes::default();
+        inner.nodes.insert(
+            PathBuf::from(Component::RootDir.as_os_str()),
+            Node {
+                content: Content::Dir,
+                mode: 0o040755,
+                ino: 0,
+                mtime: 0,
+            },
+        );
+
+        Self {
+            inner: Arc::new(Mutex::new(inner)),
+        }
+    }
+
+    fn not_found() -> io::Error {
+        io::ErrorKind::NotFound.into()
+    }
+
+    /// Put something at a path, whose parent must already be a directory.
+    fn insert(&self, path: &Path, content: Content, mode: u32) -> io::Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        match path.parent().and_then(|parent| inner.nodes.get(parent)) {
+            Some(Node {
+                content: Content::Dir,
+                ..
+            }) => {}
+            Some(_) => return Err(io::ErrorKind::NotADirectory.into()),
+            None => return Err(Self::not_found()),
+        }
+
+        inner.tick += 1;
+        let node = Node {
+            content,
+            mode,
+            ino: inner.tick,
+            mtime: inner.tick as i64,
+        };
+        inner.nodes.insert(path.to_owned(), node);
+
+        Ok(())
+    }
+
+    fn get(&self, path: &Path) -> io::Result<Node> {
+        self.inner
+            .lock()
+            .unwrap()
+            .nodes
+            .get(path)
+            .cloned()
+            .ok_or_else(Self::not_found)
+    }
+}
+
+impl Vfs for MemoryFs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        match self.get(path)?.content {
+            Content::File(data) => Ok(data),
+            Content::Dir => Err(io::ErrorKind::IsADirectory.into()),
+            Content::Symlink(_) => Err(io::ErrorKind::Unsupported.into()),
+        }
+    }
+
+    fn write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        let mode = match self.get(path) {
+            Ok(Node {
+                content: Content::Dir,
+                ..
+            }) => return Err(io::ErrorKind::IsADirectory.into()),
+            Ok(Node {
+                content: Content::File(_),
+                mode,
+                ..
+            }) => mode,
+            _ => 0o100644,
+        };
+
+        self.insert(path, Content::File(data.to_vec()), mode)
+    }
+
+    fn create_new(&self, path: &Path) -> io::Result<()> {
+        if self.exists(path) {
+            return Err(io::ErrorKind::AlreadyExists.into());
+        }
+        self.write(path, &[])
+    }
+
+    fn append(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        let mut content = match self.read(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        content.extend_from_slice(data);
+        self.write(path, &content)
+    }
+
+    fn stat(&self, path: &Path) -> io::Result<Stat> {
+        let node = self.get(path)?;
+        let (kind, size) = match &node.content {
+            Content::File(data) => (FileKind::File, data.len() as u64),
+            Content::Dir => (FileKind::Dir, 0),
+            Content::Symlink(target) => (FileKind::Symlink, target.as_os_str().len() as u64),
+        };
+
+        Ok(Stat {
+            kind,
+            mode: node.mode,
+            size,
+            mtime: node.mtime,
+            mtime_nsec: 0,
+            ctime: node.mtime,
+            ctime_nsec: 0,
+            dev: 0,
+            ino: node.ino,
+            uid: 0,
+            gid: 0,
+        })
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<(OsString, FileKind)>> {
+        let inner = self.inner.lock().unwrap();
+        match inner.nodes.get(path) {
+            Some(Node {
+                content: Content::Dir,
+                ..
+            }) => {}
+            Some(_) => return Err(io::ErrorKind::NotADirectory.into()),
+            None => return Err(Self::not_found()),
+        }
+
+        Ok(inner
+            .nodes
+            .iter()
+            .filter(|(child, _)| child.parent() == Some(path))
+            .filter_map(|(child, node)| {
+                let kind = match node.content {
+                    Content::File(_) => FileKind::File,
+                    Content::Dir => FileKind::Dir,
+                    Content::Symlink(_) => FileKind::Symlink,
+                };
+                Some((child.file_name()?.to_owned(), kind))
+            })
+            .collect())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let node = self.get(from)?;
+        if matches!(node.content, Content::Dir) {
+            return Err(io::ErrorKind::Unsupported.into());
+        }
+
+        self.insert(to, node.content, node.mode)?;
+        self.inner.lock().unwrap().nodes.remove(from);
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        if matches!(self.get(path)?.content, Content::Dir) {
+            return Err(io::ErrorKind::IsADirectory.into());
+        }
+        self.inner.lock().unwrap().nodes.remove(path);
+        Ok(())
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        if !self.read_dir(path)?.is_empty() {
+            return Err(io::ErrorKind::DirectoryNotEmpty.into());
+        }
+        self.inner.lock().unwrap().nodes.remove(path);
+        Ok(())
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.read_dir(path)?;
+        self.inner
+            .lock()
+            .unwrap()
+            .nodes
+            .retain(|child, _| !child.starts_with(path));
+        Ok(())
+    }
+
+    fn create_dir(&self, path: &Path) -> io::Result<()> {
+        if self.exists(path) {
+            return Err(io::ErrorKind::AlreadyExists.into());
+        }
+        self.insert(path, Content::Dir, 0o040755)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        for dir in path.ancestors().collect::<Vec<_>>().into_iter().rev() {
+            match self.get(dir) {
+                Ok(Node {
+                    content: Content::Dir,
+                    ..
+                }) => {}
+                Ok(_) => return Err(io::ErrorKind::AlreadyExists.into()),
+                Err(_) => self.insert(dir, Content::Dir, 0o040755)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn symlink(&self, target: &Path, link: &Path) -> io::Result<()> {
+        if self.exists(link) {
+            return Err(io::ErrorKind::AlreadyExists.into());
+        }
+        self.insert(link, Content::Symlink(target.to_owned()), 0o120777)
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        match self.get(path)?.content {
+            Content::Symlink(target) => Ok(target),
+            _ => Err(io::ErrorKind::InvalidInput.into()),
+        }
+    }
+
+    fn set_mode(&self, path: &Path, mode: u32) -> io::Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        let node = inner.nodes.get_mut(path).ok_or_else(Self::not_found)?;
+        node.mode = (node.mode & !0o7777) | (mode & 0o7777);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::Identity;
+    use crate::repository::{CommitOptions, Repository};
+
+    #[test]
+    fn commits_in_memory() {
+        let fs = MemoryFs::new();
+        fs.create_dir_all(Path::new("/repo/.git/objects")).unwrap();
+        let repo = Repository::with_vfs(fs.clone(), "/repo/.git").with_workspace_path("/repo");
+
+        let workspace = repo.workspace().unwrap();
+        workspace
+            .write_file("hello.txt", b"hello\n", 0o644)
+            .unwrap();
+        fs.create_dir(Path::new("/repo/bin")).unwrap();
+        workspace
+            .write_file("bin/run", b"#!/bin/sh\n", 0o755)
+            .unwrap();
+        workspace
+            .write_file("link", b"hello.txt", 0o120000)
+            .unwrap();
+        assert_eq!(
+            repo.status().unwrap().untracked,
+            vec!["bin/run", "hello.txt", "link"]
+        );
+
+        repo.add(&["bin", "hello.txt", "link"]).unwrap();
+        let options = CommitOptions::new(Identity::new("A. U. Thor", "author@example.com"));
+        let first = repo.commit("Initial commit\n", &options).unwrap();
+
+        assert_eq!(repo.refs().read_ref("master").unwrap(), Some(first));
+        assert!(!fs.exists(Path::new("/repo/.git/refs/heads/master.lock")));
+        let modes: Vec<_> = repo
+            .database()
+            .load_tree_list(Some(&first))
+            .unwrap()
+            .into_iter()
+            .map(|(path, entry)| (path.to_string_lossy().into_owned(), entry.mode()))
+            .collect();
+        assert_eq!(
+            modes,
+            vec![
+                ("bin/run".to_owned(), 0o100755),
+                ("hello.txt".to_owned(), 0o100644),
+                ("link".to_owned(), 0o120000)
+            ]
+        );
+
+        // The index that was written reads back the same, and sees edits to the files.
+        let status = repo.status().unwrap();
+        assert!(status.staged.is_empty() && status.unstaged.is_empty());
+
+        workspace
+            .write_file("hello.txt", b"goodbye\n", 0o644)
+            .unwrap();
+        workspace.remove("link").unwrap();
+        let codes: Vec<_> = repo
+            .status()
+            .unwrap()
+            .tracked_codes()
+            .into_iter()
+            .map(|(path, code)| (path.to_string_lossy().into_owned(), code))
+            .collect();
+        assert_eq!(
+            codes,
+            vec![
+                ("hello.txt".to_owned(), " M".to_owned()),
+                ("link".to_owned(), " D".to_owned())
+            ]
+        );
+
+        repo.add(&["hello.txt"]).unwrap();
+        let mut index = repo.index();
+        index.load_for_update().unwrap();
+        index.remove(&"link");
+        index.write_updates().unwrap();
+        let second = repo.commit("Say goodbye\n", &options).unwrap();
+        let log: Vec<_> = repo
+            .log()
+            .unwrap()
+            .map(|commit| commit.unwrap().0)
+            .collect();
+        assert_eq!(log, vec![second, first]);
+
+        repo.checkout(&first.to_string()).unwrap();
+        assert_eq!(workspace.read_file("hello.txt").unwrap(), b"hello\n");
+        assert_eq!(workspace.read_file("link").unwrap(), b"hello.txt");
+        assert_eq!(repo.refs().read_head().unwrap(), Some(first));
+    }
+}
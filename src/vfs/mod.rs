@@ -0,0 +1,232 @@
+use std::{
+    ffi::OsString,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+
+use crate::pack::Mmap;
+use crate::sys::{self, MetadataExt};
+
+mod memory;
+
+pub use memory::MemoryFs;
+
+/// What kind of thing is at a path. Symlinks are never followed, so a symlink is always
+/// reported as one, whatever it points at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    File,
+    Dir,
+    Symlink,
+}
+
+/// What nit needs to know about a file: its kind, and the fields the index records to tell
+/// whether it has changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stat {
+    pub kind: FileKind,
+    pub mode: u32,
+    pub size: u64,
+    pub mtime: i64,
+    pub mtime_nsec: i64,
+    pub ctime: i64,
+    pub ctime_nsec: i64,
+    pub dev: u64,
+    pub ino: u64,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl From<fs::FileType> for FileKind {
+    fn from(file_type: fs::FileType) -> Self {
+        if file_type.is_symlink() {
+            FileKind::Symlink
+        } else if file_type.is_dir() {
+            FileKind::Dir
+        } else {
+            FileKind::File
+        }
+    }
+}
+
+impl Stat {
+    pub fn is_file(&self) -> bool {
+        self.kind == FileKind::File
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.kind == FileKind::Dir
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.kind == FileKind::Symlink
+    }
+}
+
+impl From<fs::Metadata> for Stat {
+    fn from(metadata: fs::Metadata) -> Self {
+        Self {
+            kind: metadata.file_type().into(),
+            mode: metadata.mode(),
+            size: metadata.size(),
+            mtime: metadata.mtime(),
+            mtime_nsec: metadata.mtime_nsec(),
+            ctime: metadata.ctime(),
+            ctime_nsec: metadata.ctime_nsec(),
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+        }
+    }
+}
+
+/// The contents of a whole file, held however the filesystem likes: mapped into memory, or
+/// just read into a buffer.
+pub type FileData = Box<dyn Deref<Target = [u8]> + Send + Sync>;
+
+/// Everything nit does to files, so that the workspace, database, refs and lockfiles can
+/// work on something other than the real filesystem, such as one held in memory for tests.
+/// Errors are `io::Error`s, with the same kinds the real filesystem would give, since
+/// callers look at them: a missing file is `NotFound`, and so on.
+pub trait Vfs: Clone + Send + Sync {
+    /// Read a whole file.
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    /// Read a whole file that won't change while it's held, such as a pack. The default is
+    /// to read it into memory.
+    fn read_shared(&self, path: &Path) -> io::Result<FileData> {
+        Ok(Box::new(self.read(path)?))
+    }
+
+    /// Write a whole file, replacing anything already there.
+    fn write(&self, path: &Path, data: &[u8]) -> io::Result<()>;
+
+    /// Create a file, failing with `AlreadyExists` if there's anything at the path already.
+    fn create_new(&self, path: &Path) -> io::Result<()>;
+
+    /// Add to the end of a file, creating it if it doesn't exist.
+    fn append(&self, path: &Path, data: &[u8]) -> io::Result<()>;
+
+    /// Get a file's metadata, without following symlinks.
+    fn stat(&self, path: &Path) -> io::Result<Stat>;
+
+    /// The names of everything in a directory, along with what kind of thing each is, in no
+    /// particular order.
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<(OsString, FileKind)>>;
+
+    /// Move a file, replacing anything at its new path.
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+
+    /// Remove a directory, failing with `DirectoryNotEmpty` if it has anything in it.
+    fn remove_dir(&self, path: &Path) -> io::Result<()>;
+
+    /// Remove a directory along with everything in it.
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    fn create_dir(&self, path: &Path) -> io::Result<()>;
+
+    /// Create a directory and any of its parents that don't exist yet.
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    /// Make a symlink at `link` that points at `target`.
+    fn symlink(&self, target: &Path, link: &Path) -> io::Result<()>;
+
+    /// The path a symlink points at.
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf>;
+
+    /// Set a file's permission bits.
+    fn set_mode(&self, path: &Path, mode: u32) -> io::Result<()>;
+
+    /// Whether there's anything at a path.
+    fn exists(&self, path: &Path) -> bool {
+        self.stat(path).is_ok()
+    }
+}
+
+/// The real filesystem.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RealFs;
+
+impl Vfs for RealFs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    fn read_shared(&self, path: &Path) -> io::Result<FileData> {
+        Ok(Box::new(Mmap::open(path)?))
+    }
+
+    fn write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        fs::write(path, data)
+    }
+
+    fn create_new(&self, path: &Path) -> io::Result<()> {
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .map(drop)
+    }
+
+    fn append(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?
+            .write_all(data)
+    }
+
+    fn stat(&self, path: &Path) -> io::Result<Stat> {
+        Ok(fs::symlink_metadata(path)?.into())
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<(OsString, FileKind)>> {
+        fs::read_dir(path)?
+            .map(|entry| {
+                let entry = entry?;
+                Ok((entry.file_name(), entry.file_type()?.into()))
+            })
+            .collect()
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        fs::remove_dir(path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::remove_dir_all(path)
+    }
+
+    fn create_dir(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    fn symlink(&self, target: &Path, link: &Path) -> io::Result<()> {
+        sys::symlink(target, link)
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::read_link(path)
+    }
+
+    fn set_mode(&self, path: &Path, mode: u32) -> io::Result<()> {
+        sys::set_mode(path, mode)
+    }
+}
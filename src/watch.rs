@@ -0,0 +1,210 @@
+//! Watching a repository's working tree, so that its status can be followed as it changes
+//! rather than checked over and over, as editors and prompts want to.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::time::{Duration, Instant};
+
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use thiserror::Error;
+
+use crate::repository::{Repository, Status};
+use crate::Result;
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum WatchError {
+    #[error("could not watch the working tree: {0}")]
+    Notify(#[from] notify::Error),
+}
+
+/// How long to wait for things to settle after a change before looking at the status, so
+/// that something like a checkout, which touches many files at once, is one update.
+const SETTLE_TIME: Duration = Duration::from_millis(50);
+
+/// The files in `.git` whose changes can change the status: the index, and which commit
+/// HEAD is at.
+const WATCHED_GIT_FILES: &[&str] = &["index", "HEAD", "packed-refs"];
+
+/// A path whose status has changed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatusChange {
+    pub path: String,
+    /// The path's status now, as the two letters `status --short` shows, e.g. ` M` or `??`,
+    /// or `None` if it no longer differs from HEAD.
+    pub code: Option<String>,
+}
+
+/// The status of a repository after something in it changed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatusUpdate {
+    pub status: Status,
+    /// The paths whose status is different from the last update, in order.
+    pub changes: Vec<StatusChange>,
+}
+
+/// A repository's status, followed as its working tree changes. Each update says which
+/// paths changed since the one before, so they can be shown as they happen.
+pub struct StatusStream {
+    repo: Repository,
+    git_path: PathBuf,
+    // Dropping the watcher stops the events, so it's kept for as long as the stream is.
+    _watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+    status: Status,
+}
+
+impl StatusStream {
+    /// Start watching the working tree of `repo`, and its `.git` directory if that's
+    /// somewhere else.
+    pub fn new(repo: Repository) -> Result<Self> {
+        let status = repo.status()?;
+        let workspace_path = repo.workspace_path()?.canonicalize()?;
+        let git_path = repo.git_path().canonicalize()?;
+
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender).map_err(WatchError::from)?;
+        watcher
+            .watch(&workspace_path, RecursiveMode::Recursive)
+            .map_err(WatchError::from)?;
+        if !git_path.starts_with(&workspace_path) {
+            watcher
+                .watch(&git_path, RecursiveMode::Recursive)
+                .map_err(WatchError::from)?;
+        }
+
+        Ok(Self {
+            repo,
+            git_path,
+            _watcher: watcher,
+            events,
+            status,
+        })
+    }
+
+    /// The status as of the last update, or as it was when the stream started.
+    pub fn status(&self) -> &Status {
+        &self.status
+    }
+
+    /// Wait for the status to change, for as long as `timeout` if given, and return how it
+    /// changed. Returns `None` if it didn't change in time, or if the watcher has stopped.
+    /// Changes that leave the status as it was, like saving a file without changing it or
+    /// touching an ignored one, don't count.
+    pub fn next_update(&mut self, timeout: Option<Duration>) -> Result<Option<StatusUpdate>> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            if !self.wait_for_change(deadline)? {
+                return Ok(None);
+            }
+
+            let status = self.repo.status()?;
+            let changes = compare(&self.status, &status);
+            self.status = status;
+            if !changes.is_empty() {
+                return Ok(Some(StatusUpdate {
+                    status: self.status.clone(),
+                    changes,
+                }));
+            }
+        }
+    }
+
+    /// Wait for an event that could change the status, then for things to settle. Returns
+    /// `false` if there wasn't one before the deadline.
+    fn wait_for_change(&self, deadline: Option<Instant>) -> Result<bool> {
+        loop {
+            let event = match deadline {
+                Some(deadline) => {
+                    let timeout = deadline.saturating_duration_since(Instant::now());
+                    match self.events.recv_timeout(timeout) {
+                        Ok(event) => event,
+                        Err(_) => return Ok(false),
+                    }
+                }
+                None => match self.events.recv() {
+                    Ok(event) => event,
+                    Err(_) => return Ok(false),
+                },
+            };
+            if self.is_relevant(&event.map_err(WatchError::from)?) {
+                break;
+            }
+        }
+
+        loop {
+            match self.events.recv_timeout(SETTLE_TIME) {
+                Ok(event) => {
+                    event.map_err(WatchError::from)?;
+                }
+                Err(_) => return Ok(true),
+            }
+        }
+    }
+
+    /// Whether an event is about anything the status depends on: anything in the working
+    /// tree, but only a few things in `.git`.
+    fn is_relevant(&self, event: &Event) -> bool {
+        event.paths.iter().any(|path| {
+            let path = match path.strip_prefix(&self.git_path) {
+                Ok(path) => path,
+                Err(_) => return true,
+            };
+            WATCHED_GIT_FILES.iter().any(|file| path == Path::new(file)) || path.starts_with("refs")
+        })
+    }
+}
+
+impl Iterator for StatusStream {
+    type Item = Result<StatusUpdate>;
+
+    /// Wait for as long as it takes for the status to change.
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_update(None).transpose()
+    }
+}
+
+/// Every path with a status, and what it is, as `status --short` shows it.
+fn short_codes(status: &Status) -> BTreeMap<String, String> {
+    let mut codes: BTreeMap<String, String> = status
+        .tracked_codes()
+        .into_iter()
+        .map(|(path, code)| (path.to_string_lossy().into_owned(), code))
+        .collect();
+    for path in &status.untracked {
+        codes.insert(path.clone(), "??".to_owned());
+    }
+
+    codes
+}
+
+/// The paths whose status differs between two statuses.
+fn compare(old: &Status, new: &Status) -> Vec<StatusChange> {
+    let old = short_codes(old);
+    let new = short_codes(new);
+
+    let mut paths: Vec<&String> = old.keys().chain(new.keys()).collect();
+    paths.sort();
+    paths.dedup();
+    paths
+        .into_iter()
+        .filter(|path| old.get(*path) != new.get(*path))
+        .map(|path| StatusChange {
+            path: path.clone(),
+            code: new.get(path).cloned(),
+        })
+        .collect()
+}
+
+/// Every path with a status, as changes from a clean working tree, for showing a status in
+/// full before following it.
+pub fn initial_changes(status: &Status) -> Vec<StatusChange> {
+    short_codes(status)
+        .into_iter()
+        .map(|(path, code)| StatusChange {
+            path,
+            code: Some(code),
+        })
+        .collect()
+}
@@ -1,9 +1,19 @@
 use std::{
-    fs::{self, Metadata},
+    collections::BTreeMap,
+    ffi::OsStr,
+    io,
     path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
 };
 use thiserror::Error;
 
+use crate::database::DatabaseEntry;
+use crate::index::entry::{Entry, SYMLINK_MODE};
+use crate::migration::{Action, Migration};
+use crate::repository::parse_gitfile;
+use crate::sys::{self, OsStrExt, OsStringExt};
+use crate::vfs::{FileKind, RealFs, Stat, Vfs};
 use crate::Result;
 
 #[derive(Debug, Error)]
@@ -13,54 +23,151 @@ pub enum WorkspaceError {
     Path(PathBuf),
     #[error("Couldn't parse OsString")]
     CouldNotParseString,
+    #[error("unable to create file {0}")]
+    CouldNotWrite(PathBuf, #[source] io::Error),
+    #[error("unable to remove '{0}'")]
+    CouldNotRemove(PathBuf, #[source] io::Error),
+    #[error("cannot create directory at '{0}'")]
+    CouldNotMakeDir(PathBuf, #[source] io::Error),
+    #[error("unable to remove directory '{0}'")]
+    CouldNotRemoveDir(PathBuf, #[source] io::Error),
 }
 
-pub struct Workspace {
+/// How many files a migration has to write before they're shared out between threads.
+const PARALLEL_CHECKOUT_THRESHOLD: usize = 100;
+
+pub struct Workspace<F: Vfs = RealFs> {
+    fs: F,
     pathname: PathBuf,
+    symlinks: bool,
 }
 
 impl Workspace {
     pub fn new<P: Into<PathBuf>>(pathname: P) -> Self {
+        Self::with_vfs(RealFs, pathname)
+    }
+}
+
+impl<F: Vfs> Workspace<F> {
+    pub fn with_vfs<P: Into<PathBuf>>(fs: F, pathname: P) -> Self {
         Self {
+            fs,
             pathname: pathname.into(),
+            symlinks: sys::HAS_SYMLINKS,
+        }
+    }
+
+    /// Set whether symlinks are checked out as symlinks (`core.symlinks`). Without them,
+    /// a symlink is checked out as a plain file holding the path it points at.
+    pub fn with_symlinks(mut self, symlinks: bool) -> Self {
+        self.symlinks = symlinks;
+        self
+    }
+
+    /// The mode to give a file with this metadata in the index, where `tracked` is the
+    /// mode it's tracked with already. If symlinks are checked out as plain files, a plain
+    /// file that's tracked as a symlink stays one.
+    pub fn mode_for_stat(&self, stat: &Stat, tracked: Option<u32>) -> u32 {
+        match tracked {
+            Some(SYMLINK_MODE) if !self.symlinks && stat.is_file() => SYMLINK_MODE,
+            _ => Entry::mode_for_stat(stat),
         }
     }
 
+    /// List every file under a path, walking its directories one level at a time and
+    /// sharing each level's directories out between threads. Files are sorted by path
+    /// once the walk is done, so the result doesn't depend on which thread found what.
     fn _list_files(&self, path: Option<&Path>) -> Result<Vec<String>> {
         let path = path.unwrap_or(&self.pathname);
 
-        let res = if std::fs::metadata(path)?.is_dir() {
-            let dirs = std::fs::read_dir(path)?;
-            let mut file_names = Vec::new();
-            for dir in dirs {
-                let path = dir?.path();
-                if !&[".", "..", ".git"].iter().any(|&s| path.ends_with(s)) {
-                    let file_name = path
-                        .file_name()
-                        .ok_or_else(|| WorkspaceError::Path(path.clone()))?
-                        .to_owned();
-
-                    file_names.push(file_name);
-                }
+        let mut files = Vec::new();
+        if self.fs.stat(path)?.is_dir() {
+            let mut dirs = vec![path.to_owned()];
+            while !dirs.is_empty() {
+                let (found, subdirs) = self.read_dirs(&dirs)?;
+                files.extend(found);
+                dirs = subdirs;
             }
-            file_names
-                .iter()
-                .map(|name| self._list_files(Some(&path.join(name))))
-                .flat_map(|result| match result {
-                    Ok(vec) => vec.into_iter().map(Ok).collect(),
-                    Err(e) => vec![Err(e)],
-                })
-                .collect()
+            files.sort();
         } else {
-            let s = crate::utils::diff_paths(path, &self.pathname);
-            Ok(vec![s
-                .ok_or_else(|| WorkspaceError::Path(path.to_owned()))?
-                .to_str()
-                .ok_or(WorkspaceError::CouldNotParseString)?
-                .to_owned()])
+            files.push(path.to_owned());
+        }
+
+        files
+            .iter()
+            .map(|path| {
+                let relative = crate::utils::diff_paths(path, &self.pathname)
+                    .ok_or_else(|| WorkspaceError::Path(path.clone()))?;
+                Ok(relative
+                    .to_str()
+                    .ok_or(WorkspaceError::CouldNotParseString)?
+                    .to_owned())
+            })
+            .collect()
+    }
+
+    /// Read a set of directories, returning the files and the subdirectories found in them.
+    /// Like git, a `.git` is never listed: at the top of the workspace it's the repository
+    /// itself, and below it, it makes its directory another repository's working tree,
+    /// which is left alone rather than listed as if its files were this one's.
+    fn read_dirs(&self, dirs: &[PathBuf]) -> Result<(Vec<PathBuf>, Vec<PathBuf>)> {
+        let read_dir =
+            |dir: &Path, files: &mut Vec<PathBuf>, subdirs: &mut Vec<PathBuf>| -> Result<()> {
+                for (name, kind) in self.fs.read_dir(dir)? {
+                    if name == ".git" {
+                        continue;
+                    }
+
+                    // Symlinks are tracked as links, so even one that points at a
+                    // directory isn't followed.
+                    let path = dir.join(name);
+                    match kind {
+                        FileKind::Dir if self.is_nested_repository(&path) => {}
+                        FileKind::Dir => subdirs.push(path),
+                        _ => files.push(path),
+                    }
+                }
+                Ok(())
+            };
+
+        let workers = thread::available_parallelism().map_or(1, |n| n.get().min(dirs.len()));
+        let next = AtomicUsize::new(0);
+        let worker = || -> Result<(Vec<PathBuf>, Vec<PathBuf>)> {
+            let (mut files, mut subdirs) = (Vec::new(), Vec::new());
+            while let Some(dir) = dirs.get(next.fetch_add(1, Ordering::Relaxed)) {
+                read_dir(dir, &mut files, &mut subdirs)?;
+            }
+            Ok((files, subdirs))
         };
 
-        res
+        if workers <= 1 {
+            return worker();
+        }
+
+        thread::scope(|scope| {
+            let handles: Vec<_> = (0..workers).map(|_| scope.spawn(worker)).collect();
+            let (mut files, mut subdirs) = (Vec::new(), Vec::new());
+            for handle in handles {
+                let (found, found_dirs) = handle.join().expect("workspace scan panicked")?;
+                files.extend(found);
+                subdirs.extend(found_dirs);
+            }
+            Ok((files, subdirs))
+        })
+    }
+
+    /// Whether a directory holds a repository of its own: a `.git` directory, or a gitfile
+    /// pointing at one.
+    fn is_nested_repository(&self, dir: &Path) -> bool {
+        let git = dir.join(".git");
+        match self.fs.stat(&git) {
+            Ok(stat) if stat.is_dir() => true,
+            Ok(stat) if stat.is_file() => self
+                .fs
+                .read(&git)
+                .is_ok_and(|data| parse_gitfile(&data).is_some()),
+            _ => false,
+        }
     }
 
     /// Lists all files in a path, relative to this workspace's base directory.
@@ -77,45 +184,282 @@ impl Workspace {
     }
 
     /// Read a file's contents into a Vec<u8>, based on a path relative to this workspace's base directory.
+    /// The contents of a symlink are the path it points at.
     pub fn read_file<P: AsRef<Path>>(&self, path: P) -> Result<Vec<u8>> {
-        let r = std::fs::read(&self.pathname.join(&path))?;
-        Ok(r)
+        let path = self.pathname.join(&path);
+        if self.fs.stat(&path)?.is_symlink() {
+            return Ok(self.fs.read_link(&path)?.into_os_string().into_vec());
+        }
+
+        Ok(self.fs.read(&path)?)
     }
 
     /// Get a file's metadata, based on a path relative to this workspace's base directory.
-    pub fn stat_file<P: AsRef<Path>>(&self, path: P) -> Result<Metadata> {
-        let metadata = fs::metadata(&self.pathname.join(path))?;
-        Ok(metadata)
+    /// Symlinks aren't followed.
+    pub fn stat_file<P: AsRef<Path>>(&self, path: P) -> Result<Stat> {
+        Ok(self.fs.stat(&self.pathname.join(path))?)
+    }
+
+    /// Get a file's metadata, or None if nothing exists at that path.
+    pub fn try_stat_file<P: AsRef<Path>>(&self, path: P) -> Result<Option<Stat>> {
+        match self.fs.stat(&self.pathname.join(path)) {
+            Ok(metadata) => Ok(Some(metadata)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) if e.kind() == io::ErrorKind::NotADirectory => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// List the immediate children of a directory, relative to this workspace's base directory,
+    /// along with their metadata.
+    pub fn list_dir<P: AsRef<Path>>(&self, path: P) -> Result<BTreeMap<PathBuf, Stat>> {
+        let mut stats = BTreeMap::new();
+        for (name, _) in self.fs.read_dir(&self.pathname.join(&path))? {
+            if name == ".git" {
+                continue;
+            }
+
+            let relative = path.as_ref().join(name);
+            let stat = self.stat_file(&relative)?;
+            stats.insert(relative, stat);
+        }
+
+        Ok(stats)
+    }
+
+    /// Write a migration's planned changes out to the workspace.
+    pub fn apply_migration(&self, migration: &Migration<'_, F>) -> Result<()> {
+        self.apply_change_list(migration, Action::Delete)?;
+
+        for dir in migration.rmdirs().iter().rev() {
+            self.remove_dir_if_empty(dir)?;
+        }
+
+        for dir in migration.mkdirs() {
+            self.make_dir(dir)?;
+        }
+
+        self.write_files(migration)
+    }
+
+    /// Write a file into the workspace with the given mode, replacing whatever was at that path.
+    pub fn write_file<P: AsRef<Path>>(&self, path: P, data: &[u8], mode: u32) -> Result<()> {
+        let path = path.as_ref();
+        self.remove(path)?;
+
+        let full_path = self.pathname.join(path);
+        let error = |e| WorkspaceError::CouldNotWrite(path.to_owned(), e);
+        let permissions = match mode {
+            SYMLINK_MODE if self.symlinks => {
+                let target = Path::new(OsStr::from_bytes(data));
+                return Ok(self.fs.symlink(target, &full_path).map_err(error)?);
+            }
+            SYMLINK_MODE => 0o644,
+            mode => mode & 0o777,
+        };
+        self.fs.write(&full_path, data).map_err(error)?;
+        self.fs.set_mode(&full_path, permissions).map_err(error)?;
+
+        Ok(())
+    }
+
+    /// Remove a file, or a directory and everything in it, from the workspace, if there's
+    /// anything at that path.
+    pub fn remove<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let full_path = self.pathname.join(path);
+
+        let removed = match self.fs.stat(&full_path) {
+            Ok(stat) if stat.is_dir() => self.fs.remove_dir_all(&full_path),
+            Ok(_) => self.fs.remove_file(&full_path),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e),
+        };
+
+        Ok(removed.map_err(|e| WorkspaceError::CouldNotRemove(path.to_owned(), e))?)
+    }
+
+    /// Make a directory in the workspace, replacing any file in its way. Its parent has to
+    /// exist already.
+    pub fn make_dir<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let full_path = self.pathname.join(path);
+        let error = |e| WorkspaceError::CouldNotMakeDir(path.to_owned(), e);
+
+        match self.try_stat_file(path)? {
+            Some(stat) if stat.is_dir() => return Ok(()),
+            Some(_) => self.fs.remove_file(&full_path).map_err(error)?,
+            None => {}
+        }
+
+        Ok(self.fs.create_dir(&full_path).map_err(error)?)
+    }
+
+    /// Remove a directory from the workspace if there's nothing left in it. It's left be if
+    /// it's already gone, isn't a directory, or still has files in it.
+    pub fn remove_dir_if_empty<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        match self.fs.remove_dir(&self.pathname.join(path)) {
+            Ok(()) => Ok(()),
+            Err(e)
+                if [
+                    io::ErrorKind::NotFound,
+                    io::ErrorKind::NotADirectory,
+                    io::ErrorKind::DirectoryNotEmpty,
+                ]
+                .contains(&e.kind()) =>
+            {
+                Ok(())
+            }
+            Err(e) => Err(WorkspaceError::CouldNotRemoveDir(path.to_owned(), e).into()),
+        }
+    }
+
+    fn apply_change_list(&self, migration: &Migration<'_, F>, action: Action) -> Result<()> {
+        for (path, entry) in migration.changes(action) {
+            self.apply_change(migration, path, entry.as_ref())?;
+        }
+
+        Ok(())
+    }
+
+    fn apply_change(
+        &self,
+        migration: &Migration<'_, F>,
+        path: &Path,
+        entry: Option<&DatabaseEntry>,
+    ) -> Result<()> {
+        match entry {
+            Some(entry) => self.write_file(path, &migration.blob_data(entry.oid())?, entry.mode()),
+            None => self.remove(path),
+        }
+    }
+
+    /// Write every updated and created file. Their directories already exist by now, so
+    /// when there are enough of them, each thread can take the next unwritten file off the
+    /// list until none are left.
+    fn write_files(&self, migration: &Migration<'_, F>) -> Result<()> {
+        let files: Vec<_> = migration
+            .changes(Action::Update)
+            .iter()
+            .chain(migration.changes(Action::Create))
+            .collect();
+
+        let workers = match files.len() {
+            n if n < PARALLEL_CHECKOUT_THRESHOLD => 1,
+            n => thread::available_parallelism().map_or(1, |workers| workers.get().min(n)),
+        };
+        if workers == 1 {
+            return files
+                .iter()
+                .try_for_each(|(path, entry)| self.apply_change(migration, path, entry.as_ref()));
+        }
+
+        let next = AtomicUsize::new(0);
+        let worker = || -> Result<()> {
+            while let Some((path, entry)) = files.get(next.fetch_add(1, Ordering::Relaxed)) {
+                self.apply_change(migration, path, entry.as_ref())?;
+            }
+            Ok(())
+        };
+
+        thread::scope(|scope| {
+            let handles: Vec<_> = (0..workers).map(|_| scope.spawn(worker)).collect();
+            handles
+                .into_iter()
+                .try_for_each(|handle| handle.join().expect("checkout worker panicked"))
+        })
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::vfs::MemoryFs;
+
     #[test]
     fn list_files() {
-        let tmp_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-            .join("tmp")
-            .join("workspace-list-files");
-        std::fs::create_dir_all(&tmp_path).unwrap();
+        let fs = MemoryFs::new();
+        let root = Path::new("/workspace");
+        fs.create_dir_all(&root.join("a").join("b")).unwrap();
 
-        std::fs::write(tmp_path.join("hello.txt"), "Hey world").unwrap();
-        std::fs::write(tmp_path.join("goodbye.txt"), "Hey world").unwrap();
-        std::fs::write(tmp_path.join("okay.txt"), "Hey world").unwrap();
+        fs.write(&root.join("hello.txt"), b"Hey world").unwrap();
+        fs.write(&root.join("goodbye.txt"), b"Hey world").unwrap();
+        fs.write(&root.join("okay.txt"), b"Hey world").unwrap();
 
-        std::fs::create_dir(tmp_path.join("a")).unwrap();
-        std::fs::create_dir(tmp_path.join("a").join("b")).unwrap();
-        std::fs::write(tmp_path.join("a").join("b").join("what.txt"), "what?").unwrap();
+        fs.write(&root.join("a").join("b").join("what.txt"), b"what?")
+            .unwrap();
+        fs.write(&root.join("a.txt"), b"next to a").unwrap();
+        for i in 0..20 {
+            let dir = root.join("many").join(format!("d{:02}", i));
+            fs.create_dir_all(&dir).unwrap();
+            fs.write(&dir.join("f.txt"), b"f").unwrap();
+        }
 
-        let ws = Workspace::new(&tmp_path);
+        let ws = Workspace::with_vfs(fs, root);
 
         let entries = ws.list_files_in_root().unwrap();
 
-        assert_eq!(
-            entries,
-            vec!["a/b/what.txt", "goodbye.txt", "okay.txt", "hello.txt",]
-        );
+        let many: Vec<_> = (0..20).map(|i| format!("many/d{:02}/f.txt", i)).collect();
+        let mut expected = vec!["a/b/what.txt", "a.txt", "goodbye.txt", "hello.txt"];
+        expected.extend(many.iter().map(String::as_str));
+        expected.push("okay.txt");
+        assert_eq!(entries, expected);
+    }
 
-        std::fs::remove_dir_all(&tmp_path).unwrap();
+    #[test]
+    fn leaves_out_repositories() {
+        let fs = MemoryFs::new();
+        let root = Path::new("/workspace");
+        for dir in ["sub/.git", "linked", "plain", "deep/er"] {
+            fs.create_dir_all(&root.join(dir)).unwrap();
+        }
+        fs.write(&root.join(".git"), b"gitdir: ../repo.git\n")
+            .unwrap();
+        fs.write(&root.join("a.txt"), b"a").unwrap();
+        fs.write(&root.join("sub/.git/HEAD"), b"ref: refs/heads/main\n")
+            .unwrap();
+        fs.write(&root.join("sub/b.txt"), b"b").unwrap();
+        fs.write(&root.join("linked/.git"), b"gitdir: /elsewhere\n")
+            .unwrap();
+        fs.write(&root.join("linked/c.txt"), b"c").unwrap();
+        fs.write(&root.join("plain/.git"), b"not a gitfile")
+            .unwrap();
+        fs.write(&root.join("plain/d.txt"), b"d").unwrap();
+        fs.write(&root.join("deep/er/e.txt"), b"e").unwrap();
+
+        let ws = Workspace::with_vfs(fs, root);
+        let entries = ws.list_files_in_root().unwrap();
+        assert_eq!(entries, vec!["a.txt", "deep/er/e.txt", "plain/d.txt"]);
+    }
+
+    #[test]
+    fn writes_and_removes_files_and_directories() {
+        let fs = MemoryFs::new();
+        let root = Path::new("/workspace");
+        fs.create_dir_all(root).unwrap();
+        let ws = Workspace::with_vfs(fs, root);
+
+        ws.write_file("a", b"in the way", 0o100644).unwrap();
+        ws.make_dir("a").unwrap();
+        ws.write_file("a/b.txt", b"b", 0o100755).unwrap();
+        assert!(ws.stat_file("a").unwrap().is_dir());
+        assert_eq!(ws.read_file("a/b.txt").unwrap(), b"b");
+        assert_eq!(ws.stat_file("a/b.txt").unwrap().mode & 0o777, 0o755);
+
+        // A directory with something in it is left alone.
+        ws.remove_dir_if_empty("a").unwrap();
+        assert!(ws.stat_file("a").unwrap().is_dir());
+        ws.remove("a/b.txt").unwrap();
+        ws.remove("a/b.txt").unwrap();
+        ws.remove_dir_if_empty("a").unwrap();
+        assert!(ws.try_stat_file("a").unwrap().is_none());
+
+        match ws.write_file("missing/c.txt", b"c", 0o100644) {
+            Err(crate::Error::Workspace(WorkspaceError::CouldNotWrite(path, _))) => {
+                assert_eq!(path, Path::new("missing/c.txt"))
+            }
+            result => panic!("expected the write to fail, got {:?}", result),
+        }
     }
 }